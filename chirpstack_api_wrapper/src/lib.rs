@@ -10,8 +10,12 @@
 pub mod error;
 
 use crate::error::Error;
+use chirpstack_gwb_integration::gateway_id::GatewayId;
 use std::collections::HashSet;
-use tracing::trace;
+use tonic::metadata::{Ascii, MetadataValue};
+use tonic::transport::Channel;
+use tonic::{Request, Status, Streaming};
+use tracing::{trace, warn};
 
 /// The ChirpStack API type containing information about the API endpoint and providing methods to
 /// interact with the API.
@@ -42,26 +46,13 @@ impl ChirpStackApi {
         &self,
         limit: u32,
     ) -> Result<chirpstack_api::api::ListGatewaysResponse, Error> {
-        use tonic::{metadata::MetadataValue, transport::Channel, Request};
-
-        trace!("Creating endpoint");
-        let channel = Channel::builder(format!("{}:{}", self.url, self.port).parse()?)
-            .connect_timeout(std::time::Duration::from_secs(3));
-
-        trace!("Connecting to endpoint, creating channel");
-        let channel = channel.connect().await?;
-
-        trace!("Parsing token");
-        let token: MetadataValue<_> = format!("Bearer {}", self.api_token).parse()?;
+        let (channel, token) = self.connect().await?;
 
         trace!("Creating client");
         let mut client =
             chirpstack_api::api::gateway_service_client::GatewayServiceClient::with_interceptor(
                 channel,
-                move |mut req: Request<()>| {
-                    req.metadata_mut().insert("authorization", token.clone());
-                    Ok(req)
-                },
+                move |req: Request<()>| authorize(req, &token),
             );
 
         trace!("Creating request");
@@ -79,17 +70,87 @@ impl ChirpStackApi {
     /// Retrieves the available gateway IDs from the ChirpStack API. `limit` limits the about of gateways
     /// returned by the API.
     ///
+    /// Gateway IDs which are no 64 bit hex encoded EUIs are skipped.
+    ///
     /// # Errors
     /// Returns an error if an empty gateway list was retrieved. Also returns errors on all conditions
     /// [`request_gateways`](ChirpStackApi::request_gateways) does.
-    pub async fn request_gateway_ids(&self, limit: u32) -> Result<HashSet<String>, Error> {
+    pub async fn request_gateway_ids(&self, limit: u32) -> Result<HashSet<GatewayId>, Error> {
         let mut result = HashSet::new();
         for gateway in self.request_gateways(limit).await?.result {
-            result.insert(gateway.gateway_id);
+            match gateway.gateway_id.parse() {
+                Ok(gateway_id) => {
+                    result.insert(gateway_id);
+                }
+                Err(e) => warn!("Skipping gateway returned by ChirpStack API: {e}"),
+            }
         }
         if result.is_empty() {
             return Err(Error::NoGatewaysReturned);
         }
         Ok(result)
     }
+
+    /// Streams the frame logs of a gateway from the ChirpStack API, an alternative to receiving
+    /// the frames via MQTT if the MQTT broker of the gateway bridge is not reachable.
+    ///
+    /// Every log item describes an uplink (`up`) or downlink (`down`) frame in its description,
+    /// its body contains the frame log as JSON. The stream ends if the connection is closed and
+    /// yields a [`Status`] if the API reports an error.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if:
+    /// - the endpoint could not be parsed.
+    /// - the endpoint could not be reached.
+    /// - the bearer token could not be parsed as [`MetadataValue`](tonic::metadata::value::MetadataValue).
+    /// - the stream request failed, e.g. as the gateway is unknown.
+    pub async fn stream_gateway_frames(
+        &self,
+        gateway_id: &GatewayId,
+    ) -> Result<Streaming<chirpstack_api::api::LogItem>, Error> {
+        let (channel, token) = self.connect().await?;
+
+        trace!("Creating client");
+        let mut client =
+            chirpstack_api::api::internal_service_client::InternalServiceClient::with_interceptor(
+                channel,
+                move |req: Request<()>| authorize(req, &token),
+            );
+
+        trace!("Creating request");
+        let request = chirpstack_api::api::StreamGatewayFramesRequest {
+            gateway_id: gateway_id.to_string(),
+        };
+        trace!("Sending request");
+        Ok(client.stream_gateway_frames(request).await?.into_inner())
+    }
+
+    /// Connects to the endpoint and parses the API token.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if:
+    /// - the endpoint could not be parsed.
+    /// - the endpoint could not be reached.
+    /// - the bearer token could not be parsed as [`MetadataValue`](tonic::metadata::value::MetadataValue).
+    async fn connect(&self) -> Result<(Channel, MetadataValue<Ascii>), Error> {
+        trace!("Creating endpoint");
+        let channel = Channel::builder(format!("{}:{}", self.url, self.port).parse()?)
+            .connect_timeout(std::time::Duration::from_secs(3));
+
+        trace!("Connecting to endpoint, creating channel");
+        let channel = channel.connect().await?;
+
+        trace!("Parsing token");
+        let token = format!("Bearer {}", self.api_token).parse()?;
+        Ok((channel, token))
+    }
+}
+
+/// Adds the bearer token to the metadata of the request.
+#[allow(clippy::unnecessary_wraps)]
+fn authorize(mut req: Request<()>, token: &MetadataValue<Ascii>) -> Result<Request<()>, Status> {
+    req.metadata_mut().insert("authorization", token.clone());
+    Ok(req)
 }
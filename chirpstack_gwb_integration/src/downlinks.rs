@@ -4,6 +4,11 @@ pub mod downlink_builder;
 pub mod downlink_item_builder;
 pub mod predefined_parameters;
 
+use crate::downlinks::predefined_parameters::{DataRate, EU863_870_BAND};
+use crate::error::EnqueueError;
+use crate::gateway_id::GatewayId;
+use schemars::JsonSchema;
+use serde_derive::{Deserialize, Serialize};
 use std::fmt::Debug;
 use std::hash::Hash;
 use std::marker::PhantomData;
@@ -16,7 +21,7 @@ where
     Dt: DownlinkType,
 {
     /// Gateway ID.
-    gateway_id: String,
+    gateway_id: GatewayId,
     /// Downlink ID.
     downlink_id: u32,
     /// Items in the Downlink, only one will be sent. Priority is descending from first to last item.
@@ -35,6 +40,18 @@ where
     tx_info: TxInfo<Dt>,
 }
 
+/// Board and antenna of a gateway emitting a downlink item.
+///
+/// Gateways with multiple boards or antennas may be offered the same item on several transmission
+/// paths, see [`DownlinkBuilder::add_item_on_tx_paths`](downlink_builder::DownlinkBuilder::add_item_on_tx_paths).
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Hash, Serialize, Deserialize, JsonSchema)]
+pub struct TxPath {
+    /// The board identifier for emitting the frame.
+    pub board: u32,
+    /// The antenna identifier for emitting the frame.
+    pub antenna: u32,
+}
+
 /// Transmission info.
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
 struct TxInfo<Dt>
@@ -151,6 +168,92 @@ fn build_modulation_info(
     chirpstack_api::gw::modulation::Parameters::Lora(modulation_info_result)
 }
 
+impl<Dt> DownlinkItem<Dt>
+where
+    Dt: DownlinkType,
+{
+    /// Returns a copy of the item emitted on the board and antenna of the transmission path.
+    #[must_use]
+    pub fn on_tx_path(&self, tx_path: TxPath) -> Self {
+        let mut item = self.clone();
+        item.tx_info.board = tx_path.board;
+        item.tx_info.antenna = tx_path.antenna;
+        item
+    }
+}
+
+impl<Dt> Downlink<Dt>
+where
+    Dt: DownlinkType,
+{
+    /// Checks whether the items can be sent in the EU863-870 band, items with a modulation not
+    /// matching any predefined data rate are not checked for their payload size.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an item:
+    /// - is sent on a frequency outside of the band.
+    /// - carries a payload exceeding the maximum payload size of its data rate.
+    pub(crate) fn check_transmittable(&self) -> Result<(), EnqueueError> {
+        for item in &self.items {
+            let frequency = item.tx_info.frequency;
+            if !EU863_870_BAND.contains(&frequency) {
+                return Err(EnqueueError::FrequencyNotAllowed { frequency });
+            }
+            let modulation_info = &item.tx_info.lo_ra_modulation_info;
+            if let Ok(data_rate) = DataRate::from_raw_bandwidth_and_spreading_factor(
+                modulation_info.bandwidth,
+                modulation_info.spreading_factor,
+            ) {
+                let max = data_rate.max_allowed_payload_size(false);
+                if item.phy_payload.len() > max {
+                    return Err(EnqueueError::PayloadTooLarge {
+                        size: item.phy_payload.len(),
+                        max,
+                        data_rate,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Downlink<ImmediatelyClassC> {
+    /// Converts the downlink into a [`GpsTimingClassB`] downlink sent at the supplied time since
+    /// GPS epoch.
+    #[must_use]
+    pub fn into_gps_timed(
+        self,
+        time_since_gps_epoch: std::time::Duration,
+    ) -> Downlink<GpsTimingClassB> {
+        Downlink {
+            gateway_id: self.gateway_id,
+            downlink_id: self.downlink_id,
+            items: self
+                .items
+                .into_iter()
+                .map(|item| DownlinkItem {
+                    phy_payload: item.phy_payload,
+                    tx_info: TxInfo {
+                        frequency: item.tx_info.frequency,
+                        power: item.tx_info.power,
+                        lo_ra_modulation_info: item.tx_info.lo_ra_modulation_info,
+                        board: item.tx_info.board,
+                        antenna: item.tx_info.antenna,
+                        delay_timing_info: None,
+                        context: item.tx_info.context,
+                        gps_epoch_timing_info: Some(GpsEpochTimingInfo {
+                            time_since_gps_epoch,
+                        }),
+                        downlink_type: PhantomData,
+                    },
+                })
+                .collect(),
+        }
+    }
+}
+
 impl From<Downlink<DelayTimingClassA>> for chirpstack_api::gw::DownlinkFrame {
     fn from(downlink: Downlink<DelayTimingClassA>) -> Self {
         let items = {
@@ -201,7 +304,7 @@ impl From<Downlink<DelayTimingClassA>> for chirpstack_api::gw::DownlinkFrame {
 
         chirpstack_api::gw::DownlinkFrame {
             downlink_id: downlink.downlink_id,
-            gateway_id: downlink.gateway_id,
+            gateway_id: downlink.gateway_id.into(),
             items,
             ..Default::default()
         }
@@ -259,7 +362,7 @@ impl From<Downlink<GpsTimingClassB>> for chirpstack_api::gw::DownlinkFrame {
 
         chirpstack_api::gw::DownlinkFrame {
             downlink_id: downlink.downlink_id,
-            gateway_id: downlink.gateway_id,
+            gateway_id: downlink.gateway_id.into(),
             items,
             ..Default::default()
         }
@@ -309,7 +412,7 @@ impl From<Downlink<ImmediatelyClassC>> for chirpstack_api::gw::DownlinkFrame {
 
         chirpstack_api::gw::DownlinkFrame {
             downlink_id: downlink.downlink_id,
-            gateway_id: downlink.gateway_id,
+            gateway_id: downlink.gateway_id.into(),
             items,
             ..Default::default()
         }
@@ -318,15 +421,19 @@ impl From<Downlink<ImmediatelyClassC>> for chirpstack_api::gw::DownlinkFrame {
 
 #[cfg(test)]
 mod tests {
-    use crate::downlinks::downlink_builder::DownlinkBuilder;
+    use crate::downlinks::downlink_builder::{DownlinkBuilder, MAX_DOWNLINK_ITEMS};
     use crate::downlinks::downlink_item_builder::DownlinkItemBuilder;
-    use crate::downlinks::predefined_parameters::{DataRate, Frequency};
-    use crate::downlinks::{DelayTimingClassA, GpsTimingClassB, ImmediatelyClassC};
+    use crate::downlinks::predefined_parameters::{
+        Bandwidth, DataRate, Frequency, SpreadingFactor,
+    };
+    use crate::downlinks::{DelayTimingClassA, GpsTimingClassB, ImmediatelyClassC, TxPath};
+    use crate::error::{DownlinkBuilderError, EnqueueError};
+    use crate::gateway_id::GatewayId;
     use rand::Rng;
 
     #[test]
     fn test_create_class_a_downlink() {
-        let gateway_id = "a840411d25244150".to_owned();
+        let gateway_id: GatewayId = "a840411d25244150".parse().unwrap();
         let payload = vec![0xff; 20];
         let context = vec![0xff; 20];
         let frequency = Frequency::Freq868_1;
@@ -392,7 +499,7 @@ mod tests {
         };
 
         let expected_protobuf_downlink = chirpstack_api::gw::DownlinkFrame {
-            gateway_id,
+            gateway_id: gateway_id.into(),
             items: vec![expected_protobuf_item],
             downlink_id,
             ..chirpstack_api::gw::DownlinkFrame::default()
@@ -402,7 +509,7 @@ mod tests {
 
     #[test]
     fn test_create_class_b_downlink() {
-        let gateway_id = "a840411d25244150".to_owned();
+        let gateway_id: GatewayId = "a840411d25244150".parse().unwrap();
         let payload = vec![0xff; 20];
         let context = vec![0xff; 20];
         let frequency = Frequency::Freq868_1;
@@ -468,7 +575,7 @@ mod tests {
         };
 
         let expected_protobuf_downlink = chirpstack_api::gw::DownlinkFrame {
-            gateway_id,
+            gateway_id: gateway_id.into(),
             items: vec![expected_protobuf_item],
             downlink_id,
             ..chirpstack_api::gw::DownlinkFrame::default()
@@ -478,7 +585,7 @@ mod tests {
 
     #[test]
     fn test_create_class_c_downlink() {
-        let gateway_id = "a840411d25244150".to_owned();
+        let gateway_id: GatewayId = "a840411d25244150".parse().unwrap();
         let payload = vec![0xff; 20];
         let context = vec![0xff; 20];
         let frequency = Frequency::Freq868_1;
@@ -540,11 +647,204 @@ mod tests {
         };
 
         let expected_protobuf_downlink = chirpstack_api::gw::DownlinkFrame {
-            gateway_id,
+            gateway_id: gateway_id.into(),
             items: vec![expected_protobuf_item],
             downlink_id,
             ..chirpstack_api::gw::DownlinkFrame::default()
         };
         assert_eq!(expected_protobuf_downlink, protobuf_downlink);
     }
+
+    #[test]
+    fn test_convert_class_c_to_class_b_downlink() {
+        let gateway_id: GatewayId = "a840411d25244150".parse().unwrap();
+        let payload = vec![0xff; 20];
+        let time_since_gps_epoch = std::time::Duration::from_secs(1);
+        let downlink_id = rand::thread_rng().gen();
+        let class_c_item = DownlinkItemBuilder::<ImmediatelyClassC>::new()
+            .phy_payload(payload.clone())
+            .frequency(Frequency::Freq868_1)
+            .power(14)
+            .data_rate(DataRate::Eu863_870Dr0)
+            .board(0)
+            .antenna(0)
+            .build()
+            .expect("Failed to build downlink item");
+        let class_c_downlink = DownlinkBuilder::new()
+            .gateway_id(gateway_id.clone())
+            .downlink_id(downlink_id)
+            .add_item(class_c_item)
+            .build()
+            .expect("Failed to build downlink");
+        let class_b_item = DownlinkItemBuilder::<GpsTimingClassB>::new()
+            .phy_payload(payload)
+            .frequency(Frequency::Freq868_1)
+            .power(14)
+            .data_rate(DataRate::Eu863_870Dr0)
+            .board(0)
+            .antenna(0)
+            .time_since_gps_epoch(time_since_gps_epoch)
+            .build()
+            .expect("Failed to build downlink item");
+        let class_b_downlink = DownlinkBuilder::new()
+            .gateway_id(gateway_id)
+            .downlink_id(downlink_id)
+            .add_item(class_b_item)
+            .build()
+            .expect("Failed to build downlink");
+        assert_eq!(
+            class_b_downlink,
+            class_c_downlink.into_gps_timed(time_since_gps_epoch)
+        );
+    }
+
+    #[test]
+    fn test_downlink_builder_validation() {
+        let gateway_id: GatewayId = "a840411d25244150".parse().unwrap();
+        let class_a_item = |delay_secs: u64, context: Vec<u8>| {
+            DownlinkItemBuilder::<DelayTimingClassA>::new()
+                .phy_payload(vec![0xff; 20])
+                .frequency(Frequency::Freq868_1)
+                .power(14)
+                .data_rate(DataRate::Eu863_870Dr0)
+                .board(0)
+                .antenna(0)
+                .delay(std::time::Duration::from_secs(delay_secs))
+                .context(context)
+                .build()
+                .expect("Failed to build downlink item")
+        };
+
+        assert!(
+            DownlinkBuilder::single_item(gateway_id.clone(), 1, class_a_item(1, vec![1])).is_ok()
+        );
+        assert_eq!(
+            DownlinkBuilder::<DelayTimingClassA>::new()
+                .gateway_id(gateway_id.clone())
+                .downlink_id(1)
+                .add_items(Vec::new())
+                .build(),
+            Err(DownlinkBuilderError::NoItems)
+        );
+        assert_eq!(
+            DownlinkBuilder::new()
+                .gateway_id(gateway_id.clone())
+                .downlink_id(1)
+                .add_items(vec![class_a_item(1, vec![1]); MAX_DOWNLINK_ITEMS + 1])
+                .build(),
+            Err(DownlinkBuilderError::TooManyItems {
+                amount: MAX_DOWNLINK_ITEMS + 1,
+                max: MAX_DOWNLINK_ITEMS
+            })
+        );
+        assert_eq!(
+            DownlinkBuilder::new()
+                .gateway_id(gateway_id.clone())
+                .downlink_id(1)
+                .add_items(vec![class_a_item(1, vec![1]), class_a_item(2, vec![2])])
+                .build(),
+            Err(DownlinkBuilderError::InconsistentContext)
+        );
+        assert_eq!(
+            DownlinkBuilder::new()
+                .gateway_id(gateway_id)
+                .downlink_id(1)
+                .add_items(vec![class_a_item(2, vec![1]), class_a_item(1, vec![1])])
+                .build(),
+            Err(DownlinkBuilderError::ItemsNotSorted { index: 1 })
+        );
+    }
+
+    #[test]
+    fn test_add_item_on_tx_paths() {
+        let item = DownlinkItemBuilder::<ImmediatelyClassC>::new()
+            .phy_payload(vec![0xff; 20])
+            .frequency(Frequency::Freq868_1)
+            .power(14)
+            .data_rate(DataRate::Eu863_870Dr0)
+            .board(0)
+            .antenna(0)
+            .build()
+            .expect("Failed to build downlink item");
+        let tx_paths = [
+            TxPath {
+                board: 0,
+                antenna: 0,
+            },
+            TxPath {
+                board: 1,
+                antenna: 1,
+            },
+        ];
+        let downlink = DownlinkBuilder::new()
+            .gateway_id("a840411d25244150".parse().unwrap())
+            .downlink_id(1)
+            .add_item_on_tx_paths(item.clone(), &tx_paths)
+            .build()
+            .expect("Failed to build downlink");
+        let protobuf_downlink: chirpstack_api::gw::DownlinkFrame = downlink.into();
+        assert_eq!(protobuf_downlink.items.len(), 2);
+        let mut items = protobuf_downlink
+            .items
+            .into_iter()
+            .map(|item| item.tx_info.unwrap());
+        let (first, second) = (items.next().unwrap(), items.next().unwrap());
+        assert_eq!((second.board, second.antenna), (1, 1));
+        assert_eq!(
+            first,
+            chirpstack_api::gw::DownlinkTxInfo {
+                board: 0,
+                antenna: 0,
+                ..second
+            }
+        );
+
+        let downlink = DownlinkBuilder::new()
+            .gateway_id("a840411d25244150".parse().unwrap())
+            .downlink_id(1)
+            .add_item_on_tx_paths(item.clone(), &[])
+            .build()
+            .expect("Failed to build downlink");
+        assert_eq!(
+            downlink,
+            DownlinkBuilder::single_item("a840411d25244150".parse().unwrap(), 1, item).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_check_transmittable() {
+        let downlink = |frequency: u32, payload_size: usize| {
+            let item = DownlinkItemBuilder::<ImmediatelyClassC>::new()
+                .phy_payload(vec![0xff; payload_size])
+                .frequency_raw(frequency)
+                .power(14)
+                .raw_bandwidth(Bandwidth::Bw125)
+                .raw_spreading_factor(SpreadingFactor::SF12)
+                .board(0)
+                .antenna(0)
+                .build()
+                .expect("Failed to build downlink item");
+            DownlinkBuilder::new()
+                .gateway_id("a840411d25244150".parse().unwrap())
+                .downlink_id(1)
+                .add_item(item)
+                .build()
+                .expect("Failed to build downlink")
+        };
+        assert!(downlink(868_100_000, 64).check_transmittable().is_ok());
+        assert!(matches!(
+            downlink(915_000_000, 20).check_transmittable(),
+            Err(EnqueueError::FrequencyNotAllowed {
+                frequency: 915_000_000
+            })
+        ));
+        assert!(matches!(
+            downlink(868_100_000, 65).check_transmittable(),
+            Err(EnqueueError::PayloadTooLarge {
+                size: 65,
+                max: 64,
+                data_rate: DataRate::Eu863_870Dr0
+            })
+        ));
+    }
 }
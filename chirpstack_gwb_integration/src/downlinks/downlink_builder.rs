@@ -1,7 +1,11 @@
 //! Builders to create correct downlinks.
 
-use crate::downlinks::{Downlink, DownlinkItem, DownlinkType};
+use crate::downlinks::{Downlink, DownlinkItem, DownlinkType, TxPath};
 use crate::error::DownlinkBuilderError;
+use crate::gateway_id::GatewayId;
+
+/// Max amount of items in a [`Downlink`]. ChirpStack itself uses at most two items (RX1 and RX2).
+pub const MAX_DOWNLINK_ITEMS: usize = 4;
 
 /// Builder for [`Downlink`].
 #[derive(Debug, Clone)]
@@ -10,8 +14,10 @@ where
     Dt: DownlinkType,
 {
     /// Gateway ID.
-    gateway_id: Option<String>,
-    /// In the ChirpStack source, this is set by `rand::thread_rng().gen()`.
+    gateway_id: Option<GatewayId>,
+    /// In the ChirpStack source, this is set by `rand::thread_rng().gen()`, use
+    /// [`Runtime::next_downlink_id`](crate::runtime::Runtime::next_downlink_id) to correlate
+    /// acknowledgements reliably.
     downlink_id: Option<u32>,
     /// Downlink items.
     items: Option<Vec<DownlinkItem<Dt>>>,
@@ -41,7 +47,7 @@ where
     }
 
     /// Sets the gateway id
-    pub fn gateway_id(&mut self, gateway_id: String) -> &mut Self {
+    pub fn gateway_id(&mut self, gateway_id: GatewayId) -> &mut Self {
         self.gateway_id = Some(gateway_id);
         self
     }
@@ -76,11 +82,51 @@ where
         self
     }
 
+    /// Adds a copy of the item for every transmission path, the copies only differ by board and
+    /// antenna. The gateway emits the first copy whose board and antenna are free, the item is
+    /// added unchanged if there are no transmission paths.
+    pub fn add_item_on_tx_paths(
+        &mut self,
+        item: DownlinkItem<Dt>,
+        tx_paths: &[TxPath],
+    ) -> &mut Self {
+        if tx_paths.is_empty() {
+            return self.add_item(item);
+        }
+        self.add_items(
+            tx_paths
+                .iter()
+                .map(|tx_path| item.on_tx_path(*tx_path))
+                .collect(),
+        )
+    }
+
+    /// Creates a [`Downlink`] with a single item.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the item is invalid.
+    pub fn single_item(
+        gateway_id: GatewayId,
+        downlink_id: u32,
+        item: DownlinkItem<Dt>,
+    ) -> Result<Downlink<Dt>, DownlinkBuilderError> {
+        Self::new()
+            .gateway_id(gateway_id)
+            .downlink_id(downlink_id)
+            .add_item(item)
+            .build()
+    }
+
     /// Builds the [`Downlink`].
     ///
     /// # Errors
     ///
-    /// Returns an error if a required parameter is missing.
+    /// Returns an error if:
+    /// - a required parameter is missing.
+    /// - there are no items or more than [`MAX_DOWNLINK_ITEMS`] items.
+    /// - the items carry contexts of different uplinks.
+    /// - the items are not sorted by preference, i.e. by ascending delay or GPS time.
     pub fn build(&mut self) -> Result<Downlink<Dt>, DownlinkBuilderError> {
         if self.items.is_none() {
             return Err(DownlinkBuilderError::MissingParameter {
@@ -97,6 +143,7 @@ where
                 missing: "gateway_id".to_owned(),
             });
         }
+        self.validate()?;
 
         Ok(Downlink {
             gateway_id: self
@@ -112,4 +159,50 @@ where
                 .expect("This can't happen, variable is checked for None before."),
         })
     }
+
+    /// Validates the consistency of the items.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any check of [`DownlinkBuilder::build`] fails.
+    fn validate(&self) -> Result<(), DownlinkBuilderError> {
+        let items = self.items.as_deref().unwrap_or_default();
+        if items.is_empty() {
+            return Err(DownlinkBuilderError::NoItems);
+        }
+        if items.len() > MAX_DOWNLINK_ITEMS {
+            return Err(DownlinkBuilderError::TooManyItems {
+                amount: items.len(),
+                max: MAX_DOWNLINK_ITEMS,
+            });
+        }
+
+        let mut contexts = items
+            .iter()
+            .filter_map(|item| item.tx_info.context.as_ref());
+        if let Some(first_context) = contexts.next() {
+            if contexts.any(|context| context != first_context) {
+                return Err(DownlinkBuilderError::InconsistentContext);
+            }
+        }
+
+        for (index, pair) in items.windows(2).enumerate() {
+            let (previous, next) = (&pair[0].tx_info, &pair[1].tx_info);
+            let delay_descending = match (&previous.delay_timing_info, &next.delay_timing_info) {
+                (Some(previous), Some(next)) => next.delay < previous.delay,
+                _ => false,
+            };
+            let gps_time_descending =
+                match (&previous.gps_epoch_timing_info, &next.gps_epoch_timing_info) {
+                    (Some(previous), Some(next)) => {
+                        next.time_since_gps_epoch < previous.time_since_gps_epoch
+                    }
+                    _ => false,
+                };
+            if delay_descending || gps_time_descending {
+                return Err(DownlinkBuilderError::ItemsNotSorted { index: index + 1 });
+            }
+        }
+        Ok(())
+    }
 }
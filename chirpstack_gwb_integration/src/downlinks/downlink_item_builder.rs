@@ -1,7 +1,7 @@
 //! Builders for downlink items.
 
 use crate::downlinks::predefined_parameters::{
-    Bandwidth, CodingRate, DataRate, Frequency, SpreadingFactor,
+    check_payload_size, Bandwidth, CodingRate, DataRate, Frequency, SpreadingFactor,
 };
 use crate::downlinks::{
     DelayTimingClassA, DelayTimingInfo, DownlinkItem, DownlinkType, GpsEpochTimingInfo,
@@ -79,11 +79,7 @@ where
 
     /// Sets frequency.
     pub fn frequency(&mut self, frequency: Frequency) -> &mut Self {
-        match frequency {
-            Frequency::Freq868_1 => self.frequency_raw(868_100_000),
-            Frequency::Freq868_3 => self.frequency_raw(868_300_000),
-            Frequency::Freq868_5 => self.frequency_raw(868_500_000),
-        }
+        self.frequency_raw(frequency.hz())
     }
 
     /// Sets power.
@@ -262,15 +258,14 @@ where
 
         // Payload size checking is only enabled if `self.data_rate` is set.
         if self.data_rate.is_some() {
-            self.data_rate
-                .as_ref()
-                .expect("This can't happen, data_rate is checked for None before.")
-                .check_payload_size(
-                    self.phy_payload
-                        .as_ref()
-                        .expect("This can't happen, phy_payload is checked for None before.")
-                        .len(),
-                )?;
+            check_payload_size(
+                self.data_rate
+                    .expect("This can't happen, data_rate is checked for None before."),
+                self.phy_payload
+                    .as_ref()
+                    .expect("This can't happen, phy_payload is checked for None before.")
+                    .len(),
+            )?;
         }
 
         Ok(())
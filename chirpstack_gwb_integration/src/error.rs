@@ -1,4 +1,8 @@
 //! All errors for this crate.
+use crate::downlinks::predefined_parameters::DataRate;
+pub use lorawan_parameters::error::{
+    BandwidthConversionError, DataRateConversionError, SpreadingFactorConversionError,
+};
 use thiserror::Error;
 use uuid::Uuid;
 
@@ -48,6 +52,15 @@ pub enum CallbackRemoveError {
     NoSuchCallback { uuid: Uuid },
 }
 
+/// Errors occurring when parsing a [`GatewayId`](crate::gateway_id::GatewayId).
+#[allow(missing_docs)]
+#[allow(clippy::missing_docs_in_private_items)]
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum GatewayIdError {
+    #[error("Invalid gateway ID, expected 16 hex digits: {gateway_id}")]
+    Invalid { gateway_id: String },
+}
+
 /// Errors occurring while parsing MQTT topic strings.
 #[allow(missing_docs)]
 #[allow(clippy::missing_docs_in_private_items)]
@@ -69,6 +82,21 @@ pub enum TopicParsingError {
     TooShort { length: usize },
     #[error("No \"gateway\" marker was found.")]
     NoGatewayMarker,
+    #[error("Topic does not match the topic layout: {topic}")]
+    LayoutMismatch { topic: String },
+    #[error("Gateway ID error: {0}")]
+    GatewayId(#[from] GatewayIdError),
+}
+
+/// Errors occurring when decoding MQTT payloads.
+#[allow(missing_docs)]
+#[allow(clippy::missing_docs_in_private_items)]
+#[derive(Error, Debug)]
+pub enum PayloadDecodeError {
+    #[error("Protobuf decode error: {0}")]
+    Protobuf(#[from] prost::DecodeError),
+    #[error("JSON decode error: {0}")]
+    Json(#[from] serde_json::Error),
 }
 
 /// Errors returned by the runtime.
@@ -90,6 +118,123 @@ pub enum RuntimeError {
     Stopped,
     #[error("Rumqttc client error: {0}")]
     RumqttcClient(#[from] rumqttc::ClientError),
+    #[error("Rumqttc MQTT v5 client error: {0}")]
+    RumqttcV5Client(#[from] rumqttc::v5::ClientError),
+    #[error("Gateway time error: {0}")]
+    GatewayTime(#[from] GatewayTimeError),
+    #[error("Payload encode error: {0}")]
+    PayloadEncode(#[from] serde_json::Error),
+    #[error("Enqueue error: {0}")]
+    Enqueue(#[from] EnqueueError),
+    #[error("Broker ID is used twice: {broker_id}")]
+    DuplicateBroker { broker_id: String },
+}
+
+/// Errors occurring when enqueuing a downlink, distinguished so callers can decide whether to
+/// retry, pick another gateway or data rate, or drop the downlink.
+#[allow(missing_docs)]
+#[allow(clippy::missing_docs_in_private_items)]
+#[derive(Error, Debug)]
+pub enum EnqueueError {
+    #[error("Runtime is stopped")]
+    Stopped,
+    #[error("Not connected to the MQTT broker")]
+    NotConnected,
+    #[error("Payload of {size} bytes exceeds the maximum of {max} bytes of {data_rate:?}")]
+    PayloadTooLarge {
+        size: usize,
+        max: usize,
+        data_rate: DataRate,
+    },
+    #[error("Frequency {frequency} Hz is outside of the EU863-870 band")]
+    FrequencyNotAllowed { frequency: u32 },
+    #[error("Downlink serialization error: {0}")]
+    Serialization(#[from] serde_json::Error),
+    #[error("MQTT request queue is full")]
+    BrokerBackPressure,
+    #[error("No broker with ID: {broker_id}")]
+    NoSuchBroker { broker_id: String },
+}
+
+impl EnqueueError {
+    /// Returns whether enqueuing the same downlink again later can succeed.
+    #[must_use]
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            EnqueueError::NotConnected | EnqueueError::BrokerBackPressure
+        )
+    }
+
+    /// Returns the name of the error variant, e.g. to label metrics.
+    #[must_use]
+    pub fn kind(&self) -> &'static str {
+        match self {
+            EnqueueError::Stopped => "stopped",
+            EnqueueError::NotConnected => "not_connected",
+            EnqueueError::PayloadTooLarge { .. } => "payload_too_large",
+            EnqueueError::FrequencyNotAllowed { .. } => "frequency_not_allowed",
+            EnqueueError::Serialization(_) => "serialization",
+            EnqueueError::BrokerBackPressure => "broker_back_pressure",
+            EnqueueError::NoSuchBroker { .. } => "no_such_broker",
+        }
+    }
+}
+
+impl From<rumqttc::ClientError> for EnqueueError {
+    fn from(err: rumqttc::ClientError) -> Self {
+        // The request channel is only closed once the event loop stopped, a failed non-blocking
+        // request of a running event loop means the channel is full.
+        match err {
+            rumqttc::ClientError::TryRequest(_) => EnqueueError::BrokerBackPressure,
+            rumqttc::ClientError::Request(_) => EnqueueError::Stopped,
+        }
+    }
+}
+
+impl From<rumqttc::v5::ClientError> for EnqueueError {
+    fn from(err: rumqttc::v5::ClientError) -> Self {
+        // Same as for MQTT v3.1.1, see above.
+        match err {
+            rumqttc::v5::ClientError::TryRequest(_) => EnqueueError::BrokerBackPressure,
+            rumqttc::v5::ClientError::Request(_) => EnqueueError::Stopped,
+        }
+    }
+}
+
+/// Errors of the connection to the MQTT broker, the event loop reconnects on its own.
+#[allow(missing_docs)]
+#[derive(Error, Debug)]
+pub enum MqttConnectionError {
+    #[error(transparent)]
+    V4(#[from] rumqttc::ConnectionError),
+    #[error(transparent)]
+    V5(#[from] rumqttc::v5::ConnectionError),
+}
+
+/// Errors occurring when converting times for GPS epoch based downlinks.
+#[allow(missing_docs)]
+#[allow(clippy::missing_docs_in_private_items)]
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum GatewayTimeError {
+    #[error("Time lies before the GPS epoch")]
+    BeforeGpsEpoch,
+}
+
+/// Errors occurring when setting up logging.
+#[cfg(feature = "logging")]
+#[allow(missing_docs)]
+#[allow(clippy::missing_docs_in_private_items)]
+#[derive(Error, Debug)]
+pub enum LoggingError {
+    #[error("Invalid filter directives: {0}")]
+    FilterDirectives(#[from] tracing_subscriber::filter::ParseError),
+    #[error("Failed to open log sink: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Failed to install global subscriber: {0}")]
+    Init(#[from] tracing_subscriber::util::TryInitError),
+    #[error("Logging to {sink} is only supported on unix")]
+    UnsupportedSink { sink: &'static str },
 }
 
 /// Errors occurring when creating downlink items.
@@ -110,34 +255,29 @@ pub enum DownlinkItemBuilderError {
 pub enum DownlinkBuilderError {
     #[error("Missing parameter: {missing}")]
     MissingParameter { missing: String },
+    #[error("Downlink has no items")]
+    NoItems,
+    #[error("Downlink has {amount} items, at most {max} are allowed")]
+    TooManyItems { amount: usize, max: usize },
+    #[error("Downlink items carry contexts of different uplinks")]
+    InconsistentContext,
+    #[error("Downlink item {index} is scheduled before the preceding item")]
+    ItemsNotSorted { index: usize },
 }
 
-/// Errors occurring when converting from bandwidth and spreading factor to data rate.
+/// Errors occurring when extracting the [`UplinkInfo`](crate::uplinks::UplinkInfo) of an uplink.
 #[allow(missing_docs)]
 #[allow(clippy::missing_docs_in_private_items)]
 #[derive(Error, Debug, Clone, PartialEq, Eq)]
-pub enum DataRateConversionError {
-    #[error("Parameters do not match any data rate, bandwidth: {bandwidth} spreading_factor: {spreading_factor}")]
-    WrongParameters {
-        bandwidth: u32,
-        spreading_factor: u32,
-    },
-}
-
-/// Errors occurring when converting an integer to a [`SpreadingFactor`](crate::downlinks::predefined_parameters::SpreadingFactor).
-#[allow(missing_docs)]
-#[allow(clippy::missing_docs_in_private_items)]
-#[derive(Error, Debug, Clone, PartialEq, Eq)]
-pub enum SpreadingFactorConversionError {
-    #[error("Parameter does not match any spreading factor: {spreading_factor}")]
-    NoSuchSpreadingFactor { spreading_factor: u32 },
-}
-
-/// Errors occurring when converting from integer to a [`Bandwidth`](crate::downlinks::predefined_parameters::Bandwidth).
-#[allow(missing_docs)]
-#[allow(clippy::missing_docs_in_private_items)]
-#[derive(Error, Debug, Clone, PartialEq, Eq)]
-pub enum BandwidthConversionError {
-    #[error("Parameter does not match any bandwidth: {bandwidth}")]
-    NoSuchBandwidth { bandwidth: u32 },
+pub enum UplinkInfoError {
+    #[error("No TX info in uplink")]
+    NoTxInfo,
+    #[error("No RX info in uplink")]
+    NoRxInfo,
+    #[error("No modulation info in uplink")]
+    NoModulationInfo,
+    #[error("No LoRa parameters in modulation of uplink")]
+    NoLoRaParameters,
+    #[error("Data rate conversion error: {0}")]
+    DataRate(#[from] DataRateConversionError),
 }
@@ -0,0 +1,185 @@
+//! Canonical gateway IDs.
+//!
+//! Gateway IDs are 64 bit EUIs. The ChirpStack API and the gateway bridge use lowercase hex
+//! strings, while MQTT topics of other bridges, configurations or users may use uppercase
+//! letters, a `0x` prefix or separators like `-` and `:`. Every gateway ID is parsed into its
+//! canonical lowercase form once, so IDs of the same gateway always compare equal and callbacks
+//! registered for a gateway are not missed.
+
+use crate::error::GatewayIdError;
+use schemars::JsonSchema;
+use serde_derive::{Deserialize, Serialize};
+use std::borrow::Borrow;
+use std::fmt;
+use std::ops::Deref;
+use std::str::FromStr;
+
+/// Characters separating the bytes of a gateway ID, removed when parsing.
+const SEPARATORS: [char; 4] = ['-', ':', ' ', '_'];
+
+/// Canonical gateway ID, 16 lowercase hex digits.
+///
+/// Serialized as string, deserializing normalizes the ID like parsing it.
+#[derive(
+    Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, JsonSchema,
+)]
+#[serde(try_from = "String", into = "String")]
+#[schemars(transparent)]
+pub struct GatewayId(String);
+
+impl GatewayId {
+    /// Returns the canonical string representation.
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for GatewayId {
+    type Err = GatewayIdError;
+
+    /// Parses a gateway ID ignoring the letter case, a `0x` prefix and separators.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the ID without prefix and separators is not 16 hex digits.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let trimmed = value.trim();
+        let digits = trimmed
+            .strip_prefix("0x")
+            .or_else(|| trimmed.strip_prefix("0X"))
+            .unwrap_or(trimmed);
+        let normalized: String = digits
+            .chars()
+            .filter(|c| !SEPARATORS.contains(c))
+            .map(|c| c.to_ascii_lowercase())
+            .collect();
+        if normalized.len() != 16 || !normalized.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(GatewayIdError::Invalid {
+                gateway_id: value.to_owned(),
+            });
+        }
+        Ok(Self(normalized))
+    }
+}
+
+impl TryFrom<&str> for GatewayId {
+    type Error = GatewayIdError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl TryFrom<String> for GatewayId {
+    type Error = GatewayIdError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<[u8; 8]> for GatewayId {
+    /// Encodes the bytes of the EUI in the order they were received, e.g. from a packet
+    /// forwarder.
+    fn from(eui: [u8; 8]) -> Self {
+        Self(hex::encode(eui))
+    }
+}
+
+impl From<GatewayId> for String {
+    fn from(gateway_id: GatewayId) -> Self {
+        gateway_id.0
+    }
+}
+
+impl fmt::Display for GatewayId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Deref for GatewayId {
+    type Target = str;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl AsRef<str> for GatewayId {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Borrow<str> for GatewayId {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PartialEq<str> for GatewayId {
+    fn eq(&self, other: &str) -> bool {
+        self.0 == other
+    }
+}
+
+impl PartialEq<&str> for GatewayId {
+    fn eq(&self, other: &&str) -> bool {
+        self.0 == *other
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::GatewayIdError;
+    use crate::gateway_id::GatewayId;
+    use std::collections::HashMap;
+
+    #[test]
+    fn gateway_ids_are_normalized() {
+        let canonical: GatewayId = "a840411d25244150".parse().unwrap();
+        for variant in [
+            "A840411D25244150",
+            "0xA840411d25244150",
+            "a8-40-41-1d-25-24-41-50",
+            "A8:40:41:1D:25:24:41:50",
+            " a840411d25244150\n",
+        ] {
+            assert_eq!(variant.parse::<GatewayId>().unwrap(), canonical);
+        }
+        assert_eq!(canonical.to_string(), "a840411d25244150");
+        assert_eq!(canonical, "a840411d25244150");
+        assert_eq!(
+            GatewayId::from([0xA8, 0x40, 0x41, 0x1D, 0x25, 0x24, 0x41, 0x50]),
+            canonical
+        );
+
+        let callbacks = HashMap::from([(canonical.clone(), 1)]);
+        assert_eq!(callbacks.get("a840411d25244150"), Some(&1));
+
+        assert_eq!(
+            "gateway".parse::<GatewayId>(),
+            Err(GatewayIdError::Invalid {
+                gateway_id: "gateway".to_owned()
+            })
+        );
+        assert!("a840411d2524415".parse::<GatewayId>().is_err());
+        assert!("a840411d2524415g".parse::<GatewayId>().is_err());
+    }
+
+    #[test]
+    fn gateway_ids_are_serialized_as_strings() {
+        let gateway_id: GatewayId = serde_json::from_str("\"A840411D25244150\"").unwrap();
+        assert_eq!(gateway_id.as_str(), "a840411d25244150");
+        assert_eq!(
+            serde_json::to_string(&gateway_id).unwrap(),
+            "\"a840411d25244150\""
+        );
+        let counters: HashMap<GatewayId, u32> =
+            serde_json::from_str("{\"A840411D25244150\": 3}").unwrap();
+        assert_eq!(counters.get("a840411d25244150"), Some(&3));
+        assert!(serde_json::from_str::<GatewayId>("\"gateway\"").is_err());
+    }
+}
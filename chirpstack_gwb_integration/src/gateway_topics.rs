@@ -1,11 +1,22 @@
 //! ChirpStack MQTT topic parsing.
 
 use crate::error::TopicParsingError;
+use crate::gateway_id::GatewayId;
+use schemars::JsonSchema;
+use serde_derive::{Deserialize, Serialize};
+
+/// Placeholder for the gateway ID in a [`TopicLayout::Custom`] template.
+const GATEWAY_ID_PLACEHOLDER: &str = "{gateway_id}";
+/// Placeholder for the topic type (`event`, `command` or `state`) in a [`TopicLayout::Custom`]
+/// template.
+const TYPE_PLACEHOLDER: &str = "{type}";
+/// Placeholder for the topic sub type, e.g. `up`, in a [`TopicLayout::Custom`] template.
+const SUB_TYPE_PLACEHOLDER: &str = "{sub_type}";
 
 /// LoRaWAN regions.
 #[allow(missing_docs)]
 #[allow(clippy::missing_docs_in_private_items)]
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub enum LoRaWanRegion {
     As923,
     As923_2,
@@ -47,6 +58,28 @@ impl TryFrom<&str> for LoRaWanRegion {
     }
 }
 
+impl LoRaWanRegion {
+    /// Returns the region as used in the MQTT topics.
+    #[must_use]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LoRaWanRegion::As923 => "as923",
+            LoRaWanRegion::As923_2 => "as923-2",
+            LoRaWanRegion::As923_3 => "as923-3",
+            LoRaWanRegion::As923_4 => "as923-4",
+            LoRaWanRegion::Au915 => "au915",
+            LoRaWanRegion::Cn470 => "cn470",
+            LoRaWanRegion::Eu433 => "eu433",
+            LoRaWanRegion::Eu868 => "eu868",
+            LoRaWanRegion::In865 => "in865",
+            LoRaWanRegion::Kr920 => "kr920",
+            LoRaWanRegion::Ru864 => "ru864",
+            LoRaWanRegion::Us915 => "us915",
+            LoRaWanRegion::Ism2400 => "ism2400",
+        }
+    }
+}
+
 /// MQTT Topic types
 #[allow(missing_docs)]
 #[allow(clippy::missing_docs_in_private_items)]
@@ -57,6 +90,18 @@ pub enum TopicType {
     Command(CommandType),
 }
 
+impl TopicType {
+    /// Returns the topic type and sub type as used in the MQTT topics.
+    #[must_use]
+    pub fn as_strs(&self) -> (&'static str, &'static str) {
+        match self {
+            TopicType::Event(event_type) => ("event", event_type.as_str()),
+            TopicType::State(state_type) => ("state", state_type.as_str()),
+            TopicType::Command(command_type) => ("command", command_type.as_str()),
+        }
+    }
+}
+
 impl TryFrom<(&str, &str)> for TopicType {
     type Error = TopicParsingError;
 
@@ -85,6 +130,20 @@ pub enum EventType {
     Raw,
 }
 
+impl EventType {
+    /// Returns the event type as used in the MQTT topics.
+    #[must_use]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventType::Stats => "stats",
+            EventType::Up => "up",
+            EventType::Ack => "ack",
+            EventType::Exec => "exec",
+            EventType::Raw => "raw",
+        }
+    }
+}
+
 impl TryFrom<&str> for EventType {
     type Error = TopicParsingError;
 
@@ -111,6 +170,16 @@ pub enum StateType {
     Conn,
 }
 
+impl StateType {
+    /// Returns the state type as used in the MQTT topics.
+    #[must_use]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StateType::Conn => "conn",
+        }
+    }
+}
+
 impl TryFrom<&str> for StateType {
     type Error = TopicParsingError;
 
@@ -135,6 +204,19 @@ pub enum CommandType {
     Raw,
 }
 
+impl CommandType {
+    /// Returns the command type as used in the MQTT topics.
+    #[must_use]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CommandType::Down => "down",
+            CommandType::Config => "config",
+            CommandType::Exec => "exec",
+            CommandType::Raw => "raw",
+        }
+    }
+}
+
 impl TryFrom<&str> for CommandType {
     type Error = TopicParsingError;
 
@@ -154,10 +236,10 @@ impl TryFrom<&str> for CommandType {
 /// Parsed topic information.
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct ParsedTopic {
-    /// The region.
-    pub region: LoRaWanRegion,
+    /// The region, only part of the topics of the [`TopicLayout::V4`] layout.
+    pub region: Option<LoRaWanRegion>,
     /// The gateway ID.
-    pub gateway_id: String,
+    pub gateway_id: GatewayId,
     /// The type of topic.
     pub topic_type: TopicType,
 }
@@ -182,37 +264,167 @@ impl TryFrom<&str> for ParsedTopic {
         if *split_topic.get(1).expect("Length was checked to be 5.") != "gateway" {
             return Err(TopicParsingError::NoGatewayMarker);
         }
-        let gateway_id = (*split_topic.get(2).expect("Length was checked to be 5.")).to_owned();
+        let gateway_id = split_topic
+            .get(2)
+            .expect("Length was checked to be 5.")
+            .parse()?;
         let topic_type = TopicType::try_from((
             *split_topic.get(3).expect("Length was checked to be 5."),
             *split_topic.get(4).expect("Length was checked to be 5."),
         ))?;
 
         Ok(Self {
-            region,
+            region: Some(region),
             gateway_id,
             topic_type,
         })
     }
 }
 
+/// Layout of the MQTT topics used by the gateway bridge.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum TopicLayout {
+    /// `gateway/{gateway_id}/{type}/{sub_type}`, used by gateway bridge v3.
+    V3,
+    /// `{region}/gateway/{gateway_id}/{type}/{sub_type}`, used by gateway bridge v4.
+    V4(LoRaWanRegion),
+    /// Custom template separated by `/` containing the `{gateway_id}`, `{type}` and `{sub_type}`
+    /// placeholders, every other segment has to match literally.
+    Custom(String),
+}
+
+impl Default for TopicLayout {
+    fn default() -> Self {
+        TopicLayout::V4(LoRaWanRegion::Eu868)
+    }
+}
+
+impl TopicLayout {
+    /// Returns the topic of the topic type for the gateway.
+    #[must_use]
+    pub fn topic(&self, gateway_id: &GatewayId, topic_type: TopicType) -> String {
+        let (topic_type, topic_sub_type) = topic_type.as_strs();
+        self.fill(gateway_id, topic_type, topic_sub_type)
+    }
+
+    /// Returns the subscription for all gateways and sub types of the topic type, e.g. `event`, or
+    /// of every topic type if it is `+`.
+    #[must_use]
+    pub fn subscription(&self, topic_type: &str) -> String {
+        self.fill("+", topic_type, "+")
+    }
+
+    /// Replaces the placeholders of the layout.
+    fn fill(&self, gateway_id: &str, topic_type: &str, topic_sub_type: &str) -> String {
+        match self {
+            TopicLayout::V3 => format!("gateway/{gateway_id}/{topic_type}/{topic_sub_type}"),
+            TopicLayout::V4(region) => format!(
+                "{}/gateway/{gateway_id}/{topic_type}/{topic_sub_type}",
+                region.as_str()
+            ),
+            TopicLayout::Custom(template) => template
+                .replace(GATEWAY_ID_PLACEHOLDER, gateway_id)
+                .replace(TYPE_PLACEHOLDER, topic_type)
+                .replace(SUB_TYPE_PLACEHOLDER, topic_sub_type),
+        }
+    }
+
+    /// Parses a topic of this layout, the gateway ID is normalized.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the topic does not match the layout or contains unknown types or an
+    /// invalid gateway ID.
+    pub fn parse(&self, topic: &str) -> Result<ParsedTopic, TopicParsingError> {
+        match self {
+            TopicLayout::V4(_) => ParsedTopic::try_from(topic),
+            TopicLayout::V3 => {
+                Self::parse_template("gateway/{gateway_id}/{type}/{sub_type}", topic)
+            }
+            TopicLayout::Custom(template) => Self::parse_template(template, topic),
+        }
+    }
+
+    /// Parses a topic by matching it segment by segment against the template.
+    fn parse_template(template: &str, topic: &str) -> Result<ParsedTopic, TopicParsingError> {
+        let template_segments: Vec<&str> = template.split('/').collect();
+        let topic_segments: Vec<&str> = topic.split('/').collect();
+        if template_segments.len() != topic_segments.len() {
+            return Err(TopicParsingError::LayoutMismatch {
+                topic: topic.to_owned(),
+            });
+        }
+
+        let mut gateway_id = None;
+        let mut topic_type = None;
+        let mut topic_sub_type = None;
+        for (template_segment, topic_segment) in template_segments.into_iter().zip(topic_segments) {
+            match template_segment {
+                GATEWAY_ID_PLACEHOLDER => gateway_id = Some(topic_segment),
+                TYPE_PLACEHOLDER => topic_type = Some(topic_segment),
+                SUB_TYPE_PLACEHOLDER => topic_sub_type = Some(topic_segment),
+                literal if literal == topic_segment => {}
+                _ => {
+                    return Err(TopicParsingError::LayoutMismatch {
+                        topic: topic.to_owned(),
+                    })
+                }
+            }
+        }
+
+        let (Some(gateway_id), Some(topic_type), Some(topic_sub_type)) =
+            (gateway_id, topic_type, topic_sub_type)
+        else {
+            return Err(TopicParsingError::LayoutMismatch {
+                topic: topic.to_owned(),
+            });
+        };
+        Ok(ParsedTopic {
+            region: None,
+            gateway_id: gateway_id.parse()?,
+            topic_type: TopicType::try_from((topic_type, topic_sub_type))?,
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::error::TopicParsingError;
-    use crate::gateway_topics::{CommandType, LoRaWanRegion, ParsedTopic, TopicType};
+    use crate::error::{GatewayIdError, TopicParsingError};
+    use crate::gateway_id::GatewayId;
+    use crate::gateway_topics::{
+        CommandType, EventType, LoRaWanRegion, ParsedTopic, TopicLayout, TopicType,
+    };
 
     #[test]
     fn parse_topic() {
         let topic = "eu868/gateway/ac1f09fffe060970/command/down";
         let parsed_topic: ParsedTopic = topic.try_into().unwrap();
         let expected_parse_topic = ParsedTopic {
-            region: LoRaWanRegion::Eu868,
-            gateway_id: "ac1f09fffe060970".to_string(),
+            region: Some(LoRaWanRegion::Eu868),
+            gateway_id: "ac1f09fffe060970".parse().unwrap(),
             topic_type: TopicType::Command(CommandType::Down),
         };
         assert_eq!(parsed_topic, expected_parse_topic);
     }
 
+    #[test]
+    fn parse_topic_normalizes_gateway_id() {
+        let parsed_topic: ParsedTopic = "eu868/gateway/AC1F09FFFE060970/event/up"
+            .try_into()
+            .unwrap();
+        assert_eq!(parsed_topic.gateway_id, "ac1f09fffe060970");
+        assert_eq!(
+            TopicLayout::V3.parse("gateway/AC1F09FFFE060970/event/up"),
+            TopicLayout::V3.parse("gateway/ac1f09fffe060970/event/up")
+        );
+        assert_eq!(
+            ParsedTopic::try_from("eu868/gateway/gw-1/event/up"),
+            Err(TopicParsingError::GatewayId(GatewayIdError::Invalid {
+                gateway_id: "gw-1".to_owned()
+            }))
+        );
+    }
+
     #[test]
     fn parse_topic_wrong_region() {
         let topic = "eu68/gateway/ac1f09fffe060970/command/down";
@@ -282,4 +494,58 @@ mod tests {
             _ => panic!("Wrong error returned."),
         }
     }
+
+    #[test]
+    fn topic_layouts() {
+        let v3 = TopicLayout::V3;
+        assert_eq!(v3.subscription("event"), "gateway/+/event/+");
+        assert_eq!(v3.subscription("+"), "gateway/+/+/+");
+        let gateway_id: GatewayId = "ac1f09fffe060970".parse().unwrap();
+        assert_eq!(
+            v3.topic(&gateway_id, TopicType::Command(CommandType::Down)),
+            "gateway/ac1f09fffe060970/command/down"
+        );
+        assert_eq!(
+            v3.parse("gateway/ac1f09fffe060970/event/up"),
+            Ok(ParsedTopic {
+                region: None,
+                gateway_id: gateway_id.clone(),
+                topic_type: TopicType::Event(EventType::Up),
+            })
+        );
+        assert!(v3.parse("eu868/gateway/ac1f09fffe060970/event/up").is_err());
+
+        let v4 = TopicLayout::V4(LoRaWanRegion::Us915);
+        assert_eq!(v4.subscription("command"), "us915/gateway/+/command/+");
+        assert_eq!(
+            v4.topic(&gateway_id, TopicType::Command(CommandType::Down)),
+            "us915/gateway/ac1f09fffe060970/command/down"
+        );
+        assert_eq!(
+            v4.parse("us915/gateway/ac1f09fffe060970/event/up")
+                .map(|parsed_topic| parsed_topic.region),
+            Ok(Some(LoRaWanRegion::Us915))
+        );
+
+        let custom = TopicLayout::Custom("site-a/{gateway_id}/{type}/{sub_type}".to_string());
+        assert_eq!(custom.subscription("state"), "site-a/+/state/+");
+        assert_eq!(
+            custom.parse("site-a/ac1f09fffe060970/event/ack"),
+            Ok(ParsedTopic {
+                region: None,
+                gateway_id,
+                topic_type: TopicType::Event(EventType::Ack),
+            })
+        );
+        match custom
+            .parse("site-b/ac1f09fffe060970/event/ack")
+            .err()
+            .unwrap()
+        {
+            TopicParsingError::LayoutMismatch { topic } => {
+                assert_eq!(topic, "site-b/ac1f09fffe060970/event/ack");
+            }
+            _ => panic!("Wrong error returned."),
+        }
+    }
 }
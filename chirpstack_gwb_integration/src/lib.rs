@@ -14,5 +14,9 @@
 
 pub mod downlinks;
 pub mod error;
+pub mod gateway_id;
 pub mod gateway_topics;
+#[cfg(feature = "logging")]
+pub mod logging;
 pub mod runtime;
+pub mod uplinks;
@@ -0,0 +1,277 @@
+//! Configuration driven logging setup shared by the applications of this workspace.
+//!
+//! Supports logging to stdout, size and/or time based rotating files, syslog and journald, each
+//! with the same filter directives and per-module level overrides. Syslog and journald are only
+//! available on unix. Requires the `logging` feature.
+
+use crate::error::LoggingError;
+use crate::logging::rotating_file::RotatingFileWriter;
+#[cfg(unix)]
+use crate::logging::syslog::SyslogMakeWriter;
+use schemars::JsonSchema;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
+
+pub mod rotating_file;
+#[cfg(unix)]
+pub mod syslog;
+
+/// Logging configuration.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct LoggingConfig {
+    /// Filter directives applied to all sinks, e.g. `"spatz=info"`. Overridden by `RUST_LOG` if
+    /// set, the default directives of the application are used if neither is set.
+    #[serde(default)]
+    pub directives: Option<String>,
+    /// Per-module level overrides, e.g. `"spatz::uplink_processing" = "trace"`.
+    #[serde(default)]
+    pub module_levels: BTreeMap<String, String>,
+    /// Disables logging to stdout.
+    #[serde(default)]
+    pub disable_stdout: bool,
+    /// Logging into rotating files, disabled if not set.
+    #[serde(default)]
+    pub file: Option<FileSinkConfig>,
+    /// Logging to the local syslog daemon, disabled if not set.
+    #[serde(default)]
+    pub syslog: Option<SyslogSinkConfig>,
+    /// Logging to journald, disabled if not set.
+    #[serde(default)]
+    pub journald: Option<JournaldSinkConfig>,
+}
+
+/// Rotating log file configuration.
+///
+/// The current log file is rotated if it exceeds `max_size_bytes` or a new `rotation` period
+/// starts, whichever happens first.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct FileSinkConfig {
+    /// Path of the current log file, rotated files get the suffix `.1`, `.2`, ...
+    pub path: String,
+    /// Size in bytes after which the log file is rotated.
+    #[serde(default)]
+    pub max_size_bytes: Option<u64>,
+    /// Time based rotation.
+    #[serde(default)]
+    pub rotation: Option<FileRotation>,
+    /// Amount of rotated log files kept besides the current log file.
+    pub max_files: usize,
+}
+
+/// Time based log file rotation periods.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum FileRotation {
+    /// Rotate at the start of every hour.
+    Hourly,
+    /// Rotate at the start of every day (UTC).
+    Daily,
+}
+
+impl FileRotation {
+    /// Length of the rotation period in seconds.
+    #[must_use]
+    pub fn period_seconds(self) -> u64 {
+        match self {
+            FileRotation::Hourly => 3600,
+            FileRotation::Daily => 86_400,
+        }
+    }
+}
+
+/// Syslog configuration.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct SyslogSinkConfig {
+    /// Identity prepended to every message.
+    pub identity: String,
+    /// Path of the syslog socket, defaults to `/dev/log`.
+    #[serde(default)]
+    pub socket_path: Option<String>,
+}
+
+/// Journald configuration.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct JournaldSinkConfig {
+    /// Syslog identifier of the journal entries, defaults to the executable name.
+    #[serde(default)]
+    pub identifier: Option<String>,
+}
+
+impl LoggingConfig {
+    /// Returns the filter directives including the per-module level overrides.
+    ///
+    /// `RUST_LOG` takes precedence over the configured directives, which take precedence over
+    /// `default_directives`.
+    #[must_use]
+    pub fn filter_directives(&self, default_directives: &str) -> String {
+        let base = std::env::var("RUST_LOG")
+            .ok()
+            .or_else(|| self.directives.clone())
+            .unwrap_or_else(|| default_directives.to_owned());
+        self.append_module_levels(base)
+    }
+
+    /// Checks whether the configured directives and per-module level overrides can be parsed,
+    /// ignoring `RUST_LOG`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the filter directives cannot be parsed.
+    pub fn validate_directives(&self) -> Result<(), LoggingError> {
+        let directives = self.append_module_levels(self.directives.clone().unwrap_or_default());
+        EnvFilter::try_new(directives)?;
+        Ok(())
+    }
+
+    /// Appends the per-module level overrides to the directives.
+    fn append_module_levels(&self, base: String) -> String {
+        self.module_levels
+            .iter()
+            .fold(base, |mut directives, (module, level)| {
+                if !directives.is_empty() {
+                    directives.push(',');
+                }
+                directives.push_str(module);
+                directives.push('=');
+                directives.push_str(level);
+                directives
+            })
+    }
+}
+
+/// Installs the global tracing subscriber with all sinks of the configuration.
+///
+/// # Errors
+///
+/// Returns an error if:
+/// - the filter directives cannot be parsed.
+/// - the log file, the syslog socket or the journald socket cannot be opened.
+/// - syslog or journald is configured on a platform other than unix.
+/// - a global subscriber was already installed.
+pub fn init_logging(config: &LoggingConfig, default_directives: &str) -> Result<(), LoggingError> {
+    #[cfg(not(unix))]
+    {
+        if let Some(sink) = unsupported_sink(config) {
+            return Err(LoggingError::UnsupportedSink { sink });
+        }
+    }
+    let directives = config.filter_directives(default_directives);
+    // Every sink needs its own filter, parse once upfront to report invalid directives.
+    let filter = || EnvFilter::try_new(&directives);
+    filter()?;
+
+    let stdout_layer = if config.disable_stdout {
+        None
+    } else {
+        Some(tracing_subscriber::fmt::layer().with_filter(filter()?))
+    };
+
+    let file_layer = match &config.file {
+        Some(file_config) => Some(
+            tracing_subscriber::fmt::layer()
+                .with_ansi(false)
+                .with_writer(Mutex::new(RotatingFileWriter::new(file_config.clone())?))
+                .with_filter(filter()?),
+        ),
+        None => None,
+    };
+
+    let registry = tracing_subscriber::registry()
+        .with(stdout_layer)
+        .with(file_layer);
+
+    #[cfg(unix)]
+    let registry = {
+        let syslog_layer = match &config.syslog {
+            Some(syslog_config) => Some(
+                tracing_subscriber::fmt::layer()
+                    .with_ansi(false)
+                    .without_time()
+                    .with_writer(SyslogMakeWriter::new(syslog_config)?)
+                    .with_filter(filter()?),
+            ),
+            None => None,
+        };
+
+        let journald_layer = match &config.journald {
+            Some(journald_config) => {
+                let mut layer = tracing_journald::layer()?;
+                if let Some(identifier) = &journald_config.identifier {
+                    layer = layer.with_syslog_identifier(identifier.clone());
+                }
+                Some(layer.with_filter(filter()?))
+            }
+            None => None,
+        };
+
+        registry.with(syslog_layer).with(journald_layer)
+    };
+
+    registry.try_init()?;
+    Ok(())
+}
+
+/// Returns the first configured sink which is only available on unix.
+#[cfg_attr(unix, allow(dead_code))]
+fn unsupported_sink(config: &LoggingConfig) -> Option<&'static str> {
+    if config.syslog.is_some() {
+        Some("syslog")
+    } else if config.journald.is_some() {
+        Some("journald")
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::logging::{unsupported_sink, JournaldSinkConfig, LoggingConfig};
+
+    #[test]
+    fn test_filter_directives_with_module_levels() {
+        if std::env::var("RUST_LOG").is_ok() {
+            return;
+        }
+        let mut config = LoggingConfig {
+            directives: Some("spatz=info".to_owned()),
+            ..LoggingConfig::default()
+        };
+        config
+            .module_levels
+            .insert("spatz::uplink_processing".to_owned(), "trace".to_owned());
+        assert_eq!(
+            config.filter_directives("spatz=error"),
+            "spatz=info,spatz::uplink_processing=trace"
+        );
+
+        config.directives = None;
+        assert_eq!(
+            config.filter_directives(""),
+            "spatz::uplink_processing=trace"
+        );
+    }
+
+    #[test]
+    fn test_validate_directives() {
+        let mut config = LoggingConfig {
+            directives: Some("spatz=info".to_owned()),
+            ..LoggingConfig::default()
+        };
+        assert!(config.validate_directives().is_ok());
+        config
+            .module_levels
+            .insert("spatz::uplink_processing".to_owned(), "loud".to_owned());
+        assert!(config.validate_directives().is_err());
+    }
+
+    #[test]
+    fn test_unsupported_sink() {
+        let mut config = LoggingConfig::default();
+        assert_eq!(unsupported_sink(&config), None);
+        config.journald = Some(JournaldSinkConfig { identifier: None });
+        assert_eq!(unsupported_sink(&config), Some("journald"));
+    }
+}
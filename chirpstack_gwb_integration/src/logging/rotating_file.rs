@@ -0,0 +1,158 @@
+//! Log file writer rotating by size and/or time.
+
+use crate::logging::FileSinkConfig;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+/// Writes into a log file and rotates it according to the [`FileSinkConfig`].
+///
+/// On rotation `path.N` is renamed to `path.N+1`, `path` to `path.1` and rotated files beyond
+/// `max_files` are removed.
+#[derive(Debug)]
+pub struct RotatingFileWriter {
+    /// The file sink configuration.
+    config: FileSinkConfig,
+    /// The currently opened log file.
+    file: File,
+    /// Size of the current log file in bytes.
+    size: u64,
+    /// Rotation period the current log file was opened in.
+    period: Option<u64>,
+}
+
+impl RotatingFileWriter {
+    /// Opens the log file for appending, creates it if it does not exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the log file cannot be opened.
+    pub fn new(config: FileSinkConfig) -> std::io::Result<Self> {
+        let file = Self::open(&config.path)?;
+        let size = file.metadata()?.len();
+        let period = Self::current_period(&config);
+        Ok(Self {
+            config,
+            file,
+            size,
+            period,
+        })
+    }
+
+    /// Opens a file for appending.
+    fn open(path: &str) -> std::io::Result<File> {
+        OpenOptions::new().create(true).append(true).open(path)
+    }
+
+    /// Returns the index of the current time based rotation period.
+    fn current_period(config: &FileSinkConfig) -> Option<u64> {
+        config.rotation.map(|rotation| {
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs()
+                / rotation.period_seconds()
+        })
+    }
+
+    /// Returns the path of the rotated file with the supplied index.
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        PathBuf::from(format!("{}.{index}", self.config.path))
+    }
+
+    /// Returns whether writing `len` further bytes requires a rotation first.
+    fn needs_rotation(&self, len: usize) -> bool {
+        let size_exceeded = self
+            .config
+            .max_size_bytes
+            .is_some_and(|max_size_bytes| self.size > 0 && self.size + len as u64 > max_size_bytes);
+        size_exceeded || Self::current_period(&self.config) != self.period
+    }
+
+    /// Shifts the rotated files, moves the current file to `path.1` and opens a new file.
+    fn rotate(&mut self) -> std::io::Result<()> {
+        self.file.flush()?;
+        if self.config.max_files == 0 {
+            std::fs::remove_file(&self.config.path)?;
+        } else {
+            let oldest = self.rotated_path(self.config.max_files);
+            if oldest.exists() {
+                std::fs::remove_file(oldest)?;
+            }
+            for index in (1..self.config.max_files).rev() {
+                let from = self.rotated_path(index);
+                if from.exists() {
+                    std::fs::rename(from, self.rotated_path(index + 1))?;
+                }
+            }
+            std::fs::rename(&self.config.path, self.rotated_path(1))?;
+        }
+        self.file = Self::open(&self.config.path)?;
+        self.size = 0;
+        self.period = Self::current_period(&self.config);
+        Ok(())
+    }
+}
+
+impl Write for RotatingFileWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.needs_rotation(buf.len()) {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use crate::logging::rotating_file::RotatingFileWriter;
+    use crate::logging::FileSinkConfig;
+    use std::io::Write;
+
+    #[test]
+    fn test_rotate_by_size() {
+        let directory = std::env::temp_dir().join(format!(
+            "chirpstack_gwb_integration_log_test_{}",
+            uuid::Uuid::new_v4()
+        ));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("test.log").to_string_lossy().to_string();
+        let mut writer = RotatingFileWriter::new(FileSinkConfig {
+            path: path.clone(),
+            max_size_bytes: Some(10),
+            rotation: None,
+            max_files: 2,
+        })
+        .unwrap();
+
+        for line in [
+            "first line\n",
+            "second line\n",
+            "third line\n",
+            "fourth line\n",
+        ] {
+            writer.write_all(line.as_bytes()).unwrap();
+        }
+        writer.flush().unwrap();
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "fourth line\n");
+        assert_eq!(
+            std::fs::read_to_string(format!("{path}.1")).unwrap(),
+            "third line\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(format!("{path}.2")).unwrap(),
+            "second line\n"
+        );
+        assert!(!std::path::Path::new(&format!("{path}.3")).exists());
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
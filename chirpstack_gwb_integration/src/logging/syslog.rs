@@ -0,0 +1,116 @@
+//! Writer sending log messages to the local syslog daemon.
+
+use crate::logging::SyslogSinkConfig;
+use std::io::Write;
+use std::os::unix::net::UnixDatagram;
+use std::sync::Arc;
+use tracing::Level;
+use tracing_subscriber::fmt::MakeWriter;
+
+/// Default path of the syslog socket.
+const DEFAULT_SYSLOG_SOCKET_PATH: &str = "/dev/log";
+
+/// Syslog facility `daemon` as defined in RFC 3164.
+const SYSLOG_FACILITY_DAEMON: u8 = 3;
+
+/// Creates a [`SyslogEventWriter`] for every log event.
+#[derive(Debug, Clone)]
+pub struct SyslogMakeWriter {
+    /// Socket connected to the syslog daemon.
+    socket: Arc<UnixDatagram>,
+    /// Identity prepended to every message.
+    identity: Arc<str>,
+}
+
+impl SyslogMakeWriter {
+    /// Connects to the syslog socket.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the syslog socket cannot be connected.
+    pub fn new(config: &SyslogSinkConfig) -> std::io::Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(
+            config
+                .socket_path
+                .as_deref()
+                .unwrap_or(DEFAULT_SYSLOG_SOCKET_PATH),
+        )?;
+        Ok(Self {
+            socket: Arc::new(socket),
+            identity: Arc::from(config.identity.as_str()),
+        })
+    }
+
+    /// Creates a writer for a message with the supplied syslog severity.
+    fn writer(&self, severity: u8) -> SyslogEventWriter {
+        SyslogEventWriter {
+            socket: self.socket.clone(),
+            header: format!(
+                "<{}>{}[{}]: ",
+                SYSLOG_FACILITY_DAEMON * 8 + severity,
+                self.identity,
+                std::process::id()
+            ),
+            buffer: Vec::new(),
+        }
+    }
+}
+
+impl<'a> MakeWriter<'a> for SyslogMakeWriter {
+    type Writer = SyslogEventWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.writer(severity(Level::INFO))
+    }
+
+    fn make_writer_for(&'a self, meta: &tracing::Metadata<'_>) -> Self::Writer {
+        self.writer(severity(*meta.level()))
+    }
+}
+
+/// Maps a tracing level to a syslog severity.
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::ERROR => 3,
+        Level::WARN => 4,
+        Level::INFO => 6,
+        Level::DEBUG | Level::TRACE => 7,
+    }
+}
+
+/// Buffers a single log event and sends it to the syslog daemon when dropped.
+#[derive(Debug)]
+pub struct SyslogEventWriter {
+    /// Socket connected to the syslog daemon.
+    socket: Arc<UnixDatagram>,
+    /// Priority and identity of the message.
+    header: String,
+    /// The formatted log event.
+    buffer: Vec<u8>,
+}
+
+impl Write for SyslogEventWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for SyslogEventWriter {
+    fn drop(&mut self) {
+        let message = String::from_utf8_lossy(&self.buffer);
+        let message = message.trim_end();
+        if message.is_empty() {
+            return;
+        }
+        // Logging errors cannot be logged, the message is dropped.
+        let _ = self
+            .socket
+            .send(format!("{}{message}", self.header).as_bytes());
+    }
+}
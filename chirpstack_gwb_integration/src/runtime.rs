@@ -1,31 +1,84 @@
 //! Runtime running the event loop and providing an interface to modify callbacks.
 
 pub mod callbacks;
+pub mod downlink_ids;
 pub mod event_loop;
-
-use crate::downlinks::{Downlink, DownlinkType};
-use crate::error::{CallbackRemoveError, RuntimeError};
+pub mod gateway_time;
+pub mod marshaler;
+pub mod metrics;
+pub mod mqtt_client;
+pub mod multi_broker;
+
+use crate::downlinks::{Downlink, DownlinkType, ImmediatelyClassC};
+use crate::error::{CallbackRemoveError, EnqueueError, RuntimeError};
+use crate::gateway_id::GatewayId;
+use crate::gateway_topics::{CommandType, TopicLayout, TopicType};
 use crate::runtime::callbacks::{
-    AllGatewaysCallbackStorage, CommandConfigCallback, CommandDownCallback, CommandExecCallback,
-    CommandRawCallback, EventAckCallback, EventExecCallback, EventRawCallback, EventStatsCallback,
-    EventUpCallback, StateConnCallback,
+    AllGatewaysCallbackStorage, CallbackInfo, CallbackInfoStorage, CallbackKind, CallbackType,
+    CommandConfigCallback, CommandDownCallback, CommandExecCallback, CommandRawCallback,
+    EventAckCallback, EventExecCallback, EventRawCallback, EventStatsCallback, EventUpCallback,
+    StateConnCallback, UnknownTopicCallback, UnknownTopicCallbackStorage,
 };
 use callbacks::{CallbackDrawers, PerGatewayCallbackStorage};
-use prost::Message;
-use rumqttc::{AsyncClient, MqttOptions, QoS};
+use downlink_ids::{DownlinkIdStorage, DownlinkIds, DownlinkOrigin};
+use gateway_time::{GatewayTime, GatewayTimeStorage};
+use marshaler::{Marshaler, MarshalerState};
+use metrics::{NoopMetrics, SharedRuntimeMetrics};
+use mqtt_client::{MqttClient, MqttV5Options};
+use rumqttc::MqttOptions;
+use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 use std::fmt::Debug;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 use tokio::sync::RwLock;
 use tracing::{error, info, trace};
-use uuid::Uuid;
+pub use uuid::Uuid;
+
+/// Options of the gateway bridge the runtime connects to.
+#[derive(Debug, Clone, Default)]
+pub struct RuntimeOptions {
+    /// Payload format of the gateway bridge.
+    pub marshaler: Marshaler,
+    /// Topic layout of the gateway bridge.
+    pub topic_layout: TopicLayout,
+    /// Hooks recording the activity of the runtime, see [`metrics`]. No metrics are recorded if
+    /// `None`.
+    pub metrics: Option<SharedRuntimeMetrics>,
+    /// Clock offset above which a gateway is flagged as drifting and a warning is logged, see
+    /// [`GatewayTime::is_drifting`]. Clock drift is not checked if `None`.
+    pub clock_drift_threshold: Option<Duration>,
+    /// MQTT v5 features, see [`mqtt_client`]. The runtime connects with MQTT v3.1.1 if `None`.
+    pub mqtt_v5: Option<MqttV5Options>,
+}
 
-/// Default ChirpStack event topic.
-static EVENT_TOPIC: &str = "eu868/gateway/+/event/+";
-/// Default ChirpStack command topic.
-static COMMAND_TOPIC: &str = "eu868/gateway/+/command/+";
-/// Default ChirpStack states topic.
-static STATES_TOPIC: &str = "eu868/gateway/+/states/+";
+impl PartialEq for RuntimeOptions {
+    fn eq(&self, other: &Self) -> bool {
+        self.marshaler == other.marshaler
+            && self.topic_layout == other.topic_layout
+            && self.clock_drift_threshold == other.clock_drift_threshold
+            && self.mqtt_v5 == other.mqtt_v5
+            && match (&self.metrics, &other.metrics) {
+                (Some(metrics), Some(other_metrics)) => Arc::ptr_eq(metrics, other_metrics),
+                (None, None) => true,
+                _ => false,
+            }
+    }
+}
+
+impl Eq for RuntimeOptions {}
+
+/// Timing used when enqueuing a downlink with [`Runtime::enqueue_at`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum DownlinkTiming {
+    /// The downlink was sent as [`GpsTimingClassB`](crate::downlinks::GpsTimingClassB) downlink
+    /// at the contained time since GPS epoch.
+    GpsEpoch(Duration),
+    /// The downlink was delayed locally and sent as
+    /// [`ImmediatelyClassC`](crate::downlinks::ImmediatelyClassC) downlink.
+    Immediately,
+}
 
 /// Type to interact with the event loop of the MQTT client.
 ///
@@ -37,8 +90,24 @@ pub struct Runtime {
     per_gateway_callbacks: PerGatewayCallbackStorage,
     /// Callbacks registered for all gateways.
     all_gateways_callbacks: AllGatewaysCallbackStorage,
+    /// Callbacks for messages with unrecognized topics.
+    unknown_topic_callbacks: UnknownTopicCallbackStorage,
+    /// Metadata of the registered callbacks.
+    callback_infos: CallbackInfoStorage,
+    /// Time information learned from the gateway stats and uplinks.
+    gateway_times: GatewayTimeStorage,
+    /// Downlink IDs allocated per gateway and the subsystems they were allocated for.
+    downlink_ids: DownlinkIdStorage,
+    /// Payload format of the gateway bridge, shared with the event loop.
+    marshaler: Arc<MarshalerState>,
+    /// Topic layout of the gateway bridge.
+    topic_layout: TopicLayout,
     /// MQTT client.
-    mqtt_client: AsyncClient,
+    mqtt_client: MqttClient,
+    /// Whether the event loop is connected to the MQTT broker, shared with the event loop.
+    broker_connected: Arc<AtomicBool>,
+    /// Hooks recording the activity of the runtime, shared with the event loop.
+    metrics: SharedRuntimeMetrics,
     /// Stop signal channel transceiver end. Used to signal the event loop to stop.
     stop_signal_tx: tokio::sync::mpsc::Sender<()>,
     /// Keeps track of whether the stop method of the runtime has been called.
@@ -47,29 +116,70 @@ pub struct Runtime {
 
 impl Runtime {
     /// Create a new runtime with simplified parameters.
+    ///
+    /// The topics, e.g. of the region of the gateway bridge, follow
+    /// [`RuntimeOptions::topic_layout`], downlinks can be published to another layout with
+    /// [`Runtime::enqueue_with_topic_layout`].
     #[tracing::instrument]
     pub async fn new(
         id: &str,
         host: &str,
         port: u16,
+        options: RuntimeOptions,
         connection_error_sender: Option<tokio::sync::broadcast::Sender<String>>,
     ) -> Result<Self, RuntimeError> {
         let mqtt_options = MqttOptions::new(id, host, port);
-        Self::new_with_mqtt_options(mqtt_options, connection_error_sender).await
+        Self::new_with_options(mqtt_options, options, connection_error_sender).await
     }
 
     /// Create a new runtime with the supplied [`MqttOptions`].
+    ///
+    /// The payload format of the gateway bridge is detected automatically, the gateway bridge v4
+    /// topic layout for EU868 is used.
     #[tracing::instrument]
     pub async fn new_with_mqtt_options(
         mqtt_options: MqttOptions,
         connection_error_sender: Option<tokio::sync::broadcast::Sender<String>>,
+    ) -> Result<Self, RuntimeError> {
+        Self::new_with_options(
+            mqtt_options,
+            RuntimeOptions::default(),
+            connection_error_sender,
+        )
+        .await
+    }
+
+    /// Create a new runtime with the supplied [`MqttOptions`] for a gateway bridge with the
+    /// supplied [`RuntimeOptions`].
+    ///
+    /// Connects with MQTT v5 if [`RuntimeOptions::mqtt_v5`] is set, taking the client ID, the
+    /// broker address, the keep alive and the credentials of the [`MqttOptions`].
+    #[tracing::instrument]
+    pub async fn new_with_options(
+        mqtt_options: MqttOptions,
+        options: RuntimeOptions,
+        connection_error_sender: Option<tokio::sync::broadcast::Sender<String>>,
     ) -> Result<Self, RuntimeError> {
         info!("Connecting to {:?}", mqtt_options);
-        let (mqtt_client, event_loop) = AsyncClient::new(mqtt_options, 10);
+        let (mqtt_client, event_loop) =
+            mqtt_client::connect(mqtt_options, options.mqtt_v5.as_ref());
         let per_gateway_callbacks = Arc::new(RwLock::new(HashMap::new()));
         let per_gateway_callbacks_clone = per_gateway_callbacks.clone();
         let all_gateways_callbacks = Arc::new(RwLock::new(CallbackDrawers::new()));
         let all_gateways_callbacks_clone = all_gateways_callbacks.clone();
+        let unknown_topic_callbacks = Arc::new(RwLock::new(HashMap::new()));
+        let unknown_topic_callbacks_clone = unknown_topic_callbacks.clone();
+        let gateway_times = Arc::new(RwLock::new(HashMap::new()));
+        let gateway_times_clone = gateway_times.clone();
+        let marshaler = Arc::new(MarshalerState::new(options.marshaler));
+        let topic_layout = options.topic_layout;
+        let topic_layout_clone = topic_layout.clone();
+        let marshaler_clone = marshaler.clone();
+        let broker_connected = Arc::new(AtomicBool::new(false));
+        let broker_connected_clone = broker_connected.clone();
+        let metrics: SharedRuntimeMetrics =
+            options.metrics.unwrap_or_else(|| Arc::new(NoopMetrics));
+        let metrics_clone = metrics.clone();
         let (stop_signal_tx, stop_signal_rx) = tokio::sync::mpsc::channel(1);
         info!("Spawning event loop");
         // spawn event loop task (tokio task)
@@ -78,74 +188,94 @@ impl Runtime {
                 event_loop,
                 per_gateway_callbacks_clone,
                 all_gateways_callbacks_clone,
+                unknown_topic_callbacks_clone,
+                gateway_times_clone,
+                marshaler_clone,
+                topic_layout_clone,
+                broker_connected_clone,
+                metrics_clone,
+                options.clock_drift_threshold,
                 connection_error_sender,
                 stop_signal_rx,
             )
             .await;
         });
 
-        trace!("subscribing to {}", EVENT_TOPIC);
-        mqtt_client.subscribe(EVENT_TOPIC, QoS::AtLeastOnce).await?;
-        trace!("subscribing to {}", COMMAND_TOPIC);
-        mqtt_client
-            .subscribe(COMMAND_TOPIC, QoS::AtLeastOnce)
-            .await?;
-        trace!("subscribing to {}", STATES_TOPIC);
-        mqtt_client
-            .subscribe(STATES_TOPIC, QoS::AtLeastOnce)
-            .await?;
+        // Subscribe to every topic type, messages with unrecognized topics are passed to the
+        // unknown topic callbacks.
+        let topic = topic_layout.subscription("+");
+        trace!("subscribing to {}", topic);
+        mqtt_client.subscribe(topic).await?;
 
         Ok(Runtime {
             per_gateway_callbacks,
             all_gateways_callbacks,
+            unknown_topic_callbacks,
+            callback_infos: Arc::new(RwLock::new(HashMap::new())),
+            gateway_times,
+            downlink_ids: Arc::new(RwLock::new(DownlinkIds::default())),
+            marshaler,
+            topic_layout,
             mqtt_client,
+            broker_connected,
+            metrics,
             stop_signal_tx,
             received_stop: false,
         })
     }
 
-    /// Add a callback for a downlink command.
+    /// Add a callback for the message type of the callback, e.g. a `Box<dyn EventUpCallback>`.
     /// If `gateway_id` is `Some(...)`, the callback is only applied the gateway topic, otherwise
-    /// the callback is applied to every downlink command.
+    /// the callback is applied to every message of the type.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the runtime is stopped or the generated ID is already in use.
     #[tracing::instrument(skip(self))]
-    pub async fn add_command_config_callback(
+    pub async fn add_callback<K>(
         &mut self,
-        gateway_id: Option<String>,
-        callback: Box<dyn CommandConfigCallback>,
-    ) -> Result<Uuid, RuntimeError> {
+        gateway_id: Option<GatewayId>,
+        callback: Box<K>,
+    ) -> Result<Uuid, RuntimeError>
+    where
+        K: CallbackKind + ?Sized,
+    {
         if self.received_stop {
             return Err(RuntimeError::Stopped);
         }
         let uuid = Uuid::new_v4();
-        if let Some(gateway_id) = gateway_id {
+        let inserted = if let Some(gateway_id) = gateway_id.clone() {
             let mut callbacks_lock = self.per_gateway_callbacks.write().await;
             let callback_drawers = callbacks_lock
                 .entry(gateway_id)
                 .or_insert_with(CallbackDrawers::new);
-
-            if callback_drawers
-                .command
-                .config
-                .insert(uuid, Arc::new(callback))
-                .is_some()
-            {
-                Err(RuntimeError::UuidCollision)
-            } else {
-                Ok(uuid)
-            }
+            insert_callback(K::callbacks(callback_drawers), uuid, callback)
         } else {
             let mut all_gateways_callbacks_lock = self.all_gateways_callbacks.write().await;
-            if all_gateways_callbacks_lock
-                .command
-                .config
-                .insert(uuid, Arc::new(callback))
-                .is_some()
-            {
-                Err(RuntimeError::UuidCollision)
-            } else {
-                Ok(uuid)
-            }
+            insert_callback(
+                K::callbacks(&mut all_gateways_callbacks_lock),
+                uuid,
+                callback,
+            )
+        };
+        if !inserted {
+            return Err(RuntimeError::UuidCollision);
         }
+        self.record_callback_info(uuid, gateway_id, K::CALLBACK_TYPE)
+            .await;
+        Ok(uuid)
+    }
+
+    /// Add a callback for a downlink command.
+    /// If `gateway_id` is `Some(...)`, the callback is only applied the gateway topic, otherwise
+    /// the callback is applied to every downlink command.
+    #[tracing::instrument(skip(self))]
+    pub async fn add_command_config_callback(
+        &mut self,
+        gateway_id: Option<GatewayId>,
+        callback: Box<dyn CommandConfigCallback>,
+    ) -> Result<Uuid, RuntimeError> {
+        self.add_callback(gateway_id, callback).await
     }
 
     /// Add a callback for a downlink command.
@@ -154,42 +284,10 @@ impl Runtime {
     #[tracing::instrument(skip(self))]
     pub async fn add_command_down_callback(
         &mut self,
-        gateway_id: Option<String>,
+        gateway_id: Option<GatewayId>,
         callback: Box<dyn CommandDownCallback>,
     ) -> Result<Uuid, RuntimeError> {
-        if self.received_stop {
-            return Err(RuntimeError::Stopped);
-        }
-        let uuid = Uuid::new_v4();
-        if let Some(gateway_id) = gateway_id {
-            let mut callbacks_lock = self.per_gateway_callbacks.write().await;
-            let callback_drawers = callbacks_lock
-                .entry(gateway_id)
-                .or_insert_with(CallbackDrawers::new);
-
-            if callback_drawers
-                .command
-                .down
-                .insert(uuid, Arc::new(callback))
-                .is_some()
-            {
-                Err(RuntimeError::UuidCollision)
-            } else {
-                Ok(uuid)
-            }
-        } else {
-            let mut all_gateways_callbacks_lock = self.all_gateways_callbacks.write().await;
-            if all_gateways_callbacks_lock
-                .command
-                .down
-                .insert(uuid, Arc::new(callback))
-                .is_some()
-            {
-                Err(RuntimeError::UuidCollision)
-            } else {
-                Ok(uuid)
-            }
-        }
+        self.add_callback(gateway_id, callback).await
     }
 
     /// Add a callback for a exec command.
@@ -198,43 +296,10 @@ impl Runtime {
     #[tracing::instrument(skip(self))]
     pub async fn add_command_exec_callback(
         &mut self,
-        gateway_id: Option<String>,
+        gateway_id: Option<GatewayId>,
         callback: Box<dyn CommandExecCallback>,
     ) -> Result<Uuid, RuntimeError> {
-        if self.received_stop {
-            return Err(RuntimeError::Stopped);
-        }
-        let uuid = Uuid::new_v4();
-
-        if let Some(gateway_id) = gateway_id {
-            let mut callbacks_lock = self.per_gateway_callbacks.write().await;
-            let callback_drawers = callbacks_lock
-                .entry(gateway_id)
-                .or_insert_with(CallbackDrawers::new);
-
-            if callback_drawers
-                .command
-                .exec
-                .insert(uuid, Arc::new(callback))
-                .is_some()
-            {
-                Err(RuntimeError::UuidCollision)
-            } else {
-                Ok(uuid)
-            }
-        } else {
-            let mut all_gateways_callbacks_lock = self.all_gateways_callbacks.write().await;
-            if all_gateways_callbacks_lock
-                .command
-                .exec
-                .insert(uuid, Arc::new(callback))
-                .is_some()
-            {
-                Err(RuntimeError::UuidCollision)
-            } else {
-                Ok(uuid)
-            }
-        }
+        self.add_callback(gateway_id, callback).await
     }
 
     /// Add a callback for a raw command.
@@ -243,43 +308,10 @@ impl Runtime {
     #[tracing::instrument(skip(self))]
     pub async fn add_command_raw_callback(
         &mut self,
-        gateway_id: Option<String>,
+        gateway_id: Option<GatewayId>,
         callback: Box<dyn CommandRawCallback>,
     ) -> Result<Uuid, RuntimeError> {
-        if self.received_stop {
-            return Err(RuntimeError::Stopped);
-        }
-        let uuid = Uuid::new_v4();
-
-        if let Some(gateway_id) = gateway_id {
-            let mut callbacks_lock = self.per_gateway_callbacks.write().await;
-            let callback_drawers = callbacks_lock
-                .entry(gateway_id)
-                .or_insert_with(CallbackDrawers::new);
-
-            if callback_drawers
-                .command
-                .raw
-                .insert(uuid, Arc::new(callback))
-                .is_some()
-            {
-                Err(RuntimeError::UuidCollision)
-            } else {
-                Ok(uuid)
-            }
-        } else {
-            let mut all_gateways_callbacks_lock = self.all_gateways_callbacks.write().await;
-            if all_gateways_callbacks_lock
-                .command
-                .raw
-                .insert(uuid, Arc::new(callback))
-                .is_some()
-            {
-                Err(RuntimeError::UuidCollision)
-            } else {
-                Ok(uuid)
-            }
-        }
+        self.add_callback(gateway_id, callback).await
     }
 
     /// Add a callback for a stats event.
@@ -288,43 +320,10 @@ impl Runtime {
     #[tracing::instrument(skip(self))]
     pub async fn add_event_stats_callback(
         &mut self,
-        gateway_id: Option<String>,
+        gateway_id: Option<GatewayId>,
         callback: Box<dyn EventStatsCallback>,
     ) -> Result<Uuid, RuntimeError> {
-        if self.received_stop {
-            return Err(RuntimeError::Stopped);
-        }
-        let uuid = Uuid::new_v4();
-
-        if let Some(gateway_id) = gateway_id {
-            let mut callbacks_lock = self.per_gateway_callbacks.write().await;
-            let callback_drawers = callbacks_lock
-                .entry(gateway_id)
-                .or_insert_with(CallbackDrawers::new);
-
-            if callback_drawers
-                .event
-                .stats
-                .insert(uuid, Arc::new(callback))
-                .is_some()
-            {
-                Err(RuntimeError::UuidCollision)
-            } else {
-                Ok(uuid)
-            }
-        } else {
-            let mut all_gateways_callbacks_lock = self.all_gateways_callbacks.write().await;
-            if all_gateways_callbacks_lock
-                .event
-                .stats
-                .insert(uuid, Arc::new(callback))
-                .is_some()
-            {
-                Err(RuntimeError::UuidCollision)
-            } else {
-                Ok(uuid)
-            }
-        }
+        self.add_callback(gateway_id, callback).await
     }
 
     /// Add a callback for a up event.
@@ -333,43 +332,10 @@ impl Runtime {
     #[tracing::instrument(skip(self))]
     pub async fn add_event_up_callback(
         &mut self,
-        gateway_id: Option<String>,
+        gateway_id: Option<GatewayId>,
         callback: Box<dyn EventUpCallback>,
     ) -> Result<Uuid, RuntimeError> {
-        if self.received_stop {
-            return Err(RuntimeError::Stopped);
-        }
-        let uuid = Uuid::new_v4();
-
-        if let Some(gateway_id) = gateway_id {
-            let mut callbacks_lock = self.per_gateway_callbacks.write().await;
-            let callback_drawers = callbacks_lock
-                .entry(gateway_id)
-                .or_insert_with(CallbackDrawers::new);
-
-            if callback_drawers
-                .event
-                .up
-                .insert(uuid, Arc::new(callback))
-                .is_some()
-            {
-                Err(RuntimeError::UuidCollision)
-            } else {
-                Ok(uuid)
-            }
-        } else {
-            let mut all_gateways_callbacks_lock = self.all_gateways_callbacks.write().await;
-            if all_gateways_callbacks_lock
-                .event
-                .up
-                .insert(uuid, Arc::new(callback))
-                .is_some()
-            {
-                Err(RuntimeError::UuidCollision)
-            } else {
-                Ok(uuid)
-            }
-        }
+        self.add_callback(gateway_id, callback).await
     }
 
     /// Add a callback for a ack event.
@@ -378,43 +344,10 @@ impl Runtime {
     #[tracing::instrument(skip(self))]
     pub async fn add_event_ack_callback(
         &mut self,
-        gateway_id: Option<String>,
+        gateway_id: Option<GatewayId>,
         callback: Box<dyn EventAckCallback>,
     ) -> Result<Uuid, RuntimeError> {
-        if self.received_stop {
-            return Err(RuntimeError::Stopped);
-        }
-        let uuid = Uuid::new_v4();
-
-        if let Some(gateway_id) = gateway_id {
-            let mut callbacks_lock = self.per_gateway_callbacks.write().await;
-            let callback_drawers = callbacks_lock
-                .entry(gateway_id)
-                .or_insert_with(CallbackDrawers::new);
-
-            if callback_drawers
-                .event
-                .ack
-                .insert(uuid, Arc::new(callback))
-                .is_some()
-            {
-                Err(RuntimeError::UuidCollision)
-            } else {
-                Ok(uuid)
-            }
-        } else {
-            let mut all_gateways_callbacks_lock = self.all_gateways_callbacks.write().await;
-            if all_gateways_callbacks_lock
-                .event
-                .ack
-                .insert(uuid, Arc::new(callback))
-                .is_some()
-            {
-                Err(RuntimeError::UuidCollision)
-            } else {
-                Ok(uuid)
-            }
-        }
+        self.add_callback(gateway_id, callback).await
     }
 
     /// Add a callback for a exec event.
@@ -423,43 +356,10 @@ impl Runtime {
     #[tracing::instrument(skip(self))]
     pub async fn add_event_exec_callback(
         &mut self,
-        gateway_id: Option<String>,
+        gateway_id: Option<GatewayId>,
         callback: Box<dyn EventExecCallback>,
     ) -> Result<Uuid, RuntimeError> {
-        if self.received_stop {
-            return Err(RuntimeError::Stopped);
-        }
-        let uuid = Uuid::new_v4();
-
-        if let Some(gateway_id) = gateway_id {
-            let mut callbacks_lock = self.per_gateway_callbacks.write().await;
-            let callback_drawers = callbacks_lock
-                .entry(gateway_id)
-                .or_insert_with(CallbackDrawers::new);
-
-            if callback_drawers
-                .event
-                .exec
-                .insert(uuid, Arc::new(callback))
-                .is_some()
-            {
-                Err(RuntimeError::UuidCollision)
-            } else {
-                Ok(uuid)
-            }
-        } else {
-            let mut all_gateways_callbacks_lock = self.all_gateways_callbacks.write().await;
-            if all_gateways_callbacks_lock
-                .event
-                .exec
-                .insert(uuid, Arc::new(callback))
-                .is_some()
-            {
-                Err(RuntimeError::UuidCollision)
-            } else {
-                Ok(uuid)
-            }
-        }
+        self.add_callback(gateway_id, callback).await
     }
 
     /// Add a callback for a raw event.
@@ -468,42 +368,10 @@ impl Runtime {
     #[tracing::instrument(skip(self))]
     pub async fn add_event_raw_callback(
         &mut self,
-        gateway_id: Option<String>,
+        gateway_id: Option<GatewayId>,
         callback: Box<dyn EventRawCallback>,
     ) -> Result<Uuid, RuntimeError> {
-        if self.received_stop {
-            return Err(RuntimeError::Stopped);
-        }
-        let uuid = Uuid::new_v4();
-        if let Some(gateway_id) = gateway_id {
-            let mut callbacks_lock = self.per_gateway_callbacks.write().await;
-            let callback_drawers = callbacks_lock
-                .entry(gateway_id)
-                .or_insert_with(CallbackDrawers::new);
-
-            if callback_drawers
-                .event
-                .raw
-                .insert(uuid, Arc::new(callback))
-                .is_some()
-            {
-                Err(RuntimeError::UuidCollision)
-            } else {
-                Ok(uuid)
-            }
-        } else {
-            let mut all_gateways_callbacks_lock = self.all_gateways_callbacks.write().await;
-            if all_gateways_callbacks_lock
-                .event
-                .raw
-                .insert(uuid, Arc::new(callback))
-                .is_some()
-            {
-                Err(RuntimeError::UuidCollision)
-            } else {
-                Ok(uuid)
-            }
-        }
+        self.add_callback(gateway_id, callback).await
     }
 
     /// Add a callback for a conn state.
@@ -512,54 +380,102 @@ impl Runtime {
     #[tracing::instrument(skip(self))]
     pub async fn add_state_conn_callback(
         &mut self,
-        gateway_id: Option<String>,
+        gateway_id: Option<GatewayId>,
         callback: Box<dyn StateConnCallback>,
+    ) -> Result<Uuid, RuntimeError> {
+        self.add_callback(gateway_id, callback).await
+    }
+
+    /// Add a callback for messages whose topic could not be parsed, e.g. event types added by
+    /// newer gateway bridge versions, receiving the topic and the raw payload.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the runtime is stopped or the generated ID is already in use.
+    #[tracing::instrument(skip(self))]
+    pub async fn add_unknown_topic_callback(
+        &mut self,
+        callback: Box<dyn UnknownTopicCallback>,
     ) -> Result<Uuid, RuntimeError> {
         if self.received_stop {
             return Err(RuntimeError::Stopped);
         }
         let uuid = Uuid::new_v4();
-        if let Some(gateway_id) = gateway_id {
-            let mut callbacks_lock = self.per_gateway_callbacks.write().await;
-            let callback_drawers = callbacks_lock
-                .entry(gateway_id)
-                .or_insert_with(CallbackDrawers::new);
+        if !insert_callback(
+            &mut *self.unknown_topic_callbacks.write().await,
+            uuid,
+            callback,
+        ) {
+            return Err(RuntimeError::UuidCollision);
+        }
+        self.record_callback_info(uuid, None, CallbackType::UnknownTopic)
+            .await;
+        Ok(uuid)
+    }
 
-            if callback_drawers
-                .state
-                .conn
-                .insert(uuid, Arc::new(callback))
-                .is_some()
-            {
-                Err(RuntimeError::UuidCollision)
-            } else {
-                Ok(uuid)
-            }
-        } else {
-            let mut all_gateways_callbacks_lock = self.all_gateways_callbacks.write().await;
-            if all_gateways_callbacks_lock
-                .state
-                .conn
-                .insert(uuid, Arc::new(callback))
-                .is_some()
-            {
-                Err(RuntimeError::UuidCollision)
-            } else {
-                Ok(uuid)
+    /// Records the metadata of an added callback.
+    async fn record_callback_info(
+        &self,
+        uuid: Uuid,
+        gateway_id: Option<GatewayId>,
+        callback_type: CallbackType,
+    ) {
+        self.callback_infos.write().await.insert(
+            uuid,
+            CallbackInfo {
+                uuid,
+                gateway_id,
+                callback_type,
+                registered_at: SystemTime::now(),
+                label: None,
+            },
+        );
+    }
+
+    /// Attaches a label to the callback with the supplied ID, e.g. to identify it in
+    /// [`Runtime::list_callbacks`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no callback with the supplied ID is registered.
+    #[tracing::instrument(skip(self))]
+    pub async fn set_callback_label(&self, uuid: Uuid, label: &str) -> Result<(), RuntimeError> {
+        match self.callback_infos.write().await.get_mut(&uuid) {
+            Some(callback_info) => {
+                callback_info.label = Some(label.to_owned());
+                Ok(())
             }
+            None => Err(CallbackRemoveError::NoSuchCallback { uuid }.into()),
         }
     }
 
+    /// Returns the metadata of all registered callbacks, oldest first.
+    pub async fn list_callbacks(&self) -> Vec<CallbackInfo> {
+        let mut callback_infos: Vec<CallbackInfo> =
+            self.callback_infos.read().await.values().cloned().collect();
+        callback_infos.sort_by_key(|callback_info| callback_info.registered_at);
+        callback_infos
+    }
+
     /// Remove all callbacks for the listed gateway IDs.
     #[tracing::instrument(skip(self))]
     pub async fn remove_callbacks_with_gateways(
         &self,
-        gateway_ids: Vec<String>,
+        gateway_ids: Vec<GatewayId>,
     ) -> Result<(), RuntimeError> {
         if self.received_stop {
             return Err(RuntimeError::Stopped);
         }
         let mut callbacks = self.per_gateway_callbacks.write().await;
+        self.callback_infos
+            .write()
+            .await
+            .retain(|_, callback_info| {
+                !callback_info
+                    .gateway_id
+                    .as_ref()
+                    .is_some_and(|gateway_id| gateway_ids.contains(gateway_id))
+            });
         for gateway_id in gateway_ids {
             callbacks.remove(&gateway_id);
         }
@@ -584,7 +500,17 @@ impl Runtime {
         if all_gateways_callbacks.remove(&uuid).is_ok() {
             found = true;
         }
+        if self
+            .unknown_topic_callbacks
+            .write()
+            .await
+            .remove(&uuid)
+            .is_some()
+        {
+            found = true;
+        }
         if found {
+            self.callback_infos.write().await.remove(&uuid);
             Ok(())
         } else {
             trace!("No callback was found.");
@@ -592,71 +518,264 @@ impl Runtime {
         }
     }
 
-    /// Enqueues a downlink to be sent from the specified gateway.
-    #[tracing::instrument(skip_all)]
-    pub async fn enqueue<Dt>(
+    /// Returns whether the event loop is connected to the MQTT broker.
+    #[must_use]
+    pub fn broker_connected(&self) -> bool {
+        self.broker_connected.load(Ordering::Relaxed)
+    }
+
+    /// Checks whether the downlink can be enqueued and encodes it, returns the topic of the topic
+    /// layout and the message to publish.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the runtime is stopped or not connected to the broker, the downlink
+    /// cannot be transmitted in the EU863-870 band or cannot be serialized.
+    fn prepare_enqueue<Dt>(
         &self,
-        sender_gateway: &str,
+        sender_gateway: &GatewayId,
         downlink: Downlink<Dt>,
-    ) -> Result<(), RuntimeError>
+        topic_layout: &TopicLayout,
+    ) -> Result<(String, Vec<u8>), EnqueueError>
     where
         chirpstack_api::gw::DownlinkFrame: From<Downlink<Dt>>,
         Dt: DownlinkType,
     {
         if self.received_stop {
-            return Err(RuntimeError::Stopped);
+            return Err(EnqueueError::Stopped);
+        }
+        if !self.broker_connected() {
+            return Err(EnqueueError::NotConnected);
         }
-        let gateway_downlink_command_topic = format!("eu868/gateway/{sender_gateway}/command/down");
+        downlink.check_transmittable()?;
+        let gateway_downlink_command_topic =
+            topic_layout.topic(sender_gateway, TopicType::Command(CommandType::Down));
         let downlink_frame: chirpstack_api::gw::DownlinkFrame = downlink.into();
-        let message = downlink_frame.encode_to_vec();
+        let message = self.marshaler.encode(&downlink_frame)?;
 
         trace!(
             "Sending {:?} to: {}",
             downlink_frame,
             gateway_downlink_command_topic
         );
+        Ok((gateway_downlink_command_topic, message))
+    }
 
-        Ok(self
-            .mqtt_client
-            .publish(
-                gateway_downlink_command_topic,
-                QoS::AtMostOnce,
-                false,
-                message,
-            )
-            .await?)
+    /// Enqueues a downlink to be sent from the specified gateway, waits while the MQTT request
+    /// queue is full.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`EnqueueError`] describing why the downlink was not handed to the MQTT
+    /// client.
+    #[tracing::instrument(skip_all)]
+    pub async fn enqueue<Dt>(
+        &self,
+        sender_gateway: &GatewayId,
+        downlink: Downlink<Dt>,
+    ) -> Result<(), EnqueueError>
+    where
+        chirpstack_api::gw::DownlinkFrame: From<Downlink<Dt>>,
+        Dt: DownlinkType,
+    {
+        self.enqueue_with_topic_layout(sender_gateway, downlink, &self.topic_layout)
+            .await
     }
 
-    /// Enqueues a downlink to be sent from the specified gateway.
+    /// Enqueues a downlink to be sent from the specified gateway like [`Runtime::enqueue`], but
+    /// publishes it to the command topic of the supplied [`TopicLayout`] instead of the one of the
+    /// runtime, e.g. for a gateway of another region connected to the same broker.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`EnqueueError`] describing why the downlink was not handed to the MQTT
+    /// client.
+    #[tracing::instrument(skip_all)]
+    pub async fn enqueue_with_topic_layout<Dt>(
+        &self,
+        sender_gateway: &GatewayId,
+        downlink: Downlink<Dt>,
+        topic_layout: &TopicLayout,
+    ) -> Result<(), EnqueueError>
+    where
+        chirpstack_api::gw::DownlinkFrame: From<Downlink<Dt>>,
+        Dt: DownlinkType,
+    {
+        let result = match self.prepare_enqueue(sender_gateway, downlink, topic_layout) {
+            Ok((gateway_downlink_command_topic, message)) => {
+                self.mqtt_client
+                    .publish(&gateway_downlink_command_topic, message)
+                    .await
+            }
+            Err(err) => Err(err),
+        };
+        self.record_publish_result(result)
+    }
+
+    /// Enqueues a downlink to be sent from the specified gateway without waiting.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`EnqueueError`] describing why the downlink was not handed to the MQTT
+    /// client, [`EnqueueError::BrokerBackPressure`] if the MQTT request queue is full.
     #[tracing::instrument(skip_all)]
     pub fn try_enqueue<Dt>(
         &self,
-        sender_gateway: &str,
+        sender_gateway: &GatewayId,
         downlink: Downlink<Dt>,
-    ) -> Result<(), RuntimeError>
+    ) -> Result<(), EnqueueError>
     where
         chirpstack_api::gw::DownlinkFrame: From<Downlink<Dt>>,
         Dt: DownlinkType,
     {
-        if self.received_stop {
-            return Err(RuntimeError::Stopped);
+        self.try_enqueue_with_topic_layout(sender_gateway, downlink, &self.topic_layout)
+    }
+
+    /// Enqueues a downlink to be sent from the specified gateway without waiting like
+    /// [`Runtime::try_enqueue`], but publishes it to the command topic of the supplied
+    /// [`TopicLayout`] instead of the one of the runtime.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`EnqueueError`] describing why the downlink was not handed to the MQTT
+    /// client, [`EnqueueError::BrokerBackPressure`] if the MQTT request queue is full.
+    #[tracing::instrument(skip_all)]
+    pub fn try_enqueue_with_topic_layout<Dt>(
+        &self,
+        sender_gateway: &GatewayId,
+        downlink: Downlink<Dt>,
+        topic_layout: &TopicLayout,
+    ) -> Result<(), EnqueueError>
+    where
+        chirpstack_api::gw::DownlinkFrame: From<Downlink<Dt>>,
+        Dt: DownlinkType,
+    {
+        let result = self
+            .prepare_enqueue(sender_gateway, downlink, topic_layout)
+            .and_then(|(gateway_downlink_command_topic, message)| {
+                self.mqtt_client
+                    .try_publish(&gateway_downlink_command_topic, message)
+            });
+        self.record_publish_result(result)
+    }
+
+    /// Passes a failed publish to the metrics hooks.
+    fn record_publish_result(&self, result: Result<(), EnqueueError>) -> Result<(), EnqueueError> {
+        if let Err(err) = &result {
+            self.metrics.publish_failed(err);
         }
-        let gateway_downlink_command_topic = format!("eu868/gateway/{sender_gateway}/command/down");
-        let downlink_frame: chirpstack_api::gw::DownlinkFrame = downlink.into();
-        let message = downlink_frame.encode_to_vec();
+        result
+    }
 
-        trace!(
-            "Sending {:?} to: {}",
-            downlink_frame,
-            gateway_downlink_command_topic
-        );
+    /// Returns the time information learned about the gateway, if any message containing time
+    /// information has been received from it.
+    #[tracing::instrument(skip(self))]
+    pub async fn gateway_time(&self, gateway_id: &GatewayId) -> Option<GatewayTime> {
+        self.gateway_times.read().await.get(gateway_id).copied()
+    }
+
+    /// Returns the time information learned about all gateways, e.g. to list their clock offsets.
+    #[tracing::instrument(skip(self))]
+    pub async fn gateway_times(&self) -> HashMap<GatewayId, GatewayTime> {
+        self.gateway_times.read().await.clone()
+    }
+
+    /// Allocates the next downlink ID of the gateway and records the subsystem sending the
+    /// downlink, see [`downlink_ids`].
+    #[tracing::instrument(skip(self))]
+    pub async fn next_downlink_id(&self, gateway_id: &GatewayId, subsystem: &str) -> u32 {
+        self.downlink_ids
+            .write()
+            .await
+            .allocate(gateway_id, subsystem)
+    }
+
+    /// Returns the subsystem a downlink ID of the gateway was allocated for, if the ID is still
+    /// tracked.
+    pub async fn downlink_origin(
+        &self,
+        gateway_id: &GatewayId,
+        downlink_id: u32,
+    ) -> Option<DownlinkOrigin> {
+        self.downlink_ids
+            .read()
+            .await
+            .origin(gateway_id, downlink_id)
+    }
+
+    /// Returns the origins of all tracked downlink IDs of the gateway, oldest first.
+    pub async fn downlink_origins(&self, gateway_id: &GatewayId) -> Vec<DownlinkOrigin> {
+        self.downlink_ids.read().await.origins(gateway_id)
+    }
+
+    /// Returns the amount of downlink IDs skipped during the allocation as they were still
+    /// tracked.
+    pub async fn downlink_id_collisions(&self) -> u64 {
+        self.downlink_ids.read().await.collisions()
+    }
+
+    /// Returns the next downlink ID per gateway, persist them to continue the allocation with
+    /// [`Runtime::restore_downlink_id_counters`] after a restart.
+    pub async fn downlink_id_counters(&self) -> HashMap<GatewayId, u32> {
+        self.downlink_ids.read().await.counters()
+    }
+
+    /// Continues the allocation of downlink IDs at the persisted counters, counters lower than
+    /// the ones already reached are ignored.
+    pub async fn restore_downlink_id_counters(&self, counters: HashMap<GatewayId, u32>) {
+        self.downlink_ids.write().await.restore_counters(counters);
+    }
 
-        Ok(self.mqtt_client.try_publish(
-            gateway_downlink_command_topic,
-            QoS::AtMostOnce,
-            false,
-            message,
-        )?)
+    /// Returns the topic layout of the gateway bridge.
+    #[must_use]
+    pub fn topic_layout(&self) -> &TopicLayout {
+        &self.topic_layout
+    }
+
+    /// Enqueues a downlink to be sent from the specified gateway at the supplied wall-clock time.
+    ///
+    /// If the gateway reported GPS epoch timestamps, the downlink is converted into a
+    /// [`GpsTimingClassB`](crate::downlinks::GpsTimingClassB) downlink and handed to the gateway
+    /// immediately, the gateway schedules the transmission itself.
+    /// Otherwise the runtime waits until `when` and enqueues the downlink as is.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the runtime is stopped, `when` cannot be converted into a time since
+    /// GPS epoch or the downlink cannot be published.
+    #[tracing::instrument(skip(self, downlink))]
+    pub async fn enqueue_at(
+        &self,
+        sender_gateway: &GatewayId,
+        downlink: Downlink<ImmediatelyClassC>,
+        when: SystemTime,
+    ) -> Result<DownlinkTiming, RuntimeError> {
+        if self.received_stop {
+            return Err(RuntimeError::Stopped);
+        }
+        match self.gateway_time(sender_gateway).await {
+            Some(gateway_time) if gateway_time.supports_gps_timing() => {
+                let time_since_gps_epoch = gateway_time.time_since_gps_epoch(when)?;
+                trace!(
+                    "Scheduling downlink at {:?} since GPS epoch",
+                    time_since_gps_epoch
+                );
+                self.enqueue(
+                    sender_gateway,
+                    downlink.into_gps_timed(time_since_gps_epoch),
+                )
+                .await?;
+                Ok(DownlinkTiming::GpsEpoch(time_since_gps_epoch))
+            }
+            _ => {
+                trace!("Gateway does not support GPS timing, delaying downlink locally");
+                if let Ok(delay) = when.duration_since(SystemTime::now()) {
+                    tokio::time::sleep(delay).await;
+                }
+                self.enqueue(sender_gateway, downlink).await?;
+                Ok(DownlinkTiming::Immediately)
+            }
+        }
     }
 
     /// Stop the runtime.
@@ -672,3 +791,18 @@ impl Runtime {
         self.received_stop = true;
     }
 }
+
+/// Inserts a callback unless its ID is already in use, returns whether it was inserted.
+fn insert_callback<K: ?Sized>(
+    callbacks: &mut HashMap<Uuid, Arc<Box<K>>>,
+    uuid: Uuid,
+    callback: Box<K>,
+) -> bool {
+    match callbacks.entry(uuid) {
+        Entry::Occupied(_) => false,
+        Entry::Vacant(entry) => {
+            entry.insert(Arc::new(callback));
+            true
+        }
+    }
+}
@@ -1,23 +1,73 @@
 //! Callback traits and callback storage implementations.
 
-use crate::error::CallbackRemoveError;
+use crate::error::{CallbackRemoveError, PayloadDecodeError};
+use crate::gateway_id::GatewayId;
 use crate::gateway_topics::{CommandType, EventType, ParsedTopic, StateType, TopicType};
+use crate::runtime::marshaler::MarshalerState;
+use crate::runtime::metrics::SharedRuntimeMetrics;
 use async_trait::async_trait;
 use core::fmt;
 use prost::bytes::Bytes;
 use prost::Message;
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use serde_derive::Serialize;
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::{Instant, SystemTime};
 use tokio::sync::RwLock;
 use uuid::Uuid;
 
+/// Message type a callback is registered for.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, JsonSchema)]
+pub enum CallbackType {
+    /// [`CommandConfigCallback`].
+    CommandConfig,
+    /// [`CommandDownCallback`].
+    CommandDown,
+    /// [`CommandExecCallback`].
+    CommandExec,
+    /// [`CommandRawCallback`].
+    CommandRaw,
+    /// [`EventStatsCallback`].
+    EventStats,
+    /// [`EventUpCallback`].
+    EventUp,
+    /// [`EventAckCallback`].
+    EventAck,
+    /// [`EventExecCallback`].
+    EventExec,
+    /// [`EventRawCallback`].
+    EventRaw,
+    /// [`StateConnCallback`].
+    StateConn,
+    /// [`UnknownTopicCallback`].
+    UnknownTopic,
+}
+
+/// Metadata of a registered callback, returned by
+/// [`Runtime::list_callbacks`](crate::runtime::Runtime::list_callbacks).
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct CallbackInfo {
+    /// ID returned when the callback was added.
+    pub uuid: Uuid,
+    /// Gateway the callback is registered for, all gateways if `None`.
+    pub gateway_id: Option<GatewayId>,
+    /// Message type the callback is registered for.
+    pub callback_type: CallbackType,
+    /// Time the callback was added.
+    pub registered_at: SystemTime,
+    /// Label attached with [`Runtime::set_callback_label`](crate::runtime::Runtime::set_callback_label).
+    pub label: Option<String>,
+}
+
 /// Implement this trait if you want to build a down config callback.
 #[async_trait]
 pub trait CommandConfigCallback: Send + Sync + fmt::Debug {
     /// This function is called with every incoming message it was registered for.
     async fn dispatch_config_command(
         &self,
-        gateway_id: String,
+        gateway_id: GatewayId,
         config_command: chirpstack_api::gw::GatewayConfiguration,
     );
 }
@@ -28,7 +78,7 @@ pub trait CommandDownCallback: Send + Sync + fmt::Debug {
     /// This function is called with every incoming message it was registered for.
     async fn dispatch_down_command(
         &self,
-        gateway_id: String,
+        gateway_id: GatewayId,
         downlink_command: chirpstack_api::gw::DownlinkFrame,
     );
 }
@@ -39,7 +89,7 @@ pub trait CommandExecCallback: Send + Sync + fmt::Debug {
     /// This function is called with every incoming message it was registered for.
     async fn dispatch_exec_command(
         &self,
-        gateway_id: String,
+        gateway_id: GatewayId,
         exec_command: chirpstack_api::gw::GatewayCommandExecRequest,
     );
 }
@@ -50,7 +100,7 @@ pub trait CommandRawCallback: Send + Sync + fmt::Debug {
     /// This function is called with every incoming message it was registered for.
     async fn dispatch_raw_command(
         &self,
-        gateway_id: String,
+        gateway_id: GatewayId,
         raw_command: chirpstack_api::gw::RawPacketForwarderCommand,
     );
 }
@@ -61,7 +111,7 @@ pub trait EventStatsCallback: Send + Sync + fmt::Debug {
     /// This function is called with every incoming message it was registered for.
     async fn dispatch_stats_event(
         &self,
-        gateway_id: String,
+        gateway_id: GatewayId,
         stats_event: chirpstack_api::gw::GatewayStats,
     );
 }
@@ -72,7 +122,7 @@ pub trait EventUpCallback: Send + Sync + fmt::Debug {
     /// This function is called with every incoming message it was registered for.
     async fn dispatch_up_event(
         &self,
-        gateway_id: String,
+        gateway_id: GatewayId,
         up_event: chirpstack_api::gw::UplinkFrame,
     );
 }
@@ -83,7 +133,7 @@ pub trait EventAckCallback: Send + Sync + fmt::Debug {
     /// This function is called with every incoming message it was registered for.
     async fn dispatch_ack_event(
         &self,
-        gateway_id: String,
+        gateway_id: GatewayId,
         ack_event: chirpstack_api::gw::DownlinkTxAck,
     );
 }
@@ -94,7 +144,7 @@ pub trait EventExecCallback: Send + Sync + fmt::Debug {
     /// This function is called with every incoming message it was registered for.
     async fn dispatch_exec_event(
         &self,
-        gateway_id: String,
+        gateway_id: GatewayId,
         exec_event: chirpstack_api::gw::GatewayCommandExecResponse,
     );
 }
@@ -105,7 +155,7 @@ pub trait EventRawCallback: Send + Sync + fmt::Debug {
     /// This function is called with every incoming message it was registered for.
     async fn dispatch_raw_event(
         &self,
-        gateway_id: String,
+        gateway_id: GatewayId,
         raw_event: chirpstack_api::gw::RawPacketForwarderEvent,
     );
 }
@@ -116,11 +166,240 @@ pub trait StateConnCallback: Send + Sync + fmt::Debug {
     /// This function is called with every incoming message it was registered for.
     async fn dispatch_conn_state(
         &self,
-        gateway_id: String,
+        gateway_id: GatewayId,
         conn_state: chirpstack_api::gw::ConnState,
     );
 }
 
+/// Implement this trait if you want to build a callback for messages with unrecognized topics,
+/// e.g. event types added by newer gateway bridge versions.
+#[async_trait]
+pub trait UnknownTopicCallback: Send + Sync + fmt::Debug {
+    /// This function is called with every incoming message whose topic could not be parsed.
+    async fn dispatch_unknown_topic(&self, topic: String, payload: Bytes);
+}
+
+/// Message type callbacks can be added for with
+/// [`Runtime::add_callback`](crate::runtime::Runtime::add_callback), implemented for the trait
+/// objects of the callback traits.
+///
+/// Supporting a new message type requires a callback trait, a [`CallbackType`], a map in one of
+/// the drawers and an implementation of this trait.
+#[async_trait]
+pub trait CallbackKind: Send + Sync + fmt::Debug {
+    /// Message decoded from the payload and passed to the callbacks.
+    type Message: Message + Default + DeserializeOwned + Clone + Send + 'static;
+    /// Type of the callbacks in the callback metadata.
+    const CALLBACK_TYPE: CallbackType;
+
+    /// Returns the callbacks of this kind in the drawers.
+    fn callbacks(drawers: &mut CallbackDrawers) -> &mut HashMap<Uuid, Arc<Box<Self>>>;
+
+    /// Calls the `dispatch_...` method of the callback.
+    async fn dispatch(&self, gateway_id: GatewayId, message: Self::Message);
+}
+
+#[async_trait]
+impl CallbackKind for dyn CommandConfigCallback {
+    type Message = chirpstack_api::gw::GatewayConfiguration;
+    const CALLBACK_TYPE: CallbackType = CallbackType::CommandConfig;
+
+    fn callbacks(drawers: &mut CallbackDrawers) -> &mut HashMap<Uuid, Arc<Box<Self>>> {
+        &mut drawers.command.config
+    }
+
+    async fn dispatch(&self, gateway_id: GatewayId, message: Self::Message) {
+        self.dispatch_config_command(gateway_id, message).await;
+    }
+}
+
+#[async_trait]
+impl CallbackKind for dyn CommandDownCallback {
+    type Message = chirpstack_api::gw::DownlinkFrame;
+    const CALLBACK_TYPE: CallbackType = CallbackType::CommandDown;
+
+    fn callbacks(drawers: &mut CallbackDrawers) -> &mut HashMap<Uuid, Arc<Box<Self>>> {
+        &mut drawers.command.down
+    }
+
+    async fn dispatch(&self, gateway_id: GatewayId, message: Self::Message) {
+        self.dispatch_down_command(gateway_id, message).await;
+    }
+}
+
+#[async_trait]
+impl CallbackKind for dyn CommandExecCallback {
+    type Message = chirpstack_api::gw::GatewayCommandExecRequest;
+    const CALLBACK_TYPE: CallbackType = CallbackType::CommandExec;
+
+    fn callbacks(drawers: &mut CallbackDrawers) -> &mut HashMap<Uuid, Arc<Box<Self>>> {
+        &mut drawers.command.exec
+    }
+
+    async fn dispatch(&self, gateway_id: GatewayId, message: Self::Message) {
+        self.dispatch_exec_command(gateway_id, message).await;
+    }
+}
+
+#[async_trait]
+impl CallbackKind for dyn CommandRawCallback {
+    type Message = chirpstack_api::gw::RawPacketForwarderCommand;
+    const CALLBACK_TYPE: CallbackType = CallbackType::CommandRaw;
+
+    fn callbacks(drawers: &mut CallbackDrawers) -> &mut HashMap<Uuid, Arc<Box<Self>>> {
+        &mut drawers.command.raw
+    }
+
+    async fn dispatch(&self, gateway_id: GatewayId, message: Self::Message) {
+        self.dispatch_raw_command(gateway_id, message).await;
+    }
+}
+
+#[async_trait]
+impl CallbackKind for dyn EventStatsCallback {
+    type Message = chirpstack_api::gw::GatewayStats;
+    const CALLBACK_TYPE: CallbackType = CallbackType::EventStats;
+
+    fn callbacks(drawers: &mut CallbackDrawers) -> &mut HashMap<Uuid, Arc<Box<Self>>> {
+        &mut drawers.event.stats
+    }
+
+    async fn dispatch(&self, gateway_id: GatewayId, message: Self::Message) {
+        self.dispatch_stats_event(gateway_id, message).await;
+    }
+}
+
+#[async_trait]
+impl CallbackKind for dyn EventUpCallback {
+    type Message = chirpstack_api::gw::UplinkFrame;
+    const CALLBACK_TYPE: CallbackType = CallbackType::EventUp;
+
+    fn callbacks(drawers: &mut CallbackDrawers) -> &mut HashMap<Uuid, Arc<Box<Self>>> {
+        &mut drawers.event.up
+    }
+
+    async fn dispatch(&self, gateway_id: GatewayId, message: Self::Message) {
+        self.dispatch_up_event(gateway_id, message).await;
+    }
+}
+
+#[async_trait]
+impl CallbackKind for dyn EventAckCallback {
+    type Message = chirpstack_api::gw::DownlinkTxAck;
+    const CALLBACK_TYPE: CallbackType = CallbackType::EventAck;
+
+    fn callbacks(drawers: &mut CallbackDrawers) -> &mut HashMap<Uuid, Arc<Box<Self>>> {
+        &mut drawers.event.ack
+    }
+
+    async fn dispatch(&self, gateway_id: GatewayId, message: Self::Message) {
+        self.dispatch_ack_event(gateway_id, message).await;
+    }
+}
+
+#[async_trait]
+impl CallbackKind for dyn EventExecCallback {
+    type Message = chirpstack_api::gw::GatewayCommandExecResponse;
+    const CALLBACK_TYPE: CallbackType = CallbackType::EventExec;
+
+    fn callbacks(drawers: &mut CallbackDrawers) -> &mut HashMap<Uuid, Arc<Box<Self>>> {
+        &mut drawers.event.exec
+    }
+
+    async fn dispatch(&self, gateway_id: GatewayId, message: Self::Message) {
+        self.dispatch_exec_event(gateway_id, message).await;
+    }
+}
+
+#[async_trait]
+impl CallbackKind for dyn EventRawCallback {
+    type Message = chirpstack_api::gw::RawPacketForwarderEvent;
+    const CALLBACK_TYPE: CallbackType = CallbackType::EventRaw;
+
+    fn callbacks(drawers: &mut CallbackDrawers) -> &mut HashMap<Uuid, Arc<Box<Self>>> {
+        &mut drawers.event.raw
+    }
+
+    async fn dispatch(&self, gateway_id: GatewayId, message: Self::Message) {
+        self.dispatch_raw_event(gateway_id, message).await;
+    }
+}
+
+#[async_trait]
+impl CallbackKind for dyn StateConnCallback {
+    type Message = chirpstack_api::gw::ConnState;
+    const CALLBACK_TYPE: CallbackType = CallbackType::StateConn;
+
+    fn callbacks(drawers: &mut CallbackDrawers) -> &mut HashMap<Uuid, Arc<Box<Self>>> {
+        &mut drawers.state.conn
+    }
+
+    async fn dispatch(&self, gateway_id: GatewayId, message: Self::Message) {
+        self.dispatch_conn_state(gateway_id, message).await;
+    }
+}
+
+/// Message decoded from the payload of a topic.
+///
+/// Every message is decoded once and passed to the gateway time tracking and the callbacks.
+#[derive(Debug, Clone)]
+pub(crate) enum DecodedMessage {
+    /// Config command.
+    ConfigCommand(chirpstack_api::gw::GatewayConfiguration),
+    /// Downlink command.
+    DownCommand(chirpstack_api::gw::DownlinkFrame),
+    /// Exec command.
+    ExecCommand(chirpstack_api::gw::GatewayCommandExecRequest),
+    /// Raw command.
+    RawCommand(chirpstack_api::gw::RawPacketForwarderCommand),
+    /// Stats event.
+    StatsEvent(chirpstack_api::gw::GatewayStats),
+    /// Uplink event.
+    UpEvent(chirpstack_api::gw::UplinkFrame),
+    /// Ack event.
+    AckEvent(chirpstack_api::gw::DownlinkTxAck),
+    /// Exec event.
+    ExecEvent(chirpstack_api::gw::GatewayCommandExecResponse),
+    /// Raw event.
+    RawEvent(chirpstack_api::gw::RawPacketForwarderEvent),
+    /// Conn state.
+    ConnState(chirpstack_api::gw::ConnState),
+}
+
+impl DecodedMessage {
+    /// Decodes the message payload of a topic with the configured marshaler.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the message payload cannot be decoded with the configured marshaler.
+    pub(crate) fn decode(
+        topic_type: TopicType,
+        msg_payload: Bytes,
+        marshaler: &MarshalerState,
+    ) -> Result<Self, PayloadDecodeError> {
+        Ok(match topic_type {
+            TopicType::Command(CommandType::Config) => {
+                Self::ConfigCommand(marshaler.decode(msg_payload)?)
+            }
+            TopicType::Command(CommandType::Down) => {
+                Self::DownCommand(marshaler.decode(msg_payload)?)
+            }
+            TopicType::Command(CommandType::Exec) => {
+                Self::ExecCommand(marshaler.decode(msg_payload)?)
+            }
+            TopicType::Command(CommandType::Raw) => {
+                Self::RawCommand(marshaler.decode(msg_payload)?)
+            }
+            TopicType::Event(EventType::Stats) => Self::StatsEvent(marshaler.decode(msg_payload)?),
+            TopicType::Event(EventType::Up) => Self::UpEvent(marshaler.decode(msg_payload)?),
+            TopicType::Event(EventType::Ack) => Self::AckEvent(marshaler.decode(msg_payload)?),
+            TopicType::Event(EventType::Exec) => Self::ExecEvent(marshaler.decode(msg_payload)?),
+            TopicType::Event(EventType::Raw) => Self::RawEvent(marshaler.decode(msg_payload)?),
+            TopicType::State(StateType::Conn) => Self::ConnState(marshaler.decode(msg_payload)?),
+        })
+    }
+}
+
 /// Contains all callback drawers, is linked to a gateway id in the [`Runtime`](crate::runtime::Runtime).
 #[derive(Debug)]
 pub struct CallbackDrawers {
@@ -194,35 +473,58 @@ impl CallbackDrawers {
         }
     }
 
-    /// Calls every matching callbacks `dispatch_...` method with the gateway ID and message payload.
-    ///
-    /// # Errors
-    ///
-    /// Returns an error if the message payload cannot be decoded into a downlink [`chirpstack_api::gw::DownlinkFrame`].
-    #[tracing::instrument]
-    pub(crate) async fn dispatch(
+    /// Calls every matching callbacks `dispatch_...` method with the gateway ID and the decoded
+    /// message.
+    #[tracing::instrument(skip(message, metrics))]
+    pub(crate) fn dispatch(
         &self,
         topic: ParsedTopic,
-        msg_payload: Bytes,
-    ) -> Result<(), prost::DecodeError> {
-        match topic.topic_type {
-            TopicType::Event(event_type) => {
-                self.event
-                    .dispatch(event_type, topic.gateway_id, msg_payload)
-                    .await?;
+        message: &DecodedMessage,
+        metrics: &SharedRuntimeMetrics,
+    ) {
+        let ParsedTopic {
+            gateway_id,
+            topic_type,
+            ..
+        } = topic;
+        match message {
+            DecodedMessage::ConfigCommand(message) => {
+                dispatch_to(
+                    &self.command.config,
+                    gateway_id,
+                    message,
+                    topic_type,
+                    metrics,
+                );
+            }
+            DecodedMessage::DownCommand(message) => {
+                dispatch_to(&self.command.down, gateway_id, message, topic_type, metrics);
+            }
+            DecodedMessage::ExecCommand(message) => {
+                dispatch_to(&self.command.exec, gateway_id, message, topic_type, metrics);
+            }
+            DecodedMessage::RawCommand(message) => {
+                dispatch_to(&self.command.raw, gateway_id, message, topic_type, metrics);
+            }
+            DecodedMessage::StatsEvent(message) => {
+                dispatch_to(&self.event.stats, gateway_id, message, topic_type, metrics);
+            }
+            DecodedMessage::UpEvent(message) => {
+                dispatch_to(&self.event.up, gateway_id, message, topic_type, metrics);
+            }
+            DecodedMessage::AckEvent(message) => {
+                dispatch_to(&self.event.ack, gateway_id, message, topic_type, metrics);
+            }
+            DecodedMessage::ExecEvent(message) => {
+                dispatch_to(&self.event.exec, gateway_id, message, topic_type, metrics);
             }
-            TopicType::State(state_type) => {
-                self.state
-                    .dispatch(state_type, topic.gateway_id, msg_payload)
-                    .await?;
+            DecodedMessage::RawEvent(message) => {
+                dispatch_to(&self.event.raw, gateway_id, message, topic_type, metrics);
             }
-            TopicType::Command(command_type) => {
-                self.command
-                    .dispatch(command_type, topic.gateway_id, msg_payload)
-                    .await?;
+            DecodedMessage::ConnState(message) => {
+                dispatch_to(&self.state.conn, gateway_id, message, topic_type, metrics);
             }
         }
-        Ok(())
     }
 }
 
@@ -243,7 +545,8 @@ impl CallbackCommandDrawer {
     ///
     /// Returns an error if no callback with the provided [`Uuid`] is found.
     pub(crate) fn remove(&mut self, uuid: &Uuid) -> Result<(), CallbackRemoveError> {
-        if self.down.remove(uuid).is_some()
+        if self.config.remove(uuid).is_some()
+            | self.down.remove(uuid).is_some()
             | self.exec.remove(uuid).is_some()
             | self.raw.remove(uuid).is_some()
         {
@@ -252,75 +555,6 @@ impl CallbackCommandDrawer {
             Err(CallbackRemoveError::NoSuchCallback { uuid: *uuid })
         }
     }
-
-    /// Calls every matching callbacks `dispatch_...` method with the gateway ID and message payload.
-    ///
-    /// # Errors
-    ///
-    /// Returns an error if the message payload cannot be decoded into a downlink [`chirpstack_api::gw::DownlinkFrame`].
-    pub(crate) async fn dispatch(
-        &self,
-        command_type: CommandType,
-        gateway_id: String,
-        msg_payload: Bytes,
-    ) -> Result<(), prost::DecodeError> {
-        match command_type {
-            CommandType::Down => {
-                let downlink_frame = chirpstack_api::gw::DownlinkFrame::decode(msg_payload)?;
-                for callback_fn in self.down.values() {
-                    let downlink_frame_clone = downlink_frame.clone();
-                    let gateway_id_clone = gateway_id.clone();
-                    let callback_fn_clone = callback_fn.clone();
-                    tokio::task::spawn(async move {
-                        callback_fn_clone
-                            .dispatch_down_command(gateway_id_clone, downlink_frame_clone)
-                            .await;
-                    });
-                }
-            }
-            CommandType::Config => {
-                let config_frame = chirpstack_api::gw::GatewayConfiguration::decode(msg_payload)?;
-                for callback_fn in self.config.values() {
-                    let config_frame_clone = config_frame.clone();
-                    let gateway_id_clone = gateway_id.clone();
-                    let callback_fn_clone = callback_fn.clone();
-                    tokio::task::spawn(async move {
-                        callback_fn_clone
-                            .dispatch_config_command(gateway_id_clone, config_frame_clone)
-                            .await;
-                    });
-                }
-            }
-            CommandType::Exec => {
-                let exec_frame =
-                    chirpstack_api::gw::GatewayCommandExecRequest::decode(msg_payload)?;
-                for callback_fn in self.exec.values() {
-                    let exec_frame_clone = exec_frame.clone();
-                    let gateway_id_clone = gateway_id.clone();
-                    let callback_fn_clone = callback_fn.clone();
-                    tokio::task::spawn(async move {
-                        callback_fn_clone
-                            .dispatch_exec_command(gateway_id_clone, exec_frame_clone)
-                            .await;
-                    });
-                }
-            }
-            CommandType::Raw => {
-                let raw_frame = chirpstack_api::gw::RawPacketForwarderCommand::decode(msg_payload)?;
-                for callback_fn in self.raw.values() {
-                    let raw_frame_clone = raw_frame.clone();
-                    let gateway_id_clone = gateway_id.clone();
-                    let callback_fn_clone = callback_fn.clone();
-                    tokio::task::spawn(async move {
-                        callback_fn_clone
-                            .dispatch_raw_command(gateway_id_clone, raw_frame_clone)
-                            .await;
-                    });
-                }
-            }
-        }
-        Ok(())
-    }
 }
 
 impl CallbackEventDrawer {
@@ -352,88 +586,6 @@ impl CallbackEventDrawer {
             Err(CallbackRemoveError::NoSuchCallback { uuid: *uuid })
         }
     }
-
-    /// Calls every matching callbacks `dispatch_...` method with the gateway ID and message payload.
-    ///
-    /// # Errors
-    ///
-    /// Returns an error if the message payload cannot be decoded into a downlink [`chirpstack_api::gw::DownlinkFrame`].
-    pub(crate) async fn dispatch(
-        &self,
-        event_type: EventType,
-        gateway_id: String,
-        msg_payload: Bytes,
-    ) -> Result<(), prost::DecodeError> {
-        match event_type {
-            EventType::Stats => {
-                let gateway_stats = chirpstack_api::gw::GatewayStats::decode(msg_payload)?;
-                for callback_fn in self.stats.values() {
-                    let gateway_stats_clone = gateway_stats.clone();
-                    let gateway_id_clone = gateway_id.clone();
-                    let callback_fn_clone = callback_fn.clone();
-                    tokio::task::spawn(async move {
-                        callback_fn_clone
-                            .dispatch_stats_event(gateway_id_clone, gateway_stats_clone)
-                            .await;
-                    });
-                }
-            }
-            EventType::Up => {
-                let uplink_frame = chirpstack_api::gw::UplinkFrame::decode(msg_payload)?;
-                for callback_fn in self.up.values() {
-                    let uplink_frame_clone = uplink_frame.clone();
-                    let gateway_id_clone = gateway_id.clone();
-                    let callback_fn_clone = callback_fn.clone();
-                    tokio::task::spawn(async move {
-                        callback_fn_clone
-                            .dispatch_up_event(gateway_id_clone, uplink_frame_clone)
-                            .await;
-                    });
-                }
-            }
-            EventType::Ack => {
-                let ack_frame = chirpstack_api::gw::DownlinkTxAck::decode(msg_payload)?;
-                for callback_fn in self.ack.values() {
-                    let ack_frame_clone = ack_frame.clone();
-                    let gateway_id_clone = gateway_id.clone();
-                    let callback_fn_clone = callback_fn.clone();
-                    tokio::task::spawn(async move {
-                        callback_fn_clone
-                            .dispatch_ack_event(gateway_id_clone, ack_frame_clone)
-                            .await;
-                    });
-                }
-            }
-            EventType::Exec => {
-                let exec_frame =
-                    chirpstack_api::gw::GatewayCommandExecResponse::decode(msg_payload)?;
-                for callback_fn in self.exec.values() {
-                    let exec_frame_clone = exec_frame.clone();
-                    let gateway_id_clone = gateway_id.clone();
-                    let callback_fn_clone = callback_fn.clone();
-                    tokio::task::spawn(async move {
-                        callback_fn_clone
-                            .dispatch_exec_event(gateway_id_clone, exec_frame_clone)
-                            .await;
-                    });
-                }
-            }
-            EventType::Raw => {
-                let raw_frame = chirpstack_api::gw::RawPacketForwarderEvent::decode(msg_payload)?;
-                for callback_fn in self.raw.values() {
-                    let raw_frame_clone = raw_frame.clone();
-                    let gateway_id_clone = gateway_id.clone();
-                    let callback_fn_clone = callback_fn.clone();
-                    tokio::task::spawn(async move {
-                        callback_fn_clone
-                            .dispatch_raw_event(gateway_id_clone, raw_frame_clone)
-                            .await;
-                    });
-                }
-            }
-        }
-        Ok(())
-    }
 }
 
 impl CallbackStateDrawer {
@@ -456,38 +608,134 @@ impl CallbackStateDrawer {
             Err(CallbackRemoveError::NoSuchCallback { uuid: *uuid })
         }
     }
+}
 
-    /// Calls every matching callbacks `dispatch_...` method with the gateway ID and message payload.
-    ///
-    /// # Errors
-    ///
-    /// Returns an error if the message payload cannot be decoded into a downlink [`chirpstack_api::gw::DownlinkFrame`].
-    pub(crate) async fn dispatch(
-        &self,
-        state_type: StateType,
-        gateway_id: String,
-        msg_payload: Bytes,
-    ) -> Result<(), prost::DecodeError> {
-        match state_type {
-            StateType::Conn => {
-                let conn_state = chirpstack_api::gw::ConnState::decode(msg_payload)?;
-                for callback_fn in self.conn.values() {
-                    let conn_state_clone = conn_state.clone();
-                    let gateway_id_clone = gateway_id.clone();
-                    let callback_fn_clone = callback_fn.clone();
-                    tokio::task::spawn(async move {
-                        callback_fn_clone
-                            .dispatch_conn_state(gateway_id_clone, conn_state_clone)
-                            .await;
-                    });
-                }
-            }
-        }
-        Ok(())
+/// Calls the `dispatch_...` method of every callback with the gateway ID and the message, the
+/// time until a callback finished is passed to the metrics hooks.
+fn dispatch_to<K>(
+    callbacks: &HashMap<Uuid, Arc<Box<K>>>,
+    gateway_id: GatewayId,
+    message: &K::Message,
+    topic_type: TopicType,
+    metrics: &SharedRuntimeMetrics,
+) where
+    K: CallbackKind + ?Sized + 'static,
+{
+    let dispatched_at = Instant::now();
+    for callback_fn in callbacks.values() {
+        let message_clone = message.clone();
+        let gateway_id_clone = gateway_id.clone();
+        let callback_fn_clone = callback_fn.clone();
+        let metrics_clone = metrics.clone();
+        tokio::task::spawn(async move {
+            callback_fn_clone
+                .dispatch(gateway_id_clone, message_clone)
+                .await;
+            metrics_clone.callback_completed(topic_type, dispatched_at.elapsed());
+        });
+    }
+}
+
+/// Calls the `dispatch_unknown_topic` method of every callback with the topic and the payload.
+pub(crate) fn dispatch_unknown_topic(
+    callbacks: &HashMap<Uuid, Arc<Box<dyn UnknownTopicCallback>>>,
+    topic: &str,
+    payload: &Bytes,
+) {
+    for callback_fn in callbacks.values() {
+        let topic_clone = topic.to_owned();
+        let payload_clone = payload.clone();
+        let callback_fn_clone = callback_fn.clone();
+        tokio::task::spawn(async move {
+            callback_fn_clone
+                .dispatch_unknown_topic(topic_clone, payload_clone)
+                .await;
+        });
     }
 }
 
 /// Thread safe callback storage for the [`Runtime`](crate::runtime::Runtime).
-pub(crate) type PerGatewayCallbackStorage = Arc<RwLock<HashMap<String, CallbackDrawers>>>;
+pub(crate) type PerGatewayCallbackStorage = Arc<RwLock<HashMap<GatewayId, CallbackDrawers>>>;
 /// Thread safe callback storage for the [`Runtime`](crate::runtime::Runtime).
 pub(crate) type AllGatewaysCallbackStorage = Arc<RwLock<CallbackDrawers>>;
+/// Thread safe storage of the callbacks for unrecognized topics for the
+/// [`Runtime`](crate::runtime::Runtime).
+pub(crate) type UnknownTopicCallbackStorage =
+    Arc<RwLock<HashMap<Uuid, Arc<Box<dyn UnknownTopicCallback>>>>>;
+/// Thread safe storage of the callback metadata for the [`Runtime`](crate::runtime::Runtime).
+pub(crate) type CallbackInfoStorage = Arc<RwLock<HashMap<Uuid, CallbackInfo>>>;
+
+#[cfg(test)]
+mod tests {
+    use crate::gateway_id::GatewayId;
+    use crate::gateway_topics::{EventType, ParsedTopic, TopicType};
+    use crate::runtime::callbacks::{
+        CallbackDrawers, CallbackKind, DecodedMessage, EventUpCallback,
+    };
+    use crate::runtime::marshaler::{Marshaler, MarshalerState};
+    use crate::runtime::metrics::{CountingMetrics, SharedRuntimeMetrics};
+    use async_trait::async_trait;
+    use prost::bytes::Bytes;
+    use prost::Message;
+    use std::sync::Arc;
+    use tokio::sync::mpsc;
+    use uuid::Uuid;
+
+    #[derive(Debug)]
+    struct ForwardingUpCallback {
+        uplink_tx: mpsc::Sender<(GatewayId, chirpstack_api::gw::UplinkFrame)>,
+    }
+
+    #[async_trait]
+    impl EventUpCallback for ForwardingUpCallback {
+        async fn dispatch_up_event(
+            &self,
+            gateway_id: GatewayId,
+            up_event: chirpstack_api::gw::UplinkFrame,
+        ) {
+            self.uplink_tx.send((gateway_id, up_event)).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dispatches_to_callbacks_of_kind() {
+        let (uplink_tx, mut uplink_rx) = mpsc::channel(1);
+        let mut drawers = CallbackDrawers::new();
+        let callback: Box<dyn EventUpCallback> = Box::new(ForwardingUpCallback { uplink_tx });
+        let counting_metrics = Arc::new(CountingMetrics::new());
+        let metrics: SharedRuntimeMetrics = counting_metrics.clone();
+        <dyn EventUpCallback>::callbacks(&mut drawers).insert(Uuid::new_v4(), Arc::new(callback));
+
+        let uplink = chirpstack_api::gw::UplinkFrame {
+            phy_payload: vec![0xE0, 0x01],
+            ..chirpstack_api::gw::UplinkFrame::default()
+        };
+        let topic_type = TopicType::Event(EventType::Up);
+        let message = DecodedMessage::decode(
+            topic_type,
+            Bytes::from(uplink.encode_to_vec()),
+            &MarshalerState::new(Marshaler::Protobuf),
+        )
+        .unwrap();
+        drawers.dispatch(
+            ParsedTopic {
+                region: None,
+                gateway_id: "a840411d25244150".parse().unwrap(),
+                topic_type,
+            },
+            &message,
+            &metrics,
+        );
+
+        assert_eq!(
+            uplink_rx.recv().await.unwrap(),
+            ("a840411d25244150".parse().unwrap(), uplink)
+        );
+        // The latency is recorded once the callback returned.
+        tokio::task::yield_now().await;
+        assert_eq!(
+            counting_metrics.snapshot().callback_latency["event/up"].completed,
+            1
+        );
+    }
+}
@@ -0,0 +1,167 @@
+//! Deterministic allocation of downlink IDs and tracking of the subsystem they were allocated for.
+//!
+//! ChirpStack draws downlink IDs at random, so the acknowledgement of a downlink can only be
+//! matched by luck once many downlinks are in flight. The [`DownlinkIds`] allocate monotonically
+//! increasing IDs per gateway, skip IDs which are still tracked and remember the subsystem every
+//! ID was allocated for, so acknowledgements and log lines can be correlated reliably.
+
+use crate::gateway_id::GatewayId;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::sync::RwLock;
+
+/// Amount of allocated downlink IDs tracked per gateway, older IDs are forgotten.
+pub const MAX_TRACKED_DOWNLINK_IDS: usize = 1024;
+
+/// Downlink ID allocation per gateway ID.
+pub(crate) type DownlinkIdStorage = Arc<RwLock<DownlinkIds>>;
+
+/// Subsystem a downlink ID was allocated for, returned by
+/// [`Runtime::downlink_origin`](crate::runtime::Runtime::downlink_origin).
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct DownlinkOrigin {
+    /// The allocated downlink ID.
+    pub downlink_id: u32,
+    /// Name of the subsystem sending the downlink.
+    pub subsystem: String,
+    /// Time the downlink ID was allocated.
+    pub allocated_at: SystemTime,
+}
+
+/// Allocated downlink IDs of a gateway.
+#[derive(Debug, Clone, Default)]
+struct GatewayDownlinkIds {
+    /// Next downlink ID to allocate.
+    next: u32,
+    /// Tracked downlink IDs, oldest first.
+    allocated: VecDeque<u32>,
+    /// Origins of the tracked downlink IDs.
+    origins: HashMap<u32, DownlinkOrigin>,
+}
+
+/// Allocator of the downlink IDs of all gateways.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DownlinkIds {
+    /// Allocated downlink IDs per gateway ID.
+    gateways: HashMap<GatewayId, GatewayDownlinkIds>,
+    /// Allocations which skipped a downlink ID as it was still tracked.
+    collisions: u64,
+}
+
+impl DownlinkIds {
+    /// Allocates the next downlink ID of the gateway for the subsystem.
+    ///
+    /// IDs start at 1 as 0 marks an unset ID, IDs which are still tracked are skipped and counted
+    /// as collision.
+    pub(crate) fn allocate(&mut self, gateway_id: &GatewayId, subsystem: &str) -> u32 {
+        let gateway = self.gateways.entry(gateway_id.clone()).or_default();
+        let mut downlink_id = gateway.next.max(1);
+        while gateway.origins.contains_key(&downlink_id) {
+            self.collisions = self.collisions.saturating_add(1);
+            downlink_id = downlink_id.wrapping_add(1).max(1);
+        }
+        gateway.next = downlink_id.wrapping_add(1);
+        gateway.allocated.push_back(downlink_id);
+        gateway.origins.insert(
+            downlink_id,
+            DownlinkOrigin {
+                downlink_id,
+                subsystem: subsystem.to_owned(),
+                allocated_at: SystemTime::now(),
+            },
+        );
+        while gateway.allocated.len() > MAX_TRACKED_DOWNLINK_IDS {
+            if let Some(forgotten) = gateway.allocated.pop_front() {
+                gateway.origins.remove(&forgotten);
+            }
+        }
+        downlink_id
+    }
+
+    /// Returns the origin of a tracked downlink ID of the gateway.
+    pub(crate) fn origin(
+        &self,
+        gateway_id: &GatewayId,
+        downlink_id: u32,
+    ) -> Option<DownlinkOrigin> {
+        self.gateways
+            .get(gateway_id)
+            .and_then(|gateway| gateway.origins.get(&downlink_id))
+            .cloned()
+    }
+
+    /// Returns the origins of all tracked downlink IDs of the gateway, oldest first.
+    pub(crate) fn origins(&self, gateway_id: &GatewayId) -> Vec<DownlinkOrigin> {
+        self.gateways
+            .get(gateway_id)
+            .map(|gateway| {
+                gateway
+                    .allocated
+                    .iter()
+                    .filter_map(|downlink_id| gateway.origins.get(downlink_id))
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Returns the next downlink ID per gateway, e.g. to persist them.
+    pub(crate) fn counters(&self) -> HashMap<GatewayId, u32> {
+        self.gateways
+            .iter()
+            .map(|(gateway_id, gateway)| (gateway_id.clone(), gateway.next))
+            .collect()
+    }
+
+    /// Continues the allocation at the supplied next downlink IDs, e.g. after a restart, so
+    /// acknowledgements of downlinks sent before are not mistaken for new ones.
+    pub(crate) fn restore_counters(&mut self, counters: HashMap<GatewayId, u32>) {
+        for (gateway_id, next) in counters {
+            let gateway = self.gateways.entry(gateway_id).or_default();
+            gateway.next = gateway.next.max(next);
+        }
+    }
+
+    /// Returns the amount of skipped downlink IDs.
+    pub(crate) fn collisions(&self) -> u64 {
+        self.collisions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gateway_id::GatewayId;
+    use crate::runtime::downlink_ids::{DownlinkIds, MAX_TRACKED_DOWNLINK_IDS};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_allocation_is_monotonic_per_gateway_and_skips_tracked_ids() {
+        let a: GatewayId = "a840411d25244150".parse().unwrap();
+        let b: GatewayId = "a840411d25244151".parse().unwrap();
+        let mut downlink_ids = DownlinkIds::default();
+        assert_eq!(downlink_ids.allocate(&a, "flooding"), 1);
+        assert_eq!(downlink_ids.allocate(&a, "retransmission"), 2);
+        assert_eq!(downlink_ids.allocate(&b, "flooding"), 1);
+        assert_eq!(
+            downlink_ids.origin(&a, 2).map(|origin| origin.subsystem),
+            Some("retransmission".to_owned())
+        );
+        assert_eq!(downlink_ids.origin(&b, 2), None);
+
+        downlink_ids.restore_counters(HashMap::from([(b.clone(), u32::MAX)]));
+        assert_eq!(downlink_ids.allocate(&b, "flooding"), u32::MAX);
+        assert_eq!(downlink_ids.allocate(&b, "flooding"), 2);
+        assert_eq!(downlink_ids.collisions(), 1);
+        assert_eq!(
+            downlink_ids.counters(),
+            HashMap::from([(a.clone(), 3), (b.clone(), 3)])
+        );
+
+        for _ in 0..MAX_TRACKED_DOWNLINK_IDS {
+            downlink_ids.allocate(&a, "flooding");
+        }
+        assert_eq!(downlink_ids.origins(&a).len(), MAX_TRACKED_DOWNLINK_IDS);
+        assert_eq!(downlink_ids.origin(&a, 1), None);
+    }
+}
@@ -1,9 +1,17 @@
 //! The event loop processing incoming MQTT messages.
 
-use crate::gateway_topics::ParsedTopic;
-use crate::runtime::callbacks::{AllGatewaysCallbackStorage, PerGatewayCallbackStorage};
-use prost::Message;
-use rumqttc::{Event, EventLoop, Incoming, Publish};
+use crate::gateway_topics::TopicLayout;
+use crate::runtime::callbacks::{
+    dispatch_unknown_topic, AllGatewaysCallbackStorage, DecodedMessage, PerGatewayCallbackStorage,
+    UnknownTopicCallbackStorage,
+};
+use crate::runtime::gateway_time::{update_gateway_time, GatewayTimeStorage};
+use crate::runtime::marshaler::MarshalerState;
+use crate::runtime::metrics::SharedRuntimeMetrics;
+use crate::runtime::mqtt_client::{MqttEvent, MqttEventLoop};
+use prost::bytes::Bytes;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
 use tokio::time::Instant;
 #[cfg(debug_assertions)]
@@ -15,9 +23,16 @@ use tracing::{error, trace};
 /// Needs to be spawned in an async task and kept running continuously.
 #[tracing::instrument(skip_all)]
 pub(crate) async fn run_event_loop(
-    mut event_loop: EventLoop,
+    mut event_loop: MqttEventLoop,
     per_gateway_callbacks: PerGatewayCallbackStorage,
     all_gateways_callbacks: AllGatewaysCallbackStorage,
+    unknown_topic_callbacks: UnknownTopicCallbackStorage,
+    gateway_times: GatewayTimeStorage,
+    marshaler: Arc<MarshalerState>,
+    topic_layout: TopicLayout,
+    broker_connected: Arc<AtomicBool>,
+    metrics: SharedRuntimeMetrics,
+    clock_drift_threshold: Option<Duration>,
     connection_error_sender: Option<tokio::sync::broadcast::Sender<String>>,
     mut stop_signal_rx: tokio::sync::mpsc::Receiver<()>,
 ) {
@@ -31,44 +46,69 @@ pub(crate) async fn run_event_loop(
 
         match notification {
             Ok(notification) => {
-                if let Event::Incoming(Incoming::Publish(pub_msg)) = notification {
-                    trace!("Incoming msg Publish: {:?}", pub_msg);
+                if notification == MqttEvent::Connected {
+                    trace!("Connected to the MQTT broker");
+                    broker_connected.store(true, Ordering::Relaxed);
+                }
+                if let MqttEvent::Publish { topic, payload } = notification {
+                    trace!("Incoming msg Publish on {topic}: {payload:?}");
 
                     #[cfg(debug_assertions)]
                     {
-                        debug_printing(&pub_msg);
+                        debug_printing(&topic, &payload, &marshaler);
                     }
 
-                    let parsed_topic = match ParsedTopic::try_from(pub_msg.topic.as_str()) {
+                    let parsed_topic = match topic_layout.parse(&topic) {
                         Ok(parsed_topic) => parsed_topic,
+                        Err(e) => {
+                            metrics.unknown_topic_received();
+                            let unknown_topic_callbacks = unknown_topic_callbacks.read().await;
+                            if unknown_topic_callbacks.is_empty() {
+                                error!(%e);
+                            } else {
+                                trace!("Dispatching message of unrecognized topic: {e}");
+                                dispatch_unknown_topic(&unknown_topic_callbacks, &topic, &payload);
+                            }
+                            continue;
+                        }
+                    };
+                    let topic_type = parsed_topic.topic_type;
+                    metrics.message_received(topic_type);
+
+                    let message = match DecodedMessage::decode(topic_type, payload, &marshaler) {
+                        Ok(message) => message,
                         Err(e) => {
                             error!(%e);
+                            metrics.decode_failed(topic_type);
                             continue;
                         }
                     };
 
+                    update_gateway_time(
+                        &gateway_times,
+                        &parsed_topic.gateway_id,
+                        &message,
+                        clock_drift_threshold,
+                    )
+                    .await;
+
                     if let Some(per_gateway_callback_drawers) = per_gateway_callbacks
                         .read()
                         .await
                         .get(&parsed_topic.gateway_id)
                     {
                         trace!("Per gateway callback for message found.");
-                        if let Err(e) = per_gateway_callback_drawers
-                            .dispatch(parsed_topic.clone(), pub_msg.payload.clone())
-                            .await
-                        {
-                            error!(%e);
-                        }
+                        per_gateway_callback_drawers.dispatch(
+                            parsed_topic.clone(),
+                            &message,
+                            &metrics,
+                        );
                     }
 
-                    if let Err(e) = all_gateways_callbacks
+                    all_gateways_callbacks
                         .read()
                         .await
-                        .dispatch(parsed_topic, pub_msg.payload)
-                        .await
-                    {
-                        error!(%e);
-                    }
+                        .dispatch(parsed_topic, &message, &metrics);
                 }
             }
             Err(e) => {
@@ -76,6 +116,7 @@ pub(crate) async fn run_event_loop(
                 // Connection error handling goes here if required.
 
                 error!(%e);
+                broker_connected.store(false, Ordering::Relaxed);
 
                 // If the last error happened over 30 seconds ago, reset error timer, otherwise
                 // increase error counter.
@@ -104,64 +145,60 @@ pub(crate) async fn run_event_loop(
 ///
 /// Only included in debug builds.
 #[cfg(debug_assertions)]
-fn debug_printing(pub_msg: &Publish) {
+fn debug_printing(topic: &str, payload: &Bytes, marshaler: &MarshalerState) {
     {
-        if pub_msg.topic.contains("command") && pub_msg.topic.contains("down") {
+        if topic.contains("command") && topic.contains("down") {
             debug!(
                 "Command down frame payload: {:?}",
-                chirpstack_api::gw::DownlinkFrame::decode(pub_msg.payload.clone()).expect("Debug")
+                marshaler.decode::<chirpstack_api::gw::DownlinkFrame>(payload.clone())
             );
         }
-        if pub_msg.topic.contains("command") && pub_msg.topic.contains("exec") {
+        if topic.contains("command") && topic.contains("exec") {
             debug!(
                 "Command exec frame payload: {:?}",
-                chirpstack_api::gw::GatewayCommandExecRequest::decode(pub_msg.payload.clone())
-                    .expect("Debug")
+                marshaler.decode::<chirpstack_api::gw::GatewayCommandExecRequest>(payload.clone())
             );
         }
-        if pub_msg.topic.contains("command") && pub_msg.topic.contains("raw") {
+        if topic.contains("command") && topic.contains("raw") {
             debug!(
                 "Command raw frame payload: {:?}",
-                chirpstack_api::gw::GatewayCommandExecRequest::decode(pub_msg.payload.clone())
-                    .expect("Debug")
+                marshaler.decode::<chirpstack_api::gw::GatewayCommandExecRequest>(payload.clone())
             );
         }
-        if pub_msg.topic.contains("event") && pub_msg.topic.contains("stats") {
+        if topic.contains("event") && topic.contains("stats") {
             debug!(
                 "Event stats frame payload: {:?}",
-                chirpstack_api::gw::GatewayStats::decode(pub_msg.payload.clone()).expect("Debug")
+                marshaler.decode::<chirpstack_api::gw::GatewayStats>(payload.clone())
             );
         }
-        if pub_msg.topic.contains("event") && pub_msg.topic.contains("up") {
+        if topic.contains("event") && topic.contains("up") {
             debug!(
                 "Event up frame payload: {:?}",
-                chirpstack_api::gw::UplinkFrame::decode(pub_msg.payload.clone()).expect("Debug")
+                marshaler.decode::<chirpstack_api::gw::UplinkFrame>(payload.clone())
             );
         }
-        if pub_msg.topic.contains("event") && pub_msg.topic.contains("ack") {
+        if topic.contains("event") && topic.contains("ack") {
             debug!(
                 "Event ack frame payload: {:?}",
-                chirpstack_api::gw::DownlinkTxAck::decode(pub_msg.payload.clone()).expect("Debug")
+                marshaler.decode::<chirpstack_api::gw::DownlinkTxAck>(payload.clone())
             );
         }
-        if pub_msg.topic.contains("event") && pub_msg.topic.contains("exec") {
+        if topic.contains("event") && topic.contains("exec") {
             debug!(
                 "Event exec frame payload: {:?}",
-                chirpstack_api::gw::GatewayCommandExecResponse::decode(pub_msg.payload.clone())
-                    .expect("Debug")
+                marshaler.decode::<chirpstack_api::gw::GatewayCommandExecResponse>(payload.clone())
             );
         }
-        if pub_msg.topic.contains("event") && pub_msg.topic.contains("raw") {
+        if topic.contains("event") && topic.contains("raw") {
             debug!(
                 "Event raw frame payload: {:?}",
-                chirpstack_api::gw::RawPacketForwarderEvent::decode(pub_msg.payload.clone())
-                    .expect("Debug")
+                marshaler.decode::<chirpstack_api::gw::RawPacketForwarderEvent>(payload.clone())
             );
         }
-        if pub_msg.topic.contains("state") && pub_msg.topic.contains("conn") {
+        if topic.contains("state") && topic.contains("conn") {
             debug!(
                 "Event exec frame payload: {:?}",
-                chirpstack_api::gw::ConnState::decode(pub_msg.payload.clone()).expect("Debug")
+                marshaler.decode::<chirpstack_api::gw::ConnState>(payload.clone())
             );
         }
     }
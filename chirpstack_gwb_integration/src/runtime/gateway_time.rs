@@ -0,0 +1,354 @@
+//! Gateway time tracking and conversion of wall-clock times into GPS epoch based timings.
+//!
+//! The clock offset of a gateway is learned from the time in its stats and the gateway time of
+//! its uplinks. Gateways whose offset exceeds the clock drift threshold of the
+//! [`RuntimeOptions`](crate::runtime::RuntimeOptions) are flagged as drifting, as a drifting clock
+//! shifts receive windows and duty cycle accounting.
+
+use crate::error::GatewayTimeError;
+use crate::gateway_id::GatewayId;
+use crate::runtime::callbacks::DecodedMessage;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::sync::RwLock;
+use tracing::{info, trace, warn};
+
+/// Seconds between the UNIX epoch (1970-01-01) and the GPS epoch (1980-01-06).
+const GPS_EPOCH_UNIX_OFFSET_SECS: u64 = 315_964_800;
+
+/// UNIX times from which GPS time is one more leap second ahead of UTC, 18 seconds since
+/// 2017-01-01. Leap seconds announced in the future have to be appended.
+const LEAP_SECONDS_UNIX_SECS: [u64; 18] = [
+    362_793_600,   // 1981-07-01
+    394_329_600,   // 1982-07-01
+    425_865_600,   // 1983-07-01
+    489_024_000,   // 1985-07-01
+    567_993_600,   // 1988-01-01
+    631_152_000,   // 1990-01-01
+    662_688_000,   // 1991-01-01
+    709_948_800,   // 1992-07-01
+    741_484_800,   // 1993-07-01
+    773_020_800,   // 1994-07-01
+    820_454_400,   // 1996-01-01
+    867_715_200,   // 1997-07-01
+    915_148_800,   // 1999-01-01
+    1_136_073_600, // 2006-01-01
+    1_230_768_000, // 2009-01-01
+    1_341_100_800, // 2012-07-01
+    1_435_708_800, // 2015-07-01
+    1_483_228_800, // 2017-01-01
+];
+
+/// Time information per gateway ID.
+pub(crate) type GatewayTimeStorage = Arc<RwLock<HashMap<GatewayId, GatewayTime>>>;
+
+/// Returns the leap seconds GPS time is ahead of UTC at the UNIX time.
+fn gps_utc_leap_seconds(unix_secs: u64) -> u64 {
+    LEAP_SECONDS_UNIX_SECS.partition_point(|&leap_second| leap_second <= unix_secs) as u64
+}
+
+/// Converts a wall-clock (UTC) time into the duration since the GPS epoch, including the leap
+/// seconds inserted into UTC until then.
+///
+/// # Errors
+///
+/// Returns an error if `when` lies before the GPS epoch.
+pub fn system_time_to_gps_epoch(when: SystemTime) -> Result<Duration, GatewayTimeError> {
+    let since_unix_epoch = when
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_err(|_| GatewayTimeError::BeforeGpsEpoch)?;
+    let leap_seconds = gps_utc_leap_seconds(since_unix_epoch.as_secs());
+    since_unix_epoch
+        .checked_sub(Duration::from_secs(GPS_EPOCH_UNIX_OFFSET_SECS))
+        .map(|since_gps_epoch| since_gps_epoch + Duration::from_secs(leap_seconds))
+        .ok_or(GatewayTimeError::BeforeGpsEpoch)
+}
+
+/// Offset of a gateway clock compared to the local clock.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ClockOffset {
+    /// The gateway clock is ahead of the local clock.
+    Ahead(Duration),
+    /// The gateway clock is behind the local clock.
+    Behind(Duration),
+}
+
+impl ClockOffset {
+    /// Calculates the offset between the gateway time and the local time.
+    fn between(gateway_time: SystemTime, local_time: SystemTime) -> Self {
+        match gateway_time.duration_since(local_time) {
+            Ok(ahead) => ClockOffset::Ahead(ahead),
+            Err(behind) => ClockOffset::Behind(behind.duration()),
+        }
+    }
+
+    /// Returns the absolute offset, regardless of the direction.
+    #[must_use]
+    pub fn magnitude(self) -> Duration {
+        match self {
+            ClockOffset::Ahead(offset) | ClockOffset::Behind(offset) => offset,
+        }
+    }
+
+    /// Returns the offset in milliseconds, positive if the gateway clock is ahead.
+    #[must_use]
+    pub fn as_signed_millis(self) -> i64 {
+        let millis = i64::try_from(self.magnitude().as_millis()).unwrap_or(i64::MAX);
+        match self {
+            ClockOffset::Ahead(_) => millis,
+            ClockOffset::Behind(_) => -millis,
+        }
+    }
+
+    /// Shifts a local time into the time frame of the gateway.
+    fn apply(self, local_time: SystemTime) -> SystemTime {
+        match self {
+            ClockOffset::Ahead(offset) => local_time + offset,
+            ClockOffset::Behind(offset) => local_time - offset,
+        }
+    }
+}
+
+/// Time information learned from the messages of a gateway.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct GatewayTime {
+    /// Offset of the gateway clock, learned from the time in the gateway stats and uplinks.
+    clock_offset: Option<ClockOffset>,
+    /// Local time the clock offset was last learned.
+    clock_offset_updated_at: Option<SystemTime>,
+    /// Whether the clock offset exceeds the clock drift threshold.
+    drifting: bool,
+    /// Last time an uplink with a GPS epoch timestamp was received from the gateway.
+    last_gps_timestamp: Option<SystemTime>,
+}
+
+impl GatewayTime {
+    /// Returns whether the gateway reported GPS epoch timestamps and is therefore able to send
+    /// [`GpsTimingClassB`](crate::downlinks::GpsTimingClassB) downlinks.
+    #[must_use]
+    pub fn supports_gps_timing(&self) -> bool {
+        self.last_gps_timestamp.is_some()
+    }
+
+    /// Returns the offset of the gateway clock compared to the local clock, if known.
+    #[must_use]
+    pub fn clock_offset(&self) -> Option<ClockOffset> {
+        self.clock_offset
+    }
+
+    /// Returns the local time the clock offset was last learned, if known.
+    #[must_use]
+    pub fn clock_offset_updated_at(&self) -> Option<SystemTime> {
+        self.clock_offset_updated_at
+    }
+
+    /// Returns whether the clock offset exceeded the clock drift threshold when it was last
+    /// learned, always `false` without threshold.
+    #[must_use]
+    pub fn is_drifting(&self) -> bool {
+        self.drifting
+    }
+
+    /// Converts a local wall-clock time into the time since GPS epoch as seen by the gateway.
+    ///
+    /// Applies the clock offset learned from the gateway stats if available.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `when` lies before the GPS epoch.
+    pub fn time_since_gps_epoch(&self, when: SystemTime) -> Result<Duration, GatewayTimeError> {
+        let gateway_when = self
+            .clock_offset
+            .map_or(when, |clock_offset| clock_offset.apply(when));
+        system_time_to_gps_epoch(gateway_when)
+    }
+
+    /// Updates the clock offset with the time of a gateway stats message.
+    fn update_from_stats(&mut self, stats: &chirpstack_api::gw::GatewayStats, now: SystemTime) {
+        if let Some(Ok(gateway_time)) = stats.time.clone().map(SystemTime::try_from) {
+            self.update_clock_offset(gateway_time, now);
+        }
+    }
+
+    /// Updates the clock offset with the gateway time of an uplink and marks the gateway as GPS
+    /// capable if the uplink contains a GPS epoch timestamp.
+    fn update_from_uplink(&mut self, uplink: &chirpstack_api::gw::UplinkFrame, now: SystemTime) {
+        let Some(rx_info) = uplink.rx_info.as_ref() else {
+            return;
+        };
+        if rx_info.time_since_gps_epoch.is_some() {
+            self.last_gps_timestamp = Some(now);
+        }
+        if let Some(Ok(gateway_time)) = rx_info.gw_time.clone().map(SystemTime::try_from) {
+            self.update_clock_offset(gateway_time, now);
+        }
+    }
+
+    /// Learns the clock offset from a time reported by the gateway.
+    fn update_clock_offset(&mut self, gateway_time: SystemTime, now: SystemTime) {
+        self.clock_offset = Some(ClockOffset::between(gateway_time, now));
+        self.clock_offset_updated_at = Some(now);
+    }
+
+    /// Flags the gateway as drifting if the clock offset exceeds the threshold.
+    ///
+    /// Returns the new state if it changed.
+    fn check_drift(&mut self, threshold: Duration) -> Option<bool> {
+        let drifting = self
+            .clock_offset
+            .is_some_and(|clock_offset| clock_offset.magnitude() > threshold);
+        if drifting == self.drifting {
+            return None;
+        }
+        self.drifting = drifting;
+        Some(drifting)
+    }
+}
+
+/// Updates the gateway time information with decoded stats and uplink events, logs a warning if
+/// the clock offset of the gateway exceeds the clock drift threshold.
+pub(crate) async fn update_gateway_time(
+    gateway_times: &GatewayTimeStorage,
+    gateway_id: &GatewayId,
+    message: &DecodedMessage,
+    clock_drift_threshold: Option<Duration>,
+) {
+    let now = SystemTime::now();
+    let mut gateway_times = match message {
+        DecodedMessage::StatsEvent(stats) => {
+            trace!("Updating gateway time from stats");
+            let mut gateway_times = gateway_times.write().await;
+            gateway_times
+                .entry(gateway_id.clone())
+                .or_default()
+                .update_from_stats(stats, now);
+            gateway_times
+        }
+        DecodedMessage::UpEvent(uplink) => {
+            let mut gateway_times = gateway_times.write().await;
+            gateway_times
+                .entry(gateway_id.clone())
+                .or_default()
+                .update_from_uplink(uplink, now);
+            gateway_times
+        }
+        _ => return,
+    };
+
+    let Some(threshold) = clock_drift_threshold else {
+        return;
+    };
+    let Some(gateway_time) = gateway_times.get_mut(gateway_id) else {
+        return;
+    };
+    let offset_millis = gateway_time
+        .clock_offset
+        .map_or(0, ClockOffset::as_signed_millis);
+    match gateway_time.check_drift(threshold) {
+        Some(true) => warn!(
+            "Clock of gateway {gateway_id} is off by {offset_millis} ms, exceeding {} ms",
+            threshold.as_millis()
+        ),
+        Some(false) => info!(
+            "Clock of gateway {gateway_id} is back within {} ms, off by {offset_millis} ms",
+            threshold.as_millis()
+        ),
+        None => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::GatewayTimeError;
+    use crate::runtime::gateway_time::{
+        system_time_to_gps_epoch, ClockOffset, GatewayTime, GPS_EPOCH_UNIX_OFFSET_SECS,
+    };
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn test_gps_epoch_conversion() {
+        let gps_epoch = SystemTime::UNIX_EPOCH + Duration::from_secs(GPS_EPOCH_UNIX_OFFSET_SECS);
+        assert_eq!(system_time_to_gps_epoch(gps_epoch), Ok(Duration::ZERO));
+        assert_eq!(
+            system_time_to_gps_epoch(gps_epoch + Duration::from_millis(1500)),
+            Ok(Duration::from_millis(1500))
+        );
+    }
+
+    #[test]
+    fn test_gps_epoch_conversion_applies_leap_seconds() {
+        // 2017-01-01, the last leap second so far.
+        let leap_second = SystemTime::UNIX_EPOCH + Duration::from_secs(1_483_228_800);
+        let since_gps_epoch = Duration::from_secs(1_483_228_800 - GPS_EPOCH_UNIX_OFFSET_SECS);
+        assert_eq!(
+            system_time_to_gps_epoch(leap_second - Duration::from_secs(1)),
+            Ok(since_gps_epoch - Duration::from_secs(1) + Duration::from_secs(17))
+        );
+        assert_eq!(
+            system_time_to_gps_epoch(leap_second),
+            Ok(since_gps_epoch + Duration::from_secs(18))
+        );
+    }
+
+    #[test]
+    fn test_gps_epoch_conversion_before_epoch() {
+        assert_eq!(
+            system_time_to_gps_epoch(SystemTime::UNIX_EPOCH),
+            Err(GatewayTimeError::BeforeGpsEpoch)
+        );
+    }
+
+    #[test]
+    fn test_gateway_time_applies_clock_offset() {
+        let when = SystemTime::UNIX_EPOCH + Duration::from_secs(GPS_EPOCH_UNIX_OFFSET_SECS + 100);
+        let mut gateway_time = GatewayTime::default();
+        assert!(!gateway_time.supports_gps_timing());
+        assert_eq!(
+            gateway_time.time_since_gps_epoch(when),
+            Ok(Duration::from_secs(100))
+        );
+
+        gateway_time.clock_offset = Some(ClockOffset::between(when + Duration::from_secs(2), when));
+        assert_eq!(
+            gateway_time.time_since_gps_epoch(when),
+            Ok(Duration::from_secs(102))
+        );
+
+        gateway_time.clock_offset = Some(ClockOffset::between(when - Duration::from_secs(2), when));
+        assert_eq!(
+            gateway_time.time_since_gps_epoch(when),
+            Ok(Duration::from_secs(98))
+        );
+    }
+
+    #[test]
+    fn test_clock_drift_from_uplinks() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(GPS_EPOCH_UNIX_OFFSET_SECS + 100);
+        let uplink = |gateway_time: SystemTime| chirpstack_api::gw::UplinkFrame {
+            rx_info: Some(chirpstack_api::gw::UplinkRxInfo {
+                gw_time: Some(gateway_time.into()),
+                ..chirpstack_api::gw::UplinkRxInfo::default()
+            }),
+            ..chirpstack_api::gw::UplinkFrame::default()
+        };
+        let threshold = Duration::from_millis(500);
+        let mut gateway_time = GatewayTime::default();
+        assert_eq!(gateway_time.check_drift(threshold), None);
+
+        gateway_time.update_from_uplink(&uplink(now - Duration::from_millis(1200)), now);
+        assert_eq!(
+            gateway_time.clock_offset().unwrap().as_signed_millis(),
+            -1200
+        );
+        assert_eq!(gateway_time.clock_offset_updated_at(), Some(now));
+        assert_eq!(gateway_time.check_drift(threshold), Some(true));
+        assert!(gateway_time.is_drifting());
+        assert_eq!(gateway_time.check_drift(threshold), None);
+
+        gateway_time.update_from_uplink(&uplink(now + Duration::from_millis(100)), now);
+        assert_eq!(gateway_time.clock_offset().unwrap().as_signed_millis(), 100);
+        assert_eq!(gateway_time.check_drift(threshold), Some(false));
+        assert!(!gateway_time.is_drifting());
+    }
+}
@@ -0,0 +1,154 @@
+//! Decoding and encoding of MQTT payloads with the marshaler configured in the gateway bridge.
+//!
+//! The ChirpStack Gateway Bridge publishes either protobuf or JSON payloads. With
+//! [`Marshaler::Auto`] the format of every incoming payload is detected and commands are encoded
+//! in the format last received.
+
+use crate::error::PayloadDecodeError;
+use prost::bytes::Bytes;
+use prost::Message;
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use serde_derive::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use tracing::trace;
+
+/// Payload format used by the gateway bridge.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum Marshaler {
+    /// Detect the format of every incoming payload, commands are encoded in the format last
+    /// received, protobuf until the first payload is received.
+    #[default]
+    Auto,
+    /// Protobuf, the default of the gateway bridge.
+    Protobuf,
+    /// JSON, as produced by the `json` marshaler of the gateway bridge.
+    Json,
+}
+
+impl Marshaler {
+    /// Detects the format of a payload.
+    ///
+    /// JSON payloads of the gateway bridge are objects, a protobuf message never starts with `{`
+    /// as that would be the deprecated start group wire type of field 15.
+    #[must_use]
+    pub fn detect(payload: &[u8]) -> Self {
+        match payload.iter().find(|byte| !byte.is_ascii_whitespace()) {
+            Some(b'{') => Marshaler::Json,
+            _ => Marshaler::Protobuf,
+        }
+    }
+}
+
+/// Configured [`Marshaler`] and the format detected last, shared by the event loop and the
+/// [`Runtime`](crate::runtime::Runtime).
+#[derive(Debug, Default)]
+pub(crate) struct MarshalerState {
+    /// The configured marshaler.
+    configured: Marshaler,
+    /// Whether the last payload detected with [`Marshaler::Auto`] was JSON.
+    last_detected_json: AtomicBool,
+}
+
+impl MarshalerState {
+    /// Creates a new [`MarshalerState`] with the configured marshaler.
+    pub(crate) fn new(configured: Marshaler) -> Self {
+        Self {
+            configured,
+            last_detected_json: AtomicBool::new(false),
+        }
+    }
+
+    /// Decodes a payload with the configured marshaler or the detected format.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the payload cannot be decoded into `T`.
+    pub(crate) fn decode<T>(&self, payload: Bytes) -> Result<T, PayloadDecodeError>
+    where
+        T: Message + Default + DeserializeOwned,
+    {
+        let marshaler = match self.configured {
+            Marshaler::Auto => {
+                let detected = Marshaler::detect(&payload);
+                self.last_detected_json
+                    .store(detected == Marshaler::Json, Ordering::Relaxed);
+                detected
+            }
+            configured => configured,
+        };
+        match marshaler {
+            Marshaler::Json => {
+                trace!("Decoding JSON payload");
+                Ok(serde_json::from_slice(&payload)?)
+            }
+            Marshaler::Protobuf | Marshaler::Auto => Ok(T::decode(payload)?),
+        }
+    }
+
+    /// Encodes a message with the configured marshaler or the format detected last.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the message cannot be serialized into JSON.
+    pub(crate) fn encode<T>(&self, message: &T) -> Result<Vec<u8>, serde_json::Error>
+    where
+        T: Message + serde::Serialize,
+    {
+        let json = match self.configured {
+            Marshaler::Auto => self.last_detected_json.load(Ordering::Relaxed),
+            Marshaler::Protobuf => false,
+            Marshaler::Json => true,
+        };
+        if json {
+            serde_json::to_vec(message)
+        } else {
+            Ok(message.encode_to_vec())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::runtime::marshaler::{Marshaler, MarshalerState};
+    use chirpstack_api::gw::{DownlinkFrame, UplinkFrame};
+    use prost::bytes::Bytes;
+    use prost::Message;
+
+    #[test]
+    fn test_auto_detects_payload_format() {
+        let uplink = UplinkFrame {
+            phy_payload: vec![0xE0, 0x01, 0x02],
+            ..UplinkFrame::default()
+        };
+        let downlink = DownlinkFrame {
+            downlink_id: 7,
+            ..DownlinkFrame::default()
+        };
+        let marshaler_state = MarshalerState::new(Marshaler::Auto);
+
+        let protobuf_payload = Bytes::from(uplink.encode_to_vec());
+        assert_eq!(Marshaler::detect(&protobuf_payload), Marshaler::Protobuf);
+        assert_eq!(
+            marshaler_state
+                .decode::<UplinkFrame>(protobuf_payload)
+                .unwrap(),
+            uplink
+        );
+        assert_eq!(
+            marshaler_state.encode(&downlink).unwrap(),
+            downlink.encode_to_vec()
+        );
+
+        let json_payload = Bytes::from(serde_json::to_vec(&uplink).unwrap());
+        assert_eq!(Marshaler::detect(&json_payload), Marshaler::Json);
+        assert_eq!(
+            marshaler_state.decode::<UplinkFrame>(json_payload).unwrap(),
+            uplink
+        );
+        assert_eq!(
+            marshaler_state.encode(&downlink).unwrap(),
+            serde_json::to_vec(&downlink).unwrap()
+        );
+    }
+}
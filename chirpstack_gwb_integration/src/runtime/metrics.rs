@@ -0,0 +1,186 @@
+//! Hooks recording the activity of the runtime, e.g. to export it to a monitoring system.
+//!
+//! Pass an implementation of [`RuntimeMetrics`] in the
+//! [`RuntimeOptions`](crate::runtime::RuntimeOptions) to receive the hooks, or use
+//! [`CountingMetrics`] to keep counters which can be read with [`CountingMetrics::snapshot`].
+
+use crate::error::EnqueueError;
+use crate::gateway_topics::TopicType;
+use schemars::JsonSchema;
+use serde_derive::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Receives the activity of the runtime, every hook does nothing by default.
+///
+/// The hooks are called from the event loop and the callback tasks, so they should return quickly.
+pub trait RuntimeMetrics: Debug + Send + Sync {
+    /// Called for every MQTT message with a recognized topic received by the event loop.
+    fn message_received(&self, _topic_type: TopicType) {}
+
+    /// Called for every MQTT message whose topic is not recognized.
+    fn unknown_topic_received(&self) {}
+
+    /// Called if the payload of a message cannot be decoded with the configured marshaler.
+    fn decode_failed(&self, _topic_type: TopicType) {}
+
+    /// Called after a callback finished processing a message, with the time since the message
+    /// was dispatched to it.
+    fn callback_completed(&self, _topic_type: TopicType, _latency: Duration) {}
+
+    /// Called if a downlink cannot be handed to the MQTT client.
+    fn publish_failed(&self, _error: &EnqueueError) {}
+}
+
+/// Metrics hooks shared by the runtime, the event loop and the callback tasks.
+pub type SharedRuntimeMetrics = Arc<dyn RuntimeMetrics>;
+
+/// Ignores all hooks, used if no metrics are configured.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoopMetrics;
+
+impl RuntimeMetrics for NoopMetrics {}
+
+/// Counted callback runs of a topic type and their latency.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Serialize, JsonSchema)]
+pub struct CallbackLatency {
+    /// Finished callback runs.
+    pub completed: u64,
+    /// Sum of the latencies in microseconds.
+    pub total_micros: u64,
+    /// Highest latency in microseconds.
+    pub max_micros: u64,
+}
+
+impl CallbackLatency {
+    /// Returns the mean latency in microseconds, 0 without finished callback runs.
+    #[must_use]
+    pub fn mean_micros(&self) -> u64 {
+        self.total_micros.checked_div(self.completed).unwrap_or(0)
+    }
+}
+
+/// Counters of a [`CountingMetrics`], topic types are keyed as `<type>/<sub type>`, e.g.
+/// `event/up`.
+#[derive(Debug, Default, Clone, Eq, PartialEq, Serialize, JsonSchema)]
+pub struct RuntimeMetricsSnapshot {
+    /// Received messages with a recognized topic by topic type.
+    pub messages_received: BTreeMap<String, u64>,
+    /// Received messages with an unrecognized topic.
+    pub unknown_topics: u64,
+    /// Messages whose payload could not be decoded by topic type.
+    pub decode_failures: BTreeMap<String, u64>,
+    /// Callback runs and their latency by topic type.
+    pub callback_latency: BTreeMap<String, CallbackLatency>,
+    /// Downlinks not handed to the MQTT client by [`EnqueueError::kind`].
+    pub publish_failures: BTreeMap<String, u64>,
+}
+
+/// Counts the activity of the runtime.
+#[derive(Debug, Default)]
+pub struct CountingMetrics {
+    /// Received messages with an unrecognized topic.
+    unknown_topics: AtomicU64,
+    /// Counters keyed by topic type or error kind.
+    counters: Mutex<RuntimeMetricsSnapshot>,
+}
+
+impl CountingMetrics {
+    /// Creates a new [`CountingMetrics`] without counted activity.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the current counters.
+    #[must_use]
+    pub fn snapshot(&self) -> RuntimeMetricsSnapshot {
+        let mut snapshot = self.lock().clone();
+        snapshot.unknown_topics = self.unknown_topics.load(Ordering::Relaxed);
+        snapshot
+    }
+
+    /// Locks the counters, a poisoned lock is recovered as the counters stay consistent.
+    fn lock(&self) -> std::sync::MutexGuard<'_, RuntimeMetricsSnapshot> {
+        self.counters
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+impl RuntimeMetrics for CountingMetrics {
+    fn message_received(&self, topic_type: TopicType) {
+        increment(&mut self.lock().messages_received, topic_key(topic_type));
+    }
+
+    fn unknown_topic_received(&self) {
+        self.unknown_topics.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn decode_failed(&self, topic_type: TopicType) {
+        increment(&mut self.lock().decode_failures, topic_key(topic_type));
+    }
+
+    fn callback_completed(&self, topic_type: TopicType, latency: Duration) {
+        let micros = u64::try_from(latency.as_micros()).unwrap_or(u64::MAX);
+        let mut counters = self.lock();
+        let callback_latency = counters
+            .callback_latency
+            .entry(topic_key(topic_type))
+            .or_default();
+        callback_latency.completed = callback_latency.completed.saturating_add(1);
+        callback_latency.total_micros = callback_latency.total_micros.saturating_add(micros);
+        callback_latency.max_micros = callback_latency.max_micros.max(micros);
+    }
+
+    fn publish_failed(&self, error: &EnqueueError) {
+        increment(&mut self.lock().publish_failures, error.kind().to_owned());
+    }
+}
+
+/// Returns the key of the topic type in the [`RuntimeMetricsSnapshot`].
+fn topic_key(topic_type: TopicType) -> String {
+    let (topic_type, topic_sub_type) = topic_type.as_strs();
+    format!("{topic_type}/{topic_sub_type}")
+}
+
+/// Increments the counter of the key.
+fn increment(counters: &mut BTreeMap<String, u64>, key: String) {
+    let counter = counters.entry(key).or_insert(0);
+    *counter = counter.saturating_add(1);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::EnqueueError;
+    use crate::gateway_topics::{EventType, StateType, TopicType};
+    use crate::runtime::metrics::{CountingMetrics, RuntimeMetrics};
+    use std::time::Duration;
+
+    #[test]
+    fn test_counting_metrics() {
+        let metrics = CountingMetrics::new();
+        metrics.message_received(TopicType::Event(EventType::Up));
+        metrics.message_received(TopicType::Event(EventType::Up));
+        metrics.message_received(TopicType::State(StateType::Conn));
+        metrics.unknown_topic_received();
+        metrics.decode_failed(TopicType::Event(EventType::Up));
+        metrics.callback_completed(TopicType::Event(EventType::Up), Duration::from_micros(100));
+        metrics.callback_completed(TopicType::Event(EventType::Up), Duration::from_micros(300));
+        metrics.publish_failed(&EnqueueError::NotConnected);
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.messages_received.get("event/up"), Some(&2));
+        assert_eq!(snapshot.messages_received.get("state/conn"), Some(&1));
+        assert_eq!(snapshot.unknown_topics, 1);
+        assert_eq!(snapshot.decode_failures.get("event/up"), Some(&1));
+        let latency = snapshot.callback_latency["event/up"];
+        assert_eq!(latency.completed, 2);
+        assert_eq!(latency.mean_micros(), 200);
+        assert_eq!(latency.max_micros, 300);
+        assert_eq!(snapshot.publish_failures.get("not_connected"), Some(&1));
+    }
+}
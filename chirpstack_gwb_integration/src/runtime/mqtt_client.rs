@@ -0,0 +1,389 @@
+//! MQTT client and event loop of the runtime, connected with MQTT v3.1.1 or MQTT v5.
+//!
+//! With MQTT v5 the runtime can
+//! - request a session expiry, so the broker keeps the subscription during short reconnects.
+//! - replace the topics of repeated publishes with topic aliases, the first publish to a topic
+//!   carries the topic and its alias, the following ones only the alias. Aliases are assigned per
+//!   connection up to the maximum of the broker.
+//! - attach user properties to every publish, e.g. the node ID for broker-side filtering.
+
+use crate::error::{EnqueueError, MqttConnectionError, RuntimeError};
+use prost::bytes::Bytes;
+use rumqttc::v5::mqttbytes::v5::{Packet, PublishProperties};
+use rumqttc::{Event, EventLoop, Incoming, MqttOptions, QoS};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+/// Capacity of the request channel of the MQTT client.
+const REQUEST_CHANNEL_CAPACITY: usize = 10;
+
+/// MQTT v5 features used by the runtime.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct MqttV5Options {
+    /// Time the broker keeps the session after a disconnect, the session ends with the connection
+    /// if `None`.
+    pub session_expiry_interval: Option<Duration>,
+    /// Max amount of topic aliases per connection, limited by the maximum of the broker. Topic
+    /// aliases are not used if 0.
+    pub max_topic_aliases: u16,
+    /// User properties attached to every publish as key and value.
+    pub user_properties: Vec<(String, String)>,
+}
+
+/// Topic alias of a topic.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct TopicAlias {
+    /// The alias.
+    alias: u16,
+    /// Whether a publish with the topic and the alias was handed to the MQTT client, later
+    /// publishes only carry the alias.
+    established: bool,
+}
+
+/// Topic aliases of the current connection.
+#[derive(Debug, Default)]
+pub(crate) struct TopicAliases {
+    /// Configured max amount of topic aliases.
+    configured_max: u16,
+    /// Max amount of topic aliases of the current connection, 0 until connected.
+    max: u16,
+    /// Aliases by topic.
+    aliases: HashMap<String, TopicAlias>,
+}
+
+impl TopicAliases {
+    /// Creates new [`TopicAliases`] using at most `configured_max` aliases per connection.
+    pub(crate) fn new(configured_max: u16) -> Self {
+        Self {
+            configured_max,
+            ..Self::default()
+        }
+    }
+
+    /// Forgets all aliases on a new connection, limited by the maximum of the broker, which
+    /// does not accept aliases if not set.
+    pub(crate) fn reset(&mut self, broker_max: Option<u16>) {
+        self.aliases.clear();
+        self.max = self.configured_max.min(broker_max.unwrap_or(0));
+    }
+
+    /// Returns the topic and the alias to publish with. The topic is empty once the alias is
+    /// established, no alias is returned if all aliases are assigned.
+    pub(crate) fn resolve(&mut self, topic: &str) -> (String, Option<u16>) {
+        if let Some(topic_alias) = self.aliases.get(topic) {
+            let topic = if topic_alias.established {
+                String::new()
+            } else {
+                topic.to_owned()
+            };
+            return (topic, Some(topic_alias.alias));
+        }
+        let Some(alias) = u16::try_from(self.aliases.len())
+            .ok()
+            .and_then(|assigned| assigned.checked_add(1))
+            .filter(|alias| *alias <= self.max)
+        else {
+            return (topic.to_owned(), None);
+        };
+        self.aliases.insert(
+            topic.to_owned(),
+            TopicAlias {
+                alias,
+                established: false,
+            },
+        );
+        (topic.to_owned(), Some(alias))
+    }
+
+    /// Marks the alias as established after a publish with the topic and the alias was handed to
+    /// the MQTT client.
+    pub(crate) fn establish(&mut self, topic: &str, alias: u16) {
+        if let Some(topic_alias) = self.aliases.get_mut(topic) {
+            if topic_alias.alias == alias {
+                topic_alias.established = true;
+            }
+        }
+    }
+}
+
+/// Topic aliases shared by the MQTT client and the event loop, which resets them on reconnects.
+pub(crate) type SharedTopicAliases = Arc<Mutex<TopicAliases>>;
+
+/// Locks the topic aliases, a poisoned lock is recovered as the aliases stay consistent.
+fn lock(topic_aliases: &SharedTopicAliases) -> MutexGuard<'_, TopicAliases> {
+    topic_aliases.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// MQTT client of the runtime.
+#[derive(Debug, Clone)]
+pub(crate) enum MqttClient {
+    /// Client connected with MQTT v3.1.1.
+    V4(rumqttc::AsyncClient),
+    /// Client connected with MQTT v5.
+    V5 {
+        /// The client.
+        client: rumqttc::v5::AsyncClient,
+        /// User properties attached to every publish.
+        user_properties: Vec<(String, String)>,
+        /// Topic aliases of the current connection.
+        topic_aliases: SharedTopicAliases,
+    },
+}
+
+/// Event loop of the MQTT client of the runtime.
+pub(crate) enum MqttEventLoop {
+    /// Event loop connected with MQTT v3.1.1.
+    V4(EventLoop),
+    /// Event loop connected with MQTT v5.
+    V5 {
+        /// The event loop.
+        event_loop: rumqttc::v5::EventLoop,
+        /// Topic aliases of the current connection, reset on every connection.
+        topic_aliases: SharedTopicAliases,
+    },
+}
+
+/// Event of the MQTT event loop relevant to the runtime.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) enum MqttEvent {
+    /// The broker acknowledged the connection.
+    Connected,
+    /// A message was received.
+    Publish {
+        /// Topic of the message.
+        topic: String,
+        /// Payload of the message.
+        payload: Bytes,
+    },
+    /// Any other event.
+    Other,
+}
+
+/// Creates the MQTT client and its event loop, connecting with MQTT v5 if `mqtt_v5` is set.
+///
+/// The MQTT v5 client takes the client ID, the broker address, the keep alive and the
+/// credentials of the MQTT options.
+pub(crate) fn connect(
+    mqtt_options: MqttOptions,
+    mqtt_v5: Option<&MqttV5Options>,
+) -> (MqttClient, MqttEventLoop) {
+    let Some(mqtt_v5) = mqtt_v5 else {
+        let (client, event_loop) =
+            rumqttc::AsyncClient::new(mqtt_options, REQUEST_CHANNEL_CAPACITY);
+        return (MqttClient::V4(client), MqttEventLoop::V4(event_loop));
+    };
+    let (host, port) = mqtt_options.broker_address();
+    let mut v5_options = rumqttc::v5::MqttOptions::new(mqtt_options.client_id(), host, port);
+    v5_options.set_keep_alive(mqtt_options.keep_alive());
+    if let Some((username, password)) = mqtt_options.credentials() {
+        v5_options.set_credentials(username, password);
+    }
+    if let Some(session_expiry_interval) = mqtt_v5.session_expiry_interval {
+        v5_options.set_clean_start(false);
+        v5_options.set_session_expiry_interval(Some(
+            u32::try_from(session_expiry_interval.as_secs()).unwrap_or(u32::MAX),
+        ));
+    }
+    let (client, event_loop) = rumqttc::v5::AsyncClient::new(v5_options, REQUEST_CHANNEL_CAPACITY);
+    let topic_aliases = Arc::new(Mutex::new(TopicAliases::new(mqtt_v5.max_topic_aliases)));
+    (
+        MqttClient::V5 {
+            client,
+            user_properties: mqtt_v5.user_properties.clone(),
+            topic_aliases: topic_aliases.clone(),
+        },
+        MqttEventLoop::V5 {
+            event_loop,
+            topic_aliases,
+        },
+    )
+}
+
+impl MqttClient {
+    /// Subscribes to the topic with QoS 1.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the event loop stopped.
+    pub(crate) async fn subscribe(&self, topic: String) -> Result<(), RuntimeError> {
+        match self {
+            MqttClient::V4(client) => client.subscribe(topic, QoS::AtLeastOnce).await?,
+            MqttClient::V5 { client, .. } => {
+                client
+                    .subscribe(topic, rumqttc::v5::mqttbytes::QoS::AtLeastOnce)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Publishes the message with QoS 0, waits while the request queue is full.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the event loop stopped.
+    pub(crate) async fn publish(&self, topic: &str, message: Vec<u8>) -> Result<(), EnqueueError> {
+        match self {
+            MqttClient::V4(client) => Ok(client
+                .publish(topic, QoS::AtMostOnce, false, message)
+                .await?),
+            MqttClient::V5 {
+                client,
+                user_properties,
+                topic_aliases,
+            } => {
+                let (alias_topic, properties) =
+                    publish_properties(topic, user_properties, topic_aliases);
+                let alias = properties.topic_alias;
+                client
+                    .publish_with_properties(
+                        alias_topic,
+                        rumqttc::v5::mqttbytes::QoS::AtMostOnce,
+                        false,
+                        message,
+                        properties,
+                    )
+                    .await?;
+                establish(topic, alias, topic_aliases);
+                Ok(())
+            }
+        }
+    }
+
+    /// Publishes the message with QoS 0 without waiting.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request queue is full or the event loop stopped.
+    pub(crate) fn try_publish(&self, topic: &str, message: Vec<u8>) -> Result<(), EnqueueError> {
+        match self {
+            MqttClient::V4(client) => {
+                Ok(client.try_publish(topic, QoS::AtMostOnce, false, message)?)
+            }
+            MqttClient::V5 {
+                client,
+                user_properties,
+                topic_aliases,
+            } => {
+                let (alias_topic, properties) =
+                    publish_properties(topic, user_properties, topic_aliases);
+                let alias = properties.topic_alias;
+                client.try_publish_with_properties(
+                    alias_topic,
+                    rumqttc::v5::mqttbytes::QoS::AtMostOnce,
+                    false,
+                    message,
+                    properties,
+                )?;
+                establish(topic, alias, topic_aliases);
+                Ok(())
+            }
+        }
+    }
+
+    /// Sends a disconnect to the broker without waiting.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request queue is full or the event loop stopped.
+    pub(crate) fn try_disconnect(&self) -> Result<(), RuntimeError> {
+        match self {
+            MqttClient::V4(client) => client.try_disconnect()?,
+            MqttClient::V5 { client, .. } => client.try_disconnect()?,
+        }
+        Ok(())
+    }
+}
+
+/// Returns the topic to publish to and the properties with the user properties and the topic
+/// alias.
+fn publish_properties(
+    topic: &str,
+    user_properties: &[(String, String)],
+    topic_aliases: &SharedTopicAliases,
+) -> (String, PublishProperties) {
+    let (alias_topic, topic_alias) = lock(topic_aliases).resolve(topic);
+    (
+        alias_topic,
+        PublishProperties {
+            topic_alias,
+            user_properties: user_properties.to_vec(),
+            ..PublishProperties::default()
+        },
+    )
+}
+
+/// Establishes the alias of the topic after the publish was handed to the MQTT client.
+fn establish(topic: &str, alias: Option<u16>, topic_aliases: &SharedTopicAliases) {
+    if let Some(alias) = alias {
+        lock(topic_aliases).establish(topic, alias);
+    }
+}
+
+impl MqttEventLoop {
+    /// Polls the next event, the event loop reconnects on its own after an error.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the connection to the broker failed.
+    pub(crate) async fn poll(&mut self) -> Result<MqttEvent, MqttConnectionError> {
+        match self {
+            MqttEventLoop::V4(event_loop) => Ok(match event_loop.poll().await? {
+                Event::Incoming(Incoming::ConnAck(_)) => MqttEvent::Connected,
+                Event::Incoming(Incoming::Publish(publish)) => MqttEvent::Publish {
+                    topic: publish.topic,
+                    payload: publish.payload,
+                },
+                _ => MqttEvent::Other,
+            }),
+            MqttEventLoop::V5 {
+                event_loop,
+                topic_aliases,
+            } => Ok(match event_loop.poll().await? {
+                rumqttc::v5::Event::Incoming(Packet::ConnAck(conn_ack)) => {
+                    let broker_max = conn_ack
+                        .properties
+                        .as_ref()
+                        .and_then(|properties| properties.topic_alias_max);
+                    lock(topic_aliases).reset(broker_max);
+                    MqttEvent::Connected
+                }
+                rumqttc::v5::Event::Incoming(Packet::Publish(publish)) => MqttEvent::Publish {
+                    topic: String::from_utf8_lossy(&publish.topic).into_owned(),
+                    payload: publish.payload,
+                },
+                _ => MqttEvent::Other,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::runtime::mqtt_client::TopicAliases;
+
+    #[test]
+    fn test_topic_aliases() {
+        let mut topic_aliases = TopicAliases::new(2);
+        // No aliases until the broker accepted them.
+        assert_eq!(topic_aliases.resolve("a"), ("a".to_owned(), None));
+
+        topic_aliases.reset(Some(10));
+        assert_eq!(topic_aliases.resolve("a"), ("a".to_owned(), Some(1)));
+        // The topic is sent until the alias was handed to the MQTT client.
+        assert_eq!(topic_aliases.resolve("a"), ("a".to_owned(), Some(1)));
+        topic_aliases.establish("a", 1);
+        assert_eq!(topic_aliases.resolve("a"), (String::new(), Some(1)));
+        assert_eq!(topic_aliases.resolve("b"), ("b".to_owned(), Some(2)));
+        // The configured maximum is reached.
+        assert_eq!(topic_aliases.resolve("c"), ("c".to_owned(), None));
+
+        // Aliases are assigned anew on every connection.
+        topic_aliases.reset(Some(1));
+        assert_eq!(topic_aliases.resolve("b"), ("b".to_owned(), Some(1)));
+        assert_eq!(topic_aliases.resolve("a"), ("a".to_owned(), None));
+        topic_aliases.reset(None);
+        assert_eq!(topic_aliases.resolve("a"), ("a".to_owned(), None));
+    }
+}
@@ -0,0 +1,322 @@
+//! Runtime connected to several MQTT brokers at once, e.g. of redundant gateway bridges.
+//!
+//! Every broker is connected by its own [`Runtime`] identified by a broker ID. Callbacks are added
+//! at all brokers and built per broker with its ID, so a callback knows which broker a message was
+//! received from. Downlinks are enqueued at a single broker or at all of them, see
+//! [`BrokerTarget`].
+
+use crate::downlinks::{Downlink, DownlinkType};
+use crate::error::{CallbackRemoveError, EnqueueError, RuntimeError};
+use crate::gateway_id::GatewayId;
+use crate::runtime::callbacks::CallbackKind;
+use crate::runtime::Runtime;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tracing::{trace, warn};
+use uuid::Uuid;
+
+/// Brokers a downlink is enqueued at.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum BrokerTarget {
+    /// The broker with the contained ID.
+    Broker(String),
+    /// Every broker, e.g. for a gateway connected to either of redundant brokers.
+    All,
+}
+
+/// Type to interact with the runtimes of several MQTT brokers.
+///
+/// Add and remove callbacks at all brokers or send downlinks via one or all brokers, the
+/// [`Runtime`] of a single broker is available with [`MultiBrokerRuntime::runtime`].
+/// Don't drop the runtime as it stops the event loops.
+#[derive(Debug, Clone)]
+pub struct MultiBrokerRuntime {
+    /// Runtimes with their broker IDs, in the order they were supplied.
+    brokers: Vec<(String, Runtime)>,
+    /// IDs of the callbacks added at the single brokers per ID returned by
+    /// [`MultiBrokerRuntime::add_callback`].
+    callbacks: Arc<RwLock<HashMap<Uuid, Vec<(String, Uuid)>>>>,
+}
+
+impl MultiBrokerRuntime {
+    /// Create a new runtime from the runtimes of the single brokers with their broker IDs.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a broker ID is used twice.
+    pub fn new(brokers: Vec<(String, Runtime)>) -> Result<Self, RuntimeError> {
+        for (index, (broker_id, _)) in brokers.iter().enumerate() {
+            if brokers[..index].iter().any(|(other, _)| other == broker_id) {
+                return Err(RuntimeError::DuplicateBroker {
+                    broker_id: broker_id.clone(),
+                });
+            }
+        }
+        Ok(Self {
+            brokers,
+            callbacks: Arc::new(RwLock::new(HashMap::new())),
+        })
+    }
+
+    /// Returns the IDs of the brokers, in the order they were supplied.
+    #[must_use]
+    pub fn broker_ids(&self) -> Vec<&str> {
+        self.brokers
+            .iter()
+            .map(|(broker_id, _)| broker_id.as_str())
+            .collect()
+    }
+
+    /// Returns the runtime of the broker, e.g. to add a callback at this broker only.
+    #[must_use]
+    pub fn runtime(&self, broker_id: &str) -> Option<&Runtime> {
+        self.brokers
+            .iter()
+            .find(|(id, _)| id == broker_id)
+            .map(|(_, runtime)| runtime)
+    }
+
+    /// Returns the IDs of the brokers the event loops are connected to.
+    #[must_use]
+    pub fn connected_brokers(&self) -> Vec<&str> {
+        self.brokers
+            .iter()
+            .filter(|(_, runtime)| runtime.broker_connected())
+            .map(|(broker_id, _)| broker_id.as_str())
+            .collect()
+    }
+
+    /// Add a callback for the message type of the callback at every broker, see
+    /// [`Runtime::add_callback`]. The callback of a broker is built with `build_callback` from the
+    /// ID of the broker, e.g. to tag the received messages with it.
+    ///
+    /// Returns one ID removing the callbacks of all brokers with
+    /// [`MultiBrokerRuntime::remove_callback`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a runtime is stopped or a generated ID is already in use, the
+    /// callbacks already added at other brokers are removed again.
+    #[tracing::instrument(skip(self, build_callback))]
+    pub async fn add_callback<K, F>(
+        &mut self,
+        gateway_id: Option<GatewayId>,
+        mut build_callback: F,
+    ) -> Result<Uuid, RuntimeError>
+    where
+        K: CallbackKind + ?Sized,
+        F: FnMut(&str) -> Box<K>,
+    {
+        let mut added = Vec::with_capacity(self.brokers.len());
+        let mut failure = None;
+        for (broker_id, runtime) in &mut self.brokers {
+            match runtime
+                .add_callback(gateway_id.clone(), build_callback(broker_id.as_str()))
+                .await
+            {
+                Ok(uuid) => added.push((broker_id.clone(), uuid)),
+                Err(err) => {
+                    failure = Some(err);
+                    break;
+                }
+            }
+        }
+        if let Some(err) = failure {
+            self.remove_added_callbacks(&added).await;
+            return Err(err);
+        }
+        let uuid = Uuid::new_v4();
+        let mut callbacks = self.callbacks.write().await;
+        if callbacks.contains_key(&uuid) {
+            drop(callbacks);
+            self.remove_added_callbacks(&added).await;
+            return Err(RuntimeError::UuidCollision);
+        }
+        callbacks.insert(uuid, added);
+        Ok(uuid)
+    }
+
+    /// Remove the callbacks of all brokers added with the supplied ID.
+    ///
+    /// Every broker is tried, the callbacks which could not be removed are kept under the ID, so
+    /// the removal can be retried.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no callbacks were added with the ID. Returns the error of the last
+    /// broker if the callback of any broker could not be removed, e.g. as its runtime is stopped.
+    #[tracing::instrument(skip(self))]
+    pub async fn remove_callback(&self, uuid: Uuid) -> Result<(), RuntimeError> {
+        let mut callbacks = self.callbacks.write().await;
+        let Some(added) = callbacks.get_mut(&uuid) else {
+            return Err(CallbackRemoveError::NoSuchCallback { uuid }.into());
+        };
+        let mut remaining = Vec::new();
+        let mut failure = None;
+        for (broker_id, broker_uuid) in added.drain(..) {
+            if let Some(runtime) = self.runtime(&broker_id) {
+                if let Err(err) = runtime.remove_callback(broker_uuid).await {
+                    warn!(%broker_id, "Failed to remove callback: {err}");
+                    remaining.push((broker_id, broker_uuid));
+                    failure = Some(err);
+                }
+            }
+        }
+        if let Some(err) = failure {
+            *added = remaining;
+            return Err(err);
+        }
+        callbacks.remove(&uuid);
+        Ok(())
+    }
+
+    /// Removes callbacks of a failed [`MultiBrokerRuntime::add_callback`] again.
+    async fn remove_added_callbacks(&self, added: &[(String, Uuid)]) {
+        for (broker_id, uuid) in added {
+            if let Some(runtime) = self.runtime(broker_id) {
+                if let Err(err) = runtime.remove_callback(*uuid).await {
+                    warn!(%broker_id, "Failed to remove callback: {err}");
+                }
+            }
+        }
+    }
+
+    /// Enqueues a downlink to be sent from the specified gateway via the target brokers, waits
+    /// while the MQTT request queue of a broker is full, see [`Runtime::enqueue`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`EnqueueError::NoSuchBroker`] if the target broker is unknown. With
+    /// [`BrokerTarget::All`], returns the error of the last broker if no broker accepted the
+    /// downlink.
+    #[tracing::instrument(skip_all)]
+    pub async fn enqueue<Dt>(
+        &self,
+        target: &BrokerTarget,
+        sender_gateway: &GatewayId,
+        downlink: Downlink<Dt>,
+    ) -> Result<(), EnqueueError>
+    where
+        chirpstack_api::gw::DownlinkFrame: From<Downlink<Dt>>,
+        Dt: DownlinkType,
+    {
+        let mut results = Vec::new();
+        for (broker_id, runtime) in self.target_runtimes(target)? {
+            let result = runtime.enqueue(sender_gateway, downlink.clone()).await;
+            results.push((broker_id, result));
+        }
+        any_accepted(results)
+    }
+
+    /// Enqueues a downlink to be sent from the specified gateway via the target brokers without
+    /// waiting, see [`Runtime::try_enqueue`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`EnqueueError::NoSuchBroker`] if the target broker is unknown. With
+    /// [`BrokerTarget::All`], returns the error of the last broker if no broker accepted the
+    /// downlink.
+    #[tracing::instrument(skip_all)]
+    pub fn try_enqueue<Dt>(
+        &self,
+        target: &BrokerTarget,
+        sender_gateway: &GatewayId,
+        downlink: Downlink<Dt>,
+    ) -> Result<(), EnqueueError>
+    where
+        chirpstack_api::gw::DownlinkFrame: From<Downlink<Dt>>,
+        Dt: DownlinkType,
+    {
+        let results = self
+            .target_runtimes(target)?
+            .into_iter()
+            .map(|(broker_id, runtime)| {
+                (
+                    broker_id,
+                    runtime.try_enqueue(sender_gateway, downlink.clone()),
+                )
+            })
+            .collect();
+        any_accepted(results)
+    }
+
+    /// Returns the runtimes of the target brokers with their broker IDs.
+    fn target_runtimes(
+        &self,
+        target: &BrokerTarget,
+    ) -> Result<Vec<(&str, &Runtime)>, EnqueueError> {
+        let runtimes: Vec<(&str, &Runtime)> = self
+            .brokers
+            .iter()
+            .filter(|(broker_id, _)| match target {
+                BrokerTarget::Broker(target_id) => broker_id == target_id,
+                BrokerTarget::All => true,
+            })
+            .map(|(broker_id, runtime)| (broker_id.as_str(), runtime))
+            .collect();
+        match target {
+            BrokerTarget::Broker(broker_id) if runtimes.is_empty() => {
+                Err(EnqueueError::NoSuchBroker {
+                    broker_id: broker_id.clone(),
+                })
+            }
+            _ => Ok(runtimes),
+        }
+    }
+
+    /// Stop the runtimes of all brokers, see [`Runtime::stop_event_loop`].
+    pub fn stop_event_loops(&mut self) {
+        for (broker_id, runtime) in &mut self.brokers {
+            trace!(%broker_id, "Stopping runtime");
+            runtime.stop_event_loop();
+        }
+    }
+}
+
+/// Returns success if any broker accepted the downlink, the error of the last broker otherwise,
+/// [`EnqueueError::NotConnected`] without brokers.
+fn any_accepted(results: Vec<(&str, Result<(), EnqueueError>)>) -> Result<(), EnqueueError> {
+    let mut last_error = EnqueueError::NotConnected;
+    let mut accepted = false;
+    for (broker_id, result) in results {
+        match result {
+            Ok(()) => accepted = true,
+            Err(err) => {
+                warn!(%broker_id, "Failed to enqueue downlink: {err}");
+                last_error = err;
+            }
+        }
+    }
+    if accepted {
+        Ok(())
+    } else {
+        Err(last_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::EnqueueError;
+    use crate::runtime::multi_broker::any_accepted;
+
+    #[test]
+    fn test_any_accepted() {
+        assert!(any_accepted(vec![
+            ("primary", Err(EnqueueError::NotConnected)),
+            ("secondary", Ok(())),
+        ])
+        .is_ok());
+        assert!(matches!(
+            any_accepted(vec![
+                ("primary", Err(EnqueueError::NotConnected)),
+                ("secondary", Err(EnqueueError::BrokerBackPressure)),
+            ]),
+            Err(EnqueueError::BrokerBackPressure)
+        ));
+        assert!(matches!(
+            any_accepted(Vec::new()),
+            Err(EnqueueError::NotConnected)
+        ));
+    }
+}
@@ -0,0 +1,122 @@
+//! Extraction of the radio metadata of received uplinks.
+
+use crate::downlinks::predefined_parameters::DataRate;
+use crate::error::UplinkInfoError;
+use chirpstack_api::gw::{modulation, UplinkFrame};
+use std::time::{Duration, SystemTime};
+
+/// Radio metadata of an uplink, extracted from an [`UplinkFrame`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct UplinkInfo {
+    /// Frequency in Hz.
+    pub frequency: u32,
+    /// Data rate derived from bandwidth and spreading factor.
+    pub data_rate: DataRate,
+    /// RSSI in dBm.
+    pub rssi: i32,
+    /// LoRa SNR in dB.
+    pub snr: f32,
+    /// Gateway specific context, required to answer the uplink with a class A downlink.
+    pub context: Vec<u8>,
+    /// Time the gateway received the uplink, if the gateway reported it.
+    pub gateway_time: Option<SystemTime>,
+    /// Time since the GPS epoch the gateway received the uplink, only reported by gateways with
+    /// a GPS module.
+    pub time_since_gps_epoch: Option<Duration>,
+}
+
+impl TryFrom<&UplinkFrame> for UplinkInfo {
+    type Error = UplinkInfoError;
+
+    fn try_from(uplink: &UplinkFrame) -> Result<Self, Self::Error> {
+        let tx_info = uplink.tx_info.as_ref().ok_or(UplinkInfoError::NoTxInfo)?;
+        let rx_info = uplink.rx_info.as_ref().ok_or(UplinkInfoError::NoRxInfo)?;
+        let modulation = tx_info
+            .modulation
+            .as_ref()
+            .ok_or(UplinkInfoError::NoModulationInfo)?;
+        let Some(modulation::Parameters::Lora(lora_modulation_info)) = &modulation.parameters
+        else {
+            return Err(UplinkInfoError::NoLoRaParameters);
+        };
+        let data_rate = DataRate::from_raw_bandwidth_and_spreading_factor(
+            lora_modulation_info.bandwidth,
+            lora_modulation_info.spreading_factor,
+        )?;
+
+        Ok(Self {
+            frequency: tx_info.frequency,
+            data_rate,
+            rssi: rx_info.rssi,
+            snr: rx_info.snr,
+            context: rx_info.context.clone(),
+            gateway_time: rx_info
+                .gw_time
+                .clone()
+                .and_then(|gw_time| SystemTime::try_from(gw_time).ok()),
+            time_since_gps_epoch: rx_info
+                .time_since_gps_epoch
+                .clone()
+                .and_then(|time_since_gps_epoch| Duration::try_from(time_since_gps_epoch).ok()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::downlinks::predefined_parameters::DataRate;
+    use crate::error::UplinkInfoError;
+    use crate::uplinks::UplinkInfo;
+    use chirpstack_api::gw::{
+        modulation, LoraModulationInfo, Modulation, UplinkFrame, UplinkRxInfo, UplinkTxInfo,
+    };
+    use std::time::Duration;
+
+    #[test]
+    fn test_uplink_info_from_uplink_frame() {
+        let mut uplink = UplinkFrame {
+            tx_info: Some(UplinkTxInfo {
+                frequency: 868_100_000,
+                modulation: Some(Modulation {
+                    parameters: Some(modulation::Parameters::Lora(LoraModulationInfo {
+                        bandwidth: 125_000,
+                        spreading_factor: 9,
+                        ..LoraModulationInfo::default()
+                    })),
+                }),
+            }),
+            rx_info: Some(UplinkRxInfo {
+                rssi: -110,
+                snr: 7.5,
+                context: vec![1, 2, 3, 4],
+                time_since_gps_epoch: Duration::from_secs(5).try_into().ok(),
+                ..UplinkRxInfo::default()
+            }),
+            ..UplinkFrame::default()
+        };
+
+        assert_eq!(
+            UplinkInfo::try_from(&uplink),
+            Ok(UplinkInfo {
+                frequency: 868_100_000,
+                data_rate: DataRate::Eu863_870Dr3,
+                rssi: -110,
+                snr: 7.5,
+                context: vec![1, 2, 3, 4],
+                gateway_time: None,
+                time_since_gps_epoch: Some(Duration::from_secs(5)),
+            })
+        );
+
+        uplink.rx_info = None;
+        assert_eq!(
+            UplinkInfo::try_from(&uplink),
+            Err(UplinkInfoError::NoRxInfo)
+        );
+        uplink.tx_info = None;
+        assert_eq!(
+            UplinkInfo::try_from(&uplink),
+            Err(UplinkInfoError::NoTxInfo)
+        );
+    }
+}
@@ -1,3 +1,5 @@
+mod monitor;
+
 use std::fs::File;
 use std::io::Read;
 use std::process;
@@ -11,6 +13,8 @@ use chirpstack_gwb_integration::downlinks::downlink_item_builder::DownlinkItemBu
 use chirpstack_gwb_integration::downlinks::predefined_parameters::{
     Bandwidth, DataRate, Frequency, SpreadingFactor,
 };
+use chirpstack_gwb_integration::gateway_id::GatewayId;
+use chirpstack_gwb_integration::logging::{init_logging, LoggingConfig};
 use chirpstack_gwb_integration::runtime::callbacks::EventUpCallback;
 use chirpstack_gwb_integration::runtime::Runtime;
 
@@ -20,8 +24,6 @@ use rand::Rng;
 use rumqttc::MqttOptions;
 use serde_derive::Deserialize;
 use tracing::error;
-use tracing_subscriber::layer::SubscriberExt;
-use tracing_subscriber::util::SubscriberInitExt;
 
 #[derive(Deserialize, Clone)]
 struct Config {
@@ -31,6 +33,7 @@ struct Config {
     chirpstack_port: Option<u16>,
     mqtt_url: Option<String>,
     mqtt_port: Option<u16>,
+    logging: Option<LoggingConfig>,
 }
 
 #[derive(Parser, Debug)]
@@ -112,10 +115,31 @@ enum Subcommands {
         #[clap(long, value_parser)]
         prefix: Option<u8>,
 
-        /// Add a network id to the downlink (intended for testing the daemon impl)
+        /// Add a network id to the downlink (intended for testing the daemon impl), sets the
+        /// network id flag 0b0000_0100 of the prefix byte, which defaults to 224
         #[clap(long, value_parser)]
         network_id: Option<u32>,
     },
+
+    /// Monitors the uplinks of all gateways and prints frames/min per gateway, spreading factor
+    /// and frequency and the share of proprietary frames
+    Monitor {
+        /// Refresh interval of the summary in seconds
+        #[clap(short, long, value_parser, default_value_t = 10)]
+        interval: u64,
+
+        /// Append the summary of every interval to this CSV file
+        #[clap(long, value_parser)]
+        csv: Option<String>,
+    },
+
+    /// Prints the frame logs of a gateway streamed via the ChirpStack gRPC API, without access to
+    /// the MQTT broker
+    Frames {
+        /// Gateway ID, defaults to the first gateway returned by the API
+        #[clap(short, long, value_parser)]
+        gateway_id: Option<String>,
+    },
 }
 
 #[tokio::main]
@@ -184,16 +208,51 @@ async fn listening(_verbose: &bool, config: Config, prefix: &Option<u8>) {
     }
 }
 
+#[tokio::main]
+async fn frames(config: Config, gateway_id: &Option<String>) {
+    let chirpstack_api = ChirpStackApi {
+        url: config.chirpstack_url.unwrap(),
+        port: config.chirpstack_port.unwrap(),
+        api_token: config.api_token.unwrap(),
+        tenant_id: config.tenant_id,
+    };
+
+    let gateway_id = match gateway_id {
+        Some(gateway_id) => gateway_id.parse().unwrap(),
+        None => chirpstack_api
+            .request_gateway_ids(100)
+            .await
+            .unwrap()
+            .into_iter()
+            .next()
+            .unwrap(),
+    };
+
+    let mut frames = chirpstack_api
+        .stream_gateway_frames(&gateway_id)
+        .await
+        .unwrap();
+    while let Some(log_item) = frames.message().await.unwrap() {
+        println!(
+            "{}: {} {} | {}",
+            Utc::now().timestamp(),
+            gateway_id,
+            log_item.description,
+            log_item.body
+        );
+    }
+}
+
 #[derive(Debug)]
 pub struct UplinkCallback {
-    sender: tokio::sync::mpsc::Sender<(String, chirpstack_api::gw::UplinkFrame)>,
+    sender: tokio::sync::mpsc::Sender<(GatewayId, chirpstack_api::gw::UplinkFrame)>,
 }
 
 #[async_trait]
 impl EventUpCallback for UplinkCallback {
     async fn dispatch_up_event(
         &self,
-        gateway_id: String,
+        gateway_id: GatewayId,
         up_event: chirpstack_api::gw::UplinkFrame,
     ) {
         self.sender.send((gateway_id, up_event)).await.unwrap()
@@ -283,12 +342,15 @@ async fn downlink(
     let mut pl_bytes = Vec::<u8>::new();
     //= payload.clone();
 
-    if prefix.is_some() {
+    if network_id.is_some() {
+        // The daemon expects the network id after the MHDR flagged by an RFU bit
+        pl_bytes.push(prefix.unwrap_or(0b1110_0000) | 0b0000_0100);
+    } else if prefix.is_some() {
         pl_bytes.push(prefix.unwrap());
     }
 
     if network_id.is_some() {
-        let network_id_bytes = network_id.unwrap().to_be_bytes();
+        let network_id_bytes = network_id.unwrap().to_le_bytes();
         for network_id_byte in network_id_bytes {
             pl_bytes.push(network_id_byte)
         }
@@ -340,17 +402,23 @@ async fn downlink(
 }
 
 fn main() {
-    tracing_subscriber::registry()
-        .with(tracing_subscriber::EnvFilter::new(
-            std::env::var("RUST_LOG").unwrap_or_else(|_| {
-                "chi_bri_add_on_cli=trace,chirpstack_gwb_integration=trace".into()
-            }),
-        ))
-        .with(tracing_subscriber::fmt::layer())
-        .init();
-
     let cli = Cli::parse();
 
+    let logging_config = cli
+        .config_file
+        .as_ref()
+        .and_then(|c| std::fs::read_to_string(c).ok())
+        .and_then(|value| toml::from_str::<Config>(&value).ok())
+        .and_then(|config_file| config_file.logging)
+        .unwrap_or_default();
+    if let Err(err) = init_logging(
+        &logging_config,
+        "chi_bri_add_on_cli=trace,chirpstack_gwb_integration=trace",
+    ) {
+        eprintln!("Error setting up logging: {err}");
+        process::exit(6);
+    }
+
     let mut config = Config {
         api_token: cli.api_token,
         tenant_id: cli.tenant_id,
@@ -358,6 +426,7 @@ fn main() {
         chirpstack_port: cli.chirpstack_port,
         mqtt_url: cli.mqtt_url,
         mqtt_port: cli.mqtt_port,
+        logging: None,
     };
 
     if let Some(c) = cli.config_file {
@@ -452,6 +521,17 @@ fn main() {
                 network_id,
             );
         }
+        Some(Subcommands::Monitor { interval, csv }) => {
+            println!(
+                "'monitor' with interval = {:?}\n\t csv = {:?}",
+                interval, csv
+            );
+            monitor::monitor(config, *interval, csv);
+        }
+        Some(Subcommands::Frames { gateway_id }) => {
+            println!("'frames' with gateway_id = {:?}", gateway_id);
+            frames(config, gateway_id);
+        }
         _ => {
             println!("Please specify a subcommand!")
         }
@@ -0,0 +1,257 @@
+//! Continuous traffic monitor summarizing the uplinks of all gateways per interval.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::time::Duration;
+
+use chirpstack_api::gw::{modulation, UplinkFrame};
+use chirpstack_gwb_integration::gateway_id::GatewayId;
+use chirpstack_gwb_integration::runtime::gateway_time::GatewayTime;
+use chirpstack_gwb_integration::runtime::Runtime;
+use chrono::{DateTime, Utc};
+use rumqttc::MqttOptions;
+
+use crate::{Config, UplinkCallback};
+
+/// MHDR major type bits of proprietary LoRaWAN frames.
+const PROPRIETARY_MTYPE: u8 = 0b111;
+
+/// Uplinks counted within one interval.
+#[derive(Debug, Default)]
+struct TrafficSummary {
+    /// Counted uplinks.
+    frames: u64,
+    /// Uplinks with a proprietary MHDR.
+    proprietary: u64,
+    /// Uplinks by gateway ID.
+    per_gateway: BTreeMap<String, u64>,
+    /// Uplinks by spreading factor, 0 for non-LoRa modulations.
+    per_spreading_factor: BTreeMap<u32, u64>,
+    /// Uplinks by frequency in Hz.
+    per_frequency: BTreeMap<u32, u64>,
+}
+
+impl TrafficSummary {
+    /// Counts an uplink received by the gateway.
+    fn record(&mut self, gateway_id: &str, uplink: &UplinkFrame) {
+        self.frames += 1;
+        if uplink
+            .phy_payload
+            .first()
+            .is_some_and(|mhdr| mhdr >> 5 == PROPRIETARY_MTYPE)
+        {
+            self.proprietary += 1;
+        }
+        *self.per_gateway.entry(gateway_id.to_owned()).or_default() += 1;
+        let tx_info = uplink.tx_info.as_ref();
+        let spreading_factor = tx_info
+            .and_then(|tx_info| tx_info.modulation.as_ref())
+            .and_then(|modulation| modulation.parameters.as_ref())
+            .map_or(0, |parameters| match parameters {
+                modulation::Parameters::Lora(lora) => lora.spreading_factor,
+                _ => 0,
+            });
+        *self
+            .per_spreading_factor
+            .entry(spreading_factor)
+            .or_default() += 1;
+        let frequency = tx_info.map_or(0, |tx_info| tx_info.frequency);
+        *self.per_frequency.entry(frequency).or_default() += 1;
+    }
+
+    /// Returns the rows of the summary: dimension, key and counted uplinks.
+    fn rows(&self) -> Vec<(&'static str, String, u64)> {
+        let mut rows = vec![
+            ("type", "lorawan".to_owned(), self.frames - self.proprietary),
+            ("type", "proprietary".to_owned(), self.proprietary),
+        ];
+        rows.extend(
+            self.per_gateway
+                .iter()
+                .map(|(gateway_id, frames)| ("gateway", gateway_id.clone(), *frames)),
+        );
+        rows.extend(
+            self.per_spreading_factor
+                .iter()
+                .map(|(spreading_factor, frames)| ("sf", spreading_factor.to_string(), *frames)),
+        );
+        rows.extend(
+            self.per_frequency
+                .iter()
+                .map(|(frequency, frames)| ("frequency", frequency.to_string(), *frames)),
+        );
+        rows
+    }
+}
+
+/// Returns the uplinks per minute of a count within the interval.
+fn per_minute(frames: u64, interval: Duration) -> f64 {
+    frames as f64 * 60.0 / interval.as_secs_f64()
+}
+
+/// Returns the share of a count in percent.
+fn share(frames: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        frames as f64 * 100.0 / total as f64
+    }
+}
+
+/// Clears the terminal and prints the summary of the last interval next to the totals, followed
+/// by the clock offsets of the gateways.
+fn render(
+    interval_summary: &TrafficSummary,
+    total_summary: &TrafficSummary,
+    gateway_times: &HashMap<GatewayId, GatewayTime>,
+    interval: Duration,
+    started_at: DateTime<Utc>,
+) {
+    print!("\x1B[2J\x1B[H");
+    println!(
+        "Traffic monitor since {} | last {}s: {} frames ({:.1}/min) | total: {} frames",
+        started_at.format("%Y-%m-%d %H:%M:%S"),
+        interval.as_secs(),
+        interval_summary.frames,
+        per_minute(interval_summary.frames, interval),
+        total_summary.frames
+    );
+    let interval_rows = interval_summary.rows();
+    let mut dimension = "";
+    for (row_dimension, key, total) in &total_summary.rows() {
+        if *row_dimension != dimension {
+            dimension = row_dimension;
+            println!();
+            println!(
+                "{:<12} {:<20} {:>10} {:>8} {:>10} {:>8}",
+                dimension, "", "frames/min", "share", "total", "share"
+            );
+        }
+        let frames = interval_rows
+            .iter()
+            .find(|(interval_dimension, interval_key, _)| {
+                interval_dimension == row_dimension && interval_key == key
+            })
+            .map_or(0, |(_, _, frames)| *frames);
+        println!(
+            "{:<12} {:<20} {:>10.1} {:>7.1}% {:>10} {:>7.1}%",
+            "",
+            key,
+            per_minute(frames, interval),
+            share(frames, interval_summary.frames),
+            total,
+            share(*total, total_summary.frames)
+        );
+    }
+
+    let clock_offsets: BTreeMap<_, _> = gateway_times
+        .iter()
+        .filter_map(|(gateway_id, gateway_time)| {
+            Some((gateway_id, gateway_time.clock_offset()?.as_signed_millis()))
+        })
+        .collect();
+    if !clock_offsets.is_empty() {
+        println!();
+        println!("{:<12} {:<20} {:>10}", "clock", "", "offset ms");
+        for (gateway_id, offset_millis) in clock_offsets {
+            println!("{:<12} {:<20} {:>10}", "", gateway_id, offset_millis);
+        }
+    }
+}
+
+/// Appends the summary of an interval to the CSV file, one row per dimension and key.
+fn write_csv(
+    writer: &mut BufWriter<File>,
+    summary: &TrafficSummary,
+    interval: Duration,
+    at: DateTime<Utc>,
+) -> std::io::Result<()> {
+    for (dimension, key, frames) in summary.rows() {
+        writeln!(
+            writer,
+            "{},{},{},{},{:.2},{:.2}",
+            at.to_rfc3339(),
+            dimension,
+            key,
+            frames,
+            per_minute(frames, interval),
+            share(frames, summary.frames)
+        )?;
+    }
+    writer.flush()
+}
+
+/// Opens the CSV file for appending, writing the header if the file is new or empty.
+fn open_csv(path: &str) -> std::io::Result<BufWriter<File>> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let empty = file.metadata()?.len() == 0;
+    let mut writer = BufWriter::new(file);
+    if empty {
+        writeln!(
+            writer,
+            "timestamp,dimension,key,frames,frames_per_minute,share_percent"
+        )?;
+    }
+    Ok(writer)
+}
+
+/// Subscribes to the uplinks of all gateways and renders a summary every interval until
+/// interrupted, optionally appending every summary to a CSV file.
+#[tokio::main]
+pub async fn monitor(config: Config, interval_seconds: u64, csv: &Option<String>) {
+    let mqtt_options = MqttOptions::new(
+        "chi_bri_add_on_cli_monitor",
+        config.mqtt_url.unwrap(),
+        config.mqtt_port.unwrap(),
+    );
+    let mut runtime = Runtime::new_with_mqtt_options(mqtt_options, None)
+        .await
+        .unwrap();
+    let (sender, mut receiver) = tokio::sync::mpsc::channel(100);
+    runtime
+        .add_event_up_callback(None, Box::new(UplinkCallback { sender }))
+        .await
+        .unwrap();
+
+    let mut csv_writer = csv.as_ref().map(|path| open_csv(path).unwrap());
+    let interval = Duration::from_secs(interval_seconds.max(1));
+    let mut ticker = tokio::time::interval(interval);
+    // The first tick completes immediately.
+    ticker.tick().await;
+    let started_at = Utc::now();
+    let mut interval_summary = TrafficSummary::default();
+    let mut total_summary = TrafficSummary::default();
+    render(
+        &interval_summary,
+        &total_summary,
+        &HashMap::new(),
+        interval,
+        started_at,
+    );
+    loop {
+        tokio::select! {
+            uplink = receiver.recv() => {
+                let Some((gateway_id, uplink)) = uplink else {
+                    break;
+                };
+                interval_summary.record(&gateway_id, &uplink);
+                total_summary.record(&gateway_id, &uplink);
+            }
+            _ = ticker.tick() => {
+                let gateway_times = runtime.gateway_times().await;
+                render(&interval_summary, &total_summary, &gateway_times, interval, started_at);
+                if let Some(csv_writer) = &mut csv_writer {
+                    if let Err(err) = write_csv(csv_writer, &interval_summary, interval, Utc::now()) {
+                        println!("Error writing CSV: {err}");
+                    }
+                }
+                interval_summary = TrafficSummary::default();
+            }
+            _ = tokio::signal::ctrl_c() => {
+                break;
+            }
+        }
+    }
+    runtime.stop_event_loop();
+}
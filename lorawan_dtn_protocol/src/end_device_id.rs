@@ -0,0 +1,32 @@
+//! End device ID.
+
+use crate::error::TryFromEndDeviceId;
+use bp7::EndpointID;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// End device ID used to identify network participants.
+#[derive(Debug, Clone, Eq, PartialEq, Copy, Hash, Serialize, Deserialize, JsonSchema)]
+pub struct EndDeviceId(pub u32);
+
+impl TryFrom<EndpointID> for EndDeviceId {
+    type Error = TryFromEndDeviceId;
+
+    fn try_from(endpoint_id: EndpointID) -> Result<Self, Self::Error> {
+        if let EndpointID::Dtn(_, address) = endpoint_id {
+            let inner = u32::from_str(address.node_name())?;
+            Ok(EndDeviceId(inner))
+        } else {
+            Err(TryFromEndDeviceId::NoDtnAddress)
+        }
+    }
+}
+
+impl TryFrom<EndDeviceId> for EndpointID {
+    type Error = bp7::eid::EndpointIdError;
+
+    fn try_from(end_device_id: EndDeviceId) -> Result<Self, Self::Error> {
+        EndpointID::with_dtn(&end_device_id.0.to_string())
+    }
+}
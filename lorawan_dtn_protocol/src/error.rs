@@ -0,0 +1,208 @@
+//! All errors used in the LoRaWAN DTN protocol.
+
+use nom::error::{FromExternalError, ParseError};
+use nom::ErrorConvert;
+use std::num::ParseIntError;
+use thiserror::Error;
+
+/// Errors returned by the protocol parser.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ProtocolParserError {
+    /// Payload has no proprietary tag.
+    #[error("Payload has no proprietary tag")]
+    NoProprietaryTag,
+    /// Payload has wrong version tag.
+    #[error("Payload has wrong version tag")]
+    WrongVersionTag,
+    /// Payload has unknown packet type.
+    #[error("Payload has unknown packet type")]
+    UnknownPacketType,
+    /// Nom error.
+    #[error("Nom encountered an error: {0:?}")]
+    Nom(nom::error::ErrorKind),
+    /// Did not receive three bytes, cannot convert to u32
+    #[error("Did not receive three bytes, cannot convert to u32")]
+    NotThreeBytes,
+    /// Failed to create naive datetime from timestamp.
+    #[error("Failed to create naive datetime from timestamp")]
+    FromTimestampError,
+    /// BP7 bundle is no valid CBOR or its source or destination is no end device ID.
+    #[error("BP7 bundle is no valid CBOR or its source or destination is no end device ID")]
+    InvalidBp7Bundle,
+}
+
+/// Errors occurring when creating a complete bundle packet.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum CompleteBundleCreationError {
+    /// The payload is too large and cannot fit into one packet.
+    #[error("The payload is too large and cannot fit into one packet")]
+    PayloadTooLarge,
+}
+
+/// Errors occurring when creating a bundle fragment packet.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum BundleFragmentCreationError {
+    /// The provided payload cannot fill the packet payload completely, this is forbidden for all
+    /// but end packets.
+    #[error(
+        "The provided payload cannot fill the packet payload completely, this is forbidden for all
+        but end packets"
+    )]
+    PayloadNotFilledCompletely,
+    /// Payload is empty.
+    #[error("Payload is empty")]
+    PayloadEmpty,
+}
+
+/// Errors occurring when creating a BP7 bundle packet.
+#[derive(Error, Debug)]
+pub enum Bp7BundleCreationError {
+    /// Endpoint ID error from bp7.
+    #[error("Endpoint ID error from bp7: {0}")]
+    EndpointId(#[from] bp7::eid::EndpointIdError),
+    /// Primary builder error from bp7.
+    #[error("Primary builder error from bp7: {0}")]
+    PrimaryBuilder(#[from] bp7::primary::PrimaryBuilderError),
+}
+
+/// Errors occurring when encoding a location.
+#[allow(clippy::enum_variant_names)]
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum LocationEncodingError {
+    /// Could not encode value, was out of range (-90.00 to 90.00).
+    #[error("Could not encode value, was out of range (-90.00 to 90.00)")]
+    LatOutOfRange,
+    /// Could not encode value, was out of range (-180.00 to 180.00)
+    #[error("Could not encode value, was out of range (-180.00 to 180.00)")]
+    LongOutOfRange,
+    /// Could not encode value, was out of range (-83886.00 to 83886.00)
+    #[error("Could not encode value, was out of range (-83886.00 to 83886.00)")]
+    AltOutOfRange,
+}
+
+/// Errors occurring when trying to convert a [`EndDeviceId`](crate::EndDeviceId) into a [`bp7::EndpointID`].
+#[derive(Error, Debug)]
+pub enum TryFromEndDeviceId {
+    /// Not a Dtn address, only Dtn addressing is supported.
+    #[error("Not a Dtn address, only Dtn addressing is supported")]
+    NoDtnAddress,
+    /// Error parsing int.
+    #[error("Error parsing int: {0}")]
+    ParseInt(#[from] ParseIntError),
+}
+
+/// Errors occurring when processing a packet.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum BundleReceiveBufferProcessError {
+    /// Packets destination does not match receive buffers destination.
+    #[error("Packets destination does not match receive buffers destination")]
+    DstDoesNotMatch,
+    /// Packets source does not match receive buffers source.
+    #[error("Packets source does not match receive buffers source")]
+    SrcDoesNotMatch,
+    /// Packets timestamp does not match receive buffers timestamp.
+    #[error("Packets timestamp does not match receive buffers timestamp")]
+    TimestampDoesNotMatch,
+    /// Packets fragment offset hash does not match receive buffers fragment offset hash.
+    #[error("Packets fragment offset hash does not match receive buffers fragment offset hash")]
+    FragmentOffsetHashDoesNotMatch,
+    /// A packet with this index but another payload has already been received.
+    #[error("A packet with this index has already been received")]
+    IndexAlreadyReceived,
+    /// The same packet has already been received, e.g. via another relay.
+    #[error("The packet has already been received")]
+    DuplicateFragment,
+    /// A packet with an end index has already been received.
+    #[error("A packet with an end index has already been received")]
+    EndIndexAlreadyReceived,
+    /// A packet with an index after the end index has been received.
+    #[error("A packet with an index after the end index has been received")]
+    IndexBeyondEnd,
+    /// Fragmented bundle fragment end packet has no TADUL.
+    #[error("Fragmented bundle fragment end packet has no TADUL")]
+    NoTadul,
+    /// Fragmented bundle fragment end packet has no fragment offset.
+    #[error("Fragmented bundle fragment end packet has no fragment offset")]
+    NoFragmentOffset,
+    /// BP7 fragment extends beyond the total application data unit length.
+    #[error("BP7 fragment extends beyond the total application data unit length")]
+    OffsetBeyondTotalLength,
+}
+
+/// Errors occurring when combining the fragments in a [`BundleReceiveBuffer`](crate::reassembly::BundleReceiveBuffer).
+#[derive(Error, Debug)]
+pub enum BundleReceiveBufferCombineError {
+    /// No packet indicating the end has been received.
+    #[error("No packet indicating the end has been received")]
+    EndNotReceived,
+    /// Not all fragments have been received.
+    #[error("Not all fragments have been received")]
+    FragmentsMissing,
+    /// Endpoint ID error from bp7.
+    #[error("Endpoint ID error from bp7: {0}")]
+    EndpointId(#[from] bp7::eid::EndpointIdError),
+    /// Primary builder error from bp7.
+    #[error("Primary builder error from bp7: {0}")]
+    PrimaryBuilder(#[from] bp7::primary::PrimaryBuilderError),
+}
+
+/// Errors occurring when creating a [`Hop2HopReceiveBuffer`](crate::reassembly::Hop2HopReceiveBuffer).
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum Hop2HopReceiveBufferCreationError {
+    /// Fragment index is larger than total amount of fragments
+    #[error("Fragment index is larger than total amount of fragments")]
+    IndexLargerThanTotal,
+}
+
+/// Errors occurring when processing a Hop2Hop packet fragment.
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum Hop2HopReceiveBufferProcessPacketError {
+    /// The fragments packet hash does not match.
+    #[error("The fragments packet hash does not match")]
+    HashMismatch,
+    /// The fragments packet total fragment amount does not match.
+    #[error("The fragments packet total fragment amount does not match")]
+    TotalFragmentsMismatch,
+    /// Fragment index is larger than total amount of fragments.
+    #[error("Fragment index is larger than total amount of fragments")]
+    IndexLargerThanTotal,
+    /// A packet with this index has already been received.
+    #[error("A packet with this index has already been received")]
+    IndexAlreadyReceived,
+}
+
+/// Errors occurring when combining the fragments in a [`Hop2HopReceiveBuffer`](crate::reassembly::Hop2HopReceiveBuffer).
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum Hop2HopReceiveBufferCombineError {
+    /// Not all fragment received.
+    #[error("Not all fragments received")]
+    FragmentsMissing,
+    /// Protocol parser error.
+    #[error("Protocol parser error: {0}")]
+    ProtocolParser(#[from] ProtocolParserError),
+}
+
+impl ErrorConvert<ProtocolParserError> for ProtocolParserError {
+    fn convert(self) -> ProtocolParserError {
+        self
+    }
+}
+
+impl<I> ParseError<I> for ProtocolParserError {
+    fn from_error_kind(_: I, kind: nom::error::ErrorKind) -> Self {
+        ProtocolParserError::Nom(kind)
+    }
+
+    fn append(_: I, _: nom::error::ErrorKind, other: Self) -> Self {
+        other
+    }
+}
+
+impl FromExternalError<&[u8], ProtocolParserError> for ProtocolParserError {
+    fn from_external_error(_: &[u8], _: nom::error::ErrorKind, e: ProtocolParserError) -> Self {
+        e
+    }
+}
+
+/// Type alias for packet parsing.
+pub type IResult<I, O> = nom::IResult<I, O, ProtocolParserError>;
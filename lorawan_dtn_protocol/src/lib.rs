@@ -0,0 +1,2473 @@
+//! The custom LoRaWAN DTN protocol.
+//!
+//! A library implementing the wire format spoken by spatz nodes: the packet types including the
+//! bundle and Hop2Hop fragments, the parser and the [`reassembly`] buffers combining the
+//! fragments again. Shared by spatz, gateway simulators, CLI decoders and third-party nodes, so
+//! all of them speak the exact same protocol. Scheduling the fragments of a bundle, e.g. by
+//! priority, is left to the user.
+//!
+//! The wire format is pinned by the golden vectors in `tests/protocol_vectors`, changes to the
+//! public API or the wire format require a new minor version as long as the crate is below 1.0.
+
+#![deny(clippy::unwrap_used)]
+#![warn(missing_docs)]
+#![warn(clippy::missing_errors_doc)]
+#![warn(clippy::missing_panics_doc)]
+#![warn(clippy::missing_docs_in_private_items)]
+#![warn(clippy::pedantic)]
+#![allow(clippy::doc_markdown)]
+#![allow(clippy::module_name_repetitions)]
+
+pub mod end_device_id;
+pub mod error;
+mod location_encoding;
+mod parser;
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod protocol_vectors;
+pub mod reassembly;
+mod wireshark;
+
+pub use end_device_id::EndDeviceId;
+pub use location_encoding::{encode_alt, encode_lat, encode_long};
+pub use parser::{parse_packet, parse_phy_payload, parse_phy_payload_with_network_id};
+pub use wireshark::generate_wireshark_dissector;
+
+use crate::error::{
+    Bp7BundleCreationError, BundleFragmentCreationError, CompleteBundleCreationError,
+    LocationEncodingError, ProtocolParserError,
+};
+use crate::location_encoding::{decode_alt, decode_lat, decode_long};
+use bp7::flags::{BlockControlFlags, BundleControlFlags};
+use chrono::{DateTime, Utc};
+use lorawan_parameters::DataRate;
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::time::Duration;
+
+/// The overhead per packet: 4B Dst + 4B Src + 4B Timestamp
+pub static COMPLETE_BUNDLE_HEADERS_SIZE: usize = 4 + 4 + 4 + 1;
+/// The overhead per packet: 4B Dst + 4B Src + 4B Timestamp + 1B Fragment index
+pub static BUNDLE_FRAGMENT_HEADERS_SIZE: usize = 4 + 4 + 4 + 1;
+/// The overhead per packet: 4B Dst + 4B Src + 4B Timestamp + 1B Fragment index +
+/// 8B Bundle fragment offset + 8B TADUL
+///
+/// TADUL Total Application Data Unit Length
+pub static FRAGMENTED_BUNDLE_FRAGMENT_START_HEADERS_SIZE: usize = 4 + 4 + 4 + 1 + 8 + 8;
+/// The overhead per packet: 4B Dst + 4B Src + 4B Timestamp + 1B Fragment index + 4B Bundle fragment
+/// offset hash
+pub static FRAGMENTED_BUNDLE_FRAGMENT_HEADERS_SIZE: usize = 4 + 4 + 4 + 1 + 4;
+/// The overhead per packet: 4B Dst + 4B Src + 4B Timestamp + 1B Fragment index +
+/// 8B Bundle fragment offset + 8B TADUL
+pub static FRAGMENTED_BUNDLE_FRAGMENT_END_HEADERS_SIZE: usize = 4 + 4 + 4 + 1 + 8 + 8;
+/// The overhead per packet: 4B packet hash + 1 Fragment amount + 1B Fragment index
+pub static HOP_2_HOP_HEADERS_SIZE: usize = 4 + 1 + 1;
+/// The overhead per packet: 4B Src
+pub static LOCAL_ANNOUNCEMENT_NO_GPS_HEADERS_SIZE: usize = 4;
+/// The overhead per packet: 4B Src + 3B LAT + 3B LONG + 3B ALT
+pub static LOCAL_ANNOUNCEMENT_GPS_HEADERS_SIZE: usize = 4 + 3 + 3 + 3;
+
+/// Convert a unix timestamp to a [`bp7::DtnTime`].
+#[must_use]
+pub fn unix_ts_to_dtn_time(timestamp: u64) -> bp7::DtnTime {
+    (timestamp - bp7::dtntime::SECONDS1970_TO2K) * 1000
+}
+
+/// The LoRaWAN protocol proprietary payload tag.
+pub static LO_RA_WAN_PROPRIETARY_TAG: u8 = 0b1110_0000;
+
+/// Returns whether the phy payload starts with the proprietary MHDR of the supported protocol
+/// version, RFU bits are ignored. Used to tell own downlinks from regular LoRaWAN traffic.
+#[must_use]
+pub fn is_protocol_phy_payload(phy_payload: &[u8]) -> bool {
+    phy_payload
+        .first()
+        .is_some_and(|mhdr| mhdr & 0b1110_0011 == LO_RA_WAN_PROPRIETARY_TAG)
+}
+
+/// RFU bit of the MHDR signalling the 4B little endian network ID following the MHDR, used to
+/// separate logical networks sharing the gateways.
+pub const NETWORK_ID_FLAG: u8 = 0b0000_0100;
+
+/// Type alias for the ID of a logical network.
+pub type NetworkId = u32;
+
+/// Type alias for the bundle fragment offset hash.
+pub type BundleFragmentOffsetHash = u32;
+
+/// Type alias for the application defined tag of a service, e.g. a port.
+pub type ServiceTag = u16;
+
+/// Maximum amount of services announced per end device ID, an entry always fits into a packet at
+/// the lowest data rate.
+pub const MAX_SERVICES_PER_END_DEVICE: usize = 16;
+
+/// Maximum amount of frequencies announced in a channel plan, the announcement always fits into
+/// a packet at the lowest data rate.
+pub const MAX_ANNOUNCED_FREQUENCIES: usize = 14;
+
+/// Capability bit of nodes parsing [`Bp7Bundle`] packets and sending bundles in them if all
+/// direct neighbors do.
+pub const CAPABILITY_BP7_CBOR: u16 = 0b0000_0001;
+
+/// Status bit of reports about the reception of a bundle by its destination node.
+pub const STATUS_RECEIVED: u8 = 0b0000_0001;
+
+/// Status bit of reports about the delivery of a bundle to a local application.
+pub const STATUS_DELIVERED: u8 = 0b0000_0100;
+
+/// Status bit of reports about the deletion of a bundle without delivery.
+pub const STATUS_DELETED: u8 = 0b0000_1000;
+
+/// Receive data rates of nodes which did not announce theirs, DR0 to DR5 are supported by every
+/// gateway.
+pub const DEFAULT_RECEIVE_DATA_RATES: u8 = 0b0011_1111;
+
+/// Highest data rate index which can be announced, DR6.
+pub const MAX_DATA_RATE_INDEX: u8 = 6;
+
+/// Lifetime of the bundles created by this node.
+pub const BUNDLE_LIFETIME: Duration = Duration::from_hours(48);
+
+/// Maximum size of a phy payload, the MHDR and the largest usable payload at DR4 to DR6, used as
+/// capacity of payload buffers.
+pub const MAX_PHY_PAYLOAD_SIZE: usize = 1 + 250 + 4;
+
+/// All supported packet types of the custom LoRaWAN protocol.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[repr(u8)]
+pub enum PacketType {
+    /// Complete bundle.
+    CompleteBundle,
+    /// Fragment of a bundle.
+    BundleFragment,
+    /// End fragment of a bundle.
+    BundleFragmentEnd,
+    /// Fragment of a fragmented bundle.
+    FragmentedBundleFragment,
+    /// End fragment of a fragmented bundle.
+    FragmentedBundleFragmentEnd,
+    /// Hop 2 hop fragment.
+    Hop2HopFragment,
+    /// Local announcement.
+    LocalAnnouncement,
+    /// Announcement of end device IDs reachable through the sender.
+    ReachabilityAnnouncement,
+    // 8 is reserved and never assigned, packets of type 8 are rejected as unknown packet type,
+    // see the `invalid_unknown_packet_type` protocol vector.
+    /// Echo request for reachability diagnostics.
+    EchoRequest = 9,
+    /// Echo reply for reachability diagnostics.
+    EchoReply = 10,
+    /// Announcement of the services offered at end device IDs registered to the sender.
+    ServiceAnnouncement = 11,
+    /// Announcement of the channels the sender announces on and listens to.
+    ChannelPlanAnnouncement = 12,
+    /// Hop-level acknowledgement of a packet sent to a direct neighbor.
+    HopAck = 13,
+    /// BP7 bundle or bundle fragment encoded as CBOR.
+    Bp7Bundle = 14,
+    /// Announcement of the protocol capabilities of the sender.
+    CapabilityAnnouncement = 15,
+    /// Request of status reports for a bundle sent with the custom headers.
+    StatusReportRequest = 16,
+    /// Status report about a bundle, sent to the report-to end device ID.
+    StatusReport = 17,
+    /// Announcement of the data rates the sender prefers to receive with.
+    DataRateAnnouncement = 18,
+    /// Request of the retransmission of a bundle, sent to the source of the bundle.
+    BundleResendRequest = 19,
+}
+
+impl PacketType {
+    /// All packet types.
+    pub const ALL: [PacketType; 19] = [
+        PacketType::CompleteBundle,
+        PacketType::BundleFragment,
+        PacketType::BundleFragmentEnd,
+        PacketType::FragmentedBundleFragment,
+        PacketType::FragmentedBundleFragmentEnd,
+        PacketType::Hop2HopFragment,
+        PacketType::LocalAnnouncement,
+        PacketType::ReachabilityAnnouncement,
+        PacketType::EchoRequest,
+        PacketType::EchoReply,
+        PacketType::ServiceAnnouncement,
+        PacketType::ChannelPlanAnnouncement,
+        PacketType::HopAck,
+        PacketType::Bp7Bundle,
+        PacketType::CapabilityAnnouncement,
+        PacketType::StatusReportRequest,
+        PacketType::StatusReport,
+        PacketType::DataRateAnnouncement,
+        PacketType::BundleResendRequest,
+    ];
+
+    /// Returns the fields following the packet type byte in the order they are encoded.
+    // One table of all layouts, splitting it would scatter the wire format.
+    #[allow(clippy::too_many_lines)]
+    #[must_use]
+    pub fn layout(self) -> &'static [HeaderField] {
+        match self {
+            PacketType::CompleteBundle => &[
+                DESTINATION_FIELD,
+                SOURCE_FIELD,
+                TIMESTAMP_FIELD,
+                PAYLOAD_FIELD,
+            ],
+            PacketType::BundleFragment | PacketType::BundleFragmentEnd => &[
+                DESTINATION_FIELD,
+                SOURCE_FIELD,
+                TIMESTAMP_FIELD,
+                FRAGMENT_INDEX_FIELD,
+                PAYLOAD_FIELD,
+            ],
+            PacketType::FragmentedBundleFragment => &[
+                DESTINATION_FIELD,
+                SOURCE_FIELD,
+                TIMESTAMP_FIELD,
+                FRAGMENT_INDEX_FIELD,
+                HeaderField {
+                    name: "Bundle fragment offset hash",
+                    abbreviation: "bundle_fragment_offset_hash",
+                    kind: FieldKind::U32,
+                },
+                PAYLOAD_FIELD,
+            ],
+            PacketType::FragmentedBundleFragmentEnd => &[
+                DESTINATION_FIELD,
+                SOURCE_FIELD,
+                TIMESTAMP_FIELD,
+                FRAGMENT_INDEX_FIELD,
+                HeaderField {
+                    name: "Bundle fragment offset",
+                    abbreviation: "bundle_fragment_offset",
+                    kind: FieldKind::U64,
+                },
+                HeaderField {
+                    name: "Total application data unit length",
+                    abbreviation: "total_application_data_unit_length",
+                    kind: FieldKind::U64,
+                },
+                PAYLOAD_FIELD,
+            ],
+            PacketType::Hop2HopFragment => &[
+                HeaderField {
+                    name: "Packet hash",
+                    abbreviation: "packet_hash",
+                    kind: FieldKind::U32,
+                },
+                HeaderField {
+                    name: "Total fragments",
+                    abbreviation: "total_fragments",
+                    kind: FieldKind::U8,
+                },
+                FRAGMENT_INDEX_FIELD,
+                PAYLOAD_FIELD,
+            ],
+            PacketType::LocalAnnouncement => &[
+                HeaderField {
+                    name: "Location",
+                    abbreviation: "location",
+                    kind: FieldKind::OptionalLocation,
+                },
+                HeaderField {
+                    name: "End device ID",
+                    abbreviation: "end_device_id",
+                    kind: FieldKind::EndDeviceIds,
+                },
+            ],
+            PacketType::ReachabilityAnnouncement => &[
+                HeaderField {
+                    name: "Announcing end device ID",
+                    abbreviation: "announcer",
+                    kind: FieldKind::EndDeviceId,
+                },
+                HeaderField {
+                    name: "Reachable end device ID",
+                    abbreviation: "reachable",
+                    kind: FieldKind::ReachableEndDeviceIds,
+                },
+            ],
+            PacketType::EchoRequest => &[
+                DESTINATION_FIELD,
+                SOURCE_FIELD,
+                SEQUENCE_FIELD,
+                HOP_COUNT_FIELD,
+                HeaderField {
+                    name: "Hop limit",
+                    abbreviation: "hop_limit",
+                    kind: FieldKind::U8,
+                },
+            ],
+            PacketType::EchoReply => &[
+                DESTINATION_FIELD,
+                SOURCE_FIELD,
+                SEQUENCE_FIELD,
+                HOP_COUNT_FIELD,
+                HeaderField {
+                    name: "Hop limit reached",
+                    abbreviation: "hop_limit_reached",
+                    kind: FieldKind::U8,
+                },
+            ],
+            PacketType::ServiceAnnouncement => &[HeaderField {
+                name: "Service end device ID",
+                abbreviation: "services",
+                kind: FieldKind::EndDeviceServices,
+            }],
+            PacketType::ChannelPlanAnnouncement => &[
+                HeaderField {
+                    name: "Announcing end device ID",
+                    abbreviation: "announcer",
+                    kind: FieldKind::EndDeviceId,
+                },
+                HeaderField {
+                    name: "Frequency",
+                    abbreviation: "frequency",
+                    kind: FieldKind::Frequencies,
+                },
+            ],
+            PacketType::HopAck => &[
+                SOURCE_FIELD,
+                HeaderField {
+                    name: "Packet hash",
+                    abbreviation: "packet_hash",
+                    kind: FieldKind::U32,
+                },
+            ],
+            PacketType::Bp7Bundle => &[HeaderField {
+                name: "BP7 bundle",
+                abbreviation: "bp7_bundle",
+                kind: FieldKind::Payload,
+            }],
+            PacketType::CapabilityAnnouncement => &[
+                HeaderField {
+                    name: "Capabilities",
+                    abbreviation: "capabilities",
+                    kind: FieldKind::U16,
+                },
+                HeaderField {
+                    name: "End device ID",
+                    abbreviation: "end_device_id",
+                    kind: FieldKind::EndDeviceIds,
+                },
+            ],
+            PacketType::StatusReportRequest => &[
+                DESTINATION_FIELD,
+                SOURCE_FIELD,
+                TIMESTAMP_FIELD,
+                HeaderField {
+                    name: "Report-to",
+                    abbreviation: "report_to",
+                    kind: FieldKind::EndDeviceId,
+                },
+                HeaderField {
+                    name: "Requested reports",
+                    abbreviation: "requested_reports",
+                    kind: FieldKind::U8,
+                },
+            ],
+            PacketType::StatusReport => &[
+                DESTINATION_FIELD,
+                SOURCE_FIELD,
+                HeaderField {
+                    name: "Status",
+                    abbreviation: "status",
+                    kind: FieldKind::U8,
+                },
+                HeaderField {
+                    name: "Reason",
+                    abbreviation: "reason",
+                    kind: FieldKind::U8,
+                },
+                HeaderField {
+                    name: "Bundle source",
+                    abbreviation: "bundle_source",
+                    kind: FieldKind::EndDeviceId,
+                },
+                HeaderField {
+                    name: "Bundle timestamp",
+                    abbreviation: "bundle_timestamp",
+                    kind: FieldKind::Timestamp,
+                },
+            ],
+            PacketType::DataRateAnnouncement => &[
+                HeaderField {
+                    name: "Receive data rates",
+                    abbreviation: "receive_data_rates",
+                    kind: FieldKind::U8,
+                },
+                HeaderField {
+                    name: "End device ID",
+                    abbreviation: "end_device_id",
+                    kind: FieldKind::EndDeviceIds,
+                },
+            ],
+            PacketType::BundleResendRequest => &[DESTINATION_FIELD, SOURCE_FIELD, TIMESTAMP_FIELD],
+        }
+    }
+}
+
+/// Encoding of a [`HeaderField`], all multi-byte values are little endian.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum FieldKind {
+    /// Unsigned 8 bit value.
+    U8,
+    /// Unsigned 16 bit value.
+    U16,
+    /// Unsigned 32 bit value.
+    U32,
+    /// Unsigned 64 bit value.
+    U64,
+    /// Unix timestamp in seconds as unsigned 32 bit value.
+    Timestamp,
+    /// [`EndDeviceId`] as unsigned 32 bit value.
+    EndDeviceId,
+    /// [`GpsLocation`] as three signed 24 bit values, present if an odd amount of bytes remains.
+    OptionalLocation,
+    /// [`EndDeviceId`]s until the end of the packet.
+    EndDeviceIds,
+    /// [`ReachableEndDeviceId`]s of 9 bytes until the end of the packet.
+    ReachableEndDeviceIds,
+    /// [`EndDeviceServices`] as end device ID, amount of services and 16 bit service tags until
+    /// the end of the packet.
+    EndDeviceServices,
+    /// Frequencies in Hz as unsigned 32 bit values until the end of the packet.
+    Frequencies,
+    /// Remaining bytes of the packet.
+    Payload,
+}
+
+impl FieldKind {
+    /// Returns the size in bytes of fields with a fixed size.
+    #[must_use]
+    pub fn fixed_size(self) -> Option<usize> {
+        match self {
+            FieldKind::U8 => Some(1),
+            FieldKind::U16 => Some(2),
+            FieldKind::U32 | FieldKind::Timestamp | FieldKind::EndDeviceId => Some(4),
+            FieldKind::U64 => Some(8),
+            FieldKind::OptionalLocation
+            | FieldKind::EndDeviceIds
+            | FieldKind::ReachableEndDeviceIds
+            | FieldKind::EndDeviceServices
+            | FieldKind::Frequencies
+            | FieldKind::Payload => None,
+        }
+    }
+}
+
+/// Field of a packet header, describes the protocol for external tooling such as dissectors.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct HeaderField {
+    /// Human readable name.
+    pub name: &'static str,
+    /// Short identifier, unique among fields with different kinds.
+    pub abbreviation: &'static str,
+    /// Encoding of the field.
+    pub kind: FieldKind,
+}
+
+/// Destination header field.
+const DESTINATION_FIELD: HeaderField = HeaderField {
+    name: "Destination",
+    abbreviation: "destination",
+    kind: FieldKind::EndDeviceId,
+};
+/// Source header field.
+const SOURCE_FIELD: HeaderField = HeaderField {
+    name: "Source",
+    abbreviation: "source",
+    kind: FieldKind::EndDeviceId,
+};
+/// Timestamp header field.
+const TIMESTAMP_FIELD: HeaderField = HeaderField {
+    name: "Timestamp",
+    abbreviation: "timestamp",
+    kind: FieldKind::Timestamp,
+};
+/// Fragment index header field.
+const FRAGMENT_INDEX_FIELD: HeaderField = HeaderField {
+    name: "Fragment index",
+    abbreviation: "fragment_index",
+    kind: FieldKind::U8,
+};
+/// Echo sequence number header field.
+const SEQUENCE_FIELD: HeaderField = HeaderField {
+    name: "Sequence",
+    abbreviation: "sequence",
+    kind: FieldKind::U16,
+};
+/// Echo hop count header field.
+const HOP_COUNT_FIELD: HeaderField = HeaderField {
+    name: "Hop count",
+    abbreviation: "hop_count",
+    kind: FieldKind::U8,
+};
+/// Payload field.
+const PAYLOAD_FIELD: HeaderField = HeaderField {
+    name: "Payload",
+    abbreviation: "payload",
+    kind: FieldKind::Payload,
+};
+
+/// Trait of all LoRaWAN packets of the custom LoRaWAN protocol.
+#[typetag::serde(tag = "type")]
+pub trait LoRaWanPacket: Debug + Send + Sync {
+    /// Appends the bytes representation of the packet to the buffer, used to create the phy
+    /// payload for a LoRaWAN frame. Nothing is allocated if the buffer has enough capacity left,
+    /// e.g. [`MAX_PHY_PAYLOAD_SIZE`], so a cleared buffer can be reused for every packet.
+    fn write_phy_payload(&self, buffer: &mut Vec<u8>);
+
+    /// Creates the bytes representation of the packet. Used to create the phy payload for
+    /// a LoRaWAN frame.
+    #[must_use]
+    fn convert_to_lorawan_phy_payload(&self) -> Vec<u8> {
+        let mut result = Vec::with_capacity(MAX_PHY_PAYLOAD_SIZE);
+        self.write_phy_payload(&mut result);
+        result
+    }
+
+    /// Convert the packet to a vector of [`Hop2HopFragment`] with the provided data rate, sized
+    /// for the payload sizes allowed with a LoRaWAN repeater if `repeater_compatible` is set.
+    #[must_use]
+    fn convert_to_hop_2_hop_fragments(
+        &self,
+        data_rate: DataRate,
+        repeater_compatible: bool,
+    ) -> Vec<Hop2HopFragment> {
+        let payload = self.convert_to_lorawan_phy_payload();
+        let packet_hash = crc32fast::hash(&payload);
+        let bytes_per_packet =
+            data_rate.max_usable_payload_size(repeater_compatible) - HOP_2_HOP_HEADERS_SIZE;
+        let chunks = payload.chunks(bytes_per_packet);
+
+        // Amount of fragments is guaranteed to be less than u8::MAX since a payload can at most be
+        // 250 bytes.
+        #[allow(clippy::cast_possible_truncation)]
+        let total_fragments = chunks.len() as u8;
+
+        chunks
+            .enumerate()
+            .map(|(fragment_index, payload_slice)| {
+                // fragment_index is guaranteed ot be less than total_fragments.
+                #[allow(clippy::cast_possible_truncation)]
+                let fragment_index = fragment_index as u8;
+                Hop2HopFragment {
+                    packet_hash,
+                    total_fragments,
+                    fragment_index,
+                    payload: payload_slice.to_vec(),
+                }
+            })
+            .collect()
+    }
+
+    /// Returns the [`PacketType`] of the packet.
+    fn packet_type(&self) -> PacketType;
+
+    /// Returns the destination of the packet if present.
+    #[must_use]
+    fn packet_destination(&self) -> Option<EndDeviceId> {
+        None
+    }
+
+    /// Used to downcast trait objects.
+    fn as_any(&self) -> &dyn Any;
+
+    /// Used to downcast trait objects.
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+
+    /// Tries to downcast into a [`BundlePackets`] trait object.
+    #[must_use]
+    fn as_bundle_packet(&self) -> Option<&dyn BundlePackets> {
+        None
+    }
+
+    /// Tries to downcast into a mutable [`BundlePackets`] trait object.
+    fn as_bundle_packet_mut(&mut self) -> Option<&mut dyn BundlePackets> {
+        None
+    }
+}
+
+/// Trait of all bundle packets of the custom LoRaWAN protocol.
+pub trait BundlePackets: LoRaWanPacket {
+    /// Returns the destination.
+    fn destination(&self) -> EndDeviceId;
+    /// Returns the source.
+    fn source(&self) -> EndDeviceId;
+    /// Returns the timestamp.
+    fn timestamp(&self) -> DateTime<Utc>;
+    /// Returns whether the packet is an end packet.
+    fn is_end(&self) -> bool;
+    /// Returns the fragment index.
+    fn fragment_index(&self) -> u8;
+    /// Returns the payload.
+    fn payload(&self) -> Vec<u8>;
+    /// Returns the fragment offset hash if present.
+    ///
+    /// Only present in fragmented bundle fragments.
+    #[must_use]
+    fn bundle_fragment_offset_hash(&self) -> Option<BundleFragmentOffsetHash> {
+        None
+    }
+    /// Returnd the total application data unit length if present.
+    ///
+    /// Only present in fragmented bundle fragments.
+    #[must_use]
+    fn bundle_total_application_data_unit_length(&self) -> Option<u64> {
+        None
+    }
+    /// Returns the fragment offset is present.
+    ///
+    /// Only present in fragmented bundle fragments.
+    #[must_use]
+    fn bundle_fragment_offset(&self) -> Option<u64> {
+        None
+    }
+}
+
+/// Complete bundle packet type.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct CompleteBundle {
+    /// Destination.
+    destination: EndDeviceId,
+    /// Source.
+    source: EndDeviceId,
+    /// Timestamp.
+    timestamp: DateTime<Utc>,
+    /// Payload.
+    payload: Vec<u8>,
+}
+
+impl CompleteBundle {
+    /// Creates a new [`CompleteBundle`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the payload is too large for the provided data rate and repeater
+    /// compatibility.
+    pub fn new(
+        destination: EndDeviceId,
+        source: EndDeviceId,
+        timestamp: DateTime<Utc>,
+        payload: &mut Vec<u8>,
+        data_rate: DataRate,
+        repeater_compatible: bool,
+    ) -> Result<Self, CompleteBundleCreationError> {
+        if payload.len()
+            <= data_rate.max_usable_payload_size(repeater_compatible) - COMPLETE_BUNDLE_HEADERS_SIZE
+        {
+            Ok(Self {
+                destination,
+                source,
+                timestamp,
+                payload: std::mem::take(payload),
+            })
+        } else {
+            Err(CompleteBundleCreationError::PayloadTooLarge)
+        }
+    }
+}
+
+#[typetag::serde]
+impl LoRaWanPacket for CompleteBundle {
+    fn write_phy_payload(&self, buffer: &mut Vec<u8>) {
+        buffer.push(LO_RA_WAN_PROPRIETARY_TAG);
+        buffer.push(self.packet_type() as u8);
+        write_end_device_id(buffer, self.destination);
+        write_end_device_id(buffer, self.source);
+        write_timestamp(buffer, &self.timestamp);
+        buffer.extend_from_slice(&self.payload);
+    }
+
+    fn packet_type(&self) -> PacketType {
+        PacketType::CompleteBundle
+    }
+
+    fn packet_destination(&self) -> Option<EndDeviceId> {
+        Some(self.destination)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn as_bundle_packet(&self) -> Option<&dyn BundlePackets> {
+        Some(self)
+    }
+
+    fn as_bundle_packet_mut(&mut self) -> Option<&mut dyn BundlePackets> {
+        Some(self)
+    }
+}
+
+impl BundlePackets for CompleteBundle {
+    fn destination(&self) -> EndDeviceId {
+        self.destination
+    }
+
+    fn source(&self) -> EndDeviceId {
+        self.source
+    }
+
+    fn timestamp(&self) -> DateTime<Utc> {
+        self.timestamp
+    }
+
+    fn is_end(&self) -> bool {
+        true
+    }
+
+    /// A complete bundle is the only and therefore the first fragment.
+    fn fragment_index(&self) -> u8 {
+        0
+    }
+
+    fn payload(&self) -> Vec<u8> {
+        self.payload.clone()
+    }
+}
+
+/// Bundle fragment packet type.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct BundleFragment {
+    /// Destination.
+    destination: EndDeviceId,
+    /// Source.
+    source: EndDeviceId,
+    /// Timestamp.
+    timestamp: DateTime<Utc>,
+    /// Whether the fragment is an end fragment.
+    is_end: bool,
+    /// Fragment index.
+    fragment_index: u8,
+    /// Payload.
+    payload: Vec<u8>,
+}
+
+impl BundleFragment {
+    /// Creates a new [`BundleFragment`].
+    ///
+    /// The fragment can be of a bundle of a fragmented bundle.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if:
+    /// - the payload is empty.
+    /// - the provided payload does not fill the maximum usable payload size for the data rate. This
+    ///   is only allowed for end packets.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        destination: EndDeviceId,
+        source: EndDeviceId,
+        timestamp: DateTime<Utc>,
+        is_end: bool,
+        fragment_index: u8,
+        payload: &mut Vec<u8>,
+        data_rate: DataRate,
+        repeater_compatible: bool,
+    ) -> Result<Self, BundleFragmentCreationError> {
+        if payload.is_empty() {
+            return Err(BundleFragmentCreationError::PayloadEmpty);
+        }
+        let payload_size =
+            data_rate.max_usable_payload_size(repeater_compatible) - BUNDLE_FRAGMENT_HEADERS_SIZE;
+        if payload_size >= payload.len() && !is_end {
+            return Err(BundleFragmentCreationError::PayloadNotFilledCompletely);
+        }
+        let packet_payload: Vec<u8> = payload.drain(..payload_size.min(payload.len())).collect();
+        Ok(Self {
+            destination,
+            source,
+            timestamp,
+            is_end,
+            fragment_index,
+            payload: packet_payload,
+        })
+    }
+}
+
+#[typetag::serde]
+impl LoRaWanPacket for BundleFragment {
+    fn write_phy_payload(&self, buffer: &mut Vec<u8>) {
+        buffer.push(LO_RA_WAN_PROPRIETARY_TAG);
+        buffer.push(self.packet_type() as u8);
+        write_end_device_id(buffer, self.destination);
+        write_end_device_id(buffer, self.source);
+        write_timestamp(buffer, &self.timestamp);
+        buffer.push(self.fragment_index);
+        buffer.extend_from_slice(&self.payload);
+    }
+
+    fn packet_type(&self) -> PacketType {
+        if self.is_end {
+            PacketType::BundleFragmentEnd
+        } else {
+            PacketType::BundleFragment
+        }
+    }
+
+    fn packet_destination(&self) -> Option<EndDeviceId> {
+        Some(self.destination)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn as_bundle_packet(&self) -> Option<&dyn BundlePackets> {
+        Some(self)
+    }
+
+    fn as_bundle_packet_mut(&mut self) -> Option<&mut dyn BundlePackets> {
+        Some(self)
+    }
+}
+
+impl BundlePackets for BundleFragment {
+    fn destination(&self) -> EndDeviceId {
+        self.destination
+    }
+    fn source(&self) -> EndDeviceId {
+        self.source
+    }
+    fn timestamp(&self) -> DateTime<Utc> {
+        self.timestamp
+    }
+    fn is_end(&self) -> bool {
+        self.is_end
+    }
+    fn fragment_index(&self) -> u8 {
+        self.fragment_index
+    }
+    fn payload(&self) -> Vec<u8> {
+        self.payload.clone()
+    }
+}
+
+/// Fragmented bundle fragment packet type.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct FragmentedBundleFragment {
+    /// Destination.
+    destination: EndDeviceId,
+    /// Source.
+    source: EndDeviceId,
+    /// Timestamp.
+    timestamp: DateTime<Utc>,
+    /// Fragment index.
+    fragment_index: u8,
+    /// Bundle fragment offset hash.
+    bundle_fragment_offset_hash: BundleFragmentOffsetHash,
+    /// Payload.
+    payload: Vec<u8>,
+}
+
+impl FragmentedBundleFragment {
+    /// Creates a new [`FragmentedBundleFragment`] of the bundle fragment starting at the offset
+    /// of the payload of the original bundle.
+    #[must_use]
+    pub fn new(
+        destination: EndDeviceId,
+        source: EndDeviceId,
+        timestamp: DateTime<Utc>,
+        fragment_index: u8,
+        bundle_fragment_offset: u64,
+        payload: Vec<u8>,
+    ) -> Self {
+        Self {
+            destination,
+            source,
+            timestamp,
+            fragment_index,
+            bundle_fragment_offset_hash: crc32fast::hash(&bundle_fragment_offset.to_le_bytes()),
+            payload,
+        }
+    }
+}
+
+#[typetag::serde]
+impl LoRaWanPacket for FragmentedBundleFragment {
+    fn write_phy_payload(&self, buffer: &mut Vec<u8>) {
+        buffer.push(LO_RA_WAN_PROPRIETARY_TAG);
+        buffer.push(self.packet_type() as u8);
+        write_end_device_id(buffer, self.destination);
+        write_end_device_id(buffer, self.source);
+        write_timestamp(buffer, &self.timestamp);
+        buffer.push(self.fragment_index);
+        buffer.extend_from_slice(&self.bundle_fragment_offset_hash.to_le_bytes());
+        buffer.extend_from_slice(&self.payload);
+    }
+
+    fn packet_type(&self) -> PacketType {
+        PacketType::FragmentedBundleFragment
+    }
+
+    fn packet_destination(&self) -> Option<EndDeviceId> {
+        Some(self.destination)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn as_bundle_packet(&self) -> Option<&dyn BundlePackets> {
+        Some(self)
+    }
+
+    fn as_bundle_packet_mut(&mut self) -> Option<&mut dyn BundlePackets> {
+        Some(self)
+    }
+}
+
+impl BundlePackets for FragmentedBundleFragment {
+    fn destination(&self) -> EndDeviceId {
+        self.destination
+    }
+    fn source(&self) -> EndDeviceId {
+        self.source
+    }
+    fn timestamp(&self) -> DateTime<Utc> {
+        self.timestamp
+    }
+    fn is_end(&self) -> bool {
+        false
+    }
+    fn fragment_index(&self) -> u8 {
+        self.fragment_index
+    }
+    fn payload(&self) -> Vec<u8> {
+        self.payload.clone()
+    }
+    fn bundle_fragment_offset_hash(&self) -> Option<BundleFragmentOffsetHash> {
+        Some(self.bundle_fragment_offset_hash)
+    }
+}
+
+/// Fragmented bundle fragment end packet type.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct FragmentedBundleFragmentEnd {
+    /// Destination.
+    destination: EndDeviceId,
+    /// Source.
+    source: EndDeviceId,
+    /// Timestamp.
+    timestamp: DateTime<Utc>,
+    /// Fragment index.
+    fragment_index: u8,
+    /// Bundle fragment offset.
+    bundle_fragment_offset: u64,
+    /// Bundle total application data unit length.
+    bundle_total_application_data_unit_length: u64,
+    /// Payload.
+    payload: Vec<u8>,
+}
+
+impl FragmentedBundleFragmentEnd {
+    /// Creates a new [`FragmentedBundleFragmentEnd`] of the bundle fragment starting at the
+    /// offset of the payload of the original bundle with the total length.
+    #[must_use]
+    pub fn new(
+        destination: EndDeviceId,
+        source: EndDeviceId,
+        timestamp: DateTime<Utc>,
+        fragment_index: u8,
+        bundle_fragment_offset: u64,
+        bundle_total_application_data_unit_length: u64,
+        payload: Vec<u8>,
+    ) -> Self {
+        Self {
+            destination,
+            source,
+            timestamp,
+            fragment_index,
+            bundle_fragment_offset,
+            bundle_total_application_data_unit_length,
+            payload,
+        }
+    }
+}
+
+#[typetag::serde]
+impl LoRaWanPacket for FragmentedBundleFragmentEnd {
+    fn write_phy_payload(&self, buffer: &mut Vec<u8>) {
+        buffer.push(LO_RA_WAN_PROPRIETARY_TAG);
+        buffer.push(self.packet_type() as u8);
+        write_end_device_id(buffer, self.destination);
+        write_end_device_id(buffer, self.source);
+        write_timestamp(buffer, &self.timestamp);
+        buffer.push(self.fragment_index);
+        buffer.extend_from_slice(&self.bundle_fragment_offset.to_le_bytes());
+        buffer.extend_from_slice(&self.bundle_total_application_data_unit_length.to_le_bytes());
+        buffer.extend_from_slice(&self.payload);
+    }
+
+    fn packet_type(&self) -> PacketType {
+        PacketType::FragmentedBundleFragmentEnd
+    }
+
+    fn packet_destination(&self) -> Option<EndDeviceId> {
+        Some(self.destination)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn as_bundle_packet(&self) -> Option<&dyn BundlePackets> {
+        Some(self)
+    }
+
+    fn as_bundle_packet_mut(&mut self) -> Option<&mut dyn BundlePackets> {
+        Some(self)
+    }
+}
+
+impl BundlePackets for FragmentedBundleFragmentEnd {
+    fn destination(&self) -> EndDeviceId {
+        self.destination
+    }
+
+    fn source(&self) -> EndDeviceId {
+        self.source
+    }
+
+    fn timestamp(&self) -> DateTime<Utc> {
+        self.timestamp
+    }
+
+    fn is_end(&self) -> bool {
+        true
+    }
+
+    fn fragment_index(&self) -> u8 {
+        self.fragment_index
+    }
+
+    fn payload(&self) -> Vec<u8> {
+        self.payload.clone()
+    }
+
+    fn bundle_fragment_offset_hash(&self) -> Option<BundleFragmentOffsetHash> {
+        Some(crc32fast::hash(&self.bundle_fragment_offset.to_le_bytes()))
+    }
+
+    fn bundle_total_application_data_unit_length(&self) -> Option<u64> {
+        Some(self.bundle_total_application_data_unit_length)
+    }
+
+    fn bundle_fragment_offset(&self) -> Option<u64> {
+        Some(self.bundle_fragment_offset)
+    }
+}
+
+/// Hop 2 hop fragment packet type.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct Hop2HopFragment {
+    /// Hash of the split packet.
+    packet_hash: u32,
+    /// Total fragment amount.
+    total_fragments: u8,
+    /// Fragment index.
+    fragment_index: u8,
+    /// Payload.
+    payload: Vec<u8>,
+}
+
+impl Hop2HopFragment {
+    /// Returns the packet hash.
+    #[must_use]
+    pub fn packet_hash(&self) -> u32 {
+        self.packet_hash
+    }
+    /// Returns the total fragments amount.
+    #[must_use]
+    pub fn total_fragments(&self) -> u8 {
+        self.total_fragments
+    }
+    /// Returns the fragment index.
+    #[must_use]
+    pub fn fragment_index(&self) -> u8 {
+        self.fragment_index
+    }
+    /// Returns a reference to the payload.
+    #[must_use]
+    pub fn payload_ref(&self) -> &Vec<u8> {
+        &self.payload
+    }
+}
+
+#[typetag::serde]
+impl LoRaWanPacket for Hop2HopFragment {
+    fn write_phy_payload(&self, buffer: &mut Vec<u8>) {
+        buffer.push(LO_RA_WAN_PROPRIETARY_TAG);
+        buffer.push(self.packet_type() as u8);
+        buffer.extend_from_slice(&self.packet_hash.to_le_bytes());
+        buffer.push(self.total_fragments);
+        buffer.push(self.fragment_index);
+        buffer.extend_from_slice(&self.payload);
+    }
+
+    fn packet_type(&self) -> PacketType {
+        PacketType::Hop2HopFragment
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// Local announcement packet type.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct LocalAnnouncement {
+    /// The optional location of the sender.
+    location: Option<GpsLocation>,
+    /// All [`EndDeviceId`] registered to the sender, the first one identifies the sender.
+    end_device_ids: Vec<EndDeviceId>,
+}
+
+impl LocalAnnouncement {
+    /// Creates a new [`LocalAnnouncement`].
+    #[must_use]
+    pub fn new(location: Option<GpsLocation>, end_device_ids: Vec<EndDeviceId>) -> Self {
+        Self {
+            location,
+            end_device_ids,
+        }
+    }
+
+    /// Creates as few [`LocalAnnouncement`] as possible to announce all end device IDs at the
+    /// provided data rate and repeater compatibility. The location is only included in the first
+    /// announcement.
+    ///
+    /// The first end device ID identifies the sender and starts every announcement, so receivers
+    /// attribute all announcements to the same node.
+    #[must_use]
+    pub fn split_to_data_rate(
+        location: Option<GpsLocation>,
+        end_device_ids: &[EndDeviceId],
+        data_rate: DataRate,
+        repeater_compatible: bool,
+    ) -> Vec<LocalAnnouncement> {
+        let Some((sender, mut remaining_end_device_ids)) = end_device_ids.split_first() else {
+            return Vec::new();
+        };
+        // 1B Packet type
+        let available_bytes = data_rate.max_usable_payload_size(repeater_compatible) - 1;
+        let mut announcements = Vec::new();
+        let mut location = location;
+        loop {
+            let location_size = if location.is_some() { 9 } else { 0 };
+            // The sender takes one end device ID of every packet.
+            let end_device_ids_per_packet = (available_bytes - location_size) / 4 - 1;
+            let (packet_end_device_ids, rest) = remaining_end_device_ids
+                .split_at(end_device_ids_per_packet.min(remaining_end_device_ids.len()));
+            let mut announced_end_device_ids = Vec::with_capacity(packet_end_device_ids.len() + 1);
+            announced_end_device_ids.push(*sender);
+            announced_end_device_ids.extend_from_slice(packet_end_device_ids);
+            announcements.push(LocalAnnouncement {
+                location: location.take(),
+                end_device_ids: announced_end_device_ids,
+            });
+            remaining_end_device_ids = rest;
+            if remaining_end_device_ids.is_empty() {
+                return announcements;
+            }
+        }
+    }
+
+    /// Returns the location.
+    #[must_use]
+    pub fn location(&self) -> Option<GpsLocation> {
+        self.location
+    }
+    /// Returns the end devices vector by reference.
+    #[must_use]
+    pub fn end_device_ids_ref(&self) -> &Vec<EndDeviceId> {
+        &self.end_device_ids
+    }
+}
+
+#[typetag::serde]
+impl LoRaWanPacket for LocalAnnouncement {
+    fn write_phy_payload(&self, buffer: &mut Vec<u8>) {
+        buffer.push(LO_RA_WAN_PROPRIETARY_TAG);
+        buffer.push(self.packet_type() as u8);
+        if let Some(location) = &self.location {
+            write_location(buffer, location);
+        }
+        for end_device_id in &self.end_device_ids {
+            write_end_device_id(buffer, *end_device_id);
+        }
+    }
+
+    fn packet_type(&self) -> PacketType {
+        PacketType::LocalAnnouncement
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// An end device ID reachable through the sender of a [`ReachabilityAnnouncement`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct ReachableEndDeviceId {
+    /// The reachable end device ID.
+    pub end_device_id: EndDeviceId,
+    /// Amount of hops from the sender to the end device ID, 1 if registered at a direct
+    /// neighbor of the sender.
+    pub hop_distance: u8,
+    /// The neighbor the sender learned the end device ID from, identified by its announcing end
+    /// device ID. Receivers ignore entries learned from themselves (split horizon).
+    pub next_hop: EndDeviceId,
+}
+
+/// Reachability announcement packet type.
+///
+/// Advertises end device IDs learned from neighbor announcements on behalf of downstream nodes.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct ReachabilityAnnouncement {
+    /// The end device ID identifying the sender, the first end device ID of its local
+    /// announcements.
+    sender: EndDeviceId,
+    /// All [`ReachableEndDeviceId`] reachable through the sender.
+    reachable_end_device_ids: Vec<ReachableEndDeviceId>,
+}
+
+impl ReachabilityAnnouncement {
+    /// Creates a new [`ReachabilityAnnouncement`].
+    #[must_use]
+    pub fn new(sender: EndDeviceId, reachable_end_device_ids: Vec<ReachableEndDeviceId>) -> Self {
+        Self {
+            sender,
+            reachable_end_device_ids,
+        }
+    }
+
+    /// Creates as few [`ReachabilityAnnouncement`] as possible to announce all reachable end
+    /// device IDs at the provided data rate and repeater compatibility.
+    #[must_use]
+    pub fn split_to_data_rate(
+        sender: EndDeviceId,
+        reachable_end_device_ids: &[ReachableEndDeviceId],
+        data_rate: DataRate,
+        repeater_compatible: bool,
+    ) -> Vec<ReachabilityAnnouncement> {
+        // 1B Packet type, 4B sender, 4B end device ID + 1B hop distance + 4B next hop per entry
+        let entries_per_packet =
+            (data_rate.max_usable_payload_size(repeater_compatible) - 1 - 4) / 9;
+        reachable_end_device_ids
+            .chunks(entries_per_packet)
+            .map(|chunk| ReachabilityAnnouncement::new(sender, chunk.to_vec()))
+            .collect()
+    }
+
+    /// Returns the end device ID identifying the sender.
+    #[must_use]
+    pub fn sender(&self) -> EndDeviceId {
+        self.sender
+    }
+
+    /// Returns the reachable end device IDs by reference.
+    #[must_use]
+    pub fn reachable_end_device_ids_ref(&self) -> &Vec<ReachableEndDeviceId> {
+        &self.reachable_end_device_ids
+    }
+}
+
+#[typetag::serde]
+impl LoRaWanPacket for ReachabilityAnnouncement {
+    fn write_phy_payload(&self, buffer: &mut Vec<u8>) {
+        buffer.push(LO_RA_WAN_PROPRIETARY_TAG);
+        buffer.push(self.packet_type() as u8);
+        write_end_device_id(buffer, self.sender);
+        for reachable_end_device_id in &self.reachable_end_device_ids {
+            write_end_device_id(buffer, reachable_end_device_id.end_device_id);
+            buffer.push(reachable_end_device_id.hop_distance);
+            write_end_device_id(buffer, reachable_end_device_id.next_hop);
+        }
+    }
+
+    fn packet_type(&self) -> PacketType {
+        PacketType::ReachabilityAnnouncement
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// Services offered at an end device ID, announced in a [`ServiceAnnouncement`].
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct EndDeviceServices {
+    /// The end device ID offering the services.
+    pub end_device_id: EndDeviceId,
+    /// The offered services, at most [`MAX_SERVICES_PER_END_DEVICE`] are announced.
+    pub services: Vec<ServiceTag>,
+}
+
+impl EndDeviceServices {
+    /// Returns the announced services.
+    fn announced_services(&self) -> &[ServiceTag] {
+        &self.services[..self.services.len().min(MAX_SERVICES_PER_END_DEVICE)]
+    }
+
+    /// Returns the encoded size in bytes: 4B end device ID + 1B service amount + 2B per service.
+    fn encoded_size(&self) -> usize {
+        4 + 1 + 2 * self.announced_services().len()
+    }
+}
+
+/// Service announcement packet type.
+///
+/// Lets neighbors discover the services offered at the end device IDs of the sender before
+/// sending to them. Sent alongside [`LocalAnnouncement`], nodes not knowing the packet type drop
+/// it without affecting the local announcements.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct ServiceAnnouncement {
+    /// All [`EndDeviceServices`] of the sender.
+    end_device_services: Vec<EndDeviceServices>,
+}
+
+impl ServiceAnnouncement {
+    /// Creates a new [`ServiceAnnouncement`].
+    #[must_use]
+    pub fn new(end_device_services: Vec<EndDeviceServices>) -> Self {
+        Self {
+            end_device_services,
+        }
+    }
+
+    /// Creates as few [`ServiceAnnouncement`] as possible to announce the services of all end
+    /// device IDs at the provided data rate and repeater compatibility.
+    #[must_use]
+    pub fn split_to_data_rate(
+        end_device_services: &[EndDeviceServices],
+        data_rate: DataRate,
+        repeater_compatible: bool,
+    ) -> Vec<ServiceAnnouncement> {
+        // 1B Packet type
+        let available_bytes = data_rate.max_usable_payload_size(repeater_compatible) - 1;
+        let mut announcements = Vec::new();
+        let mut packet_end_device_services = Vec::new();
+        let mut used_bytes = 0;
+        for entry in end_device_services {
+            if used_bytes + entry.encoded_size() > available_bytes
+                && !packet_end_device_services.is_empty()
+            {
+                announcements.push(ServiceAnnouncement::new(std::mem::take(
+                    &mut packet_end_device_services,
+                )));
+                used_bytes = 0;
+            }
+            used_bytes += entry.encoded_size();
+            packet_end_device_services.push(entry.clone());
+        }
+        if !packet_end_device_services.is_empty() {
+            announcements.push(ServiceAnnouncement::new(packet_end_device_services));
+        }
+        announcements
+    }
+
+    /// Returns the end device services by reference.
+    #[must_use]
+    pub fn end_device_services_ref(&self) -> &Vec<EndDeviceServices> {
+        &self.end_device_services
+    }
+}
+
+#[typetag::serde]
+impl LoRaWanPacket for ServiceAnnouncement {
+    fn write_phy_payload(&self, buffer: &mut Vec<u8>) {
+        buffer.push(LO_RA_WAN_PROPRIETARY_TAG);
+        buffer.push(self.packet_type() as u8);
+        for entry in &self.end_device_services {
+            let services = entry.announced_services();
+            write_end_device_id(buffer, entry.end_device_id);
+            buffer.push(u8::try_from(services.len()).unwrap_or(u8::MAX));
+            for service in services {
+                buffer.extend_from_slice(&service.to_le_bytes());
+            }
+        }
+    }
+
+    fn packet_type(&self) -> PacketType {
+        PacketType::ServiceAnnouncement
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// Channel plan announcement packet type.
+///
+/// Tells neighbors on which frequencies the sender rotates its announcements, so they learn
+/// where else the node can be heard. Sent alongside [`LocalAnnouncement`], nodes not knowing the
+/// packet type drop it without affecting the local announcements.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct ChannelPlanAnnouncement {
+    /// An end device ID registered at the sender, identifying the sender.
+    end_device_id: EndDeviceId,
+    /// Frequencies of the channel plan in Hz, at most [`MAX_ANNOUNCED_FREQUENCIES`] are
+    /// announced.
+    frequencies: Vec<u32>,
+}
+
+impl ChannelPlanAnnouncement {
+    /// Creates a new [`ChannelPlanAnnouncement`].
+    #[must_use]
+    pub fn new(end_device_id: EndDeviceId, frequencies: Vec<u32>) -> Self {
+        Self {
+            end_device_id,
+            frequencies,
+        }
+    }
+
+    /// Returns the end device ID identifying the sender.
+    #[must_use]
+    pub fn end_device_id(&self) -> EndDeviceId {
+        self.end_device_id
+    }
+
+    /// Returns the announced frequencies in Hz.
+    #[must_use]
+    pub fn frequencies(&self) -> &[u32] {
+        &self.frequencies[..self.frequencies.len().min(MAX_ANNOUNCED_FREQUENCIES)]
+    }
+}
+
+#[typetag::serde]
+impl LoRaWanPacket for ChannelPlanAnnouncement {
+    fn write_phy_payload(&self, buffer: &mut Vec<u8>) {
+        buffer.push(LO_RA_WAN_PROPRIETARY_TAG);
+        buffer.push(self.packet_type() as u8);
+        write_end_device_id(buffer, self.end_device_id);
+        for frequency in self.frequencies() {
+            buffer.extend_from_slice(&frequency.to_le_bytes());
+        }
+    }
+
+    fn packet_type(&self) -> PacketType {
+        PacketType::ChannelPlanAnnouncement
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// Echo request packet type, answered with an [`EchoReply`] by the destination or by the relay
+/// at which the hop limit is reached.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct EchoRequest {
+    /// Destination end device ID.
+    pub destination: EndDeviceId,
+    /// Source end device ID, destination of the reply.
+    pub source: EndDeviceId,
+    /// Sequence number to match the reply.
+    pub sequence: u16,
+    /// Amount of relays the request passed, incremented by every relay.
+    pub hop_count: u8,
+    /// Amount of relays after which the request is answered instead of relayed.
+    pub hop_limit: u8,
+}
+
+impl EchoRequest {
+    /// Increments the hop count, returns whether the hop limit is reached.
+    pub fn increment_hop_count(&mut self) -> bool {
+        self.hop_count = self.hop_count.saturating_add(1);
+        self.hop_count >= self.hop_limit
+    }
+}
+
+#[typetag::serde]
+impl LoRaWanPacket for EchoRequest {
+    fn write_phy_payload(&self, buffer: &mut Vec<u8>) {
+        buffer.push(LO_RA_WAN_PROPRIETARY_TAG);
+        buffer.push(self.packet_type() as u8);
+        write_end_device_id(buffer, self.destination);
+        write_end_device_id(buffer, self.source);
+        buffer.extend_from_slice(&self.sequence.to_le_bytes());
+        buffer.push(self.hop_count);
+        buffer.push(self.hop_limit);
+    }
+
+    fn packet_type(&self) -> PacketType {
+        PacketType::EchoRequest
+    }
+
+    fn packet_destination(&self) -> Option<EndDeviceId> {
+        Some(self.destination)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// Echo reply packet type, answer to an [`EchoRequest`].
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct EchoReply {
+    /// Destination end device ID, the source of the request.
+    pub destination: EndDeviceId,
+    /// End device ID of the responding node.
+    pub source: EndDeviceId,
+    /// Sequence number of the request.
+    pub sequence: u16,
+    /// Amount of relays the request passed. The reply itself is relayed unchanged to be
+    /// recognized by the packet cache.
+    pub hop_count: u8,
+    /// Whether the reply was sent by a relay because the hop limit was reached.
+    pub hop_limit_reached: bool,
+}
+
+impl EchoReply {
+    /// Creates the reply to an [`EchoRequest`] sent by the node with the `source` end device ID.
+    #[must_use]
+    pub fn answer(request: &EchoRequest, source: EndDeviceId, hop_limit_reached: bool) -> Self {
+        Self {
+            destination: request.source,
+            source,
+            sequence: request.sequence,
+            hop_count: request.hop_count,
+            hop_limit_reached,
+        }
+    }
+}
+
+#[typetag::serde]
+impl LoRaWanPacket for EchoReply {
+    fn write_phy_payload(&self, buffer: &mut Vec<u8>) {
+        buffer.push(LO_RA_WAN_PROPRIETARY_TAG);
+        buffer.push(self.packet_type() as u8);
+        write_end_device_id(buffer, self.destination);
+        write_end_device_id(buffer, self.source);
+        buffer.extend_from_slice(&self.sequence.to_le_bytes());
+        buffer.push(self.hop_count);
+        buffer.push(u8::from(self.hop_limit_reached));
+    }
+
+    fn packet_type(&self) -> PacketType {
+        PacketType::EchoReply
+    }
+
+    fn packet_destination(&self) -> Option<EndDeviceId> {
+        Some(self.destination)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// Hop-level acknowledgement of a packet a neighbor sent directly to this node, see
+/// the `unicast` module of spatz.
+///
+/// Acknowledgements have no destination and are therefore never relayed.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct HopAck {
+    /// End device ID of the acknowledging node, the destination of the acknowledged packet.
+    pub source: EndDeviceId,
+    /// CRC32 of the phy payload of the acknowledged packet.
+    pub packet_hash: u32,
+}
+
+impl HopAck {
+    /// Creates the acknowledgement of the phy payload received by the node with the `source`
+    /// end device ID.
+    #[must_use]
+    pub fn acknowledge(phy_payload: &[u8], source: EndDeviceId) -> Self {
+        Self {
+            source,
+            packet_hash: crc32fast::hash(phy_payload),
+        }
+    }
+}
+
+#[typetag::serde]
+impl LoRaWanPacket for HopAck {
+    fn write_phy_payload(&self, buffer: &mut Vec<u8>) {
+        buffer.push(LO_RA_WAN_PROPRIETARY_TAG);
+        buffer.push(self.packet_type() as u8);
+        write_end_device_id(buffer, self.source);
+        buffer.extend_from_slice(&self.packet_hash.to_le_bytes());
+    }
+
+    fn packet_type(&self) -> PacketType {
+        PacketType::HopAck
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// BP7 bundle packet type, carries a BP7 bundle or bundle fragment encoded as CBOR instead of the
+/// custom headers, see the `bp7_interop` module of spatz.
+///
+/// Bundles too large for a packet are split into BP7 fragments, each a bundle of its own with
+/// the fragment offset and the total application data unit length in its primary block, so any
+/// BP7 implementation can reassemble them.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct Bp7Bundle {
+    /// Destination end device ID, taken from the primary block.
+    destination: EndDeviceId,
+    /// Source end device ID, taken from the primary block.
+    source: EndDeviceId,
+    /// The CBOR encoded bundle.
+    cbor: Vec<u8>,
+}
+
+impl Bp7Bundle {
+    /// Creates a [`Bp7Bundle`] carrying the payload, a BP7 fragment at `offset` of a payload of
+    /// `total_length` bytes if the payload is not complete.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if source or destination cannot be converted into endpoint IDs or the
+    /// primary block cannot be built.
+    pub fn new(
+        destination: EndDeviceId,
+        source: EndDeviceId,
+        timestamp: DateTime<Utc>,
+        payload: &[u8],
+        offset: usize,
+        total_length: usize,
+    ) -> Result<Self, Bp7BundleCreationError> {
+        let mut primary_block_builder = bp7::primary::PrimaryBlockBuilder::new()
+            .source(source.try_into()?)
+            .destination(destination.try_into()?)
+            // No status reports are sent, leaving out the report-to endpoint saves airtime.
+            .report_to(bp7::EndpointID::none())
+            .creation_timestamp(bp7::CreationTimestamp::with_time_and_seq(
+                unix_ts_to_dtn_time(timestamp.timestamp().unsigned_abs()),
+                0,
+            ))
+            .lifetime(BUNDLE_LIFETIME);
+        if offset > 0 || payload.len() < total_length {
+            primary_block_builder = primary_block_builder
+                .fragmentation_offset(u64::try_from(offset).unwrap_or(u64::MAX))
+                .total_data_length(u64::try_from(total_length).unwrap_or(u64::MAX))
+                .bundle_control_flags(BundleControlFlags::BUNDLE_IS_FRAGMENT.bits());
+        }
+        let canonical =
+            bp7::canonical::new_payload_block(BlockControlFlags::empty(), payload.to_vec());
+        let mut bundle = bp7::Bundle::new(primary_block_builder.build()?, vec![canonical]);
+        Ok(Self {
+            destination,
+            source,
+            cbor: bundle.to_cbor(),
+        })
+    }
+
+    /// Creates a [`Bp7Bundle`] from a CBOR encoded bundle.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the CBOR is no bundle or its source or destination is no DTN address
+    /// of an end device ID.
+    pub fn from_cbor(cbor: Vec<u8>) -> Result<Self, ProtocolParserError> {
+        let bundle = serde_cbor::from_slice::<bp7::Bundle>(&cbor)
+            .map_err(|_| ProtocolParserError::InvalidBp7Bundle)?;
+        let destination = EndDeviceId::try_from(bundle.primary.destination)
+            .map_err(|_| ProtocolParserError::InvalidBp7Bundle)?;
+        let source = EndDeviceId::try_from(bundle.primary.source)
+            .map_err(|_| ProtocolParserError::InvalidBp7Bundle)?;
+        Ok(Self {
+            destination,
+            source,
+            cbor,
+        })
+    }
+
+    /// Returns the source end device ID.
+    #[must_use]
+    pub fn source(&self) -> EndDeviceId {
+        self.source
+    }
+
+    /// Returns the CBOR encoded bundle.
+    #[must_use]
+    pub fn cbor(&self) -> &[u8] {
+        &self.cbor
+    }
+
+    /// Decodes the bundle.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the CBOR is no bundle, not possible for parsed packets.
+    pub fn bundle(&self) -> Result<bp7::Bundle, serde_cbor::Error> {
+        serde_cbor::from_slice(&self.cbor)
+    }
+}
+
+#[typetag::serde]
+impl LoRaWanPacket for Bp7Bundle {
+    fn write_phy_payload(&self, buffer: &mut Vec<u8>) {
+        buffer.push(LO_RA_WAN_PROPRIETARY_TAG);
+        buffer.push(self.packet_type() as u8);
+        buffer.extend_from_slice(&self.cbor);
+    }
+
+    fn packet_type(&self) -> PacketType {
+        PacketType::Bp7Bundle
+    }
+
+    fn packet_destination(&self) -> Option<EndDeviceId> {
+        Some(self.destination)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// Capability announcement packet type.
+///
+/// Tells neighbors which optional protocol features the sender supports, e.g.
+/// [`CAPABILITY_BP7_CBOR`], for all end device IDs registered at the sender. Nodes not knowing
+/// the packet type drop it and are treated as supporting none.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct CapabilityAnnouncement {
+    /// Bit set of the supported capabilities.
+    capabilities: u16,
+    /// End device IDs registered at the sender.
+    end_device_ids: Vec<EndDeviceId>,
+}
+
+impl CapabilityAnnouncement {
+    /// Creates a new [`CapabilityAnnouncement`].
+    #[must_use]
+    pub fn new(capabilities: u16, end_device_ids: Vec<EndDeviceId>) -> Self {
+        Self {
+            capabilities,
+            end_device_ids,
+        }
+    }
+
+    /// Creates as few [`CapabilityAnnouncement`] as possible to announce the capabilities of all
+    /// end device IDs at the provided data rate and repeater compatibility.
+    #[must_use]
+    pub fn split_to_data_rate(
+        capabilities: u16,
+        end_device_ids: &[EndDeviceId],
+        data_rate: DataRate,
+        repeater_compatible: bool,
+    ) -> Vec<CapabilityAnnouncement> {
+        // 1B Packet type + 2B Capabilities
+        let end_device_ids_per_packet =
+            (data_rate.max_usable_payload_size(repeater_compatible) - 1 - 2) / 4;
+        end_device_ids
+            .chunks(end_device_ids_per_packet.max(1))
+            .map(|chunk| CapabilityAnnouncement::new(capabilities, chunk.to_vec()))
+            .collect()
+    }
+
+    /// Returns the bit set of the supported capabilities.
+    #[must_use]
+    pub fn capabilities(&self) -> u16 {
+        self.capabilities
+    }
+
+    /// Returns the end device IDs registered at the sender.
+    #[must_use]
+    pub fn end_device_ids_ref(&self) -> &Vec<EndDeviceId> {
+        &self.end_device_ids
+    }
+}
+
+#[typetag::serde]
+impl LoRaWanPacket for CapabilityAnnouncement {
+    fn write_phy_payload(&self, buffer: &mut Vec<u8>) {
+        buffer.push(LO_RA_WAN_PROPRIETARY_TAG);
+        buffer.push(self.packet_type() as u8);
+        buffer.extend_from_slice(&self.capabilities.to_le_bytes());
+        for end_device_id in &self.end_device_ids {
+            write_end_device_id(buffer, *end_device_id);
+        }
+    }
+
+    fn packet_type(&self) -> PacketType {
+        PacketType::CapabilityAnnouncement
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// Status report request packet type, see the `status_reports` module of spatz.
+///
+/// The custom headers carry no bundle control flags, so the source node sends the requested
+/// reports of a bundle along with it. The bundle is identified by its destination, source and
+/// timestamp.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct StatusReportRequest {
+    /// Destination of the bundle.
+    pub destination: EndDeviceId,
+    /// Source of the bundle.
+    pub source: EndDeviceId,
+    /// Timestamp of the bundle.
+    pub timestamp: DateTime<Utc>,
+    /// End device ID the reports are sent to.
+    pub report_to: EndDeviceId,
+    /// Bit set of the requested reports, e.g. [`STATUS_DELIVERED`].
+    pub requested_reports: u8,
+}
+
+#[typetag::serde]
+impl LoRaWanPacket for StatusReportRequest {
+    fn write_phy_payload(&self, buffer: &mut Vec<u8>) {
+        buffer.push(LO_RA_WAN_PROPRIETARY_TAG);
+        buffer.push(self.packet_type() as u8);
+        write_end_device_id(buffer, self.destination);
+        write_end_device_id(buffer, self.source);
+        write_timestamp(buffer, &self.timestamp);
+        write_end_device_id(buffer, self.report_to);
+        buffer.push(self.requested_reports);
+    }
+
+    fn packet_type(&self) -> PacketType {
+        PacketType::StatusReportRequest
+    }
+
+    fn packet_destination(&self) -> Option<EndDeviceId> {
+        Some(self.destination)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// Status report packet type, the compact form of a BP7 bundle status report, see
+/// the `status_reports` module of spatz.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct StatusReport {
+    /// Report-to end device ID of the bundle.
+    pub destination: EndDeviceId,
+    /// End device ID of the reporting node.
+    pub source: EndDeviceId,
+    /// The reported status, e.g. [`STATUS_RECEIVED`].
+    pub status: u8,
+    /// BP7 status report reason code.
+    pub reason: u8,
+    /// Source of the bundle.
+    pub bundle_source: EndDeviceId,
+    /// Timestamp of the bundle.
+    pub bundle_timestamp: DateTime<Utc>,
+}
+
+#[typetag::serde]
+impl LoRaWanPacket for StatusReport {
+    fn write_phy_payload(&self, buffer: &mut Vec<u8>) {
+        buffer.push(LO_RA_WAN_PROPRIETARY_TAG);
+        buffer.push(self.packet_type() as u8);
+        write_end_device_id(buffer, self.destination);
+        write_end_device_id(buffer, self.source);
+        buffer.push(self.status);
+        buffer.push(self.reason);
+        write_end_device_id(buffer, self.bundle_source);
+        write_timestamp(buffer, &self.bundle_timestamp);
+    }
+
+    fn packet_type(&self) -> PacketType {
+        PacketType::StatusReport
+    }
+
+    fn packet_destination(&self) -> Option<EndDeviceId> {
+        Some(self.destination)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// Data rate announcement packet type.
+///
+/// Tells neighbors with which data rates the sender prefers to receive for all end device IDs
+/// registered at the sender, e.g. DR6 if its gateways receive 250 kHz, so senders pick the
+/// fastest data rate both support. Sent alongside [`LocalAnnouncement`], nodes not knowing the
+/// packet type drop it and are sent to with [`DEFAULT_RECEIVE_DATA_RATES`].
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct DataRateAnnouncement {
+    /// Bit set of the preferred receive data rates, bit `n` is DR`n`.
+    data_rates: u8,
+    /// End device IDs registered at the sender.
+    end_device_ids: Vec<EndDeviceId>,
+}
+
+impl DataRateAnnouncement {
+    /// Creates a new [`DataRateAnnouncement`].
+    #[must_use]
+    pub fn new(data_rates: u8, end_device_ids: Vec<EndDeviceId>) -> Self {
+        Self {
+            data_rates,
+            end_device_ids,
+        }
+    }
+
+    /// Creates as few [`DataRateAnnouncement`] as possible to announce the receive data rates of
+    /// all end device IDs at the provided data rate and repeater compatibility.
+    #[must_use]
+    pub fn split_to_data_rate(
+        data_rates: u8,
+        end_device_ids: &[EndDeviceId],
+        data_rate: DataRate,
+        repeater_compatible: bool,
+    ) -> Vec<DataRateAnnouncement> {
+        // 1B Packet type + 1B Data rates
+        let end_device_ids_per_packet =
+            (data_rate.max_usable_payload_size(repeater_compatible) - 1 - 1) / 4;
+        end_device_ids
+            .chunks(end_device_ids_per_packet.max(1))
+            .map(|chunk| DataRateAnnouncement::new(data_rates, chunk.to_vec()))
+            .collect()
+    }
+
+    /// Returns the bit set of the preferred receive data rates.
+    #[must_use]
+    pub fn data_rates(&self) -> u8 {
+        self.data_rates
+    }
+
+    /// Returns the end device IDs registered at the sender.
+    #[must_use]
+    pub fn end_device_ids_ref(&self) -> &Vec<EndDeviceId> {
+        &self.end_device_ids
+    }
+}
+
+#[typetag::serde]
+impl LoRaWanPacket for DataRateAnnouncement {
+    fn write_phy_payload(&self, buffer: &mut Vec<u8>) {
+        buffer.push(LO_RA_WAN_PROPRIETARY_TAG);
+        buffer.push(self.packet_type() as u8);
+        buffer.push(self.data_rates);
+        for end_device_id in &self.end_device_ids {
+            write_end_device_id(buffer, *end_device_id);
+        }
+    }
+
+    fn packet_type(&self) -> PacketType {
+        PacketType::DataRateAnnouncement
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// Bundle resend request packet type, see the `bundle_resend` module of spatz.
+///
+/// Sent by the destination of a bundle to its source, e.g. after the bundle was lost or its
+/// fragments could not be combined. The bundle is identified by its source, destination and
+/// timestamp.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct BundleResendRequest {
+    /// Source of the bundle, the request is sent to it.
+    pub destination: EndDeviceId,
+    /// Destination of the bundle, the requesting end device ID.
+    pub source: EndDeviceId,
+    /// Timestamp of the bundle.
+    pub timestamp: DateTime<Utc>,
+}
+
+#[typetag::serde]
+impl LoRaWanPacket for BundleResendRequest {
+    fn write_phy_payload(&self, buffer: &mut Vec<u8>) {
+        buffer.push(LO_RA_WAN_PROPRIETARY_TAG);
+        buffer.push(self.packet_type() as u8);
+        write_end_device_id(buffer, self.destination);
+        write_end_device_id(buffer, self.source);
+        write_timestamp(buffer, &self.timestamp);
+    }
+
+    fn packet_type(&self) -> PacketType {
+        PacketType::BundleResendRequest
+    }
+
+    fn packet_destination(&self) -> Option<EndDeviceId> {
+        Some(self.destination)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// Returns the bit of the data rate in a bit set of data rates, bit `n` is DR`n`.
+#[must_use]
+pub fn data_rate_bit(data_rate: DataRate) -> u8 {
+    1 << data_rate as u8
+}
+
+/// Encoded GPS location.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct GpsLocation {
+    /// Latitude.
+    latitude: i32,
+    /// Longitude.
+    longitude: i32,
+    /// Altitude.
+    altitude: i32,
+}
+
+impl GpsLocation {
+    /// Creates a new [`GpsLocation`] from floating point coordinates.
+    ///
+    /// # Errors
+    ///
+    /// Returns a error if one of the provided coordinates is out of range.
+    pub fn new(lat: f64, long: f64, alt: f64) -> Result<Self, LocationEncodingError> {
+        Ok(Self {
+            latitude: encode_lat(lat)?,
+            longitude: encode_long(long)?,
+            altitude: encode_alt(alt)?,
+        })
+    }
+
+    /// Converts the internal i32 representation to floating point representation.
+    #[must_use]
+    pub fn as_float_coords(&self) -> (f64, f64, f64) {
+        (
+            decode_lat(self.latitude),
+            decode_long(self.longitude),
+            decode_alt(self.altitude),
+        )
+    }
+}
+
+/// Appends a `[EndDeviceId`] in little endian.
+fn write_end_device_id(buffer: &mut Vec<u8>, end_device_id: EndDeviceId) {
+    buffer.extend_from_slice(&end_device_id.0.to_le_bytes());
+}
+
+/// Appends the bytes representation of a timestamp.
+fn write_timestamp(buffer: &mut Vec<u8>, timestamp: &DateTime<Utc>) {
+    let timestamp = u32::try_from(timestamp.timestamp())
+        .expect("This succeeds until u32 cannot hold the unix timestamp anymore.");
+    buffer.extend_from_slice(&timestamp.to_le_bytes());
+}
+
+/// Appends the bytes representation of a [`GpsLocation`], every coordinate as 3B little endian
+/// with the sign in the most significant bit.
+fn write_location(buffer: &mut Vec<u8>, location: &GpsLocation) {
+    for coordinate in [location.latitude, location.longitude, location.altitude] {
+        let bytes = coordinate.to_le_bytes();
+        let sign = if coordinate.is_negative() {
+            0b1000_0000
+        } else {
+            0
+        };
+        buffer.extend_from_slice(&[bytes[0], bytes[1], bytes[2] | sign]);
+    }
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use crate::end_device_id::EndDeviceId;
+    use crate::parser::{parse_location, parse_phy_payload};
+    use crate::{
+        write_location, Bp7Bundle, BundleFragment, CompleteBundle, EchoReply, EchoRequest,
+        EndDeviceServices, GpsLocation, LoRaWanPacket, LocalAnnouncement, ReachabilityAnnouncement,
+        ReachableEndDeviceId, ServiceAnnouncement, COMPLETE_BUNDLE_HEADERS_SIZE,
+        MAX_PHY_PAYLOAD_SIZE,
+    };
+    use chrono::{DateTime, Utc};
+    use lorawan_parameters::DataRate;
+
+    #[test]
+    fn convert_location_to_bytes_test() {
+        let location = GpsLocation {
+            latitude: 10,
+            longitude: -4003,
+            altitude: 123_678,
+        };
+        let mut loc_bytes = Vec::new();
+        write_location(&mut loc_bytes, &location);
+        let (_, parsed_location) = parse_location(loc_bytes.as_slice()).unwrap();
+        assert_eq!(location, parsed_location.unwrap());
+    }
+
+    #[test]
+    fn convert_bundle_fragment_to_bytes_and_back() {
+        let timestamp = DateTime::from_timestamp(Utc::now().timestamp(), 0).unwrap();
+        let packet = BundleFragment {
+            destination: EndDeviceId(0x1122_3344),
+            source: EndDeviceId(0x5566_7788),
+            timestamp,
+            is_end: false,
+            fragment_index: 10,
+            payload: vec![0xFF; 10],
+        };
+        let packet_bytes = packet.convert_to_lorawan_phy_payload();
+        // 1B MHDR + 1B Packet type +  4B DST + 4B SRC + 4B Timestamp + 1B Fragment + 10 B payload = 25
+        assert_eq!(25, packet_bytes.len());
+        let parsed_packet = parse_phy_payload(&packet_bytes).unwrap();
+        assert_eq!(
+            &packet,
+            parsed_packet
+                .as_any()
+                .downcast_ref::<BundleFragment>()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn phy_payload_buffer_is_reused() {
+        let packets: [Box<dyn LoRaWanPacket>; 2] = [
+            Box::new(BundleFragment {
+                destination: EndDeviceId(0x1122_3344),
+                source: EndDeviceId(0x5566_7788),
+                timestamp: Utc::now(),
+                is_end: true,
+                fragment_index: 3,
+                payload: vec![0xFF; 240],
+            }),
+            Box::new(EchoRequest {
+                destination: EndDeviceId(1),
+                source: EndDeviceId(2),
+                sequence: 7,
+                hop_count: 0,
+                hop_limit: 8,
+            }),
+        ];
+        let mut buffer = Vec::with_capacity(MAX_PHY_PAYLOAD_SIZE);
+        let allocation = buffer.as_ptr();
+        for packet in &packets {
+            buffer.clear();
+            packet.write_phy_payload(&mut buffer);
+            assert_eq!(buffer, packet.convert_to_lorawan_phy_payload());
+            assert_eq!(buffer.as_ptr(), allocation);
+        }
+    }
+
+    #[test]
+    fn convert_announcement_to_bytes_and_back() {
+        let packet = LocalAnnouncement {
+            location: Some(GpsLocation {
+                latitude: 30,
+                longitude: -1534,
+                altitude: 86432,
+            }),
+            end_device_ids: vec![EndDeviceId(0x1122_3344), EndDeviceId(0x2233_4455)],
+        };
+        let packet_bytes = packet.convert_to_lorawan_phy_payload();
+        let parse_packet = parse_phy_payload(&packet_bytes).unwrap();
+        assert_eq!(
+            &packet,
+            parse_packet
+                .as_any()
+                .downcast_ref::<LocalAnnouncement>()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn convert_reachability_announcement_to_bytes_and_back() {
+        let packet = ReachabilityAnnouncement {
+            sender: EndDeviceId(0x5566_7788),
+            reachable_end_device_ids: vec![
+                ReachableEndDeviceId {
+                    end_device_id: EndDeviceId(0x1122_3344),
+                    hop_distance: 1,
+                    next_hop: EndDeviceId(0x1122_3344),
+                },
+                ReachableEndDeviceId {
+                    end_device_id: EndDeviceId(0x2233_4455),
+                    hop_distance: 3,
+                    next_hop: EndDeviceId(0x3344_5566),
+                },
+            ],
+        };
+        let packet_bytes = packet.convert_to_lorawan_phy_payload();
+        // 1B MHDR + 1B Packet type + 4B sender
+        // + 2 * (4B end device ID + 1B hop distance + 4B next hop) = 24
+        assert_eq!(24, packet_bytes.len());
+        let parse_packet = parse_phy_payload(&packet_bytes).unwrap();
+        assert_eq!(
+            &packet,
+            parse_packet
+                .as_any()
+                .downcast_ref::<ReachabilityAnnouncement>()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn convert_echo_packets_to_bytes_and_back() {
+        let mut request = EchoRequest {
+            destination: EndDeviceId(0x1122_3344),
+            source: EndDeviceId(0x5566_7788),
+            sequence: 0x0102,
+            hop_count: 0,
+            hop_limit: 2,
+        };
+        assert!(!request.increment_hop_count());
+        let request_bytes = request.convert_to_lorawan_phy_payload();
+        // 1B MHDR + 1B Packet type + 4B destination + 4B source + 2B sequence + 1B hop count
+        // + 1B hop limit = 14
+        assert_eq!(14, request_bytes.len());
+        let parsed_request = parse_phy_payload(&request_bytes).unwrap();
+        assert_eq!(
+            &request,
+            parsed_request
+                .as_any()
+                .downcast_ref::<EchoRequest>()
+                .unwrap()
+        );
+        assert!(request.increment_hop_count());
+
+        let reply = EchoReply::answer(&request, EndDeviceId(0x99AA_BBCC), true);
+        assert_eq!(reply.destination, request.source);
+        assert_eq!(reply.hop_count, 2);
+        let reply_bytes = reply.convert_to_lorawan_phy_payload();
+        assert_eq!(14, reply_bytes.len());
+        let parsed_reply = parse_phy_payload(&reply_bytes).unwrap();
+        assert_eq!(
+            &reply,
+            parsed_reply.as_any().downcast_ref::<EchoReply>().unwrap()
+        );
+    }
+
+    #[test]
+    fn split_announcement_to_data_rate() {
+        let location = GpsLocation {
+            latitude: 30,
+            longitude: -1534,
+            altitude: 86432,
+        };
+        let end_device_ids: Vec<EndDeviceId> = (0..20).map(EndDeviceId).collect();
+        // 63B usable - 1B Packet type - 9B location = 53B = 13 end device IDs
+        let announcements = LocalAnnouncement::split_to_data_rate(
+            Some(location),
+            &end_device_ids,
+            DataRate::Eu863_870Dr0,
+            false,
+        );
+        assert_eq!(announcements.len(), 2);
+        assert_eq!(announcements[0].location(), Some(location));
+        assert_eq!(announcements[0].end_device_ids_ref(), &end_device_ids[..13]);
+        assert_eq!(announcements[1].location(), None);
+        // The sender starts every announcement.
+        assert_eq!(announcements[1].end_device_ids_ref()[0], end_device_ids[0]);
+        assert_eq!(
+            announcements[1].end_device_ids_ref()[1..],
+            end_device_ids[13..]
+        );
+        for announcement in announcements {
+            assert!(
+                announcement.convert_to_lorawan_phy_payload().len()
+                    <= DataRate::Eu863_870Dr0.max_usable_payload_size(false) + 1
+            );
+        }
+    }
+
+    #[test]
+    fn split_service_announcement_to_data_rate() {
+        let end_device_services: Vec<EndDeviceServices> = (0..3)
+            .map(|end_device_id| EndDeviceServices {
+                end_device_id: EndDeviceId(end_device_id),
+                services: (0..20).collect(),
+            })
+            .collect();
+        // 63B usable - 1B Packet type = 62B, 37B per entry with 16 announced services
+        let announcements = ServiceAnnouncement::split_to_data_rate(
+            &end_device_services,
+            DataRate::Eu863_870Dr0,
+            false,
+        );
+        assert_eq!(announcements.len(), 3);
+        for announcement in announcements {
+            let phy_payload = announcement.convert_to_lorawan_phy_payload();
+            assert!(phy_payload.len() <= DataRate::Eu863_870Dr0.max_usable_payload_size(false) + 1);
+            let parsed = parse_phy_payload(&phy_payload).unwrap();
+            let parsed = parsed
+                .as_any()
+                .downcast_ref::<ServiceAnnouncement>()
+                .unwrap();
+            assert_eq!(
+                parsed.end_device_services_ref()[0].services,
+                (0..16).collect::<Vec<_>>()
+            );
+        }
+    }
+
+    #[test]
+    fn end_device_id_to_endpoint_id_to_end_device_id() {
+        let end_device_id = EndDeviceId(0x1234);
+        let endpoint_id: bp7::EndpointID = end_device_id.try_into().unwrap();
+        let end_device_id_2: EndDeviceId = endpoint_id.try_into().unwrap();
+        assert_eq!(end_device_id, end_device_id_2);
+    }
+
+    #[test]
+    fn endpoint_id_to_end_device_id_to_endpoint_id() {
+        let endpoint_id = bp7::EndpointID::with_dtn("//12356").unwrap();
+
+        let end_device_id: EndDeviceId = endpoint_id.clone().try_into().unwrap();
+
+        let endpoint_id2: bp7::EndpointID = end_device_id.try_into().unwrap();
+        assert_eq!(endpoint_id, endpoint_id2);
+    }
+
+    #[test]
+    fn convert_to_hop2hop_fragments() {
+        let timestamp = DateTime::from_timestamp(Utc::now().timestamp(), 0).unwrap();
+        let packet = BundleFragment {
+            destination: EndDeviceId(0x1122_3344),
+            source: EndDeviceId(0x5566_7788),
+            timestamp,
+            is_end: false,
+            fragment_index: 10,
+            payload: vec![0xFF; 10],
+        };
+        let packet_hash = crc32fast::hash(&packet.convert_to_lorawan_phy_payload());
+
+        let hop2hop_fragments =
+            packet.convert_to_hop_2_hop_fragments(DataRate::Eu863_870Dr0, false);
+        assert_eq!(hop2hop_fragments.len(), 1);
+        assert_eq!(hop2hop_fragments.first().unwrap().packet_hash, packet_hash);
+        assert_eq!(hop2hop_fragments.first().unwrap().fragment_index, 0);
+        assert_eq!(hop2hop_fragments.first().unwrap().total_fragments, 1);
+
+        let packet = BundleFragment {
+            destination: EndDeviceId(0x1122_3344),
+            source: EndDeviceId(0x5566_7788),
+            timestamp,
+            is_end: false,
+            fragment_index: 10,
+            payload: vec![0xFF; 100],
+        };
+        let packet_hash = crc32fast::hash(&packet.convert_to_lorawan_phy_payload());
+
+        let hop2hop_fragments =
+            packet.convert_to_hop_2_hop_fragments(DataRate::Eu863_870Dr0, false);
+        assert_eq!(hop2hop_fragments.len(), 3);
+        assert_eq!(hop2hop_fragments.first().unwrap().packet_hash, packet_hash);
+        assert_eq!(hop2hop_fragments.first().unwrap().fragment_index, 0);
+        assert_eq!(hop2hop_fragments.first().unwrap().total_fragments, 3);
+    }
+
+    #[test]
+    fn repeater_compatible_complete_bundle_size() {
+        let payload_size =
+            DataRate::Eu863_870Dr5.max_usable_payload_size(false) - COMPLETE_BUNDLE_HEADERS_SIZE;
+        let create = |repeater_compatible| {
+            CompleteBundle::new(
+                EndDeviceId(0x1122_3344),
+                EndDeviceId(0x5566_7788),
+                Utc::now(),
+                &mut vec![0xFF; payload_size],
+                DataRate::Eu863_870Dr5,
+                repeater_compatible,
+            )
+        };
+        assert!(create(false).is_ok());
+        assert!(create(true).is_err());
+    }
+
+    #[test]
+    fn bp7_bundle_fragment_round_trip() {
+        let packet = Bp7Bundle::new(
+            EndDeviceId(0x1122_3344),
+            EndDeviceId(0x5566_7788),
+            Utc::now(),
+            b"world",
+            5,
+            10,
+        )
+        .unwrap();
+        let parsed = parse_phy_payload(&packet.convert_to_lorawan_phy_payload()).unwrap();
+        assert_eq!(parsed.packet_destination(), Some(EndDeviceId(0x1122_3344)));
+        let parsed = parsed.as_any().downcast_ref::<Bp7Bundle>().unwrap();
+        assert_eq!(parsed, &packet);
+
+        let bundle = parsed.bundle().unwrap();
+        assert!(bundle.primary.has_fragmentation());
+        assert_eq!(bundle.primary.fragmentation_offset, 5);
+        assert_eq!(bundle.primary.total_data_length, 10);
+        assert_eq!(bundle.payload(), Some(&b"world".to_vec()));
+    }
+}
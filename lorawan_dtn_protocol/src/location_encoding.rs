@@ -29,6 +29,10 @@ const LONG_ENCODING_VALUE: f64 = 180_f64 / 2_i32.pow(23) as f64;
 const ALT_ENCODING_VALUE: f64 = 100_f64;
 
 /// Encode a floating point latitude into a 3 byte singed value.
+///
+/// # Errors
+///
+/// Returns an error if the latitude is out of range.
 #[allow(clippy::cast_possible_truncation)]
 pub fn encode_lat(lat: f64) -> Result<i32, LocationEncodingError> {
     trace!("Encoding latitude from: {lat}");
@@ -41,12 +45,17 @@ pub fn encode_lat(lat: f64) -> Result<i32, LocationEncodingError> {
 }
 
 /// Decode a singed 3 byte encoded latitude into a floating point value.
+#[must_use]
 pub fn decode_lat(lat: i32) -> f64 {
     trace!("Decoding latitude from: {lat}");
     ((LAT_ENCODING_VALUE * f64::from(lat)) * 100_000_f64).round() / 100_000_f64
 }
 
 /// Encode a floating point longitude into a 3 byte singed value.
+///
+/// # Errors
+///
+/// Returns an error if the longitude is out of range.
 #[allow(clippy::cast_possible_truncation)]
 pub fn encode_long(long: f64) -> Result<i32, LocationEncodingError> {
     trace!("Encoding longitude from: {long}");
@@ -59,6 +68,7 @@ pub fn encode_long(long: f64) -> Result<i32, LocationEncodingError> {
 }
 
 /// Decode a singed 3 byte encoded longitude into a floating point value.
+#[must_use]
 pub fn decode_long(long: i32) -> f64 {
     trace!("Decoding longitude from: {long}");
     ((LONG_ENCODING_VALUE * f64::from(long)) * 100_000_f64).round() / 100_000_f64
@@ -66,6 +76,10 @@ pub fn decode_long(long: i32) -> f64 {
 
 /// Limit altitude to a max of 41943.00 as 24 bit 2 complement can only hold values between
 /// 8388607 and -8388607. Precision two decimal values (e.g. 4022.53).
+///
+/// # Errors
+///
+/// Returns an error if the altitude is out of range.
 #[allow(clippy::cast_possible_truncation)]
 pub fn encode_alt(alt: f64) -> Result<i32, LocationEncodingError> {
     trace!("Encoding altitude from: {alt}");
@@ -78,6 +92,7 @@ pub fn encode_alt(alt: f64) -> Result<i32, LocationEncodingError> {
 }
 
 /// Decode a singed 3 byte encoded altitude into a floating point value.
+#[must_use]
 pub fn decode_alt(alt: i32) -> f64 {
     trace!("Decoding altitude from: {alt}");
     f64::from(alt) / ALT_ENCODING_VALUE
@@ -87,7 +102,7 @@ pub fn decode_alt(alt: i32) -> f64 {
 #[cfg(test)]
 mod tests {
     use crate::error::LocationEncodingError;
-    use crate::lorawan_protocol::location_encoding::{
+    use crate::location_encoding::{
         decode_alt, decode_lat, decode_long, encode_alt, encode_lat, encode_long,
     };
 
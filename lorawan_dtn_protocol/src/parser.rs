@@ -2,14 +2,18 @@
 
 use crate::end_device_id::EndDeviceId;
 use crate::error::{IResult, ProtocolParserError};
-use crate::lorawan_protocol::{
-    BundleFragment, CompleteBundle, FragmentedBundleFragment, FragmentedBundleFragmentEnd,
-    GpsLocation, Hop2HopFragment, LoRaWanPacket, LocalAnnouncement, PacketType,
+use crate::{
+    Bp7Bundle, BundleFragment, BundleResendRequest, CapabilityAnnouncement,
+    ChannelPlanAnnouncement, CompleteBundle, DataRateAnnouncement, EchoReply, EchoRequest,
+    EndDeviceServices, FragmentedBundleFragment, FragmentedBundleFragmentEnd, GpsLocation,
+    Hop2HopFragment, HopAck, LoRaWanPacket, LocalAnnouncement, NetworkId, PacketType,
+    ReachabilityAnnouncement, ReachableEndDeviceId, ServiceAnnouncement, StatusReport,
+    StatusReportRequest, NETWORK_ID_FLAG,
 };
 use chrono::{DateTime, Utc};
 use nom::branch::alt;
 use nom::combinator::{map, map_res, value};
-use nom::multi::many1;
+use nom::multi::{count, many1};
 use nom::sequence::tuple;
 use nom::Err::Failure;
 use nom::Finish;
@@ -42,6 +46,8 @@ fn parse_mac_header(input: &[u8]) -> IResult<&[u8], bool> {
 }
 
 /// Parses a packet type.
+// One branch per packet type, like `PacketType::layout`.
+#[allow(clippy::too_many_lines)]
 fn parse_packet_type(input: &[u8]) -> IResult<&[u8], PacketType> {
     trace!("Parsing packet type");
     let complete_bundle_tag = nom::bits::complete::tag::<_, _, _, ProtocolParserError>(
@@ -72,6 +78,52 @@ fn parse_packet_type(input: &[u8]) -> IResult<&[u8], PacketType> {
         PacketType::LocalAnnouncement as u8,
         8_usize,
     );
+    let reachability_announcement_tag = nom::bits::complete::tag::<_, _, _, ProtocolParserError>(
+        PacketType::ReachabilityAnnouncement as u8,
+        8_usize,
+    );
+    let echo_request_tag = nom::bits::complete::tag::<_, _, _, ProtocolParserError>(
+        PacketType::EchoRequest as u8,
+        8_usize,
+    );
+    let echo_reply_tag = nom::bits::complete::tag::<_, _, _, ProtocolParserError>(
+        PacketType::EchoReply as u8,
+        8_usize,
+    );
+    let service_announcement_tag = nom::bits::complete::tag::<_, _, _, ProtocolParserError>(
+        PacketType::ServiceAnnouncement as u8,
+        8_usize,
+    );
+    let channel_plan_announcement_tag = nom::bits::complete::tag::<_, _, _, ProtocolParserError>(
+        PacketType::ChannelPlanAnnouncement as u8,
+        8_usize,
+    );
+    let hop_ack_tag =
+        nom::bits::complete::tag::<_, _, _, ProtocolParserError>(PacketType::HopAck as u8, 8_usize);
+    let bp7_bundle_tag = nom::bits::complete::tag::<_, _, _, ProtocolParserError>(
+        PacketType::Bp7Bundle as u8,
+        8_usize,
+    );
+    let capability_announcement_tag = nom::bits::complete::tag::<_, _, _, ProtocolParserError>(
+        PacketType::CapabilityAnnouncement as u8,
+        8_usize,
+    );
+    let status_report_request_tag = nom::bits::complete::tag::<_, _, _, ProtocolParserError>(
+        PacketType::StatusReportRequest as u8,
+        8_usize,
+    );
+    let status_report_tag = nom::bits::complete::tag::<_, _, _, ProtocolParserError>(
+        PacketType::StatusReport as u8,
+        8_usize,
+    );
+    let data_rate_announcement_tag = nom::bits::complete::tag::<_, _, _, ProtocolParserError>(
+        PacketType::DataRateAnnouncement as u8,
+        8_usize,
+    );
+    let bundle_resend_request_tag = nom::bits::complete::tag::<_, _, _, ProtocolParserError>(
+        PacketType::BundleResendRequest as u8,
+        8_usize,
+    );
 
     nom::bits::bits::<_, _, _, _, _>(alt((
         value(PacketType::CompleteBundle, complete_bundle_tag),
@@ -87,6 +139,27 @@ fn parse_packet_type(input: &[u8]) -> IResult<&[u8], PacketType> {
         ),
         value(PacketType::Hop2HopFragment, hop_2_hop_fragment_tag),
         value(PacketType::LocalAnnouncement, local_announcement_tag),
+        value(
+            PacketType::ReachabilityAnnouncement,
+            reachability_announcement_tag,
+        ),
+        value(PacketType::EchoRequest, echo_request_tag),
+        value(PacketType::EchoReply, echo_reply_tag),
+        value(PacketType::ServiceAnnouncement, service_announcement_tag),
+        value(
+            PacketType::ChannelPlanAnnouncement,
+            channel_plan_announcement_tag,
+        ),
+        value(PacketType::HopAck, hop_ack_tag),
+        value(PacketType::Bp7Bundle, bp7_bundle_tag),
+        value(
+            PacketType::CapabilityAnnouncement,
+            capability_announcement_tag,
+        ),
+        value(PacketType::StatusReportRequest, status_report_request_tag),
+        value(PacketType::StatusReport, status_report_tag),
+        value(PacketType::DataRateAnnouncement, data_rate_announcement_tag),
+        value(PacketType::BundleResendRequest, bundle_resend_request_tag),
     )))(input)
     .map_err(|_: nom::Err<_>| Failure(ProtocolParserError::UnknownPacketType))
 }
@@ -110,10 +183,7 @@ fn parse_timestamp(input: &[u8]) -> nom::IResult<&[u8], DateTime<Utc>, ProtocolP
                 .expect("We take four bytes with nom, this conversion will not fail."),
         );
         let unix_timestamp = i64::from(unix_timestamp);
-        let Some(naive_time) = chrono::naive::NaiveDateTime::from_timestamp_opt(unix_timestamp, 0) else{
-            return Err(ProtocolParserError::FromTimestampError);
-        };
-        Ok(DateTime::from_utc(naive_time, Utc))
+        DateTime::from_timestamp(unix_timestamp, 0).ok_or(ProtocolParserError::FromTimestampError)
     })(input)
 }
 
@@ -152,12 +222,58 @@ fn convert_3_bytes_to_i32(input: &[u8]) -> Result<i32, ProtocolParserError> {
     Ok(value)
 }
 
+/// Parses a single byte.
+fn parse_u8(input: &[u8]) -> IResult<&[u8], u8> {
+    nom::number::complete::u8(input)
+}
+
+/// Parses a little endian u16 from 2 bytes.
+fn parse_u16(input: &[u8]) -> IResult<&[u8], u16> {
+    nom::number::complete::le_u16(input)
+}
+
+/// Parses a little endian u32 from 4 bytes.
+fn parse_u32(input: &[u8]) -> IResult<&[u8], u32> {
+    nom::number::complete::le_u32(input)
+}
+
 /// Parses one or more end device IDs.
 fn parse_multiple_end_device_ids(input: &[u8]) -> IResult<&[u8], Vec<EndDeviceId>> {
     trace!("Parsing multiple end device IDs");
     many1(parse_end_device_id)(input)
 }
 
+/// Parses an end device ID followed by its hop distance and next hop.
+fn parse_reachable_end_device_id(input: &[u8]) -> IResult<&[u8], ReachableEndDeviceId> {
+    trace!("Parsing reachable end device ID");
+    let (input, end_device_id) = parse_end_device_id(input)?;
+    let (input, hop_distance) = parse_u8(input)?;
+    let (input, next_hop) = parse_end_device_id(input)?;
+    Ok((
+        input,
+        ReachableEndDeviceId {
+            end_device_id,
+            hop_distance,
+            next_hop,
+        },
+    ))
+}
+
+/// Parses an end device ID followed by the amount of its services and the service tags.
+fn parse_end_device_services(input: &[u8]) -> IResult<&[u8], EndDeviceServices> {
+    trace!("Parsing end device services");
+    let (input, end_device_id) = parse_end_device_id(input)?;
+    let (input, service_amount) = parse_u8(input)?;
+    let (input, services) = count(parse_u16, usize::from(service_amount))(input)?;
+    Ok((
+        input,
+        EndDeviceServices {
+            end_device_id,
+            services,
+        },
+    ))
+}
+
 /// Parses bytes into a  [`CompleteBundle`].
 ///
 /// # Errors
@@ -314,7 +430,7 @@ fn parse_hop_2_hop_fragment(input: &[u8]) -> Result<Hop2HopFragment, ProtocolPar
 /// Returns an error if any header cannot be parsed.
 fn parse_local_announcement(input: &[u8]) -> Result<LocalAnnouncement, ProtocolParserError> {
     trace!("Parsing local announcment");
-    let (input, location) = if input.len() % 2 == 0 {
+    let (input, location) = if input.len().is_multiple_of(2) {
         (input, None)
     } else {
         parse_location(input).finish()?
@@ -326,17 +442,243 @@ fn parse_local_announcement(input: &[u8]) -> Result<LocalAnnouncement, ProtocolP
     })
 }
 
-/// Parses the phy payload of a LoRaWAN frame.
-#[instrument(skip_all)]
+/// Parses bytes into a [`ReachabilityAnnouncement`].
+///
+/// # Errors
+///
+/// Returns an error if any entry cannot be parsed.
+fn parse_reachability_announcement(
+    input: &[u8],
+) -> Result<ReachabilityAnnouncement, ProtocolParserError> {
+    trace!("Parsing reachability announcement");
+    let (input, sender) = parse_end_device_id(input).finish()?;
+    let (_, reachable_end_device_ids) = many1(parse_reachable_end_device_id)(input).finish()?;
+    Ok(ReachabilityAnnouncement::new(
+        sender,
+        reachable_end_device_ids,
+    ))
+}
+
+/// Parses bytes into a [`ServiceAnnouncement`].
+///
+/// # Errors
+///
+/// Returns an error if any entry cannot be parsed.
+fn parse_service_announcement(input: &[u8]) -> Result<ServiceAnnouncement, ProtocolParserError> {
+    trace!("Parsing service announcement");
+    let (_, end_device_services) = many1(parse_end_device_services)(input).finish()?;
+    Ok(ServiceAnnouncement::new(end_device_services))
+}
+
+/// Parses bytes into a [`ChannelPlanAnnouncement`].
+///
+/// # Errors
+///
+/// Returns an error if the end device ID or any frequency cannot be parsed.
+fn parse_channel_plan_announcement(
+    input: &[u8],
+) -> Result<ChannelPlanAnnouncement, ProtocolParserError> {
+    trace!("Parsing channel plan announcement");
+    let (input, end_device_id) = parse_end_device_id(input).finish()?;
+    let (_, frequencies) = many1(parse_u32)(input).finish()?;
+    Ok(ChannelPlanAnnouncement::new(end_device_id, frequencies))
+}
+
+/// Parses bytes into an [`EchoRequest`].
+///
+/// # Errors
+///
+/// Returns an error if any header cannot be parsed.
+fn parse_echo_request(input: &[u8]) -> Result<EchoRequest, ProtocolParserError> {
+    trace!("Parsing echo request");
+    let (input, destination) = parse_end_device_id(input).finish()?;
+    let (input, source) = parse_end_device_id(input).finish()?;
+    let (input, sequence) = parse_u16(input).finish()?;
+    let (input, hop_count) = parse_u8(input).finish()?;
+    let (_, hop_limit) = parse_u8(input).finish()?;
+    Ok(EchoRequest {
+        destination,
+        source,
+        sequence,
+        hop_count,
+        hop_limit,
+    })
+}
+
+/// Parses bytes into an [`EchoReply`].
+///
+/// # Errors
+///
+/// Returns an error if any header cannot be parsed.
+fn parse_echo_reply(input: &[u8]) -> Result<EchoReply, ProtocolParserError> {
+    trace!("Parsing echo reply");
+    let (input, destination) = parse_end_device_id(input).finish()?;
+    let (input, source) = parse_end_device_id(input).finish()?;
+    let (input, sequence) = parse_u16(input).finish()?;
+    let (input, hop_count) = parse_u8(input).finish()?;
+    let (_, hop_limit_reached) = parse_u8(input).finish()?;
+    Ok(EchoReply {
+        destination,
+        source,
+        sequence,
+        hop_count,
+        hop_limit_reached: hop_limit_reached != 0,
+    })
+}
+
+/// Parses bytes into a [`HopAck`].
+///
+/// # Errors
+///
+/// Returns an error if any header cannot be parsed.
+fn parse_hop_ack(input: &[u8]) -> Result<HopAck, ProtocolParserError> {
+    trace!("Parsing hop acknowledgement");
+    let (input, source) = parse_end_device_id(input).finish()?;
+    let (_, packet_hash) = parse_u32(input).finish()?;
+    Ok(HopAck {
+        source,
+        packet_hash,
+    })
+}
+
+/// Parses bytes into a [`Bp7Bundle`].
+///
+/// # Errors
+///
+/// Returns an error if the bytes are no CBOR encoded bundle addressed to and from end device IDs.
+fn parse_bp7_bundle(input: &[u8]) -> Result<Bp7Bundle, ProtocolParserError> {
+    trace!("Parsing BP7 bundle");
+    Bp7Bundle::from_cbor(input.to_vec())
+}
+
+/// Parses bytes into a [`CapabilityAnnouncement`].
+///
+/// # Errors
+///
+/// Returns an error if the capabilities or any end device ID cannot be parsed.
+fn parse_capability_announcement(
+    input: &[u8],
+) -> Result<CapabilityAnnouncement, ProtocolParserError> {
+    trace!("Parsing capability announcement");
+    let (input, capabilities) = parse_u16(input).finish()?;
+    let (_, end_device_ids) = parse_multiple_end_device_ids(input).finish()?;
+    Ok(CapabilityAnnouncement::new(capabilities, end_device_ids))
+}
+
+/// Parses bytes into a [`StatusReportRequest`].
+///
+/// # Errors
+///
+/// Returns an error if any header cannot be parsed.
+fn parse_status_report_request(input: &[u8]) -> Result<StatusReportRequest, ProtocolParserError> {
+    trace!("Parsing status report request");
+    let (input, destination) = parse_end_device_id(input).finish()?;
+    let (input, source) = parse_end_device_id(input).finish()?;
+    let (input, timestamp) = parse_timestamp(input).finish()?;
+    let (input, report_to) = parse_end_device_id(input).finish()?;
+    let (_, requested_reports) = parse_u8(input).finish()?;
+    Ok(StatusReportRequest {
+        destination,
+        source,
+        timestamp,
+        report_to,
+        requested_reports,
+    })
+}
+
+/// Parses bytes into a [`StatusReport`].
+///
+/// # Errors
+///
+/// Returns an error if any header cannot be parsed.
+fn parse_status_report(input: &[u8]) -> Result<StatusReport, ProtocolParserError> {
+    trace!("Parsing status report");
+    let (input, destination) = parse_end_device_id(input).finish()?;
+    let (input, source) = parse_end_device_id(input).finish()?;
+    let (input, status) = parse_u8(input).finish()?;
+    let (input, reason) = parse_u8(input).finish()?;
+    let (input, bundle_source) = parse_end_device_id(input).finish()?;
+    let (_, bundle_timestamp) = parse_timestamp(input).finish()?;
+    Ok(StatusReport {
+        destination,
+        source,
+        status,
+        reason,
+        bundle_source,
+        bundle_timestamp,
+    })
+}
+
+/// Parses bytes into a [`DataRateAnnouncement`].
+///
+/// # Errors
+///
+/// Returns an error if the data rates or the end device IDs cannot be parsed.
+fn parse_data_rate_announcement(input: &[u8]) -> Result<DataRateAnnouncement, ProtocolParserError> {
+    trace!("Parsing data rate announcement");
+    let (input, data_rates) = parse_u8(input).finish()?;
+    let (_, end_device_ids) = parse_multiple_end_device_ids(input).finish()?;
+    Ok(DataRateAnnouncement::new(data_rates, end_device_ids))
+}
+
+/// Parses bytes into a [`BundleResendRequest`].
+///
+/// # Errors
+///
+/// Returns an error if any header cannot be parsed.
+fn parse_bundle_resend_request(input: &[u8]) -> Result<BundleResendRequest, ProtocolParserError> {
+    trace!("Parsing bundle resend request");
+    let (input, destination) = parse_end_device_id(input).finish()?;
+    let (input, source) = parse_end_device_id(input).finish()?;
+    let (_, timestamp) = parse_timestamp(input).finish()?;
+    Ok(BundleResendRequest {
+        destination,
+        source,
+        timestamp,
+    })
+}
+
+/// Parses the network ID following the MHDR if the [`NETWORK_ID_FLAG`] is set in the MHDR.
+fn parse_network_id(mhdr: u8, input: &[u8]) -> IResult<&[u8], Option<NetworkId>> {
+    if mhdr & NETWORK_ID_FLAG == 0 {
+        return Ok((input, None));
+    }
+    trace!("Parsing network ID");
+    map(parse_u32, Some)(input)
+}
+
+/// Parses the phy payload of a LoRaWAN frame, a network ID prefix is stripped.
+///
+/// # Errors
+///
+/// Returns an error if the phy payload is no valid packet of the protocol.
 pub fn parse_phy_payload(input: &[u8]) -> Result<Box<dyn LoRaWanPacket>, ProtocolParserError> {
+    parse_phy_payload_with_network_id(input).map(|(_, packet)| packet)
+}
+
+/// Parses the phy payload of a LoRaWAN frame and returns the network ID prefix if present.
+///
+/// # Errors
+///
+/// Returns an error if the phy payload is no valid packet of the protocol.
+#[instrument(skip_all)]
+pub fn parse_phy_payload_with_network_id(
+    input: &[u8],
+) -> Result<(Option<NetworkId>, Box<dyn LoRaWanPacket>), ProtocolParserError> {
     trace!("Entering phy payload parsing");
+    let mhdr = input.first().copied().unwrap_or_default();
     let (input, _) = parse_mac_header(input).finish()?;
-    parse_packet(input)
+    let (input, network_id) = parse_network_id(mhdr, input).finish()?;
+    Ok((network_id, parse_packet(input)?))
 }
 
 /// Parses packet data.
 ///
 /// Used to parse reassembled Hop2Hop packets.
+///
+/// # Errors
+///
+/// Returns an error if the packet data is no valid packet of the protocol.
 pub fn parse_packet(input: &[u8]) -> Result<Box<dyn LoRaWanPacket>, ProtocolParserError> {
     let (input, packet_type_helper) = parse_packet_type(input).finish()?;
     match packet_type_helper {
@@ -351,6 +693,22 @@ pub fn parse_packet(input: &[u8]) -> Result<Box<dyn LoRaWanPacket>, ProtocolPars
         }
         PacketType::Hop2HopFragment => Ok(Box::new(parse_hop_2_hop_fragment(input)?)),
         PacketType::LocalAnnouncement => Ok(Box::new(parse_local_announcement(input)?)),
+        PacketType::ReachabilityAnnouncement => {
+            Ok(Box::new(parse_reachability_announcement(input)?))
+        }
+        PacketType::EchoRequest => Ok(Box::new(parse_echo_request(input)?)),
+        PacketType::EchoReply => Ok(Box::new(parse_echo_reply(input)?)),
+        PacketType::ServiceAnnouncement => Ok(Box::new(parse_service_announcement(input)?)),
+        PacketType::ChannelPlanAnnouncement => {
+            Ok(Box::new(parse_channel_plan_announcement(input)?))
+        }
+        PacketType::HopAck => Ok(Box::new(parse_hop_ack(input)?)),
+        PacketType::Bp7Bundle => Ok(Box::new(parse_bp7_bundle(input)?)),
+        PacketType::CapabilityAnnouncement => Ok(Box::new(parse_capability_announcement(input)?)),
+        PacketType::StatusReportRequest => Ok(Box::new(parse_status_report_request(input)?)),
+        PacketType::StatusReport => Ok(Box::new(parse_status_report(input)?)),
+        PacketType::DataRateAnnouncement => Ok(Box::new(parse_data_rate_announcement(input)?)),
+        PacketType::BundleResendRequest => Ok(Box::new(parse_bundle_resend_request(input)?)),
     }
 }
 
@@ -359,12 +717,13 @@ pub fn parse_packet(input: &[u8]) -> Result<Box<dyn LoRaWanPacket>, ProtocolPars
 mod tests {
     use crate::end_device_id::EndDeviceId;
     use crate::error::ProtocolParserError;
-    use crate::lorawan_protocol::parser::{
+    use crate::parser::{
         parse_complete_bundle, parse_end_device_id, parse_local_announcement, parse_location,
-        parse_mac_header, parse_packet_type, parse_timestamp, PacketType,
+        parse_mac_header, parse_packet_type, parse_phy_payload, parse_phy_payload_with_network_id,
+        parse_timestamp, PacketType,
     };
-    use crate::lorawan_protocol::{CompleteBundle, GpsLocation, LocalAnnouncement};
-    use chrono::{DateTime, NaiveDateTime, Utc};
+    use crate::{CompleteBundle, GpsLocation, LoRaWanPacket, LocalAnnouncement, NETWORK_ID_FLAG};
+    use chrono::{DateTime, Utc};
 
     #[test]
     fn parse_proprietary_success() {
@@ -430,6 +789,50 @@ mod tests {
         let packet_type = [0b0000_0110u8];
         let (_, result) = parse_packet_type(&packet_type).unwrap();
         assert_eq!(PacketType::LocalAnnouncement, result);
+
+        let packet_type = [0b0000_0111u8];
+        let (_, result) = parse_packet_type(&packet_type).unwrap();
+        assert_eq!(PacketType::ReachabilityAnnouncement, result);
+
+        let packet_type = [0b0000_1001u8];
+        let (_, result) = parse_packet_type(&packet_type).unwrap();
+        assert_eq!(PacketType::EchoRequest, result);
+
+        let packet_type = [0b0000_1010u8];
+        let (_, result) = parse_packet_type(&packet_type).unwrap();
+        assert_eq!(PacketType::EchoReply, result);
+
+        let packet_type = [0b0000_1100u8];
+        let (_, result) = parse_packet_type(&packet_type).unwrap();
+        assert_eq!(PacketType::ChannelPlanAnnouncement, result);
+
+        let packet_type = [0b0000_1101u8];
+        let (_, result) = parse_packet_type(&packet_type).unwrap();
+        assert_eq!(PacketType::HopAck, result);
+
+        let packet_type = [0b0000_1110u8];
+        let (_, result) = parse_packet_type(&packet_type).unwrap();
+        assert_eq!(PacketType::Bp7Bundle, result);
+
+        let packet_type = [0b0000_1111u8];
+        let (_, result) = parse_packet_type(&packet_type).unwrap();
+        assert_eq!(PacketType::CapabilityAnnouncement, result);
+
+        let packet_type = [0b0001_0000u8];
+        let (_, result) = parse_packet_type(&packet_type).unwrap();
+        assert_eq!(PacketType::StatusReportRequest, result);
+
+        let packet_type = [0b0001_0001u8];
+        let (_, result) = parse_packet_type(&packet_type).unwrap();
+        assert_eq!(PacketType::StatusReport, result);
+
+        let packet_type = [0b0001_0010u8];
+        let (_, result) = parse_packet_type(&packet_type).unwrap();
+        assert_eq!(PacketType::DataRateAnnouncement, result);
+
+        let packet_type = [0b0001_0011u8];
+        let (_, result) = parse_packet_type(&packet_type).unwrap();
+        assert_eq!(PacketType::BundleResendRequest, result);
     }
 
     #[test]
@@ -444,12 +847,12 @@ mod tests {
             Err(nom::Err::Failure(ProtocolParserError::UnknownPacketType)),
             parse_packet_type(&packet_type)
         );
-        let packet_type = [0b0000_1100_u8];
+        let packet_type = [0b0001_0100_u8];
         assert_eq!(
             Err(nom::Err::Failure(ProtocolParserError::UnknownPacketType)),
             parse_packet_type(&packet_type)
         );
-        let packet_type = [0b0000_1101_u8];
+        let packet_type = [0b1000_0000_u8];
         assert_eq!(
             Err(nom::Err::Failure(ProtocolParserError::UnknownPacketType)),
             parse_packet_type(&packet_type)
@@ -534,10 +937,7 @@ mod tests {
         let expected_bundle = CompleteBundle {
             destination: EndDeviceId(0x7856_3412),
             source: EndDeviceId(0x1234_5678),
-            timestamp: DateTime::from_utc(
-                NaiveDateTime::from_timestamp_opt(now.timestamp(), 0).unwrap(),
-                Utc,
-            ),
+            timestamp: DateTime::from_timestamp(now.timestamp(), 0).unwrap(),
             payload: vec![0xFF; 10],
         };
         assert_eq!(expected_bundle, parsed_bundle);
@@ -581,4 +981,26 @@ mod tests {
         };
         assert_eq!(expected_announcement, parse_announcement);
     }
+
+    #[test]
+    fn parse_network_id_prefix() {
+        let announcement = LocalAnnouncement::new(None, vec![EndDeviceId(0x1122_3344)]);
+        let untagged = announcement.convert_to_lorawan_phy_payload();
+        let (network_id, _) = parse_phy_payload_with_network_id(&untagged).unwrap();
+        assert_eq!(network_id, None);
+
+        let mut tagged = vec![untagged[0] | NETWORK_ID_FLAG, 0x04, 0x03, 0x02, 0x01];
+        tagged.extend_from_slice(&untagged[1..]);
+        let (network_id, parsed) = parse_phy_payload_with_network_id(&tagged).unwrap();
+        assert_eq!(network_id, Some(0x0102_0304));
+        assert_eq!(
+            parsed.as_any().downcast_ref::<LocalAnnouncement>(),
+            Some(&announcement)
+        );
+        assert!(parse_phy_payload(&tagged).is_ok());
+        assert_eq!(
+            parse_phy_payload(&tagged[..3]).unwrap_err(),
+            ProtocolParserError::Nom(nom::error::ErrorKind::Eof)
+        );
+    }
 }
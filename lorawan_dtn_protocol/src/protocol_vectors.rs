@@ -0,0 +1,443 @@
+//! Conformance tests against the golden phy payloads in `tests/protocol_vectors`, keeping the
+//! wire format stable across refactors and independent implementations.
+
+use crate::end_device_id::EndDeviceId;
+use crate::error::ProtocolParserError;
+use crate::parser::parse_phy_payload;
+use crate::{
+    BundleFragment, BundleResendRequest, CapabilityAnnouncement, ChannelPlanAnnouncement,
+    CompleteBundle, DataRateAnnouncement, EchoReply, EchoRequest, EndDeviceServices, FieldKind,
+    FragmentedBundleFragment, FragmentedBundleFragmentEnd, GpsLocation, Hop2HopFragment, HopAck,
+    LoRaWanPacket, LocalAnnouncement, PacketType, ReachabilityAnnouncement, ReachableEndDeviceId,
+    ServiceAnnouncement, StatusReport, StatusReportRequest, CAPABILITY_BP7_CBOR,
+    COMPLETE_BUNDLE_HEADERS_SIZE, STATUS_DELETED, STATUS_DELIVERED, STATUS_RECEIVED,
+};
+use chrono::{DateTime, Utc};
+use lorawan_parameters::DataRate;
+use std::path::{Path, PathBuf};
+
+/// Destination used by the vectors.
+const DESTINATION: EndDeviceId = EndDeviceId(0x1122_3344);
+/// Source used by the vectors.
+const SOURCE: EndDeviceId = EndDeviceId(0x5566_7788);
+
+/// Timestamp used by the vectors.
+fn timestamp() -> DateTime<Utc> {
+    DateTime::from_timestamp(1_700_000_000, 0).unwrap()
+}
+
+/// Directory containing the vectors.
+fn vectors_directory() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/protocol_vectors")
+}
+
+/// Loads the phy payload of the vector, ignoring whitespace and `#` comments.
+fn load_vector(name: &str) -> Vec<u8> {
+    let path = vectors_directory().join(format!("{name}.hex"));
+    let content = std::fs::read_to_string(&path)
+        .unwrap_or_else(|err| panic!("Failed to read {}: {err}", path.display()));
+    let hex: String = content
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default())
+        .flat_map(str::split_whitespace)
+        .collect();
+    hex::decode(hex).unwrap()
+}
+
+/// Asserts that the packet is encoded into the vector and the vector is parsed into the packet.
+fn assert_conforms<T: LoRaWanPacket + PartialEq + 'static>(name: &str, packet: &T) {
+    let vector = load_vector(name);
+    assert_eq!(
+        packet.convert_to_lorawan_phy_payload(),
+        vector,
+        "Encoding of {name} differs"
+    );
+    let parsed = parse_phy_payload(&vector).unwrap();
+    assert_eq!(
+        parsed.as_any().downcast_ref::<T>(),
+        Some(packet),
+        "Parsing of {name} differs"
+    );
+}
+
+/// Returns `len` bytes counting up from zero.
+fn counting_payload(len: usize) -> Vec<u8> {
+    (0..=u8::MAX).cycle().take(len).collect()
+}
+
+#[test]
+fn complete_bundle() {
+    assert_conforms(
+        "complete_bundle",
+        &CompleteBundle {
+            destination: DESTINATION,
+            source: SOURCE,
+            timestamp: timestamp(),
+            payload: b"hello".to_vec(),
+        },
+    );
+}
+
+#[test]
+fn complete_bundle_max_payload_per_data_rate() {
+    for (name, data_rate, repeater_compatible) in [
+        ("complete_bundle_max_dr0", DataRate::Eu863_870Dr0, false),
+        ("complete_bundle_max_dr3", DataRate::Eu863_870Dr3, false),
+        ("complete_bundle_max_dr5", DataRate::Eu863_870Dr5, false),
+        (
+            "complete_bundle_max_dr5_repeater",
+            DataRate::Eu863_870Dr5,
+            true,
+        ),
+    ] {
+        let max_payload_size =
+            data_rate.max_usable_payload_size(repeater_compatible) - COMPLETE_BUNDLE_HEADERS_SIZE;
+        assert!(CompleteBundle::new(
+            DESTINATION,
+            SOURCE,
+            timestamp(),
+            &mut counting_payload(max_payload_size + 1),
+            data_rate,
+            repeater_compatible,
+        )
+        .is_err());
+        let packet = CompleteBundle::new(
+            DESTINATION,
+            SOURCE,
+            timestamp(),
+            &mut counting_payload(max_payload_size),
+            data_rate,
+            repeater_compatible,
+        )
+        .unwrap();
+        assert_conforms(name, &packet);
+    }
+}
+
+#[test]
+fn bundle_fragments() {
+    let mut payload = counting_payload(60);
+    let packet = BundleFragment::new(
+        DESTINATION,
+        SOURCE,
+        timestamp(),
+        false,
+        0,
+        &mut payload,
+        DataRate::Eu863_870Dr0,
+        false,
+    )
+    .unwrap();
+    assert_conforms("bundle_fragment", &packet);
+    assert_eq!(payload.len(), 10);
+
+    assert_conforms(
+        "bundle_fragment_end",
+        &BundleFragment {
+            destination: DESTINATION,
+            source: SOURCE,
+            timestamp: timestamp(),
+            is_end: true,
+            fragment_index: 3,
+            payload: vec![0xDE, 0xAD, 0xBE, 0xEF],
+        },
+    );
+}
+
+#[test]
+fn fragmented_bundle_fragments() {
+    assert_conforms(
+        "fragmented_bundle_fragment",
+        &FragmentedBundleFragment {
+            destination: DESTINATION,
+            source: SOURCE,
+            timestamp: timestamp(),
+            fragment_index: 1,
+            bundle_fragment_offset_hash: 0xCAFE_BABE,
+            payload: vec![0x01, 0x02, 0x03],
+        },
+    );
+    assert_conforms(
+        "fragmented_bundle_fragment_end",
+        &FragmentedBundleFragmentEnd {
+            destination: DESTINATION,
+            source: SOURCE,
+            timestamp: timestamp(),
+            fragment_index: 2,
+            bundle_fragment_offset: 4096,
+            bundle_total_application_data_unit_length: 65_536,
+            payload: vec![0x0A, 0x0B],
+        },
+    );
+}
+
+#[test]
+fn hop_2_hop_fragment() {
+    assert_conforms(
+        "hop_2_hop_fragment",
+        &Hop2HopFragment {
+            packet_hash: 0x0102_0304,
+            total_fragments: 3,
+            fragment_index: 1,
+            payload: vec![0xAA, 0xBB, 0xCC],
+        },
+    );
+}
+
+#[test]
+fn hop_2_hop_fragment_boundaries() {
+    // 57 bytes of the phy payload fit into a hop 2 hop fragment at DR0, the complete bundle
+    // headers take 14 bytes.
+    for (payload_size, names) in [
+        (43, vec!["hop_2_hop_boundary_single"]),
+        (
+            44,
+            vec!["hop_2_hop_boundary_split_0", "hop_2_hop_boundary_split_1"],
+        ),
+    ] {
+        let packet = CompleteBundle {
+            destination: DESTINATION,
+            source: SOURCE,
+            timestamp: timestamp(),
+            payload: vec![0xFF; payload_size],
+        };
+        let fragments = packet.convert_to_hop_2_hop_fragments(DataRate::Eu863_870Dr0, false);
+        assert_eq!(fragments.len(), names.len());
+        for (fragment, name) in fragments.iter().zip(names) {
+            assert_conforms(name, fragment);
+        }
+    }
+}
+
+#[test]
+fn local_announcements() {
+    assert_conforms(
+        "local_announcement",
+        &LocalAnnouncement::new(None, vec![DESTINATION, SOURCE]),
+    );
+    let location = GpsLocation::new(-33.8688, -151.2093, -12.5).unwrap();
+    assert_eq!(
+        location,
+        GpsLocation {
+            latitude: -3_156_801,
+            longitude: -7_046_864,
+            altitude: -1250,
+        }
+    );
+    assert_conforms(
+        "local_announcement_negative_coordinates",
+        &LocalAnnouncement::new(Some(location), vec![DESTINATION]),
+    );
+}
+
+#[test]
+fn reachability_announcement() {
+    assert_conforms(
+        "reachability_announcement",
+        &ReachabilityAnnouncement::new(
+            SOURCE,
+            vec![
+                ReachableEndDeviceId {
+                    end_device_id: DESTINATION,
+                    hop_distance: 1,
+                    next_hop: DESTINATION,
+                },
+                ReachableEndDeviceId {
+                    end_device_id: EndDeviceId(0x99AA_BBCC),
+                    hop_distance: 3,
+                    next_hop: DESTINATION,
+                },
+            ],
+        ),
+    );
+}
+
+#[test]
+fn service_announcement() {
+    assert_conforms(
+        "service_announcement",
+        &ServiceAnnouncement::new(vec![
+            EndDeviceServices {
+                end_device_id: DESTINATION,
+                services: vec![80, 8080],
+            },
+            EndDeviceServices {
+                end_device_id: SOURCE,
+                services: vec![1],
+            },
+        ]),
+    );
+}
+
+#[test]
+fn channel_plan_announcement() {
+    assert_conforms(
+        "channel_plan_announcement",
+        &ChannelPlanAnnouncement::new(SOURCE, vec![868_100_000, 868_300_000, 868_500_000]),
+    );
+}
+
+#[test]
+fn data_rate_announcement() {
+    assert_conforms(
+        "data_rate_announcement",
+        &DataRateAnnouncement::new(0b0111_1111, vec![SOURCE, DESTINATION]),
+    );
+}
+
+#[test]
+fn echo_packets() {
+    let request = EchoRequest {
+        destination: DESTINATION,
+        source: SOURCE,
+        sequence: 0x0102,
+        hop_count: 1,
+        hop_limit: 4,
+    };
+    assert_conforms("echo_request", &request);
+    assert_conforms(
+        "echo_reply",
+        &EchoReply {
+            destination: SOURCE,
+            source: EndDeviceId(0x99AA_BBCC),
+            sequence: 0x0102,
+            hop_count: 4,
+            hop_limit_reached: true,
+        },
+    );
+}
+
+#[test]
+fn hop_ack() {
+    assert_conforms(
+        "hop_ack",
+        &HopAck {
+            source: DESTINATION,
+            packet_hash: 0x0A0B_0C0D,
+        },
+    );
+}
+
+#[test]
+fn capability_announcement() {
+    assert_conforms(
+        "capability_announcement",
+        &CapabilityAnnouncement::new(CAPABILITY_BP7_CBOR, vec![SOURCE, DESTINATION]),
+    );
+}
+
+#[test]
+fn status_report_request() {
+    assert_conforms(
+        "status_report_request",
+        &StatusReportRequest {
+            destination: DESTINATION,
+            source: SOURCE,
+            timestamp: timestamp(),
+            report_to: SOURCE,
+            requested_reports: STATUS_RECEIVED | STATUS_DELIVERED,
+        },
+    );
+}
+
+#[test]
+fn status_report() {
+    assert_conforms(
+        "status_report",
+        &StatusReport {
+            destination: SOURCE,
+            source: DESTINATION,
+            status: STATUS_DELETED,
+            reason: 1,
+            bundle_source: SOURCE,
+            bundle_timestamp: timestamp(),
+        },
+    );
+}
+
+#[test]
+fn bundle_resend_request() {
+    assert_conforms(
+        "bundle_resend_request",
+        &BundleResendRequest {
+            destination: SOURCE,
+            source: DESTINATION,
+            timestamp: timestamp(),
+        },
+    );
+}
+
+/// Returns the amount of bytes covered by complete entries of end device services.
+fn end_device_services_length(input: &[u8]) -> usize {
+    let mut length = 0;
+    while let Some(service_amount) = input.get(length + 4) {
+        let entry_length = 5 + 2 * usize::from(*service_amount);
+        if length + entry_length > input.len() {
+            break;
+        }
+        length += entry_length;
+    }
+    length
+}
+
+/// Returns the amount of bytes of the phy payload covered by the layout of its packet type.
+fn layout_length(phy_payload: &[u8]) -> usize {
+    let packet_type = PacketType::ALL
+        .into_iter()
+        .find(|packet_type| *packet_type as u8 == phy_payload[1])
+        .unwrap();
+    let mut offset = 2;
+    for field in packet_type.layout() {
+        let remaining = phy_payload.len() - offset;
+        offset += match (field.kind.fixed_size(), field.kind) {
+            (Some(size), _) => size,
+            (None, FieldKind::OptionalLocation) if remaining % 2 == 1 => 9,
+            (None, FieldKind::OptionalLocation) => 0,
+            (None, FieldKind::EndDeviceIds | FieldKind::Frequencies) => remaining - remaining % 4,
+            (None, FieldKind::ReachableEndDeviceIds) => remaining - remaining % 9,
+            (None, FieldKind::EndDeviceServices) => {
+                end_device_services_length(&phy_payload[offset..])
+            }
+            (None, _) => remaining,
+        };
+    }
+    offset
+}
+
+#[test]
+fn layouts_cover_vectors() {
+    let mut vectors = 0;
+    for entry in std::fs::read_dir(vectors_directory()).unwrap() {
+        let path = entry.unwrap().path();
+        let name = path.file_stem().unwrap().to_string_lossy().to_string();
+        if path.extension().is_none_or(|extension| extension != "hex")
+            || name.starts_with("invalid_")
+        {
+            continue;
+        }
+        let vector = load_vector(&name);
+        assert_eq!(
+            layout_length(&vector),
+            vector.len(),
+            "Layout of {name} differs"
+        );
+        vectors += 1;
+    }
+    assert!(vectors > 0);
+}
+
+#[test]
+fn invalid_vectors_rejected() {
+    assert_eq!(
+        parse_phy_payload(&load_vector("invalid_unknown_packet_type")).unwrap_err(),
+        ProtocolParserError::UnknownPacketType
+    );
+    assert_eq!(
+        parse_phy_payload(&load_vector("invalid_version")).unwrap_err(),
+        ProtocolParserError::WrongVersionTag
+    );
+    assert_eq!(
+        parse_phy_payload(&load_vector("invalid_bp7_bundle")).unwrap_err(),
+        ProtocolParserError::InvalidBp7Bundle
+    );
+}
@@ -0,0 +1,13 @@
+//! Receive buffers reassembling the fragments of the protocol.
+//!
+//! Bundles split into bundle fragments, BP7 bundle fragments and Hop2Hop fragments are each
+//! collected in their own buffer until all fragments have been received and the buffer can be
+//! combined. Managing the buffers, e.g. evicting incomplete ones, is left to the user.
+
+mod bp7;
+mod bundle;
+mod hop2hop;
+
+pub use bp7::{Bp7BundleKey, Bp7ReceiveBuffer};
+pub use bundle::BundleReceiveBuffer;
+pub use hop2hop::Hop2HopReceiveBuffer;
@@ -0,0 +1,130 @@
+//! BP7 fragment receive buffer.
+//!
+//! Collects the BP7 fragments received in [`Bp7Bundle`](crate::Bp7Bundle)
+//! packets. Fragments are placed by the fragment offset of their primary block, so they may
+//! differ in size and overlap.
+
+use crate::error::{BundleReceiveBufferCombineError, BundleReceiveBufferProcessError};
+use bp7::flags::BlockControlFlags;
+use std::collections::BTreeMap;
+
+/// Identifies the bundle a BP7 fragment belongs to: source, creation time, creation sequence
+/// number and total application data unit length.
+pub type Bp7BundleKey = (String, bp7::DtnTime, u64, u64);
+
+/// Buffer to collect the BP7 fragments of a bundle.
+#[derive(Debug, Clone)]
+pub struct Bp7ReceiveBuffer {
+    /// Primary block of the first received fragment.
+    primary: bp7::primary::PrimaryBlock,
+    /// Total application data unit length.
+    total_length: usize,
+    /// Collection of received payloads by fragment offset.
+    received_fragments: BTreeMap<usize, Vec<u8>>,
+}
+
+impl Bp7ReceiveBuffer {
+    /// Creates a receive buffer from the first received fragment.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the fragment extends beyond the total application data unit length.
+    pub fn new(fragment: &bp7::Bundle) -> Result<Self, BundleReceiveBufferProcessError> {
+        let mut receive_buffer = Self {
+            primary: fragment.primary.clone(),
+            total_length: usize::try_from(fragment.primary.total_data_length).unwrap_or(usize::MAX),
+            received_fragments: BTreeMap::new(),
+        };
+        receive_buffer.process_fragment(fragment)?;
+        Ok(receive_buffer)
+    }
+
+    /// Returns the key of the bundle the fragment belongs to.
+    #[must_use]
+    pub fn key(fragment: &bp7::Bundle) -> Bp7BundleKey {
+        (
+            fragment.primary.source.to_string(),
+            fragment.primary.creation_timestamp.dtntime(),
+            fragment.primary.creation_timestamp.seqno(),
+            fragment.primary.total_data_length,
+        )
+    }
+
+    /// Processes a fragment of the bundle into the receive buffer.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if:
+    /// - a fragment with the same offset was already received.
+    /// - the fragment extends beyond the total application data unit length.
+    pub fn process_fragment(
+        &mut self,
+        fragment: &bp7::Bundle,
+    ) -> Result<(), BundleReceiveBufferProcessError> {
+        let payload = fragment.payload().cloned().unwrap_or_default();
+        let offset = usize::try_from(fragment.primary.fragmentation_offset).unwrap_or(usize::MAX);
+        if offset.saturating_add(payload.len()) > self.total_length {
+            return Err(BundleReceiveBufferProcessError::OffsetBeyondTotalLength);
+        }
+        if self.received_fragments.contains_key(&offset) {
+            return Err(BundleReceiveBufferProcessError::IndexAlreadyReceived);
+        }
+        self.received_fragments.insert(offset, payload);
+        Ok(())
+    }
+
+    /// Returns the total length of the received fragments.
+    #[must_use]
+    pub fn size(&self) -> usize {
+        self.received_fragments.values().map(Vec::len).sum()
+    }
+
+    /// Returns the creation time of the bundle.
+    #[must_use]
+    pub fn creation_time(&self) -> bp7::DtnTime {
+        self.primary.creation_timestamp.dtntime()
+    }
+
+    /// Returns whether the received fragments cover the whole payload.
+    #[must_use]
+    pub fn is_combinable(&self) -> bool {
+        let mut covered: usize = 0;
+        for (offset, payload) in &self.received_fragments {
+            if *offset > covered {
+                return false;
+            }
+            covered = covered.max(offset.saturating_add(payload.len()));
+        }
+        covered >= self.total_length
+    }
+
+    /// Combines the collected fragments into the original bundle.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if:
+    /// - the fragments do not cover the whole payload.
+    /// - the primary block cannot be built.
+    pub fn combine(self) -> Result<bp7::Bundle, BundleReceiveBufferCombineError> {
+        if !self.is_combinable() {
+            return Err(BundleReceiveBufferCombineError::FragmentsMissing);
+        }
+        let mut payload = Vec::with_capacity(self.total_length);
+        for (offset, fragment) in self.received_fragments {
+            // Overlapping fragments only contribute the bytes not received yet.
+            let skip = payload.len().saturating_sub(offset);
+            payload.extend(fragment.into_iter().skip(skip));
+        }
+        let primary_block = bp7::primary::PrimaryBlockBuilder::new()
+            .source(self.primary.source)
+            .destination(self.primary.destination)
+            .report_to(self.primary.report_to)
+            .creation_timestamp(self.primary.creation_timestamp)
+            .lifetime(self.primary.lifetime)
+            .build()?;
+
+        let canonical = bp7::canonical::new_payload_block(BlockControlFlags::empty(), payload);
+
+        Ok(bp7::Bundle::new(primary_block, vec![canonical]))
+    }
+}
@@ -1,14 +1,16 @@
 //! Bundle receive buffer.
+//!
+//! The sender may switch the data rate between the fragments of a bundle, so the fragments may
+//! differ in size. Their offsets within the payload are derived from the lengths of the preceding
+//! fragments instead of the fragment index.
 
 use crate::end_device_id::EndDeviceId;
 use crate::error::{BundleReceiveBufferCombineError, BundleReceiveBufferProcessError};
-use crate::lorawan_protocol::{BundleFragmentOffsetHash, BundlePackets};
-use crate::receive_buffers::unix_ts_to_dtn_time;
+use crate::{unix_ts_to_dtn_time, BundleFragmentOffsetHash, BundlePackets, BUNDLE_LIFETIME};
 use bp7::flags::{BlockControlFlags, BundleControlFlags};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
-use std::time::Duration;
 
 /// Buffer to collect bundle fragments.
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
@@ -34,7 +36,7 @@ pub struct BundleReceiveBuffer {
 impl From<&mut dyn BundlePackets> for BundleReceiveBuffer {
     fn from(bundle_fragment: &mut dyn BundlePackets) -> Self {
         let total_fragments = if bundle_fragment.is_end() {
-            Some(usize::from(bundle_fragment.fragment_index()))
+            Some(usize::from(bundle_fragment.fragment_index()) + 1)
         } else {
             None
         };
@@ -61,8 +63,11 @@ impl BundleReceiveBuffer {
     ///
     /// Returns an error if:
     /// - the destination, source or timestamp of the packet does not match the receive buffers
-    /// destination, source or timestamp.
-    /// - the fragment index was already received.
+    ///   destination, source or timestamp.
+    /// - the fragment index was already received,
+    ///   [`BundleReceiveBufferProcessError::DuplicateFragment`] if with the same payload, e.g. via
+    ///   another relay.
+    /// - the fragment index is after the index of the end packet.
     /// - the fragment offset hash does not match the receive buffers fragment offset hash.
     /// - the to process packet is an end packet and an end packet has already been processed before.
     /// - the end packet of a fragmented bundle had no TADUL or fragment offset.
@@ -82,40 +87,84 @@ impl BundleReceiveBuffer {
             return Err(BundleReceiveBufferProcessError::TimestampDoesNotMatch);
         }
 
-        if self
-            .received_fragments
-            .contains_key(&packet.fragment_index())
-        {
-            return Err(BundleReceiveBufferProcessError::IndexAlreadyReceived);
+        if let Some(payload) = self.received_fragments.get(&packet.fragment_index()) {
+            return Err(if *payload == packet.payload() {
+                BundleReceiveBufferProcessError::DuplicateFragment
+            } else {
+                BundleReceiveBufferProcessError::IndexAlreadyReceived
+            });
         }
 
         if self.bundle_fragment_offset_hash != packet.bundle_fragment_offset_hash() {
             return Err(BundleReceiveBufferProcessError::FragmentOffsetHashDoesNotMatch);
         }
 
+        if self
+            .total_fragments
+            .is_some_and(|total_fragments| usize::from(packet.fragment_index()) >= total_fragments)
+        {
+            return Err(BundleReceiveBufferProcessError::IndexBeyondEnd);
+        }
+
         if packet.is_end() {
             if self.total_fragments.is_some() {
                 return Err(BundleReceiveBufferProcessError::EndIndexAlreadyReceived);
             }
-            self.total_fragments = Some(usize::from(packet.fragment_index()) + 1);
-            if packet.bundle_total_application_data_unit_length().is_some() {
-                if packet.bundle_fragment_offset().is_some() {
-                    self.bundle_fragment_offset = packet.bundle_fragment_offset();
-                    self.bundle_total_application_data_unit_length =
-                        packet.bundle_total_application_data_unit_length();
+            if self
+                .received_fragments
+                .keys()
+                .next_back()
+                .is_some_and(|last_index| *last_index > packet.fragment_index())
+            {
+                return Err(BundleReceiveBufferProcessError::IndexBeyondEnd);
+            }
+            // Only the end packet of a fragmented bundle carries the TADUL and fragment offset.
+            if self.bundle_fragment_offset_hash.is_some() {
+                if packet.bundle_total_application_data_unit_length().is_some() {
+                    if packet.bundle_fragment_offset().is_some() {
+                        self.bundle_fragment_offset = packet.bundle_fragment_offset();
+                        self.bundle_total_application_data_unit_length =
+                            packet.bundle_total_application_data_unit_length();
+                    } else {
+                        return Err(BundleReceiveBufferProcessError::NoFragmentOffset);
+                    }
                 } else {
-                    return Err(BundleReceiveBufferProcessError::NoFragmentOffset);
+                    return Err(BundleReceiveBufferProcessError::NoTadul);
                 }
-            } else {
-                return Err(BundleReceiveBufferProcessError::NoTadul);
             }
+            self.total_fragments = Some(usize::from(packet.fragment_index()) + 1);
         }
         self.received_fragments
             .insert(packet.fragment_index(), packet.payload());
         Ok(())
     }
 
+    /// Returns the total length of the received fragments.
+    #[must_use]
+    pub fn size(&self) -> usize {
+        self.received_fragments.values().map(Vec::len).sum()
+    }
+
+    /// Returns the offsets of the received fragments within the payload by fragment index.
+    ///
+    /// The offset of a fragment is the sum of the lengths of the preceding fragments, fragments
+    /// after a missing fragment have no offset yet.
+    #[must_use]
+    pub fn fragment_offsets(&self) -> BTreeMap<u8, usize> {
+        let mut offsets = BTreeMap::new();
+        let mut offset: usize = 0;
+        for (expected_index, (index, payload)) in (0..=u8::MAX).zip(&self.received_fragments) {
+            if *index != expected_index {
+                break;
+            }
+            offsets.insert(*index, offset);
+            offset = offset.saturating_add(payload.len());
+        }
+        offsets
+    }
+
     /// Returns whether the receive buffer has received all packets and the bundle can be reassembled.
+    #[must_use]
     pub fn is_combinable(&self) -> bool {
         if let Some(total_fragments) = self.total_fragments {
             total_fragments == self.received_fragments.len()
@@ -126,17 +175,18 @@ impl BundleReceiveBuffer {
 
     /// Combines the collected fragments into a bundle.
     ///
-    /// # Errors:
+    /// # Errors
     ///
     /// Returns an error if:
     /// - a fragment is missing.
     /// - the end has not been received.
     /// - the source and destination cannot be converted from [`EndDeviceId`] to
-    /// [`EndpointID`](bp7::eid::EndpointID).
-    ///
+    ///   [`EndpointID`](bp7::eid::EndpointID).
     pub fn combine(mut self) -> Result<bp7::Bundle, BundleReceiveBufferCombineError> {
         if let Some(total_fragments) = self.total_fragments {
-            if total_fragments != self.received_fragments.len() {
+            if total_fragments != self.received_fragments.len()
+                || total_fragments != self.fragment_offsets().len()
+            {
                 return Err(BundleReceiveBufferCombineError::FragmentsMissing);
             }
         } else {
@@ -150,7 +200,7 @@ impl BundleReceiveBuffer {
                 unix_ts_to_dtn_time(self.timestamp.timestamp().unsigned_abs()),
                 0,
             ))
-            .lifetime(Duration::from_secs(2 * 24 * 60 * 60));
+            .lifetime(BUNDLE_LIFETIME);
         let payload = self
             .received_fragments
             .values_mut()
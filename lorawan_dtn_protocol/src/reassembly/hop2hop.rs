@@ -4,7 +4,7 @@ use crate::error::{
     Hop2HopReceiveBufferCombineError, Hop2HopReceiveBufferCreationError,
     Hop2HopReceiveBufferProcessPacketError,
 };
-use crate::lorawan_protocol::{parse_packet, Hop2HopFragment, LoRaWanPacket};
+use crate::{parse_packet, Hop2HopFragment, LoRaWanPacket};
 use std::collections::BTreeMap;
 
 /// Buffer to collect hop 2 hop fragments.
@@ -46,7 +46,7 @@ impl Hop2HopReceiveBuffer {
     ///
     /// Returns an error if:
     /// - the packet hash or total fragments amount of the packet does not match the receive buffers
-    /// packet hash or total fragments amount.
+    ///   packet hash or total fragments amount.
     /// - the fragment index is larger than the total amount of fragments.
     /// - the fragment index was already received before.
     pub fn process_packet(
@@ -72,8 +72,16 @@ impl Hop2HopReceiveBuffer {
             .insert(packet.fragment_index(), packet.payload_ref().clone());
         Ok(())
     }
+
+    /// Returns the total length of the received fragments.
+    #[must_use]
+    pub fn size(&self) -> usize {
+        self.received_fragments.values().map(Vec::len).sum()
+    }
+
     /// Returns whether the receive buffer has received all packets and the original packet can be
     /// reassembled.
+    #[must_use]
     pub fn is_combinable(&self) -> bool {
         self.received_fragments.len() == self.total_fragments
     }
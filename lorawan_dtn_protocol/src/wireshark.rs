@@ -0,0 +1,242 @@
+//! Generation of a Lua Wireshark dissector from the packet layouts, keeping the capture tooling
+//! in sync with the protocol.
+//!
+//! The dissector is registered for the `USER0` link-layer type, captures of raw phy payloads
+//! are decoded after selecting `DLT_USER0` for them.
+
+use crate::{FieldKind, HeaderField, PacketType, LO_RA_WAN_PROPRIETARY_TAG, NETWORK_ID_FLAG};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Returns the Lua declarations of the protocol fields by their Lua identifier.
+fn field_declarations(field: &HeaderField) -> Vec<(String, String)> {
+    let abbreviation = field.abbreviation;
+    let name = field.name;
+    let declaration = |suffix: &str, kind: &str, name: &str, base: &str| {
+        let key = format!("{abbreviation}{suffix}");
+        (
+            key.clone(),
+            format!("ProtoField.{kind}(\"spatz.{key}\", \"{name}\"{base})"),
+        )
+    };
+    match field.kind {
+        FieldKind::U8 => vec![declaration("", "uint8", name, ", base.DEC")],
+        FieldKind::U16 => vec![declaration("", "uint16", name, ", base.DEC")],
+        FieldKind::U32 | FieldKind::EndDeviceId | FieldKind::EndDeviceIds => {
+            vec![declaration("", "uint32", name, ", base.HEX")]
+        }
+        FieldKind::U64 => vec![declaration("", "uint64", name, ", base.DEC")],
+        FieldKind::Frequencies => vec![declaration(
+            "",
+            "uint32",
+            &format!("{name} (Hz)"),
+            ", base.DEC",
+        )],
+        FieldKind::Timestamp => vec![declaration(
+            "",
+            "uint32",
+            &format!("{name} (unix seconds)"),
+            ", base.DEC",
+        )],
+        FieldKind::OptionalLocation => vec![
+            declaration(
+                "_latitude",
+                "int24",
+                "Latitude (90/2^23 degrees)",
+                ", base.DEC",
+            ),
+            declaration(
+                "_longitude",
+                "int24",
+                "Longitude (180/2^23 degrees)",
+                ", base.DEC",
+            ),
+            declaration("_altitude", "int24", "Altitude (cm)", ", base.DEC"),
+        ],
+        FieldKind::ReachableEndDeviceIds => vec![
+            declaration("_end_device_id", "uint32", name, ", base.HEX"),
+            declaration("_hop_distance", "uint8", "Hop distance", ", base.DEC"),
+            declaration("_next_hop", "uint32", "Next hop", ", base.HEX"),
+        ],
+        FieldKind::EndDeviceServices => vec![
+            declaration("_end_device_id", "uint32", name, ", base.HEX"),
+            declaration("_amount", "uint8", "Service amount", ", base.DEC"),
+            declaration("_tag", "uint16", "Service tag", ", base.DEC"),
+        ],
+        FieldKind::Payload => vec![declaration("", "bytes", name, "")],
+    }
+}
+
+/// Returns the Lua statements dissecting the field at `offset`.
+fn field_dissection(field: &HeaderField) -> String {
+    let abbreviation = field.abbreviation;
+    match field.kind {
+        FieldKind::OptionalLocation => format!(
+            "    if (buffer:len() - offset) % 2 == 1 then\n\
+             \x20       for _, field in ipairs({{fields.{abbreviation}_latitude, fields.{abbreviation}_longitude, fields.{abbreviation}_altitude}}) do\n\
+             \x20           subtree:add_le(field, buffer(offset, 3))\n\
+             \x20           offset = offset + 3\n\
+             \x20       end\n\
+             \x20   end\n"
+        ),
+        FieldKind::EndDeviceIds | FieldKind::Frequencies => format!(
+            "    while offset + 4 <= buffer:len() do\n\
+             \x20       subtree:add_le(fields.{abbreviation}, buffer(offset, 4))\n\
+             \x20       offset = offset + 4\n\
+             \x20   end\n"
+        ),
+        FieldKind::ReachableEndDeviceIds => format!(
+            "    while offset + 9 <= buffer:len() do\n\
+             \x20       subtree:add_le(fields.{abbreviation}_end_device_id, buffer(offset, 4))\n\
+             \x20       subtree:add(fields.{abbreviation}_hop_distance, buffer(offset + 4, 1))\n\
+             \x20       subtree:add_le(fields.{abbreviation}_next_hop, buffer(offset + 5, 4))\n\
+             \x20       offset = offset + 9\n\
+             \x20   end\n"
+        ),
+        FieldKind::EndDeviceServices => format!(
+            "    while offset + 5 <= buffer:len() do\n\
+             \x20       subtree:add_le(fields.{abbreviation}_end_device_id, buffer(offset, 4))\n\
+             \x20       subtree:add(fields.{abbreviation}_amount, buffer(offset + 4, 1))\n\
+             \x20       local amount = buffer(offset + 4, 1):uint()\n\
+             \x20       offset = offset + 5\n\
+             \x20       for _ = 1, amount do\n\
+             \x20           if offset + 2 > buffer:len() then return offset end\n\
+             \x20           subtree:add_le(fields.{abbreviation}_tag, buffer(offset, 2))\n\
+             \x20           offset = offset + 2\n\
+             \x20       end\n\
+             \x20   end\n"
+        ),
+        FieldKind::Payload => format!(
+            "    if offset < buffer:len() then\n\
+             \x20       subtree:add(fields.{abbreviation}, buffer(offset))\n\
+             \x20       offset = buffer:len()\n\
+             \x20   end\n"
+        ),
+        FieldKind::U8
+        | FieldKind::U16
+        | FieldKind::U32
+        | FieldKind::U64
+        | FieldKind::Timestamp
+        | FieldKind::EndDeviceId => {
+            let size = field.kind.fixed_size().unwrap_or_default();
+            format!(
+                "    if offset + {size} > buffer:len() then return offset end\n\
+                 \x20   subtree:add_le(fields.{abbreviation}, buffer(offset, {size}))\n\
+                 \x20   offset = offset + {size}\n"
+            )
+        }
+    }
+}
+
+/// Generates a Lua Wireshark dissector for all packet types.
+#[must_use]
+pub fn generate_wireshark_dissector() -> String {
+    let mut declarations = BTreeMap::new();
+    for packet_type in PacketType::ALL {
+        for field in packet_type.layout() {
+            declarations.extend(field_declarations(field));
+        }
+    }
+
+    let mut lua = String::from(
+        "-- Wireshark dissector of the Spatz LoRaWAN protocol.\n\
+         -- Generated by `spatz --export-wireshark-dissector`, do not edit.\n\n\
+         local spatz = Proto(\"spatz\", \"Spatz LoRaWAN DTN\")\n\n\
+         local packet_types = {\n",
+    );
+    // Writing to a String never fails.
+    for packet_type in PacketType::ALL {
+        let _ = writeln!(lua, "    [{}] = \"{packet_type:?}\",", packet_type as u8);
+    }
+    lua.push_str(
+        "}\n\n\
+         local mhdr = ProtoField.uint8(\"spatz.mhdr\", \"MHDR\", base.HEX)\n\
+         local network_id = ProtoField.uint32(\"spatz.network_id\", \"Network ID\", base.DEC)\n\
+         local packet_type = ProtoField.uint8(\"spatz.packet_type\", \"Packet type\", base.DEC, packet_types)\n\
+         local fields = {\n",
+    );
+    for (key, declaration) in &declarations {
+        let _ = writeln!(lua, "    {key} = {declaration},");
+    }
+    lua.push_str(
+        "}\n\n\
+         spatz.fields = { mhdr, network_id, packet_type }\n\
+         for _, field in pairs(fields) do\n\
+         \x20   table.insert(spatz.fields, field)\n\
+         end\n\n\
+         local dissectors = {}\n",
+    );
+    for packet_type in PacketType::ALL {
+        let _ = writeln!(
+            lua,
+            "\n-- {packet_type:?}\n\
+             dissectors[{}] = function(buffer, subtree, offset)",
+            packet_type as u8
+        );
+        for field in packet_type.layout() {
+            lua.push_str(&field_dissection(field));
+        }
+        lua.push_str("    return offset\nend\n");
+    }
+    let _ = write!(
+        lua,
+        "\nfunction spatz.dissector(buffer, pinfo, tree)\n\
+         \x20   if buffer:len() < 2 or bit.band(buffer(0, 1):uint(), 0xE3) ~= {LO_RA_WAN_PROPRIETARY_TAG} then\n\
+         \x20       return 0\n\
+         \x20   end\n\
+         \x20   pinfo.cols.protocol = spatz.name\n\
+         \x20   local subtree = tree:add(spatz, buffer(), \"Spatz LoRaWAN DTN\")\n\
+         \x20   subtree:add(mhdr, buffer(0, 1))\n\
+         \x20   local offset = 1\n\
+         \x20   if bit.band(buffer(0, 1):uint(), {NETWORK_ID_FLAG}) ~= 0 then\n\
+         \x20       if buffer:len() < 6 then return 1 end\n\
+         \x20       subtree:add_le(network_id, buffer(1, 4))\n\
+         \x20       offset = 5\n\
+         \x20   end\n\
+         \x20   subtree:add(packet_type, buffer(offset, 1))\n\
+         \x20   local type_value = buffer(offset, 1):uint()\n\
+         \x20   pinfo.cols.info = packet_types[type_value] or \"Unknown packet type\"\n\
+         \x20   local dissect = dissectors[type_value]\n\
+         \x20   if dissect == nil then\n\
+         \x20       return offset + 1\n\
+         \x20   end\n\
+         \x20   return dissect(buffer, subtree, offset + 1)\n\
+         end\n\n\
+         DissectorTable.get(\"wtap_encap\"):add(wtap.USER0, spatz)\n"
+    );
+    lua
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::wireshark::generate_wireshark_dissector;
+    use crate::PacketType;
+    use std::collections::HashMap;
+
+    #[test]
+    fn abbreviations_unique_per_kind() {
+        let mut kinds = HashMap::new();
+        for packet_type in PacketType::ALL {
+            for field in packet_type.layout() {
+                assert_eq!(
+                    *kinds.entry(field.abbreviation).or_insert(field.kind),
+                    field.kind,
+                    "{} is used with different kinds",
+                    field.abbreviation
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn dissector_covers_all_packet_types() {
+        let dissector = generate_wireshark_dissector();
+        for packet_type in PacketType::ALL {
+            assert!(dissector.contains(&format!("[{}] = \"{packet_type:?}\"", packet_type as u8)));
+            assert!(dissector.contains(&format!("dissectors[{}] =", packet_type as u8)));
+            for field in packet_type.layout() {
+                assert!(dissector.contains(&format!("\"spatz.{}", field.abbreviation)));
+            }
+        }
+    }
+}
@@ -0,0 +1,32 @@
+//! All errors for this crate.
+use thiserror::Error;
+
+/// Errors occurring when converting from bandwidth and spreading factor to data rate.
+#[allow(missing_docs)]
+#[allow(clippy::missing_docs_in_private_items)]
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum DataRateConversionError {
+    #[error("Parameters do not match any data rate, bandwidth: {bandwidth} spreading_factor: {spreading_factor}")]
+    WrongParameters {
+        bandwidth: u32,
+        spreading_factor: u32,
+    },
+}
+
+/// Errors occurring when converting an integer to a [`SpreadingFactor`](crate::SpreadingFactor).
+#[allow(missing_docs)]
+#[allow(clippy::missing_docs_in_private_items)]
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum SpreadingFactorConversionError {
+    #[error("Parameter does not match any spreading factor: {spreading_factor}")]
+    NoSuchSpreadingFactor { spreading_factor: u32 },
+}
+
+/// Errors occurring when converting from integer to a [`Bandwidth`](crate::Bandwidth).
+#[allow(missing_docs)]
+#[allow(clippy::missing_docs_in_private_items)]
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum BandwidthConversionError {
+    #[error("Parameter does not match any bandwidth: {bandwidth}")]
+    NoSuchBandwidth { bandwidth: u32 },
+}
@@ -0,0 +1,191 @@
+// Operator dashboard of the Spatz, polls the REST API and renders the status, queues, duty cycle
+// and neighbors.
+
+"use strict";
+
+/** Interval between two refreshes in milliseconds. */
+const REFRESH_INTERVAL_MS = 5000;
+
+/** Window the duty cycle airtime is summed over in milliseconds. */
+const DUTY_CYCLE_WINDOW_MS = 60 * 60 * 1000;
+
+/** Usage of a queue in percent above which it is highlighted. */
+const QUEUE_WARNING_PERCENT = 80;
+
+/** API token passed as `/ui?token=<token>`, requests are sent without token if not set. */
+const API_TOKEN = new URLSearchParams(window.location.search).get("token");
+
+/** Fetches an API endpoint and parses its JSON response. */
+async function fetchJson(path) {
+  const headers = API_TOKEN ? { Authorization: `Bearer ${API_TOKEN}` } : {};
+  const response = await fetch(path, { headers });
+  if (!response.ok) {
+    throw new Error(`${path}: ${response.status}`);
+  }
+  return response.json();
+}
+
+/** Creates a table row with a cell per value. */
+function row(values) {
+  const tr = document.createElement("tr");
+  for (const value of values) {
+    const td = document.createElement("td");
+    if (value instanceof Node) {
+      td.appendChild(value);
+    } else {
+      td.textContent = value;
+    }
+    tr.appendChild(td);
+  }
+  return tr;
+}
+
+/** Replaces the rows of the table body. */
+function fillTable(id, rows) {
+  const tbody = document.querySelector(`#${id} tbody`);
+  tbody.replaceChildren(...rows);
+}
+
+/** Returns the age of an RFC 3339 timestamp in a human readable form. */
+function age(timestamp) {
+  const seconds = Math.max(0, Math.round((Date.now() - Date.parse(timestamp)) / 1000));
+  if (seconds < 120) {
+    return `${seconds} s ago`;
+  }
+  if (seconds < 7200) {
+    return `${Math.round(seconds / 60)} min ago`;
+  }
+  return `${Math.round(seconds / 3600)} h ago`;
+}
+
+/** Renders the operating mode, the subsystem switches and the degraded conditions. */
+function renderStatus(status, control) {
+  const mode = document.getElementById("mode");
+  mode.textContent = status.mode;
+  mode.className = `badge ${status.mode.toLowerCase()}`;
+
+  const entries = [
+    ["Operating mode", status.mode],
+    ["Relaying", control.relaying_paused ? "paused" : "active"],
+    ["Announcements", control.announcements_paused ? "paused" : "active"],
+  ];
+  const dl = document.getElementById("status");
+  dl.replaceChildren(
+    ...entries.flatMap(([term, description]) => {
+      const dt = document.createElement("dt");
+      dt.textContent = term;
+      const dd = document.createElement("dd");
+      dd.textContent = description;
+      return [dt, dd];
+    }),
+  );
+
+  const conditions = document.getElementById("degraded-conditions");
+  conditions.replaceChildren(
+    ...status.degraded_conditions.map((info) => {
+      const li = document.createElement("li");
+      li.className = "warning";
+      li.textContent = `${info.condition} since ${age(info.since)}: ${info.reason}`;
+      return li;
+    }),
+  );
+}
+
+/** Renders the fill level of the relay, bundle and announcement queues. */
+function renderQueues(queues) {
+  const rows = [
+    ["Relay packets", queues.relay_packets, queues.max_relay_packets],
+    ["Bundles", queues.bundles, queues.max_bundles],
+    ["Announcements", queues.announcements, queues.max_announcements],
+  ].map(([name, queued, max]) => {
+    const percent = max > 0 ? Math.round((queued / max) * 100) : 0;
+    const usage = document.createElement("span");
+    usage.textContent = `${percent} %`;
+    if (percent >= QUEUE_WARNING_PERCENT) {
+      usage.className = "warning";
+    }
+    return row([name, queued, max, usage]);
+  });
+  fillTable("queues", rows);
+}
+
+/** Sums the airtime of the band entries within the duty cycle window. */
+function recentAirtimeMs(entries) {
+  const since = Date.now() - DUTY_CYCLE_WINDOW_MS;
+  return (entries || [])
+    .filter(([time]) => Date.parse(time) >= since)
+    .reduce((sum, [, airtime]) => sum + airtime, 0);
+}
+
+/** Formats an airtime with its share of the duty cycle window. */
+function airtime(airtimeMs) {
+  const percent = (airtimeMs / DUTY_CYCLE_WINDOW_MS) * 100;
+  return `${Math.round(airtimeMs)} ms (${percent.toFixed(3)} %)`;
+}
+
+/** Renders the airtime per gateway and band. */
+function renderDutyCycle(dutyCycle) {
+  const rows = [];
+  for (const [gateway, manager] of Object.entries(dutyCycle).sort()) {
+    const bands = new Set([
+      ...Object.keys(manager.bands || {}),
+      ...Object.keys(manager.network_server_bands || {}),
+    ]);
+    for (const band of [...bands].sort()) {
+      rows.push(
+        row([
+          gateway,
+          band,
+          airtime(recentAirtimeMs(manager.bands[band])),
+          airtime(recentAirtimeMs((manager.network_server_bands || {})[band])),
+        ]),
+      );
+    }
+  }
+  fillTable("duty-cycle", rows);
+}
+
+/** Renders the neighbor table, nearest neighbors first. */
+function renderNeighbors(neighbors) {
+  const rows = Object.entries(neighbors)
+    .sort(([, a], [, b]) => a.hop_distance - b.hop_distance)
+    .map(([endDeviceId, entry]) =>
+      row([
+        endDeviceId,
+        entry.reachability,
+        entry.hop_distance,
+        entry.gateway_id,
+        `${entry.signal_quality.rssi} dBm`,
+        `${entry.signal_quality.snr} dB`,
+        age(entry.last_seen),
+      ]),
+    );
+  fillTable("neighbors", rows);
+}
+
+/** Fetches all endpoints and renders the dashboard. */
+async function refresh() {
+  const updated = document.getElementById("updated");
+  try {
+    const [status, control, queues, dutyCycle, neighbors] = await Promise.all([
+      fetchJson("/api/status"),
+      fetchJson("/api/control"),
+      fetchJson("/api/stats/queues"),
+      fetchJson("/api/stats/duty_cycle"),
+      fetchJson("/api/stats/neighbors"),
+    ]);
+    renderStatus(status, control);
+    renderQueues(queues);
+    renderDutyCycle(dutyCycle);
+    renderNeighbors(neighbors);
+    updated.textContent = `Updated ${new Date().toLocaleTimeString()}`;
+  } catch (err) {
+    const mode = document.getElementById("mode");
+    mode.textContent = "Unreachable";
+    mode.className = "badge error";
+    updated.textContent = `Refresh failed: ${err.message}`;
+  }
+}
+
+refresh();
+setInterval(refresh, REFRESH_INTERVAL_MS);
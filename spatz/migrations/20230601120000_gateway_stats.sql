@@ -0,0 +1,7 @@
+-- Gateway stats history
+CREATE TABLE IF NOT EXISTS GatewayStatsTable (
+    GatewayId TEXT NOT NULL,
+    Time INTEGER NOT NULL,
+    Stats TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS GatewayStatsTableGatewayIdTime ON GatewayStatsTable (GatewayId, Time);
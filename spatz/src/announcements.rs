@@ -0,0 +1,580 @@
+//! Periodic local announcements of the end device IDs registered at this node.
+
+use crate::configuration::{
+    AnnouncementConfig, AnnouncementDeferralConfig, AnnouncementTrimmingConfig,
+};
+use crate::diagnostics::local_end_device_ids;
+use crate::end_device_id::{EndDeviceId, ManagedEndDeviceId};
+use crate::graceful_shutdown::ShutdownAgent;
+use crate::AppState;
+use chirpstack_gwb_integration::downlinks::predefined_parameters::{DataRate, Frequency};
+use chrono::{DateTime, Utc};
+use lorawan_dtn_protocol::{
+    data_rate_bit, CapabilityAnnouncement, ChannelPlanAnnouncement, DataRateAnnouncement,
+    EndDeviceServices, LoRaWanPacket, LocalAnnouncement, ReachabilityAnnouncement,
+    ReachableEndDeviceId, ServiceAnnouncement, CAPABILITY_BP7_CBOR, DEFAULT_RECEIVE_DATA_RATES,
+};
+use std::cmp::Reverse;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::{info, instrument, trace};
+
+/// Data rate the announcements are split for, matches the data rate used by the routing
+/// algorithm.
+const ANNOUNCEMENT_DATA_RATE: DataRate = DataRate::Eu863_870Dr3;
+
+/// Frequency the announcements are sent on if no channels are configured, matches the frequency
+/// used by the routing algorithm.
+const ANNOUNCEMENT_FREQUENCY: Frequency = Frequency::Freq868_3;
+
+/// Interval between checks of the relay and bundle queues while announcements are deferred.
+const DEFERRAL_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Announcement intervals a neighbor table entry is kept without being heard again if no proxying
+/// is configured.
+const NEIGHBOR_MAX_AGE_INTERVALS: u64 = 10;
+
+/// Maximum age in seconds of neighbor table entries if this node does not announce itself.
+const DEFAULT_NEIGHBOR_MAX_AGE_SECONDS: u64 = 600;
+
+/// Extent of the announcements depending on the remaining duty cycle budget.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AnnouncementExtent {
+    /// All end device IDs are announced.
+    Full,
+    /// At most the contained amount of end device IDs is announced.
+    Trimmed(usize),
+    /// No announcements are enqueued.
+    Skipped,
+}
+
+impl AnnouncementExtent {
+    /// Returns the extent for the remaining share of the duty cycle budget, announcing in full if
+    /// the budget is unlimited or unknown.
+    fn from_budget(remaining: Option<f64>, trimming: &AnnouncementTrimmingConfig) -> Self {
+        match remaining {
+            Some(remaining) if remaining < trimming.skip_below() => Self::Skipped,
+            Some(remaining) if remaining < trimming.trim_below() => Self::trimmed(trimming),
+            _ => Self::Full,
+        }
+    }
+
+    /// Returns the trimmed extent of the trimming configuration.
+    fn trimmed(trimming: &AnnouncementTrimmingConfig) -> Self {
+        Self::Trimmed(usize::try_from(trimming.max_end_device_ids).unwrap_or(usize::MAX))
+    }
+}
+
+/// Task periodically enqueuing local announcements.
+///
+/// Announcements are suppressed if all own end device IDs were recently announced by stronger
+/// neighbors, at most `max_consecutive_suppressions` times in a row. If proxying is configured,
+/// end device IDs learned from neighbors are advertised with their hop distance as well. The
+/// configured services of the announced end device IDs, the channel plan, the capabilities and,
+/// if unicast is configured, the receive data rates follow the local announcements. No
+/// announcements are enqueued while they are paused.
+///
+/// If trimming is configured, announcements are limited to the highest priority end device IDs
+/// while the remaining duty cycle budget is low and skipped while it is nearly exhausted, at most
+/// `max_consecutive_skips` times in a row. Full announcements resume once the budget recovers.
+///
+/// If deferral is configured, announcements due while the relay or bundle queue is deep are
+/// deferred until the queues drain, at most `max_deferral_seconds`, leaving the airtime to the
+/// queued packets.
+#[instrument(skip_all)]
+pub async fn announcement_task(
+    state: Arc<AppState>,
+    announcement_config: AnnouncementConfig,
+    mut shutdown_agent: ShutdownAgent,
+) {
+    trace!("Starting up");
+    let mut consecutive_suppressions = 0;
+    let mut consecutive_skips = 0;
+    let mut previous_extent = AnnouncementExtent::Full;
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_secs(announcement_config.interval_seconds)) => {},
+            _ = shutdown_agent.await_shutdown() => {
+                trace!("Shutting down");
+                return
+            }
+        }
+
+        if state.subsystem_control.announcements_paused() {
+            trace!("Announcements paused");
+            continue;
+        }
+
+        if let Some(deferral) = &announcement_config.deferral {
+            if !defer_under_relay_load(&state, deferral, &mut shutdown_agent).await {
+                trace!("Shutting down");
+                return;
+            }
+        }
+
+        let mut extent = AnnouncementExtent::Full;
+        if let Some(trimming) = &announcement_config.trimming {
+            let remaining = remaining_budget(&state, &announcement_config).await;
+            extent = AnnouncementExtent::from_budget(remaining, trimming);
+            if extent != previous_extent {
+                log_extent_change(extent, remaining);
+                previous_extent = extent;
+            }
+            if extent == AnnouncementExtent::Skipped {
+                if consecutive_skips < trimming.max_consecutive_skips {
+                    trace!("Duty cycle budget exhausted, skipping announcements");
+                    consecutive_skips += 1;
+                    continue;
+                }
+                extent = AnnouncementExtent::trimmed(trimming);
+            }
+        }
+        consecutive_skips = 0;
+
+        // The first end device ID identifies this node, it is kept by the trimming and starts
+        // every local announcement.
+        let mut end_device_ids = local_end_device_ids(&state).await;
+
+        // Identifies this node as next hop of the reachable end device IDs, see
+        // `NeighborTable::process_reachability_announcement`.
+        let sender = end_device_ids.first().copied();
+        let mut reachable_end_device_ids = Vec::new();
+        if let Some(proxy) = &announcement_config.proxy {
+            let neighbor_table_lock = state.neighbor_table.lock().await;
+            reachable_end_device_ids = neighbor_table_lock
+                .reachable_end_device_ids(&end_device_ids, proxy.max_hop_distance)
+                .into_iter()
+                .map(|reachable| {
+                    let last_seen = neighbor_table_lock
+                        .entries()
+                        .get(&reachable.end_device_id)
+                        .map(|entry| entry.last_seen);
+                    (reachable, last_seen)
+                })
+                .collect();
+        }
+        if let AnnouncementExtent::Trimmed(max_end_device_ids) = extent {
+            trim_end_device_ids(
+                &mut end_device_ids,
+                &mut reachable_end_device_ids,
+                max_end_device_ids,
+            );
+        }
+
+        if let (Some(_), Some(sender)) = (&announcement_config.proxy, sender) {
+            let reachable_end_device_ids: Vec<ReachableEndDeviceId> = reachable_end_device_ids
+                .into_iter()
+                .map(|(reachable, _)| reachable)
+                .collect();
+            if !reachable_end_device_ids.is_empty() {
+                trace!("Enqueuing reachability announcement");
+                state
+                    .queue_manager
+                    .enqueue_announcements(
+                        ReachabilityAnnouncement::split_to_data_rate(
+                            sender,
+                            &reachable_end_device_ids,
+                            ANNOUNCEMENT_DATA_RATE,
+                            state.repeater_compatible,
+                        )
+                        .into_iter()
+                        .map(|announcement| Box::new(announcement) as Box<dyn LoRaWanPacket>)
+                        .collect(),
+                    )
+                    .await;
+            }
+        }
+
+        if end_device_ids.is_empty() {
+            trace!("No end device IDs to announce");
+            continue;
+        }
+
+        if let Some(suppression) = &announcement_config.suppression {
+            let max_age = max_age_from_seconds(suppression.max_age_seconds);
+            if consecutive_suppressions < suppression.max_consecutive_suppressions
+                && state
+                    .neighbor_table
+                    .lock()
+                    .await
+                    .announced_by_stronger_neighbors(
+                        &end_device_ids,
+                        suppression.min_rssi,
+                        suppression.min_snr,
+                        max_age,
+                    )
+            {
+                trace!("All end device IDs announced by stronger neighbors, suppressing");
+                consecutive_suppressions += 1;
+                continue;
+            }
+        }
+        consecutive_suppressions = 0;
+
+        trace!("Enqueuing local announcement");
+        state
+            .queue_manager
+            .enqueue_announcements(
+                LocalAnnouncement::split_to_data_rate(
+                    None,
+                    &end_device_ids,
+                    ANNOUNCEMENT_DATA_RATE,
+                    state.repeater_compatible,
+                )
+                .into_iter()
+                .map(|announcement| Box::new(announcement) as Box<dyn LoRaWanPacket>)
+                .collect(),
+            )
+            .await;
+
+        let end_device_services: Vec<EndDeviceServices> = announcement_config
+            .services
+            .iter()
+            .map(|announced_services| EndDeviceServices {
+                end_device_id: EndDeviceId::from(ManagedEndDeviceId::from(
+                    &announced_services.end_device_id,
+                )),
+                services: announced_services.services.clone(),
+            })
+            .filter(|entry| end_device_ids.contains(&entry.end_device_id))
+            .collect();
+        if !end_device_services.is_empty() {
+            trace!("Enqueuing service announcement");
+            state
+                .queue_manager
+                .enqueue_announcements(
+                    ServiceAnnouncement::split_to_data_rate(
+                        &end_device_services,
+                        ANNOUNCEMENT_DATA_RATE,
+                        state.repeater_compatible,
+                    )
+                    .into_iter()
+                    .map(|announcement| Box::new(announcement) as Box<dyn LoRaWanPacket>)
+                    .collect(),
+                )
+                .await;
+        }
+
+        if !announcement_config.channels.is_empty() {
+            trace!("Enqueuing channel plan announcement");
+            // The first end device ID identifies this node, there is at least one left after
+            // trimming.
+            state
+                .queue_manager
+                .enqueue_announcements(vec![Box::new(ChannelPlanAnnouncement::new(
+                    end_device_ids[0],
+                    announcement_config.channels.clone(),
+                ))])
+                .await;
+        }
+
+        if state.bp7_interop.is_some() {
+            trace!("Enqueuing capability announcement");
+            state
+                .queue_manager
+                .enqueue_announcements(
+                    CapabilityAnnouncement::split_to_data_rate(
+                        CAPABILITY_BP7_CBOR,
+                        &end_device_ids,
+                        ANNOUNCEMENT_DATA_RATE,
+                        state.repeater_compatible,
+                    )
+                    .into_iter()
+                    .map(|announcement| Box::new(announcement) as Box<dyn LoRaWanPacket>)
+                    .collect(),
+                )
+                .await;
+        }
+
+        if state.unicast.is_some() {
+            trace!("Enqueuing data rate announcement");
+            let data_rates = receive_data_rates(&state, &announcement_config).await;
+            state
+                .queue_manager
+                .enqueue_announcements(
+                    DataRateAnnouncement::split_to_data_rate(
+                        data_rates,
+                        &end_device_ids,
+                        ANNOUNCEMENT_DATA_RATE,
+                        state.repeater_compatible,
+                    )
+                    .into_iter()
+                    .map(|announcement| Box::new(announcement) as Box<dyn LoRaWanPacket>)
+                    .collect(),
+                )
+                .await;
+        }
+    }
+}
+
+/// Defers the announcements while the relay or bundle queue reaches its threshold, at most
+/// `max_deferral_seconds`.
+///
+/// Returns `false` if shut down while deferring.
+async fn defer_under_relay_load(
+    state: &AppState,
+    deferral: &AnnouncementDeferralConfig,
+    shutdown_agent: &mut ShutdownAgent,
+) -> bool {
+    let relay_packets_threshold =
+        usize::try_from(deferral.relay_packets_threshold).unwrap_or(usize::MAX);
+    let bundles_threshold = usize::try_from(deferral.bundles_threshold).unwrap_or(usize::MAX);
+    if !state
+        .queue_manager
+        .is_congested(relay_packets_threshold, bundles_threshold)
+        .await
+    {
+        return true;
+    }
+
+    info!("Relay load high, deferring announcements");
+    let max_deferral = Duration::from_secs(deferral.max_deferral_seconds);
+    let deferred_since = Instant::now();
+    loop {
+        let remaining = max_deferral.saturating_sub(deferred_since.elapsed());
+        if remaining.is_zero() {
+            info!(
+                "Announcements deferred for {} s, announcing anyway",
+                deferral.max_deferral_seconds
+            );
+            return true;
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(DEFERRAL_CHECK_INTERVAL.min(remaining)) => {},
+            _ = shutdown_agent.await_shutdown() => return false,
+        }
+        if !state
+            .queue_manager
+            .is_congested(relay_packets_threshold, bundles_threshold)
+            .await
+        {
+            info!(
+                "Relay load dropped after {} s, sending deferred announcements",
+                deferred_since.elapsed().as_secs()
+            );
+            return true;
+        }
+        trace!("Relay load still high, deferring announcements");
+    }
+}
+
+/// Returns the lowest share of the duty cycle budget remaining among the connected gateways on the
+/// frequencies the announcements are sent on, `None` if the budget is unlimited or unknown.
+async fn remaining_budget(
+    state: &AppState,
+    announcement_config: &AnnouncementConfig,
+) -> Option<f64> {
+    let gateway_ids = state.gateway_ids_manager.gateway_ids.lock().await.clone();
+    let mut frequencies = announcement_config.frequencies();
+    if frequencies.is_empty() {
+        frequencies.push(ANNOUNCEMENT_FREQUENCY);
+    }
+    let mut duty_cycle_manager = state.duty_cycle_manager.lock().await;
+    gateway_ids
+        .iter()
+        .flat_map(|gateway_id| {
+            frequencies
+                .iter()
+                .map(move |frequency| (gateway_id, frequency))
+        })
+        .filter_map(|(gateway_id, frequency)| {
+            duty_cycle_manager
+                .remaining_share(frequency.hz(), gateway_id)
+                .ok()
+                .flatten()
+        })
+        .reduce(f64::min)
+}
+
+/// Logs the change of the announcement extent at the remaining share of the duty cycle budget.
+fn log_extent_change(extent: AnnouncementExtent, remaining: Option<f64>) {
+    let remaining_percent = remaining.unwrap_or(1.0) * 100.0;
+    match extent {
+        AnnouncementExtent::Full => {
+            info!("Duty cycle budget recovered to {remaining_percent:.1} %, announcing in full");
+        }
+        AnnouncementExtent::Trimmed(max_end_device_ids) => info!(
+            "Duty cycle budget low at {remaining_percent:.1} %, announcing at most \
+             {max_end_device_ids} end device IDs"
+        ),
+        AnnouncementExtent::Skipped => {
+            info!(
+                "Duty cycle budget exhausted at {remaining_percent:.1} %, skipping announcements"
+            );
+        }
+    }
+}
+
+/// Trims the announced end device IDs to at most `max_end_device_ids` in total.
+///
+/// Locally registered end device IDs are kept first, the remaining slots go to the reachable end
+/// device IDs seen most recently, closer ones first if seen at the same time.
+fn trim_end_device_ids(
+    end_device_ids: &mut Vec<EndDeviceId>,
+    reachable_end_device_ids: &mut Vec<(ReachableEndDeviceId, Option<DateTime<Utc>>)>,
+    max_end_device_ids: usize,
+) {
+    end_device_ids.truncate(max_end_device_ids);
+    reachable_end_device_ids
+        .sort_by_key(|(reachable, last_seen)| (Reverse(*last_seen), reachable.hop_distance));
+    reachable_end_device_ids.truncate(max_end_device_ids - end_device_ids.len());
+}
+
+/// Returns the bit set of the data rates this node prefers to receive with, the configured ones
+/// or DR0 to DR5 and DR6 if all connected gateways are known to receive 250 kHz.
+async fn receive_data_rates(state: &AppState, announcement_config: &AnnouncementConfig) -> u8 {
+    if let Some(data_rates) = announcement_config.receive_data_rates_bits() {
+        return data_rates;
+    }
+    let gateway_ids = state.gateway_ids_manager.gateway_ids.lock().await.clone();
+    let mut bandwidth_250_khz = !gateway_ids.is_empty();
+    for gateway_id in &gateway_ids {
+        bandwidth_250_khz &= state
+            .gateway_ids_manager
+            .capabilities(gateway_id)
+            .await
+            .bandwidth_250_khz
+            == Some(true);
+    }
+    if bandwidth_250_khz {
+        DEFAULT_RECEIVE_DATA_RATES | data_rate_bit(DataRate::Eu863_870Dr6)
+    } else {
+        DEFAULT_RECEIVE_DATA_RATES
+    }
+}
+
+/// Returns the maximum age of neighbor table entries, the advertised age if proxying is
+/// configured, otherwise [`NEIGHBOR_MAX_AGE_INTERVALS`] announcement intervals but at least the
+/// considered age of suppressing neighbor announcements.
+///
+/// Without announcements [`DEFAULT_NEIGHBOR_MAX_AGE_SECONDS`] is used.
+pub fn neighbor_max_age(announcement_config: Option<&AnnouncementConfig>) -> chrono::Duration {
+    let Some(announcement_config) = announcement_config else {
+        return max_age_from_seconds(DEFAULT_NEIGHBOR_MAX_AGE_SECONDS);
+    };
+    let seconds = announcement_config.proxy.as_ref().map_or_else(
+        || {
+            let seconds = announcement_config
+                .interval_seconds
+                .saturating_mul(NEIGHBOR_MAX_AGE_INTERVALS);
+            announcement_config
+                .suppression
+                .as_ref()
+                .map_or(seconds, |suppression| {
+                    seconds.max(suppression.max_age_seconds)
+                })
+        },
+        |proxy| proxy.max_age_seconds,
+    );
+    max_age_from_seconds(seconds)
+}
+
+/// Converts a maximum age in seconds into a [`chrono::Duration`], saturating on overflow.
+fn max_age_from_seconds(seconds: u64) -> chrono::Duration {
+    chrono::Duration::from_std(std::time::Duration::from_secs(seconds))
+        .unwrap_or_else(|_| chrono::Duration::max_value())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::announcements::{neighbor_max_age, trim_end_device_ids, AnnouncementExtent};
+    use crate::configuration::{
+        AnnouncementConfig, AnnouncementProxyConfig, AnnouncementSuppressionConfig,
+        AnnouncementTrimmingConfig,
+    };
+    use crate::end_device_id::EndDeviceId;
+    use chrono::{Duration, Utc};
+    use lorawan_dtn_protocol::ReachableEndDeviceId;
+
+    #[test]
+    fn extent_follows_remaining_budget() {
+        let trimming = AnnouncementTrimmingConfig {
+            trim_below_percent: 50,
+            skip_below_percent: 10,
+            max_end_device_ids: 4,
+            max_consecutive_skips: 2,
+        };
+        assert_eq!(
+            AnnouncementExtent::from_budget(None, &trimming),
+            AnnouncementExtent::Full
+        );
+        assert_eq!(
+            AnnouncementExtent::from_budget(Some(0.8), &trimming),
+            AnnouncementExtent::Full
+        );
+        assert_eq!(
+            AnnouncementExtent::from_budget(Some(0.3), &trimming),
+            AnnouncementExtent::Trimmed(4)
+        );
+        assert_eq!(
+            AnnouncementExtent::from_budget(Some(0.05), &trimming),
+            AnnouncementExtent::Skipped
+        );
+    }
+
+    #[test]
+    fn neighbor_max_age_follows_proxy_and_suppression() {
+        let mut config = AnnouncementConfig {
+            interval_seconds: 60,
+            suppression: None,
+            proxy: None,
+            trimming: None,
+            deferral: None,
+            services: Vec::new(),
+            channels: Vec::new(),
+            receive_data_rates: Vec::new(),
+        };
+        assert_eq!(neighbor_max_age(None), Duration::seconds(600));
+        assert_eq!(neighbor_max_age(Some(&config)), Duration::seconds(600));
+
+        config.suppression = Some(AnnouncementSuppressionConfig {
+            min_rssi: -100,
+            min_snr: 0,
+            max_age_seconds: 900,
+            max_consecutive_suppressions: 3,
+        });
+        assert_eq!(neighbor_max_age(Some(&config)), Duration::seconds(900));
+
+        config.proxy = Some(AnnouncementProxyConfig {
+            max_hop_distance: 3,
+            max_age_seconds: 300,
+        });
+        assert_eq!(neighbor_max_age(Some(&config)), Duration::seconds(300));
+    }
+
+    #[test]
+    fn trimming_prefers_local_and_recently_seen_end_device_ids() {
+        let now = Utc::now();
+        let reachable = |end_device_id, hop_distance, seconds_ago| {
+            (
+                ReachableEndDeviceId {
+                    end_device_id: EndDeviceId(end_device_id),
+                    hop_distance,
+                    next_hop: EndDeviceId(end_device_id),
+                },
+                Some(now - Duration::seconds(seconds_ago)),
+            )
+        };
+        let mut end_device_ids = vec![EndDeviceId(1), EndDeviceId(2)];
+        let mut reachable_end_device_ids = vec![
+            reachable(10, 1, 600),
+            reachable(11, 3, 10),
+            reachable(12, 2, 10),
+            reachable(13, 1, 300),
+        ];
+        trim_end_device_ids(&mut end_device_ids, &mut reachable_end_device_ids, 4);
+        assert_eq!(end_device_ids, vec![EndDeviceId(1), EndDeviceId(2)]);
+        assert_eq!(
+            reachable_end_device_ids
+                .iter()
+                .map(|(reachable, _)| reachable.end_device_id)
+                .collect::<Vec<_>>(),
+            vec![EndDeviceId(12), EndDeviceId(11)]
+        );
+
+        trim_end_device_ids(&mut end_device_ids, &mut reachable_end_device_ids, 1);
+        assert_eq!(end_device_ids, vec![EndDeviceId(1)]);
+        assert!(reachable_end_device_ids.is_empty());
+    }
+}
@@ -1,5 +1,6 @@
 //! The REST/WS API of Spatz.
 
+use crate::api::authorization::{authorize, ApiAuthorization};
 use crate::AppState;
 use aide::axum::{ApiRouter, IntoApiResponse};
 use aide::openapi::{Info, OpenApi};
@@ -10,14 +11,27 @@ use tower_http::cors::CorsLayer;
 use tower_http::trace::{DefaultMakeSpan, TraceLayer};
 use tracing::trace;
 
+pub mod api_error;
+pub mod authorization;
+#[cfg(feature = "dashboard")]
+pub mod dashboard;
 pub mod rest_bind_config;
 pub mod rest_chirpstack_config;
+pub mod rest_configuration;
+pub mod rest_control;
+pub mod rest_diagnostics;
 pub mod rest_duty_cycle;
 pub mod rest_end_devices;
+pub mod rest_gateways;
 pub mod rest_mqtt_config;
+pub mod rest_neighbors;
 pub mod rest_packet_cache;
+pub mod rest_protocol;
 pub mod rest_queues;
+pub mod rest_radio_silence;
 pub mod rest_restart;
+pub mod rest_routing;
+pub mod rest_status;
 pub mod websockets;
 
 /// Serves the generated OpenAPI spec.
@@ -26,9 +40,9 @@ pub async fn serve_api(Extension(api): Extension<OpenApi>) -> impl IntoApiRespon
     Json(api)
 }
 
-/// Returns the Spatz API.
+/// Returns the Spatz API, requests are authorized with the tokens of the authorization.
 #[allow(clippy::too_many_lines)]
-pub fn create_api(state: Arc<AppState>) -> Router {
+pub fn create_api(state: Arc<AppState>, authorization: ApiAuthorization) -> Router {
     let mut api = OpenApi {
         info: Info {
             description: Some("The Spatz REST API".to_string()),
@@ -38,9 +52,17 @@ pub fn create_api(state: Arc<AppState>) -> Router {
     };
 
     trace!("Creating Axum application");
-    ApiRouter::new()
+    let router = ApiRouter::new()
         .route("/api.json", axum::routing::get(serve_api))
         // Config
+        .api_route(
+            "/api/config/schema",
+            aide::axum::routing::get(rest_configuration::get_configuration_schema),
+        )
+        .api_route(
+            "/api/config/validate",
+            aide::axum::routing::post(rest_configuration::validate_configuration),
+        )
         // Bind
         .api_route(
             "/api/config/current/bind",
@@ -106,23 +128,281 @@ pub fn create_api(state: Arc<AppState>) -> Router {
             "/api/config/next/queues",
             aide::axum::routing::post(rest_queues::set_next_queues_config),
         )
+        .api_route(
+            "/api/queues/message_queue/pin",
+            aide::axum::routing::post(rest_queues::pin_bundle),
+        )
+        .api_route(
+            "/api/queues/message_queue/deprioritize",
+            aide::axum::routing::post(rest_queues::deprioritize_bundle),
+        )
+        .api_route(
+            "/api/queues/message_queue/freeze",
+            aide::axum::routing::post(rest_queues::freeze_bundle),
+        )
+        .api_route(
+            "/api/bundles/resend",
+            aide::axum::routing::post(rest_routing::request_bundle_resend),
+        )
+        .api_route(
+            "/api/bundles/poll",
+            aide::axum::routing::get(rest_routing::poll_bundles),
+        )
+        .api_route(
+            "/api/bundles/oversize",
+            aide::axum::routing::get(rest_routing::get_oversize_bundles),
+        )
         // Stats
         .api_route(
             "/api/stats/packet_cache",
             aide::axum::routing::get(rest_packet_cache::get_packet_cache_contents),
         )
+        .api_route(
+            "/api/packet_cache",
+            aide::axum::routing::get(rest_packet_cache::get_packet_cache_report),
+        )
+        .api_route(
+            "/api/packet_cache",
+            aide::axum::routing::delete(rest_packet_cache::flush_packet_cache),
+        )
+        .api_route(
+            "/api/stats/inbound_duplicates",
+            aide::axum::routing::get(rest_packet_cache::get_inbound_duplicate_stats),
+        )
+        .api_route(
+            "/api/stats/uplink_validation",
+            aide::axum::routing::get(rest_packet_cache::get_uplink_validation_stats),
+        )
+        .api_route(
+            "/api/stats/networks",
+            aide::axum::routing::get(rest_packet_cache::get_network_stats),
+        )
         .api_route(
             "/api/stats/message_queue",
             aide::axum::routing::get(rest_queues::get_message_buffer_queue),
         )
+        .api_route(
+            "/api/stats/parked_bundles",
+            aide::axum::routing::get(rest_queues::get_parked_bundles),
+        )
+        .api_route(
+            "/api/stats/late_deliveries",
+            aide::axum::routing::get(rest_queues::get_late_delivery_stats),
+        )
+        .api_route(
+            "/api/stats/payload_profiles",
+            aide::axum::routing::get(rest_queues::get_payload_profile_stats),
+        )
+        .api_route(
+            "/api/stats/delivery_ledger",
+            aide::axum::routing::get(rest_queues::get_delivery_ledger_stats),
+        )
+        .api_route(
+            "/api/stats/queues",
+            aide::axum::routing::get(rest_queues::get_queue_stats),
+        )
         .api_route(
             "/api/stats/relay_packet_queue",
             aide::axum::routing::get(rest_queues::get_relay_packet_queue),
         )
+        .api_route(
+            "/api/stats/routing",
+            aide::axum::routing::get(rest_routing::get_routing_stats),
+        )
+        .api_route(
+            "/api/stats/unicast",
+            aide::axum::routing::get(rest_routing::get_unicast_stats),
+        )
+        .api_route(
+            "/api/stats/path_cache",
+            aide::axum::routing::get(rest_routing::get_path_cache),
+        )
+        .api_route(
+            "/api/stats/bp7_interop",
+            aide::axum::routing::get(rest_routing::get_bp7_interop_stats),
+        )
+        .api_route(
+            "/api/stats/status_reports",
+            aide::axum::routing::get(rest_routing::get_status_report_stats),
+        )
+        .api_route(
+            "/api/stats/bundle_resend",
+            aide::axum::routing::get(rest_routing::get_bundle_resend_stats),
+        )
         .api_route(
             "/api/stats/duty_cycle",
             aide::axum::routing::get(rest_duty_cycle::get_duty_cycle_stats),
         )
+        .api_route(
+            "/api/duty_cycle/lockouts",
+            aide::axum::routing::get(rest_duty_cycle::get_lockouts),
+        )
+        .api_route(
+            "/api/duty_cycle/lockouts",
+            aide::axum::routing::post(rest_duty_cycle::lock_out),
+        )
+        .api_route(
+            "/api/duty_cycle/lockouts",
+            aide::axum::routing::delete(rest_duty_cycle::lift_lockout),
+        )
+        .api_route(
+            "/api/duty_cycle/journal",
+            aide::axum::routing::get(rest_duty_cycle::get_scheduling_journal),
+        )
+        .api_route(
+            "/api/stats/clients",
+            aide::axum::routing::get(rest_duty_cycle::get_client_airtime_stats),
+        )
+        .api_route(
+            "/api/stats/neighbors",
+            aide::axum::routing::get(rest_neighbors::get_neighbor_table),
+        )
+        .api_route(
+            "/api/stats/neighbors/services",
+            aide::axum::routing::get(rest_neighbors::get_neighbor_services),
+        )
+        .api_route(
+            "/api/stats/neighbors/channel_plans",
+            aide::axum::routing::get(rest_neighbors::get_neighbor_channel_plans),
+        )
+        .api_route(
+            "/api/stats/neighbors/capabilities",
+            aide::axum::routing::get(rest_neighbors::get_neighbor_capabilities),
+        )
+        .api_route(
+            "/api/stats/neighbors/data_rates",
+            aide::axum::routing::get(rest_neighbors::get_neighbor_data_rates),
+        )
+        .api_route(
+            "/api/neighbors/trust",
+            aide::axum::routing::get(rest_neighbors::get_neighbor_trust),
+        )
+        .api_route(
+            "/api/neighbors/trust/audit",
+            aide::axum::routing::get(rest_neighbors::get_neighbor_trust_audit),
+        )
+        .api_route(
+            "/api/neighbors/blacklist",
+            aide::axum::routing::post(rest_neighbors::blacklist_neighbor),
+        )
+        .api_route(
+            "/api/neighbors/blacklist",
+            aide::axum::routing::delete(rest_neighbors::unblacklist_neighbor),
+        )
+        .api_route(
+            "/api/topology",
+            aide::axum::routing::get(rest_neighbors::get_topology),
+        )
+        .api_route(
+            "/api/stats/radio",
+            aide::axum::routing::get(rest_gateways::get_radio_stats),
+        )
+        .api_route(
+            "/api/stats/database",
+            aide::axum::routing::get(rest_status::get_database_stats),
+        )
+        .api_route(
+            "/api/stats/memory",
+            aide::axum::routing::get(rest_status::get_memory_stats),
+        )
+        // Status
+        .api_route(
+            "/api/status",
+            aide::axum::routing::get(rest_status::get_status),
+        )
+        .api_route(
+            "/api/status/last_shutdown",
+            aide::axum::routing::get(rest_status::get_last_shutdown),
+        )
+        .api_route(
+            "/api/status/startup",
+            aide::axum::routing::get(rest_status::get_startup_summary),
+        )
+        // Radio silence
+        .api_route(
+            "/api/radio_silence",
+            aide::axum::routing::get(rest_radio_silence::get_radio_silence),
+        )
+        .api_route(
+            "/api/radio_silence",
+            aide::axum::routing::post(rest_radio_silence::set_radio_silence),
+        )
+        .api_route(
+            "/api/radio_silence/windows",
+            aide::axum::routing::post(rest_radio_silence::set_radio_silence_windows),
+        )
+        // Subsystem control
+        .api_route(
+            "/api/control",
+            aide::axum::routing::get(rest_control::get_subsystem_control),
+        )
+        .api_route(
+            "/api/control/relaying",
+            aide::axum::routing::post(rest_control::set_relaying),
+        )
+        .api_route(
+            "/api/control/announcements",
+            aide::axum::routing::post(rest_control::set_announcements),
+        )
+        // Gateways
+        .api_route(
+            "/api/stats/runtime",
+            aide::axum::routing::get(rest_gateways::get_runtime_metrics),
+        )
+        .api_route(
+            "/api/gateways/transmissions",
+            aide::axum::routing::get(rest_gateways::get_gateway_transmissions),
+        )
+        .api_route(
+            "/api/gateways/clocks",
+            aide::axum::routing::get(rest_gateways::get_gateway_clocks),
+        )
+        .api_route(
+            "/api/gateways/capabilities",
+            aide::axum::routing::get(rest_gateways::get_gateway_capabilities),
+        )
+        .api_route(
+            "/api/gateways/send_queues",
+            aide::axum::routing::get(rest_gateways::get_gateway_send_queue_stats),
+        )
+        .api_route(
+            "/api/gateways/acks",
+            aide::axum::routing::get(rest_gateways::get_downlink_ack_stats),
+        )
+        .api_route(
+            "/api/gateways/:gateway_id/stats",
+            aide::axum::routing::get(rest_gateways::get_gateway_stats),
+        )
+        .api_route(
+            "/api/gateways/:gateway_id/downlinks",
+            aide::axum::routing::get(rest_gateways::get_gateway_downlinks),
+        )
+        // Protocol packets
+        .api_route(
+            "/api/protocol/packets",
+            aide::axum::routing::post(rest_protocol::inject_packet),
+        )
+        .api_route(
+            "/api/protocol/packets",
+            aide::axum::routing::get(rest_protocol::get_received_packets),
+        )
+        // Diagnostics
+        .api_route(
+            "/api/diagnostics/ping",
+            aide::axum::routing::post(rest_diagnostics::ping),
+        )
+        .api_route(
+            "/api/diagnostics/traceroute",
+            aide::axum::routing::post(rest_diagnostics::traceroute),
+        )
+        .api_route(
+            "/api/diagnostics/measure",
+            aide::axum::routing::post(rest_diagnostics::measure),
+        )
+        .api_route(
+            "/api/diagnostics/callbacks",
+            aide::axum::routing::get(rest_diagnostics::get_runtime_callbacks),
+        )
         // End devices
         .api_route(
             "/api/end_devices",
@@ -136,6 +416,23 @@ pub fn create_api(state: Arc<AppState>) -> Router {
             "/api/end_devices",
             aide::axum::routing::post(rest_end_devices::add_end_devices),
         )
+        .api_route(
+            "/api/end_devices/registry",
+            aide::axum::routing::get(rest_end_devices::list_end_device_registry),
+        )
+        // Groups
+        .api_route(
+            "/api/groups",
+            aide::axum::routing::delete(rest_end_devices::delete_groups),
+        )
+        .api_route(
+            "/api/groups",
+            aide::axum::routing::get(rest_end_devices::list_groups),
+        )
+        .api_route(
+            "/api/groups",
+            aide::axum::routing::post(rest_end_devices::add_groups),
+        )
         // Restart
         .api_route(
             "/api/restart_pending",
@@ -145,11 +442,18 @@ pub fn create_api(state: Arc<AppState>) -> Router {
             "/api/restart",
             aide::axum::routing::post(rest_restart::restart),
         )
-        .route("/ws", axum::routing::get(websockets::ws_handler))
+        .route("/ws", axum::routing::get(websockets::ws_handler));
+    #[cfg(feature = "dashboard")]
+    let router = dashboard::add_dashboard_routes(router);
+    router
         .with_state(state)
         // Redoc route needs to be added after state as work around: https://github.com/tamasfe/aide/issues/26
         .route("/redoc", Redoc::new("/api.json").axum_route())
         .finish_api(&mut api)
+        .layer(axum::middleware::from_fn_with_state(
+            Arc::new(authorization),
+            authorize,
+        ))
         .layer(CorsLayer::permissive())
         .layer(Extension(api))
         .layer(
@@ -0,0 +1,357 @@
+//! Structured errors of the REST and WebSocket API.
+//!
+//! Every error is returned as JSON object with a stable, machine-readable [`ApiErrorCode`], an
+//! English message for humans and optional details, e.g.
+//! `{"code": "RELAY_QUEUE_FULL", "message": "Relay packet queue is full"}`. Clients react to and
+//! translate the code, the message may change between versions.
+
+use crate::error::{
+    AirtimeQuotaError, BundleResendError, BundleSendBufferConversionError,
+    BundleSendBufferCreationError, DbError, DiagnosticsError, MeasurementError,
+    OversizeBundleError, QueueOperationError, RoutingHintsError,
+};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use schemars::JsonSchema;
+use serde::Serialize;
+use serde_json::json;
+
+/// Stable codes of the API errors, serialized in `SCREAMING_SNAKE_CASE`.
+///
+/// Codes are only ever added, never renamed or reused for another meaning.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ApiErrorCode {
+    /// The database is read-only, nothing can be persisted until the next restart.
+    DatabaseReadOnly,
+    /// Reading from or writing to the database failed.
+    DatabaseError,
+    /// The response could not be serialized.
+    SerializationFailed,
+    /// No queued bundle at the requested index.
+    BundleNotFound,
+    /// Too many bundles are pinned already.
+    TooManyPinned,
+    /// Too many bundles are frozen already.
+    TooManyFrozen,
+    /// The bundle is frozen and cannot be pinned.
+    BundleFrozen,
+    /// No end device ID is managed by this node to receive replies.
+    NoLocalEndDeviceId,
+    /// The relay packet queue is full.
+    RelayQueueFull,
+    /// The queue of submitted bundles is full.
+    BundleQueueFull,
+    /// No echo reply was received in time.
+    EchoTimeout,
+    /// A radio silence window starts and ends at the same time.
+    InvalidRadioSilenceWindow,
+    /// The node is relay-only and does not serve local bundles.
+    RelayOnlyNode,
+    /// No codec is registered for the payload profile.
+    UnknownPayloadProfile,
+    /// The payload could not be encoded with the payload profile.
+    PayloadEncodingFailed,
+    /// The client consumed its daily airtime quota.
+    AirtimeQuotaExceeded,
+    /// The bundle has no payload block.
+    MissingPayload,
+    /// The bundle payload is too large to be sent at the lowest data rate.
+    PayloadTooLarge,
+    /// The source or destination of the bundle is no DTN address of an end device ID.
+    InvalidEndpointId,
+    /// The creation timestamp of the bundle is invalid.
+    InvalidCreationTimestamp,
+    /// The request carries no bearer token or an unknown one.
+    Unauthorized,
+    /// The role of the token does not permit the request.
+    InsufficientRole,
+    /// The routing hints of the bundle are invalid or cannot be honored.
+    InvalidRoutingHints,
+    /// Another measurement is running.
+    MeasurementRunning,
+    /// The parameters of the measurement are invalid.
+    InvalidMeasurementParameters,
+    /// The frequency to lock out is not in any sub band.
+    InvalidLockout,
+    /// The frequency or sub band is not locked out.
+    LockoutNotFound,
+    /// The node is not blacklisted.
+    NodeNotBlacklisted,
+    /// The batch size or the size cap of WebSocket deliveries is zero.
+    InvalidBatchParameters,
+    /// The end device ID is not managed by this node.
+    NotLocalEndDeviceId,
+    /// No payload profile is configured to compress oversize bundles.
+    CompressionUnavailable,
+    /// No bundle mailbox is configured to poll delivered bundles from.
+    BundleMailboxUnavailable,
+}
+
+impl ApiErrorCode {
+    /// Returns the HTTP status code the error is returned with by the REST API.
+    pub fn status(self) -> StatusCode {
+        match self {
+            ApiErrorCode::DatabaseReadOnly
+            | ApiErrorCode::RelayQueueFull
+            | ApiErrorCode::BundleQueueFull => StatusCode::SERVICE_UNAVAILABLE,
+            ApiErrorCode::DatabaseError | ApiErrorCode::SerializationFailed => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+            ApiErrorCode::BundleNotFound
+            | ApiErrorCode::LockoutNotFound
+            | ApiErrorCode::NodeNotBlacklisted => StatusCode::NOT_FOUND,
+            ApiErrorCode::TooManyPinned
+            | ApiErrorCode::TooManyFrozen
+            | ApiErrorCode::BundleFrozen
+            | ApiErrorCode::NoLocalEndDeviceId
+            | ApiErrorCode::MeasurementRunning => StatusCode::CONFLICT,
+            ApiErrorCode::EchoTimeout => StatusCode::GATEWAY_TIMEOUT,
+            ApiErrorCode::InvalidRadioSilenceWindow
+            | ApiErrorCode::UnknownPayloadProfile
+            | ApiErrorCode::PayloadEncodingFailed
+            | ApiErrorCode::MissingPayload
+            | ApiErrorCode::InvalidEndpointId
+            | ApiErrorCode::InvalidCreationTimestamp
+            | ApiErrorCode::InvalidRoutingHints
+            | ApiErrorCode::InvalidMeasurementParameters
+            | ApiErrorCode::InvalidLockout
+            | ApiErrorCode::InvalidBatchParameters
+            | ApiErrorCode::NotLocalEndDeviceId
+            | ApiErrorCode::CompressionUnavailable
+            | ApiErrorCode::BundleMailboxUnavailable => StatusCode::BAD_REQUEST,
+            ApiErrorCode::Unauthorized => StatusCode::UNAUTHORIZED,
+            ApiErrorCode::RelayOnlyNode | ApiErrorCode::InsufficientRole => StatusCode::FORBIDDEN,
+            ApiErrorCode::AirtimeQuotaExceeded => StatusCode::TOO_MANY_REQUESTS,
+            ApiErrorCode::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+        }
+    }
+}
+
+/// Error returned by the API as JSON.
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+pub struct ApiError {
+    /// Stable code of the error.
+    pub code: ApiErrorCode,
+    /// Description of the error in English, not meant to be parsed.
+    pub message: String,
+    /// Values of the error the message is built from, e.g. to translate it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<serde_json::Value>,
+}
+
+impl ApiError {
+    /// Creates an error without details.
+    pub fn new(code: ApiErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            details: None,
+        }
+    }
+
+    /// Adds details to the error.
+    #[must_use]
+    pub fn with_details(mut self, details: serde_json::Value) -> Self {
+        self.details = Some(details);
+        self
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.code.status(), Json(self)).into_response()
+    }
+}
+
+impl From<DbError> for ApiError {
+    fn from(err: DbError) -> Self {
+        let code = match err {
+            DbError::ReadOnly => ApiErrorCode::DatabaseReadOnly,
+            _ => ApiErrorCode::DatabaseError,
+        };
+        ApiError::new(code, err.to_string())
+    }
+}
+
+impl From<serde_json::Error> for ApiError {
+    fn from(err: serde_json::Error) -> Self {
+        ApiError::new(ApiErrorCode::SerializationFailed, err.to_string())
+    }
+}
+
+impl From<DiagnosticsError> for ApiError {
+    fn from(err: DiagnosticsError) -> Self {
+        let code = match err {
+            DiagnosticsError::NoLocalEndDeviceId => ApiErrorCode::NoLocalEndDeviceId,
+            DiagnosticsError::QueueFull => ApiErrorCode::RelayQueueFull,
+            DiagnosticsError::Timeout => ApiErrorCode::EchoTimeout,
+        };
+        ApiError::new(code, err.to_string())
+    }
+}
+
+impl From<BundleResendError> for ApiError {
+    fn from(err: BundleResendError) -> Self {
+        let code = match err {
+            BundleResendError::RelayOnlyNode => ApiErrorCode::RelayOnlyNode,
+            BundleResendError::NotLocal { .. } => ApiErrorCode::NotLocalEndDeviceId,
+            BundleResendError::QueueFull => ApiErrorCode::RelayQueueFull,
+        };
+        ApiError::new(code, err.to_string())
+    }
+}
+
+impl From<QueueOperationError> for ApiError {
+    fn from(err: QueueOperationError) -> Self {
+        let (code, details) = match err {
+            QueueOperationError::NoSuchItem { index } => {
+                (ApiErrorCode::BundleNotFound, json!({ "index": index }))
+            }
+            QueueOperationError::TooManyPinned { max } => {
+                (ApiErrorCode::TooManyPinned, json!({ "max": max }))
+            }
+            QueueOperationError::TooManyFrozen { max } => {
+                (ApiErrorCode::TooManyFrozen, json!({ "max": max }))
+            }
+            QueueOperationError::Frozen { index } => {
+                (ApiErrorCode::BundleFrozen, json!({ "index": index }))
+            }
+        };
+        ApiError::new(code, err.to_string()).with_details(details)
+    }
+}
+
+impl From<AirtimeQuotaError> for ApiError {
+    fn from(err: AirtimeQuotaError) -> Self {
+        let message = err.to_string();
+        match err {
+            AirtimeQuotaError::DailyQuotaExceeded {
+                client,
+                used_ms,
+                quota_ms,
+            } => ApiError::new(ApiErrorCode::AirtimeQuotaExceeded, message).with_details(json!({
+                "client": client,
+                "used_ms": used_ms,
+                "quota_ms": quota_ms,
+            })),
+        }
+    }
+}
+
+impl From<BundleSendBufferConversionError> for ApiError {
+    fn from(err: BundleSendBufferConversionError) -> Self {
+        let code = match err {
+            BundleSendBufferConversionError::NoPayload => ApiErrorCode::MissingPayload,
+            BundleSendBufferConversionError::TryFromTimestampError => {
+                ApiErrorCode::InvalidCreationTimestamp
+            }
+            BundleSendBufferConversionError::TryFromEndpointId(_) => {
+                ApiErrorCode::InvalidEndpointId
+            }
+            BundleSendBufferConversionError::BundleSendBuffer(
+                BundleSendBufferCreationError::PayloadTooLarge,
+            ) => ApiErrorCode::PayloadTooLarge,
+        };
+        ApiError::new(code, err.to_string())
+    }
+}
+
+impl From<OversizeBundleError> for ApiError {
+    fn from(err: OversizeBundleError) -> Self {
+        let message = err.to_string();
+        match err {
+            OversizeBundleError::Conversion(err) => err.into(),
+            OversizeBundleError::NoCompressionProfile => {
+                ApiError::new(ApiErrorCode::CompressionUnavailable, message)
+            }
+            OversizeBundleError::Compression(_) => {
+                ApiError::new(ApiErrorCode::PayloadEncodingFailed, message)
+            }
+            OversizeBundleError::StillTooLarge { size, max } => {
+                ApiError::new(ApiErrorCode::PayloadTooLarge, message)
+                    .with_details(json!({ "size": size, "max": max }))
+            }
+        }
+    }
+}
+
+impl From<RoutingHintsError> for ApiError {
+    fn from(err: RoutingHintsError) -> Self {
+        let error = ApiError::new(ApiErrorCode::InvalidRoutingHints, err.to_string());
+        match err {
+            RoutingHintsError::TooLargeForSinglePacket { size, max } => {
+                error.with_details(json!({ "size": size, "max": max }))
+            }
+            RoutingHintsError::DestinationTooFar {
+                hop_distance,
+                max_hops,
+            } => error.with_details(json!({ "hop_distance": hop_distance, "max_hops": max_hops })),
+            RoutingHintsError::ZeroMaxHops | RoutingHintsError::Expired => error,
+        }
+    }
+}
+
+impl From<MeasurementError> for ApiError {
+    fn from(err: MeasurementError) -> Self {
+        let code = match err {
+            MeasurementError::AlreadyRunning => ApiErrorCode::MeasurementRunning,
+            MeasurementError::InvalidBundleAmount { max } => {
+                return ApiError::new(ApiErrorCode::InvalidMeasurementParameters, err.to_string())
+                    .with_details(json!({ "max": max }));
+            }
+            MeasurementError::EmptyPayload | MeasurementError::ZeroInterval => {
+                ApiErrorCode::InvalidMeasurementParameters
+            }
+            MeasurementError::NoLocalEndDeviceId => ApiErrorCode::NoLocalEndDeviceId,
+            MeasurementError::EndpointId(_) | MeasurementError::PrimaryBuilder(_) => {
+                ApiErrorCode::InvalidEndpointId
+            }
+            MeasurementError::Conversion(err) => return ApiError::from(err),
+            MeasurementError::QueueClosed => ApiErrorCode::BundleQueueFull,
+        };
+        ApiError::new(code, err.to_string())
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use crate::api::api_error::{ApiError, ApiErrorCode};
+    use crate::error::{AirtimeQuotaError, DbError, QueueOperationError};
+    use axum::http::StatusCode;
+    use serde_json::json;
+
+    #[test]
+    fn errors_serialize_with_stable_codes() {
+        let error = ApiError::from(QueueOperationError::TooManyPinned { max: 2 });
+        assert_eq!(error.code.status(), StatusCode::CONFLICT);
+        assert_eq!(
+            serde_json::to_value(&error).unwrap(),
+            json!({
+                "code": "TOO_MANY_PINNED",
+                "message": "At most 2 bundles can be pinned",
+                "details": {"max": 2},
+            })
+        );
+
+        let error = ApiError::from(DbError::ReadOnly);
+        assert_eq!(error.code.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            serde_json::to_value(&error).unwrap(),
+            json!({"code": "DATABASE_READ_ONLY", "message": "Database is read-only"})
+        );
+
+        let error = ApiError::from(AirtimeQuotaError::DailyQuotaExceeded {
+            client: "app".to_owned(),
+            used_ms: 36_500,
+            quota_ms: 36_000,
+        });
+        assert_eq!(
+            serde_json::to_value(&error).unwrap()["code"],
+            json!("AIRTIME_QUOTA_EXCEEDED")
+        );
+        assert_eq!(error.details.unwrap()["used_ms"], json!(36_500));
+    }
+}
@@ -0,0 +1,339 @@
+//! Role-based authorization of the HTTP API with bearer tokens.
+//!
+//! Every route declares the [`ApiRole`] it requires in [`ROUTE_POLICIES`]. Undeclared routes
+//! require [`ApiRole::ReadOnly`] for `GET` and [`ApiRole::Admin`] for all other methods, so a new
+//! mutating route is never accidentally open to read-only tokens. Without configured tokens the
+//! API stays open to everyone.
+
+use crate::api::api_error::{ApiError, ApiErrorCode};
+use crate::configuration::{ApiRole, ApiTokenConfig};
+use axum::extract::{MatchedPath, State};
+use axum::http::{header, Method, Request};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use serde_json::json;
+use std::sync::Arc;
+use tracing::{trace, warn};
+
+/// Role a route requires.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RoutePolicy {
+    /// Method of the route.
+    pub method: &'static str,
+    /// Path of the route as registered, including path parameters.
+    pub path: &'static str,
+    /// Lowest role permitted to call the route, open to everyone if not set.
+    pub role: Option<ApiRole>,
+}
+
+/// Query parameter carrying the token if the client cannot set the `Authorization` header, e.g.
+/// WebSocket connections of browsers.
+const TOKEN_QUERY_PARAMETER: &str = "token";
+
+/// Routes accepting the token as query parameter, other routes only read the `Authorization`
+/// header as query strings end up in the request logs of the API and of proxies.
+const QUERY_TOKEN_ROUTES: &[&str] = &["/ws", "/ui", "/ui/*path"];
+
+/// Declared roles of the routes deviating from the default.
+pub const ROUTE_POLICIES: &[RoutePolicy] = &[
+    // Static routes, the dashboard authorizes its API requests itself
+    public("GET", "/api.json"),
+    public("GET", "/redoc"),
+    public("GET", "/ui"),
+    public("GET", "/ui/*path"),
+    // Validating a configuration changes nothing
+    role("POST", "/api/config/validate", ApiRole::ReadOnly),
+    // Bundles
+    role("GET", "/ws", ApiRole::Operator),
+    role("GET", "/api/bundles/poll", ApiRole::Operator),
+    role("POST", "/api/queues/message_queue/pin", ApiRole::Operator),
+    role(
+        "POST",
+        "/api/queues/message_queue/deprioritize",
+        ApiRole::Operator,
+    ),
+    role(
+        "POST",
+        "/api/queues/message_queue/freeze",
+        ApiRole::Operator,
+    ),
+    role("POST", "/api/bundles/resend", ApiRole::Operator),
+    // Control of the running node
+    role("POST", "/api/radio_silence", ApiRole::Operator),
+    role("POST", "/api/radio_silence/windows", ApiRole::Operator),
+    role("POST", "/api/control/relaying", ApiRole::Operator),
+    role("POST", "/api/control/announcements", ApiRole::Operator),
+    role("POST", "/api/protocol/packets", ApiRole::Operator),
+    role("POST", "/api/diagnostics/ping", ApiRole::Operator),
+    role("POST", "/api/diagnostics/traceroute", ApiRole::Operator),
+    role("POST", "/api/diagnostics/measure", ApiRole::Operator),
+    // Administration
+    // The ChirpStack configuration contains the ChirpStack API token
+    role("GET", "/api/config/current/chirpstack", ApiRole::Admin),
+    role("GET", "/api/config/next/chirpstack", ApiRole::Admin),
+    role("POST", "/api/config/next/bind", ApiRole::Admin),
+    role("POST", "/api/config/next/chirpstack", ApiRole::Admin),
+    role("POST", "/api/config/next/mqtt", ApiRole::Admin),
+    role("POST", "/api/config/next/packet_cache", ApiRole::Admin),
+    role("POST", "/api/config/next/queues", ApiRole::Admin),
+    role("DELETE", "/api/packet_cache", ApiRole::Admin),
+    role("POST", "/api/end_devices", ApiRole::Admin),
+    role("DELETE", "/api/end_devices", ApiRole::Admin),
+    role("POST", "/api/groups", ApiRole::Admin),
+    role("DELETE", "/api/groups", ApiRole::Admin),
+    role("POST", "/api/restart", ApiRole::Admin),
+];
+
+/// Declares a route requiring the role.
+const fn role(method: &'static str, path: &'static str, role: ApiRole) -> RoutePolicy {
+    RoutePolicy {
+        method,
+        path,
+        role: Some(role),
+    }
+}
+
+/// Declares a route open to everyone.
+const fn public(method: &'static str, path: &'static str) -> RoutePolicy {
+    RoutePolicy {
+        method,
+        path,
+        role: None,
+    }
+}
+
+/// Returns the role the route requires, `None` if it is open to everyone.
+pub fn required_role(method: &Method, path: &str) -> Option<ApiRole> {
+    match ROUTE_POLICIES
+        .iter()
+        .find(|policy| policy.method == method.as_str() && policy.path == path)
+    {
+        Some(policy) => policy.role,
+        None if method == Method::GET => Some(ApiRole::ReadOnly),
+        None => Some(ApiRole::Admin),
+    }
+}
+
+/// Configured tokens of the HTTP API.
+#[derive(Debug, Clone)]
+pub struct ApiAuthorization {
+    /// Tokens with their roles.
+    tokens: Vec<ApiTokenConfig>,
+}
+
+impl ApiAuthorization {
+    /// Creates a new [`ApiAuthorization`], every request is permitted if there are no tokens.
+    pub fn new(tokens: Vec<ApiTokenConfig>) -> Self {
+        Self { tokens }
+    }
+
+    /// Checks whether the token permits a request to the route.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if:
+    /// - the token is missing or unknown.
+    /// - the role of the token is lower than the role required by the route.
+    pub fn authorize(
+        &self,
+        method: &Method,
+        path: &str,
+        token: Option<&str>,
+    ) -> Result<(), ApiError> {
+        let Some(required) = required_role(method, path) else {
+            return Ok(());
+        };
+        if self.tokens.is_empty() {
+            return Ok(());
+        }
+        let Some(api_token) = token.and_then(|token| self.find_token(token)) else {
+            return Err(ApiError::new(
+                ApiErrorCode::Unauthorized,
+                "Missing or unknown API token",
+            ));
+        };
+        if api_token.role < required {
+            warn!(
+                "Denied {method} {path} to \"{}\" with role {:?}",
+                api_token.name, api_token.role
+            );
+            return Err(ApiError::new(
+                ApiErrorCode::InsufficientRole,
+                format!("The request requires the role {required:?}"),
+            )
+            .with_details(json!({ "required": required, "role": api_token.role })));
+        }
+        Ok(())
+    }
+
+    /// Returns the configured token matching the token of a request.
+    fn find_token(&self, token: &str) -> Option<&ApiTokenConfig> {
+        self.tokens
+            .iter()
+            .find(|api_token| constant_time_eq(api_token.token.as_bytes(), token.as_bytes()))
+    }
+}
+
+/// Compares the bytes in time independent of the position of the first difference.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// Extracts the token from the `Authorization: Bearer` header or, on the [`QUERY_TOKEN_ROUTES`],
+/// the `token` query parameter.
+fn request_token<'a, B>(request: &'a Request<B>, path: &str) -> Option<&'a str> {
+    request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .or_else(|| {
+            if !QUERY_TOKEN_ROUTES.contains(&path) {
+                return None;
+            }
+            request.uri().query().and_then(|query| {
+                query.split('&').find_map(|pair| {
+                    pair.strip_prefix(TOKEN_QUERY_PARAMETER)
+                        .and_then(|rest| rest.strip_prefix('='))
+                })
+            })
+        })
+}
+
+/// Middleware rejecting requests whose token does not permit the matched route.
+pub async fn authorize<B>(
+    State(authorization): State<Arc<ApiAuthorization>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let path = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or_else(|| request.uri().path(), MatchedPath::as_str)
+        .to_owned();
+    if let Err(err) =
+        authorization.authorize(request.method(), &path, request_token(&request, &path))
+    {
+        trace!("Unauthorized request to {path}: {}", err.message);
+        return err.into_response();
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use crate::api::api_error::ApiErrorCode;
+    use crate::api::authorization::{request_token, required_role, ApiAuthorization};
+    use crate::configuration::{ApiRole, ApiTokenConfig};
+    use axum::http::{Method, Request};
+
+    #[test]
+    fn routes_require_their_declared_roles() {
+        assert_eq!(
+            required_role(&Method::GET, "/api/stats/neighbors"),
+            Some(ApiRole::ReadOnly)
+        );
+        assert_eq!(
+            required_role(&Method::POST, "/api/control/relaying"),
+            Some(ApiRole::Operator)
+        );
+        assert_eq!(
+            required_role(&Method::DELETE, "/api/packet_cache"),
+            Some(ApiRole::Admin)
+        );
+        assert_eq!(
+            required_role(&Method::POST, "/api/undeclared"),
+            Some(ApiRole::Admin)
+        );
+        assert_eq!(required_role(&Method::GET, "/ui"), None);
+        assert_eq!(
+            required_role(&Method::GET, "/api/config/current/chirpstack"),
+            Some(ApiRole::Admin)
+        );
+        assert_eq!(
+            required_role(&Method::GET, "/api/config/next/chirpstack"),
+            Some(ApiRole::Admin)
+        );
+    }
+
+    #[test]
+    fn query_tokens_are_only_read_on_websocket_and_dashboard() {
+        let request = |uri: &str| Request::builder().uri(uri).body(()).unwrap();
+        let ws = request("/ws?client=app&token=secret");
+        assert_eq!(request_token(&ws, "/ws"), Some("secret"));
+        let status = request("/api/status?token=secret");
+        assert_eq!(request_token(&status, "/api/status"), None);
+        let header = Request::builder()
+            .uri("/api/status")
+            .header("Authorization", "Bearer secret")
+            .body(())
+            .unwrap();
+        assert_eq!(request_token(&header, "/api/status"), Some("secret"));
+    }
+
+    #[test]
+    fn tokens_are_checked_against_the_roles() {
+        let token = |name: &str, role| ApiTokenConfig {
+            name: name.to_owned(),
+            token: format!("{name}-secret"),
+            role,
+        };
+        let authorization = ApiAuthorization::new(vec![
+            token("viewer", ApiRole::ReadOnly),
+            token("operator", ApiRole::Operator),
+            token("admin", ApiRole::Admin),
+        ]);
+        let code = |method, path, token| {
+            authorization
+                .authorize(&method, path, token)
+                .err()
+                .map(|err| err.code)
+        };
+
+        assert_eq!(
+            code(Method::GET, "/api/status", None),
+            Some(ApiErrorCode::Unauthorized)
+        );
+        assert_eq!(
+            code(Method::GET, "/api/status", Some("unknown")),
+            Some(ApiErrorCode::Unauthorized)
+        );
+        assert_eq!(
+            code(Method::GET, "/api/status", Some("viewer-secret")),
+            None
+        );
+        assert_eq!(
+            code(Method::POST, "/api/control/relaying", Some("viewer-secret")),
+            Some(ApiErrorCode::InsufficientRole)
+        );
+        assert_eq!(
+            code(
+                Method::POST,
+                "/api/control/relaying",
+                Some("operator-secret")
+            ),
+            None
+        );
+        assert_eq!(
+            code(Method::POST, "/api/restart", Some("operator-secret")),
+            Some(ApiErrorCode::InsufficientRole)
+        );
+        assert_eq!(
+            code(Method::POST, "/api/restart", Some("admin-secret")),
+            None
+        );
+        assert_eq!(code(Method::GET, "/ui", None), None);
+        assert_eq!(
+            code(
+                Method::GET,
+                "/api/config/current/chirpstack",
+                Some("viewer-secret")
+            ),
+            Some(ApiErrorCode::InsufficientRole)
+        );
+
+        let open = ApiAuthorization::new(Vec::new());
+        assert!(open.authorize(&Method::POST, "/api/restart", None).is_ok());
+    }
+}
@@ -0,0 +1,84 @@
+//! Operator dashboard served at `/ui`.
+//!
+//! The single-page dashboard in the `dashboard` directory is embedded into the binary and only
+//! consumes the REST API, so operators in the field need nothing but a browser.
+
+use aide::axum::ApiRouter;
+use axum::extract::Path;
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use include_dir::{include_dir, Dir};
+use tracing::trace;
+
+/// Assets of the dashboard.
+static DASHBOARD_ASSETS: Dir<'_> = include_dir!("$CARGO_MANIFEST_DIR/dashboard");
+
+/// Entry point of the dashboard.
+const DASHBOARD_INDEX: &str = "index.html";
+
+/// Adds the routes of the dashboard, they are not part of the OpenAPI spec.
+pub fn add_dashboard_routes<S>(router: ApiRouter<S>) -> ApiRouter<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    router
+        .route("/ui", axum::routing::get(serve_dashboard_index))
+        .route("/ui/*path", axum::routing::get(serve_dashboard_asset))
+}
+
+/// Serves the entry point of the dashboard.
+#[allow(clippy::unused_async)]
+pub async fn serve_dashboard_index() -> Response {
+    trace!("Dashboard request");
+
+    serve_asset(DASHBOARD_INDEX)
+}
+
+/// Serves an asset of the dashboard, the entry point if the path is empty.
+#[allow(clippy::unused_async)]
+pub async fn serve_dashboard_asset(Path(path): Path<String>) -> Response {
+    trace!("Dashboard asset request: {path}");
+
+    let path = path.trim_start_matches('/');
+    serve_asset(if path.is_empty() {
+        DASHBOARD_INDEX
+    } else {
+        path
+    })
+}
+
+/// Returns the embedded asset with its content type, `404 Not Found` if there is none.
+fn serve_asset(path: &str) -> Response {
+    match DASHBOARD_ASSETS.get_file(path) {
+        Some(file) => (
+            [(header::CONTENT_TYPE, content_type(path))],
+            file.contents(),
+        )
+            .into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+/// Returns the content type of an asset by its extension.
+fn content_type(path: &str) -> &'static str {
+    match path.rsplit_once('.').map(|(_, extension)| extension) {
+        Some("html") => "text/html; charset=utf-8",
+        Some("js") => "text/javascript; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("svg") => "image/svg+xml",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::dashboard::{content_type, DASHBOARD_ASSETS, DASHBOARD_INDEX};
+
+    #[test]
+    fn assets_are_embedded_with_content_types() {
+        assert!(DASHBOARD_ASSETS.get_file(DASHBOARD_INDEX).is_some());
+        assert!(DASHBOARD_ASSETS.get_file("dashboard.js").is_some());
+        assert_eq!(content_type("dashboard.css"), "text/css; charset=utf-8");
+        assert_eq!(content_type("favicon"), "application/octet-stream");
+    }
+}
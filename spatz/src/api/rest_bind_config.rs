@@ -1,18 +1,21 @@
 //! REST API endpoints for the bind config API.
 
+use crate::api::api_error::ApiError;
 use crate::configuration::BindConfig;
-use crate::database::{insert_into_db, DataKey};
+use crate::database::{persist, DataKey};
 use crate::AppState;
 use aide::axum::IntoApiResponse;
 use axum::extract::State;
 use axum::http::StatusCode;
+use axum::response::IntoResponse;
 use axum::Json;
 use std::sync::Arc;
 use tracing::trace;
 
 /// Sets the address and port to bind to for the next restart of the instance.
 ///
-/// Returns an internal server error if the configuration could not be saved to the database.
+/// Returns an internal server error if the configuration could not be saved to the database and
+/// service unavailable if the database is read-only.
 pub async fn set_next_bind_config(
     State(state): State<Arc<AppState>>,
     Json(bind_config): Json<BindConfig>,
@@ -21,15 +24,15 @@ pub async fn set_next_bind_config(
     let mut config_lock = state.configuration.lock().await;
 
     config_lock.next_configuration.daemon.bind_config = bind_config;
-    match insert_into_db(
+    match persist(
+        &state,
         DataKey::Configuration,
         &config_lock.next_configuration,
-        state.db_pool.clone(),
     )
     .await
     {
-        Ok(_) => StatusCode::OK,
-        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        Ok(()) => StatusCode::OK.into_response(),
+        Err(err) => ApiError::from(err).into_response(),
     }
 }
 
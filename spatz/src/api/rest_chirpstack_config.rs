@@ -1,11 +1,13 @@
 //! REST API endpoints for the ChirpStack config API.
 
+use crate::api::api_error::ApiError;
 use crate::configuration::ChirpStackApiConfig;
-use crate::database::{insert_into_db, DataKey};
+use crate::database::{persist, DataKey};
 use crate::AppState;
 use aide::axum::IntoApiResponse;
 use axum::extract::State;
 use axum::http::StatusCode;
+use axum::response::IntoResponse;
 use axum::Json;
 use std::sync::Arc;
 use tracing::trace;
@@ -46,7 +48,8 @@ pub async fn get_next_chirpstack_config(
 
 /// Sets the ChirpStack configuration for the next restart of the instance.
 ///
-/// Returns an internal server error if the configuration could not be saved to the database.
+/// Returns an internal server error if the configuration could not be saved to the database and
+/// service unavailable if the database is read-only.
 pub async fn set_next_chirpstack_config(
     State(state): State<Arc<AppState>>,
     Json(chirpstack_config): Json<ChirpStackApiConfig>,
@@ -55,14 +58,14 @@ pub async fn set_next_chirpstack_config(
     let mut config_lock = state.configuration.lock().await;
 
     config_lock.next_configuration.chirpstack_api = chirpstack_config;
-    match insert_into_db(
+    match persist(
+        &state,
         DataKey::Configuration,
         &config_lock.next_configuration,
-        state.db_pool.clone(),
     )
     .await
     {
-        Ok(_) => StatusCode::OK,
-        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        Ok(()) => StatusCode::OK.into_response(),
+        Err(err) => ApiError::from(err).into_response(),
     }
 }
@@ -0,0 +1,48 @@
+//! REST API endpoints for the JSON schema and the validation of the whole configuration.
+
+use crate::configuration::Configuration;
+use aide::axum::IntoApiResponse;
+use axum::Json;
+use schemars::JsonSchema;
+use serde::Serialize;
+use tracing::trace;
+
+/// Result of a configuration validation.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct ConfigurationValidation {
+    /// Whether the configuration can be applied.
+    pub valid: bool,
+    /// Problems found in the configuration.
+    pub errors: Vec<String>,
+}
+
+/// Returns the JSON schema of the configuration.
+#[allow(clippy::unused_async)]
+pub async fn get_configuration_schema() -> impl IntoApiResponse {
+    trace!("Configuration schema request");
+
+    Json(schemars::schema_for!(Configuration))
+}
+
+/// Checks a configuration without applying it.
+///
+/// Reports deserialization errors as well as values which cannot be applied.
+#[allow(clippy::unused_async)]
+pub async fn validate_configuration(
+    Json(configuration): Json<serde_json::Value>,
+) -> impl IntoApiResponse {
+    trace!("Configuration validation request");
+    let errors = match serde_json::from_value::<Configuration>(configuration) {
+        Ok(configuration) => configuration
+            .validate()
+            .iter()
+            .map(ToString::to_string)
+            .collect(),
+        Err(err) => vec![err.to_string()],
+    };
+
+    Json(ConfigurationValidation {
+        valid: errors.is_empty(),
+        errors,
+    })
+}
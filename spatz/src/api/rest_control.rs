@@ -0,0 +1,72 @@
+//! REST API endpoints pausing and resuming subsystems at runtime.
+
+use crate::api::api_error::ApiError;
+use crate::database::{persist, DataKey};
+use crate::AppState;
+use aide::axum::IntoApiResponse;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::sync::Arc;
+use tracing::trace;
+
+/// JSON parameter to pause or resume a subsystem.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct PauseJsonParameter {
+    /// Pauses the subsystem if set, resumes it otherwise.
+    pub paused: bool,
+}
+
+/// Returns whether the relaying and the announcements are paused.
+#[allow(clippy::unused_async)]
+pub async fn get_subsystem_control(State(state): State<Arc<AppState>>) -> impl IntoApiResponse {
+    trace!("Subsystem control request");
+    Json(state.subsystem_control.status())
+}
+
+/// Pauses or resumes the relaying of packets for other nodes, takes effect immediately.
+///
+/// Returns an internal server error if the switch could not be saved to the database and service
+/// unavailable if the database is read-only, the switch applies until the next restart then.
+pub async fn set_relaying(
+    State(state): State<Arc<AppState>>,
+    Json(parameter): Json<PauseJsonParameter>,
+) -> impl IntoApiResponse {
+    trace!("Set relaying request: {parameter:?}");
+    state
+        .subsystem_control
+        .set_relaying_paused(parameter.paused);
+    persist_subsystem_control(&state).await
+}
+
+/// Pauses or resumes the announcements, takes effect immediately.
+///
+/// Returns an internal server error if the switch could not be saved to the database and service
+/// unavailable if the database is read-only, the switch applies until the next restart then.
+pub async fn set_announcements(
+    State(state): State<Arc<AppState>>,
+    Json(parameter): Json<PauseJsonParameter>,
+) -> impl IntoApiResponse {
+    trace!("Set announcements request: {parameter:?}");
+    state
+        .subsystem_control
+        .set_announcements_paused(parameter.paused);
+    persist_subsystem_control(&state).await
+}
+
+/// Persists the switches and maps the result to the response.
+async fn persist_subsystem_control(state: &AppState) -> Response {
+    match persist(
+        state,
+        DataKey::SubsystemControl,
+        &state.subsystem_control.status(),
+    )
+    .await
+    {
+        Ok(()) => StatusCode::OK.into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
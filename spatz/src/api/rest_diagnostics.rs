@@ -0,0 +1,158 @@
+//! REST API endpoints for ping, traceroute and measurement diagnostics between Spatz nodes.
+
+use crate::api::api_error::ApiError;
+use crate::diagnostics;
+use crate::end_device_id::EndDeviceId;
+use crate::error::DiagnosticsError;
+use crate::measurement::{self, MeasurementParameter};
+use crate::AppState;
+use aide::axum::IntoApiResponse;
+use axum::extract::State;
+use axum::response::IntoResponse;
+use axum::Json;
+use chirpstack_gwb_integration::gateway_id::GatewayId;
+use chirpstack_gwb_integration::runtime::callbacks::CallbackType;
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::trace;
+
+/// Metadata of a callback registered in the runtime.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct RegisteredCallback {
+    /// ID of the callback.
+    pub uuid: String,
+    /// Gateway the callback is registered for, all gateways if not set.
+    pub gateway_id: Option<GatewayId>,
+    /// Message type the callback is registered for.
+    pub callback_type: CallbackType,
+    /// Time the callback was added.
+    pub registered_at: DateTime<Utc>,
+    /// Label describing the purpose of the callback.
+    pub label: Option<String>,
+}
+
+/// Default hop limit of echo requests.
+const DEFAULT_HOP_LIMIT: u8 = 16;
+
+/// Default time to wait for an echo reply in seconds.
+const DEFAULT_TIMEOUT_SECONDS: u64 = 60;
+
+/// JSON parameter for a ping.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct PingJsonParameter {
+    /// The end device ID to ping.
+    pub destination: EndDeviceId,
+    /// Amount of relays after which the request is answered by the relay, defaults to 16.
+    pub hop_limit: Option<u8>,
+    /// Time to wait for the reply in seconds, defaults to 60.
+    pub timeout_seconds: Option<u64>,
+}
+
+/// JSON parameter for a traceroute.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct TracerouteJsonParameter {
+    /// The end device ID to trace the route to.
+    pub destination: EndDeviceId,
+    /// Max amount of relays to probe, defaults to 16.
+    pub max_hops: Option<u8>,
+    /// Time to wait for each reply in seconds, defaults to 60.
+    pub timeout_seconds: Option<u64>,
+}
+
+/// Sends an echo request to the destination and returns the round trip time and hop count.
+///
+/// Returns gateway timeout if no reply is received in time, service unavailable if the relay
+/// queue is full and conflict if no end device ID is managed by this node.
+pub async fn ping(
+    State(state): State<Arc<AppState>>,
+    Json(ping): Json<PingJsonParameter>,
+) -> impl IntoApiResponse {
+    trace!("Ping request: {ping:?}");
+    match diagnostics::ping(
+        &state,
+        ping.destination,
+        ping.hop_limit.unwrap_or(DEFAULT_HOP_LIMIT),
+        Duration::from_secs(ping.timeout_seconds.unwrap_or(DEFAULT_TIMEOUT_SECONDS)),
+    )
+    .await
+    {
+        Ok(ping_result) => Json(ping_result).into_response(),
+        Err(err) => diagnostics_error_response(err),
+    }
+}
+
+/// Probes the path to the destination with increasing hop limits and returns the replying
+/// relays.
+///
+/// Returns service unavailable if the relay queue is full and conflict if no end device ID is
+/// managed by this node.
+pub async fn traceroute(
+    State(state): State<Arc<AppState>>,
+    Json(traceroute): Json<TracerouteJsonParameter>,
+) -> impl IntoApiResponse {
+    trace!("Traceroute request: {traceroute:?}");
+    match diagnostics::traceroute(
+        &state,
+        traceroute.destination,
+        traceroute.max_hops.unwrap_or(DEFAULT_HOP_LIMIT),
+        Duration::from_secs(
+            traceroute
+                .timeout_seconds
+                .unwrap_or(DEFAULT_TIMEOUT_SECONDS),
+        ),
+    )
+    .await
+    {
+        Ok(hops) => Json(hops).into_response(),
+        Err(err) => diagnostics_error_response(err),
+    }
+}
+
+/// Sends a train of test bundles to the destination and returns the delivery ratio, the latency
+/// distribution and the airtime cost once all deliveries were reported or the timeout elapsed.
+///
+/// Returns conflict if another measurement is running or no end device ID is managed by this
+/// node and bad request if the parameters are invalid.
+pub async fn measure(
+    State(state): State<Arc<AppState>>,
+    Json(parameter): Json<MeasurementParameter>,
+) -> impl IntoApiResponse {
+    trace!("Measurement request: {parameter:?}");
+    match measurement::measure(&state, &parameter).await {
+        Ok(report) => Json(report).into_response(),
+        Err(err) => {
+            trace!(%err);
+            ApiError::from(err).into_response()
+        }
+    }
+}
+
+/// Maps a [`DiagnosticsError`] to the response.
+fn diagnostics_error_response(err: DiagnosticsError) -> axum::response::Response {
+    trace!(%err);
+    ApiError::from(err).into_response()
+}
+
+/// Returns the callbacks registered in the runtime, oldest first, to find leaked callbacks.
+pub async fn get_runtime_callbacks(State(state): State<Arc<AppState>>) -> impl IntoApiResponse {
+    trace!("Runtime callbacks request");
+
+    Json(
+        state
+            .runtime
+            .list_callbacks()
+            .await
+            .into_iter()
+            .map(|callback_info| RegisteredCallback {
+                uuid: callback_info.uuid.to_string(),
+                gateway_id: callback_info.gateway_id,
+                callback_type: callback_info.callback_type,
+                registered_at: DateTime::<Utc>::from(callback_info.registered_at),
+                label: callback_info.label,
+            })
+            .collect::<Vec<_>>(),
+    )
+}
@@ -1,15 +1,151 @@
 //! REST API endpoints for the duty cycle API.
 
+use crate::api::api_error::{ApiError, ApiErrorCode};
+use crate::database::{persist, DataKey};
+use crate::duty_cycle_manager::EuSubBand;
+use crate::frequency_lockouts::{Lockout, LockoutTarget};
 use crate::AppState;
 use aide::axum::IntoApiResponse;
-use axum::extract::State;
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
 use axum::Json;
+use chirpstack_gwb_integration::gateway_id::GatewayId;
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::Deserialize;
 use std::sync::Arc;
 use tracing::trace;
 
+/// JSON parameter to lock out a frequency or sub band.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct LockoutJsonParameter {
+    /// Frequency in Hz or sub band to lock out.
+    pub target: LockoutTarget,
+    /// Reason of the lockout, e.g. the reference of the complaint.
+    pub reason: String,
+    /// Duration of the lockout starting now, lasts until lifted if not set.
+    pub duration_seconds: Option<u32>,
+}
+
+/// JSON parameter to lift the lockout of a frequency or sub band.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct LiftLockoutJsonParameter {
+    /// Locked out frequency in Hz or sub band.
+    pub target: LockoutTarget,
+}
+
+/// Query parameters for the scheduling journal.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct SchedulingJournalQuery {
+    /// Gateway whose decisions are returned, decisions of all gateways if not set.
+    pub gateway_id: Option<GatewayId>,
+    /// Time of the oldest returned decision, all kept decisions if not set.
+    pub since: Option<DateTime<Utc>>,
+}
+
 /// Returns the currently active packet cache configuration.
 pub async fn get_duty_cycle_stats(State(state): State<Arc<AppState>>) -> impl IntoApiResponse {
     trace!("Duty cycle stats request");
 
     Json(state.duty_cycle_manager.lock().await.stats())
 }
+
+/// Returns the daily airtime quota and the airtime usage of every API client.
+pub async fn get_client_airtime_stats(State(state): State<Arc<AppState>>) -> impl IntoApiResponse {
+    trace!("Client airtime stats request");
+
+    Json(state.client_airtime.stats().await)
+}
+
+/// Returns the recorded transmission decisions matching the query, oldest first, not set if the
+/// scheduling journal is not configured.
+#[allow(clippy::unused_async)]
+pub async fn get_scheduling_journal(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<SchedulingJournalQuery>,
+) -> impl IntoApiResponse {
+    trace!("Scheduling journal request: {query:?}");
+
+    Json(
+        state
+            .scheduling_journal
+            .as_ref()
+            .map(|journal| journal.query(query.gateway_id.as_ref(), query.since)),
+    )
+}
+
+/// Returns the active lockouts of frequencies and sub bands.
+#[allow(clippy::unused_async)]
+pub async fn get_lockouts(State(state): State<Arc<AppState>>) -> impl IntoApiResponse {
+    trace!("Lockouts request");
+
+    Json(state.frequency_lockouts.active(Utc::now()))
+}
+
+/// Locks out a frequency or sub band, replacing an earlier lockout of it. Takes effect
+/// immediately, downlinks already queued for it are dropped.
+///
+/// Returns bad request if the frequency is not in any sub band, an internal server error if the
+/// lockout could not be saved to the database and service unavailable if the database is
+/// read-only, the lockout applies until the next restart then.
+pub async fn lock_out(
+    State(state): State<Arc<AppState>>,
+    Json(parameter): Json<LockoutJsonParameter>,
+) -> impl IntoApiResponse {
+    trace!("Lock out request: {parameter:?}");
+
+    if let LockoutTarget::Frequency(freq) = parameter.target {
+        if EuSubBand::try_from_freq(freq).is_err() {
+            return ApiError::new(
+                ApiErrorCode::InvalidLockout,
+                format!("Frequency {freq} is not in any sub band"),
+            )
+            .into_response();
+        }
+    }
+    let since = Utc::now();
+    state.frequency_lockouts.lock_out(Lockout {
+        target: parameter.target,
+        reason: parameter.reason,
+        since,
+        until: parameter
+            .duration_seconds
+            .map(|duration_seconds| since + chrono::Duration::seconds(i64::from(duration_seconds))),
+    });
+    persist_lockouts(&state).await
+}
+
+/// Lifts the lockout of a frequency or sub band, takes effect immediately.
+///
+/// Returns not found if the target is not locked out and the same errors as [`lock_out`] if the
+/// lockouts could not be saved.
+pub async fn lift_lockout(
+    State(state): State<Arc<AppState>>,
+    Json(parameter): Json<LiftLockoutJsonParameter>,
+) -> impl IntoApiResponse {
+    trace!("Lift lockout request: {parameter:?}");
+
+    if !state.frequency_lockouts.lift(parameter.target) {
+        return ApiError::new(
+            ApiErrorCode::LockoutNotFound,
+            format!("{:?} is not locked out", parameter.target),
+        )
+        .into_response();
+    }
+    persist_lockouts(&state).await
+}
+
+/// Persists the active lockouts and maps the result to the response.
+async fn persist_lockouts(state: &AppState) -> Response {
+    match persist(
+        state,
+        DataKey::FrequencyLockouts,
+        &state.frequency_lockouts.active(Utc::now()),
+    )
+    .await
+    {
+        Ok(()) => StatusCode::OK.into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
@@ -1,19 +1,18 @@
 //! REST API endpoints for the end device API.
 
-use crate::database::{insert_into_db, DataKey};
+use crate::database::{persist, DataKey};
 use crate::end_device_id::ManagedEndDeviceId;
+use crate::end_device_registry::EndDeviceCategory;
 use crate::error::DbError;
-use crate::{AppState, SpatzConfig};
+use crate::AppState;
 use aide::axum::IntoApiResponse;
-use axum::extract::State;
+use axum::extract::{Query, State};
 use axum::http::StatusCode;
 use axum::Json;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use sqlx::SqlitePool;
 use std::collections::HashSet;
 use std::sync::Arc;
-use tokio::sync::Mutex;
 use tracing::trace;
 
 /// JSON parameter and response for end device numbers.
@@ -34,13 +33,7 @@ pub async fn delete_end_devices(
         end_device_id_lock.remove(&number.into());
     }
     let updated_end_device_ids = end_device_id_lock.clone();
-    if let Err(err) = update_config_end_device_ids(
-        updated_end_device_ids,
-        state.configuration.clone(),
-        state.db_pool.clone(),
-    )
-    .await
-    {
+    if let Err(err) = update_config_end_device_ids(updated_end_device_ids, &state).await {
         trace!("Error writing config to database: {err}");
     }
 
@@ -75,13 +68,7 @@ pub async fn add_end_devices(
             end_device_id_lock.insert(end_device_id);
         });
     let updated_end_device_ids = end_device_id_lock.clone();
-    if let Err(err) = update_config_end_device_ids(
-        updated_end_device_ids,
-        state.configuration.clone(),
-        state.db_pool.clone(),
-    )
-    .await
-    {
+    if let Err(err) = update_config_end_device_ids(updated_end_device_ids, &state).await {
         trace!("Error writing config to database: {err}");
     }
 
@@ -95,23 +82,123 @@ pub async fn add_end_devices(
 /// Returns an error if the database returned an error.
 async fn update_config_end_device_ids(
     end_device_ids: HashSet<ManagedEndDeviceId>,
-    config: Arc<Mutex<SpatzConfig>>,
-    db_pool: SqlitePool,
+    state: &AppState,
 ) -> Result<(), DbError> {
     let updated_end_device_ids = end_device_ids.into_iter().fold(Vec::new(), |mut acc, id| {
         acc.push(id.phone_number());
         acc
     });
-    let mut config_lock = config.lock().await;
+    let mut config_lock = state.configuration.lock().await;
     config_lock
         .currently_active_configuration
         .daemon
         .end_device_ids = updated_end_device_ids.clone();
     config_lock.next_configuration.daemon.end_device_ids = updated_end_device_ids;
-    insert_into_db(
+    persist(
+        state,
+        DataKey::Configuration,
+        &config_lock.next_configuration,
+    )
+    .await
+}
+
+/// JSON parameter and response for group IDs.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct GroupIdsJsonParameter {
+    /// Group IDs.
+    pub group_ids: Vec<String>,
+}
+
+/// Handler to leave groups, packets addressed to them are only relayed afterwards. Will always
+/// return HTTP 200.
+pub async fn delete_groups(
+    State(state): State<Arc<AppState>>,
+    Json(group_ids): Json<GroupIdsJsonParameter>,
+) -> impl IntoApiResponse {
+    trace!("Leaving groups: {:?}", group_ids.group_ids);
+    let mut group_id_lock = state.group_ids.lock().await;
+    for group_id in group_ids.group_ids {
+        group_id_lock.remove(&group_id.into());
+    }
+    let updated_group_ids = group_id_lock.clone();
+    if let Err(err) = update_config_group_ids(updated_group_ids, &state).await {
+        trace!("Error writing config to database: {err}");
+    }
+
+    StatusCode::OK
+}
+
+/// Returns the group IDs this node is a member of. An empty list if it is no member of any group.
+pub async fn list_groups(State(state): State<Arc<AppState>>) -> impl IntoApiResponse {
+    trace!("Listing groups");
+    let group_id_lock = state.group_ids.lock().await;
+    Json(GroupIdsJsonParameter {
+        group_ids: group_id_lock
+            .iter()
+            .map(ManagedEndDeviceId::phone_number)
+            .collect(),
+    })
+}
+
+/// Joins the groups, packets addressed to them are delivered locally and still relayed. Always
+/// returns HTTP 200.
+pub async fn add_groups(
+    State(state): State<Arc<AppState>>,
+    Json(group_ids): Json<GroupIdsJsonParameter>,
+) -> impl IntoApiResponse {
+    trace!("Joining groups: {:?}", group_ids.group_ids);
+    let mut group_id_lock = state.group_ids.lock().await;
+    group_ids
+        .group_ids
+        .iter()
+        .map(ManagedEndDeviceId::from)
+        .for_each(|group_id| {
+            group_id_lock.insert(group_id);
+        });
+    let updated_group_ids = group_id_lock.clone();
+    if let Err(err) = update_config_group_ids(updated_group_ids, &state).await {
+        trace!("Error writing config to database: {err}");
+    }
+
+    StatusCode::OK
+}
+
+/// Updates group IDs in the global config and the database.
+///
+/// # Error
+///
+/// Returns an error if the database returned an error.
+async fn update_config_group_ids(
+    group_ids: HashSet<ManagedEndDeviceId>,
+    state: &AppState,
+) -> Result<(), DbError> {
+    let updated_group_ids: Vec<String> = group_ids
+        .iter()
+        .map(ManagedEndDeviceId::phone_number)
+        .collect();
+    let mut config_lock = state.configuration.lock().await;
+    config_lock.currently_active_configuration.daemon.group_ids = updated_group_ids.clone();
+    config_lock.next_configuration.daemon.group_ids = updated_group_ids;
+    persist(
+        state,
         DataKey::Configuration,
         &config_lock.next_configuration,
-        db_pool,
     )
     .await
 }
+
+/// Query parameters for the end device registry.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct EndDeviceRegistryQuery {
+    /// Only return end device IDs of this category.
+    pub category: Option<EndDeviceCategory>,
+}
+
+/// Returns all known end device IDs with their category, local services first.
+pub async fn list_end_device_registry(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<EndDeviceRegistryQuery>,
+) -> impl IntoApiResponse {
+    trace!("Listing end device registry");
+    Json(state.end_device_registry.entries(query.category).await)
+}
@@ -0,0 +1,189 @@
+//! REST API endpoints for the gateway API.
+
+use crate::api::api_error::ApiError;
+use crate::database::fetch_gateway_stats;
+use crate::AppState;
+use aide::axum::IntoApiResponse;
+use axum::extract::{Path, Query, State};
+use axum::response::IntoResponse;
+use axum::Json;
+use chirpstack_gwb_integration::gateway_id::GatewayId;
+use chirpstack_gwb_integration::runtime::gateway_time::ClockOffset;
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::trace;
+
+/// Default time range of returned gateway stats in seconds.
+const DEFAULT_STATS_RANGE_SECONDS: u64 = 86_400;
+
+/// Query parameters for the gateway stats history.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct GatewayStatsQuery {
+    /// Time range in seconds before now to return stats for, defaults to one day.
+    pub range: Option<u64>,
+}
+
+/// Subsystem a tracked downlink ID of a gateway was allocated for.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct DownlinkOriginResponse {
+    /// The downlink ID.
+    pub downlink_id: u32,
+    /// Subsystem sending the downlink, e.g. `flooding` or `retransmission`.
+    pub subsystem: String,
+    /// Time the downlink ID was allocated.
+    pub allocated_at: DateTime<Utc>,
+}
+
+/// Tracked downlink IDs of a gateway.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct GatewayDownlinksResponse {
+    /// Tracked downlink IDs with their origins, oldest first.
+    pub downlinks: Vec<DownlinkOriginResponse>,
+    /// Downlink IDs skipped on all gateways as they were still tracked.
+    pub collisions: u64,
+}
+
+/// Clock offset of a gateway learned from its stats and uplinks.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct GatewayClockResponse {
+    /// Offset of the gateway clock in milliseconds, positive if the gateway clock is ahead.
+    pub offset_ms: Option<i64>,
+    /// Time the offset was last learned.
+    pub updated_at: Option<DateTime<Utc>>,
+    /// Whether the offset exceeds the configured clock drift threshold.
+    pub drifting: bool,
+    /// Whether the gateway reported GPS epoch timestamps.
+    pub gps_timing: bool,
+}
+
+/// Returns the distribution of the received frames per spreading factor, bandwidth, frequency
+/// and hour, not set if not configured.
+pub async fn get_radio_stats(State(state): State<Arc<AppState>>) -> impl IntoApiResponse {
+    trace!("Radio stats request");
+
+    let report = match &state.radio_stats {
+        Some(radio_stats) => Some(radio_stats.report(Utc::now()).await),
+        None => None,
+    };
+    Json(report)
+}
+
+/// Returns the transmission counters and locations of the gateways.
+pub async fn get_gateway_transmissions(State(state): State<Arc<AppState>>) -> impl IntoApiResponse {
+    trace!("Gateway transmissions request");
+
+    Json(state.gateway_selector.lock().await.gateways().clone())
+}
+
+/// Returns the MQTT messages, decode failures, callback latency and publish failures counted by
+/// the runtime.
+#[allow(clippy::unused_async)]
+pub async fn get_runtime_metrics(State(state): State<Arc<AppState>>) -> impl IntoApiResponse {
+    trace!("Runtime metrics request");
+
+    Json(state.runtime_metrics.snapshot())
+}
+
+/// Returns the clock offset of every gateway which reported its time.
+pub async fn get_gateway_clocks(State(state): State<Arc<AppState>>) -> impl IntoApiResponse {
+    trace!("Gateway clocks request");
+
+    let clocks: HashMap<GatewayId, GatewayClockResponse> = state
+        .runtime
+        .gateway_times()
+        .await
+        .into_iter()
+        .map(|(gateway_id, gateway_time)| {
+            (
+                gateway_id,
+                GatewayClockResponse {
+                    offset_ms: gateway_time
+                        .clock_offset()
+                        .map(ClockOffset::as_signed_millis),
+                    updated_at: gateway_time.clock_offset_updated_at().map(DateTime::from),
+                    drifting: gateway_time.is_drifting(),
+                    gps_timing: gateway_time.supports_gps_timing(),
+                },
+            )
+        })
+        .collect();
+    Json(clocks)
+}
+
+/// Returns the configured and probed transmission capabilities of the gateways.
+pub async fn get_gateway_capabilities(State(state): State<Arc<AppState>>) -> impl IntoApiResponse {
+    trace!("Gateway capabilities request");
+
+    Json(state.gateway_ids_manager.all_capabilities().await)
+}
+
+/// Returns the counters of the gateway send queues, empty if they are not configured.
+pub async fn get_gateway_send_queue_stats(
+    State(state): State<Arc<AppState>>,
+) -> impl IntoApiResponse {
+    trace!("Gateway send queue stats request");
+
+    match &state.gateway_send_queues {
+        Some(gateway_send_queues) => Json(gateway_send_queues.stats().await),
+        None => Json(HashMap::new()),
+    }
+}
+
+/// Returns the acknowledgement counters by status and the retransmission counters.
+pub async fn get_downlink_ack_stats(State(state): State<Arc<AppState>>) -> impl IntoApiResponse {
+    trace!("Downlink ack stats request");
+
+    Json(state.downlink_retransmission.stats().await)
+}
+
+/// Returns the persisted stats snapshots of a gateway within the requested range, oldest first.
+///
+/// Returns an internal server error if the stats could not be fetched from the database.
+pub async fn get_gateway_stats(
+    State(state): State<Arc<AppState>>,
+    Path(gateway_id): Path<GatewayId>,
+    Query(query): Query<GatewayStatsQuery>,
+) -> impl IntoApiResponse {
+    trace!("Gateway stats request for gateway \"{gateway_id}\"");
+    let range = chrono::Duration::from_std(std::time::Duration::from_secs(
+        query.range.unwrap_or(DEFAULT_STATS_RANGE_SECONDS),
+    ))
+    .unwrap_or(chrono::Duration::MAX);
+    let since = Utc::now()
+        .checked_sub_signed(range)
+        .unwrap_or(chrono::DateTime::<Utc>::MIN_UTC);
+    match fetch_gateway_stats(&gateway_id, since, state.db_pool.clone()).await {
+        Ok(stats) => Json(stats).into_response(),
+        Err(err) => {
+            trace!(%err);
+            ApiError::from(err).into_response()
+        }
+    }
+}
+
+/// Returns the tracked downlink IDs of a gateway and the subsystems they were allocated for, to
+/// correlate acknowledgements and log lines.
+pub async fn get_gateway_downlinks(
+    State(state): State<Arc<AppState>>,
+    Path(gateway_id): Path<GatewayId>,
+) -> impl IntoApiResponse {
+    trace!("Gateway downlinks request for gateway \"{gateway_id}\"");
+
+    Json(GatewayDownlinksResponse {
+        downlinks: state
+            .runtime
+            .downlink_origins(&gateway_id)
+            .await
+            .into_iter()
+            .map(|origin| DownlinkOriginResponse {
+                downlink_id: origin.downlink_id,
+                subsystem: origin.subsystem,
+                allocated_at: origin.allocated_at.into(),
+            })
+            .collect(),
+        collisions: state.runtime.downlink_id_collisions().await,
+    })
+}
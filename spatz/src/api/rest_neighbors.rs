@@ -0,0 +1,232 @@
+//! REST API endpoints for the neighbor table API.
+
+use crate::api::api_error::{ApiError, ApiErrorCode};
+use crate::database::{persist, DataKey};
+use crate::end_device_id::EndDeviceId;
+use crate::neighbor_trust::{BlacklistEntry, NodeTrust};
+use crate::topology::Topology;
+use crate::AppState;
+use aide::axum::IntoApiResponse;
+use axum::extract::{Query, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use chirpstack_gwb_integration::gateway_id::GatewayId;
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::trace;
+
+/// Format of the topology graph.
+#[derive(Debug, Default, Clone, Copy, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TopologyFormat {
+    /// Nodes and links as JSON, e.g. for a web UI.
+    #[default]
+    Json,
+    /// The DOT language of Graphviz.
+    Dot,
+}
+
+/// Query parameters for the topology graph.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct TopologyQuery {
+    /// Format of the graph, defaults to `json`.
+    #[serde(default)]
+    pub format: TopologyFormat,
+}
+
+/// Blacklisted nodes and trust scores of the nodes sending packets.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct NeighborTrustResponse {
+    /// Blacklisted nodes.
+    pub blacklist: Vec<BlacklistEntry>,
+    /// Current trust scores, empty if trust scoring is not configured.
+    pub scores: Vec<NodeTrust>,
+}
+
+/// Query parameters for the audit trail of the neighbor trust.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct TrustAuditQuery {
+    /// Time of the oldest returned change, all kept changes if not set.
+    pub since: Option<DateTime<Utc>>,
+}
+
+/// JSON parameter to blacklist a node.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct BlacklistJsonParameter {
+    /// Node ID, an end device ID of the node.
+    pub node_id: EndDeviceId,
+    /// Reason of the blacklisting, e.g. the observed misbehavior.
+    pub reason: String,
+}
+
+/// JSON parameter to remove a node from the blacklist.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct UnblacklistJsonParameter {
+    /// Blacklisted node ID.
+    pub node_id: EndDeviceId,
+}
+
+/// Returns the end device IDs announced by neighbors and how they are reachable.
+pub async fn get_neighbor_table(State(state): State<Arc<AppState>>) -> impl IntoApiResponse {
+    trace!("Neighbor table request");
+
+    Json(state.neighbor_table.lock().await.entries().clone())
+}
+
+/// Returns the services neighbors announced for their end device IDs.
+pub async fn get_neighbor_services(State(state): State<Arc<AppState>>) -> impl IntoApiResponse {
+    trace!("Neighbor services request");
+
+    Json(state.neighbor_table.lock().await.services().clone())
+}
+
+/// Returns the channel plans neighbors announced, keyed by an end device ID of the neighbor.
+pub async fn get_neighbor_channel_plans(
+    State(state): State<Arc<AppState>>,
+) -> impl IntoApiResponse {
+    trace!("Neighbor channel plans request");
+
+    Json(state.neighbor_table.lock().await.channel_plans().clone())
+}
+
+/// Returns the protocol capabilities neighbors announced for their end device IDs.
+pub async fn get_neighbor_capabilities(State(state): State<Arc<AppState>>) -> impl IntoApiResponse {
+    trace!("Neighbor capabilities request");
+
+    Json(state.neighbor_table.lock().await.capabilities().clone())
+}
+
+/// Returns the receive data rates neighbors announced for their end device IDs.
+pub async fn get_neighbor_data_rates(State(state): State<Arc<AppState>>) -> impl IntoApiResponse {
+    trace!("Neighbor data rates request");
+
+    Json(
+        state
+            .neighbor_table
+            .lock()
+            .await
+            .receive_data_rates()
+            .clone(),
+    )
+}
+
+/// Returns the graph of this node, its gateways and the known neighbors with the signal quality
+/// and the time of their last announcement.
+pub async fn get_topology(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<TopologyQuery>,
+) -> impl IntoApiResponse {
+    trace!("Topology request");
+
+    let local_end_device_ids: Vec<EndDeviceId> = state
+        .end_device_ids
+        .lock()
+        .await
+        .iter()
+        .map(|end_device_id| EndDeviceId(end_device_id.hash()))
+        .collect();
+    let gateway_ids: Vec<GatewayId> = state
+        .gateway_ids_manager
+        .gateway_ids
+        .lock()
+        .await
+        .iter()
+        .cloned()
+        .collect();
+    let topology = Topology::new(
+        &local_end_device_ids,
+        &gateway_ids,
+        state.neighbor_table.lock().await.entries(),
+        Utc::now(),
+    );
+    match query.format {
+        TopologyFormat::Json => Json(topology).into_response(),
+        TopologyFormat::Dot => (
+            [(header::CONTENT_TYPE, "text/vnd.graphviz; charset=utf-8")],
+            topology.to_dot(),
+        )
+            .into_response(),
+    }
+}
+
+/// Returns the blacklisted nodes and the current trust scores.
+#[allow(clippy::unused_async)]
+pub async fn get_neighbor_trust(State(state): State<Arc<AppState>>) -> impl IntoApiResponse {
+    trace!("Neighbor trust request");
+
+    Json(NeighborTrustResponse {
+        blacklist: state.neighbor_trust.blacklisted(),
+        scores: state.neighbor_trust.scores(Utc::now()),
+    })
+}
+
+/// Returns the changes of the blacklist and the trust scores matching the query, oldest first.
+#[allow(clippy::unused_async)]
+pub async fn get_neighbor_trust_audit(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<TrustAuditQuery>,
+) -> impl IntoApiResponse {
+    trace!("Neighbor trust audit request: {query:?}");
+
+    Json(state.neighbor_trust.audit_trail(query.since))
+}
+
+/// Blacklists a node, takes effect immediately. Its packets are dropped and its entries are
+/// removed from the neighbor table.
+///
+/// Returns an internal server error if the blacklist could not be saved to the database and
+/// service unavailable if the database is read-only, the blacklist applies until the next restart
+/// then.
+pub async fn blacklist_neighbor(
+    State(state): State<Arc<AppState>>,
+    Json(parameter): Json<BlacklistJsonParameter>,
+) -> impl IntoApiResponse {
+    trace!("Blacklist request: {parameter:?}");
+
+    state
+        .neighbor_trust
+        .blacklist(parameter.node_id, parameter.reason, Utc::now());
+    state.neighbor_table.lock().await.remove(parameter.node_id);
+    persist_neighbor_trust(&state).await
+}
+
+/// Removes a node from the blacklist, takes effect immediately.
+///
+/// Returns not found if the node is not blacklisted and the same errors as
+/// [`blacklist_neighbor`] if the blacklist could not be saved.
+pub async fn unblacklist_neighbor(
+    State(state): State<Arc<AppState>>,
+    Json(parameter): Json<UnblacklistJsonParameter>,
+) -> impl IntoApiResponse {
+    trace!("Unblacklist request: {parameter:?}");
+
+    if !state
+        .neighbor_trust
+        .unblacklist(parameter.node_id, Utc::now())
+    {
+        return ApiError::new(
+            ApiErrorCode::NodeNotBlacklisted,
+            format!("Node {} is not blacklisted", parameter.node_id.0),
+        )
+        .into_response();
+    }
+    persist_neighbor_trust(&state).await
+}
+
+/// Persists the blacklist, the trust scores and the audit trail and maps the result to the
+/// response.
+async fn persist_neighbor_trust(state: &AppState) -> Response {
+    match persist(
+        state,
+        DataKey::NeighborTrust,
+        &state.neighbor_trust.persisted(),
+    )
+    .await
+    {
+        Ok(()) => StatusCode::OK.into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
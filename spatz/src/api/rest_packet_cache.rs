@@ -1,11 +1,13 @@
 //! REST API endpoints for the packet cache API.
 
+use crate::api::api_error::ApiError;
 use crate::configuration::PacketCacheConfig;
-use crate::database::{insert_into_db, DataKey};
+use crate::database::{persist, DataKey};
 use crate::AppState;
 use aide::axum::IntoApiResponse;
 use axum::extract::State;
 use axum::http::StatusCode;
+use axum::response::IntoResponse;
 use axum::Json;
 use std::sync::Arc;
 use tracing::trace;
@@ -29,7 +31,8 @@ pub async fn get_current_packet_cache_config(
 
 /// Sets the packet cache configuration for the next restart of the instance.
 ///
-/// Returns an internal server error if the configuration could not be saved to the database.
+/// Returns an internal server error if the configuration could not be saved to the database and
+/// service unavailable if the database is read-only.
 pub async fn set_next_packet_cache_config(
     State(state): State<Arc<AppState>>,
     Json(packet_cache_config): Json<PacketCacheConfig>,
@@ -38,15 +41,15 @@ pub async fn set_next_packet_cache_config(
     let mut config_lock = state.configuration.lock().await;
 
     config_lock.next_configuration.daemon.packet_cache = packet_cache_config;
-    match insert_into_db(
+    match persist(
+        &state,
         DataKey::Configuration,
         &config_lock.next_configuration,
-        state.db_pool.clone(),
     )
     .await
     {
-        Ok(_) => StatusCode::OK,
-        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        Ok(()) => StatusCode::OK.into_response(),
+        Err(err) => ApiError::from(err).into_response(),
     }
 }
 
@@ -72,3 +75,42 @@ pub async fn get_packet_cache_contents(State(state): State<Arc<AppState>>) -> im
     trace!("Packet cache content request");
     Json(state.packet_cache.contents().await)
 }
+
+/// Returns the cached packet hashes with their age and origin, and the hit, miss and eviction
+/// counters of the packet cache.
+pub async fn get_packet_cache_report(State(state): State<Arc<AppState>>) -> impl IntoApiResponse {
+    trace!("Packet cache report request");
+    Json(state.packet_cache.report(state.clock.now()).await)
+}
+
+/// Flushes the packet cache so already seen packets are processed again, returns the amount of
+/// removed entries.
+pub async fn flush_packet_cache(State(state): State<Arc<AppState>>) -> impl IntoApiResponse {
+    trace!("Packet cache flush request");
+    Json(state.packet_cache.flush().await)
+}
+
+/// Returns the counters of uplinks suppressed as recently received before parsing.
+#[allow(clippy::unused_async)]
+pub async fn get_inbound_duplicate_stats(
+    State(state): State<Arc<AppState>>,
+) -> impl IntoApiResponse {
+    trace!("Inbound duplicate stats request");
+    Json(state.inbound_duplicate_metrics.stats())
+}
+
+/// Returns the counters of uplinks by CRC status and of uplinks dropped due to their modulation.
+#[allow(clippy::unused_async)]
+pub async fn get_uplink_validation_stats(
+    State(state): State<Arc<AppState>>,
+) -> impl IntoApiResponse {
+    trace!("Uplink validation stats request");
+    Json(state.uplink_validator.stats())
+}
+
+/// Returns the accepted and dropped packets of every network ID.
+#[allow(clippy::unused_async)]
+pub async fn get_network_stats(State(state): State<Arc<AppState>>) -> impl IntoApiResponse {
+    trace!("Network stats request");
+    Json(state.network_filter.stats())
+}
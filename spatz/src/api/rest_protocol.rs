@@ -0,0 +1,89 @@
+//! REST API endpoints to inject and read raw protocol packets, intended for research tooling.
+
+use crate::api::api_error::{ApiError, ApiErrorCode};
+use crate::packet_cache::PacketSource;
+use crate::AppState;
+use aide::axum::IntoApiResponse;
+use axum::extract::{Query, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::IntoResponse;
+use axum::Json;
+use chirpstack_gwb_integration::downlinks::predefined_parameters::DataRate;
+use chrono::{DateTime, Utc};
+use lorawan_dtn_protocol::LoRaWanPacket;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::sync::Arc;
+use tracing::trace;
+
+/// JSON parameter for a packet to be transmitted.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct InjectPacketJsonParameter {
+    /// The packet, tagged with its packet type in the `type` field, e.g.
+    /// `{"type": "LocalAnnouncement", "location": null, "end_device_ids": [1234]}`.
+    #[schemars(with = "serde_json::Value")]
+    pub packet: Box<dyn LoRaWanPacket>,
+    /// The data rate to transmit the packet with, e.g. `Eu863_870Dr3`.
+    #[schemars(with = "String")]
+    pub data_rate: DataRate,
+}
+
+/// Query parameters for received packets.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct ReceivedPacketsQuery {
+    /// Only return packets received after this time.
+    pub since: Option<DateTime<Utc>>,
+    /// Only return the most recent packets up to this amount.
+    pub limit: Option<usize>,
+}
+
+/// Serializes the packet and enqueues it for transmission by the routing algorithm.
+///
+/// The packet is added to the packet cache to not relay it again once received from a neighbor.
+/// Returns service unavailable if the relay queue is full.
+pub async fn inject_packet(
+    State(state): State<Arc<AppState>>,
+    Json(inject_packet): Json<InjectPacketJsonParameter>,
+) -> impl IntoApiResponse {
+    trace!("Injecting packet: {:?}", inject_packet.packet);
+    let phy_payload = inject_packet.packet.convert_to_lorawan_phy_payload();
+    if !state
+        .queue_manager
+        .enqueue_relay_packet(inject_packet.packet, inject_packet.data_rate)
+        .await
+    {
+        return ApiError::new(ApiErrorCode::RelayQueueFull, "Relay packet queue is full")
+            .into_response();
+    }
+    // An already cached packet is sent anyway, injecting duplicates is a valid use case.
+    let _ = state
+        .packet_cache
+        .insert(&phy_payload, PacketSource::Local)
+        .await;
+    StatusCode::OK.into_response()
+}
+
+/// Returns the recently received packets, oldest first.
+pub async fn get_received_packets(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<ReceivedPacketsQuery>,
+) -> impl IntoApiResponse {
+    trace!("Received packets request");
+    let received_packets_lock = state.received_packets.lock().await;
+    match serde_json::to_string(&received_packets_lock.query(query.since, query.limit)) {
+        Ok(received_packets) => {
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                header::CONTENT_TYPE,
+                "application/json"
+                    .parse()
+                    .expect("Failed to build json header"),
+            );
+            (headers, received_packets).into_response()
+        }
+        Err(err) => {
+            trace!(%err);
+            ApiError::from(err).into_response()
+        }
+    }
+}
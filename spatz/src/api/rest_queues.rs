@@ -1,16 +1,37 @@
 //! REST API endpoints for the message/packet queues API.
 
+use crate::api::api_error::ApiError;
 use crate::configuration::QueueConfig;
-use crate::database::{insert_into_db, DataKey};
+use crate::database::{persist, DataKey};
+use crate::delivery_ledger::DeliveryLedger;
+use crate::error::QueueOperationError;
 use crate::AppState;
 use aide::axum::IntoApiResponse;
 use axum::extract::State;
 use axum::http::{header, HeaderMap, StatusCode};
-use axum::response::IntoResponse;
+use axum::response::{IntoResponse, Response};
 use axum::Json;
+use schemars::JsonSchema;
+use serde::Deserialize;
 use std::sync::Arc;
 use tracing::trace;
 
+/// JSON parameter selecting a queued bundle by its index in the message queue.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct BundleIndexJsonParameter {
+    /// Index of the bundle in the message queue.
+    pub index: usize,
+}
+
+/// JSON parameter to freeze or unfreeze a queued bundle.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct FreezeBundleJsonParameter {
+    /// Index of the bundle in the message queue.
+    pub index: usize,
+    /// Whether the bundle is frozen or unfrozen.
+    pub frozen: bool,
+}
+
 /// Returns the message buffer queue.
 pub async fn get_message_buffer_queue(State(state): State<Arc<AppState>>) -> impl IntoApiResponse {
     trace!("Message buffer queue request");
@@ -27,11 +48,19 @@ pub async fn get_message_buffer_queue(State(state): State<Arc<AppState>>) -> imp
         }
         Err(err) => {
             trace!(%err);
-            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            ApiError::from(err).into_response()
         }
     }
 }
 
+/// Returns the amount of queued relay packets, bundles and announcements and the maximum of every
+/// queue.
+pub async fn get_queue_stats(State(state): State<Arc<AppState>>) -> impl IntoApiResponse {
+    trace!("Queue stats request");
+
+    Json(state.queue_manager.stats().await)
+}
+
 /// Returns the relay packet queue.
 pub async fn get_relay_packet_queue(State(state): State<Arc<AppState>>) -> impl IntoApiResponse {
     trace!("Relay packet queue request");
@@ -48,11 +77,43 @@ pub async fn get_relay_packet_queue(State(state): State<Arc<AppState>>) -> impl
         }
         Err(err) => {
             trace!(%err);
-            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            ApiError::from(err).into_response()
         }
     }
 }
 
+/// Returns the bundles parked without a known next hop and the parking counters, none if the
+/// parking is not configured.
+pub async fn get_parked_bundles(State(state): State<Arc<AppState>>) -> impl IntoApiResponse {
+    trace!("Parked bundles request");
+    let report = match &state.bundle_parking {
+        Some(bundle_parking) => Some(bundle_parking.report().await),
+        None => None,
+    };
+    Json(report)
+}
+
+/// Returns the late delivery policy and the counters of bundles delivered to local services.
+#[allow(clippy::unused_async)]
+pub async fn get_late_delivery_stats(State(state): State<Arc<AppState>>) -> impl IntoApiResponse {
+    trace!("Late delivery stats request");
+    Json(state.late_delivery.stats())
+}
+
+/// Returns the size statistics of the payload profiles.
+#[allow(clippy::unused_async)]
+pub async fn get_payload_profile_stats(State(state): State<Arc<AppState>>) -> impl IntoApiResponse {
+    trace!("Payload profile stats request");
+    Json(state.payload_codecs.stats())
+}
+
+/// Returns the counters of the delivery ledger, `null` if the ledger is not configured.
+#[allow(clippy::unused_async)]
+pub async fn get_delivery_ledger_stats(State(state): State<Arc<AppState>>) -> impl IntoApiResponse {
+    trace!("Delivery ledger stats request");
+    Json(state.delivery_ledger.as_ref().map(DeliveryLedger::stats))
+}
+
 /// Returns the currently active message/packet configuration.
 pub async fn get_current_queues_config(State(state): State<Arc<AppState>>) -> impl IntoApiResponse {
     trace!("Current message/packet config request");
@@ -87,7 +148,8 @@ pub async fn get_next_queues_config(State(state): State<Arc<AppState>>) -> impl
 
 /// Sets the message/packet configuration for the next restart of the instance.
 ///
-/// Returns an internal server error if the configuration could not be saved to the database.
+/// Returns an internal server error if the configuration could not be saved to the database and
+/// service unavailable if the database is read-only.
 pub async fn set_next_queues_config(
     State(state): State<Arc<AppState>>,
     Json(queue_config): Json<QueueConfig>,
@@ -96,14 +158,70 @@ pub async fn set_next_queues_config(
     let mut config_lock = state.configuration.lock().await;
 
     config_lock.next_configuration.daemon.queue_config = queue_config;
-    match insert_into_db(
+    match persist(
+        &state,
         DataKey::Configuration,
         &config_lock.next_configuration,
-        state.db_pool.clone(),
     )
     .await
     {
-        Ok(_) => StatusCode::OK,
-        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        Ok(()) => StatusCode::OK.into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+/// Moves a queued bundle to the front of the message queue.
+///
+/// Returns not found if there is no bundle at the index and conflict if the bundle is frozen or
+/// too many bundles are pinned.
+pub async fn pin_bundle(
+    State(state): State<Arc<AppState>>,
+    Json(bundle_index): Json<BundleIndexJsonParameter>,
+) -> impl IntoApiResponse {
+    trace!("Pin bundle request: {bundle_index:?}");
+    queue_operation_response(state.queue_manager.pin_bundle(bundle_index.index).await)
+}
+
+/// Moves a queued bundle to the back of the message queue.
+///
+/// Returns not found if there is no bundle at the index.
+pub async fn deprioritize_bundle(
+    State(state): State<Arc<AppState>>,
+    Json(bundle_index): Json<BundleIndexJsonParameter>,
+) -> impl IntoApiResponse {
+    trace!("Deprioritize bundle request: {bundle_index:?}");
+    queue_operation_response(
+        state
+            .queue_manager
+            .deprioritize_bundle(bundle_index.index)
+            .await,
+    )
+}
+
+/// Freezes or unfreezes a queued bundle, frozen bundles are not sent.
+///
+/// Returns not found if there is no bundle at the index and conflict if too many bundles are
+/// frozen.
+pub async fn freeze_bundle(
+    State(state): State<Arc<AppState>>,
+    Json(freeze): Json<FreezeBundleJsonParameter>,
+) -> impl IntoApiResponse {
+    trace!("Freeze bundle request: {freeze:?}");
+    queue_operation_response(
+        state
+            .queue_manager
+            .set_bundle_frozen(freeze.index, freeze.frozen)
+            .await,
+    )
+}
+
+/// Maps the result of a queue operation to the response.
+fn queue_operation_response(result: Result<(), QueueOperationError>) -> Response {
+    match result {
+        Ok(()) => StatusCode::OK.into_response(),
+        Err(err) => {
+            trace!(%err);
+            ApiError::from(err).into_response()
+        }
     }
 }
@@ -0,0 +1,64 @@
+//! REST API endpoints for the radio silence API.
+
+use crate::api::api_error::{ApiError, ApiErrorCode};
+use crate::configuration::RadioSilenceWindow;
+use crate::AppState;
+use aide::axum::IntoApiResponse;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::Json;
+use chrono::Utc;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::sync::Arc;
+use tracing::trace;
+
+/// JSON parameter to start or end a radio silence.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct RadioSilenceJsonParameter {
+    /// Duration of the radio silence starting now, ends the radio silence started via the API if
+    /// not set.
+    pub duration_seconds: Option<u32>,
+}
+
+/// Returns whether a radio silence is active and the daily radio silence windows.
+pub async fn get_radio_silence(State(state): State<Arc<AppState>>) -> impl IntoApiResponse {
+    trace!("Radio silence status request");
+
+    Json(state.radio_silence.status(Utc::now()).await)
+}
+
+/// Starts a radio silence for the duration or ends it, takes effect immediately.
+pub async fn set_radio_silence(
+    State(state): State<Arc<AppState>>,
+    Json(parameter): Json<RadioSilenceJsonParameter>,
+) -> impl IntoApiResponse {
+    trace!("Set radio silence request: {parameter:?}");
+
+    let silent_until = parameter.duration_seconds.map(|duration_seconds| {
+        Utc::now() + chrono::Duration::seconds(i64::from(duration_seconds))
+    });
+    state.radio_silence.set_silent_until(silent_until).await;
+    StatusCode::OK
+}
+
+/// Replaces the daily radio silence windows until the next restart, takes effect immediately.
+///
+/// Returns bad request if a window starts and ends at the same time.
+pub async fn set_radio_silence_windows(
+    State(state): State<Arc<AppState>>,
+    Json(windows): Json<Vec<RadioSilenceWindow>>,
+) -> impl IntoApiResponse {
+    trace!("Set radio silence windows request: {windows:?}");
+
+    if windows.iter().any(|window| window.start == window.end) {
+        return ApiError::new(
+            ApiErrorCode::InvalidRadioSilenceWindow,
+            "A radio silence window starts and ends at the same time",
+        )
+        .into_response();
+    }
+    state.radio_silence.set_windows(windows).await;
+    StatusCode::OK.into_response()
+}
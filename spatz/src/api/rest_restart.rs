@@ -1,6 +1,6 @@
 //! REST API endpoint indicating whether a restart is needed to apply the configuration.
 
-use crate::graceful_shutdown::ShutdownConditions;
+use crate::graceful_shutdown::{ShutdownConditions, ShutdownReason};
 use crate::AppState;
 use aide::axum::IntoApiResponse;
 use axum::extract::State;
@@ -24,7 +24,10 @@ pub async fn restart(State(state): State<Arc<AppState>>) -> impl IntoApiResponse
     trace!("Restart request");
     state
         .restart_initiator
-        .initiate_shutdown(ShutdownConditions::Restart);
+        .initiate_shutdown(ShutdownReason::new(
+            ShutdownConditions::Restart,
+            module_path!(),
+        ));
 
     StatusCode::OK
 }
@@ -0,0 +1,190 @@
+//! REST API endpoints for the routing algorithms.
+
+use crate::api::api_error::{ApiError, ApiErrorCode};
+use crate::bundle_delivery::{BundleAge, BundleDelivery};
+use crate::bundle_resend;
+use crate::client_airtime::ANONYMOUS_CLIENT;
+use crate::end_device_id::EndDeviceId;
+use crate::unicast::Unicast;
+use crate::AppState;
+use aide::axum::IntoApiResponse;
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::Json;
+use chrono::{DateTime, Utc};
+use lorawan_dtn_protocol::BundleResendRequest;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::trace;
+
+/// JSON parameter requesting the retransmission of a bundle from its source.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct BundleResendJsonParameter {
+    /// Source of the bundle, the request is sent to it.
+    pub bundle_source: EndDeviceId,
+    /// Destination of the bundle, an end device ID of a local service.
+    pub bundle_destination: EndDeviceId,
+    /// Creation time of the bundle, only the seconds are sent.
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Query parameters for polling the delivered bundles.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct BundlePollQuery {
+    /// Cursor of the previous response, acknowledging the bundles before it. The last
+    /// acknowledged cursor of the client is used if not set.
+    pub cursor: Option<u64>,
+    /// ID of the client the cursor is remembered for, `anonymous` if not set.
+    pub client: Option<String>,
+    /// Time to wait for a delivered bundle if there is none yet in seconds, capped at and
+    /// defaulting to the configured maximum.
+    pub wait_seconds: Option<u64>,
+}
+
+/// Bundles returned to a polling client.
+#[derive(Debug, Serialize)]
+pub struct BundlePollResponse<'a> {
+    /// Bundles delivered since the cursor with their age, oldest first.
+    pub bundles: Vec<BundleDelivery<'a>>,
+    /// Cursor to send with the next poll, acknowledging the returned bundles.
+    pub cursor: u64,
+    /// Bundles since the cursor which were removed before they were polled.
+    pub missed: u64,
+}
+
+/// Returns the destination classes and the amount of sent packets per routing algorithm.
+#[allow(clippy::unused_async)]
+pub async fn get_routing_stats(State(state): State<Arc<AppState>>) -> impl IntoApiResponse {
+    trace!("Routing stats request");
+
+    Json(state.routing_dispatcher.stats())
+}
+
+/// Returns the counters of the directed transmissions to direct neighbors, `null` if unicast is
+/// not configured.
+#[allow(clippy::unused_async)]
+pub async fn get_unicast_stats(State(state): State<Arc<AppState>>) -> impl IntoApiResponse {
+    trace!("Unicast stats request");
+
+    Json(state.unicast.as_ref().map(Unicast::stats))
+}
+
+/// Returns the learned paths and the counters of the directed transmissions via them, `null` if
+/// the path cache is not configured.
+pub async fn get_path_cache(State(state): State<Arc<AppState>>) -> impl IntoApiResponse {
+    trace!("Path cache request");
+
+    let report = match &state.path_cache {
+        Some(path_cache) => Some(path_cache.report(Utc::now()).await),
+        None => None,
+    };
+    Json(report)
+}
+
+/// Returns whether bundles are sent as BP7 fragments and the counters of the BP7
+/// interoperability mode, `null` if it is not configured.
+pub async fn get_bp7_interop_stats(State(state): State<Arc<AppState>>) -> impl IntoApiResponse {
+    trace!("BP7 interoperability stats request");
+
+    let stats = match &state.bp7_interop {
+        Some(bp7_interop) => Some(bp7_interop.stats(&*state.neighbor_table.lock().await)),
+        None => None,
+    };
+    Json(stats)
+}
+
+/// Returns the counters of the bundle status report requests and reports.
+#[allow(clippy::unused_async)]
+pub async fn get_status_report_stats(State(state): State<Arc<AppState>>) -> impl IntoApiResponse {
+    trace!("Status report stats request");
+
+    Json(state.status_reports.stats())
+}
+
+/// Requests the retransmission of a bundle from its source, the source resends the bundle with a
+/// new creation timestamp if it still keeps it.
+///
+/// Returns accepted once the request is queued, bad request if the destination of the bundle is
+/// not managed by this node, forbidden on relay-only nodes and service unavailable if the relay
+/// queue is full.
+pub async fn request_bundle_resend(
+    State(state): State<Arc<AppState>>,
+    Json(parameter): Json<BundleResendJsonParameter>,
+) -> impl IntoApiResponse {
+    trace!("Bundle resend request: {parameter:?}");
+
+    let request = BundleResendRequest {
+        destination: parameter.bundle_source,
+        source: parameter.bundle_destination,
+        timestamp: parameter.timestamp,
+    };
+    match bundle_resend::send_request(&state, request).await {
+        Ok(()) => StatusCode::ACCEPTED.into_response(),
+        Err(err) => ApiError::from(err).into_response(),
+    }
+}
+
+/// Returns the bundles delivered since the cursor wrapped in [`BundleDelivery`] envelopes, waits
+/// for a bundle to be delivered if there is none yet.
+///
+/// Returns forbidden on relay-only nodes and bad request if no bundle mailbox is configured.
+pub async fn poll_bundles(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<BundlePollQuery>,
+) -> impl IntoApiResponse {
+    trace!("Bundle poll request: {query:?}");
+
+    if !state.node_profile.serves_local_bundles() {
+        return ApiError::new(
+            ApiErrorCode::RelayOnlyNode,
+            "Relay-only nodes do not serve local bundles",
+        )
+        .into_response();
+    }
+    let Some(bundle_mailbox) = &state.bundle_mailbox else {
+        return ApiError::new(
+            ApiErrorCode::BundleMailboxUnavailable,
+            "No bundle mailbox is configured",
+        )
+        .into_response();
+    };
+    let client = query.client.as_deref().unwrap_or(ANONYMOUS_CLIENT);
+    let wait = query
+        .wait_seconds
+        .map_or(Duration::MAX, Duration::from_secs);
+    let poll = bundle_mailbox.poll(client, query.cursor, wait).await;
+    let now = Utc::now();
+    Json(BundlePollResponse {
+        bundles: poll
+            .bundles
+            .iter()
+            .map(|(_, bundle)| BundleDelivery {
+                age: BundleAge::of(bundle, now),
+                bundle,
+            })
+            .collect(),
+        cursor: poll.cursor,
+        missed: poll.missed,
+    })
+    .into_response()
+}
+
+/// Returns the counters of the bundles kept for retransmission and the resend requests.
+#[allow(clippy::unused_async)]
+pub async fn get_bundle_resend_stats(State(state): State<Arc<AppState>>) -> impl IntoApiResponse {
+    trace!("Bundle resend stats request");
+
+    Json(state.bundle_resend.stats())
+}
+
+/// Returns the oversize policy, its counters and the sub-bundle statuses of recently split
+/// bundles.
+#[allow(clippy::unused_async)]
+pub async fn get_oversize_bundles(State(state): State<Arc<AppState>>) -> impl IntoApiResponse {
+    trace!("Oversize bundles request");
+
+    Json(state.oversize_bundles.report())
+}
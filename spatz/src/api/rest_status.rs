@@ -0,0 +1,69 @@
+//! REST API endpoints for the status API.
+
+use crate::database::is_database_read_only;
+use crate::operating_mode::OperatingModeStatus;
+use crate::watchdog::TaskStatus;
+use crate::AppState;
+use aide::axum::IntoApiResponse;
+use axum::extract::State;
+use axum::Json;
+use chrono::Utc;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::sync::Arc;
+use tracing::trace;
+
+/// Status of the operating mode and the liveness of the supervised tasks.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct Status {
+    /// The operating mode and the active degraded conditions.
+    #[serde(flatten)]
+    pub operating_mode: OperatingModeStatus,
+    /// Liveness of the supervised tasks, ordered by name.
+    pub tasks: Vec<TaskStatus>,
+}
+
+/// Returns the current operating mode, the active degraded conditions and the liveness of the
+/// supervised tasks.
+pub async fn get_status(State(state): State<Arc<AppState>>) -> impl IntoApiResponse {
+    trace!("Status request");
+
+    Json(Status {
+        operating_mode: state.operating_mode.lock().await.status(),
+        tasks: state.watchdog.status(Utc::now()),
+    })
+}
+
+/// Returns the outcome of every startup step in the order they were started, `null` while the
+/// startup is not completed.
+#[allow(clippy::unused_async)]
+pub async fn get_startup_summary(State(state): State<Arc<AppState>>) -> impl IntoApiResponse {
+    trace!("Startup summary request");
+
+    Json(state.startup_summary.get().cloned())
+}
+
+/// Returns the database error policy, whether the database is read-only and the write counters.
+pub async fn get_database_stats(State(state): State<Arc<AppState>>) -> impl IntoApiResponse {
+    trace!("Database stats request");
+
+    let read_only = is_database_read_only(&state).await;
+    Json(state.database_health.stats(read_only))
+}
+
+/// Returns the report of the shutdown before the current start, `null` if none was saved.
+#[allow(clippy::unused_async)]
+pub async fn get_last_shutdown(State(state): State<Arc<AppState>>) -> impl IntoApiResponse {
+    trace!("Last shutdown request");
+
+    Json(state.last_shutdown.clone())
+}
+
+/// Returns the approximate memory usage of the buffers, the budget and the rejections and
+/// evictions.
+#[allow(clippy::unused_async)]
+pub async fn get_memory_stats(State(state): State<Arc<AppState>>) -> impl IntoApiResponse {
+    trace!("Memory stats request");
+
+    Json(state.memory_budget.stats())
+}
@@ -1,29 +1,383 @@
 //! WebSocket API.
 
+use crate::api::api_error::{ApiError, ApiErrorCode};
+use crate::bundle_delivery::{BundleAge, BundleDelivery};
+use crate::bundle_processing::SubmittedBundle;
+use crate::client_airtime::ANONYMOUS_CLIENT;
+use crate::routing_hints::RoutingHints;
+use crate::send_buffers::BundleSendBuffer;
 use crate::AppState;
 use axum::extract::ws::{Message, WebSocket};
-use axum::extract::{State, WebSocketUpgrade};
+use axum::extract::{Query, State, WebSocketUpgrade};
 use axum::response::IntoResponse;
+use chrono::Utc;
+use futures_util::stream::SplitSink;
 use futures_util::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
 use std::sync::Arc;
-use tracing::{error, trace};
+use tokio::sync::mpsc;
+use tracing::{error, info, trace, warn};
+
+/// Maximum size of the CBOR encoded bundles of a batch in bytes if the client sets none.
+const DEFAULT_BATCH_BYTES: usize = 64 * 1024;
+
+/// Maximum amount of bundles waiting for credits per connection, the oldest are dropped beyond.
+const MAX_PENDING_DELIVERIES: usize = 256;
+
+/// Query parameters identifying the API client of a WebSocket connection.
+#[derive(Debug, Deserialize)]
+pub struct WsClientParameter {
+    /// ID of the client the airtime of its bundles is accounted to, `anonymous` if not set.
+    pub client: Option<String>,
+    /// Payload profile the payloads of the submitted bundles are encoded with, sent unchanged if
+    /// not set.
+    pub profile: Option<u8>,
+    /// Maximum amount of bundles delivered per message, every bundle is delivered in own messages
+    /// if not set.
+    pub batch_size: Option<usize>,
+    /// Maximum size of the CBOR encoded bundles of a batch in bytes, 64 KiB if not set. A larger
+    /// bundle is delivered alone.
+    pub batch_bytes: Option<usize>,
+    /// Bundles the client is ready to receive, granted again with `{"credits": n}` messages.
+    /// Bundles are delivered without flow control if not set.
+    pub window: Option<u32>,
+}
+
+/// Message of the client, either granting delivery credits or submitting a bundle.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum ClientMessage {
+    /// Grants credits for the delivery of further bundles.
+    Credits {
+        /// Amount of further bundles the client is ready to receive.
+        credits: u32,
+    },
+    /// Submits a bundle.
+    Submission(BundleSubmission),
+}
+
+/// Message submitting a bundle, either the plain bundle or an object carrying the bundle next to
+/// the routing hints.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum BundleSubmission {
+    /// Bundle with routing hints.
+    WithRoutingHints {
+        /// The submitted bundle.
+        bundle: bp7::Bundle,
+        /// Routing hints of the bundle, the defaults are used for missing hints.
+        #[serde(default)]
+        routing_hints: RoutingHints,
+    },
+    /// Bundle without routing hints.
+    Plain(bp7::Bundle),
+}
+
+impl BundleSubmission {
+    /// Returns the bundle and its routing hints.
+    fn into_parts(self) -> (bp7::Bundle, RoutingHints) {
+        match self {
+            BundleSubmission::WithRoutingHints {
+                bundle,
+                routing_hints,
+            } => (bundle, routing_hints),
+            BundleSubmission::Plain(bundle) => (bundle, RoutingHints::default()),
+        }
+    }
+}
+
+/// Batch of bundles delivered in one JSON text message.
+#[derive(Debug, Serialize)]
+struct BatchDelivery<'a> {
+    /// The delivered bundles with their age.
+    bundles: Vec<BundleDelivery<'a>>,
+}
+
+/// Per-connection state of the delivery to the client. Bundles wait for credits of the client and
+/// are grouped into batches if configured.
+#[derive(Debug)]
+struct DeliveryWindow {
+    /// Bundles the client is ready to receive, unlimited if not set.
+    credits: Option<u32>,
+    /// Maximum amount of bundles per message, bundles are delivered one by one if not set.
+    batch_size: Option<usize>,
+    /// Maximum size of the CBOR encoded bundles of a batch in bytes.
+    batch_bytes: usize,
+    /// Bundles waiting for their delivery with their CBOR encoding.
+    pending: VecDeque<(bp7::Bundle, Vec<u8>)>,
+}
+
+impl DeliveryWindow {
+    /// Creates the delivery state of a connection with the parameters of the client.
+    fn new(parameter: &WsClientParameter) -> Self {
+        Self {
+            credits: parameter.window,
+            batch_size: parameter.batch_size,
+            batch_bytes: parameter.batch_bytes.unwrap_or(DEFAULT_BATCH_BYTES),
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Queues a bundle for the delivery, drops the oldest bundle if too many are waiting.
+    fn push(&mut self, mut bundle: bp7::Bundle) {
+        if self.pending.len() >= MAX_PENDING_DELIVERIES {
+            warn!("Client grants no credits, dropping oldest undelivered bundle");
+            self.pending.pop_front();
+        }
+        let cbor = bundle.to_cbor();
+        self.pending.push_back((bundle, cbor));
+    }
+
+    /// Grants further credits.
+    fn grant(&mut self, credits: u32) {
+        if let Some(available) = &mut self.credits {
+            *available = available.saturating_add(credits);
+        }
+    }
+
+    /// Returns the next bundles to deliver in one message and consumes their credits, none if
+    /// nothing is pending or no credits are left. Batches are limited by the batch size and the
+    /// size cap, without batching every bundle is returned alone.
+    fn next_batch(&mut self) -> Vec<(bp7::Bundle, Vec<u8>)> {
+        let credits = self.credits.map_or(usize::MAX, |credits| {
+            usize::try_from(credits).unwrap_or(usize::MAX)
+        });
+        let max_bundles = self.batch_size.unwrap_or(1).min(credits);
+        let mut batch = Vec::new();
+        let mut batch_bytes = 0_usize;
+        while batch.len() < max_bundles {
+            let Some((_, cbor)) = self.pending.front() else {
+                break;
+            };
+            let size = batch_bytes.saturating_add(cbor.len());
+            if !batch.is_empty() && size > self.batch_bytes {
+                break;
+            }
+            batch_bytes = size;
+            batch.extend(self.pending.pop_front());
+        }
+        if let Some(credits) = &mut self.credits {
+            *credits = credits.saturating_sub(u32::try_from(batch.len()).unwrap_or(u32::MAX));
+        }
+        batch
+    }
+}
 
 /// On successful upgrade, hands connections off to the [`handle_socket`] function.
+///
+/// Returns forbidden on relay-only nodes as they do not serve local services and bad request if
+/// no codec is registered for the payload profile or the batch size or size cap is zero, both with
+/// an [`ApiError`].
 #[allow(clippy::unused_async)]
 pub async fn ws_handler(
     State(state): State<Arc<AppState>>,
+    Query(parameter): Query<WsClientParameter>,
     ws: WebSocketUpgrade,
 ) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| handle_socket(socket, state))
+    if !state.node_profile.serves_local_bundles() {
+        trace!("Relay-only node, rejecting WS connection");
+        return ApiError::new(
+            ApiErrorCode::RelayOnlyNode,
+            "Relay-only nodes do not serve local bundles",
+        )
+        .into_response();
+    }
+    if let Some(profile) = parameter.profile {
+        if !state.payload_codecs.contains(profile) {
+            trace!("Unknown payload profile {profile}, rejecting WS connection");
+            return ApiError::new(
+                ApiErrorCode::UnknownPayloadProfile,
+                format!("No codec registered for payload profile {profile}"),
+            )
+            .with_details(serde_json::json!({ "profile": profile }))
+            .into_response();
+        }
+    }
+    if parameter.batch_size == Some(0) || parameter.batch_bytes == Some(0) {
+        trace!("Empty batches, rejecting WS connection");
+        return ApiError::new(
+            ApiErrorCode::InvalidBatchParameters,
+            "The batch size and the size cap of batches must be greater than zero",
+        )
+        .into_response();
+    }
+    ws.on_upgrade(move |socket| handle_socket(socket, state, parameter))
+        .into_response()
+}
+
+/// Submits a bundle received from the client, rejections are sent back to the client as
+/// [`ApiError`]. The payload is encoded with the payload profile of the connection, bundles are
+/// rejected if their payload cannot be encoded, they cannot be sent via LoRaWAN, their routing
+/// hints are invalid, the client exhausted its airtime quota or the bundle queue is full.
+async fn submit_bundle(
+    state: &AppState,
+    client: &str,
+    profile: Option<u8>,
+    submission: BundleSubmission,
+    rejections_tx: &mpsc::Sender<ApiError>,
+) {
+    let (bundle, routing_hints) = submission.into_parts();
+    if let Err(rejection) = try_submit_bundle(state, client, profile, bundle, routing_hints).await {
+        info!("Rejecting bundle of client {client}: {}", rejection.message);
+        if let Err(err) = rejections_tx.try_send(rejection) {
+            error!(%err);
+        }
+    }
+}
+
+/// Encodes, checks and submits a bundle received from the client.
+async fn try_submit_bundle(
+    state: &AppState,
+    client: &str,
+    profile: Option<u8>,
+    mut bundle: bp7::Bundle,
+    routing_hints: RoutingHints,
+) -> Result<(), ApiError> {
+    if let Some(profile) = profile {
+        if let Err(err) = state.payload_codecs.encode(profile, &mut bundle) {
+            return Err(ApiError::new(
+                ApiErrorCode::PayloadEncodingFailed,
+                format!("Failed to encode payload with profile {profile}: {err}"),
+            ));
+        }
+    }
+    let send_buffers = state.oversize_bundles.send_buffers(
+        &mut bundle,
+        state.oversize_bundles.policy(&routing_hints),
+        &state.payload_codecs,
+        state.repeater_compatible,
+    )?;
+    let now = Utc::now();
+    routing_hints.validate(
+        bundle.payload().map_or(0, Vec::len),
+        state.repeater_compatible,
+        now,
+    )?;
+    let hop_distance = match send_buffers.first().map(BundleSendBuffer::destination) {
+        Some(destination) => state
+            .neighbor_table
+            .lock()
+            .await
+            .entries()
+            .get(&destination)
+            .map(|entry| entry.hop_distance),
+        None => None,
+    };
+    routing_hints.check_hop_distance(hop_distance)?;
+    state.client_airtime.admit(client, now).await?;
+    let submitted_bundle = SubmittedBundle {
+        bundle,
+        client: Some(client.to_owned()),
+        routing_hints,
+    };
+    state
+        .bundles_from_ws
+        .try_send(submitted_bundle)
+        .map_err(|err| ApiError::new(ApiErrorCode::BundleQueueFull, err.to_string()))
+}
+
+/// Sends a received bundle to the client as CBOR binary and as JSON text message.
+async fn send_bundle(
+    ws_tx: &mut SplitSink<WebSocket, Message>,
+    bundle: &bp7::Bundle,
+    cbor: Vec<u8>,
+) {
+    trace!("Sending bundle via WS as CBOR binary.");
+    if let Err(err) = ws_tx.send(Message::Binary(cbor)).await {
+        error!(%err);
+    };
+    trace!("Sending bundle via WS as JSON text.");
+    let delivery = BundleDelivery {
+        age: BundleAge::of(bundle, Utc::now()),
+        bundle,
+    };
+    let json = match serde_json::to_string(&delivery) {
+        Ok(json) => json,
+        Err(err) => {
+            error!(%err);
+            return;
+        }
+    };
+    if let Err(err) = ws_tx.send(Message::Text(json)).await {
+        error!(%err);
+    };
+}
+
+/// Sends a batch of received bundles to the client as CBOR binary message holding an array of the
+/// CBOR encoded bundles and as JSON text message wrapping the bundles in [`BundleDelivery`]
+/// envelopes.
+async fn send_batch(ws_tx: &mut SplitSink<WebSocket, Message>, batch: &[(bp7::Bundle, Vec<u8>)]) {
+    trace!("Sending batch of {} bundles via WS.", batch.len());
+    let encoded = serde_cbor::Value::Array(
+        batch
+            .iter()
+            .map(|(_, cbor)| serde_cbor::Value::Bytes(cbor.clone()))
+            .collect(),
+    );
+    match serde_cbor::to_vec(&encoded) {
+        Ok(cbor) => {
+            if let Err(err) = ws_tx.send(Message::Binary(cbor)).await {
+                error!(%err);
+            }
+        }
+        Err(err) => error!(%err),
+    }
+    let now = Utc::now();
+    let delivery = BatchDelivery {
+        bundles: batch
+            .iter()
+            .map(|(bundle, _)| BundleDelivery {
+                age: BundleAge::of(bundle, now),
+                bundle,
+            })
+            .collect(),
+    };
+    match serde_json::to_string(&delivery) {
+        Ok(json) => {
+            if let Err(err) = ws_tx.send(Message::Text(json)).await {
+                error!(%err);
+            }
+        }
+        Err(err) => error!(%err),
+    }
 }
 
-/// Handles websocket connections. Incoming bundles are sent via channel to be processed.
-/// Via LoRaWAN received bundles are sent as CBOR and JSON encoded binary and strict respectively.
-async fn handle_socket(socket: WebSocket, state: Arc<AppState>) {
+/// Delivers the pending bundles the client has credits for, batched if configured.
+async fn flush_deliveries(ws_tx: &mut SplitSink<WebSocket, Message>, window: &mut DeliveryWindow) {
+    loop {
+        let mut batch = window.next_batch();
+        if window.batch_size.is_some() {
+            if batch.is_empty() {
+                return;
+            }
+            send_batch(ws_tx, &batch).await;
+        } else {
+            let Some((bundle, cbor)) = batch.pop() else {
+                return;
+            };
+            send_bundle(ws_tx, &bundle, cbor).await;
+        }
+    }
+}
+
+/// Handles websocket connections. Incoming bundles, optionally next to their routing hints, are
+/// sent via channel to be processed, rejections are sent back as [`ApiError`] JSON text message.
+/// Via LoRaWAN received bundles are sent as CBOR and JSON encoded binary and strict respectively,
+/// the JSON text message wraps the bundle in a [`BundleDelivery`] envelope with its age and
+/// remaining lifetime. Bundles wait for credits granted by the client if it set a window and are
+/// delivered in batches if it set a batch size.
+async fn handle_socket(socket: WebSocket, state: Arc<AppState>, parameter: WsClientParameter) {
     let (mut ws_tx, mut ws_rx) = socket.split();
 
     let mut bundles_to_ws_rx = state.bundles_to_ws.subscribe();
-    let bundles_from_ws_tx = state.bundles_from_ws.clone();
+    let (rejections_tx, mut rejections_rx) = mpsc::channel(10);
+    let (credits_tx, mut credits_rx) = mpsc::channel(10);
+    let mut window = DeliveryWindow::new(&parameter);
+    let client = parameter
+        .client
+        .unwrap_or_else(|| ANONYMOUS_CLIENT.to_owned());
+    let profile = parameter.profile;
 
     trace!("Spawning WS receiver task.");
     tokio::spawn(async move {
@@ -32,13 +386,18 @@ async fn handle_socket(socket: WebSocket, state: Arc<AppState>) {
                 match msg {
                     Message::Text(t) => {
                         trace!("Received text message: {}", t);
-                        match serde_json::from_str::<bp7::Bundle>(&t) {
-                            Ok(bundle) => {
-                                trace!("received bundle via text message: {:?}", bundle);
-                                if let Err(err) = bundles_from_ws_tx.try_send(bundle) {
+                        match serde_json::from_str::<ClientMessage>(&t) {
+                            Ok(ClientMessage::Credits { credits }) => {
+                                trace!("Client granted {credits} credits");
+                                if let Err(err) = credits_tx.try_send(credits) {
                                     error!(%err);
                                 }
                             }
+                            Ok(ClientMessage::Submission(submission)) => {
+                                trace!("received bundle via text message: {:?}", submission);
+                                submit_bundle(&state, &client, profile, submission, &rejections_tx)
+                                    .await;
+                            }
                             Err(e) => {
                                 error!(
                                     "Could not deserialize bundle received via text message: {e:?}"
@@ -47,13 +406,18 @@ async fn handle_socket(socket: WebSocket, state: Arc<AppState>) {
                         }
                     }
                     Message::Binary(payload) => {
-                        match serde_cbor::from_slice::<bp7::Bundle>(&payload) {
-                            Ok(bundle) => {
-                                trace!("received bundle via binary message: {:?}", bundle);
-                                if let Err(err) = bundles_from_ws_tx.try_send(bundle) {
+                        match serde_cbor::from_slice::<ClientMessage>(&payload) {
+                            Ok(ClientMessage::Credits { credits }) => {
+                                trace!("Client granted {credits} credits");
+                                if let Err(err) = credits_tx.try_send(credits) {
                                     error!(%err);
                                 }
                             }
+                            Ok(ClientMessage::Submission(submission)) => {
+                                trace!("received bundle via binary message: {:?}", submission);
+                                submit_bundle(&state, &client, profile, submission, &rejections_tx)
+                                    .await;
+                            }
                             Err(e) => {
                                 error!("Could not deserialize bundle received via binary message: {e:?}");
                             }
@@ -79,15 +443,94 @@ async fn handle_socket(socket: WebSocket, state: Arc<AppState>) {
 
     trace!("Spawning WS sender task.");
     tokio::spawn(async move {
-        while let Ok(mut bundle) = bundles_to_ws_rx.recv().await {
-            trace!("Sending bundle via WS as CBOR binary.");
-            if let Err(err) = ws_tx.send(Message::Binary(bundle.to_cbor())).await {
-                error!(%err);
-            };
-            trace!("Sending bundle via WS as JSON text.");
-            if let Err(err) = ws_tx.send(Message::Text(bundle.to_json())).await {
-                error!(%err);
-            };
+        loop {
+            tokio::select! {
+                bundle = bundles_to_ws_rx.recv() => {
+                    let Ok(bundle) = bundle else {
+                        return;
+                    };
+                    window.push(bundle);
+                    // Bundles received meanwhile are delivered in the same batch.
+                    while let Ok(bundle) = bundles_to_ws_rx.try_recv() {
+                        window.push(bundle);
+                    }
+                    flush_deliveries(&mut ws_tx, &mut window).await;
+                }
+                Some(credits) = credits_rx.recv() => {
+                    window.grant(credits);
+                    flush_deliveries(&mut ws_tx, &mut window).await;
+                }
+                Some(rejection) = rejections_rx.recv() => {
+                    trace!("Sending rejection via WS as JSON text.");
+                    match serde_json::to_string(&rejection) {
+                        Ok(json) => {
+                            if let Err(err) = ws_tx.send(Message::Text(json)).await {
+                                error!(%err);
+                            }
+                        }
+                        Err(err) => error!(%err),
+                    }
+                }
+            }
         }
     });
 }
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use crate::api::websockets::{DeliveryWindow, WsClientParameter};
+    use crate::end_device_id::EndDeviceId;
+    use std::time::Duration;
+
+    fn bundle() -> bp7::Bundle {
+        bp7::primary::PrimaryBlockBuilder::new()
+            .source(EndDeviceId(0x1234).try_into().unwrap())
+            .destination(EndDeviceId(0x5678).try_into().unwrap())
+            .lifetime(Duration::from_secs(3600))
+            .build()
+            .map(|primary| bp7::Bundle::new(primary, vec![]))
+            .unwrap()
+    }
+
+    fn window(
+        batch_size: Option<usize>,
+        batch_bytes: Option<usize>,
+        window: Option<u32>,
+    ) -> DeliveryWindow {
+        DeliveryWindow::new(&WsClientParameter {
+            client: None,
+            profile: None,
+            batch_size,
+            batch_bytes,
+            window,
+        })
+    }
+
+    #[test]
+    fn batches_are_limited_by_credits_and_size() {
+        let mut delivery = window(Some(2), None, Some(3));
+        for _ in 0..4 {
+            delivery.push(bundle());
+        }
+        assert_eq!(delivery.next_batch().len(), 2);
+        assert_eq!(delivery.next_batch().len(), 1);
+        assert!(delivery.next_batch().is_empty());
+        delivery.grant(5);
+        assert_eq!(delivery.next_batch().len(), 1);
+        assert!(delivery.next_batch().is_empty());
+
+        let size = bundle().to_cbor().len();
+        let mut capped = window(Some(10), Some(size.saturating_mul(2)), None);
+        for _ in 0..3 {
+            capped.push(bundle());
+        }
+        assert_eq!(capped.next_batch().len(), 2);
+        assert_eq!(capped.next_batch().len(), 1);
+
+        let mut unbatched = window(None, None, None);
+        unbatched.push(bundle());
+        unbatched.push(bundle());
+        assert_eq!(unbatched.next_batch().len(), 1);
+    }
+}
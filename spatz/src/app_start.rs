@@ -1,53 +1,177 @@
 //! Methods used when starting the Spatz application.
 
+#[cfg(feature = "api")]
+use crate::api::authorization::ApiAuthorization;
+#[cfg(feature = "api")]
 use crate::api::create_api;
+#[cfg(feature = "beaconing")]
+use crate::beaconing;
+use crate::bp7_interop::Bp7Interop;
+use crate::bundle_delivery::LateDelivery;
+use crate::bundle_mailbox::BundleMailbox;
+use crate::bundle_parking::BundleParking;
 use crate::bundle_processing::bundles_processor_task;
-use crate::configuration::{CliParameters, Configuration, RoutingAlgorithmConfig};
-use crate::database::{fetch_from_db, insert_into_db, DataKey};
-use crate::duty_cycle_manager::{DownlinkCallback, DutyCycleManager};
+use crate::bundle_resend::BundleResend;
+use crate::client_airtime::ClientAirtime;
+use crate::clock::SystemClock;
+use crate::configuration::{
+    AnnouncementConfig, CliParameters, Configuration, DestinationClass, DutyCycleSharingConfig,
+    MqttV5Config, PacketExportFormat, RoutingAlgorithmConfig, UplinkTraceConfig,
+};
+use crate::database::{
+    check_database_writable, fetch_from_db, insert_into_db, open_database, DataKey, DatabaseHealth,
+    DbPool,
+};
+use crate::delivery_ledger::DeliveryLedger;
+use crate::diagnostics::Diagnostics;
+use crate::duty_cycle_manager::{DownlinkCallback, DutyCycleManager, EuDutyCycle};
 use crate::end_device_id::{EndDeviceId, ManagedEndDeviceId};
-use crate::gateway_ids_manager::GatewayIdsManager;
-use crate::graceful_shutdown::{ShutdownAgent, ShutdownConditions, ShutdownInitiator};
+use crate::end_device_registry::EndDeviceRegistry;
+use crate::frequency_lockouts::FrequencyLockouts;
+use crate::gateway_ids_manager::{GatewayCapabilitiesCallback, GatewayIdsManager};
+use crate::gateway_selection::{GatewayLocationCallback, GatewaySelector, TxAckCallback};
+use crate::gateway_stats::GatewayStatsCallback;
+use crate::graceful_shutdown::{
+    ShutdownAgent, ShutdownConditions, ShutdownInitiator, ShutdownReason,
+};
+use crate::inbound_duplicates::InboundDuplicateMetrics;
+use crate::measurement::Measurements;
+#[cfg(feature = "api")]
+use crate::measurement::{request_measurement, MeasurementParameter};
+use crate::memory_budget::MemoryBudget;
+use crate::neighbor_table::NeighborTable;
+use crate::neighbor_trust::NeighborTrust;
+use crate::network_filter::NetworkFilter;
+use crate::operating_mode::{DegradedCondition, OperatingMode};
+use crate::oversize_bundles::OversizeBundles;
 use crate::packet_cache::PacketCache;
+use crate::packet_export::PacketExport;
 use crate::packet_queue_manager::QueueManager;
-use crate::routing::{Flooding, RoutingAlgorithm};
+use crate::path_cache::PathCache;
+use crate::payload_codecs::CodecRegistry;
+use crate::radio_silence::RadioSilence;
+use crate::radio_stats::RadioStats;
+use crate::received_packets::{ReceivedPacketLog, RECEIVED_PACKETS_LOG_SIZE};
+use crate::routing::{
+    DownlinkRetransmission, Flooding, RoutingAlgorithm, RoutingDispatcher, RoutingScope,
+    TdmaCoordinator,
+};
+use crate::scheduling_journal::SchedulingJournal;
+use crate::send_buffers::{BundleSendBuffer, SendBuffer, SendBufferProgress};
+use crate::startup::{Retry, StartupSupervisor};
+use crate::status_reports::StatusReports;
+use crate::subsystem_control::SubsystemControl;
+use crate::unicast::Unicast;
 use crate::uplink_processing::UplinkCallback;
+use crate::uplink_trace::UplinkTraceRecorder;
+use crate::uplink_validation::UplinkValidator;
+use crate::watchdog::{SupervisedTask, Watchdog, HEARTBEAT_INTERVAL};
+#[cfg(feature = "webhooks")]
+use crate::webhooks;
 use crate::{
-    duty_cycle_manager, packet_cache, receive_buffers, uplink_processing, AppState, SpatzConfig,
+    announcements, bundle_parking, duty_cycle_manager, file_drop, gateway_ids_manager,
+    gateway_selection, gateway_send_queues, gateway_stats, memory_budget, neighbor_table,
+    packet_cache, packet_export, plugins, startup, uplink_processing, uplink_trace, watchdog,
+    AppState, SpatzConfig,
 };
+#[cfg(feature = "api")]
 use axum::Router;
 use chirpstack_api_wrapper::ChirpStackApi;
+use chirpstack_gwb_integration::downlinks::predefined_parameters::Frequency;
+use chirpstack_gwb_integration::logging::{init_logging, LoggingConfig};
+use chirpstack_gwb_integration::runtime::metrics::CountingMetrics;
+use chirpstack_gwb_integration::runtime::{RuntimeOptions, Uuid};
 use clap::Parser;
 use config::Config;
-use sqlx::sqlite::SqliteConnectOptions;
-use sqlx::SqlitePool;
+use lorawan_dtn_protocol::generate_wireshark_dissector;
 use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::future::Future;
+#[cfg(feature = "api")]
 use std::net::SocketAddr;
-use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
 use tokio::sync::{broadcast, mpsc, Mutex};
-use tracing::{error, instrument, trace};
+use tracing::{error, info, instrument, trace, warn};
 
-/// Creates the database connection and handles the configuration parsing.
-pub async fn database_and_config(cli_parameters: &CliParameters) -> (SqlitePool, Configuration) {
-    let db_pool = SqlitePool::connect_with(
-        SqliteConnectOptions::from_str(&cli_parameters.db_url)
-            .expect("Failed to create DB connection from URL")
-            .create_if_missing(true),
-    )
-    .await
-    .expect("Failed to open DB file");
+/// Default log filter directives if neither `RUST_LOG` nor the logging configuration set any.
+#[cfg(debug_assertions)]
+const DEFAULT_LOG_DIRECTIVES: &str = "spatz=trace,tower_http=trace";
 
-    trace!("Running DB migrations");
-    sqlx::migrate!()
-        .run(&db_pool)
-        .await
-        .expect("Failed to run DB migrations");
+/// Default log filter directives if neither `RUST_LOG` nor the logging configuration set any.
+#[cfg(not(debug_assertions))]
+const DEFAULT_LOG_DIRECTIVES: &str = "spatz=error";
+
+/// Attempts to connect the runtime to the MQTT broker.
+const RUNTIME_RETRY: Retry = Retry {
+    attempts: 3,
+    delay: std::time::Duration::from_secs(5),
+};
+
+/// Attempts to add a callback to the runtime, only failing on a UUID collision or a stopped
+/// runtime.
+const CALLBACK_RETRY: Retry = Retry {
+    attempts: 2,
+    delay: std::time::Duration::ZERO,
+};
+
+/// Logging configuration installed when the process started.
+static INSTALLED_LOGGING: OnceLock<LoggingConfig> = OnceLock::new();
+
+/// Installs the logging sinks of the configuration file before the database is opened, so the
+/// startup is logged as well.
+///
+/// Logging cannot be reconfigured while running, it is configured by the configuration file when
+/// the process starts. Falls back to logging to stdout if the configuration file cannot be read
+/// or the configured sinks cannot be set up.
+pub fn setup_logging() {
+    let cli_parameters = CliParameters::parse();
+    let logging_config = match Config::builder()
+        .add_source(config::File::with_name(&cli_parameters.config_file_path).required(false))
+        .build()
+        .and_then(|config| config.get::<LoggingConfig>("logging"))
+    {
+        Ok(logging_config) => logging_config,
+        Err(config::ConfigError::NotFound(_)) => LoggingConfig::default(),
+        Err(err) => {
+            eprintln!("Failed to read the logging configuration, logging to stdout: {err}");
+            LoggingConfig::default()
+        }
+    };
+    if let Err(err) = init_logging(&logging_config, DEFAULT_LOG_DIRECTIVES) {
+        let fallback_result = init_logging(&LoggingConfig::default(), DEFAULT_LOG_DIRECTIVES);
+        error!("Failed to set up logging, falling back to stdout: {err}");
+        if let Err(err) = fallback_result {
+            eprintln!("Failed to set up logging: {err}");
+        }
+    }
+    let _ = INSTALLED_LOGGING.set(logging_config);
+}
+
+/// Logs the problems of the configuration, returns whether it can be applied.
+fn check_configuration(configuration: &Configuration) -> bool {
+    let errors = configuration.validate();
+    for err in &errors {
+        error!("Invalid configuration: {err}");
+    }
+    errors.is_empty()
+}
+
+/// Creates the database connection and handles the configuration parsing.
+///
+/// A read-only database is tolerated, the instance is operated in volatile mode. Returns `None`
+/// if the configuration is invalid, a configuration file is only stored in the database if valid.
+pub async fn database_and_config(
+    cli_parameters: &CliParameters,
+) -> Option<(DbPool, Configuration)> {
+    let db_pool = open_database(&cli_parameters.db_url).await;
 
     trace!("Building configuration");
     let configuration: Configuration =
         if let Ok(configuration) = fetch_from_db(DataKey::Configuration, db_pool.clone()).await {
             trace!("Using database configuration");
+            if !check_configuration(&configuration) {
+                return None;
+            }
             configuration
         } else {
             trace!("Using configuration file");
@@ -60,12 +184,21 @@ pub async fn database_and_config(cli_parameters: &CliParameters) -> (SqlitePool,
             let configuration = configuration
                 .try_deserialize::<Configuration>()
                 .expect("Failed to deserialize configuration");
-            insert_into_db(DataKey::Configuration, &configuration, db_pool.clone())
-                .await
-                .expect("Failed to insert configuration into database");
+            if !check_configuration(&configuration) {
+                return None;
+            }
+            if let Err(err) =
+                insert_into_db(DataKey::Configuration, &configuration, db_pool.clone()).await
+            {
+                assert!(
+                    check_database_writable(db_pool.clone()).await.is_err(),
+                    "Failed to insert configuration into database: {err}"
+                );
+                warn!("Failed to insert configuration into read-only database: {err}");
+            }
             configuration
         };
-    (db_pool, configuration)
+    Some((db_pool, configuration))
 }
 
 /// Starts all parts of the application.
@@ -73,11 +206,54 @@ pub async fn database_and_config(cli_parameters: &CliParameters) -> (SqlitePool,
 pub async fn start_app(
     shutdown_agent: ShutdownAgent,
     shutdown_initiator: ShutdownInitiator,
+    database_shutdown_initiator: ShutdownInitiator,
 ) -> Result<Arc<AppState>, ()> {
     trace!("Parsing cli parameters");
     let cli_parameters = CliParameters::parse();
+    if let Some(path) = &cli_parameters.export_wireshark_dissector {
+        if let Err(err) = std::fs::write(path, generate_wireshark_dissector()) {
+            eprintln!("Failed to write the Wireshark dissector to {path}: {err}");
+        }
+        return Err(());
+    }
+    if let Some(destination) = cli_parameters.measure {
+        #[cfg(feature = "api")]
+        {
+            let parameter = MeasurementParameter {
+                destination: EndDeviceId(destination),
+                bundles: cli_parameters.measure_bundles,
+                payload_size: cli_parameters.measure_payload_size,
+                interval_seconds: cli_parameters.measure_interval_seconds,
+                timeout_seconds: None,
+            };
+            match request_measurement(
+                &cli_parameters.api_url,
+                cli_parameters.api_token.as_deref(),
+                &parameter,
+            )
+            .await
+            {
+                Ok(report) => println!("{report}"),
+                Err(err) => eprintln!("Measurement failed: {err}"),
+            }
+        }
+        #[cfg(not(feature = "api"))]
+        eprintln!("Measuring the delivery to {destination} requires the api feature");
+        return Err(());
+    }
 
-    let (db_pool, configuration) = database_and_config(&cli_parameters).await;
+    let Some((db_pool, configuration)) = database_and_config(&cli_parameters).await else {
+        eprintln!("Refusing to start with an invalid configuration");
+        return Err(());
+    };
+    if INSTALLED_LOGGING
+        .get()
+        .is_some_and(|installed| *installed != configuration.logging)
+    {
+        warn!(
+            "Ignoring the differing logging configuration of the database until the file changes"
+        );
+    }
 
     trace!("Creating channels");
     let (bundles_from_ws_tx, bundles_from_ws_rx) = mpsc::channel(10);
@@ -85,48 +261,283 @@ pub async fn start_app(
     let (uplink_callback_tx, uplink_callback_rx) = mpsc::channel(10);
     let (relay_tx, relay_rx) = mpsc::channel(10);
     let (bundle_send_buffer_tx, bundle_send_buffer_rx) = mpsc::channel(10);
+    let (parking_tx, parking_rx) = mpsc::channel(10);
     let (downlink_callback_tx, downlink_callback_rx) = mpsc::channel(10);
     let (mqtt_connection_error_tx, mqtt_connection_error_rx) = broadcast::channel(10);
 
+    let mut supervisor = StartupSupervisor::new();
+
     trace!("Creating runtime");
-    let mut runtime = match chirpstack_gwb_integration::runtime::Runtime::new(
-        &configuration.mqtt.client_id,
-        &configuration.mqtt.url,
-        configuration.mqtt.port,
-        Some(mqtt_connection_error_tx),
-    )
-    .await
-    {
-        Ok(runtime) => runtime,
-        Err(e) => {
-            error!("Failed to create runtime: {e}");
-            return Err(());
-        }
+    let runtime_metrics = Arc::new(CountingMetrics::new());
+    let runtime_options = RuntimeOptions {
+        marshaler: configuration.mqtt.marshaler,
+        topic_layout: configuration.mqtt.topic_layout.clone(),
+        metrics: Some(runtime_metrics.clone()),
+        clock_drift_threshold: configuration
+            .mqtt
+            .clock_drift_threshold_ms
+            .map(std::time::Duration::from_millis),
+        mqtt_v5: configuration
+            .mqtt
+            .v5
+            .as_ref()
+            .map(MqttV5Config::runtime_options),
     };
-
-    trace!("Adding universal uplink callback to runtime");
-    if let Err(e) = runtime
-        .add_event_up_callback(None, Box::new(UplinkCallback { uplink_callback_tx }))
+    let Some(runtime) = supervisor
+        .run(startup::RUNTIME, &[], RUNTIME_RETRY, || {
+            chirpstack_gwb_integration::runtime::Runtime::new(
+                &configuration.mqtt.client_id,
+                &configuration.mqtt.url,
+                configuration.mqtt.port,
+                runtime_options.clone(),
+                Some(mqtt_connection_error_tx.clone()),
+            )
+        })
         .await
-    {
-        error!("Failed to add callback to mqtt runtime: {e}");
+    else {
+        supervisor.finish();
         return Err(());
+    };
+
+    trace!("Fetching downlink ID counters from database");
+    if let Ok(downlink_id_counters) =
+        fetch_from_db(DataKey::DownlinkIdCounters, db_pool.clone()).await
+    {
+        runtime
+            .restore_downlink_id_counters(downlink_id_counters)
+            .await;
     }
 
+    let replay_uplink_tx = uplink_callback_tx.clone();
+    #[cfg(feature = "beaconing")]
+    let beaconing_uplink_tx = uplink_callback_tx.clone();
+    trace!("Adding universal uplink callback to runtime");
+    add_callback(
+        &mut supervisor,
+        &runtime,
+        startup::UPLINK_CALLBACK,
+        "uplink processing",
+        |mut runtime| {
+            let uplink_callback_tx = uplink_callback_tx.clone();
+            async move {
+                runtime
+                    .add_event_up_callback(None, Box::new(UplinkCallback { uplink_callback_tx }))
+                    .await
+            }
+        },
+    )
+    .await;
+
     trace!("Adding universal downlink callback to runtime");
-    if let Err(e) = runtime
-        .add_command_down_callback(
-            None,
-            Box::new(DownlinkCallback {
-                downlink_callback_tx,
-            }),
+    add_callback(
+        &mut supervisor,
+        &runtime,
+        startup::DOWNLINK_CALLBACK,
+        "duty cycle accounting",
+        |mut runtime| {
+            let downlink_callback_tx = downlink_callback_tx.clone();
+            async move {
+                runtime
+                    .add_command_down_callback(
+                        None,
+                        Box::new(DownlinkCallback {
+                            downlink_callback_tx,
+                        }),
+                    )
+                    .await
+            }
+        },
+    )
+    .await;
+
+    let gateway_stats_rx = if configuration.daemon.gateway_stats.is_some() {
+        trace!("Adding universal stats callback to runtime");
+        let (stats_callback_tx, stats_callback_rx) = mpsc::channel(10);
+        add_callback(
+            &mut supervisor,
+            &runtime,
+            startup::GATEWAY_STATS_CALLBACK,
+            "gateway stats",
+            |mut runtime| {
+                let stats_callback_tx = stats_callback_tx.clone();
+                async move {
+                    runtime
+                        .add_event_stats_callback(
+                            None,
+                            Box::new(GatewayStatsCallback { stats_callback_tx }),
+                        )
+                        .await
+                }
+            },
         )
-        .await
-    {
-        error!("Failed to add callback to mqtt runtime: {e}");
+        .await;
+        Some(stats_callback_rx)
+    } else {
+        supervisor.disable(
+            startup::GATEWAY_STATS_CALLBACK,
+            "Gateway stats not configured",
+        );
+        None
+    };
+
+    trace!("Adding universal ack callback to runtime");
+    let (ack_callback_tx, ack_callback_rx) = mpsc::channel(10);
+    add_callback(
+        &mut supervisor,
+        &runtime,
+        startup::ACK_CALLBACK,
+        "downlink acknowledgements",
+        |mut runtime| {
+            let ack_callback_tx = ack_callback_tx.clone();
+            async move {
+                runtime
+                    .add_event_ack_callback(None, Box::new(TxAckCallback { ack_callback_tx }))
+                    .await
+            }
+        },
+    )
+    .await;
+
+    trace!("Adding universal gateway location callback to runtime");
+    let (location_callback_tx, location_callback_rx) = mpsc::channel(10);
+    add_callback(
+        &mut supervisor,
+        &runtime,
+        startup::GATEWAY_LOCATION_CALLBACK,
+        "gateway locations",
+        |mut runtime| {
+            let location_callback_tx = location_callback_tx.clone();
+            async move {
+                runtime
+                    .add_event_stats_callback(
+                        None,
+                        Box::new(GatewayLocationCallback {
+                            location_callback_tx,
+                        }),
+                    )
+                    .await
+            }
+        },
+    )
+    .await;
+
+    trace!("Adding universal gateway capabilities callback to runtime");
+    let (capabilities_callback_tx, capabilities_callback_rx) = mpsc::channel(10);
+    add_callback(
+        &mut supervisor,
+        &runtime,
+        startup::GATEWAY_CAPABILITIES_CALLBACK,
+        "gateway capabilities",
+        |mut runtime| {
+            let capabilities_callback_tx = capabilities_callback_tx.clone();
+            async move {
+                runtime
+                    .add_event_stats_callback(
+                        None,
+                        Box::new(GatewayCapabilitiesCallback {
+                            capabilities_callback_tx,
+                        }),
+                    )
+                    .await
+            }
+        },
+    )
+    .await;
+
+    // Uplinks, downlinks or gateway events would be lost without the callbacks.
+    if !supervisor.summary().is_complete() {
+        supervisor.finish();
         return Err(());
     }
 
+    let delivery_ledger =
+        if let Some(delivery_ledger_config) = &configuration.daemon.delivery_ledger {
+            trace!("Fetching delivered bundles from database");
+            let delivered = fetch_from_db(DataKey::DeliveredBundles, db_pool.clone())
+                .await
+                .unwrap_or_default();
+            Some(DeliveryLedger::new(delivery_ledger_config, delivered))
+        } else {
+            None
+        };
+
+    let bundle_mailbox = if let Some(bundle_mailbox_config) = &configuration.daemon.bundle_mailbox {
+        trace!("Fetching bundle mailbox from database");
+        let contents = fetch_from_db(DataKey::BundleMailbox, db_pool.clone())
+            .await
+            .unwrap_or_default();
+        Some(BundleMailbox::new(bundle_mailbox_config, contents))
+    } else {
+        None
+    };
+
+    trace!("Fetching client airtime usage from database");
+    let client_airtime = ClientAirtime::new(
+        configuration.daemon.client_airtime_quota.as_ref(),
+        fetch_from_db(DataKey::ClientAirtime, db_pool.clone())
+            .await
+            .unwrap_or_default(),
+    );
+
+    let radio_stats = if let Some(radio_stats_config) = &configuration.daemon.radio_stats {
+        trace!("Fetching radio stats from database");
+        let counts = fetch_from_db(DataKey::RadioStats, db_pool.clone())
+            .await
+            .unwrap_or_default();
+        Some(RadioStats::new(radio_stats_config, counts))
+    } else {
+        None
+    };
+
+    let scheduling_journal =
+        if let Some(scheduling_journal_config) = &configuration.daemon.scheduling_journal {
+            trace!("Fetching scheduling journal from database");
+            let entries = fetch_from_db(DataKey::SchedulingJournal, db_pool.clone())
+                .await
+                .unwrap_or_default();
+            Some(SchedulingJournal::new(scheduling_journal_config, entries))
+        } else {
+            None
+        };
+
+    let packet_export = configuration
+        .daemon
+        .packet_export
+        .as_ref()
+        .filter(|packet_export_config| {
+            let available = cfg!(feature = "parquet-export")
+                || packet_export_config.format != PacketExportFormat::Parquet;
+            if !available {
+                error!(
+                    "Parquet export is configured but the parquet-export feature is not enabled"
+                );
+            }
+            available
+        })
+        .map(PacketExport::new);
+
+    trace!("Fetching neighbor trust from database");
+    let neighbor_trust = NeighborTrust::new(
+        configuration.daemon.neighbor_trust.clone(),
+        &configuration.daemon.neighbor_blacklist,
+        fetch_from_db(DataKey::NeighborTrust, db_pool.clone())
+            .await
+            .unwrap_or_default(),
+        chrono::Utc::now(),
+    );
+
+    trace!("Fetching last shutdown report from database");
+    let last_shutdown = fetch_from_db(DataKey::LastShutdown, db_pool.clone())
+        .await
+        .ok();
+
+    trace!("Fetching paused subsystems from database");
+    let subsystem_control = SubsystemControl::new(
+        fetch_from_db(DataKey::SubsystemControl, db_pool.clone())
+            .await
+            .unwrap_or_default(),
+    );
+
     trace!("Creating ChirpStack API info");
     let chirpstack_api = ChirpStackApi {
         url: configuration.chirpstack_api.url.clone(),
@@ -145,12 +556,13 @@ pub async fn start_app(
         HashMap::new()
     };
 
+    let clock = SystemClock::shared();
+
     trace!("Creating packet cache");
     let packet_cache = PacketCache::new(
         packet_cache_data,
-        configuration.daemon.packet_cache.timeout_minutes,
-        configuration.daemon.packet_cache.cleanup_interval_seconds,
-        configuration.daemon.packet_cache.reset_timeout,
+        &configuration.daemon.packet_cache,
+        clock.clone(),
     );
 
     trace!("Calculating end device IDs");
@@ -160,6 +572,12 @@ pub async fn start_app(
         .iter()
         .map(ManagedEndDeviceId::from)
         .collect();
+    let group_ids: HashSet<ManagedEndDeviceId> = configuration
+        .daemon
+        .group_ids
+        .iter()
+        .map(ManagedEndDeviceId::from)
+        .collect();
 
     trace!("Fetching duty cycle data from database");
     let duty_cycle_data =
@@ -170,8 +588,25 @@ pub async fn start_app(
             HashMap::new()
         };
 
+    trace!("Fetching frequency lockouts from database");
+    let frequency_lockouts = Arc::new(FrequencyLockouts::new(
+        fetch_from_db(DataKey::FrequencyLockouts, db_pool.clone())
+            .await
+            .unwrap_or_default(),
+    ));
+
     trace!("Creating duty cycle manager");
-    let duty_cycle_manager = Arc::new(Mutex::new(DutyCycleManager::new(duty_cycle_data)));
+    let duty_cycle_manager = Arc::new(Mutex::new(DutyCycleManager::new(
+        duty_cycle_data,
+        Box::new(EuDutyCycle),
+        configuration
+            .daemon
+            .duty_cycle_sharing
+            .as_ref()
+            .map(DutyCycleSharingConfig::dtn_share),
+        frequency_lockouts.clone(),
+        clock.clone(),
+    )));
 
     trace!("Fetching message buffers and relay messages from database");
     let relay_packet_queue = if let Ok(relay_packet_queue) =
@@ -182,149 +617,717 @@ pub async fn start_app(
     } else {
         Arc::new(Mutex::new(Vec::new()))
     };
-    let bundle_send_buffer_queue = if let Ok(bundle_send_buffer_queue) =
-        fetch_from_db(DataKey::MessageBuffers, db_pool.clone()).await
+    let bundle_send_buffer_queue = if let Ok(mut bundle_send_buffer_queue) =
+        fetch_from_db::<Vec<BundleSendBuffer>>(DataKey::MessageBuffers, db_pool.clone()).await
     {
+        trace!("Fetching send buffer progress from database");
+        let send_buffer_progress: Vec<SendBufferProgress> =
+            fetch_from_db(DataKey::SendBufferProgress, db_pool.clone())
+                .await
+                .unwrap_or_default();
+        for bundle_send_buffer in &mut bundle_send_buffer_queue {
+            if send_buffer_progress
+                .iter()
+                .any(|progress| bundle_send_buffer.resume(progress))
+            {
+                info!(
+                    source = ?bundle_send_buffer.source(),
+                    "Resuming partially transmitted bundle"
+                );
+            }
+        }
+        bundle_send_buffer_queue.retain(|bundle_send_buffer| !bundle_send_buffer.is_empty());
         Arc::new(Mutex::new(bundle_send_buffer_queue))
     } else {
         Arc::new(Mutex::new(Vec::new()))
     };
 
+    let memory_budget = Arc::new(MemoryBudget::new(
+        configuration.daemon.memory_budget.as_ref(),
+    ));
+
     trace!("Creating queue manager");
     let queue_manager = Arc::new(QueueManager::new(
         relay_packet_queue,
         configuration.daemon.queue_config.relay_queue_size,
         bundle_send_buffer_queue,
         configuration.daemon.queue_config.bundle_queue_size,
+        configuration.daemon.queue_config.announcement_queue_size,
+        memory_budget.clone(),
+        configuration.daemon.queue_config.preemption,
     ));
 
+    trace!("Creating operating mode");
+    let mut operating_mode = OperatingMode::new();
+    if let Err(err) = check_database_writable(db_pool.clone()).await {
+        operating_mode.enter(DegradedCondition::DatabaseReadOnly, err.to_string());
+    }
+
+    trace!("Fetching last known gateway IDs from database");
+    let gateway_ids =
+        if let Ok(gateway_ids) = fetch_from_db(DataKey::GatewayIds, db_pool.clone()).await {
+            trace!("Fetch last known gateway IDs from database");
+            gateway_ids
+        } else {
+            HashSet::new()
+        };
+
     trace!("Creating gateway IDs manager");
-    let gateway_ids_manager = GatewayIdsManager::new(std::time::Duration::from_secs(60));
+    let gateway_ids_manager = GatewayIdsManager::new(
+        std::time::Duration::from_secs(60),
+        gateway_ids,
+        &configuration.daemon.gateway_capabilities,
+    );
 
-    trace!("Creating routing algorithm");
-    let mut routing_algo = Box::new(match &configuration.daemon.routing_algorithm_config {
-        RoutingAlgorithmConfig::Flooding(config) => {
-            Flooding::new(std::time::Duration::from_secs(config.periodic_send_delay))
-        }
-    });
-    // Provides a shutdown agent to the routing algorithm.
-    routing_algo.provide_shutdown_agent(shutdown_agent.clone());
+    let gateway_selection = match &configuration.daemon.routing_algorithm_config {
+        RoutingAlgorithmConfig::Flooding(config) => config.gateway_selection.clone(),
+    };
+
+    trace!("Creating routing algorithms");
+    let node_profile = configuration.daemon.node_profile;
+    let mut default_destination_classes: HashSet<DestinationClass> = DestinationClass::ALL
+        .into_iter()
+        .filter(|destination_class| node_profile.routes(*destination_class))
+        .collect();
+    let announcement_frequencies = configuration
+        .daemon
+        .announcement_config
+        .as_ref()
+        .map(AnnouncementConfig::frequencies)
+        .unwrap_or_default();
+    let mut routing_algorithms = Vec::new();
+    for scoped_routing_algorithm in &configuration.daemon.scoped_routing_algorithms {
+        let destination_classes: HashSet<DestinationClass> = scoped_routing_algorithm
+            .destination_classes
+            .iter()
+            .copied()
+            .filter(|destination_class| default_destination_classes.remove(destination_class))
+            .collect();
+        routing_algorithms.push(create_routing_algorithm(
+            &scoped_routing_algorithm.routing_algorithm_config,
+            destination_classes,
+            &end_device_ids,
+            &announcement_frequencies,
+        ));
+    }
+    if !default_destination_classes.is_empty() {
+        routing_algorithms.push(create_routing_algorithm(
+            &configuration.daemon.routing_algorithm_config,
+            default_destination_classes,
+            &end_device_ids,
+            &announcement_frequencies,
+        ));
+    }
+    // Provides a shutdown agent to the routing algorithms.
+    for (routing_algorithm, _) in &mut routing_algorithms {
+        routing_algorithm.provide_shutdown_agent(shutdown_agent.clone());
+    }
+    let routing_dispatcher = RoutingDispatcher::new(routing_algorithms);
 
     let spatz_config = SpatzConfig {
         next_configuration: configuration.clone(),
         currently_active_configuration: configuration.clone(),
     };
 
+    trace!("Creating end device registry");
+    let end_device_ids = Arc::new(Mutex::new(end_device_ids));
+    let group_ids = Arc::new(Mutex::new(group_ids));
+    let neighbor_table = Arc::new(Mutex::new(NeighborTable::new()));
+    let end_device_registry = EndDeviceRegistry::new(
+        end_device_ids.clone(),
+        group_ids.clone(),
+        neighbor_table.clone(),
+        configuration
+            .daemon
+            .announcement_config
+            .as_ref()
+            .and_then(|announcement_config| announcement_config.proxy.as_ref())
+            .map(|proxy| proxy.max_hop_distance),
+    );
+
+    let (gateway_send_queues, gateway_send_queues_rx) = configuration
+        .daemon
+        .gateway_send_queues
+        .clone()
+        .map(GatewaySendQueues::new)
+        .unzip();
+
     trace!("Creating state");
     let state = Arc::new(AppState {
         bundles_to_ws: bundles_to_ws_tx,
         bundles_from_ws: bundles_from_ws_tx,
         runtime: runtime.clone(),
-        end_device_ids: Arc::new(Mutex::new(end_device_ids)),
+        runtime_metrics,
+        end_device_ids,
+        group_ids,
         chirpstack_api,
         packet_cache,
+        clock,
         duty_cycle_manager,
+        frequency_lockouts,
         queue_manager,
         gateway_ids_manager,
-        routing_algo,
+        routing_dispatcher,
         db_pool: db_pool.clone(),
         restart_initiator: shutdown_initiator,
         configuration: Arc::new(Mutex::new(spatz_config)),
+        neighbor_table,
+        received_packets: Arc::new(Mutex::new(ReceivedPacketLog::new(
+            RECEIVED_PACKETS_LOG_SIZE,
+        ))),
+        operating_mode: Arc::new(Mutex::new(operating_mode)),
+        end_device_registry,
+        diagnostics: Diagnostics::new(),
+        measurements: Measurements::new(),
+        status_reports: StatusReports::new(),
+        bundle_resend: BundleResend::new(configuration.daemon.bundle_resend.clone()),
+        gateway_selector: Arc::new(Mutex::new(GatewaySelector::new(gateway_selection))),
+        gateway_send_queues,
+        downlink_retransmission: DownlinkRetransmission::new(
+            configuration.daemon.downlink_retransmission.clone(),
+        ),
+        bundle_parking: configuration
+            .daemon
+            .bundle_parking
+            .as_ref()
+            .map(BundleParking::new),
+        inbound_duplicate_metrics: InboundDuplicateMetrics::default(),
+        network_filter: NetworkFilter::new(configuration.daemon.network_filter.clone()),
+        uplink_validator: UplinkValidator::new(configuration.daemon.process_crc_errors),
+        repeater_compatible: configuration.daemon.repeater_compatible,
+        node_profile,
+        late_delivery: LateDelivery::new(configuration.daemon.late_delivery_policy),
+        delivery_ledger,
+        bundle_mailbox,
+        client_airtime,
+        radio_stats,
+        scheduling_journal,
+        packet_export,
+        neighbor_trust,
+        radio_silence: RadioSilence::new(configuration.daemon.radio_silence.clone()),
+        last_shutdown,
+        memory_budget,
+        payload_codecs: CodecRegistry::new(&configuration.daemon.payload_profiles),
+        oversize_bundles: OversizeBundles::new(configuration.daemon.oversize_bundles),
+        subsystem_control,
+        unicast: configuration.daemon.unicast.clone().map(Unicast::new),
+        path_cache: configuration.daemon.path_cache.clone().map(PathCache::new),
+        bp7_interop: configuration
+            .daemon
+            .bp7_interop
+            .clone()
+            .map(Bp7Interop::new),
+        database_health: DatabaseHealth::new(
+            configuration.daemon.database_error_policy,
+            database_shutdown_initiator,
+        ),
+        watchdog: Watchdog::new(configuration.daemon.watchdog.as_ref()),
+        startup_summary: OnceLock::new(),
     });
 
-    let addr = SocketAddr::from((
-        configuration.daemon.bind_config.bind_addr,
-        configuration.daemon.bind_config.bind_port,
-    ));
-
-    trace!("Spawn flooding task");
-    let flooding_shutdown_agent = shutdown_agent.clone();
-    let state_clone = state.clone();
-    tokio::spawn(async move {
-        let state_clone1 = state_clone.clone();
-        state_clone
-            .routing_algo
-            .routing_task(state_clone1, flooding_shutdown_agent)
-            .await;
-    });
+    let mut supervised_tasks = Vec::new();
 
     trace!("Spawn MQTT connection error listener");
     let mqtt_shutdown_agent = shutdown_agent.clone();
-    tokio::spawn(async move {
-        mqtt_connection_error_task(mqtt_connection_error_rx, mqtt_shutdown_agent).await;
+    supervisor.start(startup::MQTT_CONNECTION_ERRORS, &[startup::RUNTIME], || {
+        tokio::spawn(async move {
+            mqtt_connection_error_task(mqtt_connection_error_rx, mqtt_shutdown_agent).await;
+        })
     });
 
     trace!("Spawn runtime shutdown task");
     let runtime_shutdown_agent = shutdown_agent.clone();
     let runtime_clone = runtime.clone();
-    tokio::spawn(async move { runtime_shutdown_task(runtime_clone, runtime_shutdown_agent).await });
+    supervisor.start(startup::RUNTIME_SHUTDOWN, &[startup::RUNTIME], || {
+        tokio::spawn(
+            async move { runtime_shutdown_task(runtime_clone, runtime_shutdown_agent).await },
+        )
+    });
 
     trace!("Spawning QueueManager::collect_send_items task");
     let consolidate_send_items_shutdown_agent = shutdown_agent.clone();
     let queue_manager_clone = state.queue_manager.clone();
-    tokio::spawn(async move {
-        queue_manager_clone
-            .collect_send_items_task(
-                relay_rx,
-                bundle_send_buffer_rx,
-                consolidate_send_items_shutdown_agent,
-            )
-            .await;
+    supervisor.start(startup::SEND_ITEM_COLLECTOR, &[], || {
+        tokio::spawn(async move {
+            queue_manager_clone
+                .collect_send_items_task(
+                    relay_rx,
+                    bundle_send_buffer_rx,
+                    consolidate_send_items_shutdown_agent,
+                )
+                .await;
+        })
     });
 
     trace!("Spawning packet cache clean task");
+    let spawn_cache_clean_task = {
+        let state = state.clone();
+        let shutdown_agent = shutdown_agent.clone();
+        move || {
+            let state_clone = state.clone();
+            let cache_clean_task_shutdown_agent = shutdown_agent.clone();
+            tokio::spawn(async move {
+                packet_cache::cache_clean_task(state_clone, cache_clean_task_shutdown_agent).await;
+            })
+        }
+    };
+    if let Some(handle) =
+        supervisor.start(watchdog::PACKET_CACHE_CLEANER, &[], &spawn_cache_clean_task)
+    {
+        state.watchdog.register(
+            watchdog::PACKET_CACHE_CLEANER,
+            std::time::Duration::from_secs(state.packet_cache.cleanup_interval_seconds),
+            chrono::Utc::now(),
+        );
+        supervised_tasks.push(SupervisedTask {
+            name: watchdog::PACKET_CACHE_CLEANER,
+            handle,
+            restart: Some(Box::new(spawn_cache_clean_task)),
+        });
+    }
+
+    trace!("Spawning memory budget task");
     let state_clone = state.clone();
-    let cache_clean_task_shutdown_agent = shutdown_agent.clone();
-    tokio::spawn(async move {
-        packet_cache::cache_clean_task(state_clone, cache_clean_task_shutdown_agent).await;
+    let memory_budget_task_shutdown_agent = shutdown_agent.clone();
+    supervisor.start(startup::MEMORY_BUDGET, &[], || {
+        tokio::spawn(async move {
+            memory_budget::memory_budget_task(state_clone, memory_budget_task_shutdown_agent).await;
+        })
     });
 
     trace!("Spawning duty cycle manager callback task");
     let state_clone = state.clone();
     let downlink_duty_cycle_collector_shutdown_agent = shutdown_agent.clone();
-    tokio::spawn(async move {
-        duty_cycle_manager::downlink_duty_cycle_collector_task(
-            downlink_callback_rx,
-            state_clone,
-            downlink_duty_cycle_collector_shutdown_agent,
-        )
-        .await;
-    });
+    supervisor.start(
+        startup::DUTY_CYCLE_COLLECTOR,
+        &[startup::DOWNLINK_CALLBACK],
+        || {
+            tokio::spawn(async move {
+                duty_cycle_manager::downlink_duty_cycle_collector_task(
+                    downlink_callback_rx,
+                    state_clone,
+                    downlink_duty_cycle_collector_shutdown_agent,
+                )
+                .await;
+            })
+        },
+    );
+
+    let trace_recorder = match &configuration.daemon.uplink_trace {
+        Some(UplinkTraceConfig::Record { path }) => {
+            let topic_layout = state.runtime.topic_layout().clone();
+            match UplinkTraceRecorder::open(path, topic_layout).await {
+                Ok(trace_recorder) => Some(trace_recorder),
+                Err(err) => {
+                    error!(path, "Failed to open uplink trace, not recording: {err}");
+                    None
+                }
+            }
+        }
+        Some(UplinkTraceConfig::Replay { .. }) | None => None,
+    };
 
     trace!("Spawning uplink processor task");
     let state_clone = state.clone();
     let uplink_processor_shutdown_agent = shutdown_agent.clone();
-    tokio::spawn(async move {
-        uplink_processing::uplink_processor_task(
-            uplink_callback_rx,
-            relay_tx,
-            state_clone,
-            uplink_processor_shutdown_agent,
-        )
-        .await;
-    });
+    let uplink_processor = supervisor.start(
+        watchdog::UPLINK_PROCESSOR,
+        &[startup::UPLINK_CALLBACK, startup::SEND_ITEM_COLLECTOR],
+        || {
+            tokio::spawn(async move {
+                uplink_processing::uplink_processor_task(
+                    uplink_callback_rx,
+                    relay_tx,
+                    trace_recorder,
+                    state_clone,
+                    uplink_processor_shutdown_agent,
+                )
+                .await;
+            })
+        },
+    );
+    if let Some(handle) = uplink_processor {
+        state.watchdog.register(
+            watchdog::UPLINK_PROCESSOR,
+            HEARTBEAT_INTERVAL,
+            chrono::Utc::now(),
+        );
+        supervised_tasks.push(SupervisedTask {
+            name: watchdog::UPLINK_PROCESSOR,
+            handle,
+            restart: None,
+        });
+    }
+
+    if let Some(UplinkTraceConfig::Replay { path, speedup }) = &configuration.daemon.uplink_trace {
+        trace!("Spawning uplink trace replay task");
+        let path = path.clone();
+        let speedup = *speedup;
+        let topic_layout = state.runtime.topic_layout().clone();
+        let replay_shutdown_agent = shutdown_agent.clone();
+        supervisor.start(
+            startup::UPLINK_TRACE_REPLAY,
+            &[watchdog::UPLINK_PROCESSOR],
+            || {
+                tokio::spawn(async move {
+                    uplink_trace::replay_task(
+                        path,
+                        speedup,
+                        topic_layout,
+                        replay_uplink_tx,
+                        replay_shutdown_agent,
+                    )
+                    .await;
+                })
+            },
+        );
+    }
+
+    let processed_bundle_tx = if state.bundle_parking.is_some() {
+        trace!("Spawning bundle parking task");
+        let state_clone = state.clone();
+        let bundle_parking_shutdown_agent = shutdown_agent.clone();
+        supervisor.start(
+            startup::BUNDLE_PARKING,
+            &[startup::SEND_ITEM_COLLECTOR],
+            || {
+                tokio::spawn(async move {
+                    bundle_parking::bundle_parking_task(
+                        parking_rx,
+                        bundle_send_buffer_tx,
+                        state_clone,
+                        bundle_parking_shutdown_agent,
+                    )
+                    .await;
+                })
+            },
+        );
+        parking_tx
+    } else {
+        supervisor.disable(startup::BUNDLE_PARKING, "Bundle parking not configured");
+        bundle_send_buffer_tx
+    };
 
     trace!("Spawning bundles processor task");
     let bundles_processor_shutdown_agent = shutdown_agent.clone();
-    tokio::spawn(async move {
-        bundles_processor_task(
-            bundles_from_ws_rx,
-            bundle_send_buffer_tx,
-            bundles_processor_shutdown_agent,
-        )
-        .await;
+    let state_clone = state.clone();
+    let bundles_processor_dependencies: &[&'static str] = if state.bundle_parking.is_some() {
+        &[startup::SEND_ITEM_COLLECTOR, startup::BUNDLE_PARKING]
+    } else {
+        &[startup::SEND_ITEM_COLLECTOR]
+    };
+    supervisor.start(
+        startup::BUNDLES_PROCESSOR,
+        bundles_processor_dependencies,
+        || {
+            tokio::spawn(async move {
+                bundles_processor_task(
+                    state_clone,
+                    bundles_from_ws_rx,
+                    processed_bundle_tx,
+                    bundles_processor_shutdown_agent,
+                )
+                .await;
+            })
+        },
+    );
+
+    trace!("Spawn routing task");
+    let spawn_routing_task = {
+        let state = state.clone();
+        let shutdown_agent = shutdown_agent.clone();
+        move || {
+            let routing_shutdown_agent = shutdown_agent.clone();
+            let state_clone = state.clone();
+            tokio::spawn(async move {
+                let state_clone1 = state_clone.clone();
+                state_clone
+                    .routing_dispatcher
+                    .routing_task(state_clone1, routing_shutdown_agent)
+                    .await;
+            })
+        }
+    };
+    let routing = supervisor.start(
+        watchdog::ROUTING,
+        &[startup::SEND_ITEM_COLLECTOR, startup::DUTY_CYCLE_COLLECTOR],
+        &spawn_routing_task,
+    );
+    if let Some(handle) = routing {
+        state
+            .watchdog
+            .register(watchdog::ROUTING, HEARTBEAT_INTERVAL, chrono::Utc::now());
+        supervised_tasks.push(SupervisedTask {
+            name: watchdog::ROUTING,
+            handle,
+            restart: Some(Box::new(spawn_routing_task)),
+        });
+    }
+
+    if let Some(announcement_config) = configuration.daemon.announcement_config.clone() {
+        trace!("Spawning announcement task");
+        let state_clone = state.clone();
+        let announcement_shutdown_agent = shutdown_agent.clone();
+        supervisor.start(startup::ANNOUNCEMENTS, &[watchdog::ROUTING], || {
+            tokio::spawn(async move {
+                announcements::announcement_task(
+                    state_clone,
+                    announcement_config,
+                    announcement_shutdown_agent,
+                )
+                .await;
+            })
+        });
+    } else {
+        supervisor.disable(startup::ANNOUNCEMENTS, "Announcements not configured");
+    }
+
+    trace!("Spawning neighbor expiry task");
+    let state_clone = state.clone();
+    let neighbor_max_age =
+        announcements::neighbor_max_age(configuration.daemon.announcement_config.as_ref());
+    let neighbor_expiry_shutdown_agent = shutdown_agent.clone();
+    supervisor.start(startup::NEIGHBOR_EXPIRY, &[], || {
+        tokio::spawn(async move {
+            neighbor_table::expiry_task(
+                state_clone,
+                neighbor_max_age,
+                neighbor_expiry_shutdown_agent,
+            )
+            .await;
+        })
     });
 
+    if let (Some(gateway_stats_config), Some(gateway_stats_rx)) =
+        (configuration.daemon.gateway_stats.clone(), gateway_stats_rx)
+    {
+        trace!("Spawning gateway stats collector task");
+        let state_clone = state.clone();
+        let gateway_stats_shutdown_agent = shutdown_agent.clone();
+        supervisor.start(
+            startup::GATEWAY_STATS_COLLECTOR,
+            &[startup::GATEWAY_STATS_CALLBACK],
+            || {
+                tokio::spawn(async move {
+                    gateway_stats::gateway_stats_collector_task(
+                        gateway_stats_rx,
+                        state_clone,
+                        gateway_stats_config,
+                        gateway_stats_shutdown_agent,
+                    )
+                    .await;
+                })
+            },
+        );
+    } else {
+        supervisor.disable(
+            startup::GATEWAY_STATS_COLLECTOR,
+            "Gateway stats not configured",
+        );
+    }
+
+    trace!("Spawning gateway selection task");
+    let state_clone = state.clone();
+    let gateway_selection_shutdown_agent = shutdown_agent.clone();
+    supervisor.start(
+        startup::GATEWAY_SELECTION,
+        &[startup::ACK_CALLBACK, startup::GATEWAY_LOCATION_CALLBACK],
+        || {
+            tokio::spawn(async move {
+                gateway_selection::gateway_selection_task(
+                    ack_callback_rx,
+                    location_callback_rx,
+                    state_clone,
+                    gateway_selection_shutdown_agent,
+                )
+                .await;
+            })
+        },
+    );
+
+    trace!("Spawning gateway capabilities task");
+    let state_clone = state.clone();
+    let gateway_capabilities_shutdown_agent = shutdown_agent.clone();
+    supervisor.start(
+        startup::GATEWAY_CAPABILITIES,
+        &[startup::GATEWAY_CAPABILITIES_CALLBACK],
+        || {
+            tokio::spawn(async move {
+                gateway_ids_manager::gateway_capabilities_task(
+                    capabilities_callback_rx,
+                    state_clone,
+                    gateway_capabilities_shutdown_agent,
+                )
+                .await;
+            })
+        },
+    );
+
+    if let Some(gateway_send_queues_rx) = gateway_send_queues_rx {
+        trace!("Spawning gateway send queues task");
+        let state_clone = state.clone();
+        let gateway_send_queues_shutdown_agent = shutdown_agent.clone();
+        supervisor.start(
+            startup::GATEWAY_SEND_QUEUES,
+            &[startup::DUTY_CYCLE_COLLECTOR],
+            || {
+                tokio::spawn(async move {
+                    gateway_send_queues::gateway_send_queues_task(
+                        gateway_send_queues_rx,
+                        state_clone,
+                        gateway_send_queues_shutdown_agent,
+                    )
+                    .await;
+                })
+            },
+        );
+    } else {
+        supervisor.disable(
+            startup::GATEWAY_SEND_QUEUES,
+            "Gateway send queues not configured",
+        );
+    }
+
+    for plugin_config in configuration.daemon.plugins.clone() {
+        trace!("Spawning plugin task");
+        let state_clone = state.clone();
+        let plugin_shutdown_agent = shutdown_agent.clone();
+        supervisor.start(startup::PLUGIN, &[startup::BUNDLES_PROCESSOR], || {
+            tokio::spawn(async move {
+                plugins::plugin_task(plugin_config, state_clone, plugin_shutdown_agent).await;
+            })
+        });
+    }
+
+    if let Some(file_drop_config) = configuration.daemon.file_drop.clone() {
+        trace!("Spawning file drop task");
+        let state_clone = state.clone();
+        let file_drop_shutdown_agent = shutdown_agent.clone();
+        supervisor.start(startup::FILE_DROP, &[startup::BUNDLES_PROCESSOR], || {
+            tokio::spawn(async move {
+                file_drop::file_drop_task(file_drop_config, state_clone, file_drop_shutdown_agent)
+                    .await;
+            })
+        });
+    } else {
+        supervisor.disable(startup::FILE_DROP, "File drop not configured");
+    }
+
+    if let Some(packet_export_config) = configuration
+        .daemon
+        .packet_export
+        .clone()
+        .filter(|_| state.packet_export.is_some())
+    {
+        trace!("Spawning packet export task");
+        let state_clone = state.clone();
+        let packet_export_shutdown_agent = shutdown_agent.clone();
+        supervisor.start(startup::PACKET_EXPORT, &[], || {
+            tokio::spawn(async move {
+                packet_export::packet_export_task(
+                    packet_export_config,
+                    state_clone,
+                    packet_export_shutdown_agent,
+                )
+                .await;
+            })
+        });
+    } else {
+        supervisor.disable(startup::PACKET_EXPORT, "Packet export not configured");
+    }
+
+    #[cfg(feature = "beaconing")]
+    if let Some(beaconing_config) = configuration.daemon.beaconing.clone() {
+        trace!("Spawning beaconing task");
+        let state_clone = state.clone();
+        let beaconing_shutdown_agent = shutdown_agent.clone();
+        supervisor.start(startup::BEACONING, &[watchdog::UPLINK_PROCESSOR], || {
+            tokio::spawn(async move {
+                beaconing::beaconing_task(
+                    beaconing_config,
+                    state_clone,
+                    beaconing_uplink_tx,
+                    beaconing_shutdown_agent,
+                )
+                .await;
+            })
+        });
+    } else {
+        supervisor.disable(startup::BEACONING, "Beaconing not configured");
+    }
+    #[cfg(not(feature = "beaconing"))]
+    if configuration.daemon.beaconing.is_some() {
+        error!("Beaconing is configured but the beaconing feature is not enabled");
+        supervisor.disable(startup::BEACONING, "Beaconing feature not enabled");
+    } else {
+        supervisor.disable(startup::BEACONING, "Beaconing not configured");
+    }
+
+    #[cfg(feature = "webhooks")]
+    for webhook_config in configuration.daemon.webhooks.clone() {
+        trace!("Spawning webhook task");
+        let state_clone = state.clone();
+        let webhook_shutdown_agent = shutdown_agent.clone();
+        supervisor.start(startup::WEBHOOK, &[], || {
+            tokio::spawn(async move {
+                webhooks::webhook_task(webhook_config, state_clone, webhook_shutdown_agent).await;
+            })
+        });
+    }
+    #[cfg(not(feature = "webhooks"))]
+    if !configuration.daemon.webhooks.is_empty() {
+        error!("Webhooks are configured but the webhooks feature is not enabled");
+        supervisor.disable(startup::WEBHOOK, "Webhooks feature not enabled");
+    }
+
+    // Gateways are only discovered once the tasks handling their uplinks and events run.
     trace!("Spawning gateway manager update task");
     let gateway_manager_shutdown_agent = shutdown_agent.clone();
     let state_clone = state.clone();
-    tokio::spawn(async move {
-        let state_clone2 = state_clone.clone();
-        state_clone
-            .gateway_ids_manager
-            .update_gateways(state_clone2, gateway_manager_shutdown_agent)
-            .await;
-    });
+    supervisor.start(
+        startup::GATEWAY_DISCOVERY,
+        &[
+            watchdog::UPLINK_PROCESSOR,
+            startup::GATEWAY_SELECTION,
+            startup::GATEWAY_CAPABILITIES,
+        ],
+        || {
+            tokio::spawn(async move {
+                let state_clone2 = state_clone.clone();
+                state_clone
+                    .gateway_ids_manager
+                    .update_gateways(state_clone2, gateway_manager_shutdown_agent)
+                    .await;
+            })
+        },
+    );
+
+    if let Some(watchdog_config) = configuration.daemon.watchdog.clone() {
+        trace!("Spawning watchdog task");
+        let state_clone = state.clone();
+        let watchdog_shutdown_agent = shutdown_agent.clone();
+        supervisor.start(
+            startup::WATCHDOG,
+            &[
+                watchdog::ROUTING,
+                watchdog::PACKET_CACHE_CLEANER,
+                watchdog::UPLINK_PROCESSOR,
+            ],
+            || {
+                tokio::spawn(async move {
+                    watchdog::watchdog_task(
+                        watchdog_config,
+                        state_clone,
+                        supervised_tasks,
+                        watchdog_shutdown_agent,
+                    )
+                    .await;
+                })
+            },
+        );
+    } else {
+        supervisor.disable(startup::WATCHDOG, "Watchdog not configured");
+    }
 
     //TODO remove
     #[cfg(debug_assertions)]
@@ -337,18 +1340,108 @@ pub async fn start_app(
     }
     //end remove
 
-    trace!("Spawning Axum server on {}", addr);
-    trace!("OpenAPI spec at /api.json");
-    let axum_server_shutdown_agent = shutdown_agent.clone();
-    tokio::spawn({
-        let state = state.clone();
-        async move {
-            axum_task(create_api(state), addr, axum_server_shutdown_agent).await;
-        }
-    });
+    #[cfg(feature = "api")]
+    {
+        let addr = SocketAddr::from((
+            configuration.daemon.bind_config.bind_addr,
+            configuration.daemon.bind_config.bind_port,
+        ));
+        trace!("Spawning Axum server on {}", addr);
+        trace!("OpenAPI spec at /api.json");
+        let axum_server_shutdown_agent = shutdown_agent.clone();
+        let api_tokens = configuration.daemon.api_tokens.clone();
+        supervisor.start(startup::API, &[startup::BUNDLES_PROCESSOR], || {
+            tokio::spawn({
+                let state = state.clone();
+                async move {
+                    axum_task(
+                        create_api(state, ApiAuthorization::new(api_tokens)),
+                        addr,
+                        axum_server_shutdown_agent,
+                    )
+                    .await;
+                }
+            })
+        });
+    }
+
+    if state.startup_summary.set(supervisor.finish()).is_err() {
+        warn!("Startup summary was already set");
+    }
     Ok(state)
 }
 
+/// Adds a universal callback to the runtime by `add` and labels it, a UUID collision is retried.
+async fn add_callback<F, Fut, E>(
+    supervisor: &mut StartupSupervisor,
+    runtime: &chirpstack_gwb_integration::runtime::Runtime,
+    step: &'static str,
+    label: &str,
+    mut add: F,
+) where
+    F: FnMut(chirpstack_gwb_integration::runtime::Runtime) -> Fut,
+    Fut: Future<Output = Result<Uuid, E>>,
+    E: Display,
+{
+    if let Some(uuid) = supervisor
+        .run(step, &[startup::RUNTIME], CALLBACK_RETRY, || {
+            add(runtime.clone())
+        })
+        .await
+    {
+        label_callback(runtime, uuid, label).await;
+    }
+}
+
+/// Creates a routing algorithm from its configuration, responsible for the destination classes.
+fn create_routing_algorithm(
+    routing_algorithm_config: &RoutingAlgorithmConfig,
+    destination_classes: HashSet<DestinationClass>,
+    end_device_ids: &HashSet<ManagedEndDeviceId>,
+    announcement_frequencies: &[Frequency],
+) -> (Box<dyn RoutingAlgorithm>, Arc<RoutingScope>) {
+    let scope = Arc::new(RoutingScope::new(destination_classes));
+    let routing_algorithm: Box<dyn RoutingAlgorithm> = match routing_algorithm_config {
+        RoutingAlgorithmConfig::Flooding(config) => {
+            let tdma_coordinator = config.tdma.as_ref().map(|tdma_config| {
+                let node_id = tdma_config.node_id.unwrap_or_else(|| {
+                    end_device_ids
+                        .iter()
+                        .map(ManagedEndDeviceId::hash)
+                        .min()
+                        .unwrap_or_default()
+                });
+                TdmaCoordinator::new(
+                    tdma_config.slot_length_ms,
+                    tdma_config.frame_length,
+                    node_id,
+                    tdma_config.fallback_to_unslotted,
+                )
+            });
+            Box::new(Flooding::new(
+                std::time::Duration::from_secs(config.periodic_send_delay),
+                config.airtime_pacing.clone(),
+                config.relay_signal_policy.clone(),
+                tdma_coordinator,
+                scope.clone(),
+                announcement_frequencies.to_vec(),
+            ))
+        }
+    };
+    (routing_algorithm, scope)
+}
+
+/// Labels a callback added to the runtime, listed at `/api/diagnostics/callbacks`.
+async fn label_callback(
+    runtime: &chirpstack_gwb_integration::runtime::Runtime,
+    uuid: Uuid,
+    label: &str,
+) {
+    if let Err(err) = runtime.set_callback_label(uuid, label).await {
+        error!(%err);
+    }
+}
+
 /// Async task to receive MQTT connection errors.
 #[instrument(skip_all)]
 async fn mqtt_connection_error_task(
@@ -364,7 +1457,10 @@ async fn mqtt_connection_error_task(
                     error!(
                         "More than 3 MQTT connection errors within 30 seconds. Last error was: {err_msg}"
                     );
-                    mqtt_shutdown_agent_clone.initiate_shutdown(ShutdownConditions::MqttError);
+                    mqtt_shutdown_agent_clone.initiate_shutdown(
+                        ShutdownReason::new(ShutdownConditions::MqttError, module_path!())
+                            .with_message(err_msg),
+                    );
                 }
             },
             _ = shutdown_agent.await_shutdown() => {
@@ -388,6 +1484,7 @@ async fn runtime_shutdown_task(
 }
 
 /// Async task to run axum server.
+#[cfg(feature = "api")]
 #[instrument(skip_all)]
 async fn axum_task(axum_router: Router, addr: SocketAddr, mut shutdown_agent: ShutdownAgent) {
     trace!("Starting up");
@@ -400,7 +1497,9 @@ async fn axum_task(axum_router: Router, addr: SocketAddr, mut shutdown_agent: Sh
         .await
     {
         error!("Failed to start axum: {e}");
-        shutdown_agent.initiate_shutdown(ShutdownConditions::AxumStartFailed);
+        shutdown_agent.initiate_shutdown(
+            ShutdownReason::new(ShutdownConditions::AxumStartFailed, module_path!()).with_error(&e),
+        );
     };
 }
 
@@ -425,7 +1524,9 @@ async fn send_bundle_after_delay(state: Arc<AppState>) {
         .source(source.try_into().unwrap())
         .destination(destination.try_into().unwrap())
         .creation_timestamp(bp7::CreationTimestamp::with_time_and_seq(
-            receive_buffers::unix_ts_to_dtn_time(u64::try_from(timestamp.timestamp()).unwrap()),
+            lorawan_dtn_protocol::unix_ts_to_dtn_time(
+                u64::try_from(timestamp.timestamp()).unwrap(),
+            ),
             0,
         ))
         .lifetime(std::time::Duration::from_secs(2 * 24 * 60 * 60))
@@ -437,6 +1538,6 @@ async fn send_bundle_after_delay(state: Arc<AppState>) {
         initial_payload.clone(),
     );
     let bp7_bundle = bp7::Bundle::new(primary, vec![canonical]);
-    state.bundles_from_ws.send(bp7_bundle).await.unwrap();
+    state.bundles_from_ws.send(bp7_bundle.into()).await.unwrap();
     trace!("send_bundle_after_delay: exit");
 }
@@ -0,0 +1,648 @@
+//! Last-resort beaconing via a directly attached concentrator while no gateway is reachable.
+//!
+//! The concentrator is driven via SPI or USB by a packet forwarder, e.g. the Semtech
+//! `lora_pkt_fwd`, which sends to the configured UDP address with the Semtech UDP protocol. If
+//! the ChirpStack API and the MQTT broker are both unreachable for the configured time, the local
+//! announcement is transmitted via the concentrator on the configured interval and the frames
+//! received by the concentrator are processed like uplinks of a gateway named after the EUI of
+//! the packet forwarder. Once a gateway is reachable again, the received frames are ignored as
+//! the gateways take over.
+
+use crate::configuration::BeaconingConfig;
+use crate::diagnostics::local_end_device_ids;
+use crate::error::BeaconingError;
+use crate::graceful_shutdown::ShutdownAgent;
+use crate::operating_mode::DegradedCondition;
+use crate::packet_cache::PacketSource;
+use crate::AppState;
+use base64::Engine;
+use chirpstack_api::gw::{
+    modulation, CrcStatus, LoraModulationInfo, Modulation, UplinkFrame, UplinkRxInfo, UplinkTxInfo,
+};
+use chirpstack_gwb_integration::downlinks::predefined_parameters::DataRate;
+use chirpstack_gwb_integration::gateway_id::GatewayId;
+use chrono::Utc;
+use lorawan_dtn_protocol::{LoRaWanPacket, LocalAnnouncement, MAX_PHY_PAYLOAD_SIZE};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tracing::{error, info, instrument, trace, warn};
+
+/// Version of the Semtech UDP protocol.
+const PROTOCOL_VERSION: u8 = 2;
+
+/// Identifier of a PUSH_DATA packet carrying received frames and stats.
+const PUSH_DATA: u8 = 0x00;
+
+/// Identifier of a PUSH_ACK packet acknowledging a PUSH_DATA packet.
+const PUSH_ACK: u8 = 0x01;
+
+/// Identifier of a PULL_DATA packet keeping the downlink route open.
+const PULL_DATA: u8 = 0x02;
+
+/// Identifier of a PULL_RESP packet carrying a frame to transmit.
+const PULL_RESP: u8 = 0x03;
+
+/// Identifier of a PULL_ACK packet acknowledging a PULL_DATA packet.
+const PULL_ACK: u8 = 0x04;
+
+/// Identifier of a TX_ACK packet reporting the result of a PULL_RESP packet.
+const TX_ACK: u8 = 0x05;
+
+/// Largest datagram read from the packet forwarder.
+const MAX_DATAGRAM_SIZE: usize = 65_507;
+
+/// Data rate the beacons are sent with, matches the data rate of the announcements.
+const BEACON_DATA_RATE: DataRate = DataRate::Eu863_870Dr3;
+
+/// Interval the reachability of the gateways is checked.
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Frame received by the concentrator, the `rxpk` object of the Semtech UDP protocol.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+struct Rxpk {
+    /// Frequency in MHz.
+    freq: f64,
+    /// Concentrator IF channel.
+    #[serde(default)]
+    chan: u32,
+    /// Concentrator RF chain.
+    #[serde(default)]
+    rfch: u32,
+    /// CRC status: 1 for OK, -1 for a bad CRC and 0 without CRC.
+    stat: i8,
+    /// Modulation, `LORA` or `FSK`.
+    modu: String,
+    /// LoRa data rate, e.g. `SF9BW125`, the bit rate for FSK.
+    datr: serde_json::Value,
+    /// RSSI in dBm.
+    rssi: i32,
+    /// LoRa SNR in dB.
+    #[serde(default)]
+    lsnr: f32,
+    /// Base64 encoded PHY payload.
+    data: String,
+}
+
+/// Payload of a PUSH_DATA packet, stats are ignored.
+#[derive(Debug, Default, Deserialize)]
+struct PushData {
+    /// Received frames.
+    #[serde(default)]
+    rxpk: Vec<Rxpk>,
+}
+
+/// Frame to transmit, the `txpk` object of the Semtech UDP protocol.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct Txpk {
+    /// Whether to transmit immediately.
+    imme: bool,
+    /// Frequency in MHz.
+    freq: f64,
+    /// Concentrator RF chain.
+    rfch: u32,
+    /// Transmission power in dBm.
+    powe: i32,
+    /// Modulation.
+    modu: &'static str,
+    /// LoRa data rate, e.g. `SF9BW125`.
+    datr: String,
+    /// LoRa code rate.
+    codr: &'static str,
+    /// Polarization inversion, not set so gateways and concentrators receive the beacon.
+    ipol: bool,
+    /// Size of the PHY payload in bytes.
+    size: usize,
+    /// Base64 encoded PHY payload.
+    data: String,
+}
+
+/// Payload of a PULL_RESP packet.
+#[derive(Debug, Serialize)]
+struct PullResp<'a> {
+    /// Frame to transmit.
+    txpk: &'a Txpk,
+}
+
+/// Payload of a TX_ACK packet.
+#[derive(Debug, Default, Deserialize)]
+struct TxAck {
+    /// Result of the transmission.
+    #[serde(default)]
+    txpk_ack: Option<TxAckResult>,
+}
+
+/// Result of a transmission.
+#[derive(Debug, Default, Deserialize)]
+struct TxAckResult {
+    /// Error of the transmission, `NONE` if transmitted.
+    #[serde(default)]
+    error: Option<String>,
+}
+
+/// Packet sent by the packet forwarder.
+#[derive(Debug, PartialEq)]
+enum ForwarderPacket {
+    /// Received frames.
+    PushData {
+        /// Token to acknowledge.
+        token: [u8; 2],
+        /// EUI of the packet forwarder.
+        gateway_eui: [u8; 8],
+        /// Received frames.
+        rxpk: Vec<Rxpk>,
+    },
+    /// Keep-alive of the downlink route.
+    PullData {
+        /// Token to acknowledge.
+        token: [u8; 2],
+    },
+    /// Result of a transmission, `None` if transmitted.
+    TxAck {
+        /// Error of the transmission.
+        error: Option<String>,
+    },
+}
+
+impl ForwarderPacket {
+    /// Parses a datagram sent by the packet forwarder.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the datagram is too short, has another protocol version or identifier
+    /// or the JSON payload is invalid.
+    fn parse(datagram: &[u8]) -> Result<Self, BeaconingError> {
+        let [version, token_high, token_low, identifier, rest @ ..] = datagram else {
+            return Err(BeaconingError::Truncated);
+        };
+        if *version != PROTOCOL_VERSION {
+            return Err(BeaconingError::UnsupportedVersion(*version));
+        }
+        let token = [*token_high, *token_low];
+        if *identifier == PULL_DATA {
+            return Ok(Self::PullData { token });
+        }
+        if rest.len() < 8 {
+            return Err(BeaconingError::Truncated);
+        }
+        let (gateway_eui, payload) = rest.split_at(8);
+        let gateway_eui: [u8; 8] = gateway_eui
+            .try_into()
+            .map_err(|_| BeaconingError::Truncated)?;
+        match *identifier {
+            PUSH_DATA => Ok(Self::PushData {
+                token,
+                gateway_eui,
+                rxpk: serde_json::from_slice::<PushData>(payload)?.rxpk,
+            }),
+            TX_ACK => {
+                let tx_ack = if payload.is_empty() {
+                    TxAck::default()
+                } else {
+                    serde_json::from_slice(payload)?
+                };
+                Ok(Self::TxAck {
+                    error: tx_ack
+                        .txpk_ack
+                        .and_then(|result| result.error)
+                        .filter(|error| error != "NONE"),
+                })
+            }
+            identifier => Err(BeaconingError::UnknownIdentifier(identifier)),
+        }
+    }
+}
+
+/// Returns the spreading factor and the bandwidth in Hz of a LoRa data rate, e.g. `SF9BW125`.
+fn parse_lora_data_rate(datr: &str) -> Option<(u32, u32)> {
+    let (spreading_factor, bandwidth) = datr.strip_prefix("SF")?.split_once("BW")?;
+    Some((
+        spreading_factor.parse().ok()?,
+        bandwidth.parse::<u32>().ok()?.checked_mul(1000)?,
+    ))
+}
+
+impl Rxpk {
+    /// Converts the received frame into an uplink of the gateway.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the frame is no LoRa frame or its payload is no valid base64.
+    fn into_uplink_frame(self, gateway_id: &GatewayId) -> Result<UplinkFrame, BeaconingError> {
+        let (spreading_factor, bandwidth) = match (self.modu.as_str(), self.datr.as_str()) {
+            ("LORA", Some(datr)) => parse_lora_data_rate(datr)
+                .ok_or_else(|| BeaconingError::InvalidDataRate(datr.to_owned()))?,
+            _ => return Err(BeaconingError::NoLoRa),
+        };
+        let crc_status = match self.stat {
+            1 => CrcStatus::CrcOk,
+            -1 => CrcStatus::BadCrc,
+            _ => CrcStatus::NoCrc,
+        };
+        // Frequencies of LoRa channels fit into u32 Hz.
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let frequency = (self.freq * 1_000_000.0).round() as u32;
+        Ok(UplinkFrame {
+            phy_payload: base64::engine::general_purpose::STANDARD.decode(&self.data)?,
+            tx_info: Some(UplinkTxInfo {
+                frequency,
+                modulation: Some(Modulation {
+                    parameters: Some(modulation::Parameters::Lora(LoraModulationInfo {
+                        bandwidth,
+                        spreading_factor,
+                        ..LoraModulationInfo::default()
+                    })),
+                }),
+            }),
+            rx_info: Some(UplinkRxInfo {
+                gateway_id: gateway_id.to_string(),
+                rssi: self.rssi,
+                snr: self.lsnr,
+                channel: self.chan,
+                rf_chain: self.rfch,
+                crc_status: crc_status as i32,
+                ..UplinkRxInfo::default()
+            }),
+            ..UplinkFrame::default()
+        })
+    }
+}
+
+impl Txpk {
+    /// Creates a frame transmitted immediately with the configured frequency and power at the
+    /// data rate of the announcements.
+    fn beacon(config: &BeaconingConfig, phy_payload: &[u8]) -> Self {
+        let (bandwidth, spreading_factor) =
+            BEACON_DATA_RATE.into_raw_bandwidth_and_spreading_factor();
+        Self {
+            imme: true,
+            freq: f64::from(config.frequency) / 1_000_000.0,
+            rfch: 0,
+            powe: config.power,
+            modu: "LORA",
+            datr: format!("SF{spreading_factor}BW{}", bandwidth / 1000),
+            codr: "4/5",
+            ipol: false,
+            size: phy_payload.len(),
+            data: base64::engine::general_purpose::STANDARD.encode(phy_payload),
+        }
+    }
+}
+
+/// Creates a PULL_RESP packet transmitting the frame.
+///
+/// # Errors
+///
+/// Returns an error if the frame cannot be serialized.
+fn pull_resp(token: [u8; 2], txpk: &Txpk) -> Result<Vec<u8>, BeaconingError> {
+    let mut packet = vec![PROTOCOL_VERSION, token[0], token[1], PULL_RESP];
+    serde_json::to_writer(&mut packet, &PullResp { txpk })?;
+    Ok(packet)
+}
+
+/// Whether the beaconing is active, it becomes active once no gateway was reachable for the
+/// activation delay.
+#[derive(Debug)]
+struct Activation {
+    /// Time no gateway has to be reachable before the beaconing becomes active.
+    delay: Duration,
+    /// Time since no gateway is reachable.
+    unreachable_since: Option<Instant>,
+    /// Whether the beaconing is active.
+    active: bool,
+}
+
+impl Activation {
+    /// Creates a new inactive [`Activation`].
+    fn new(delay: Duration) -> Self {
+        Self {
+            delay,
+            unreachable_since: None,
+            active: false,
+        }
+    }
+
+    /// Updates the reachability of the gateways, returns whether the beaconing is active if it
+    /// changed.
+    fn update(&mut self, unreachable: bool, now: Instant) -> Option<bool> {
+        let active = if unreachable {
+            let since = *self.unreachable_since.get_or_insert(now);
+            now.saturating_duration_since(since) >= self.delay
+        } else {
+            self.unreachable_since = None;
+            false
+        };
+        if active == self.active {
+            return None;
+        }
+        self.active = active;
+        Some(active)
+    }
+}
+
+/// Handles a datagram of the packet forwarder. Received frames are forwarded as uplinks while
+/// beaconing, the address of the packet forwarder is remembered for the beacons.
+async fn handle_datagram(
+    datagram: &[u8],
+    peer: SocketAddr,
+    socket: &UdpSocket,
+    active: bool,
+    uplink_tx: &mpsc::Sender<(GatewayId, UplinkFrame)>,
+    forwarder: &mut Option<SocketAddr>,
+) {
+    let packet = match ForwarderPacket::parse(datagram) {
+        Ok(packet) => packet,
+        Err(err) => {
+            warn!(%peer, "Invalid packet from the packet forwarder: {err}");
+            return;
+        }
+    };
+    match packet {
+        ForwarderPacket::PushData {
+            token,
+            gateway_eui,
+            rxpk,
+        } => {
+            if let Err(err) = socket
+                .send_to(&[PROTOCOL_VERSION, token[0], token[1], PUSH_ACK], peer)
+                .await
+            {
+                error!(%peer, "Failed to acknowledge PUSH_DATA: {err}");
+            }
+            if !active {
+                return;
+            }
+            let gateway_id = GatewayId::from(gateway_eui);
+            for rxpk in rxpk {
+                match rxpk.into_uplink_frame(&gateway_id) {
+                    Ok(uplink) => {
+                        if let Err(err) = uplink_tx.try_send((gateway_id.clone(), uplink)) {
+                            error!(%err);
+                        }
+                    }
+                    Err(err) => trace!("Ignoring received frame: {err}"),
+                }
+            }
+        }
+        ForwarderPacket::PullData { token } => {
+            if forwarder.replace(peer) != Some(peer) {
+                info!(%peer, "Packet forwarder connected");
+            }
+            if let Err(err) = socket
+                .send_to(&[PROTOCOL_VERSION, token[0], token[1], PULL_ACK], peer)
+                .await
+            {
+                error!(%peer, "Failed to acknowledge PULL_DATA: {err}");
+            }
+        }
+        ForwarderPacket::TxAck { error: Some(err) } => {
+            warn!(%peer, "Beacon not transmitted: {err}");
+        }
+        ForwarderPacket::TxAck { error: None } => trace!("Beacon transmitted"),
+    }
+}
+
+/// Transmits the local announcement via the packet forwarder. Nothing is sent while the
+/// announcements are paused or during radio silence.
+async fn beacon(
+    config: &BeaconingConfig,
+    state: &AppState,
+    socket: &UdpSocket,
+    forwarder: SocketAddr,
+) {
+    if state.subsystem_control.announcements_paused() || state.radio_silence.is_active().await {
+        trace!("Announcements paused or radio silence, not beaconing");
+        return;
+    }
+    if state
+        .frequency_lockouts
+        .is_locked_out(config.frequency, Utc::now())
+    {
+        trace!("Beacon frequency locked out, not beaconing");
+        return;
+    }
+    let end_device_ids = local_end_device_ids(state).await;
+    if end_device_ids.is_empty() {
+        trace!("No end device IDs to beacon");
+        return;
+    }
+    let mut phy_payload = Vec::with_capacity(MAX_PHY_PAYLOAD_SIZE);
+    for announcement in LocalAnnouncement::split_to_data_rate(
+        None,
+        &end_device_ids,
+        BEACON_DATA_RATE,
+        state.repeater_compatible,
+    ) {
+        phy_payload.clear();
+        announcement.write_phy_payload(&mut phy_payload);
+        // Neighbors relaying the beacon back are not processed again.
+        let _ = state
+            .packet_cache
+            .insert(&phy_payload, PacketSource::Local)
+            .await;
+        let packet = match pull_resp(rand::random(), &Txpk::beacon(config, &phy_payload)) {
+            Ok(packet) => packet,
+            Err(err) => {
+                error!("Failed to create beacon: {err}");
+                return;
+            }
+        };
+        if let Err(err) = socket.send_to(&packet, forwarder).await {
+            error!(%forwarder, "Failed to send beacon: {err}");
+            return;
+        }
+        trace!("Sent beacon");
+    }
+}
+
+/// Task serving the packet forwarder of the concentrator and beaconing the local announcement
+/// while neither the ChirpStack API nor the MQTT broker is reachable.
+///
+/// While beaconing, the [`DegradedCondition::GatewaysUnreachable`] is active and the frames
+/// received by the concentrator are sent to the uplink processor.
+#[instrument(skip_all, fields(bind = %config.bind))]
+pub async fn beaconing_task(
+    config: BeaconingConfig,
+    state: Arc<AppState>,
+    uplink_tx: mpsc::Sender<(GatewayId, UplinkFrame)>,
+    mut shutdown_agent: ShutdownAgent,
+) {
+    trace!("Starting up");
+    let socket = match UdpSocket::bind(&config.bind).await {
+        Ok(socket) => socket,
+        Err(err) => {
+            error!("Failed to bind the packet forwarder socket: {err}");
+            return;
+        }
+    };
+    let beacon_interval = Duration::from_secs(config.interval_seconds);
+    let mut activation = Activation::new(Duration::from_secs(config.activation_seconds));
+    let mut check_interval = tokio::time::interval(CHECK_INTERVAL);
+    let mut forwarder = None;
+    let mut last_beacon: Option<Instant> = None;
+    let mut buffer = vec![0; MAX_DATAGRAM_SIZE];
+
+    loop {
+        tokio::select! {
+            received = socket.recv_from(&mut buffer) => {
+                match received {
+                    Ok((len, peer)) => {
+                        handle_datagram(
+                            &buffer[..len],
+                            peer,
+                            &socket,
+                            activation.active,
+                            &uplink_tx,
+                            &mut forwarder,
+                        )
+                        .await;
+                    }
+                    Err(err) => error!("Failed to receive from the packet forwarder: {err}"),
+                }
+                continue
+            }
+            _ = check_interval.tick() => {}
+            _ = shutdown_agent.await_shutdown() => {
+                trace!("Shutting down");
+                return
+            }
+        }
+
+        let unreachable = !state.runtime.broker_connected()
+            && state
+                .operating_mode
+                .lock()
+                .await
+                .is_active(DegradedCondition::ChirpStackApiUnreachable);
+        match activation.update(unreachable, Instant::now()) {
+            Some(true) => state.operating_mode.lock().await.enter(
+                DegradedCondition::GatewaysUnreachable,
+                "ChirpStack API and MQTT broker unreachable, beaconing via the concentrator"
+                    .to_owned(),
+            ),
+            Some(false) => {
+                last_beacon = None;
+                state
+                    .operating_mode
+                    .lock()
+                    .await
+                    .leave(DegradedCondition::GatewaysUnreachable);
+            }
+            None => {}
+        }
+
+        if !activation.active
+            || last_beacon.is_some_and(|last_beacon| last_beacon.elapsed() < beacon_interval)
+        {
+            continue;
+        }
+        let Some(forwarder) = forwarder else {
+            warn!("No packet forwarder connected, cannot beacon");
+            last_beacon = Some(Instant::now());
+            continue;
+        };
+        beacon(&config, &state, &socket, forwarder).await;
+        last_beacon = Some(Instant::now());
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use crate::beaconing::{
+        parse_lora_data_rate, pull_resp, Activation, ForwarderPacket, Txpk, PULL_RESP,
+    };
+    use crate::configuration::BeaconingConfig;
+    use chirpstack_api::gw::{modulation, CrcStatus};
+    use chirpstack_gwb_integration::gateway_id::GatewayId;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn push_data_is_converted_into_uplinks() {
+        let mut datagram = vec![2, 0x12, 0x34, 0x00, 1, 2, 3, 4, 5, 6, 7, 8];
+        datagram.extend_from_slice(
+            br#"{"rxpk":[{"tmst":1,"freq":868.3,"chan":2,"rfch":1,"stat":1,"modu":"LORA","datr":"SF9BW125","codr":"4/5","rssi":-97,"lsnr":6.5,"size":3,"data":"4AEC"}]}"#,
+        );
+        let ForwarderPacket::PushData {
+            token,
+            gateway_eui,
+            rxpk,
+        } = ForwarderPacket::parse(&datagram).unwrap()
+        else {
+            panic!("Expected PUSH_DATA");
+        };
+        assert_eq!(token, [0x12, 0x34]);
+        let uplink = rxpk[0]
+            .clone()
+            .into_uplink_frame(&GatewayId::from(gateway_eui))
+            .unwrap();
+        assert_eq!(uplink.phy_payload, vec![0xE0, 0x01, 0x02]);
+        let tx_info = uplink.tx_info.unwrap();
+        assert_eq!(tx_info.frequency, 868_300_000);
+        let Some(modulation::Parameters::Lora(lora)) = tx_info.modulation.unwrap().parameters
+        else {
+            panic!("Expected LoRa modulation");
+        };
+        assert_eq!((lora.spreading_factor, lora.bandwidth), (9, 125_000));
+        let rx_info = uplink.rx_info.unwrap();
+        assert_eq!(rx_info.gateway_id, "0102030405060708");
+        assert_eq!(rx_info.rssi, -97);
+        assert_eq!(rx_info.crc_status, CrcStatus::CrcOk as i32);
+
+        assert_eq!(
+            ForwarderPacket::parse(&[2, 0, 1, 0x02, 1, 2, 3, 4, 5, 6, 7, 8]).unwrap(),
+            ForwarderPacket::PullData { token: [0, 1] }
+        );
+        assert!(ForwarderPacket::parse(&[1, 0, 1, 0x02]).is_err());
+        assert_eq!(parse_lora_data_rate("SF12BW250"), Some((12, 250_000)));
+        assert_eq!(parse_lora_data_rate("50000"), None);
+    }
+
+    #[test]
+    fn beacons_are_sent_as_pull_resp() {
+        let config = BeaconingConfig {
+            bind: "127.0.0.1:1700".to_owned(),
+            activation_seconds: 60,
+            interval_seconds: 300,
+            frequency: 868_300_000,
+            power: 14,
+        };
+        let packet = pull_resp([0xAB, 0xCD], &Txpk::beacon(&config, &[0x01, 0x02])).unwrap();
+        assert_eq!(packet[..4], [2, 0xAB, 0xCD, PULL_RESP]);
+        let json: serde_json::Value = serde_json::from_slice(&packet[4..]).unwrap();
+        assert_eq!(json["txpk"]["freq"], 868.3);
+        assert_eq!(json["txpk"]["datr"], "SF9BW125");
+        assert_eq!(json["txpk"]["data"], "AQI=");
+        assert_eq!(json["txpk"]["imme"], true);
+    }
+
+    #[test]
+    fn beaconing_activates_after_delay() {
+        let mut activation = Activation::new(Duration::from_secs(60));
+        let start = Instant::now();
+        assert_eq!(activation.update(true, start), None);
+        assert_eq!(
+            activation.update(true, start + Duration::from_secs(59)),
+            None
+        );
+        assert_eq!(
+            activation.update(true, start + Duration::from_secs(60)),
+            Some(true)
+        );
+        assert_eq!(
+            activation.update(true, start + Duration::from_secs(90)),
+            None
+        );
+        assert_eq!(
+            activation.update(false, start + Duration::from_secs(95)),
+            Some(false)
+        );
+        assert_eq!(
+            activation.update(true, start + Duration::from_secs(100)),
+            None
+        );
+    }
+}
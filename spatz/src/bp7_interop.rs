@@ -0,0 +1,140 @@
+//! Interoperability mode sending bundles as CBOR encoded BP7 fragments.
+//!
+//! Instead of the custom headers, bundles are sent in
+//! [`Bp7Bundle`](lorawan_dtn_protocol::Bp7Bundle) packets carrying the bundle or a BP7
+//! fragment of it as CBOR, so other BP7-over-LoRa implementations can receive them. All nodes of
+//! this version parse these packets, but nodes of older versions drop them. Configured nodes
+//! therefore announce the [`CAPABILITY_BP7_CBOR`] capability and, if negotiating, only send
+//! bundles as BP7 fragments while all recently announced direct neighbors announced it too.
+//!
+//! The framing is decided once per bundle when it is queued. The BP7 blocks take about 60 bytes
+//! per packet, bundles are sent with the custom headers at data rates too slow for them.
+
+use crate::configuration::Bp7InteropConfig;
+use crate::neighbor_table::NeighborTable;
+use lorawan_dtn_protocol::CAPABILITY_BP7_CBOR;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Counters of the interoperability mode.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Serialize, JsonSchema)]
+pub struct Bp7InteropStats {
+    /// Whether bundles queued now are sent as BP7 fragments.
+    pub active: bool,
+    /// Bundles queued to be sent as BP7 fragments.
+    pub bp7_bundles_queued: u64,
+    /// Bundles queued with the custom headers as not all direct neighbors announced the
+    /// capability.
+    pub custom_bundles_queued: u64,
+    /// Received BP7 bundle packets addressed to this node.
+    pub bp7_packets_received: u64,
+}
+
+/// Decides the framing of queued bundles and counts the BP7 traffic.
+#[derive(Debug)]
+pub struct Bp7Interop {
+    /// Negotiation settings.
+    config: Bp7InteropConfig,
+    /// Bundles queued to be sent as BP7 fragments.
+    bp7_bundles_queued: AtomicU64,
+    /// Bundles queued with the custom headers.
+    custom_bundles_queued: AtomicU64,
+    /// Received BP7 bundle packets.
+    bp7_packets_received: AtomicU64,
+}
+
+impl Bp7Interop {
+    /// Creates a new [`Bp7Interop`] without counted traffic.
+    pub fn new(config: Bp7InteropConfig) -> Self {
+        Self {
+            config,
+            bp7_bundles_queued: AtomicU64::new(0),
+            custom_bundles_queued: AtomicU64::new(0),
+            bp7_packets_received: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns whether bundles are sent as BP7 fragments, always if not negotiating.
+    pub fn active(&self, neighbor_table: &NeighborTable) -> bool {
+        if !self.config.negotiate {
+            return true;
+        }
+        let max_age =
+            chrono::Duration::from_std(Duration::from_secs(self.config.max_neighbor_age_seconds))
+                .unwrap_or(chrono::Duration::MAX);
+        neighbor_table.direct_neighbors_support(CAPABILITY_BP7_CBOR, max_age)
+    }
+
+    /// Decides the framing of a queued bundle, returns whether it is sent as BP7 fragments.
+    pub fn choose_framing(&self, neighbor_table: &NeighborTable) -> bool {
+        let bp7_framing = self.active(neighbor_table);
+        if bp7_framing {
+            self.bp7_bundles_queued.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.custom_bundles_queued.fetch_add(1, Ordering::Relaxed);
+        }
+        bp7_framing
+    }
+
+    /// Counts a received BP7 bundle packet.
+    pub fn record_received(&self) {
+        self.bp7_packets_received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the counters of the interoperability mode.
+    pub fn stats(&self, neighbor_table: &NeighborTable) -> Bp7InteropStats {
+        Bp7InteropStats {
+            active: self.active(neighbor_table),
+            bp7_bundles_queued: self.bp7_bundles_queued.load(Ordering::Relaxed),
+            custom_bundles_queued: self.custom_bundles_queued.load(Ordering::Relaxed),
+            bp7_packets_received: self.bp7_packets_received.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bp7_interop::Bp7Interop;
+    use crate::configuration::Bp7InteropConfig;
+    use crate::end_device_id::EndDeviceId;
+    use crate::neighbor_table::{NeighborTable, SignalQuality};
+    use lorawan_dtn_protocol::{CapabilityAnnouncement, LocalAnnouncement, CAPABILITY_BP7_CBOR};
+
+    #[test]
+    fn framing_is_negotiated_with_direct_neighbors() {
+        let config = Bp7InteropConfig {
+            negotiate: true,
+            max_neighbor_age_seconds: 600,
+        };
+        let bp7_interop = Bp7Interop::new(config.clone());
+        let mut neighbor_table = NeighborTable::new();
+        neighbor_table.process_announcement(
+            &LocalAnnouncement::new(None, vec![EndDeviceId(0x1234)]),
+            "a840411d25244150",
+            SignalQuality {
+                rssi: -80,
+                snr: 7.5,
+            },
+        );
+        assert!(!bp7_interop.choose_framing(&neighbor_table));
+
+        neighbor_table.process_capability_announcement(
+            &CapabilityAnnouncement::new(CAPABILITY_BP7_CBOR, vec![EndDeviceId(0x1234)]),
+            "a840411d25244150",
+        );
+        assert!(bp7_interop.choose_framing(&neighbor_table));
+
+        let stats = bp7_interop.stats(&neighbor_table);
+        assert!(stats.active);
+        assert_eq!(stats.bp7_bundles_queued, 1);
+        assert_eq!(stats.custom_bundles_queued, 1);
+
+        let always = Bp7Interop::new(Bp7InteropConfig {
+            negotiate: false,
+            ..config
+        });
+        assert!(always.choose_framing(&NeighborTable::new()));
+    }
+}
@@ -0,0 +1,188 @@
+//! Age and lifetime of reassembled bundles and the policy for bundles delivered after their
+//! lifetime.
+
+use crate::configuration::LateDeliveryPolicy;
+use chrono::{DateTime, TimeZone, Utc};
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Age and remaining lifetime of a bundle at the time of its delivery.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize)]
+pub struct BundleAge {
+    /// Creation time of the bundle, not set if the source had no accurate clock.
+    pub creation_timestamp: Option<DateTime<Utc>>,
+    /// Seconds since the creation of the bundle.
+    pub age_seconds: Option<u64>,
+    /// Seconds until the lifetime of the bundle is exceeded, `0` for expired bundles.
+    pub remaining_lifetime_seconds: Option<u64>,
+}
+
+impl BundleAge {
+    /// Computes the age of the bundle from the creation timestamp and the lifetime of its primary
+    /// block.
+    pub fn of(bundle: &bp7::Bundle, now: DateTime<Utc>) -> Self {
+        let dtn_time = bundle.primary.creation_timestamp.dtntime();
+        let creation_timestamp = if dtn_time == 0 {
+            None
+        } else {
+            dtn_time
+                .checked_add(bp7::dtntime::SECONDS1970_TO2K * 1000)
+                .and_then(|unix_millis| i64::try_from(unix_millis).ok())
+                .and_then(|unix_millis| Utc.timestamp_millis_opt(unix_millis).single())
+        };
+        let age_seconds = creation_timestamp.map(|creation_timestamp| {
+            u64::try_from(now.signed_duration_since(creation_timestamp).num_seconds()).unwrap_or(0)
+        });
+        let remaining_lifetime_seconds = age_seconds.map(|age_seconds| {
+            bundle
+                .primary
+                .lifetime
+                .as_secs()
+                .saturating_sub(age_seconds)
+        });
+        Self {
+            creation_timestamp,
+            age_seconds,
+            remaining_lifetime_seconds,
+        }
+    }
+
+    /// Whether the bundle is older than its lifetime, bundles of unknown age never expire.
+    pub fn is_expired(&self) -> bool {
+        self.remaining_lifetime_seconds == Some(0)
+    }
+}
+
+/// Envelope of a bundle delivered to a local service as JSON.
+#[derive(Debug, Serialize)]
+pub struct BundleDelivery<'a> {
+    /// Age and remaining lifetime of the bundle.
+    #[serde(flatten)]
+    pub age: BundleAge,
+    /// The delivered bundle.
+    pub bundle: &'a bp7::Bundle,
+}
+
+/// Counters of the bundles delivered to local services.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, JsonSchema)]
+pub struct LateDeliveryStats {
+    /// Active policy for bundles past their lifetime.
+    pub policy: LateDeliveryPolicy,
+    /// Delivered bundles.
+    pub delivered: u64,
+    /// Delivered bundles which were past their lifetime.
+    pub delivered_expired: u64,
+    /// Bundles not delivered as they were past their lifetime.
+    pub suppressed: u64,
+}
+
+/// Applies the [`LateDeliveryPolicy`] to reassembled bundles and counts the deliveries.
+#[derive(Debug)]
+pub struct LateDelivery {
+    /// Policy for bundles past their lifetime.
+    policy: LateDeliveryPolicy,
+    /// Delivered bundles.
+    delivered: AtomicU64,
+    /// Delivered bundles which were past their lifetime.
+    delivered_expired: AtomicU64,
+    /// Bundles not delivered as they were past their lifetime.
+    suppressed: AtomicU64,
+}
+
+impl LateDelivery {
+    /// Creates a new [`LateDelivery`] applying the policy.
+    pub fn new(policy: LateDeliveryPolicy) -> Self {
+        Self {
+            policy,
+            delivered: AtomicU64::new(0),
+            delivered_expired: AtomicU64::new(0),
+            suppressed: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns whether the bundle is delivered to the local services.
+    ///
+    /// Suppressed bundles are still treated as received, their fragments are not requested again.
+    pub fn admit(&self, bundle: &bp7::Bundle, now: DateTime<Utc>) -> bool {
+        let expired = BundleAge::of(bundle, now).is_expired();
+        match (expired, self.policy) {
+            (false, _) => {
+                self.delivered.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            (true, LateDeliveryPolicy::Deliver) => {
+                self.delivered.fetch_add(1, Ordering::Relaxed);
+                self.delivered_expired.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            (true, LateDeliveryPolicy::Suppress) => {
+                self.suppressed.fetch_add(1, Ordering::Relaxed);
+                false
+            }
+        }
+    }
+
+    /// Returns the current counters.
+    pub fn stats(&self) -> LateDeliveryStats {
+        LateDeliveryStats {
+            policy: self.policy,
+            delivered: self.delivered.load(Ordering::Relaxed),
+            delivered_expired: self.delivered_expired.load(Ordering::Relaxed),
+            suppressed: self.suppressed.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use crate::bundle_delivery::{BundleAge, LateDelivery};
+    use crate::configuration::LateDeliveryPolicy;
+    use crate::end_device_id::EndDeviceId;
+    use chrono::{TimeZone, Utc};
+    use lorawan_dtn_protocol::unix_ts_to_dtn_time;
+    use std::time::Duration;
+
+    fn bundle(created_at: u64, lifetime: Duration) -> bp7::Bundle {
+        bp7::primary::PrimaryBlockBuilder::new()
+            .source(EndDeviceId(0x1234).try_into().unwrap())
+            .destination(EndDeviceId(0x5678).try_into().unwrap())
+            .creation_timestamp(bp7::CreationTimestamp::with_time_and_seq(
+                unix_ts_to_dtn_time(created_at),
+                0,
+            ))
+            .lifetime(lifetime)
+            .build()
+            .map(|primary| bp7::Bundle::new(primary, vec![]))
+            .unwrap()
+    }
+
+    #[test]
+    fn age_and_remaining_lifetime_from_primary_block() {
+        let now = Utc.timestamp_opt(1_700_000_100, 0).unwrap();
+        let age = BundleAge::of(&bundle(1_700_000_000, Duration::from_secs(3600)), now);
+        assert_eq!(
+            age.creation_timestamp,
+            Some(Utc.timestamp_opt(1_700_000_000, 0).unwrap())
+        );
+        assert_eq!(age.age_seconds, Some(100));
+        assert_eq!(age.remaining_lifetime_seconds, Some(3500));
+        assert!(!age.is_expired());
+
+        let expired = bundle(1_700_000_000, Duration::from_secs(60));
+        assert!(BundleAge::of(&expired, now).is_expired());
+
+        let suppressing = LateDelivery::new(LateDeliveryPolicy::Suppress);
+        assert!(!suppressing.admit(&expired, now));
+        let delivering = LateDelivery::new(LateDeliveryPolicy::Deliver);
+        assert!(delivering.admit(&expired, now));
+        assert_eq!(
+            (
+                suppressing.stats().suppressed,
+                delivering.stats().delivered_expired
+            ),
+            (1, 1)
+        );
+    }
+}
@@ -0,0 +1,346 @@
+//! Store of the delivered bundles for clients polling via HTTP instead of holding a WebSocket
+//! connection, e.g. behind proxies dropping long-lived connections.
+//!
+//! Every bundle delivered to the local applications is kept with an increasing sequence number,
+//! the oldest bundles are removed beyond the configured size. Clients long-poll
+//! `/api/bundles/poll` with the cursor of the previous response and receive the bundles since the
+//! cursor, or wait until one is delivered. The cursor a client sends acknowledges the bundles
+//! before it and is remembered per client, a client polling without cursor continues after its
+//! last acknowledged cursor. The cursors of the clients which polled least recently are forgotten
+//! beyond the configured amount of clients. The bundles and the cursors are persisted with the
+//! state.
+
+use crate::configuration::BundleMailboxConfig;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::time::Instant;
+use tracing::error;
+
+/// Delivered bundle kept for polling clients.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MailboxEntry {
+    /// Sequence number of the bundle, the first bundle has sequence number 1.
+    pub sequence: u64,
+    /// Time the bundle was delivered.
+    pub delivered_at: DateTime<Utc>,
+    /// The CBOR encoded bundle.
+    pub cbor: Vec<u8>,
+}
+
+/// Bundles and client cursors of the mailbox as persisted in the database.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MailboxContents {
+    /// Sequence number of the next delivered bundle.
+    pub next_sequence: u64,
+    /// Kept bundles, oldest first.
+    pub entries: VecDeque<MailboxEntry>,
+    /// Last acknowledged cursor per client.
+    pub cursors: BTreeMap<String, ClientCursor>,
+}
+
+/// Last acknowledged cursor of a client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClientCursor {
+    /// Sequence number of the first bundle not yet acknowledged by the client.
+    pub cursor: u64,
+    /// Time the client polled last.
+    pub polled_at: DateTime<Utc>,
+}
+
+/// Bundles returned to a polling client.
+#[derive(Debug, Clone)]
+pub struct MailboxPoll {
+    /// Sequence numbers and bundles delivered since the cursor, oldest first.
+    pub bundles: Vec<(u64, bp7::Bundle)>,
+    /// Cursor the client sends with the next poll to acknowledge the returned bundles.
+    pub cursor: u64,
+    /// Bundles since the cursor which were removed before the client polled them.
+    pub missed: u64,
+}
+
+/// Delivered bundles kept for polling clients.
+#[derive(Debug)]
+pub struct BundleMailbox {
+    /// Maximum amount of kept bundles.
+    max_bundles: usize,
+    /// Maximum amount of remembered client cursors.
+    max_clients: usize,
+    /// Maximum time a poll waits for a delivered bundle.
+    max_wait: Duration,
+    /// Kept bundles and client cursors.
+    contents: Mutex<MailboxContents>,
+    /// Notified whenever a bundle is delivered.
+    delivered: Notify,
+}
+
+impl BundleMailbox {
+    /// Creates a new [`BundleMailbox`] restoring the persisted bundles and cursors, the oldest
+    /// bundles and the least recently polled clients exceeding the configured sizes are removed.
+    pub fn new(config: &BundleMailboxConfig, mut contents: MailboxContents) -> Self {
+        let excess = contents.entries.len().saturating_sub(config.max_bundles);
+        contents.entries.drain(..excess);
+        contents.next_sequence = contents.next_sequence.max(1);
+        forget_least_recent_clients(&mut contents.cursors, config.max_clients);
+        Self {
+            max_bundles: config.max_bundles,
+            max_clients: config.max_clients,
+            max_wait: Duration::from_secs(config.max_wait_seconds),
+            contents: Mutex::new(contents),
+            delivered: Notify::new(),
+        }
+    }
+
+    /// Keeps a delivered bundle, removing the oldest bundle if the mailbox is full, and wakes the
+    /// waiting polls.
+    pub fn store(&self, bundle: &bp7::Bundle, now: DateTime<Utc>) {
+        let cbor = bundle.clone().to_cbor();
+        {
+            let mut contents = self.lock();
+            if contents.entries.len() >= self.max_bundles {
+                contents.entries.pop_front();
+            }
+            let sequence = contents.next_sequence;
+            contents.next_sequence += 1;
+            contents.entries.push_back(MailboxEntry {
+                sequence,
+                delivered_at: now,
+                cbor,
+            });
+        }
+        self.delivered.notify_waiters();
+    }
+
+    /// Returns the bundles delivered since the cursor, waits up to `wait`, capped at the
+    /// configured maximum, for a bundle if there is none yet.
+    ///
+    /// The cursor is remembered as acknowledged cursor of the client, the last acknowledged
+    /// cursor of the client is used if not set, all kept bundles are returned to new clients.
+    pub async fn poll(&self, client: &str, cursor: Option<u64>, wait: Duration) -> MailboxPoll {
+        let deadline = Instant::now() + wait.min(self.max_wait);
+        loop {
+            let delivered = self.delivered.notified();
+            let poll = self.collect(client, cursor, Utc::now());
+            if !poll.bundles.is_empty()
+                || tokio::time::timeout_at(deadline, delivered).await.is_err()
+            {
+                return poll;
+            }
+        }
+    }
+
+    /// Returns the persisted bundles and client cursors.
+    pub fn contents(&self) -> MailboxContents {
+        self.lock().clone()
+    }
+
+    /// Returns the bundles delivered since the cursor and records the cursor of the client.
+    ///
+    /// The kept bundles are decoded after releasing the lock.
+    fn collect(&self, client: &str, cursor: Option<u64>, now: DateTime<Utc>) -> MailboxPoll {
+        let (cursor, oldest, kept) = {
+            let mut contents = self.lock();
+            let cursor = cursor
+                .or_else(|| contents.cursors.get(client).map(|client| client.cursor))
+                .unwrap_or(0)
+                .min(contents.next_sequence);
+            if !contents.cursors.contains_key(client) {
+                forget_least_recent_clients(
+                    &mut contents.cursors,
+                    self.max_clients.saturating_sub(1),
+                );
+            }
+            contents.cursors.insert(
+                client.to_owned(),
+                ClientCursor {
+                    cursor,
+                    polled_at: now,
+                },
+            );
+            let oldest = contents
+                .entries
+                .front()
+                .map_or(contents.next_sequence, |entry| entry.sequence);
+            let kept: Vec<(u64, Vec<u8>)> = contents
+                .entries
+                .iter()
+                .filter(|entry| entry.sequence >= cursor)
+                .map(|entry| (entry.sequence, entry.cbor.clone()))
+                .collect();
+            (cursor, oldest, kept)
+        };
+        let bundles: Vec<(u64, bp7::Bundle)> = kept
+            .into_iter()
+            .filter_map(
+                |(sequence, cbor)| match serde_cbor::from_slice::<bp7::Bundle>(&cbor) {
+                    Ok(bundle) => Some((sequence, bundle)),
+                    Err(err) => {
+                        error!(sequence, "Failed to decode kept bundle: {err}");
+                        None
+                    }
+                },
+            )
+            .collect();
+        MailboxPoll {
+            cursor: bundles
+                .last()
+                .map_or(cursor.max(oldest), |(sequence, _)| sequence + 1),
+            missed: oldest.saturating_sub(cursor.max(1)),
+            bundles,
+        }
+    }
+
+    /// Locks the contents, a poisoned lock is recovered as the contents stay consistent.
+    fn lock(&self) -> MutexGuard<'_, MailboxContents> {
+        self.contents.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Removes the cursors of the least recently polled clients until at most `max_clients` remain.
+fn forget_least_recent_clients(cursors: &mut BTreeMap<String, ClientCursor>, max_clients: usize) {
+    while cursors.len() > max_clients {
+        let Some(client) = cursors
+            .iter()
+            .min_by_key(|(_, client)| client.polled_at)
+            .map(|(client, _)| client.clone())
+        else {
+            return;
+        };
+        cursors.remove(&client);
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use crate::bundle_mailbox::{BundleMailbox, MailboxContents, MailboxPoll};
+    use crate::configuration::BundleMailboxConfig;
+    use crate::end_device_id::EndDeviceId;
+    use bp7::flags::BlockControlFlags;
+    use chrono::Utc;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn bundle(source: u32) -> bp7::Bundle {
+        bp7::primary::PrimaryBlockBuilder::new()
+            .source(EndDeviceId(source).try_into().unwrap())
+            .destination(EndDeviceId(0x5678).try_into().unwrap())
+            .lifetime(Duration::from_secs(3600))
+            .build()
+            .map(|primary| {
+                bp7::Bundle::new(
+                    primary,
+                    vec![bp7::canonical::new_payload_block(
+                        BlockControlFlags::empty(),
+                        b"payload".to_vec(),
+                    )],
+                )
+            })
+            .unwrap()
+    }
+
+    fn sequences(poll: &MailboxPoll) -> Vec<u64> {
+        poll.bundles.iter().map(|(sequence, _)| *sequence).collect()
+    }
+
+    fn mailbox() -> BundleMailbox {
+        BundleMailbox::new(
+            &BundleMailboxConfig {
+                max_bundles: 3,
+                max_clients: 2,
+                max_wait_seconds: 30,
+            },
+            MailboxContents::default(),
+        )
+    }
+
+    #[tokio::test]
+    async fn polls_continue_after_the_acknowledged_cursor() {
+        let mailbox = mailbox();
+        for source in 1..=4 {
+            mailbox.store(&bundle(source), Utc::now());
+        }
+
+        let poll = mailbox.poll("app", None, Duration::ZERO).await;
+        assert_eq!(sequences(&poll), vec![2, 3, 4]);
+        assert_eq!(poll.cursor, 5);
+        assert_eq!(poll.missed, 1);
+        assert_eq!(
+            EndDeviceId::try_from(poll.bundles[0].1.primary.source.clone()).unwrap(),
+            EndDeviceId(2)
+        );
+
+        // Without acknowledgement the same bundles are returned again.
+        let poll = mailbox.poll("app", None, Duration::ZERO).await;
+        assert_eq!(sequences(&poll), vec![2, 3, 4]);
+
+        let poll = mailbox.poll("app", Some(4), Duration::ZERO).await;
+        assert_eq!(sequences(&poll), vec![4]);
+        let poll = mailbox.poll("app", Some(5), Duration::ZERO).await;
+        assert!(poll.bundles.is_empty());
+        assert_eq!(poll.cursor, 5);
+        assert_eq!(poll.missed, 0);
+        assert_eq!(
+            mailbox
+                .contents()
+                .cursors
+                .get("app")
+                .map(|client| client.cursor),
+            Some(5)
+        );
+
+        let restored = BundleMailbox::new(
+            &BundleMailboxConfig {
+                max_bundles: 2,
+                max_clients: 2,
+                max_wait_seconds: 30,
+            },
+            mailbox.contents(),
+        );
+        let poll = restored.poll("app", None, Duration::ZERO).await;
+        assert!(poll.bundles.is_empty());
+        let poll = restored.poll("other", None, Duration::ZERO).await;
+        assert_eq!(sequences(&poll), vec![3, 4]);
+        assert_eq!(poll.missed, 2);
+    }
+
+    #[tokio::test]
+    async fn least_recently_polled_clients_are_forgotten() {
+        let mailbox = mailbox();
+        let now = Utc::now();
+        mailbox.store(&bundle(1), now);
+        mailbox.collect("first", Some(2), now);
+        mailbox.collect("second", Some(2), now + chrono::Duration::seconds(1));
+        mailbox.collect("first", None, now + chrono::Duration::seconds(2));
+        mailbox.collect("third", Some(2), now + chrono::Duration::seconds(3));
+
+        let cursors = mailbox.contents().cursors;
+        assert_eq!(
+            cursors.keys().map(String::as_str).collect::<Vec<_>>(),
+            vec!["first", "third"]
+        );
+        let poll = mailbox.poll("second", None, Duration::ZERO).await;
+        assert_eq!(sequences(&poll), vec![1]);
+    }
+
+    #[tokio::test]
+    async fn waiting_polls_return_delivered_bundles() {
+        let mailbox = Arc::new(mailbox());
+        let waiting = tokio::spawn({
+            let mailbox = mailbox.clone();
+            async move { mailbox.poll("app", None, Duration::from_secs(10)).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        mailbox.store(&bundle(1), Utc::now());
+
+        let poll = tokio::time::timeout(Duration::from_secs(1), waiting)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(sequences(&poll), vec![1]);
+        assert_eq!(poll.cursor, 2);
+    }
+}
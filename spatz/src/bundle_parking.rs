@@ -0,0 +1,274 @@
+//! Parking of bundles without a known next hop until their destination is announced.
+
+use crate::configuration::BundleParkingConfig;
+use crate::end_device_id::EndDeviceId;
+use crate::graceful_shutdown::ShutdownAgent;
+use crate::send_buffers::BundleSendBuffer;
+use crate::AppState;
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, Mutex, Notify};
+use tracing::{error, info, instrument, trace, warn};
+
+/// Interval at which parked bundles are checked for known destinations and expiry, in addition
+/// to the checks after received announcements.
+const PARKING_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Bundle waiting for its destination to become known.
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct ParkedBundle {
+    /// Time the bundle was parked.
+    pub parked_at: DateTime<Utc>,
+    /// The parked bundle.
+    pub bundle: BundleSendBuffer,
+}
+
+/// Parked bundles and the counters of the parking.
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct BundleParkingReport {
+    /// Bundles re-queued after their destination became known.
+    pub requeued: u64,
+    /// Bundles dropped after waiting `max_age_seconds`.
+    pub expired: u64,
+    /// Bundles queued without known next hop as the parking was full.
+    pub overflowed: u64,
+    /// Currently parked bundles.
+    pub bundles: Vec<ParkedBundle>,
+}
+
+/// Holds bundles whose destination is neither a local service nor learned from a neighbor,
+/// instead of flooding them pointlessly.
+#[derive(Debug)]
+pub struct BundleParking {
+    /// Max amount of parked bundles.
+    max_parked: usize,
+    /// Max time a bundle stays parked.
+    max_age: chrono::Duration,
+    /// Parked bundles, oldest first.
+    parked: Mutex<Vec<ParkedBundle>>,
+    /// Signalled when announcements may have made new destinations known.
+    destinations_changed: Notify,
+    /// Bundles re-queued after their destination became known.
+    requeued: AtomicU64,
+    /// Bundles dropped after waiting `max_age`.
+    expired: AtomicU64,
+    /// Bundles queued without known next hop as the parking was full.
+    overflowed: AtomicU64,
+}
+
+impl BundleParking {
+    /// Creates a new empty [`BundleParking`].
+    pub fn new(config: &BundleParkingConfig) -> Self {
+        Self {
+            max_parked: config.max_parked,
+            max_age: chrono::Duration::from_std(Duration::from_secs(config.max_age_seconds))
+                .unwrap_or(chrono::Duration::MAX),
+            parked: Mutex::new(Vec::new()),
+            destinations_changed: Notify::new(),
+            requeued: AtomicU64::new(0),
+            expired: AtomicU64::new(0),
+            overflowed: AtomicU64::new(0),
+        }
+    }
+
+    /// Signals that new destinations may be known, e.g. after a received announcement.
+    pub fn notify_destinations_changed(&self) {
+        self.destinations_changed.notify_one();
+    }
+
+    /// Parks a bundle, returns the bundle if the parking is full.
+    pub async fn park(
+        &self,
+        bundle: BundleSendBuffer,
+        now: DateTime<Utc>,
+    ) -> Option<BundleSendBuffer> {
+        let mut parked = self.parked.lock().await;
+        if parked.len() >= self.max_parked {
+            self.overflowed.fetch_add(1, Ordering::Relaxed);
+            return Some(bundle);
+        }
+        parked.push(ParkedBundle {
+            parked_at: now,
+            bundle,
+        });
+        None
+    }
+
+    /// Returns the destinations of the parked bundles.
+    pub async fn destinations(&self) -> HashSet<EndDeviceId> {
+        self.parked
+            .lock()
+            .await
+            .iter()
+            .map(|parked_bundle| parked_bundle.bundle.destination())
+            .collect()
+    }
+
+    /// Removes and returns the parked bundles to one of the `known` destinations in the order they
+    /// were parked, drops the bundles parked longer than the max age.
+    pub async fn release(
+        &self,
+        known: &HashSet<EndDeviceId>,
+        now: DateTime<Utc>,
+    ) -> Vec<BundleSendBuffer> {
+        let mut parked = self.parked.lock().await;
+        let mut released = Vec::new();
+        let mut expired = 0;
+        parked.retain(|parked_bundle| {
+            if known.contains(&parked_bundle.bundle.destination()) {
+                released.push(parked_bundle.bundle.clone());
+                false
+            } else if now - parked_bundle.parked_at > self.max_age {
+                expired += 1;
+                false
+            } else {
+                true
+            }
+        });
+        self.requeued
+            .fetch_add(released.len() as u64, Ordering::Relaxed);
+        self.expired.fetch_add(expired, Ordering::Relaxed);
+        released
+    }
+
+    /// Returns the parked bundles and the counters.
+    pub async fn report(&self) -> BundleParkingReport {
+        BundleParkingReport {
+            requeued: self.requeued.load(Ordering::Relaxed),
+            expired: self.expired.load(Ordering::Relaxed),
+            overflowed: self.overflowed.load(Ordering::Relaxed),
+            bundles: self.parked.lock().await.clone(),
+        }
+    }
+
+    /// Re-queues the parked bundles whose destination became known.
+    async fn requeue_known(
+        &self,
+        state: &AppState,
+        bundle_send_buffer_tx: &mpsc::Sender<BundleSendBuffer>,
+    ) {
+        let mut known = HashSet::new();
+        for destination in self.destinations().await {
+            if state
+                .end_device_registry
+                .category(destination)
+                .await
+                .is_some()
+            {
+                known.insert(destination);
+            }
+        }
+        for bundle in self.release(&known, Utc::now()).await {
+            info!(destination = ?bundle.destination(), "Re-queueing parked bundle");
+            forward(bundle_send_buffer_tx, bundle).await;
+        }
+    }
+}
+
+/// Hands a bundle to the bundle queue.
+async fn forward(bundle_send_buffer_tx: &mpsc::Sender<BundleSendBuffer>, bundle: BundleSendBuffer) {
+    if let Err(err) = bundle_send_buffer_tx.send(bundle).await {
+        error!(%err);
+    }
+}
+
+/// Task parking the bundles without a known destination and re-queueing them once an announcement
+/// makes their destination known.
+#[instrument(skip_all)]
+pub async fn bundle_parking_task(
+    mut parking_rx: mpsc::Receiver<BundleSendBuffer>,
+    bundle_send_buffer_tx: mpsc::Sender<BundleSendBuffer>,
+    state: Arc<AppState>,
+    mut shutdown_agent: ShutdownAgent,
+) {
+    trace!("Starting up");
+    let Some(bundle_parking) = &state.bundle_parking else {
+        return;
+    };
+
+    loop {
+        tokio::select! {
+            bundle = parking_rx.recv() => {
+                let Some(bundle) = bundle else {
+                    return;
+                };
+                if state.end_device_registry.category(bundle.destination()).await.is_some() {
+                    forward(&bundle_send_buffer_tx, bundle).await;
+                } else if let Some(bundle) = bundle_parking.park(bundle, Utc::now()).await {
+                    warn!("Parking full, queueing bundle without known next hop");
+                    forward(&bundle_send_buffer_tx, bundle).await;
+                } else {
+                    trace!("Parked bundle without known next hop");
+                }
+            }
+            () = bundle_parking.destinations_changed.notified() => {
+                bundle_parking.requeue_known(&state, &bundle_send_buffer_tx).await;
+            }
+            () = tokio::time::sleep(PARKING_CHECK_INTERVAL) => {
+                bundle_parking.requeue_known(&state, &bundle_send_buffer_tx).await;
+            }
+            _ = shutdown_agent.await_shutdown() => {
+                trace!("Shutting down");
+                return
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use crate::bundle_parking::BundleParking;
+    use crate::configuration::BundleParkingConfig;
+    use crate::end_device_id::EndDeviceId;
+    use crate::send_buffers::BundleSendBuffer;
+    use chrono::Utc;
+    use std::collections::HashSet;
+
+    fn bundle(destination: u32) -> BundleSendBuffer {
+        BundleSendBuffer::new(
+            EndDeviceId(destination),
+            EndDeviceId(0x1234),
+            Utc::now(),
+            vec![0xFF; 10],
+            false,
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn bundles_are_released_for_known_destinations() {
+        let parking = BundleParking::new(&BundleParkingConfig {
+            max_parked: 3,
+            max_age_seconds: 60,
+        });
+        let now = Utc::now();
+        assert!(parking.park(bundle(1), now).await.is_none());
+        assert!(parking.park(bundle(2), now).await.is_none());
+        assert!(parking
+            .park(bundle(1), now - chrono::Duration::seconds(120))
+            .await
+            .is_none());
+        assert!(parking.park(bundle(3), now).await.is_some());
+        assert_eq!(
+            parking.destinations().await,
+            HashSet::from([EndDeviceId(1), EndDeviceId(2)])
+        );
+
+        let released = parking.release(&HashSet::from([EndDeviceId(2)]), now).await;
+        assert_eq!(released.len(), 1);
+        assert_eq!(released[0].destination(), EndDeviceId(2));
+
+        let report = parking.report().await;
+        assert_eq!(
+            (report.requeued, report.expired, report.overflowed),
+            (1, 1, 1)
+        );
+        assert_eq!(report.bundles.len(), 1);
+    }
+}
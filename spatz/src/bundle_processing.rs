@@ -1,15 +1,51 @@
 //! Processing of incoming bundles.
 
 use crate::graceful_shutdown::ShutdownAgent;
+use crate::oversize_bundles::SubBundleStatus;
+use crate::routing_hints::RoutingHints;
 use crate::send_buffers::BundleSendBuffer;
+use crate::status_reports;
+use crate::AppState;
+use chrono::Utc;
+use std::sync::Arc;
 use tokio::sync::mpsc;
 use tracing::{error, instrument, trace};
 
+/// Bundle submitted to be sent via LoRaWAN.
+#[derive(Debug, Clone)]
+pub struct SubmittedBundle {
+    /// The submitted bundle.
+    pub bundle: bp7::Bundle,
+    /// API client which submitted the bundle and is accounted the airtime, not set for bundles
+    /// of plugins.
+    pub client: Option<String>,
+    /// Routing hints of the submitting client, checked on submission.
+    pub routing_hints: RoutingHints,
+}
+
+impl From<bp7::Bundle> for SubmittedBundle {
+    fn from(bundle: bp7::Bundle) -> Self {
+        Self {
+            bundle,
+            client: None,
+            routing_hints: RoutingHints::default(),
+        }
+    }
+}
+
 /// Async task to process incoming bundle from the `bundles_from_ws_receiver` channel.
-/// Creates a [`BundleSendBuffer`] from the incoming [`bp7::Bundle`].
+/// Creates a [`BundleSendBuffer`] from the incoming [`bp7::Bundle`], fragmented for the payload
+/// sizes allowed with a LoRaWAN repeater if configured. Bundles exceeding the max size are
+/// rejected, compressed or split into multiple send buffers, see
+/// [`oversize_bundles`](crate::oversize_bundles). The bundle is sent as BP7 fragments if
+/// the [`Bp7Interop`](crate::bp7_interop::Bp7Interop) mode is active and carries the routing hints
+/// of the submitting client. Bundles requesting status reports are followed by a
+/// [`StatusReportRequest`](lorawan_dtn_protocol::StatusReportRequest). Queued bundles are kept
+/// for retransmission if configured, see [`bundle_resend`](crate::bundle_resend).
 #[instrument(skip_all)]
 pub async fn bundles_processor_task(
-    mut bundles_from_ws_rx: mpsc::Receiver<bp7::Bundle>,
+    state: Arc<AppState>,
+    mut bundles_from_ws_rx: mpsc::Receiver<SubmittedBundle>,
     bundle_send_buffer_tx: mpsc::Sender<BundleSendBuffer>,
     mut shutdown_agent: ShutdownAgent,
 ) {
@@ -22,18 +58,65 @@ pub async fn bundles_processor_task(
                 return
             }
         };
-        if let Some(bundle) = bundle {
+        if let Some(submitted) = bundle {
+            let kept = state
+                .bundle_resend
+                .keeps_bundles()
+                .then(|| submitted.clone());
+            let SubmittedBundle {
+                mut bundle,
+                client,
+                routing_hints,
+            } = submitted;
             trace!("Received bundle: {bundle}");
 
-            match BundleSendBuffer::try_from(bundle) {
-                Ok(send_buffer) => {
-                    if let Err(err) = bundle_send_buffer_tx.try_send(send_buffer) {
-                        error!(%err);
-                    }
-                }
+            let status_report_request = status_reports::request_for(&bundle);
+            let policy = state.oversize_bundles.policy(&routing_hints);
+            let send_buffers = match state.oversize_bundles.send_buffers(
+                &mut bundle,
+                policy,
+                &state.payload_codecs,
+                state.repeater_compatible,
+            ) {
+                Ok(send_buffers) => send_buffers,
                 Err(err) => {
                     error!(%err);
+                    continue;
                 }
+            };
+            let bp7_framing = match &state.bp7_interop {
+                Some(bp7_interop) => {
+                    Some(bp7_interop.choose_framing(&*state.neighbor_table.lock().await))
+                }
+                None => None,
+            };
+            state.oversize_bundles.track_split(&send_buffers);
+            let mut queued = false;
+            for mut send_buffer in send_buffers {
+                send_buffer.set_client(client.clone());
+                send_buffer.set_routing_hints(routing_hints.clone());
+                if let Some(bp7_framing) = bp7_framing {
+                    send_buffer.set_bp7_framing(bp7_framing);
+                }
+                let sub_bundle_key = send_buffer.sub_bundle_key();
+                match bundle_send_buffer_tx.try_send(send_buffer) {
+                    Ok(()) => queued = true,
+                    Err(err) => {
+                        error!(%err);
+                        if let Some(key) = sub_bundle_key {
+                            state.oversize_bundles.update(key, SubBundleStatus::Dropped);
+                        }
+                    }
+                }
+            }
+            if !queued {
+                continue;
+            }
+            if let Some(kept) = kept {
+                state.bundle_resend.keep(kept, Utc::now());
+            }
+            if let Some(request) = status_report_request {
+                status_reports::send_request(&state, request).await;
             }
         }
     }
@@ -0,0 +1,341 @@
+//! Retransmission of bundles on request of their destination.
+//!
+//! The destination node of a lost bundle, e.g. whose fragments could not be combined, sends a
+//! [`BundleResendRequest`] to the source of the bundle, identifying the bundle by its source,
+//! destination and timestamp. If configured, the source node keeps the submitted bundles for a
+//! window and submits a requested bundle again with the client and routing hints of the original
+//! submission. The resent bundle gets a new creation timestamp, as the packet caches along the
+//! path and the delivery ledger of the destination drop packets equal to the lost ones.
+//!
+//! Relays forward requests like any other packet addressed to another node.
+
+use crate::bundle_processing::SubmittedBundle;
+use crate::configuration::BundleResendConfig;
+use crate::end_device_id::EndDeviceId;
+use crate::end_device_registry::EndDeviceCategory;
+use crate::error::BundleResendError;
+use crate::packet_cache::PacketSource;
+use crate::status_reports::creation_time;
+use crate::AppState;
+use chirpstack_gwb_integration::downlinks::predefined_parameters::DataRate;
+use chrono::{DateTime, Utc};
+use lorawan_dtn_protocol::unix_ts_to_dtn_time;
+use lorawan_dtn_protocol::{BundleResendRequest, LoRaWanPacket};
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use tracing::{error, trace};
+
+/// Data rate requests are sent with.
+const BUNDLE_RESEND_REQUEST_DATA_RATE: DataRate = DataRate::Eu863_870Dr3;
+
+/// Counters of the bundle retransmissions.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Serialize, JsonSchema)]
+pub struct BundleResendStats {
+    /// Submitted bundles currently kept for retransmission.
+    pub kept_bundles: u64,
+    /// Requests sent for bundles addressed to this node.
+    pub requests_sent: u64,
+    /// Requests received for bundles sent by this node.
+    pub requests_received: u64,
+    /// Bundles submitted again on request.
+    pub bundles_resent: u64,
+    /// Requests received for bundles which are not kept, e.g. as the window passed.
+    pub unknown_requests: u64,
+}
+
+/// Key of a kept bundle: source, destination and timestamp, as sent in the custom headers.
+type BundleKey = (EndDeviceId, EndDeviceId, DateTime<Utc>);
+
+/// Submitted bundle kept for retransmission.
+#[derive(Debug, Clone)]
+struct KeptBundle {
+    /// Key the bundle is requested with.
+    key: BundleKey,
+    /// Time the bundle was queued.
+    kept_at: DateTime<Utc>,
+    /// The submitted bundle with its client and routing hints.
+    submitted: SubmittedBundle,
+}
+
+/// Submitted bundles kept for retransmission and counters of the retransmissions.
+///
+/// Uses a [`std::sync::Mutex`] as the bundles are kept and looked up without awaiting.
+#[derive(Debug)]
+pub struct BundleResend {
+    /// The configuration, no bundles are kept if not set.
+    config: Option<BundleResendConfig>,
+    /// Kept bundles, oldest first.
+    kept: Mutex<VecDeque<KeptBundle>>,
+    /// Requests sent for bundles addressed to this node.
+    requests_sent: AtomicU64,
+    /// Requests received for bundles sent by this node.
+    requests_received: AtomicU64,
+    /// Bundles submitted again on request.
+    bundles_resent: AtomicU64,
+    /// Requests received for bundles which are not kept.
+    unknown_requests: AtomicU64,
+}
+
+impl BundleResend {
+    /// Creates a new [`BundleResend`] without kept bundles.
+    pub fn new(config: Option<BundleResendConfig>) -> Self {
+        Self {
+            config,
+            kept: Mutex::new(VecDeque::new()),
+            requests_sent: AtomicU64::new(0),
+            requests_received: AtomicU64::new(0),
+            bundles_resent: AtomicU64::new(0),
+            unknown_requests: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns whether submitted bundles are kept for retransmission.
+    pub fn keeps_bundles(&self) -> bool {
+        self.config.is_some()
+    }
+
+    /// Keeps a queued bundle for the configured window, the oldest bundles are removed beyond
+    /// the maximum amount. Bundles whose end points are no end device IDs are not kept, they
+    /// cannot be requested.
+    pub fn keep(&self, submitted: SubmittedBundle, now: DateTime<Utc>) {
+        let Some(config) = &self.config else {
+            return;
+        };
+        let Some(key) = bundle_key(&submitted.bundle) else {
+            return;
+        };
+        let mut kept = self.lock();
+        remove_expired(&mut kept, config, now);
+        kept.retain(|kept_bundle| kept_bundle.key != key);
+        while kept.len() >= config.max_bundles {
+            kept.pop_front();
+        }
+        kept.push_back(KeptBundle {
+            key,
+            kept_at: now,
+            submitted,
+        });
+    }
+
+    /// Returns the kept bundle requested by the request, `None` if it is not kept. The bundle
+    /// stays kept, so it can be requested again within the window.
+    pub fn lookup(
+        &self,
+        request: &BundleResendRequest,
+        now: DateTime<Utc>,
+    ) -> Option<SubmittedBundle> {
+        let config = self.config.as_ref()?;
+        let mut kept = self.lock();
+        remove_expired(&mut kept, config, now);
+        let key = (request.destination, request.source, request.timestamp);
+        kept.iter()
+            .find(|kept_bundle| kept_bundle.key == key)
+            .map(|kept_bundle| kept_bundle.submitted.clone())
+    }
+
+    /// Returns the counters of the retransmissions.
+    pub fn stats(&self) -> BundleResendStats {
+        BundleResendStats {
+            kept_bundles: u64::try_from(self.lock().len()).unwrap_or(u64::MAX),
+            requests_sent: self.requests_sent.load(Ordering::Relaxed),
+            requests_received: self.requests_received.load(Ordering::Relaxed),
+            bundles_resent: self.bundles_resent.load(Ordering::Relaxed),
+            unknown_requests: self.unknown_requests.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Locks the kept bundles, a poisoned lock is still used as the bundles stay valid.
+    fn lock(&self) -> MutexGuard<'_, VecDeque<KeptBundle>> {
+        self.kept.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Returns the key of the bundle, `None` if its end points are no end device IDs or it has no
+/// creation time.
+fn bundle_key(bundle: &bp7::Bundle) -> Option<BundleKey> {
+    Some((
+        EndDeviceId::try_from(bundle.primary.source.clone()).ok()?,
+        EndDeviceId::try_from(bundle.primary.destination.clone()).ok()?,
+        creation_time(bundle)?,
+    ))
+}
+
+/// Removes the bundles kept longer than the window.
+fn remove_expired(
+    kept: &mut VecDeque<KeptBundle>,
+    config: &BundleResendConfig,
+    now: DateTime<Utc>,
+) {
+    let window = chrono::Duration::from_std(std::time::Duration::from_secs(config.window_seconds))
+        .unwrap_or(chrono::Duration::MAX);
+    while kept
+        .front()
+        .is_some_and(|kept_bundle| now.signed_duration_since(kept_bundle.kept_at) >= window)
+    {
+        kept.pop_front();
+    }
+}
+
+/// Enqueues a request for a bundle addressed to a local end device ID and adds it to the packet
+/// cache to not relay it again.
+///
+/// # Errors
+///
+/// Returns an error if:
+/// - the node does not serve local bundles, the resent bundle would be dropped.
+/// - the requesting end device ID is not the one of a local service.
+/// - the relay queue is full.
+pub async fn send_request(
+    state: &AppState,
+    request: BundleResendRequest,
+) -> Result<(), BundleResendError> {
+    if !state.node_profile.serves_local_bundles() {
+        return Err(BundleResendError::RelayOnlyNode);
+    }
+    if state.end_device_registry.category(request.source).await
+        != Some(EndDeviceCategory::LocalService)
+    {
+        return Err(BundleResendError::NotLocal {
+            end_device_id: request.source.0,
+        });
+    }
+    trace!("Sending bundle resend request: {request:?}");
+    let phy_payload = request.convert_to_lorawan_phy_payload();
+    if !state
+        .queue_manager
+        .enqueue_relay_packet(Box::new(request), BUNDLE_RESEND_REQUEST_DATA_RATE)
+        .await
+    {
+        return Err(BundleResendError::QueueFull);
+    }
+    let _ = state
+        .packet_cache
+        .insert(&phy_payload, PacketSource::Local)
+        .await;
+    state
+        .bundle_resend
+        .requests_sent
+        .fetch_add(1, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Submits the requested bundle again with a new creation timestamp if it is kept.
+pub fn process_request(state: &AppState, request: &BundleResendRequest, now: DateTime<Utc>) {
+    let bundle_resend = &state.bundle_resend;
+    bundle_resend
+        .requests_received
+        .fetch_add(1, Ordering::Relaxed);
+    let Some(mut submitted) = bundle_resend.lookup(request, now) else {
+        trace!("Requested bundle is not kept, ignoring request");
+        bundle_resend
+            .unknown_requests
+            .fetch_add(1, Ordering::Relaxed);
+        return;
+    };
+    submitted.bundle.primary.creation_timestamp = bp7::CreationTimestamp::with_time_and_seq(
+        unix_ts_to_dtn_time(now.timestamp().unsigned_abs()),
+        0,
+    );
+    if let Err(err) = state.bundles_from_ws.try_send(submitted) {
+        error!("Failed to resend bundle: {err}");
+    } else {
+        bundle_resend.bundles_resent.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use crate::bundle_processing::SubmittedBundle;
+    use crate::bundle_resend::BundleResend;
+    use crate::configuration::BundleResendConfig;
+    use crate::end_device_id::EndDeviceId;
+    use bp7::flags::BlockControlFlags;
+    use chrono::{DateTime, Duration, TimeZone, Utc};
+    use lorawan_dtn_protocol::unix_ts_to_dtn_time;
+    use lorawan_dtn_protocol::{BundleResendRequest, BUNDLE_LIFETIME};
+
+    fn bundle(timestamp: DateTime<Utc>) -> SubmittedBundle {
+        let primary_block = bp7::primary::PrimaryBlockBuilder::new()
+            .source(EndDeviceId(1).try_into().unwrap())
+            .destination(EndDeviceId(2).try_into().unwrap())
+            .report_to(EndDeviceId(1).try_into().unwrap())
+            .creation_timestamp(bp7::CreationTimestamp::with_time_and_seq(
+                unix_ts_to_dtn_time(timestamp.timestamp().unsigned_abs()),
+                0,
+            ))
+            .lifetime(BUNDLE_LIFETIME)
+            .build()
+            .unwrap();
+        let payload = bp7::canonical::new_payload_block(BlockControlFlags::empty(), vec![1, 2]);
+        bp7::Bundle::new(primary_block, vec![payload]).into()
+    }
+
+    fn request(timestamp: DateTime<Utc>) -> BundleResendRequest {
+        BundleResendRequest {
+            destination: EndDeviceId(1),
+            source: EndDeviceId(2),
+            timestamp,
+        }
+    }
+
+    #[test]
+    fn bundles_are_kept_for_the_window() {
+        let now = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        let bundle_resend = BundleResend::new(Some(BundleResendConfig {
+            window_seconds: 600,
+            max_bundles: 2,
+        }));
+        for offset in 0..3 {
+            let timestamp = now + Duration::seconds(offset);
+            bundle_resend.keep(bundle(timestamp), timestamp);
+        }
+        assert_eq!(bundle_resend.stats().kept_bundles, 2);
+        assert!(bundle_resend.lookup(&request(now), now).is_none());
+
+        let timestamp = now + Duration::seconds(1);
+        let kept = bundle_resend
+            .lookup(&request(timestamp), timestamp)
+            .unwrap();
+        assert_eq!(
+            EndDeviceId::try_from(kept.bundle.primary.destination).unwrap(),
+            EndDeviceId(2)
+        );
+        assert!(bundle_resend
+            .lookup(&request(timestamp), timestamp)
+            .is_some());
+        assert!(bundle_resend
+            .lookup(
+                &BundleResendRequest {
+                    source: EndDeviceId(3),
+                    ..request(timestamp)
+                },
+                timestamp
+            )
+            .is_none());
+
+        assert!(bundle_resend
+            .lookup(&request(timestamp), now + Duration::seconds(601))
+            .is_none());
+        assert_eq!(bundle_resend.stats().kept_bundles, 1);
+        assert!(bundle_resend
+            .lookup(
+                &request(now + Duration::seconds(2)),
+                now + Duration::seconds(601)
+            )
+            .is_some());
+    }
+
+    #[test]
+    fn bundles_are_not_kept_without_config() {
+        let now = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        let bundle_resend = BundleResend::new(None);
+        bundle_resend.keep(bundle(now), now);
+        assert!(!bundle_resend.keeps_bundles());
+        assert!(bundle_resend.lookup(&request(now), now).is_none());
+        assert_eq!(bundle_resend.stats().kept_bundles, 0);
+    }
+}
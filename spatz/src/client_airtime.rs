@@ -0,0 +1,178 @@
+//! Airtime accounting per API client, optionally enforcing a daily airtime quota so clients of a
+//! shared node get fair access.
+
+use crate::configuration::ClientAirtimeQuotaConfig;
+use crate::error::AirtimeQuotaError;
+use chrono::{DateTime, NaiveDate, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use tokio::sync::Mutex;
+
+/// Client of bundles submitted via WebSocket without client ID.
+pub const ANONYMOUS_CLIENT: &str = "anonymous";
+
+/// Airtime consumed by the bundles of a client.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ClientAirtimeUsage {
+    /// Airtime of all sent fragments in milliseconds.
+    pub total_ms: f64,
+    /// Day the `today_ms` were consumed on, in UTC.
+    pub day: NaiveDate,
+    /// Airtime of the fragments sent on `day` in milliseconds.
+    pub today_ms: f64,
+    /// Accepted bundles.
+    pub bundles: u64,
+    /// Bundles rejected as the daily quota was exhausted.
+    pub rejected: u64,
+}
+
+impl ClientAirtimeUsage {
+    /// Creates an empty [`ClientAirtimeUsage`] for the day.
+    fn new(day: NaiveDate) -> Self {
+        Self {
+            total_ms: 0.0,
+            day,
+            today_ms: 0.0,
+            bundles: 0,
+            rejected: 0,
+        }
+    }
+
+    /// Resets the airtime of the day if `today` is a new day.
+    fn roll_over(&mut self, today: NaiveDate) {
+        if self.day != today {
+            self.day = today;
+            self.today_ms = 0.0;
+        }
+    }
+}
+
+/// Daily quota and airtime usage of all clients.
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+pub struct ClientAirtimeStats {
+    /// Daily airtime quota per client in milliseconds, not enforced if not set.
+    pub daily_quota_ms: Option<u64>,
+    /// Airtime usage by client ID.
+    pub clients: BTreeMap<String, ClientAirtimeUsage>,
+}
+
+/// Accounts the airtime of the sent bundle fragments to the clients which submitted the bundles.
+#[derive(Debug)]
+pub struct ClientAirtime {
+    /// Daily airtime quota per client in milliseconds, not enforced if not set.
+    daily_quota_ms: Option<u64>,
+    /// Airtime usage by client ID.
+    usage: Mutex<BTreeMap<String, ClientAirtimeUsage>>,
+}
+
+impl ClientAirtime {
+    /// Creates a new [`ClientAirtime`] with the usage loaded from the database, enforcing the
+    /// quota if configured.
+    pub fn new(
+        quota: Option<&ClientAirtimeQuotaConfig>,
+        usage: BTreeMap<String, ClientAirtimeUsage>,
+    ) -> Self {
+        Self {
+            daily_quota_ms: quota.map(|quota| quota.daily_quota_ms),
+            usage: Mutex::new(usage),
+        }
+    }
+
+    /// Accepts a bundle of the client unless the client exhausted its daily quota.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the client consumed its daily airtime quota.
+    pub async fn admit(&self, client: &str, now: DateTime<Utc>) -> Result<(), AirtimeQuotaError> {
+        let today = now.date_naive();
+        let mut usage = self.usage.lock().await;
+        let client_usage = usage
+            .entry(client.to_owned())
+            .or_insert_with(|| ClientAirtimeUsage::new(today));
+        client_usage.roll_over(today);
+        if let Some(quota_ms) = self.daily_quota_ms {
+            // Saturating float to int conversion, the airtime is never negative.
+            #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+            let used_ms = client_usage.today_ms as u64;
+            if used_ms >= quota_ms {
+                client_usage.rejected += 1;
+                return Err(AirtimeQuotaError::DailyQuotaExceeded {
+                    client: client.to_owned(),
+                    used_ms,
+                    quota_ms,
+                });
+            }
+        }
+        client_usage.bundles += 1;
+        Ok(())
+    }
+
+    /// Accounts the airtime of a sent fragment to the client.
+    pub async fn record(&self, client: &str, airtime_ms: f64, now: DateTime<Utc>) {
+        let today = now.date_naive();
+        let mut usage = self.usage.lock().await;
+        let client_usage = usage
+            .entry(client.to_owned())
+            .or_insert_with(|| ClientAirtimeUsage::new(today));
+        client_usage.roll_over(today);
+        client_usage.total_ms += airtime_ms;
+        client_usage.today_ms += airtime_ms;
+    }
+
+    /// Returns the airtime usage by client ID.
+    pub async fn usage(&self) -> BTreeMap<String, ClientAirtimeUsage> {
+        self.usage.lock().await.clone()
+    }
+
+    /// Returns the quota and the airtime usage of all clients.
+    pub async fn stats(&self) -> ClientAirtimeStats {
+        ClientAirtimeStats {
+            daily_quota_ms: self.daily_quota_ms,
+            clients: self.usage().await,
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use crate::client_airtime::ClientAirtime;
+    use crate::configuration::ClientAirtimeQuotaConfig;
+    use crate::error::AirtimeQuotaError;
+    use chrono::{TimeZone, Utc};
+    use std::collections::BTreeMap;
+
+    #[tokio::test]
+    async fn daily_quota_is_enforced_per_client() {
+        let client_airtime = ClientAirtime::new(
+            Some(&ClientAirtimeQuotaConfig {
+                daily_quota_ms: 1000,
+            }),
+            BTreeMap::new(),
+        );
+        let now = Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap();
+        assert!(client_airtime.admit("a", now).await.is_ok());
+        client_airtime.record("a", 600.0, now).await;
+        assert!(client_airtime.admit("a", now).await.is_ok());
+        client_airtime.record("a", 600.0, now).await;
+        assert_eq!(
+            client_airtime.admit("a", now).await,
+            Err(AirtimeQuotaError::DailyQuotaExceeded {
+                client: "a".to_owned(),
+                used_ms: 1200,
+                quota_ms: 1000,
+            })
+        );
+        assert!(client_airtime.admit("b", now).await.is_ok());
+
+        let tomorrow = Utc.with_ymd_and_hms(2024, 5, 2, 0, 0, 1).unwrap();
+        assert!(client_airtime.admit("a", tomorrow).await.is_ok());
+
+        let usage = client_airtime.usage().await;
+        let a = usage.get("a").unwrap();
+        assert!((a.total_ms - 1200.0).abs() < f64::EPSILON);
+        assert!(a.today_ms.abs() < f64::EPSILON);
+        assert_eq!((a.bundles, a.rejected), (3, 1));
+    }
+}
@@ -0,0 +1,73 @@
+//! Source of the current time.
+//!
+//! Components whose expiry and windowing logic depends on the current time, e.g. the duty cycle
+//! manager and the packet cache, get the time from a [`Clock`] instead of calling
+//! [`Utc::now`]. Spatz uses the [`SystemClock`], tests advance a `MockClock` to check the logic
+//! deterministically.
+
+use chrono::{DateTime, Utc};
+use std::fmt::Debug;
+use std::sync::Arc;
+
+/// Source of the current time.
+pub trait Clock: Debug + Send + Sync {
+    /// Returns the current time.
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// Shared source of the current time.
+pub type SharedClock = Arc<dyn Clock>;
+
+/// Clock returning the time of the system.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct SystemClock;
+
+impl SystemClock {
+    /// Returns the system clock as [`SharedClock`].
+    pub fn shared() -> SharedClock {
+        Arc::new(SystemClock)
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// Clock returning a time set by the test, it only advances when told to.
+#[cfg(test)]
+#[derive(Debug)]
+pub struct MockClock {
+    /// The current time.
+    now: std::sync::Mutex<DateTime<Utc>>,
+}
+
+#[cfg(test)]
+impl MockClock {
+    /// Creates a new [`MockClock`] starting at `now`.
+    pub fn new(now: DateTime<Utc>) -> Arc<Self> {
+        Arc::new(Self {
+            now: std::sync::Mutex::new(now),
+        })
+    }
+
+    /// Advances the clock by the duration.
+    pub fn advance(&self, duration: chrono::Duration) {
+        let mut now = self
+            .now
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        *now = *now + duration;
+    }
+}
+
+#[cfg(test)]
+impl Clock for MockClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self
+            .now
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
@@ -1,12 +1,28 @@
 //! Configuration types.
 
+use crate::end_device_id::{EndDeviceId, ManagedEndDeviceId};
+use crate::error::ConfigurationValidationError;
+use crate::neighbor_table::SignalQuality;
+use crate::packet_cache::PacketClass;
+use chirpstack_gwb_integration::downlinks::downlink_builder::MAX_DOWNLINK_ITEMS;
+use chirpstack_gwb_integration::downlinks::predefined_parameters::Frequency;
+use chirpstack_gwb_integration::downlinks::TxPath;
+use chirpstack_gwb_integration::gateway_id::GatewayId;
+use chirpstack_gwb_integration::gateway_topics::TopicLayout;
+use chirpstack_gwb_integration::logging::LoggingConfig;
+use chirpstack_gwb_integration::runtime::marshaler::Marshaler;
+use chirpstack_gwb_integration::runtime::mqtt_client::MqttV5Options;
+use chrono::NaiveTime;
 use clap::Parser;
+use lorawan_dtn_protocol::{ServiceTag, MAX_DATA_RATE_INDEX, MAX_SERVICES_PER_END_DEVICE};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
 use std::net::IpAddr;
+use std::time::Duration;
 
 /// Configuration of the daemon application.
-#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct Configuration {
     /// ChirpStack API credentials and parameters.
     pub chirpstack_api: ChirpStackApiConfig,
@@ -14,6 +30,562 @@ pub struct Configuration {
     pub mqtt: MqttConfig,
     /// Daemon configuration
     pub daemon: DaemonConfig,
+    /// Logging sinks, only stdout logging if not set. Read from the configuration file when the
+    /// process starts, before the database is opened.
+    #[serde(default)]
+    pub logging: LoggingConfig,
+}
+
+impl Configuration {
+    /// Checks the configuration for values which deserialize but cannot be applied.
+    ///
+    /// Returns all found problems, an empty list if the configuration is valid.
+    pub fn validate(&self) -> Vec<ConfigurationValidationError> {
+        let mut errors = Vec::new();
+
+        let queue_config = &self.daemon.queue_config;
+        for (field, size) in [
+            (
+                "daemon.queue_config.relay_queue_size",
+                queue_config.relay_queue_size,
+            ),
+            (
+                "daemon.queue_config.bundle_queue_size",
+                queue_config.bundle_queue_size,
+            ),
+            (
+                "daemon.queue_config.announcement_queue_size",
+                queue_config.announcement_queue_size,
+            ),
+        ] {
+            require_non_zero(&mut errors, field, u64::try_from(size).unwrap_or(u64::MAX));
+        }
+        if let PreemptionPolicy::Interleave { packets } = queue_config.preemption {
+            require_non_zero(
+                &mut errors,
+                "daemon.queue_config.preemption.packets",
+                u64::from(packets),
+            );
+        }
+        require_non_zero(
+            &mut errors,
+            "daemon.packet_cache.cleanup_interval_seconds",
+            self.daemon.packet_cache.cleanup_interval_seconds,
+        );
+        for (field, class_config) in [
+            (
+                "daemon.packet_cache.announcements",
+                &self.daemon.packet_cache.announcements,
+            ),
+            (
+                "daemon.packet_cache.fragments",
+                &self.daemon.packet_cache.fragments,
+            ),
+            (
+                "daemon.packet_cache.complete_bundles",
+                &self.daemon.packet_cache.complete_bundles,
+            ),
+        ] {
+            if let Some(max_entries) = class_config
+                .as_ref()
+                .and_then(|class_config| class_config.max_entries)
+            {
+                require_non_zero(
+                    &mut errors,
+                    &format!("{field}.max_entries"),
+                    u64::try_from(max_entries).unwrap_or(u64::MAX),
+                );
+            }
+        }
+        validate_routing_algorithm_config(
+            &mut errors,
+            "daemon.routing_algorithm_config",
+            &self.daemon.routing_algorithm_config,
+        );
+        let mut destination_classes = HashSet::new();
+        for (index, scoped_routing_algorithm) in
+            self.daemon.scoped_routing_algorithms.iter().enumerate()
+        {
+            let prefix = format!("daemon.scoped_routing_algorithms[{index}]");
+            require_non_zero(
+                &mut errors,
+                &format!("{prefix}.destination_classes"),
+                u64::try_from(scoped_routing_algorithm.destination_classes.len())
+                    .unwrap_or(u64::MAX),
+            );
+            for destination_class in &scoped_routing_algorithm.destination_classes {
+                if !destination_classes.insert(*destination_class) {
+                    errors.push(ConfigurationValidationError::DuplicateDestinationClass(
+                        *destination_class,
+                    ));
+                }
+            }
+            validate_routing_algorithm_config(
+                &mut errors,
+                &format!("{prefix}.routing_algorithm_config"),
+                &scoped_routing_algorithm.routing_algorithm_config,
+            );
+        }
+        if let Some(announcement_config) = &self.daemon.announcement_config {
+            require_non_zero(
+                &mut errors,
+                "daemon.announcement_config.interval_seconds",
+                announcement_config.interval_seconds,
+            );
+            for (index, announced_services) in announcement_config.services.iter().enumerate() {
+                let field = format!("daemon.announcement_config.services[{index}].services");
+                require_non_zero(
+                    &mut errors,
+                    &field,
+                    u64::try_from(announced_services.services.len()).unwrap_or(u64::MAX),
+                );
+                if announced_services.services.len() > MAX_SERVICES_PER_END_DEVICE {
+                    errors.push(ConfigurationValidationError::TooMany(
+                        field,
+                        MAX_SERVICES_PER_END_DEVICE,
+                    ));
+                }
+            }
+            for index in &announcement_config.receive_data_rates {
+                if *index > MAX_DATA_RATE_INDEX {
+                    errors.push(ConfigurationValidationError::AboveMaximum(
+                        "daemon.announcement_config.receive_data_rates".to_owned(),
+                        u64::from(MAX_DATA_RATE_INDEX),
+                    ));
+                }
+            }
+            for frequency in &announcement_config.channels {
+                if Frequency::from_hz(*frequency).is_none() {
+                    errors.push(ConfigurationValidationError::UnsupportedFrequency(
+                        "daemon.announcement_config.channels".to_owned(),
+                        *frequency,
+                    ));
+                }
+            }
+            if let Some(trimming) = &announcement_config.trimming {
+                require_non_zero(
+                    &mut errors,
+                    "daemon.announcement_config.trimming.max_end_device_ids",
+                    u64::from(trimming.max_end_device_ids),
+                );
+                if trimming.trim_below_percent > 100 {
+                    errors.push(ConfigurationValidationError::AboveMaximum(
+                        "daemon.announcement_config.trimming.trim_below_percent".to_owned(),
+                        100,
+                    ));
+                }
+                if trimming.skip_below_percent > trimming.trim_below_percent {
+                    errors.push(ConfigurationValidationError::AboveMaximum(
+                        "daemon.announcement_config.trimming.skip_below_percent".to_owned(),
+                        u64::from(trimming.trim_below_percent),
+                    ));
+                }
+            }
+            if let Some(deferral) = &announcement_config.deferral {
+                require_non_zero(
+                    &mut errors,
+                    "daemon.announcement_config.deferral.relay_packets_threshold",
+                    u64::from(deferral.relay_packets_threshold),
+                );
+                require_non_zero(
+                    &mut errors,
+                    "daemon.announcement_config.deferral.bundles_threshold",
+                    u64::from(deferral.bundles_threshold),
+                );
+                require_non_zero(
+                    &mut errors,
+                    "daemon.announcement_config.deferral.max_deferral_seconds",
+                    deferral.max_deferral_seconds,
+                );
+            }
+        }
+        if let Some(gateway_stats) = &self.daemon.gateway_stats {
+            require_non_zero(
+                &mut errors,
+                "daemon.gateway_stats.retention_hours",
+                gateway_stats.retention_hours,
+            );
+            require_non_zero(
+                &mut errors,
+                "daemon.gateway_stats.max_entries_per_gateway",
+                u64::from(gateway_stats.max_entries_per_gateway),
+            );
+        }
+        if let Some(radio_stats) = &self.daemon.radio_stats {
+            require_non_zero(
+                &mut errors,
+                "daemon.radio_stats.retention_hours",
+                radio_stats.retention_hours,
+            );
+        }
+        if let Some(gateway_send_queues) = &self.daemon.gateway_send_queues {
+            require_non_zero(
+                &mut errors,
+                "daemon.gateway_send_queues.queue_size",
+                u64::try_from(gateway_send_queues.queue_size).unwrap_or(u64::MAX),
+            );
+            require_non_zero(
+                &mut errors,
+                "daemon.gateway_send_queues.min_gap_ms",
+                gateway_send_queues.min_gap_ms,
+            );
+        }
+        if let Some(bundle_parking) = &self.daemon.bundle_parking {
+            require_non_zero(
+                &mut errors,
+                "daemon.bundle_parking.max_parked",
+                u64::try_from(bundle_parking.max_parked).unwrap_or(u64::MAX),
+            );
+            require_non_zero(
+                &mut errors,
+                "daemon.bundle_parking.max_age_seconds",
+                bundle_parking.max_age_seconds,
+            );
+        }
+        if let Some(downlink_retransmission) = &self.daemon.downlink_retransmission {
+            require_non_zero(
+                &mut errors,
+                "daemon.downlink_retransmission.max_retries",
+                u64::from(downlink_retransmission.max_retries),
+            );
+            require_non_zero(
+                &mut errors,
+                "daemon.downlink_retransmission.backoff_ms",
+                downlink_retransmission.backoff_ms,
+            );
+        }
+        if let Some(delivery_ledger) = &self.daemon.delivery_ledger {
+            require_non_zero(
+                &mut errors,
+                "daemon.delivery_ledger.retention_seconds",
+                delivery_ledger.retention_seconds,
+            );
+            require_non_zero(
+                &mut errors,
+                "daemon.delivery_ledger.max_entries",
+                u64::try_from(delivery_ledger.max_entries).unwrap_or(u64::MAX),
+            );
+        }
+        if let Some(client_airtime_quota) = &self.daemon.client_airtime_quota {
+            require_non_zero(
+                &mut errors,
+                "daemon.client_airtime_quota.daily_quota_ms",
+                client_airtime_quota.daily_quota_ms,
+            );
+        }
+        for (index, window) in self.daemon.radio_silence.iter().enumerate() {
+            if window.start == window.end {
+                errors.push(ConfigurationValidationError::EmptyRange(format!(
+                    "daemon.radio_silence[{index}]"
+                )));
+            }
+        }
+        if let Some(memory_budget) = &self.daemon.memory_budget {
+            require_non_zero(
+                &mut errors,
+                "daemon.memory_budget.max_bytes",
+                memory_budget.max_bytes,
+            );
+        }
+        let mut payload_profiles = HashSet::new();
+        for payload_profile in &self.daemon.payload_profiles {
+            if !payload_profiles.insert(payload_profile.profile) {
+                errors.push(ConfigurationValidationError::DuplicatePayloadProfile(
+                    payload_profile.profile,
+                ));
+            }
+        }
+        let oversize_bundles = &self.daemon.oversize_bundles;
+        let compression_profile = oversize_bundles.compression_profile.map(|profile| {
+            self.daemon.payload_profiles.iter().any(|payload_profile| {
+                payload_profile.profile == profile
+                    && payload_profile.codec == PayloadCodecKind::Deflate
+            })
+        });
+        if compression_profile == Some(false)
+            || (compression_profile.is_none()
+                && oversize_bundles.policy == OversizePolicy::Compress)
+        {
+            errors.push(ConfigurationValidationError::NoCompressionProfile(
+                "daemon.oversize_bundles.compression_profile".to_owned(),
+            ));
+        }
+        let mut api_tokens = HashSet::new();
+        for api_token in &self.daemon.api_tokens {
+            if api_token.token.is_empty() || !api_tokens.insert(&api_token.token) {
+                errors.push(ConfigurationValidationError::InvalidApiToken(
+                    api_token.name.clone(),
+                ));
+            }
+        }
+        if let Some(duty_cycle_sharing) = &self.daemon.duty_cycle_sharing {
+            require_non_zero(
+                &mut errors,
+                "daemon.duty_cycle_sharing.dtn_share_percent",
+                u64::from(duty_cycle_sharing.dtn_share_percent),
+            );
+            if duty_cycle_sharing.dtn_share_percent > 100 {
+                errors.push(ConfigurationValidationError::AboveMaximum(
+                    "daemon.duty_cycle_sharing.dtn_share_percent".to_owned(),
+                    100,
+                ));
+            }
+        }
+        if let Some(unicast) = &self.daemon.unicast {
+            require_non_zero(
+                &mut errors,
+                "daemon.unicast.ack_timeout_seconds",
+                unicast.ack_timeout_seconds,
+            );
+        }
+        if let Some(path_cache) = &self.daemon.path_cache {
+            require_non_zero(
+                &mut errors,
+                "daemon.path_cache.ttl_seconds",
+                path_cache.ttl_seconds,
+            );
+            require_non_zero(
+                &mut errors,
+                "daemon.path_cache.max_failures",
+                u64::from(path_cache.max_failures),
+            );
+        }
+        if let Some(bp7_interop) = &self.daemon.bp7_interop {
+            require_non_zero(
+                &mut errors,
+                "daemon.bp7_interop.max_neighbor_age_seconds",
+                bp7_interop.max_neighbor_age_seconds,
+            );
+        }
+        if let Some(UplinkTraceConfig::Replay { speedup, .. }) = &self.daemon.uplink_trace {
+            require_non_zero(
+                &mut errors,
+                "daemon.uplink_trace.Replay.speedup",
+                u64::from(*speedup),
+            );
+        }
+        for (index, plugin) in self.daemon.plugins.iter().enumerate() {
+            require_non_zero(
+                &mut errors,
+                &format!("daemon.plugins[{index}].end_device_ids"),
+                u64::try_from(plugin.end_device_ids.len()).unwrap_or(u64::MAX),
+            );
+        }
+        if let Some(file_drop) = &self.daemon.file_drop {
+            require_non_zero(
+                &mut errors,
+                "daemon.file_drop.poll_interval_seconds",
+                file_drop.poll_interval_seconds,
+            );
+            require_non_zero(
+                &mut errors,
+                "daemon.file_drop.lifetime_seconds",
+                file_drop.lifetime_seconds,
+            );
+        }
+        if let Some(watchdog) = &self.daemon.watchdog {
+            require_non_zero(
+                &mut errors,
+                "daemon.watchdog.check_interval_seconds",
+                watchdog.check_interval_seconds,
+            );
+            require_non_zero(
+                &mut errors,
+                "daemon.watchdog.missed_beats",
+                u64::from(watchdog.missed_beats),
+            );
+        }
+        if let Some(beaconing) = &self.daemon.beaconing {
+            require_non_zero(
+                &mut errors,
+                "daemon.beaconing.interval_seconds",
+                beaconing.interval_seconds,
+            );
+            require_non_zero(
+                &mut errors,
+                "daemon.beaconing.frequency",
+                u64::from(beaconing.frequency),
+            );
+        }
+        if let Some(network_filter) = &self.daemon.network_filter {
+            if !network_filter.accept_untagged {
+                require_non_zero(
+                    &mut errors,
+                    "daemon.network_filter.accepted_network_ids",
+                    u64::try_from(network_filter.accepted_network_ids.len()).unwrap_or(u64::MAX),
+                );
+            }
+        }
+        if let Some(neighbor_trust) = &self.daemon.neighbor_trust {
+            for (field, value) in [
+                (
+                    "daemon.neighbor_trust.max_packets_per_minute",
+                    neighbor_trust.max_packets_per_minute,
+                ),
+                (
+                    "daemon.neighbor_trust.recovery_per_hour",
+                    neighbor_trust.recovery_per_hour,
+                ),
+            ] {
+                require_non_zero(&mut errors, field, u64::from(value));
+            }
+            if neighbor_trust.min_score > 100 {
+                errors.push(ConfigurationValidationError::AboveMaximum(
+                    "daemon.neighbor_trust.min_score".to_owned(),
+                    100,
+                ));
+            }
+        }
+        if let Some(bundle_resend) = &self.daemon.bundle_resend {
+            require_non_zero(
+                &mut errors,
+                "daemon.bundle_resend.window_seconds",
+                bundle_resend.window_seconds,
+            );
+            require_non_zero(
+                &mut errors,
+                "daemon.bundle_resend.max_bundles",
+                u64::try_from(bundle_resend.max_bundles).unwrap_or(u64::MAX),
+            );
+        }
+        if let Some(packet_export) = &self.daemon.packet_export {
+            require_non_zero(
+                &mut errors,
+                "daemon.packet_export.flush_interval_seconds",
+                packet_export.flush_interval_seconds,
+            );
+            require_non_zero(
+                &mut errors,
+                "daemon.packet_export.rotation_seconds",
+                packet_export.rotation_seconds,
+            );
+        }
+        if let Some(scheduling_journal) = &self.daemon.scheduling_journal {
+            require_non_zero(
+                &mut errors,
+                "daemon.scheduling_journal.max_entries",
+                u64::try_from(scheduling_journal.max_entries).unwrap_or(u64::MAX),
+            );
+        }
+        if let Some(bundle_mailbox) = &self.daemon.bundle_mailbox {
+            require_non_zero(
+                &mut errors,
+                "daemon.bundle_mailbox.max_bundles",
+                u64::try_from(bundle_mailbox.max_bundles).unwrap_or(u64::MAX),
+            );
+            require_non_zero(
+                &mut errors,
+                "daemon.bundle_mailbox.max_clients",
+                u64::try_from(bundle_mailbox.max_clients).unwrap_or(u64::MAX),
+            );
+        }
+        for (index, webhook) in self.daemon.webhooks.iter().enumerate() {
+            require_non_zero(
+                &mut errors,
+                &format!("daemon.webhooks[{index}].initial_backoff_ms"),
+                webhook.initial_backoff_ms,
+            );
+        }
+
+        for (index, gateway_capabilities) in self.daemon.gateway_capabilities.iter().enumerate() {
+            let field = format!("daemon.gateway_capabilities[{index}].tx_paths");
+            if gateway_capabilities.tx_paths.len() > MAX_DOWNLINK_ITEMS {
+                errors.push(ConfigurationValidationError::TooMany(
+                    field.clone(),
+                    MAX_DOWNLINK_ITEMS,
+                ));
+            }
+            let mut tx_paths = HashSet::new();
+            if !gateway_capabilities
+                .tx_paths
+                .iter()
+                .all(|tx_path| tx_paths.insert(*tx_path))
+            {
+                errors.push(ConfigurationValidationError::DuplicateTxPath(field));
+            }
+        }
+
+        let mut end_device_ids = HashSet::new();
+        for end_device_id in self
+            .daemon
+            .end_device_ids
+            .iter()
+            .chain(&self.daemon.group_ids)
+        {
+            if !end_device_ids.insert(ManagedEndDeviceId::from(end_device_id)) {
+                errors.push(ConfigurationValidationError::DuplicateEndDeviceId(
+                    end_device_id.clone(),
+                ));
+            }
+        }
+
+        if let Err(err) = self.logging.validate_directives() {
+            errors.push(ConfigurationValidationError::Logging(err.to_string()));
+        }
+        errors
+    }
+}
+
+/// Adds an error if `value` is zero.
+fn require_non_zero(errors: &mut Vec<ConfigurationValidationError>, field: &str, value: u64) {
+    if value == 0 {
+        errors.push(ConfigurationValidationError::Zero(field.to_owned()));
+    }
+}
+
+/// Checks the routing algorithm configuration at `prefix` for values which cannot be applied.
+fn validate_routing_algorithm_config(
+    errors: &mut Vec<ConfigurationValidationError>,
+    prefix: &str,
+    routing_algorithm_config: &RoutingAlgorithmConfig,
+) {
+    match routing_algorithm_config {
+        RoutingAlgorithmConfig::Flooding(flooding_config) => {
+            require_non_zero(
+                errors,
+                &format!("{prefix}.Flooding.periodic_send_delay"),
+                flooding_config.periodic_send_delay,
+            );
+            if let Some(GatewaySelectionConfig::Subset { k }) = &flooding_config.gateway_selection {
+                require_non_zero(
+                    errors,
+                    &format!("{prefix}.Flooding.gateway_selection.Subset.k"),
+                    u64::try_from(*k).unwrap_or(u64::MAX),
+                );
+            }
+            if let Some(airtime_pacing) = &flooding_config.airtime_pacing {
+                require_non_zero(
+                    errors,
+                    &format!("{prefix}.Flooding.airtime_pacing.min_gap_ms"),
+                    airtime_pacing.min_gap_ms,
+                );
+            }
+            if let Some(RelaySignalPolicy {
+                max_rssi: Some(max_rssi),
+                min_rssi: Some(min_rssi),
+                ..
+            }) = &flooding_config.relay_signal_policy
+            {
+                if min_rssi > max_rssi {
+                    errors.push(ConfigurationValidationError::EmptyRange(format!(
+                        "{prefix}.Flooding.relay_signal_policy.min_rssi..max_rssi"
+                    )));
+                }
+            }
+            if let Some(tdma_config) = &flooding_config.tdma {
+                require_non_zero(
+                    errors,
+                    &format!("{prefix}.Flooding.tdma.slot_length_ms"),
+                    tdma_config.slot_length_ms,
+                );
+                require_non_zero(
+                    errors,
+                    &format!("{prefix}.Flooding.tdma.frame_length"),
+                    u64::from(tdma_config.frame_length),
+                );
+            }
+        }
+    }
 }
 
 /// ChirpStack API credentials and parameters.
@@ -38,10 +610,54 @@ pub struct MqttConfig {
     pub port: u16,
     /// MQTT client ID
     pub client_id: String,
+    /// Payload format of the gateway bridge, detected per message if not set
+    #[serde(default)]
+    pub marshaler: Marshaler,
+    /// Topic layout of the gateway bridge, defaults to the v4 layout for EU868
+    #[serde(default)]
+    pub topic_layout: TopicLayout,
+    /// Clock offset of a gateway in milliseconds above which a warning is logged, clock drift is
+    /// not checked if not set
+    #[serde(default)]
+    pub clock_drift_threshold_ms: Option<u64>,
+    /// MQTT v5 features, the broker is connected with MQTT v3.1.1 if not set
+    #[serde(default)]
+    pub v5: Option<MqttV5Config>,
+}
+
+/// MQTT v5 features of the connection to the broker.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct MqttV5Config {
+    /// Time the broker keeps the session and the subscription after a disconnect in seconds, the
+    /// session ends with the connection if not set.
+    #[serde(default)]
+    pub session_expiry_seconds: Option<u64>,
+    /// Max amount of topic aliases replacing the topics of the downlinks, limited by the broker,
+    /// defaults to 0 which disables topic aliases.
+    #[serde(default)]
+    pub max_topic_aliases: u16,
+    /// User properties attached to every publish, e.g. the node ID, defaults to none.
+    #[serde(default)]
+    pub user_properties: BTreeMap<String, String>,
+}
+
+impl MqttV5Config {
+    /// Returns the MQTT v5 options of the runtime.
+    pub fn runtime_options(&self) -> MqttV5Options {
+        MqttV5Options {
+            session_expiry_interval: self.session_expiry_seconds.map(Duration::from_secs),
+            max_topic_aliases: self.max_topic_aliases,
+            user_properties: self
+                .user_properties
+                .iter()
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect(),
+        }
+    }
 }
 
 /// Daemon configuration
-#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct DaemonConfig {
     /// Bind configuration
     pub bind_config: BindConfig,
@@ -49,6 +665,10 @@ pub struct DaemonConfig {
     ///
     /// Identification number inside the emergency LoRaWAN network
     pub end_device_ids: Vec<String>,
+    /// Group IDs this node is a member of, packets addressed to a group are delivered locally and
+    /// relayed further, defaults to none
+    #[serde(default)]
+    pub group_ids: Vec<String>,
     /// Send configuration
     pub queue_config: QueueConfig,
     /// Configuration of the packet cache
@@ -57,6 +677,483 @@ pub struct DaemonConfig {
     pub routing_algorithm_config: RoutingAlgorithmConfig,
     /// Path to SQLITE database file
     pub db_path: Option<String>,
+    /// Configuration of the periodic local announcements, no announcements are sent if not set
+    #[serde(default)]
+    pub announcement_config: Option<AnnouncementConfig>,
+    /// Persistence of gateway stats snapshots, gateway stats are not persisted if not set
+    #[serde(default)]
+    pub gateway_stats: Option<GatewayStatsConfig>,
+    /// Whether packets are fragmented for the payload sizes allowed with a LoRaWAN repeater,
+    /// defaults to `false`
+    #[serde(default)]
+    pub repeater_compatible: bool,
+    /// Routing algorithms replacing the routing algorithm configured in
+    /// `routing_algorithm_config` for the listed destination classes
+    #[serde(default)]
+    pub scoped_routing_algorithms: Vec<ScopedRoutingAlgorithmConfig>,
+    /// Reaction to database errors during operation, defaults to `ReadOnly`
+    #[serde(default)]
+    pub database_error_policy: DatabaseErrorPolicy,
+    /// Role of the node, defaults to `Full`
+    #[serde(default)]
+    pub node_profile: NodeProfile,
+    /// Plugins receiving the bundles for configured end device IDs, defaults to none
+    #[serde(default)]
+    pub plugins: Vec<PluginConfig>,
+    /// HTTP endpoints received bundles are pushed to, requires the `webhooks` feature, defaults to
+    /// none
+    #[serde(default)]
+    pub webhooks: Vec<WebhookConfig>,
+    /// Send queues per gateway with independent pacing and duty cycle budgets, packets are
+    /// handed to the gateways directly if not set
+    #[serde(default)]
+    pub gateway_send_queues: Option<GatewaySendQueueConfig>,
+    /// Process uplinks the gateway reported with an invalid CRC, e.g. for research, defaults to
+    /// false
+    #[serde(default)]
+    pub process_crc_errors: bool,
+    /// Parking of bundles to unknown destinations until an announcement makes them known,
+    /// bundles are flooded right away if not set
+    #[serde(default)]
+    pub bundle_parking: Option<BundleParkingConfig>,
+    /// Retransmission of downlinks the gateways acknowledged as failed, failed downlinks are
+    /// only counted if not set
+    #[serde(default)]
+    pub downlink_retransmission: Option<DownlinkRetransmissionConfig>,
+    /// Delivery of reassembled bundles past their bp7 lifetime to local services, defaults to
+    /// `Deliver`
+    #[serde(default)]
+    pub late_delivery_policy: LateDeliveryPolicy,
+    /// Persistent ledger of the bundles delivered to local applications suppressing bundles
+    /// arriving again via another path, duplicates are delivered again if not set
+    #[serde(default)]
+    pub delivery_ledger: Option<DeliveryLedgerConfig>,
+    /// Daily airtime quota per API client, the airtime is only accounted if not set
+    #[serde(default)]
+    pub client_airtime_quota: Option<ClientAirtimeQuotaConfig>,
+    /// Daily windows during which packets are neither relayed nor sent, only buffered, defaults
+    /// to none
+    #[serde(default)]
+    pub radio_silence: Vec<RadioSilenceWindow>,
+    /// Global budget of the receive buffers, queues and packet cache, the sizes are only
+    /// accounted if not set
+    #[serde(default)]
+    pub memory_budget: Option<MemoryBudgetConfig>,
+    /// Recording of the incoming uplinks into a trace file or replay of a trace file, neither if
+    /// not set
+    #[serde(default)]
+    pub uplink_trace: Option<UplinkTraceConfig>,
+    /// Transmission features of gateways known in advance, overriding the ones probed from the
+    /// gateway stats, defaults to none
+    #[serde(default)]
+    pub gateway_capabilities: Vec<GatewayCapabilitiesConfig>,
+    /// Split of the duty cycle of every band between the DTN traffic and the traffic of the
+    /// network server, the DTN traffic may use the whole remaining duty cycle if not set
+    #[serde(default)]
+    pub duty_cycle_sharing: Option<DutyCycleSharingConfig>,
+    /// Codecs of the payload profiles applications submit and receive bundles with, defaults to
+    /// none
+    #[serde(default)]
+    pub payload_profiles: Vec<PayloadProfileConfig>,
+    /// Handling of bundles whose payload exceeds the max size of a bundle, they are rejected if
+    /// not set
+    #[serde(default)]
+    pub oversize_bundles: OversizeBundleConfig,
+    /// Directed transmission of bundles to direct neighbors via the gateway they were heard by,
+    /// bundles are always flooded if not set
+    #[serde(default)]
+    pub unicast: Option<UnicastConfig>,
+    /// Directed forwarding of packets via the gateway which received the last status report or
+    /// echo reply of their destination, packets are only sent directed to direct neighbors if not
+    /// set
+    #[serde(default)]
+    pub path_cache: Option<PathCacheConfig>,
+    /// Interoperability with other BP7-over-LoRa implementations by sending bundles as CBOR
+    /// encoded BP7 fragments, bundles are always sent with the custom headers if not set
+    #[serde(default)]
+    pub bp7_interop: Option<Bp7InteropConfig>,
+    /// Persisted distribution of the received frames per spreading factor, bandwidth, frequency
+    /// and hour, not collected if not set
+    #[serde(default)]
+    pub radio_stats: Option<RadioStatsConfig>,
+    /// Bearer tokens authorizing requests to the HTTP API with their roles, the API is open to
+    /// everyone if empty, defaults to none
+    #[serde(default)]
+    pub api_tokens: Vec<ApiTokenConfig>,
+    /// Directory whose files are submitted as bundles, no directory is watched if not set
+    #[serde(default)]
+    pub file_drop: Option<FileDropConfig>,
+    /// Supervision of the heartbeats of the internal tasks, missed heartbeats are only shown in
+    /// the status if not set
+    #[serde(default)]
+    pub watchdog: Option<WatchdogConfig>,
+    /// Beaconing via a directly attached concentrator while no gateway is reachable, requires
+    /// the `beaconing` feature, the node is silent without gateways if not set
+    #[serde(default)]
+    pub beaconing: Option<BeaconingConfig>,
+    /// Logical networks whose packets are processed, distinguished by the network ID prefix of
+    /// the packets, packets of all networks are processed if not set
+    #[serde(default)]
+    pub network_filter: Option<NetworkFilterConfig>,
+    /// Persisted journal of the transmission decisions for the audit of duty cycle violations,
+    /// served at `/api/duty_cycle/journal`, no decisions are recorded if not set
+    #[serde(default)]
+    pub scheduling_journal: Option<SchedulingJournalConfig>,
+    /// Node IDs whose packets are dropped, more nodes can be blacklisted via the API, defaults to
+    /// none
+    #[serde(default)]
+    pub neighbor_blacklist: Vec<EndDeviceId>,
+    /// Automatic trust scores of the nodes sending packets, lowered by excessive traffic and
+    /// malformed packets, nodes are not scored if not set
+    #[serde(default)]
+    pub neighbor_trust: Option<NeighborTrustConfig>,
+    /// Submitted bundles kept for retransmission on request of their destination, requests
+    /// for bundles of this node are ignored if not set
+    #[serde(default)]
+    pub bundle_resend: Option<BundleResendConfig>,
+    /// Export of anonymized per-packet records to rotating files for research, packets are not
+    /// exported if not set
+    #[serde(default)]
+    pub packet_export: Option<PacketExportConfig>,
+    /// Persisted store of the delivered bundles for clients long-polling `/api/bundles/poll`
+    /// instead of holding a WebSocket connection, bundles are not kept for polling if not set
+    #[serde(default)]
+    pub bundle_mailbox: Option<BundleMailboxConfig>,
+}
+
+/// Directory watched for files which are submitted as bundles, e.g. by legacy applications.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct FileDropConfig {
+    /// Watched directory, submitted files are moved to its `done` subdirectory, rejected files
+    /// to its `failed` subdirectory.
+    pub directory: String,
+    /// Interval the directory is scanned for new files in seconds.
+    pub poll_interval_seconds: u64,
+    /// End device ID the bundles are sent from.
+    pub source: String,
+    /// Lifetime of the bundles in seconds, a sidecar file may set another lifetime.
+    pub lifetime_seconds: u64,
+}
+
+/// Supervision of the heartbeats of the uplink processor, the routing task and the packet cache
+/// cleaner.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct WatchdogConfig {
+    /// Interval the heartbeats are checked in seconds.
+    pub check_interval_seconds: u64,
+    /// Intervals a task may miss to check in before it missed its heartbeat.
+    pub missed_beats: u32,
+    /// Reaction to a missed heartbeat, defaults to `Log`.
+    #[serde(default)]
+    pub action: WatchdogAction,
+}
+
+/// Reaction of the watchdog to a missed heartbeat.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum WatchdogAction {
+    /// Log the missed heartbeat.
+    #[default]
+    Log,
+    /// Restart the task, all of Spatz is restarted if the task cannot be restarted on its own.
+    Restart,
+    /// Shut down the instance.
+    Shutdown,
+}
+
+/// Last-resort beaconing via a concentrator attached via SPI or USB, driven by a packet forwarder
+/// speaking the Semtech UDP protocol.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct BeaconingConfig {
+    /// UDP address the packet forwarder sends to, e.g. `127.0.0.1:1700`.
+    pub bind: String,
+    /// Time the ChirpStack API and the MQTT broker have to be unreachable before beaconing in
+    /// seconds.
+    pub activation_seconds: u64,
+    /// Interval the local announcement is beaconed in seconds.
+    pub interval_seconds: u64,
+    /// Frequency of the beacons in Hz.
+    pub frequency: u32,
+    /// Transmission power of the beacons in dBm.
+    pub power: i32,
+}
+
+/// Filter of the received packets by their network ID prefix, letting multiple logical networks
+/// share the gateways.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct NetworkFilterConfig {
+    /// Network IDs whose packets are processed.
+    pub accepted_network_ids: Vec<u32>,
+    /// Whether packets without network ID prefix are processed.
+    pub accept_untagged: bool,
+}
+
+/// Journal of the transmission decisions of the gateway send queues and the duty cycle
+/// collector.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct SchedulingJournalConfig {
+    /// Maximum amount of kept decisions, the oldest decisions are removed first.
+    pub max_entries: usize,
+}
+
+/// Trust scores of the nodes sending packets, between 0 and 100, see
+/// [`neighbor_trust`](crate::neighbor_trust).
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct NeighborTrustConfig {
+    /// Packets a node may send within a minute, every minute exceeding it lowers the score.
+    pub max_packets_per_minute: u32,
+    /// Score deducted for a minute of excessive traffic.
+    pub traffic_penalty: u32,
+    /// Score deducted for a malformed packet.
+    pub malformed_penalty: u32,
+    /// Score regained per hour, up to 100.
+    pub recovery_per_hour: u32,
+    /// Score below which the packets of a node are dropped.
+    pub min_score: u32,
+}
+
+/// Submitted bundles kept for retransmission on request of their destination, see
+/// [`bundle_resend`](crate::bundle_resend).
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct BundleResendConfig {
+    /// Time a bundle is kept after it was queued in seconds.
+    pub window_seconds: u64,
+    /// Maximum amount of kept bundles, the oldest bundles are removed first.
+    pub max_bundles: usize,
+}
+
+/// Delivered bundles kept for polling clients, see [`bundle_mailbox`](crate::bundle_mailbox).
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct BundleMailboxConfig {
+    /// Maximum amount of kept bundles, the oldest bundles are removed first.
+    pub max_bundles: usize,
+    /// Maximum amount of remembered client cursors, the cursors of the least recently polled
+    /// clients are forgotten first.
+    pub max_clients: usize,
+    /// Maximum time a poll waits for a delivered bundle in seconds, polls return immediately if
+    /// `0`.
+    pub max_wait_seconds: u64,
+}
+
+/// Export of anonymized per-packet records, see [`packet_export`](crate::packet_export).
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct PacketExportConfig {
+    /// Directory the files and the schema description are written to.
+    pub directory: String,
+    /// Format of the files, defaults to `Csv`.
+    #[serde(default)]
+    pub format: PacketExportFormat,
+    /// Exported fields in column order, all fields if empty.
+    #[serde(default)]
+    pub fields: Vec<PacketExportField>,
+    /// Interval the buffered records are written in seconds.
+    pub flush_interval_seconds: u64,
+    /// Time after which a new CSV file is started in seconds.
+    pub rotation_seconds: u64,
+    /// Time files are kept in hours, files are kept forever if not set.
+    #[serde(default)]
+    pub retention_hours: Option<u64>,
+    /// Key the end device IDs and gateway IDs are pseudonymized with, a random key per start if
+    /// not set, so the pseudonyms cannot be linked across restarts.
+    #[serde(default)]
+    pub pseudonym_key: Option<String>,
+}
+
+/// Format of the exported packet records.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum PacketExportFormat {
+    /// Comma separated values with a header row, appended to the current file.
+    #[default]
+    Csv,
+    /// Apache Parquet, one file per flush. Requires the `parquet-export` feature.
+    Parquet,
+}
+
+/// Field of an exported packet record.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, JsonSchema)]
+pub enum PacketExportField {
+    /// Time the packet was received or sent.
+    Timestamp,
+    /// Whether the packet was received or sent.
+    Direction,
+    /// Type of the packet.
+    PacketType,
+    /// Size of the phy payload.
+    Size,
+    /// Index of the data rate.
+    DataRate,
+    /// RSSI of a received packet.
+    Rssi,
+    /// SNR of a received packet.
+    Snr,
+    /// Hop distance to the sender of a received packet known from the neighbor table.
+    Hops,
+    /// Time since the creation of the bundle of a bundle packet.
+    LatencyMs,
+    /// Pseudonym of the source or announcing end device ID.
+    Source,
+    /// Pseudonym of the gateway which received the packet.
+    Gateway,
+}
+
+impl PacketExportField {
+    /// All fields in their default column order.
+    pub const ALL: [PacketExportField; 11] = [
+        PacketExportField::Timestamp,
+        PacketExportField::Direction,
+        PacketExportField::PacketType,
+        PacketExportField::Size,
+        PacketExportField::DataRate,
+        PacketExportField::Rssi,
+        PacketExportField::Snr,
+        PacketExportField::Hops,
+        PacketExportField::LatencyMs,
+        PacketExportField::Source,
+        PacketExportField::Gateway,
+    ];
+}
+
+/// Role of an HTTP API token, every role includes the permissions of the lower roles.
+#[derive(
+    Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize, JsonSchema,
+)]
+pub enum ApiRole {
+    /// Reads the status, stats and configuration.
+    ReadOnly,
+    /// Sends bundles and controls the running node, e.g. pauses the relaying.
+    Operator,
+    /// Changes the configuration, manages the end device IDs, restarts the node and flushes the
+    /// packet cache.
+    Admin,
+}
+
+/// Bearer token of the HTTP API
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ApiTokenConfig {
+    /// Name of the token holder, logged with denied requests.
+    pub name: String,
+    /// The secret token sent as `Authorization: Bearer <token>`.
+    pub token: String,
+    /// Role granted to requests with the token.
+    pub role: ApiRole,
+}
+
+/// Role of a node, constrained nodes can dedicate their duty cycle to their role.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum NodeProfile {
+    /// Relays foreign traffic and serves local services.
+    #[default]
+    Full,
+    /// Only relays foreign traffic, the local bundle API is disabled.
+    RelayOnly,
+    /// Only serves local services, foreign traffic is not relayed.
+    EndpointOnly,
+}
+
+impl NodeProfile {
+    /// Returns whether packets of the destination class are routed by nodes with this profile.
+    pub fn routes(self, destination_class: DestinationClass) -> bool {
+        match destination_class {
+            DestinationClass::Relay => self != NodeProfile::EndpointOnly,
+            DestinationClass::Bundle => self != NodeProfile::RelayOnly,
+            DestinationClass::Announcement => true,
+        }
+    }
+
+    /// Returns whether packets received from neighbors for other nodes are relayed.
+    pub fn relays_foreign_traffic(self) -> bool {
+        self.routes(DestinationClass::Relay)
+    }
+
+    /// Returns whether the local bundle API accepts and delivers bundles.
+    pub fn serves_local_bundles(self) -> bool {
+        self.routes(DestinationClass::Bundle)
+    }
+}
+
+/// Plugin receiving the bundles addressed to a set of end device IDs.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct PluginConfig {
+    /// End device IDs whose received bundles are handed to the plugin.
+    pub end_device_ids: Vec<String>,
+    /// The plugin.
+    pub plugin: PluginKind,
+}
+
+/// Kind of a bundle plugin.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum PluginKind {
+    /// External process receiving every bundle as a JSON line on stdin, bundles written as JSON
+    /// lines to stdout are sent.
+    Process {
+        /// Command to start.
+        command: String,
+        /// Arguments of the command.
+        #[serde(default)]
+        args: Vec<String>,
+    },
+    /// WASM module exporting `memory`, `alloc` and `on_bundle`, requires the `wasm-plugins`
+    /// feature.
+    Wasm {
+        /// Path of the module.
+        module_path: String,
+    },
+}
+
+/// HTTP endpoint received bundles and optionally status events are POSTed to.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct WebhookConfig {
+    /// URL of the endpoint.
+    pub url: String,
+    /// Secret the HMAC-SHA256 signature of every request is computed with, requests are not
+    /// signed if not set.
+    #[serde(default)]
+    pub secret: Option<String>,
+    /// Whether changes of the operating mode are POSTed as well.
+    #[serde(default)]
+    pub status_events: bool,
+    /// Retries of a failed delivery before the event is dropped.
+    pub max_retries: u32,
+    /// Delay before the first retry in milliseconds, doubled with every retry.
+    pub initial_backoff_ms: u64,
+}
+
+/// Recording or replay of the raw uplink stream, e.g. for regression tests against field captures.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum UplinkTraceConfig {
+    /// Appends every incoming uplink with its topic and receive time as a JSON line to the file.
+    Record {
+        /// Path of the trace file.
+        path: String,
+    },
+    /// Feeds the uplinks of the trace file through the uplink pipeline once after the start.
+    Replay {
+        /// Path of the trace file.
+        path: String,
+        /// Factor the original gaps between the uplinks are shortened by, `1` replays at the
+        /// original speed.
+        speedup: u32,
+    },
+}
+
+/// Reaction to a failing database during operation.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum DatabaseErrorPolicy {
+    /// Stop persisting and keep relaying, the database is treated as read-only until the next
+    /// restart.
+    #[default]
+    ReadOnly,
+    /// Shut down the instance.
+    Shutdown,
+}
+
+/// Delivery of reassembled bundles past their bp7 lifetime to local services.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum LateDeliveryPolicy {
+    /// Deliver bundles past their lifetime like any other bundle.
+    #[default]
+    Deliver,
+    /// Drop bundles past their lifetime, they are still treated as received.
+    Suppress,
 }
 
 /// Bind configuration
@@ -77,20 +1174,484 @@ pub struct QueueConfig {
     pub bundle_queue_size: usize,
     /// Max amount of queued announcements.
     pub announcement_queue_size: usize,
+    /// Handling of a bundle transfer in progress when another bundle is queued ahead of it,
+    /// defaults to `Preempt`.
+    #[serde(default)]
+    pub preemption: PreemptionPolicy,
+}
+
+/// Handling of a bundle transfer in progress when another bundle is queued ahead of it, e.g. a
+/// bundle of a higher priority or a pinned bundle.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum PreemptionPolicy {
+    /// The bundles ahead are sent first, the transfer resumes at its progress afterwards.
+    #[default]
+    Preempt,
+    /// The packets of the bundles ahead are interleaved with the packets of the transfer, so the
+    /// transfer is slowed down instead of stalled.
+    Interleave {
+        /// Packets of the bundles ahead sent before every packet of the transfer.
+        packets: u32,
+    },
 }
 
 /// Configuration for routing algorithms
-#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub enum RoutingAlgorithmConfig {
     /// Configuration for the flooding routing algorithm
     Flooding(FloodingConfig),
 }
 
+/// Class of the traffic a routing algorithm is responsible for.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, JsonSchema)]
+pub enum DestinationClass {
+    /// Packets received from neighbors to be relayed.
+    Relay,
+    /// Bundles of local services addressed to a single destination.
+    Bundle,
+    /// Local and reachability announcements broadcast to all neighbors.
+    Announcement,
+}
+
+impl DestinationClass {
+    /// All destination classes.
+    pub const ALL: [DestinationClass; 3] = [
+        DestinationClass::Relay,
+        DestinationClass::Bundle,
+        DestinationClass::Announcement,
+    ];
+}
+
+/// Routing algorithm responsible for a subset of the destination classes.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ScopedRoutingAlgorithmConfig {
+    /// Destination classes handled by the routing algorithm, every class can only be assigned
+    /// to a single routing algorithm.
+    pub destination_classes: Vec<DestinationClass>,
+    /// Configuration of the routing algorithm.
+    pub routing_algorithm_config: RoutingAlgorithmConfig,
+}
+
 /// Flooding routing algorithm configuration
-#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct FloodingConfig {
     /// Delay between send attempts in seconds.
     pub periodic_send_delay: u64,
+    /// Slotted TDMA configuration, sends are unslotted if not set.
+    #[serde(default)]
+    pub tdma: Option<TdmaConfig>,
+    /// Selection of the gateways a packet is sent from, sent from every gateway if not set.
+    #[serde(default)]
+    pub gateway_selection: Option<GatewaySelectionConfig>,
+    /// Pacing derived from the airtime of the previous transmission, replaces
+    /// `periodic_send_delay` if set. Not used with TDMA.
+    #[serde(default)]
+    pub airtime_pacing: Option<AirtimePacingConfig>,
+    /// Skips relaying packets received with a signal outside the thresholds, every packet is
+    /// relayed if not set.
+    #[serde(default)]
+    pub relay_signal_policy: Option<RelaySignalPolicy>,
+}
+
+/// Thresholds of the signal a packet was received with for the packet to be relayed.
+///
+/// Packets heard extremely strongly were likely sent from nearby, so the sender shares the
+/// coverage of this node. Packets heard extremely weakly are likely corrupt. Skipping both saves
+/// airtime at the cost of delivery probability.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct RelaySignalPolicy {
+    /// Packets received with a higher RSSI in dBm are not relayed.
+    #[serde(default)]
+    pub max_rssi: Option<i32>,
+    /// Packets received with a lower RSSI in dBm are not relayed.
+    #[serde(default)]
+    pub min_rssi: Option<i32>,
+    /// Packets received with a lower SNR in dB are not relayed.
+    #[serde(default)]
+    pub min_snr: Option<i32>,
+}
+
+impl RelaySignalPolicy {
+    /// Returns whether a packet received with the signal quality is relayed.
+    pub fn relays(&self, signal_quality: SignalQuality) -> bool {
+        if self
+            .max_rssi
+            .is_some_and(|max_rssi| signal_quality.rssi > max_rssi)
+        {
+            return false;
+        }
+        signal_quality.reaches(
+            self.min_rssi.unwrap_or(i32::MIN),
+            self.min_snr.unwrap_or(i32::MIN),
+        )
+    }
+}
+
+/// Pacing of transmissions derived from the airtime of the previous transmission.
+///
+/// The next packet is sent after `max(airtime * airtime_factor, min_gap_ms)`, e.g. an
+/// `airtime_factor` of 99 keeps a single sub-band within a 1% duty cycle.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct AirtimePacingConfig {
+    /// Factor applied to the airtime of the previous transmission.
+    pub airtime_factor: u32,
+    /// Minimum gap between transmissions in milliseconds, also used as polling interval while
+    /// no packets are queued.
+    pub min_gap_ms: u64,
+}
+
+/// Configuration of the send queue of every gateway
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct GatewaySendQueueConfig {
+    /// Max amount of downlinks queued per gateway.
+    pub queue_size: usize,
+    /// Factor applied to the airtime of the previous transmission of the gateway.
+    pub airtime_factor: u32,
+    /// Minimum gap between transmissions of a gateway in milliseconds, also used as polling
+    /// interval of the routing algorithm.
+    pub min_gap_ms: u64,
+}
+
+/// Configuration of the parking of bundles without a known next hop
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct BundleParkingConfig {
+    /// Max amount of parked bundles, further bundles are queued without known next hop.
+    pub max_parked: usize,
+    /// Max time a bundle stays parked before it is dropped.
+    pub max_age_seconds: u64,
+}
+
+/// Configuration of the directed transmission of bundles to direct neighbors
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct UnicastConfig {
+    /// Time to wait for the hop acknowledgement before the packet is flooded.
+    pub ack_timeout_seconds: u64,
+    /// Max age of the announcement of the destination for it to count as direct neighbor.
+    pub max_neighbor_age_seconds: u64,
+    /// Margin in dB above the SNR required by a data rate for it to be used.
+    pub snr_margin_db: i32,
+}
+
+/// Configuration of the path cache learned from status reports and echo replies
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct PathCacheConfig {
+    /// Time a learned path is used after the last status report or echo reply of the destination.
+    pub ttl_seconds: u64,
+    /// Consecutive failed directed transmissions after which a path is invalidated.
+    pub max_failures: u32,
+}
+
+/// Configuration of the interoperability mode sending bundles as CBOR encoded BP7 fragments
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Bp7InteropConfig {
+    /// Whether bundles are only sent as BP7 fragments while all recently announced direct
+    /// neighbors announced the capability, always if disabled.
+    pub negotiate: bool,
+    /// Max age of the announcements of the direct neighbors and their capabilities to be
+    /// considered in the negotiation.
+    pub max_neighbor_age_seconds: u64,
+}
+
+/// Configuration of the ledger of bundles delivered to local applications
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct DeliveryLedgerConfig {
+    /// Time a delivered bundle is remembered.
+    pub retention_seconds: u64,
+    /// Max amount of remembered bundles, the oldest are forgotten first.
+    pub max_entries: usize,
+}
+
+/// Configuration of the airtime quota of API clients
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ClientAirtimeQuotaConfig {
+    /// Airtime a client may consume per day (UTC) in milliseconds, further bundles of the client
+    /// are rejected.
+    pub daily_quota_ms: u64,
+}
+
+/// Daily window of radio silence, e.g. for regulatory or operational reasons
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct RadioSilenceWindow {
+    /// Start of the window in UTC, e.g. `"22:00:00"`.
+    pub start: NaiveTime,
+    /// End of the window in UTC, before `start` if the window spans midnight.
+    pub end: NaiveTime,
+    /// Reason of the radio silence, used in the logs and the status.
+    #[serde(default)]
+    pub reason: String,
+}
+
+/// Configuration of the global memory budget of the buffers
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct MemoryBudgetConfig {
+    /// Max approximate bytes of the receive buffers, queues and packet cache together.
+    pub max_bytes: u64,
+}
+
+/// Transmission features of a gateway, unset features are probed from the gateway stats
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct GatewayCapabilitiesConfig {
+    /// ID of the gateway, in any letter case and with or without separators
+    pub gateway_id: GatewayId,
+    /// Whether the gateway transmits at GPS timestamps as required for Class B timing
+    #[serde(default)]
+    pub class_b_timing: Option<bool>,
+    /// Whether the gateway transmits with 250 kHz bandwidth as required for DR6
+    #[serde(default)]
+    pub bandwidth_250_khz: Option<bool>,
+    /// Boards and antennas every downlink is offered on, the gateway emits it on the first one not
+    /// busy, board 0 and antenna 0 if empty
+    #[serde(default)]
+    pub tx_paths: Vec<TxPath>,
+}
+
+/// Configuration of the split of the duty cycle between the DTN traffic and the network server
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct DutyCycleSharingConfig {
+    /// Share of the duty cycle of every band available to the DTN traffic in percent, the rest
+    /// is kept free for the downlinks of the network server
+    pub dtn_share_percent: u8,
+}
+
+impl DutyCycleSharingConfig {
+    /// Returns the share of the DTN traffic between 0 and 1.
+    pub fn dtn_share(&self) -> f64 {
+        f64::from(self.dtn_share_percent) / 100.0
+    }
+}
+
+/// Codec of a payload profile
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum PayloadCodecKind {
+    /// Payload sent unchanged, e.g. plain text, protobuf or CBOR encoded by the application
+    Raw,
+    /// JSON submitted by the application sent as the more compact CBOR and delivered as JSON
+    JsonCbor,
+    /// Payload compressed with deflate and delivered inflated
+    Deflate,
+}
+
+/// Payload profile, bundles of the profile carry the profile byte in front of the payload
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct PayloadProfileConfig {
+    /// Profile byte in front of the payload, identical on all nodes
+    pub profile: u8,
+    /// Name of the profile, used in the stats
+    pub name: String,
+    /// Codec encoding the payloads of the profile
+    pub codec: PayloadCodecKind,
+}
+
+/// Handling of bundles whose payload exceeds the max size of a bundle
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct OversizeBundleConfig {
+    /// Policy applied unless the routing hints of a bundle select another one, defaults to
+    /// `Reject`
+    #[serde(default)]
+    pub policy: OversizePolicy,
+    /// Payload profile with the `Deflate` codec compressing oversize bundles, required by the
+    /// `Compress` policy
+    #[serde(default)]
+    pub compression_profile: Option<u8>,
+}
+
+/// Handling of a bundle whose payload exceeds the max size of a bundle.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum OversizePolicy {
+    /// Reject the bundle, API clients get a `PAYLOAD_TOO_LARGE` error.
+    #[default]
+    Reject,
+    /// Compress the payload with the compression profile, rejected if still too large.
+    Compress,
+    /// Split the payload into sub-bundles sent as BP7 fragments, reassembled by the
+    /// destination.
+    Split,
+}
+
+/// Configuration of the retransmission of failed downlinks
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct DownlinkRetransmissionConfig {
+    /// Max amount of retransmissions of a phy payload.
+    pub max_retries: u32,
+    /// Backoff before the first retransmission in milliseconds, doubled with every further retry.
+    pub backoff_ms: u64,
+}
+
+/// Policy selecting the gateways a packet is sent from, avoiding duplicate transmissions of
+/// overlapping gateways.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum GatewaySelectionConfig {
+    /// Send from at most `k` gateways, preferring gateways with fewer acknowledged transmissions.
+    Subset {
+        /// Max amount of gateways per transmission.
+        k: usize,
+    },
+    /// Skip gateways closer than `min_distance_meters` to an already selected gateway. Gateways
+    /// without a location reported in their stats are always selected.
+    Coverage {
+        /// Min distance in meters between selected gateways.
+        min_distance_meters: u32,
+    },
+}
+
+/// Slotted TDMA configuration
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct TdmaConfig {
+    /// Length of a single slot in milliseconds.
+    pub slot_length_ms: u64,
+    /// Amount of slots per frame.
+    pub frame_length: u32,
+    /// Node ID used to derive the own slot. Derived from the end device IDs if not set.
+    #[serde(default)]
+    pub node_id: Option<u32>,
+    /// Whether gateways without GPS timing send unslotted instead of delaying until the slot.
+    #[serde(default)]
+    pub fallback_to_unslotted: bool,
+}
+
+/// Local announcement configuration
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct AnnouncementConfig {
+    /// Interval between local announcements in seconds.
+    pub interval_seconds: u64,
+    /// Suppression of announcements already made by stronger neighbors, never suppressed if not
+    /// set.
+    #[serde(default)]
+    pub suppression: Option<AnnouncementSuppressionConfig>,
+    /// Advertisement of end device IDs reachable through this node, nothing is advertised on
+    /// behalf of neighbors if not set.
+    #[serde(default)]
+    pub proxy: Option<AnnouncementProxyConfig>,
+    /// Trimming of announcements while the duty cycle budget is low, always announced in full if
+    /// not set.
+    #[serde(default)]
+    pub trimming: Option<AnnouncementTrimmingConfig>,
+    /// Deferral of announcements while the relay and bundle queues are deep, never deferred if not
+    /// set.
+    #[serde(default)]
+    pub deferral: Option<AnnouncementDeferralConfig>,
+    /// Services offered at the end device IDs, announced alongside the end device IDs, none are
+    /// announced if empty.
+    #[serde(default)]
+    pub services: Vec<AnnouncedServicesConfig>,
+    /// Frequencies in Hz the announcements rotate across, so neighbors listening on other
+    /// channels hear them as well. The frequencies are announced as the channel plan of this
+    /// node. Announcements are sent on the default frequency if empty.
+    #[serde(default)]
+    pub channels: Vec<u32>,
+    /// Indices of the data rates this node prefers to receive with, e.g. `[3, 4, 5, 6]`, announced
+    /// to neighbors sending directed transmissions. Derived from the connected gateways if empty,
+    /// DR0 to DR5 and DR6 if all of them receive 250 kHz.
+    #[serde(default)]
+    pub receive_data_rates: Vec<u8>,
+}
+
+impl AnnouncementConfig {
+    /// Returns the bit set of the configured receive data rates, `None` if none are configured.
+    pub fn receive_data_rates_bits(&self) -> Option<u8> {
+        (!self.receive_data_rates.is_empty()).then(|| {
+            self.receive_data_rates
+                .iter()
+                .filter(|index| **index <= MAX_DATA_RATE_INDEX)
+                .fold(0, |bits, index| bits | (1 << index))
+        })
+    }
+
+    /// Returns the predefined frequencies of the configured channels, unsupported ones are
+    /// skipped.
+    pub fn frequencies(&self) -> Vec<Frequency> {
+        self.channels
+            .iter()
+            .filter_map(|frequency| Frequency::from_hz(*frequency))
+            .collect()
+    }
+}
+
+/// Services offered at an end device ID.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct AnnouncedServicesConfig {
+    /// End device ID offering the services.
+    pub end_device_id: String,
+    /// Application defined service tags, e.g. ports.
+    pub services: Vec<ServiceTag>,
+}
+
+/// Reachability advertisement configuration
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct AnnouncementProxyConfig {
+    /// Maximum hop distance of advertised end device IDs.
+    pub max_hop_distance: u8,
+    /// Maximum age of a neighbor table entry in seconds to be advertised.
+    pub max_age_seconds: u64,
+}
+
+/// Neighbor-aware announcement suppression configuration
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct AnnouncementSuppressionConfig {
+    /// Minimum RSSI in dBm of a neighbor announcement to count as stronger neighbor.
+    pub min_rssi: i32,
+    /// Minimum SNR in dB of a neighbor announcement to count as stronger neighbor.
+    pub min_snr: i32,
+    /// Maximum age of a neighbor announcement in seconds to be considered.
+    pub max_age_seconds: u64,
+    /// Maximum amount of consecutively suppressed announcements before announcing anyway.
+    pub max_consecutive_suppressions: u32,
+}
+
+/// Announcement trimming under duty cycle pressure configuration
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct AnnouncementTrimmingConfig {
+    /// Remaining duty cycle budget in percent below which announcements are trimmed.
+    pub trim_below_percent: u8,
+    /// Remaining duty cycle budget in percent below which announcements are skipped.
+    pub skip_below_percent: u8,
+    /// Maximum amount of end device IDs in trimmed announcements, locally registered ones are
+    /// included first, followed by the most recently seen reachable ones.
+    pub max_end_device_ids: u32,
+    /// Maximum amount of consecutively skipped announcements before announcing trimmed anyway.
+    pub max_consecutive_skips: u32,
+}
+
+impl AnnouncementTrimmingConfig {
+    /// Returns the remaining duty cycle budget below which announcements are trimmed, between 0
+    /// and 1.
+    pub fn trim_below(&self) -> f64 {
+        f64::from(self.trim_below_percent) / 100.0
+    }
+
+    /// Returns the remaining duty cycle budget below which announcements are skipped, between 0
+    /// and 1.
+    pub fn skip_below(&self) -> f64 {
+        f64::from(self.skip_below_percent) / 100.0
+    }
+}
+
+/// Announcement deferral under relay load configuration
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct AnnouncementDeferralConfig {
+    /// Amount of queued relay packets from which announcements are deferred.
+    pub relay_packets_threshold: u32,
+    /// Amount of queued bundles from which announcements are deferred.
+    pub bundles_threshold: u32,
+    /// Maximum time in seconds announcements are deferred beyond their interval before they are
+    /// sent anyway.
+    pub max_deferral_seconds: u64,
+}
+
+/// Gateway stats history configuration
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct GatewayStatsConfig {
+    /// Time in hours gateway stats snapshots are kept.
+    pub retention_hours: u64,
+    /// Maximum amount of kept snapshots per gateway, the oldest snapshots are removed first.
+    pub max_entries_per_gateway: u32,
+}
+
+/// Received frames distribution configuration
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct RadioStatsConfig {
+    /// Time in hours the counts of the received frames are kept.
+    pub retention_hours: u64,
 }
 
 /// Message Cache configuration
@@ -103,6 +1664,42 @@ pub struct PacketCacheConfig {
     /// Whether the timeout is reset if the same packet is seen again while the timeout has not
     /// elapsed.
     pub reset_timeout: bool,
+    /// Timeout and size limit of announcements, the global timeout without limit if not set.
+    #[serde(default)]
+    pub announcements: Option<PacketClassCacheConfig>,
+    /// Timeout and size limit of bundle fragments and hop-to-hop fragments, the global timeout
+    /// without limit if not set.
+    #[serde(default)]
+    pub fragments: Option<PacketClassCacheConfig>,
+    /// Timeout and size limit of complete bundles, the global timeout without limit if not set.
+    #[serde(default)]
+    pub complete_bundles: Option<PacketClassCacheConfig>,
+}
+
+impl PacketCacheConfig {
+    /// Returns the configuration of the packet class, `None` for the global timeout without
+    /// limit.
+    pub fn class_config(&self, class: PacketClass) -> Option<&PacketClassCacheConfig> {
+        match class {
+            PacketClass::Announcement => self.announcements.as_ref(),
+            PacketClass::Fragment => self.fragments.as_ref(),
+            PacketClass::CompleteBundle => self.complete_bundles.as_ref(),
+            PacketClass::Other => None,
+        }
+    }
+}
+
+/// Timeout and size limit of the cached packets of a class.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct PacketClassCacheConfig {
+    /// The timeout for which the same packet of the class is ignored in minutes, the global
+    /// timeout if not set.
+    #[serde(default)]
+    pub timeout_minutes: Option<u32>,
+    /// Maximum amount of cached packets of the class, the packets seen the longest time ago are
+    /// removed beyond. Not limited if not set.
+    #[serde(default)]
+    pub max_entries: Option<usize>,
 }
 
 /// CLI parameters.
@@ -116,4 +1713,33 @@ pub struct CliParameters {
     /// Path to sqlite DB file
     #[clap(long, value_parser, default_value = "sqlite://spatz_db.sqlite")]
     pub db_url: String,
+
+    /// Write a Lua Wireshark dissector of the protocol to the path and exit
+    #[clap(long, value_parser)]
+    pub export_wireshark_dissector: Option<String>,
+
+    /// Measure the delivery to the end device ID via the API of a running node, print the JSON
+    /// report and exit, requires the api feature
+    #[clap(long, value_parser)]
+    pub measure: Option<u32>,
+
+    /// Amount of test bundles of the measurement
+    #[clap(long, value_parser)]
+    pub measure_bundles: Option<u32>,
+
+    /// Payload size of the test bundles in bytes
+    #[clap(long, value_parser)]
+    pub measure_payload_size: Option<usize>,
+
+    /// Time between two test bundles in seconds
+    #[clap(long, value_parser)]
+    pub measure_interval_seconds: Option<u64>,
+
+    /// URL of the API of the running node
+    #[clap(long, value_parser, default_value = "http://127.0.0.1:3000")]
+    pub api_url: String,
+
+    /// Bearer token for the API of the running node
+    #[clap(long, value_parser)]
+    pub api_token: Option<String>,
 }
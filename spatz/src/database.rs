@@ -1,15 +1,47 @@
 //! Methods and enums to interact with the database.
+//!
+//! Without the `database` feature the state is kept in the in-memory store of the `memory`
+//! module instead of SQLite.
 
+#[cfg(not(feature = "database"))]
+mod memory;
+mod migrations;
+
+use crate::configuration::DatabaseErrorPolicy;
 use crate::error::DbError;
+use crate::gateway_stats::GatewayStatsSnapshot;
+use crate::graceful_shutdown::{
+    ShutdownConditions, ShutdownInitiator, ShutdownReason, ShutdownReport,
+};
+use crate::operating_mode::DegradedCondition;
+use crate::send_buffers::SendBufferProgress;
 use crate::AppState;
+use chirpstack_gwb_integration::gateway_id::GatewayId;
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
-use sqlx::SqlitePool;
+#[cfg(feature = "database")]
+use sqlx::sqlite::SqliteConnectOptions;
+#[cfg(feature = "database")]
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use tracing::trace;
+use tracing::{error, info, trace, warn};
+
+#[cfg(not(feature = "database"))]
+pub use memory::{
+    check_database_writable, fetch_from_db, fetch_gateway_stats, insert_gateway_stats,
+    insert_into_db, open_database, remove_expired_gateway_stats, DbPool,
+};
+
+/// Connection pool of the database the state is persisted in.
+#[cfg(feature = "database")]
+pub type DbPool = sqlx::SqlitePool;
 
 /// The key to retrieve data from the database.
-#[derive(sqlx::Type, Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "database", derive(sqlx::Type))]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 #[repr(i32)]
 pub enum DataKey {
     /// Configuration
@@ -22,6 +54,196 @@ pub enum DataKey {
     DutyCycleData = 4,
     /// Packet cache data
     PacketCacheData = 5,
+    /// Last known gateway IDs
+    GatewayIds = 6,
+    /// Bundles delivered to local applications
+    DeliveredBundles = 7,
+    /// Airtime usage of API clients
+    ClientAirtime = 8,
+    /// Report of the last shutdown
+    LastShutdown = 9,
+    /// Paused subsystems
+    SubsystemControl = 10,
+    /// Next downlink ID per gateway
+    DownlinkIdCounters = 11,
+    /// Transmission progress of the queued bundles
+    SendBufferProgress = 12,
+    /// Received frames per hour and modulation
+    RadioStats = 13,
+    /// Locked out frequencies and sub bands
+    FrequencyLockouts = 14,
+    /// Transmission decisions of the scheduling journal
+    SchedulingJournal = 15,
+    /// Blacklist, trust scores and audit trail of the nodes sending packets
+    NeighborTrust = 16,
+    /// Delivered bundles and cursors of the polling clients
+    BundleMailbox = 17,
+}
+
+/// Applies the [`DatabaseErrorPolicy`] to database errors during operation and counts the
+/// outcome of persisted writes.
+#[derive(Debug)]
+pub struct DatabaseHealth {
+    /// Reaction to database errors.
+    policy: DatabaseErrorPolicy,
+    /// Initiator for the shutdown if the policy is [`DatabaseErrorPolicy::Shutdown`].
+    shutdown_initiator: ShutdownInitiator,
+    /// Successful writes.
+    writes: AtomicU64,
+    /// Failed writes.
+    failed_writes: AtomicU64,
+    /// Writes skipped because the database is read-only.
+    skipped_writes: AtomicU64,
+}
+
+/// Snapshot of the [`DatabaseHealth`] counters.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, JsonSchema)]
+pub struct DatabaseStats {
+    /// Reaction to database errors.
+    pub policy: DatabaseErrorPolicy,
+    /// Whether the database is treated as read-only.
+    pub read_only: bool,
+    /// Successful writes.
+    pub writes: u64,
+    /// Failed writes.
+    pub failed_writes: u64,
+    /// Writes skipped because the database is read-only.
+    pub skipped_writes: u64,
+}
+
+impl DatabaseHealth {
+    /// Creates a new [`DatabaseHealth`] with the policy and the initiator used if the policy is
+    /// [`DatabaseErrorPolicy::Shutdown`].
+    pub fn new(policy: DatabaseErrorPolicy, shutdown_initiator: ShutdownInitiator) -> Self {
+        Self {
+            policy,
+            shutdown_initiator,
+            writes: AtomicU64::new(0),
+            failed_writes: AtomicU64::new(0),
+            skipped_writes: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the current counters, `read_only` is provided by the caller as it is part of the
+    /// operating mode.
+    pub fn stats(&self, read_only: bool) -> DatabaseStats {
+        DatabaseStats {
+            policy: self.policy,
+            read_only,
+            writes: self.writes.load(Ordering::Relaxed),
+            failed_writes: self.failed_writes.load(Ordering::Relaxed),
+            skipped_writes: self.skipped_writes.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Returns whether the database is treated as read-only.
+pub async fn is_database_read_only(state: &AppState) -> bool {
+    state
+        .operating_mode
+        .lock()
+        .await
+        .is_active(DegradedCondition::DatabaseReadOnly)
+}
+
+/// Persists data unless the database is read-only and applies the [`DatabaseErrorPolicy`] if
+/// the database fails.
+///
+/// # Error
+///
+/// Returns an error if:
+/// - the database is read-only.
+/// - the database insert returns an error.
+/// - the provided data cannot be serialized.
+pub async fn persist(
+    state: &AppState,
+    data_key: DataKey,
+    data: &impl Serialize,
+) -> Result<(), DbError> {
+    if is_database_read_only(state).await {
+        trace!("Database is read-only, {data_key:?} is not persisted");
+        state
+            .database_health
+            .skipped_writes
+            .fetch_add(1, Ordering::Relaxed);
+        return Err(DbError::ReadOnly);
+    }
+
+    track_write(
+        state,
+        insert_into_db(data_key, data, state.db_pool.clone()).await,
+    )
+    .await
+}
+
+/// Counts the result of a database write and applies the [`DatabaseErrorPolicy`] if the
+/// database failed.
+///
+/// # Error
+///
+/// Returns the error of the write.
+pub async fn track_write(state: &AppState, result: Result<(), DbError>) -> Result<(), DbError> {
+    let health = &state.database_health;
+    match &result {
+        Ok(()) => {
+            health.writes.fetch_add(1, Ordering::Relaxed);
+        }
+        #[cfg(feature = "database")]
+        Err(DbError::Sqlx(err)) => {
+            health.failed_writes.fetch_add(1, Ordering::Relaxed);
+            error!("Database error: {err}");
+            match health.policy {
+                DatabaseErrorPolicy::ReadOnly => {
+                    state
+                        .operating_mode
+                        .lock()
+                        .await
+                        .enter(DegradedCondition::DatabaseReadOnly, err.to_string());
+                }
+                DatabaseErrorPolicy::Shutdown => {
+                    health.shutdown_initiator.initiate_shutdown(
+                        ShutdownReason::new(ShutdownConditions::DatabaseError, module_path!())
+                            .with_error(err),
+                    );
+                }
+            }
+        }
+        Err(DbError::SerdeJson(_) | DbError::UnsupportedSchemaVersion { .. }) => {
+            health.failed_writes.fetch_add(1, Ordering::Relaxed);
+        }
+        Err(DbError::ReadOnly) => {
+            health.skipped_writes.fetch_add(1, Ordering::Relaxed);
+        }
+        #[cfg(not(feature = "database"))]
+        Err(DbError::NotFound(_)) => {}
+    }
+    result
+}
+
+/// Opens the SQLite database and runs the migrations, a read-only database is tolerated.
+///
+/// # Panics
+///
+/// Panics if the database cannot be opened or the migrations fail on a writable database.
+#[cfg(feature = "database")]
+pub async fn open_database(db_url: &str) -> DbPool {
+    let db_pool = DbPool::connect_with(
+        SqliteConnectOptions::from_str(db_url)
+            .expect("Failed to create DB connection from URL")
+            .create_if_missing(true),
+    )
+    .await
+    .expect("Failed to open DB file");
+
+    trace!("Running DB migrations");
+    if let Err(err) = sqlx::migrate!().run(&db_pool).await {
+        assert!(
+            check_database_writable(db_pool.clone()).await.is_err(),
+            "Failed to run DB migrations: {err}"
+        );
+        warn!("Failed to run DB migrations on read-only database: {err}");
+    }
+    db_pool
 }
 
 /// Inserts data into the database.
@@ -31,13 +253,14 @@ pub enum DataKey {
 /// Returns an error if:
 /// - the database insert returns an error.
 /// - the provided data cannot be serialized.
+#[cfg(feature = "database")]
 pub async fn insert_into_db(
     data_key: DataKey,
     data: &impl Serialize,
-    db_pool: SqlitePool,
+    db_pool: DbPool,
 ) -> Result<(), DbError> {
     trace!("Serializing data for database");
-    let data_string = serde_json::to_string(data)?;
+    let data_string = migrations::encode(data_key, data)?;
     trace!("Inserting {data_key:?} into database");
     sqlx::query!("REPLACE INTO DataTable VALUES(?,?)", data_key, data_string)
         .execute(&db_pool)
@@ -53,9 +276,10 @@ pub async fn insert_into_db(
 /// Returns an error if:
 /// - the database query returns an error.
 /// - the returned data cannot be deserialized.
+#[cfg(feature = "database")]
 pub async fn fetch_from_db<T: DeserializeOwned>(
     data_key: DataKey,
-    db_pool: SqlitePool,
+    db_pool: DbPool,
 ) -> Result<T, DbError> {
     trace!("Fetching data from database");
     let config_string = sqlx::query!("SELECT Data FROM DataTable WHERE DataKey=?", data_key)
@@ -63,16 +287,133 @@ pub async fn fetch_from_db<T: DeserializeOwned>(
         .await?;
 
     trace!("Deserializing data from database");
-    Ok(serde_json::from_str(&config_string.Data)?)
+    migrations::decode(data_key, &config_string.Data)
 }
 
-/// Saves the next configuration and message/packet queues to the database.
+/// Checks whether the database accepts writes by running a write statement in a transaction
+/// which is rolled back afterwards.
+///
+/// # Error
+///
+/// Returns an error if the database does not accept writes.
+#[cfg(feature = "database")]
+pub async fn check_database_writable(db_pool: DbPool) -> Result<(), DbError> {
+    trace!("Checking whether the database is writable");
+    let mut transaction = db_pool.begin().await?;
+    sqlx::query!("DELETE FROM DataTable WHERE DataKey IS NULL")
+        .execute(&mut transaction)
+        .await?;
+    transaction.rollback().await?;
+    Ok(())
+}
+
+/// Inserts a gateway stats snapshot into the database.
+///
+/// # Error
+///
+/// Returns an error if:
+/// - the database insert returns an error.
+/// - the provided snapshot cannot be serialized.
+#[cfg(feature = "database")]
+pub async fn insert_gateway_stats(
+    gateway_id: &GatewayId,
+    snapshot: &GatewayStatsSnapshot,
+    db_pool: DbPool,
+) -> Result<(), DbError> {
+    trace!("Serializing gateway stats for database");
+    let stats_string = serde_json::to_string(snapshot)?;
+    let time = snapshot.time.timestamp_millis();
+    trace!("Inserting gateway stats into database");
+    sqlx::query!(
+        "INSERT INTO GatewayStatsTable VALUES(?,?,?)",
+        gateway_id.as_str(),
+        time,
+        stats_string
+    )
+    .execute(&db_pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Fetches the gateway stats snapshots of a gateway received since `since`, oldest first.
+///
+/// # Error
+///
+/// Returns an error if:
+/// - the database query returns an error.
+/// - the returned snapshots cannot be deserialized.
+#[cfg(feature = "database")]
+pub async fn fetch_gateway_stats(
+    gateway_id: &GatewayId,
+    since: DateTime<Utc>,
+    db_pool: DbPool,
+) -> Result<Vec<GatewayStatsSnapshot>, DbError> {
+    trace!("Fetching gateway stats from database");
+    let since = since.timestamp_millis();
+    let rows = sqlx::query!(
+        "SELECT Stats FROM GatewayStatsTable WHERE GatewayId=? AND Time>=? ORDER BY Time",
+        gateway_id.as_str(),
+        since
+    )
+    .fetch_all(&db_pool)
+    .await?;
+
+    trace!("Deserializing gateway stats from database");
+    rows.iter()
+        .map(|row| Ok(serde_json::from_str(&row.Stats)?))
+        .collect()
+}
+
+/// Removes gateway stats snapshots older than `older_than` and the oldest snapshots of every
+/// gateway exceeding `max_entries_per_gateway`.
+///
+/// # Error
+///
+/// Returns an error if the database query returns an error.
+#[cfg(feature = "database")]
+pub async fn remove_expired_gateway_stats(
+    older_than: DateTime<Utc>,
+    max_entries_per_gateway: u32,
+    db_pool: DbPool,
+) -> Result<(), DbError> {
+    let older_than = older_than.timestamp_millis();
+    trace!("Removing expired gateway stats from database");
+    sqlx::query!("DELETE FROM GatewayStatsTable WHERE Time<?", older_than)
+        .execute(&db_pool)
+        .await?;
+
+    trace!("Removing gateway stats exceeding the max entries per gateway from database");
+    sqlx::query!(
+        "DELETE FROM GatewayStatsTable WHERE rowid IN (
+            SELECT rowid FROM (
+                SELECT rowid, ROW_NUMBER() OVER (PARTITION BY GatewayId ORDER BY Time DESC) AS Position
+                FROM GatewayStatsTable
+            ) WHERE Position>?
+        )",
+        max_entries_per_gateway
+    )
+    .execute(&db_pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Saves the next configuration, message/packet queues, delivered bundles, bundle mailbox, client
+/// airtime usage, radio stats, scheduling journal and downlink ID counters to the database.
+///
+/// Nothing is saved if the database is read-only.
 pub async fn save_state_to_db(state: Arc<AppState>) {
+    if is_database_read_only(&state).await {
+        warn!("Database is read-only, state is not saved");
+        return;
+    }
+
     trace!("Writing config to database");
-    if let Err(err) = insert_into_db(
+    if let Err(err) = persist(
+        &state,
         DataKey::Configuration,
         &state.configuration.lock().await.next_configuration,
-        state.db_pool.clone(),
     )
     .await
     {
@@ -80,10 +421,10 @@ pub async fn save_state_to_db(state: Arc<AppState>) {
     }
 
     trace!("Writing relay messages to database");
-    if let Err(err) = insert_into_db(
+    if let Err(err) = persist(
+        &state,
         DataKey::RelayMessages,
         &(*state.queue_manager.relay_packet_queue.lock().await),
-        state.db_pool.clone(),
     )
     .await
     {
@@ -91,10 +432,10 @@ pub async fn save_state_to_db(state: Arc<AppState>) {
     }
 
     trace!("Writing message buffers to database");
-    if let Err(err) = insert_into_db(
+    if let Err(err) = persist(
+        &state,
         DataKey::MessageBuffers,
         &(*state.queue_manager.bundle_send_buffer_queue.lock().await),
-        state.db_pool.clone(),
     )
     .await
     {
@@ -102,10 +443,10 @@ pub async fn save_state_to_db(state: Arc<AppState>) {
     }
 
     trace!("Writing duty cycle data to database");
-    if let Err(err) = insert_into_db(
+    if let Err(err) = persist(
+        &state,
         DataKey::DutyCycleData,
         &state.duty_cycle_manager.lock().await.stats(),
-        state.db_pool.clone(),
     )
     .await
     {
@@ -113,13 +454,138 @@ pub async fn save_state_to_db(state: Arc<AppState>) {
     }
 
     trace!("Writing packet cache data to database");
-    if let Err(err) = insert_into_db(
+    if let Err(err) = persist(
+        &state,
         DataKey::PacketCacheData,
         &state.packet_cache.contents().await,
-        state.db_pool.clone(),
     )
     .await
     {
         trace!("Error writing packet cache data to database: {err}");
     }
+
+    if let Some(delivery_ledger) = &state.delivery_ledger {
+        trace!("Writing delivered bundles to database");
+        if let Err(err) = persist(
+            &state,
+            DataKey::DeliveredBundles,
+            &delivery_ledger.entries(),
+        )
+        .await
+        {
+            trace!("Error writing delivered bundles to database: {err}");
+        }
+    }
+
+    if let Some(bundle_mailbox) = &state.bundle_mailbox {
+        trace!("Writing bundle mailbox to database");
+        if let Err(err) = persist(&state, DataKey::BundleMailbox, &bundle_mailbox.contents()).await
+        {
+            trace!("Error writing bundle mailbox to database: {err}");
+        }
+    }
+
+    trace!("Writing client airtime usage to database");
+    if let Err(err) = persist(
+        &state,
+        DataKey::ClientAirtime,
+        &state.client_airtime.usage().await,
+    )
+    .await
+    {
+        trace!("Error writing client airtime usage to database: {err}");
+    }
+
+    if let Some(radio_stats) = &state.radio_stats {
+        trace!("Writing radio stats to database");
+        if let Err(err) = persist(
+            &state,
+            DataKey::RadioStats,
+            &radio_stats.counts(Utc::now()).await,
+        )
+        .await
+        {
+            trace!("Error writing radio stats to database: {err}");
+        }
+    }
+
+    if let Some(scheduling_journal) = &state.scheduling_journal {
+        trace!("Writing scheduling journal to database");
+        if let Err(err) = persist(
+            &state,
+            DataKey::SchedulingJournal,
+            &scheduling_journal.entries(),
+        )
+        .await
+        {
+            trace!("Error writing scheduling journal to database: {err}");
+        }
+    }
+
+    trace!("Writing neighbor trust to database");
+    if let Err(err) = persist(
+        &state,
+        DataKey::NeighborTrust,
+        &state.neighbor_trust.persisted(),
+    )
+    .await
+    {
+        trace!("Error writing neighbor trust to database: {err}");
+    }
+
+    trace!("Writing downlink ID counters to database");
+    if let Err(err) = persist(
+        &state,
+        DataKey::DownlinkIdCounters,
+        &state.runtime.downlink_id_counters().await,
+    )
+    .await
+    {
+        trace!("Error writing downlink ID counters to database: {err}");
+    }
+}
+
+/// Saves the transmission progress of the queued bundles, so they are resumed after a restart
+/// instead of being sent from the start.
+pub async fn save_send_buffer_progress(state: &AppState, progress: &[SendBufferProgress]) {
+    if let Err(err) = persist(state, DataKey::SendBufferProgress, &progress).await {
+        trace!("Error writing send buffer progress to database: {err}");
+    }
+}
+
+/// Logs the report of the shutdown and saves it to the database, it is served after the next
+/// start.
+///
+/// Written even if the database is read-only, as the report may name the cause of the database
+/// failure.
+pub async fn save_shutdown_report(state: &AppState, reason: ShutdownReason, graceful: bool) {
+    let report = ShutdownReport {
+        time: Utc::now(),
+        reason,
+        graceful,
+    };
+    match report.reason.condition {
+        ShutdownConditions::Restart | ShutdownConditions::Interrupted => {
+            info!(
+                condition = ?report.reason.condition,
+                module = %report.reason.module,
+                graceful,
+                "Shutdown report"
+            );
+        }
+        _ => {
+            error!(
+                condition = ?report.reason.condition,
+                module = %report.reason.module,
+                error_chain = ?report.reason.error_chain,
+                graceful,
+                "Shutdown report"
+            );
+        }
+    }
+
+    trace!("Writing shutdown report to database");
+    if let Err(err) = insert_into_db(DataKey::LastShutdown, &report, state.db_pool.clone()).await {
+        error!("Error writing shutdown report to database: {err}");
+    }
 }
@@ -0,0 +1,162 @@
+//! In-memory store replacing the SQLite database in builds without the `database` feature.
+//!
+//! The store is shared by all starts within the process, so the state survives restarts via the
+//! API but not the exit of the process.
+
+use crate::database::{migrations, DataKey};
+use crate::error::DbError;
+use crate::gateway_stats::GatewayStatsSnapshot;
+use chirpstack_gwb_integration::gateway_id::GatewayId;
+use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, PoisonError};
+use tracing::trace;
+
+/// Store shared by all starts within the process.
+static STORE: OnceLock<DbPool> = OnceLock::new();
+
+/// Persisted data of the in-memory store.
+#[derive(Debug, Default)]
+struct Store {
+    /// Serialized data by key.
+    data: HashMap<DataKey, String>,
+    /// Gateway ID, time and gateway stats snapshot, ordered by insertion.
+    gateway_stats: Vec<(GatewayId, DateTime<Utc>, GatewayStatsSnapshot)>,
+}
+
+/// Handle of the in-memory store, used in place of the connection pool of the database.
+#[derive(Debug, Clone, Default)]
+pub struct DbPool {
+    /// The shared store.
+    store: Arc<Mutex<Store>>,
+}
+
+impl DbPool {
+    /// Locks the store, a poisoned lock is used anyway as the store stays consistent.
+    fn lock(&self) -> MutexGuard<'_, Store> {
+        self.store.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Returns the store shared by all starts within the process, the database URL is ignored.
+#[allow(clippy::unused_async)]
+pub async fn open_database(_db_url: &str) -> DbPool {
+    trace!("Using in-memory store, nothing is persisted beyond the process");
+    STORE.get_or_init(DbPool::default).clone()
+}
+
+/// Inserts data into the store.
+///
+/// # Error
+///
+/// Returns an error if the provided data cannot be serialized.
+#[allow(clippy::unused_async)]
+pub async fn insert_into_db(
+    data_key: DataKey,
+    data: &impl Serialize,
+    db_pool: DbPool,
+) -> Result<(), DbError> {
+    let data_string = migrations::encode(data_key, data)?;
+    trace!("Inserting {data_key:?} into in-memory store");
+    db_pool.lock().data.insert(data_key, data_string);
+    Ok(())
+}
+
+/// Fetches the data from the store.
+///
+/// # Error
+///
+/// Returns an error if:
+/// - nothing is stored for the key.
+/// - the stored data cannot be deserialized.
+#[allow(clippy::unused_async)]
+pub async fn fetch_from_db<T: DeserializeOwned>(
+    data_key: DataKey,
+    db_pool: DbPool,
+) -> Result<T, DbError> {
+    let store = db_pool.lock();
+    let data_string = store
+        .data
+        .get(&data_key)
+        .ok_or(DbError::NotFound(data_key))?;
+    migrations::decode(data_key, data_string)
+}
+
+/// The in-memory store always accepts writes.
+///
+/// # Error
+///
+/// Never returns an error.
+#[allow(clippy::unused_async)]
+pub async fn check_database_writable(_db_pool: DbPool) -> Result<(), DbError> {
+    Ok(())
+}
+
+/// Inserts a gateway stats snapshot into the store.
+///
+/// # Error
+///
+/// Never returns an error.
+#[allow(clippy::unused_async)]
+pub async fn insert_gateway_stats(
+    gateway_id: &GatewayId,
+    snapshot: &GatewayStatsSnapshot,
+    db_pool: DbPool,
+) -> Result<(), DbError> {
+    db_pool
+        .lock()
+        .gateway_stats
+        .push((gateway_id.clone(), snapshot.time, snapshot.clone()));
+    Ok(())
+}
+
+/// Fetches the gateway stats snapshots of a gateway received since `since`, oldest first.
+///
+/// # Error
+///
+/// Never returns an error.
+#[allow(clippy::unused_async)]
+pub async fn fetch_gateway_stats(
+    gateway_id: &GatewayId,
+    since: DateTime<Utc>,
+    db_pool: DbPool,
+) -> Result<Vec<GatewayStatsSnapshot>, DbError> {
+    let mut snapshots: Vec<_> = db_pool
+        .lock()
+        .gateway_stats
+        .iter()
+        .filter(|(id, time, _)| id == gateway_id && *time >= since)
+        .map(|(_, _, snapshot)| snapshot.clone())
+        .collect();
+    snapshots.sort_by_key(|snapshot| snapshot.time);
+    Ok(snapshots)
+}
+
+/// Removes gateway stats snapshots older than `older_than` and the oldest snapshots of every
+/// gateway exceeding `max_entries_per_gateway`.
+///
+/// # Error
+///
+/// Never returns an error.
+#[allow(clippy::unused_async)]
+pub async fn remove_expired_gateway_stats(
+    older_than: DateTime<Utc>,
+    max_entries_per_gateway: u32,
+    db_pool: DbPool,
+) -> Result<(), DbError> {
+    let mut store = db_pool.lock();
+    store
+        .gateway_stats
+        .retain(|(_, time, _)| *time >= older_than);
+    store.gateway_stats.sort_by(|(_, a, _), (_, b, _)| b.cmp(a));
+    let max_entries = usize::try_from(max_entries_per_gateway).unwrap_or(usize::MAX);
+    let mut entries_per_gateway: HashMap<GatewayId, usize> = HashMap::new();
+    store.gateway_stats.retain(|(gateway_id, _, _)| {
+        let entries = entries_per_gateway.entry(gateway_id.clone()).or_default();
+        *entries += 1;
+        *entries <= max_entries
+    });
+    Ok(())
+}
@@ -0,0 +1,256 @@
+//! Schema versions and migrations of the persisted data.
+//!
+//! Every blob is stored as `{"version": <schema version>, "data": <blob>}`. Blobs written before
+//! the versioning are plain JSON and treated as version 0. Before a blob is deserialized, the
+//! migrations of its [`DataKey`] from the stored version up to the current version are applied,
+//! so the persisted state survives updates changing the persisted structs.
+//!
+//! A change of a persisted struct which older blobs do not deserialize into appends a migration
+//! to [`DataKey::migrations`], which increments the schema version of the data key, and a
+//! snapshot of the old format to `tests/persisted_snapshots`.
+
+use crate::database::DataKey;
+use crate::error::DbError;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use tracing::info;
+
+/// Field of the envelope containing the schema version.
+const VERSION_FIELD: &str = "version";
+/// Field of the envelope containing the data.
+const DATA_FIELD: &str = "data";
+
+/// Migration of a blob from one schema version to the next.
+type Migration = fn(Value) -> Result<Value, serde_json::Error>;
+
+/// Blob written with the schema version of its data key.
+#[derive(Debug, Serialize)]
+struct VersionedBlob<'a, T> {
+    /// Schema version of the data.
+    version: u32,
+    /// The persisted data.
+    data: &'a T,
+}
+
+impl DataKey {
+    /// Returns the migrations of the data kind, the migration at index `n` migrates a blob of
+    /// schema version `n` to `n + 1`.
+    fn migrations(self) -> &'static [Migration] {
+        match self {
+            DataKey::Configuration
+            | DataKey::RelayMessages
+            | DataKey::MessageBuffers
+            | DataKey::DutyCycleData
+            | DataKey::PacketCacheData
+            | DataKey::GatewayIds
+            | DataKey::DeliveredBundles
+            | DataKey::ClientAirtime
+            | DataKey::LastShutdown
+            | DataKey::SubsystemControl
+            | DataKey::DownlinkIdCounters
+            | DataKey::SendBufferProgress
+            | DataKey::RadioStats
+            | DataKey::FrequencyLockouts
+            | DataKey::SchedulingJournal
+            | DataKey::NeighborTrust
+            | DataKey::BundleMailbox => &[unversioned],
+        }
+    }
+
+    /// Returns the current schema version of the data kind, the amount of its migrations.
+    pub fn schema_version(self) -> u32 {
+        u32::try_from(self.migrations().len()).unwrap_or(u32::MAX)
+    }
+}
+
+/// Blobs written before the versioning already match schema version 1.
+#[allow(clippy::unnecessary_wraps)]
+fn unversioned(data: Value) -> Result<Value, serde_json::Error> {
+    Ok(data)
+}
+
+/// Serializes the data with the current schema version of the data key.
+///
+/// # Error
+///
+/// Returns an error if the data cannot be serialized.
+pub fn encode(data_key: DataKey, data: &impl Serialize) -> Result<String, DbError> {
+    Ok(serde_json::to_string(&VersionedBlob {
+        version: data_key.schema_version(),
+        data,
+    })?)
+}
+
+/// Deserializes a stored blob after migrating it to the current schema version of the data key.
+///
+/// # Error
+///
+/// Returns an error if:
+/// - the blob was written with a newer schema version.
+/// - a migration fails.
+/// - the migrated data cannot be deserialized.
+pub fn decode<T: DeserializeOwned>(data_key: DataKey, blob: &str) -> Result<T, DbError> {
+    let (version, data) = split_version(serde_json::from_str(blob)?);
+    let data = migrate(data_key, version, data, data_key.migrations())?;
+    Ok(serde_json::from_value(data)?)
+}
+
+/// Splits a stored blob into its schema version and data, blobs without envelope have version 0.
+fn split_version(blob: Value) -> (u32, Value) {
+    match blob {
+        Value::Object(mut envelope) if is_envelope(&envelope) => {
+            let version = envelope
+                .get(VERSION_FIELD)
+                .and_then(Value::as_u64)
+                .map_or(u32::MAX, |version| {
+                    u32::try_from(version).unwrap_or(u32::MAX)
+                });
+            (version, envelope.remove(DATA_FIELD).unwrap_or(Value::Null))
+        }
+        blob => (0, blob),
+    }
+}
+
+/// Returns whether the object is an envelope written by [`encode`].
+fn is_envelope(object: &Map<String, Value>) -> bool {
+    object.len() == 2
+        && object.get(VERSION_FIELD).is_some_and(Value::is_u64)
+        && object.contains_key(DATA_FIELD)
+}
+
+/// Applies the migrations from the version up to the current version.
+fn migrate(
+    data_key: DataKey,
+    version: u32,
+    mut data: Value,
+    migrations: &[Migration],
+) -> Result<Value, DbError> {
+    let current_version = u32::try_from(migrations.len()).unwrap_or(u32::MAX);
+    let pending = usize::try_from(version)
+        .ok()
+        .and_then(|version| migrations.get(version..))
+        .ok_or(DbError::UnsupportedSchemaVersion {
+            data_key,
+            version,
+            supported: current_version,
+        })?;
+    if !pending.is_empty() {
+        info!("Migrating {data_key:?} from schema version {version} to {current_version}");
+    }
+    for migration in pending {
+        data = migration(data)?;
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use crate::database::migrations::{decode, encode, migrate, Migration};
+    use crate::database::DataKey;
+    use crate::duty_cycle_manager::PerGatewayDutyCycleManager;
+    use crate::end_device_id::EndDeviceId;
+    use crate::error::DbError;
+    use crate::send_buffers::BundleSendBuffer;
+    use chirpstack_gwb_integration::gateway_id::GatewayId;
+    use chrono::{DateTime, Utc};
+    use serde_json::{json, Value};
+    use std::collections::HashMap;
+    use std::path::Path;
+
+    /// Loads a snapshot from `tests/persisted_snapshots`.
+    fn load_snapshot(name: &str) -> String {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/persisted_snapshots")
+            .join(name);
+        std::fs::read_to_string(&path)
+            .unwrap_or_else(|err| panic!("Failed to read {}: {err}", path.display()))
+    }
+
+    #[test]
+    fn unversioned_snapshots_load() {
+        let message_buffers: Vec<BundleSendBuffer> = decode(
+            DataKey::MessageBuffers,
+            &load_snapshot("v0/message_buffers.json"),
+        )
+        .unwrap();
+        assert_eq!(message_buffers.len(), 1);
+        assert_eq!(message_buffers[0].destination(), EndDeviceId(0x1122_3344));
+        assert_eq!(message_buffers[0].payload_len(), 4);
+
+        let duty_cycle_data: HashMap<GatewayId, PerGatewayDutyCycleManager> = decode(
+            DataKey::DutyCycleData,
+            &load_snapshot("v0/duty_cycle_data.json"),
+        )
+        .unwrap();
+        assert!(duty_cycle_data.contains_key("0016c001ff10a235"));
+
+        let packet_cache_data: HashMap<String, DateTime<Utc>> = decode(
+            DataKey::PacketCacheData,
+            &load_snapshot("v0/packet_cache_data.json"),
+        )
+        .unwrap();
+        assert_eq!(packet_cache_data.len(), 2);
+    }
+
+    #[test]
+    fn versioned_snapshots_load() {
+        let counters: HashMap<GatewayId, u32> = decode(
+            DataKey::DownlinkIdCounters,
+            &load_snapshot("v1/downlink_id_counters.json"),
+        )
+        .unwrap();
+        assert_eq!(counters.get("0016c001ff10a235"), Some(&42));
+    }
+
+    #[test]
+    fn encoded_data_is_versioned_and_decodes() {
+        let counters = HashMap::from([("gateway".to_owned(), 7_u32)]);
+        let blob = encode(DataKey::DownlinkIdCounters, &counters).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Value>(&blob).unwrap(),
+            json!({"version": DataKey::DownlinkIdCounters.schema_version(), "data": {"gateway": 7}})
+        );
+        let decoded: HashMap<String, u32> = decode(DataKey::DownlinkIdCounters, &blob).unwrap();
+        assert_eq!(decoded, counters);
+    }
+
+    #[test]
+    fn newer_schema_versions_are_rejected() {
+        let blob = json!({"version": 1000, "data": {}}).to_string();
+        assert!(matches!(
+            decode::<HashMap<String, u32>>(DataKey::DownlinkIdCounters, &blob),
+            Err(DbError::UnsupportedSchemaVersion { version: 1000, .. })
+        ));
+    }
+
+    #[test]
+    fn migrations_are_applied_in_order() {
+        let migrations: [Migration; 2] = [
+            |data| Ok(json!({ "values": data })),
+            |mut data| {
+                data["count"] = json!(data["values"].as_array().map_or(0, Vec::len));
+                Ok(data)
+            },
+        ];
+        assert_eq!(
+            migrate(DataKey::ClientAirtime, 0, json!([1, 2]), &migrations).unwrap(),
+            json!({"values": [1, 2], "count": 2})
+        );
+        assert_eq!(
+            migrate(
+                DataKey::ClientAirtime,
+                1,
+                json!({"values": [1]}),
+                &migrations
+            )
+            .unwrap(),
+            json!({"values": [1], "count": 1})
+        );
+        assert_eq!(
+            migrate(DataKey::ClientAirtime, 2, json!({}), &migrations).unwrap(),
+            json!({})
+        );
+    }
+}
@@ -0,0 +1,151 @@
+//! Ledger of the bundles delivered to local applications, suppressing bundles arriving again via
+//! another path after they expired from the packet cache.
+
+use crate::configuration::DeliveryLedgerConfig;
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::Serialize;
+use sha3::{Digest, Sha3_256};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+/// Counters of the delivery ledger.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, JsonSchema)]
+pub struct DeliveryLedgerStats {
+    /// Delivered bundles currently remembered.
+    pub entries: usize,
+    /// Bundles recorded as delivered.
+    pub recorded: u64,
+    /// Bundles not delivered as they were already delivered within the retention.
+    pub suppressed_duplicates: u64,
+}
+
+/// Remembers the hash of the primary block of every delivered bundle for the configured
+/// retention.
+///
+/// Locked with a std mutex as the receive buffers deliver bundles outside an async context.
+#[derive(Debug)]
+pub struct DeliveryLedger {
+    /// Time a delivered bundle is remembered.
+    retention: chrono::Duration,
+    /// Max amount of remembered bundles, the oldest are forgotten first.
+    max_entries: usize,
+    /// Delivery time by hex encoded SHA-256 of the primary block.
+    delivered: Mutex<HashMap<String, DateTime<Utc>>>,
+    /// Bundles recorded as delivered.
+    recorded: AtomicU64,
+    /// Bundles not delivered as they were already delivered.
+    suppressed_duplicates: AtomicU64,
+}
+
+impl DeliveryLedger {
+    /// Creates a new [`DeliveryLedger`] with the delivered bundles loaded from the database.
+    pub fn new(config: &DeliveryLedgerConfig, delivered: HashMap<String, DateTime<Utc>>) -> Self {
+        Self {
+            retention: chrono::Duration::from_std(Duration::from_secs(config.retention_seconds))
+                .unwrap_or(chrono::Duration::MAX),
+            max_entries: config.max_entries,
+            delivered: Mutex::new(delivered),
+            recorded: AtomicU64::new(0),
+            suppressed_duplicates: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the ledger key of the bundle, the hex encoded SHA3-256 of its CBOR encoded primary
+    /// block.
+    pub fn key(bundle: &bp7::Bundle) -> Option<String> {
+        let primary = serde_cbor::to_vec(&bundle.primary).ok()?;
+        Some(hex::encode(Sha3_256::digest(primary)))
+    }
+
+    /// Returns whether the bundle was already delivered within the retention and counts it as
+    /// suppressed duplicate if so.
+    pub fn is_duplicate(&self, key: &str, now: DateTime<Utc>) -> bool {
+        let delivered = self.lock();
+        let duplicate = delivered
+            .get(key)
+            .is_some_and(|delivered_at| now - *delivered_at < self.retention);
+        if duplicate {
+            self.suppressed_duplicates.fetch_add(1, Ordering::Relaxed);
+        }
+        duplicate
+    }
+
+    /// Records the bundle as delivered and forgets the bundles delivered before the retention or
+    /// exceeding the max amount of entries.
+    pub fn record(&self, key: String, now: DateTime<Utc>) {
+        let mut delivered = self.lock();
+        delivered.retain(|_, delivered_at| now - *delivered_at < self.retention);
+        while delivered.len() >= self.max_entries {
+            let Some(oldest) = delivered
+                .iter()
+                .min_by_key(|(_, delivered_at)| **delivered_at)
+                .map(|(key, _)| key.clone())
+            else {
+                break;
+            };
+            delivered.remove(&oldest);
+        }
+        delivered.insert(key, now);
+        self.recorded.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the remembered bundles to persist them.
+    pub fn entries(&self) -> HashMap<String, DateTime<Utc>> {
+        self.lock().clone()
+    }
+
+    /// Returns the current counters.
+    pub fn stats(&self) -> DeliveryLedgerStats {
+        DeliveryLedgerStats {
+            entries: self.lock().len(),
+            recorded: self.recorded.load(Ordering::Relaxed),
+            suppressed_duplicates: self.suppressed_duplicates.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Locks the remembered bundles, a poisoned lock is still used as the entries stay valid.
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, DateTime<Utc>>> {
+        self.delivered
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use crate::configuration::DeliveryLedgerConfig;
+    use crate::delivery_ledger::DeliveryLedger;
+    use chrono::Utc;
+    use std::collections::HashMap;
+
+    #[test]
+    fn duplicates_suppressed_within_retention() {
+        let ledger = DeliveryLedger::new(
+            &DeliveryLedgerConfig {
+                retention_seconds: 60,
+                max_entries: 2,
+            },
+            HashMap::new(),
+        );
+        let now = Utc::now();
+        assert!(!ledger.is_duplicate("a", now));
+        ledger.record("a".to_owned(), now);
+        assert!(ledger.is_duplicate("a", now + chrono::Duration::seconds(30)));
+        assert!(!ledger.is_duplicate("a", now + chrono::Duration::seconds(90)));
+
+        ledger.record("b".to_owned(), now + chrono::Duration::seconds(1));
+        ledger.record("c".to_owned(), now + chrono::Duration::seconds(2));
+        assert!(!ledger.is_duplicate("a", now + chrono::Duration::seconds(3)));
+        assert!(ledger.is_duplicate("b", now + chrono::Duration::seconds(3)));
+
+        let stats = ledger.stats();
+        assert_eq!(
+            (stats.entries, stats.recorded, stats.suppressed_duplicates),
+            (2, 3, 2)
+        );
+    }
+}
@@ -0,0 +1,289 @@
+//! DTN layer diagnostics between Spatz nodes: ping and traceroute based on echo packets.
+
+use crate::end_device_id::{EndDeviceId, ManagedEndDeviceId};
+use crate::error::DiagnosticsError;
+use crate::packet_cache::PacketSource;
+use crate::AppState;
+use chirpstack_gwb_integration::downlinks::predefined_parameters::DataRate;
+use lorawan_dtn_protocol::{EchoReply, EchoRequest, LoRaWanPacket};
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio::sync::{oneshot, Mutex};
+use tracing::trace;
+
+/// Data rate echo requests are sent with, matches the data rate used by the routing algorithm.
+const ECHO_DATA_RATE: DataRate = DataRate::Eu863_870Dr3;
+
+/// Time a relayed echo request is remembered to not relay it again with a different hop count.
+const RELAYED_ECHO_REQUEST_MAX_AGE: Duration = Duration::from_secs(600);
+
+/// Result of a single echo request.
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct PingResult {
+    /// End device ID of the node which replied.
+    pub responder: EndDeviceId,
+    /// Whether the destination replied, `false` if a relay replied because the hop limit was
+    /// reached.
+    pub reached: bool,
+    /// Amount of relays the echo request passed.
+    pub hop_count: u8,
+    /// Round trip time in milliseconds.
+    pub rtt_ms: u64,
+}
+
+/// Result of a traceroute step with a fixed hop limit.
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct TracerouteHop {
+    /// Hop limit of the echo request.
+    pub hop_limit: u8,
+    /// The reply, `None` if no reply was received in time.
+    pub reply: Option<PingResult>,
+}
+
+/// Echo requests awaiting their reply.
+#[derive(Debug, Default)]
+struct PendingEchoRequests {
+    /// Sequence number of the next echo request.
+    next_sequence: u16,
+    /// Reply channels by sequence number.
+    pending: HashMap<u16, oneshot::Sender<EchoReply>>,
+}
+
+/// Keeps track of sent echo requests and matches incoming echo replies.
+#[derive(Debug, Default)]
+pub struct Diagnostics {
+    /// Echo requests awaiting their reply.
+    pending_echo_requests: Mutex<PendingEchoRequests>,
+    /// Time the echo requests of other nodes were first seen, by source and sequence number.
+    ///
+    /// Relays rewrite the hop count, so the packet cache does not recognize a request coming back.
+    relayed_echo_requests: Mutex<HashMap<(EndDeviceId, u16), Instant>>,
+}
+
+impl Diagnostics {
+    /// Creates a new [`Diagnostics`] without pending echo requests.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Hands a received echo reply to the waiting request, replies without a waiting request are
+    /// dropped.
+    pub async fn process_echo_reply(&self, echo_reply: EchoReply) {
+        let sender = self
+            .pending_echo_requests
+            .lock()
+            .await
+            .pending
+            .remove(&echo_reply.sequence);
+        if let Some(sender) = sender {
+            let _ = sender.send(echo_reply);
+        } else {
+            trace!("Dropping echo reply without pending request");
+        }
+    }
+
+    /// Remembers an echo request of another node, returns whether it was not seen before and is
+    /// to be relayed or answered.
+    pub async fn first_seen(&self, echo_request: &EchoRequest) -> bool {
+        let now = Instant::now();
+        let mut relayed_lock = self.relayed_echo_requests.lock().await;
+        relayed_lock
+            .retain(|_, first_seen| now.duration_since(*first_seen) < RELAYED_ECHO_REQUEST_MAX_AGE);
+        match relayed_lock.entry((echo_request.source, echo_request.sequence)) {
+            Entry::Occupied(_) => false,
+            Entry::Vacant(entry) => {
+                entry.insert(now);
+                true
+            }
+        }
+    }
+
+    /// Registers a new echo request, returns its sequence number and the receiver of the reply.
+    async fn register(&self) -> (u16, oneshot::Receiver<EchoReply>) {
+        let mut pending_lock = self.pending_echo_requests.lock().await;
+        let sequence = pending_lock.next_sequence;
+        pending_lock.next_sequence = sequence.wrapping_add(1);
+        let (reply_tx, reply_rx) = oneshot::channel();
+        pending_lock.pending.insert(sequence, reply_tx);
+        (sequence, reply_rx)
+    }
+
+    /// Removes an echo request which will not be awaited anymore.
+    async fn unregister(&self, sequence: u16) {
+        self.pending_echo_requests
+            .lock()
+            .await
+            .pending
+            .remove(&sequence);
+    }
+}
+
+/// Returns the end device ID identifying this node, the managed end device ID with the lowest
+/// hash.
+///
+/// Used as source of diagnostic packets and as sender of announcements.
+pub async fn local_end_device_id(state: &AppState) -> Option<EndDeviceId> {
+    state
+        .end_device_ids
+        .lock()
+        .await
+        .iter()
+        .map(ManagedEndDeviceId::hash)
+        .min()
+        .map(EndDeviceId)
+}
+
+/// Returns all managed end device IDs ordered by hash, the first one is the
+/// [`local_end_device_id`] identifying this node.
+pub async fn local_end_device_ids(state: &AppState) -> Vec<EndDeviceId> {
+    let mut end_device_ids: Vec<EndDeviceId> = state
+        .end_device_ids
+        .lock()
+        .await
+        .iter()
+        .map(ManagedEndDeviceId::hash)
+        .map(EndDeviceId)
+        .collect();
+    end_device_ids.sort_unstable_by_key(|end_device_id| end_device_id.0);
+    end_device_ids
+}
+
+/// Enqueues an echo reply and adds it to the packet cache to not relay it again.
+pub async fn send_echo_reply(state: &AppState, echo_reply: EchoReply) {
+    trace!("Sending echo reply: {echo_reply:?}");
+    let phy_payload = echo_reply.convert_to_lorawan_phy_payload();
+    if state
+        .queue_manager
+        .enqueue_relay_packet(Box::new(echo_reply), ECHO_DATA_RATE)
+        .await
+    {
+        let _ = state
+            .packet_cache
+            .insert(&phy_payload, PacketSource::Local)
+            .await;
+    }
+}
+
+/// Sends an echo request to `destination` and waits for the reply.
+///
+/// Relays answer the request themselves once it passed `hop_limit` relays.
+///
+/// # Errors
+///
+/// Returns an error if no end device ID is managed by this node, the relay queue is full or no
+/// reply is received within `timeout`.
+pub async fn ping(
+    state: &AppState,
+    destination: EndDeviceId,
+    hop_limit: u8,
+    timeout: Duration,
+) -> Result<PingResult, DiagnosticsError> {
+    let source = local_end_device_id(state)
+        .await
+        .ok_or(DiagnosticsError::NoLocalEndDeviceId)?;
+    let (sequence, reply_rx) = state.diagnostics.register().await;
+    let echo_request = EchoRequest {
+        destination,
+        source,
+        sequence,
+        hop_count: 0,
+        hop_limit,
+    };
+    trace!("Sending echo request: {echo_request:?}");
+    let phy_payload = echo_request.convert_to_lorawan_phy_payload();
+    let start = Instant::now();
+    if !state
+        .queue_manager
+        .enqueue_relay_packet(Box::new(echo_request), ECHO_DATA_RATE)
+        .await
+    {
+        state.diagnostics.unregister(sequence).await;
+        return Err(DiagnosticsError::QueueFull);
+    }
+    // Do not relay the own echo request once it is received from a neighbor.
+    let _ = state
+        .packet_cache
+        .insert(&phy_payload, PacketSource::Local)
+        .await;
+
+    match tokio::time::timeout(timeout, reply_rx).await {
+        Ok(Ok(echo_reply)) => Ok(PingResult {
+            responder: echo_reply.source,
+            reached: !echo_reply.hop_limit_reached,
+            hop_count: echo_reply.hop_count,
+            rtt_ms: u64::try_from(start.elapsed().as_millis()).unwrap_or(u64::MAX),
+        }),
+        _ => {
+            state.diagnostics.unregister(sequence).await;
+            Err(DiagnosticsError::Timeout)
+        }
+    }
+}
+
+/// Sends echo requests with increasing hop limits until the destination replies or `max_hops`
+/// is reached, listing the replying relays along the path.
+///
+/// # Errors
+///
+/// Returns an error if no end device ID is managed by this node or the relay queue is full.
+/// Missing replies are reported per hop.
+pub async fn traceroute(
+    state: &AppState,
+    destination: EndDeviceId,
+    max_hops: u8,
+    timeout: Duration,
+) -> Result<Vec<TracerouteHop>, DiagnosticsError> {
+    let mut hops = Vec::new();
+    for hop_limit in 1..=max_hops {
+        let reply = match ping(state, destination, hop_limit, timeout).await {
+            Ok(ping_result) => Some(ping_result),
+            Err(DiagnosticsError::Timeout) => None,
+            Err(err) => return Err(err),
+        };
+        let reached = reply.as_ref().is_some_and(|reply| reply.reached);
+        hops.push(TracerouteHop { hop_limit, reply });
+        if reached {
+            break;
+        }
+    }
+    Ok(hops)
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use crate::diagnostics::Diagnostics;
+    use crate::end_device_id::EndDeviceId;
+    use lorawan_dtn_protocol::{EchoReply, EchoRequest};
+
+    #[tokio::test]
+    async fn echo_reply_matched_by_sequence() {
+        let diagnostics = Diagnostics::new();
+        let (first_sequence, _first_rx) = diagnostics.register().await;
+        let (second_sequence, second_rx) = diagnostics.register().await;
+        assert_ne!(first_sequence, second_sequence);
+
+        let echo_request = EchoRequest {
+            destination: EndDeviceId(0x1234),
+            source: EndDeviceId(0x5678),
+            sequence: second_sequence,
+            hop_count: 2,
+            hop_limit: 16,
+        };
+        let echo_reply = EchoReply::answer(&echo_request, EndDeviceId(0x1234), false);
+        diagnostics.process_echo_reply(echo_reply.clone()).await;
+        assert_eq!(second_rx.await.unwrap(), echo_reply);
+
+        // A second reply to the same request is dropped.
+        diagnostics.process_echo_reply(echo_reply).await;
+        assert!(diagnostics
+            .pending_echo_requests
+            .lock()
+            .await
+            .pending
+            .contains_key(&first_sequence));
+    }
+}
@@ -1,15 +1,22 @@
 //! Collection and management of duty cycle information.
 
 mod airtime_calculator;
+mod regulatory_policy;
 
+use crate::clock::SharedClock;
 use crate::error::{ConsumeDutyCycleTimeError, SubBandCreationError};
+use crate::frequency_lockouts::FrequencyLockouts;
 use crate::graceful_shutdown::ShutdownAgent;
+use crate::scheduling_journal::{packet_hash, JournalEntry, SchedulingDecision};
 use crate::AppState;
-pub use airtime_calculator::calc_max_downlink_airtime;
+pub use airtime_calculator::{calc_downlink_airtime, calc_max_downlink_airtime};
 use async_trait::async_trait;
 use chirpstack_api::gw::DownlinkFrame;
+use chirpstack_gwb_integration::gateway_id::GatewayId;
 use chirpstack_gwb_integration::runtime::callbacks::CommandDownCallback;
-use chrono::Utc;
+use chrono::{DateTime, Utc};
+use lorawan_dtn_protocol::is_protocol_phy_payload;
+pub use regulatory_policy::{EuDutyCycle, RegulatoryPolicy};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::collections::hash_map::Entry;
@@ -23,13 +30,13 @@ use tracing::{error, instrument};
 #[derive(Debug)]
 pub struct DownlinkCallback {
     /// Channel to send the gateway ID and the downlink frame.
-    pub downlink_callback_tx: mpsc::Sender<(String, DownlinkFrame)>,
+    pub downlink_callback_tx: mpsc::Sender<(GatewayId, DownlinkFrame)>,
 }
 
 #[async_trait]
 impl CommandDownCallback for DownlinkCallback {
     /// Send observed downlink commands via the channel in the [`DownlinkCallback`] struct.
-    async fn dispatch_down_command(&self, gateway_id: String, downlink_command: DownlinkFrame) {
+    async fn dispatch_down_command(&self, gateway_id: GatewayId, downlink_command: DownlinkFrame) {
         trace!("Dispatch down command called");
         if let Err(err) = self
             .downlink_callback_tx
@@ -40,9 +47,34 @@ impl CommandDownCallback for DownlinkCallback {
     }
 }
 
+/// Originator of downlinks sharing the duty cycle of a gateway.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum DutyCycleTenant {
+    /// Downlinks of the custom LoRaWAN protocol sent by spatz.
+    Dtn,
+    /// Regular LoRaWAN downlinks sent by the ChirpStack network server.
+    NetworkServer,
+}
+
+impl DutyCycleTenant {
+    /// Returns the tenant of an observed downlink, told apart by the MHDR of its first item.
+    pub fn of(downlink: &DownlinkFrame) -> Self {
+        if downlink
+            .items
+            .first()
+            .is_some_and(|item| is_protocol_phy_payload(&item.phy_payload))
+        {
+            DutyCycleTenant::Dtn
+        } else {
+            DutyCycleTenant::NetworkServer
+        }
+    }
+}
+
+/// Task accounting the airtime of all observed downlinks to the tenant sending them.
 #[instrument(skip_all)]
 pub async fn downlink_duty_cycle_collector_task(
-    mut downlink_rx: mpsc::Receiver<(String, DownlinkFrame)>,
+    mut downlink_rx: mpsc::Receiver<(GatewayId, DownlinkFrame)>,
     state: Arc<AppState>,
     mut shutdown_agent: ShutdownAgent,
 ) {
@@ -58,6 +90,13 @@ pub async fn downlink_duty_cycle_collector_task(
 
         if let Some((gateway_id, downlink)) = downlink {
             trace!("Received downlink for gateway \"{gateway_id}\"");
+            let tenant = DutyCycleTenant::of(&downlink);
+            let downlink_id = downlink.downlink_id;
+            let packet_hash = downlink
+                .items
+                .first()
+                .map(|item| packet_hash(&item.phy_payload))
+                .unwrap_or_default();
             let (freq, airtime) = match calc_max_downlink_airtime(downlink) {
                 Ok(airtime) => airtime,
                 Err(err) => {
@@ -65,17 +104,42 @@ pub async fn downlink_duty_cycle_collector_task(
                     continue;
                 }
             };
-            trace!("Max airtime for downlink on frequency {freq}: {airtime}");
-
-            {
-                if let Err(err) = state
-                    .duty_cycle_manager
-                    .lock()
-                    .await
-                    .consume_capacity(airtime, freq, gateway_id)
-                {
-                    error!(%err);
-                }
+            trace!("Max airtime for {tenant:?} downlink on frequency {freq}: {airtime}");
+
+            let mut duty_cycle_manager = state.duty_cycle_manager.lock().await;
+            let remaining_before_ms = duty_cycle_manager
+                .remaining_capacity(freq, &gateway_id)
+                .ok()
+                .flatten();
+            let result =
+                duty_cycle_manager.consume_capacity(airtime, freq, gateway_id.clone(), tenant);
+            if let Err(err) = &result {
+                error!(%err);
+            }
+            if let Some(scheduling_journal) = &state.scheduling_journal {
+                scheduling_journal.record(JournalEntry {
+                    time: state.clock.now(),
+                    band: duty_cycle_manager.band(freq).ok(),
+                    remaining_after_ms: duty_cycle_manager
+                        .remaining_capacity(freq, &gateway_id)
+                        .ok()
+                        .flatten(),
+                    gateway_id,
+                    frequency: freq,
+                    airtime_ms: airtime,
+                    remaining_before_ms,
+                    tenant,
+                    decision: match result {
+                        Ok(()) => SchedulingDecision::Accounted,
+                        Err(ConsumeDutyCycleTimeError::CapacityOverused) => {
+                            SchedulingDecision::Overused
+                        }
+                        Err(ConsumeDutyCycleTimeError::SubBand(_)) => SchedulingDecision::OutOfBand,
+                    },
+                    downlink_id,
+                    packet_hash,
+                    reason: result.err().map(|err| err.to_string()),
+                });
             }
         }
     }
@@ -94,7 +158,34 @@ pub enum EuSubBand {
     Sb869700_870000,
 }
 
+/// All sub bands of the EU 868MHz to 870MHz band.
+const EU_SUB_BANDS: [EuSubBand; 6] = [
+    EuSubBand::Sb863000_865000,
+    EuSubBand::Sb865000_868000,
+    EuSubBand::Sb868000_868600,
+    EuSubBand::Sb868700_869200,
+    EuSubBand::Sb869400_869650,
+    EuSubBand::Sb869700_870000,
+];
+
 impl EuSubBand {
+    /// Returns the name of the sub band, as used in the persisted duty cycle data.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EuSubBand::Sb863000_865000 => "Sb863000_865000",
+            EuSubBand::Sb865000_868000 => "Sb865000_868000",
+            EuSubBand::Sb868000_868600 => "Sb868000_868600",
+            EuSubBand::Sb868700_869200 => "Sb868700_869200",
+            EuSubBand::Sb869400_869650 => "Sb869400_869650",
+            EuSubBand::Sb869700_870000 => "Sb869700_870000",
+        }
+    }
+
+    /// Returns the sub band with the name, `None` if there is none.
+    pub fn from_name(name: &str) -> Option<Self> {
+        EU_SUB_BANDS.into_iter().find(|band| band.as_str() == name)
+    }
+
     /// Duty cycle limitations according to "ETSI EN 300 220-2 V3.2.1 (2018-06)" page 21.
     /// <https://www.etsi.org/deliver/etsi_en/300200_300299/30022002/03.02.01_60/en_30022002v030201p.pdf>
     #[allow(clippy::match_same_arms)]
@@ -129,67 +220,160 @@ impl EuSubBand {
 
 /// Collects and manages duty cycle information for all gateways.
 ///
-/// Keeps track of the amount of time already used for every sub band for every gateway.
+/// Keeps track of the amount of time already used for every band for every gateway, the bands
+/// and their limits are defined by the [`RegulatoryPolicy`]. The airtime of the network server
+/// is accounted separately, the DTN traffic can be limited to a share of every band. Locked out
+/// frequencies get no capacity. The windows are evaluated at the time of the clock.
 #[derive(Debug)]
 pub struct DutyCycleManager {
-    /// Data storage for every sub band.
-    gateways: HashMap<String, PerGatewayDutyCycleManager>,
+    /// Data storage for every band.
+    gateways: HashMap<GatewayId, PerGatewayDutyCycleManager>,
+    /// Rules limiting the airtime.
+    policy: Box<dyn RegulatoryPolicy>,
+    /// Share of the airtime of every band available to the DTN traffic, between 0 and 1.
+    dtn_share: Option<f64>,
+    /// Frequencies and sub bands locked out from transmissions.
+    lockouts: Arc<FrequencyLockouts>,
+    /// Source of the current time.
+    clock: SharedClock,
 }
 
 impl DutyCycleManager {
-    /// Creates a new [`DutyCycleManager`].
-    pub fn new(gateways: HashMap<String, PerGatewayDutyCycleManager>) -> Self {
-        Self { gateways }
+    /// Creates a new [`DutyCycleManager`] applying the policy and the lockouts, the DTN traffic
+    /// may use `dtn_share` of every band if set.
+    pub fn new(
+        gateways: HashMap<GatewayId, PerGatewayDutyCycleManager>,
+        policy: Box<dyn RegulatoryPolicy>,
+        dtn_share: Option<f64>,
+        lockouts: Arc<FrequencyLockouts>,
+        clock: SharedClock,
+    ) -> Self {
+        Self {
+            gateways,
+            policy,
+            dtn_share,
+            lockouts,
+            clock,
+        }
     }
 
     /// Returns the current duty cycle information per gateway.
-    pub fn stats(&self) -> HashMap<String, PerGatewayDutyCycleManager> {
+    pub fn stats(&self) -> HashMap<GatewayId, PerGatewayDutyCycleManager> {
         self.gateways.clone()
     }
 
-    /// Returns whether the needed capacity is still available for the gateway in the sub band of the provided frequency.
+    /// Returns whether the needed capacity is still available to the DTN traffic of the gateway in the band of the provided frequency.
     ///
     /// Adds a new entry for gateways not yet in the duty cycle manager.
     /// # Errors
     ///
-    /// Returns an error if the frequency does not match any sub band.
+    /// Returns an error if the frequency does not match any band or is locked out.
     pub fn is_capacity_available(
         &mut self,
         needed_capacity: f64,
         freq: u32,
-        gateway_id: String,
+        gateway_id: GatewayId,
     ) -> Result<bool, SubBandCreationError> {
+        let now = self.clock.now();
+        if self.lockouts.is_locked_out(freq, now) {
+            return Err(SubBandCreationError::LockedOut { freq });
+        }
+        let policy = self.policy.as_ref();
+        let dtn_share = self.dtn_share;
         match self.gateways.entry(gateway_id) {
-            Entry::Occupied(mut entry) => {
-                entry.get_mut().is_capacity_available(needed_capacity, freq)
-            }
+            Entry::Occupied(mut entry) => entry.get_mut().is_dtn_capacity_available(
+                policy,
+                dtn_share,
+                needed_capacity,
+                freq,
+                now,
+            ),
             Entry::Vacant(entry) => {
                 let entry = entry.insert(PerGatewayDutyCycleManager::new());
-                entry.is_capacity_available(needed_capacity, freq)
+                entry.is_dtn_capacity_available(policy, dtn_share, needed_capacity, freq, now)
             }
         }
     }
 
-    /// Consumes the provided capacity for the gateway in the sub band corresponding to the provided frequency.
+    /// Returns the band of the provided frequency.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the frequency does not match any band.
+    pub fn band(&self, freq: u32) -> Result<String, SubBandCreationError> {
+        self.policy.band(freq)
+    }
+
+    /// Returns the capacity still available to all tenants of the gateway in the band of the
+    /// provided frequency, `None` if the band has no accumulated limit.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the frequency does not match any band.
+    pub fn remaining_capacity(
+        &mut self,
+        freq: u32,
+        gateway_id: &GatewayId,
+    ) -> Result<Option<f64>, SubBandCreationError> {
+        let policy = self.policy.as_ref();
+        let now = self.clock.now();
+        match self.gateways.get_mut(gateway_id) {
+            Some(gateway) => gateway.remaining_capacity(policy, freq, now),
+            None => PerGatewayDutyCycleManager::new().remaining_capacity(policy, freq, now),
+        }
+    }
+
+    /// Returns the share of the capacity still available to all tenants of the gateway in the
+    /// band of the provided frequency between 0 and 1, `None` if the band has no accumulated
+    /// limit.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the frequency does not match any band.
+    pub fn remaining_share(
+        &mut self,
+        freq: u32,
+        gateway_id: &GatewayId,
+    ) -> Result<Option<f64>, SubBandCreationError> {
+        let band = self.policy.band(freq)?;
+        let Some(max_capacity) = self.policy.max_airtime_ms(&band) else {
+            return Ok(None);
+        };
+        if max_capacity <= 0.0 {
+            return Ok(Some(0.0));
+        }
+        Ok(self
+            .remaining_capacity(freq, gateway_id)?
+            .map(|remaining| (remaining / max_capacity).clamp(0.0, 1.0)))
+    }
+
+    /// Consumes the provided capacity of the tenant for the gateway in the band corresponding to the provided frequency.
     ///
     /// Adds a new entry for gateways not yet in the duty cycle manager.
     /// # Errors
     ///
     /// Returns an error if:
-    /// - the frequency does not match any sub band.
-    /// - there was not capacity left in the sub band.
+    /// - the frequency does not match any band.
+    /// - there was not capacity left in the band.
     pub fn consume_capacity(
         &mut self,
         used_capacity: f64,
         freq: u32,
-        gateway_id: String,
+        gateway_id: GatewayId,
+        tenant: DutyCycleTenant,
     ) -> Result<(), ConsumeDutyCycleTimeError> {
         trace!("Consume capacity for gateway: {gateway_id}");
+        let policy = self.policy.as_ref();
+        let now = self.clock.now();
         match self.gateways.entry(gateway_id) {
-            Entry::Occupied(mut entry) => entry.get_mut().consume_capacity(used_capacity, freq),
+            Entry::Occupied(mut entry) => {
+                entry
+                    .get_mut()
+                    .consume_capacity(policy, tenant, used_capacity, freq, now)
+            }
             Entry::Vacant(entry) => {
                 let entry = entry.insert(PerGatewayDutyCycleManager::new());
-                entry.consume_capacity(used_capacity, freq)
+                entry.consume_capacity(policy, tenant, used_capacity, freq, now)
             }
         }
     }
@@ -197,101 +381,178 @@ impl DutyCycleManager {
 
 /// Collects and manages duty cycle information for one gateway.
 ///
-/// Keeps track of the amount of time already used for every sub band.
-#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+/// Keeps track of the amount of time already used for every band.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
 pub struct PerGatewayDutyCycleManager {
-    /// Data storage for every sub band.
-    bands: HashMap<EuSubBand, Vec<(chrono::DateTime<Utc>, f64)>>,
-}
-
-impl Default for PerGatewayDutyCycleManager {
-    fn default() -> Self {
-        Self::new()
-    }
+    /// Data storage of the DTN traffic for every band, by band name.
+    bands: HashMap<String, Vec<(chrono::DateTime<Utc>, f64)>>,
+    /// Data storage of the network server traffic for every band, by band name.
+    #[serde(default)]
+    network_server_bands: HashMap<String, Vec<(chrono::DateTime<Utc>, f64)>>,
 }
 
 impl PerGatewayDutyCycleManager {
     /// Creates a new [`PerGatewayDutyCycleManager`].
     pub fn new() -> Self {
-        let mut bands = HashMap::new();
-        bands.insert(EuSubBand::Sb863000_865000, Vec::new());
-        bands.insert(EuSubBand::Sb865000_868000, Vec::new());
-        bands.insert(EuSubBand::Sb868000_868600, Vec::new());
-        bands.insert(EuSubBand::Sb868700_869200, Vec::new());
-        bands.insert(EuSubBand::Sb869400_869650, Vec::new());
-        bands.insert(EuSubBand::Sb869700_870000, Vec::new());
-        Self { bands }
+        Self::default()
     }
 
-    /// Removes all entries of the capacity vec older than one hour.
-    fn remove_outdated_capacity(&mut self) {
-        let now = Utc::now();
-        for capacity_vec in self.bands.values_mut() {
-            let mut i = 0;
-            while i < capacity_vec.len() {
-                if (now - capacity_vec[i].0).num_minutes() > 60 {
-                    let _ = capacity_vec.remove(i);
-                } else {
-                    i += 1;
-                }
-            }
+    /// Returns the data storage of the tenant.
+    fn tenant_bands(
+        &mut self,
+        tenant: DutyCycleTenant,
+    ) -> &mut HashMap<String, Vec<(chrono::DateTime<Utc>, f64)>> {
+        match tenant {
+            DutyCycleTenant::Dtn => &mut self.bands,
+            DutyCycleTenant::NetworkServer => &mut self.network_server_bands,
         }
     }
 
-    /// Calculates the capacity currently used for the provided band.
-    fn calculate_used_capacity(&mut self, band: EuSubBand) -> f64 {
-        self.remove_outdated_capacity();
-        let used_capacity = self
+    /// Removes all entries of the capacity vec older than the window of the policy at `now`.
+    fn remove_outdated_capacity(&mut self, window: chrono::Duration, now: DateTime<Utc>) {
+        for capacity_vec in self
             .bands
-            .get(&band)
-            .expect("Band is missing, should be added in new()");
-        used_capacity
-            .iter()
-            .fold(0.0, |sum, (_, capacity)| sum + capacity)
+            .values_mut()
+            .chain(self.network_server_bands.values_mut())
+        {
+            capacity_vec.retain(|(time, _)| now - *time <= window);
+        }
+    }
+
+    /// Calculates the capacity used by the tenant for the provided band at `now`.
+    fn calculate_used_capacity(
+        &mut self,
+        tenant: DutyCycleTenant,
+        band: &str,
+        window: chrono::Duration,
+        now: DateTime<Utc>,
+    ) -> f64 {
+        self.remove_outdated_capacity(window, now);
+        self.tenant_bands(tenant)
+            .get(band)
+            .map_or(0.0, |used_capacity| {
+                used_capacity
+                    .iter()
+                    .fold(0.0, |sum, (_, capacity)| sum + capacity)
+            })
+    }
+
+    /// Calculates the capacity used by all tenants for the provided band at `now`.
+    fn calculate_total_used_capacity(
+        &mut self,
+        band: &str,
+        window: chrono::Duration,
+        now: DateTime<Utc>,
+    ) -> f64 {
+        self.calculate_used_capacity(DutyCycleTenant::Dtn, band, window, now)
+            + self.calculate_used_capacity(DutyCycleTenant::NetworkServer, band, window, now)
+    }
+
+    /// Returns whether the needed capacity is still available to the DTN traffic in the band of
+    /// the provided frequency at `now`, limited to `dtn_share` of the band if set.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the frequency does not match any band.
+    pub fn is_dtn_capacity_available(
+        &mut self,
+        policy: &dyn RegulatoryPolicy,
+        dtn_share: Option<f64>,
+        needed_capacity: f64,
+        freq: u32,
+        now: DateTime<Utc>,
+    ) -> Result<bool, SubBandCreationError> {
+        if !self.is_capacity_available(policy, needed_capacity, freq, now)? {
+            return Ok(false);
+        }
+        let band = policy.band(freq)?;
+        let (Some(dtn_share), Some(max_capacity)) = (dtn_share, policy.max_airtime_ms(&band))
+        else {
+            return Ok(true);
+        };
+        let used_capacity =
+            self.calculate_used_capacity(DutyCycleTenant::Dtn, &band, policy.window(), now);
+        Ok(max_capacity * dtn_share >= used_capacity + needed_capacity)
     }
 
-    /// Returns whether the needed capacity is still available in the sub band of the provided frequency.
+    /// Returns whether the needed capacity is still available in the band of the provided frequency
+    /// at `now`.
     ///
     /// # Errors
     ///
-    /// Returns an error if the frequency does not match any sub band.
+    /// Returns an error if the frequency does not match any band.
     pub fn is_capacity_available(
         &mut self,
+        policy: &dyn RegulatoryPolicy,
         needed_capacity: f64,
         freq: u32,
+        now: DateTime<Utc>,
     ) -> Result<bool, SubBandCreationError> {
-        let band = EuSubBand::try_from_freq(freq)?;
-        // 3600000.0ms in one hour
-        let max_capacity = band.duty_cycle() * 3_600_000.0;
+        let band = policy.band(freq)?;
+        if policy
+            .max_transmission_ms(&band)
+            .is_some_and(|max_transmission| needed_capacity > max_transmission)
+        {
+            return Ok(false);
+        }
+        let Some(max_capacity) = policy.max_airtime_ms(&band) else {
+            return Ok(true);
+        };
+
+        Ok(max_capacity
+            >= self.calculate_total_used_capacity(&band, policy.window(), now) + needed_capacity)
+    }
 
-        Ok(max_capacity >= self.calculate_used_capacity(band) + needed_capacity)
+    /// Returns the capacity still available to all tenants in the band of the provided
+    /// frequency at `now`, negative if the band was overused, `None` if the band has no
+    /// accumulated limit.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the frequency does not match any band.
+    pub fn remaining_capacity(
+        &mut self,
+        policy: &dyn RegulatoryPolicy,
+        freq: u32,
+        now: DateTime<Utc>,
+    ) -> Result<Option<f64>, SubBandCreationError> {
+        let band = policy.band(freq)?;
+        let Some(max_capacity) = policy.max_airtime_ms(&band) else {
+            return Ok(None);
+        };
+        Ok(Some(
+            max_capacity - self.calculate_total_used_capacity(&band, policy.window(), now),
+        ))
     }
 
-    /// Consumes the provided capacity in the sub band corresponding to the provided frequency.
+    /// Consumes the provided capacity of the tenant in the band corresponding to the provided
+    /// frequency at `now`.
     ///
     /// # Errors
     ///
     /// Returns an error if:
-    /// - the frequency does not match any sub band.
-    /// - there was not capacity left in the sub band.
+    /// - the frequency does not match any band.
+    /// - there was not capacity left in the band.
     pub fn consume_capacity(
         &mut self,
+        policy: &dyn RegulatoryPolicy,
+        tenant: DutyCycleTenant,
         used_capacity: f64,
         freq: u32,
+        now: DateTime<Utc>,
     ) -> Result<(), ConsumeDutyCycleTimeError> {
-        if self.is_capacity_available(used_capacity, freq)? {
-            let band = EuSubBand::try_from_freq(freq)?;
-            let capacity_vec = self
-                .bands
-                .get_mut(&band)
-                .expect("Band is missing, should be added in new()");
-            capacity_vec.push((Utc::now(), used_capacity));
+        if self.is_capacity_available(policy, used_capacity, freq, now)? {
+            let band = policy.band(freq)?;
+            self.tenant_bands(tenant)
+                .entry(band.clone())
+                .or_default()
+                .push((now, used_capacity));
 
             if cfg!(debug_assertions) {
-                let capacity = self.calculate_used_capacity(band);
+                let capacity = self.calculate_total_used_capacity(&band, policy.window(), now);
                 trace!(
-                    "Used {capacity} of {} in band {band:?}",
-                    band.duty_cycle() * 3_600_000.0,
+                    "Used {capacity} of {:?} in band {band}",
+                    policy.max_airtime_ms(&band),
                 );
             }
 
@@ -304,9 +565,19 @@ impl PerGatewayDutyCycleManager {
 
 #[cfg(test)]
 mod tests {
-    use crate::duty_cycle_manager::{EuSubBand, PerGatewayDutyCycleManager};
+    use crate::clock::MockClock;
+    use crate::duty_cycle_manager::{
+        DutyCycleManager, DutyCycleTenant, EuDutyCycle, EuSubBand, PerGatewayDutyCycleManager,
+        RegulatoryPolicy,
+    };
     use crate::error::ConsumeDutyCycleTimeError;
+    use crate::frequency_lockouts::FrequencyLockouts;
+    use chirpstack_api::gw::{DownlinkFrame, DownlinkFrameItem};
+    use chirpstack_gwb_integration::gateway_id::GatewayId;
     use chrono::{Duration, Utc};
+    use lorawan_dtn_protocol::LO_RA_WAN_PROPRIETARY_TAG;
+    use std::collections::HashMap;
+    use std::sync::Arc;
 
     #[allow(clippy::unwrap_used)]
     #[test]
@@ -314,14 +585,15 @@ mod tests {
         let mut pg_duty_cycle_manager = PerGatewayDutyCycleManager::new();
         let band = pg_duty_cycle_manager
             .bands
-            .get_mut(&EuSubBand::Sb863000_865000)
-            .unwrap();
-        band.push((Utc::now() - Duration::minutes(65), 100.0));
+            .entry(EuSubBand::Sb863000_865000.as_str().to_owned())
+            .or_default();
+        let now = Utc::now();
+        band.push((now - Duration::minutes(65), 100.0));
         assert!(!band.is_empty());
-        pg_duty_cycle_manager.remove_outdated_capacity();
+        pg_duty_cycle_manager.remove_outdated_capacity(EuDutyCycle.window(), now);
         let band = pg_duty_cycle_manager
             .bands
-            .get_mut(&EuSubBand::Sb863000_865000)
+            .get_mut(EuSubBand::Sb863000_865000.as_str())
             .unwrap();
         assert!(band.is_empty());
     }
@@ -332,19 +604,183 @@ mod tests {
         let mut pg_duty_cycle_manager = PerGatewayDutyCycleManager::new();
         let band = pg_duty_cycle_manager
             .bands
-            .get_mut(&EuSubBand::Sb863000_865000)
-            .unwrap();
-        band.push((Utc::now() - Duration::minutes(65), f64::MAX));
+            .entry(EuSubBand::Sb863000_865000.as_str().to_owned())
+            .or_default();
+        let now = Utc::now();
+        band.push((now - Duration::minutes(65), f64::MAX));
         assert_eq!(
             Ok(()),
             pg_duty_cycle_manager.consume_capacity(
+                &EuDutyCycle,
+                DutyCycleTenant::Dtn,
                 EuSubBand::Sb863000_865000.duty_cycle() * 3_600_000.0,
-                863_000_000
+                863_000_000,
+                now
             )
         );
         assert_eq!(
             Err(ConsumeDutyCycleTimeError::CapacityOverused),
-            pg_duty_cycle_manager.consume_capacity(1.0, 863_000_000)
+            pg_duty_cycle_manager.consume_capacity(
+                &EuDutyCycle,
+                DutyCycleTenant::NetworkServer,
+                1.0,
+                863_000_000,
+                now
+            )
+        );
+    }
+
+    /// Policy limiting single transmissions like a dwell time, without accumulated limit.
+    #[derive(Debug)]
+    struct DwellTime;
+
+    impl RegulatoryPolicy for DwellTime {
+        fn band(&self, _freq: u32) -> Result<String, crate::error::SubBandCreationError> {
+            Ok("all".to_owned())
+        }
+
+        fn window(&self) -> Duration {
+            Duration::seconds(20)
+        }
+
+        fn max_airtime_ms(&self, _band: &str) -> Option<f64> {
+            None
+        }
+
+        fn max_transmission_ms(&self, _band: &str) -> Option<f64> {
+            Some(400.0)
+        }
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn policy_replaces_eu_sub_bands() {
+        let mut pg_duty_cycle_manager = PerGatewayDutyCycleManager::new();
+        let now = Utc::now();
+        for _ in 0..100 {
+            pg_duty_cycle_manager
+                .consume_capacity(&DwellTime, DutyCycleTenant::Dtn, 399.0, 915_000_000, now)
+                .unwrap();
+        }
+        assert!(!pg_duty_cycle_manager
+            .is_capacity_available(&DwellTime, 401.0, 915_000_000, now)
+            .unwrap());
+        assert!(pg_duty_cycle_manager
+            .is_capacity_available(&EuDutyCycle, 1.0, 915_000_000, now)
+            .is_err());
+        assert_eq!(
+            EuSubBand::from_name("Sb869400_869650"),
+            Some(EuSubBand::Sb869400_869650)
+        );
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn dtn_limited_to_share_of_band() {
+        let downlink = |phy_payload: Vec<u8>| DownlinkFrame {
+            items: vec![DownlinkFrameItem {
+                phy_payload,
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        assert_eq!(
+            DutyCycleTenant::of(&downlink(vec![LO_RA_WAN_PROPRIETARY_TAG, 0x06])),
+            DutyCycleTenant::Dtn
+        );
+        assert_eq!(
+            DutyCycleTenant::of(&downlink(vec![0x60, 0x01])),
+            DutyCycleTenant::NetworkServer
+        );
+
+        // 36 s of airtime per hour in the 868.0 to 868.6 MHz sub band.
+        let mut pg_duty_cycle_manager = PerGatewayDutyCycleManager::new();
+        let now = Utc::now();
+        pg_duty_cycle_manager
+            .consume_capacity(
+                &EuDutyCycle,
+                DutyCycleTenant::NetworkServer,
+                20_000.0,
+                868_100_000,
+                now,
+            )
+            .unwrap();
+        pg_duty_cycle_manager
+            .consume_capacity(
+                &EuDutyCycle,
+                DutyCycleTenant::Dtn,
+                10_000.0,
+                868_300_000,
+                now,
+            )
+            .unwrap();
+        assert!(pg_duty_cycle_manager
+            .is_dtn_capacity_available(&EuDutyCycle, None, 6_000.0, 868_500_000, now)
+            .unwrap());
+        assert!(!pg_duty_cycle_manager
+            .is_dtn_capacity_available(&EuDutyCycle, None, 7_000.0, 868_500_000, now)
+            .unwrap());
+        assert!(!pg_duty_cycle_manager
+            .is_dtn_capacity_available(&EuDutyCycle, Some(0.3), 1_000.0, 868_500_000, now)
+            .unwrap());
+        assert!(pg_duty_cycle_manager
+            .is_dtn_capacity_available(&EuDutyCycle, Some(0.5), 1_000.0, 868_500_000, now)
+            .unwrap());
+    }
+
+    #[allow(clippy::unwrap_used)]
+    #[test]
+    fn capacity_is_released_after_the_window() {
+        let clock = MockClock::new(Utc::now());
+        let mut duty_cycle_manager = DutyCycleManager::new(
+            HashMap::new(),
+            Box::new(EuDutyCycle),
+            None,
+            Arc::new(FrequencyLockouts::new(Vec::new())),
+            clock.clone(),
+        );
+        let gateway_id: GatewayId = "a840411d25244150".parse().unwrap();
+
+        // 36 s of airtime per hour in the 868.0 to 868.6 MHz sub band.
+        duty_cycle_manager
+            .consume_capacity(
+                30_000.0,
+                868_100_000,
+                gateway_id.clone(),
+                DutyCycleTenant::Dtn,
+            )
+            .unwrap();
+        clock.advance(Duration::minutes(30));
+        assert!(!duty_cycle_manager
+            .is_capacity_available(10_000.0, 868_300_000, gateway_id.clone())
+            .unwrap());
+        assert_eq!(
+            duty_cycle_manager
+                .remaining_capacity(868_300_000, &gateway_id)
+                .unwrap(),
+            Some(6_000.0)
+        );
+        assert_eq!(
+            duty_cycle_manager
+                .remaining_share(868_300_000, &gateway_id)
+                .unwrap(),
+            Some(1.0 / 6.0)
+        );
+
+        // The airtime is released exactly once it left the window of an hour.
+        clock.advance(Duration::minutes(30));
+        assert!(!duty_cycle_manager
+            .is_capacity_available(10_000.0, 868_300_000, gateway_id.clone())
+            .unwrap());
+        clock.advance(Duration::seconds(1));
+        assert!(duty_cycle_manager
+            .is_capacity_available(10_000.0, 868_300_000, gateway_id.clone())
+            .unwrap());
+        assert_eq!(
+            duty_cycle_manager
+                .remaining_capacity(868_300_000, &gateway_id)
+                .unwrap(),
+            Some(36_000.0)
         );
     }
 }
@@ -5,7 +5,7 @@ use crate::error::AirtimeCalculationError;
 use crate::lora_modulation_extraction::extract_modulation_freq_info_from_downlink_tx_info;
 use chirpstack_api::gw::LoraModulationInfo;
 use chirpstack_gwb_integration::downlinks::predefined_parameters::{
-    Bandwidth, CodingRate, SpreadingFactor,
+    Bandwidth, CodingRate, DataRate, SpreadingFactor,
 };
 /// Amount of symbols in the preamble for the EU868-870 bands.
 static LORA_PREAMBLE_LENGTH_EU868_870_IN_SYMBOLS: f64 = 8.0;
@@ -164,6 +164,18 @@ pub fn calc_max_downlink_airtime(
         .expect("Empty airtimes vector, cannot happen, at least one item is processed"))
 }
 
+/// Calculates the airtime in ms of a downlink with `phy_payload_len_bytes` sent at `data_rate`.
+pub fn calc_downlink_airtime(phy_payload_len_bytes: u32, data_rate: DataRate) -> f64 {
+    let (bandwidth, spreading_factor) = data_rate.into_bandwidth_and_spreading_factor();
+    calculate_lora_airtime(
+        phy_payload_len_bytes,
+        spreading_factor,
+        bandwidth,
+        false,
+        false,
+    )
+}
+
 #[allow(clippy::unwrap_used)]
 #[cfg(test)]
 mod tests {
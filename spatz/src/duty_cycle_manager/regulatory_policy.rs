@@ -0,0 +1,52 @@
+//! Regional rules limiting the airtime of the gateways.
+//!
+//! The [`DutyCycleManager`](crate::duty_cycle_manager::DutyCycleManager) accounts the airtime
+//! per band and asks its [`RegulatoryPolicy`] which band a frequency belongs to and how much
+//! airtime is allowed, so other rules, e.g. polite spectrum access or dwell time limits, can
+//! replace the EU sub band table without touching the send paths.
+
+use crate::duty_cycle_manager::EuSubBand;
+use crate::error::SubBandCreationError;
+use std::fmt::Debug;
+
+/// Rules deciding how much airtime a gateway may use.
+pub trait RegulatoryPolicy: Debug + Send + Sync {
+    /// Returns the band whose airtime is accounted together for the frequency in Hz.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if transmissions on the frequency are not allowed.
+    fn band(&self, freq: u32) -> Result<String, SubBandCreationError>;
+
+    /// Returns the time window in which the airtime of a band is accounted.
+    fn window(&self) -> chrono::Duration;
+
+    /// Returns the max accumulated airtime of the band in milliseconds within the window,
+    /// unlimited if `None`.
+    fn max_airtime_ms(&self, band: &str) -> Option<f64>;
+
+    /// Returns the max airtime of a single transmission in the band in milliseconds, e.g. the
+    /// dwell time, unlimited if `None`.
+    fn max_transmission_ms(&self, _band: &str) -> Option<f64> {
+        None
+    }
+}
+
+/// Duty cycle limits of the EU 868MHz to 870MHz sub bands, accounted per hour.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct EuDutyCycle;
+
+impl RegulatoryPolicy for EuDutyCycle {
+    fn band(&self, freq: u32) -> Result<String, SubBandCreationError> {
+        EuSubBand::try_from_freq(freq).map(|band| band.as_str().to_owned())
+    }
+
+    fn window(&self) -> chrono::Duration {
+        chrono::Duration::hours(1)
+    }
+
+    /// Unknown bands get no airtime.
+    fn max_airtime_ms(&self, band: &str) -> Option<f64> {
+        Some(EuSubBand::from_name(band).map_or(0.0, |band| band.duty_cycle() * 3_600_000.0))
+    }
+}
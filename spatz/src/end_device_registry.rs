@@ -0,0 +1,222 @@
+//! Typed registry of all known end device IDs.
+//!
+//! Distinguishes end device IDs of local services managed at this node, groups this node is a
+//! member of, end device IDs this node advertises on behalf of downstream nodes and remote
+//! destinations learned from announcements.
+
+use crate::end_device_id::{EndDeviceId, ManagedEndDeviceId};
+use crate::neighbor_table::{NeighborEntry, NeighborTable};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// Category of a known end device ID.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize, JsonSchema)]
+pub enum EndDeviceCategory {
+    /// Registered at this node by a local application, packets are delivered locally.
+    LocalService,
+    /// Group registered at this node, packets are delivered locally and relayed to the other
+    /// members. Groups are not announced, so packets to groups are flooded.
+    Group,
+    /// Learned from neighbors and advertised by this node on behalf of downstream nodes,
+    /// packets are relayed regardless of the relay signal quality thresholds, as neighbors route
+    /// them through this node.
+    Proxy,
+    /// Learned from neighbors but not advertised by this node, packets are relayed within the
+    /// relay signal quality thresholds like packets to unknown destinations.
+    RemoteDestination,
+}
+
+/// A known end device ID with its category.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct RegisteredEndDevice {
+    /// The end device ID.
+    pub end_device_id: EndDeviceId,
+    /// The category of the end device ID.
+    pub category: EndDeviceCategory,
+    /// The clear text representation, only known for local services and groups.
+    pub phone_number: Option<String>,
+    /// How the end device ID is reachable, only known for learned end device IDs.
+    pub neighbor_entry: Option<NeighborEntry>,
+}
+
+/// Registry combining the managed end device IDs and the end device IDs learned from neighbors.
+#[derive(Debug, Clone)]
+pub struct EndDeviceRegistry {
+    /// End device IDs of local services.
+    local: Arc<Mutex<HashSet<ManagedEndDeviceId>>>,
+    /// Group IDs this node is a member of.
+    groups: Arc<Mutex<HashSet<ManagedEndDeviceId>>>,
+    /// End device IDs learned from neighbor announcements.
+    neighbor_table: Arc<Mutex<NeighborTable>>,
+    /// Maximum hop distance of end device IDs advertised on behalf of downstream nodes, none if
+    /// proxying is disabled.
+    proxy_max_hop_distance: Option<u8>,
+}
+
+impl EndDeviceRegistry {
+    /// Creates a new [`EndDeviceRegistry`] on top of the managed end device IDs, the group IDs
+    /// and the neighbor table.
+    pub fn new(
+        local: Arc<Mutex<HashSet<ManagedEndDeviceId>>>,
+        groups: Arc<Mutex<HashSet<ManagedEndDeviceId>>>,
+        neighbor_table: Arc<Mutex<NeighborTable>>,
+        proxy_max_hop_distance: Option<u8>,
+    ) -> Self {
+        Self {
+            local,
+            groups,
+            neighbor_table,
+            proxy_max_hop_distance,
+        }
+    }
+
+    /// Returns the category of a learned end device ID.
+    fn learned_category(&self, neighbor_entry: &NeighborEntry) -> EndDeviceCategory {
+        if self
+            .proxy_max_hop_distance
+            .is_some_and(|max_hop_distance| neighbor_entry.hop_distance <= max_hop_distance)
+        {
+            EndDeviceCategory::Proxy
+        } else {
+            EndDeviceCategory::RemoteDestination
+        }
+    }
+
+    /// Returns the category of the end device ID, none if it is unknown.
+    ///
+    /// Local services take precedence over groups, groups over learned end device IDs.
+    pub async fn category(&self, end_device_id: EndDeviceId) -> Option<EndDeviceCategory> {
+        let managed_end_device_id = ManagedEndDeviceId::from(end_device_id);
+        if self.local.lock().await.contains(&managed_end_device_id) {
+            return Some(EndDeviceCategory::LocalService);
+        }
+        if self.groups.lock().await.contains(&managed_end_device_id) {
+            return Some(EndDeviceCategory::Group);
+        }
+        self.neighbor_table
+            .lock()
+            .await
+            .entries()
+            .get(&end_device_id)
+            .map(|neighbor_entry| self.learned_category(neighbor_entry))
+    }
+
+    /// Returns all known end device IDs, optionally only of the supplied category.
+    pub async fn entries(&self, category: Option<EndDeviceCategory>) -> Vec<RegisteredEndDevice> {
+        let local = self.local.lock().await;
+        let groups = self.groups.lock().await;
+        let mut entries: Vec<RegisteredEndDevice> = local
+            .iter()
+            .map(|managed_end_device_id| (managed_end_device_id, EndDeviceCategory::LocalService))
+            .chain(
+                groups
+                    .iter()
+                    .filter(|group_id| !local.contains(group_id))
+                    .map(|group_id| (group_id, EndDeviceCategory::Group)),
+            )
+            .map(|(managed_end_device_id, category)| RegisteredEndDevice {
+                end_device_id: EndDeviceId::from(managed_end_device_id.clone()),
+                category,
+                phone_number: Some(managed_end_device_id.phone_number()),
+                neighbor_entry: None,
+            })
+            .collect();
+        entries.extend(
+            self.neighbor_table
+                .lock()
+                .await
+                .entries()
+                .iter()
+                .filter(|(end_device_id, _)| {
+                    let managed_end_device_id = ManagedEndDeviceId::from(*end_device_id);
+                    !local.contains(&managed_end_device_id)
+                        && !groups.contains(&managed_end_device_id)
+                })
+                .map(|(end_device_id, neighbor_entry)| RegisteredEndDevice {
+                    end_device_id: *end_device_id,
+                    category: self.learned_category(neighbor_entry),
+                    phone_number: None,
+                    neighbor_entry: Some(neighbor_entry.clone()),
+                }),
+        );
+        entries.retain(|entry| !category.is_some_and(|category| entry.category != category));
+        entries.sort_by_key(|entry| (entry.category as u8, entry.end_device_id.0));
+        entries
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use crate::end_device_id::{EndDeviceId, ManagedEndDeviceId};
+    use crate::end_device_registry::{EndDeviceCategory, EndDeviceRegistry};
+    use crate::neighbor_table::{NeighborEntry, NeighborTable, Reachability, SignalQuality};
+    use chrono::Utc;
+    use std::collections::HashSet;
+    use std::sync::Arc;
+    use tokio::sync::Mutex;
+
+    fn neighbor_entry(hop_distance: u8) -> NeighborEntry {
+        NeighborEntry {
+            reachability: if hop_distance == 1 {
+                Reachability::Own
+            } else {
+                Reachability::Proxied
+            },
+            hop_distance,
+            neighbor: EndDeviceId(0x9999),
+            gateway_id: "a840411d25244150".parse().unwrap(),
+            signal_quality: SignalQuality {
+                rssi: -80,
+                snr: 7.5,
+            },
+            last_seen: Utc::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn categorize_end_device_ids() {
+        let local = HashSet::from([ManagedEndDeviceId::from("1234567890".to_owned())]);
+        let local_id = EndDeviceId::from(ManagedEndDeviceId::from("1234567890".to_owned()));
+        let mut neighbor_table = NeighborTable::new();
+        neighbor_table.insert(local_id, neighbor_entry(1));
+        neighbor_table.insert(EndDeviceId(0x1111), neighbor_entry(1));
+        neighbor_table.insert(EndDeviceId(0x2222), neighbor_entry(3));
+        let groups = HashSet::from([ManagedEndDeviceId::from("team-alpha".to_owned())]);
+        let group_id = EndDeviceId::from(ManagedEndDeviceId::from("team-alpha".to_owned()));
+        let registry = EndDeviceRegistry::new(
+            Arc::new(Mutex::new(local)),
+            Arc::new(Mutex::new(groups)),
+            Arc::new(Mutex::new(neighbor_table)),
+            Some(2),
+        );
+
+        assert_eq!(
+            registry.category(local_id).await,
+            Some(EndDeviceCategory::LocalService)
+        );
+        assert_eq!(
+            registry.category(EndDeviceId(0x1111)).await,
+            Some(EndDeviceCategory::Proxy)
+        );
+        assert_eq!(
+            registry.category(EndDeviceId(0x2222)).await,
+            Some(EndDeviceCategory::RemoteDestination)
+        );
+        assert_eq!(
+            registry.category(group_id).await,
+            Some(EndDeviceCategory::Group)
+        );
+        assert_eq!(registry.category(EndDeviceId(0x3333)).await, None);
+
+        assert_eq!(registry.entries(None).await.len(), 4);
+        let local_entries = registry
+            .entries(Some(EndDeviceCategory::LocalService))
+            .await;
+        assert_eq!(local_entries.len(), 1);
+        assert_eq!(local_entries[0].phone_number.as_deref(), Some("1234567890"));
+    }
+}
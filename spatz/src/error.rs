@@ -1,9 +1,11 @@
 //! All errors used in the spatz code.
 
-use chirpstack_gwb_integration::error::{BandwidthConversionError, SpreadingFactorConversionError};
-use nom::error::{FromExternalError, ParseError};
-use nom::ErrorConvert;
-use std::num::{ParseIntError, TryFromIntError};
+use crate::configuration::DestinationClass;
+use chirpstack_gwb_integration::error::{
+    BandwidthConversionError, SpreadingFactorConversionError, TopicParsingError,
+};
+use lorawan_dtn_protocol::error::TryFromEndDeviceId;
+use std::num::TryFromIntError;
 use thiserror::Error;
 
 /// Errors returned by the packet cache.
@@ -14,65 +16,18 @@ pub enum PacketCacheError {
     NotTimedOut,
 }
 
-/// Errors returned by the protocol parser.
-#[derive(Error, Debug, Clone, PartialEq, Eq)]
-pub enum ProtocolParserError {
-    /// Payload has no proprietary tag.
-    #[error("Payload has no proprietary tag")]
-    NoProprietaryTag,
-    /// Payload has wrong version tag.
-    #[error("Payload has wrong version tag")]
-    WrongVersionTag,
-    /// Payload has unknown packet type.
-    #[error("Payload has unknown packet type")]
-    UnknownPacketType,
-    /// Nom error.
-    #[error("Nom encountered an error: {0:?}")]
-    Nom(nom::error::ErrorKind),
-    /// Did not receive three bytes, cannot convert to u32
-    #[error("Did not receive three bytes, cannot convert to u32")]
-    NotThreeBytes,
-    /// Failed to create naive datetime from timestamp.
-    #[error("Failed to create naive datetime from timestamp")]
-    FromTimestampError,
-}
-
-/// Errors occurring when creating a complete bundle packet.
-#[derive(Error, Debug, Clone, PartialEq, Eq)]
-pub enum CompleteBundleCreationError {
-    /// The payload is too large and cannot fit into one packet.
-    #[error("The payload is too large and cannot fit into one packet")]
-    PayloadTooLarge,
-}
-
-/// Errors occurring when creating a bundle fragment packet.
-#[derive(Error, Debug, Clone, PartialEq, Eq)]
-pub enum BundleFragmentCreationError {
-    /// The provided payload cannot fill the packet payload completely, this is forbidden for all
-    /// but end packets.
-    #[error(
-        "The provided payload cannot fill the packet payload completely, this is forbidden for all
-        but end packets"
-    )]
-    PayloadNotFilledCompletely,
-    /// Payload is empty.
-    #[error("Payload is empty")]
-    PayloadEmpty,
-}
-
-/// Errors occurring when encoding a location.
-#[allow(clippy::enum_variant_names)]
-#[derive(Error, Debug, Clone, PartialEq, Eq)]
-pub enum LocationEncodingError {
-    /// Could not encode value, was out of range (-90.00 to 90.00).
-    #[error("Could not encode value, was out of range (-90.00 to 90.00)")]
-    LatOutOfRange,
-    /// Could not encode value, was out of range (-180.00 to 180.00)
-    #[error("Could not encode value, was out of range (-180.00 to 180.00)")]
-    LongOutOfRange,
-    /// Could not encode value, was out of range (-83886.00 to 83886.00)
-    #[error("Could not encode value, was out of range (-83886.00 to 83886.00)")]
-    AltOutOfRange,
+/// Errors occurring when converting a status report into a BP7 administrative record.
+#[derive(Error, Debug)]
+pub enum StatusReportError {
+    /// Endpoint ID error from bp7.
+    #[error("Endpoint ID error from bp7: {0}")]
+    EndpointId(#[from] bp7::eid::EndpointIdError),
+    /// Primary builder error from bp7.
+    #[error("Primary builder error from bp7: {0}")]
+    PrimaryBuilder(#[from] bp7::primary::PrimaryBuilderError),
+    /// Encoding error of the administrative record.
+    #[error("Failed to encode the administrative record: {0}")]
+    Cbor(#[from] serde_cbor::Error),
 }
 
 /// Errors occurring when using the send buffer.
@@ -120,6 +75,12 @@ pub enum SubBandCreationError {
         /// The provided frequency that could not be matched to a sub band.
         freq: u32,
     },
+    /// The frequency or its sub band is locked out.
+    #[error("Frequency {freq} is locked out")]
+    LockedOut {
+        /// The locked out frequency.
+        freq: u32,
+    },
 }
 
 /// Errors occurring when creating a sub band.
@@ -150,46 +111,6 @@ pub enum ConsumeDutyCycleTimeError {
     SubBand(#[from] SubBandCreationError),
 }
 
-/// Errors occurring when trying to convert a [`EndDeviceId`](crate::EndDeviceId) into a [`bp7::EndpointID`].
-#[derive(Error, Debug)]
-pub enum TryFromEndDeviceId {
-    /// Not a Dtn address, only Dtn addressing is supported.
-    #[error("Not a Dtn address, only Dtn addressing is supported")]
-    NoDtnAddress,
-    /// Error parsing int.
-    #[error("Error parsing int: {0}")]
-    ParseInt(#[from] ParseIntError),
-}
-
-/// Errors occurring when processing a packet.
-#[derive(Error, Debug, Clone, PartialEq, Eq)]
-pub enum BundleReceiveBufferProcessError {
-    /// Packets destination does not match receive buffers destination.
-    #[error("Packets destination does not match receive buffers destination")]
-    DstDoesNotMatch,
-    /// Packets source does not match receive buffers source.
-    #[error("Packets source does not match receive buffers source")]
-    SrcDoesNotMatch,
-    /// Packets timestamp does not match receive buffers timestamp.
-    #[error("Packets timestamp does not match receive buffers timestamp")]
-    TimestampDoesNotMatch,
-    /// Packets fragment offset hash does not match receive buffers fragment offset hash.
-    #[error("Packets fragment offset hash does not match receive buffers fragment offset hash")]
-    FragmentOffsetHashDoesNotMatch,
-    /// A packet with this index has already been received.
-    #[error("A packet with this index has already been received")]
-    IndexAlreadyReceived,
-    /// A packet with an end index has already been received.
-    #[error("A packet with an end index has already been received")]
-    EndIndexAlreadyReceived,
-    /// Fragmented bundle fragment end packet has no TADUL.
-    #[error("Fragmented bundle fragment end packet has no TADUL")]
-    NoTadul,
-    /// Fragmented bundle fragment end packet has no fragment offset.
-    #[error("Fragmented bundle fragment end packet has no fragment offset")]
-    NoFragmentOffset,
-}
-
 /// Errors occurring when trying to create a [`BundleSendBuffer`](crate::send_buffers::BundleSendBuffer) from a [`bp7::Bundle`].
 #[derive(Error, Debug)]
 pub enum BundleSendBufferConversionError {
@@ -207,21 +128,32 @@ pub enum BundleSendBufferConversionError {
     BundleSendBuffer(#[from] BundleSendBufferCreationError),
 }
 
-/// Errors occurring when combining the fragments in a [`BundleReceiveBuffer`](crate::receive_buffers::BundleReceiveBuffer).
-#[derive(Error, Debug)]
-pub enum BundleReceiveBufferCombineError {
-    /// No packet indicating the end has been received.
-    #[error("No packet indicating the end has been received")]
-    EndNotReceived,
-    /// Not all fragments have been received.
-    #[error("Not all fragments have been received")]
-    FragmentsMissing,
-    /// Endpoint ID error from bp7.
-    #[error("Endpoint ID error from bp7: {0}")]
-    EndpointId(#[from] bp7::eid::EndpointIdError),
-    /// Primary builder error from bp7.
-    #[error("Primary builder error from bp7: {0}")]
-    PrimaryBuilder(#[from] bp7::primary::PrimaryBuilderError),
+/// Errors occurring when checking the [`RoutingHints`](crate::routing_hints::RoutingHints) of a
+/// submitted bundle.
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum RoutingHintsError {
+    /// The max amount of hops is 0.
+    #[error("Max hops must be at least 1")]
+    ZeroMaxHops,
+    /// The expiry is not in the future.
+    #[error("Bundle expired before submission")]
+    Expired,
+    /// The bundle must not be fragmented but its payload does not fit into a single packet.
+    #[error("Payload of {size} bytes exceeds the {max} bytes of a single packet")]
+    TooLargeForSinglePacket {
+        /// Size of the payload.
+        size: usize,
+        /// Max payload size of a single packet at the lowest data rate.
+        max: usize,
+    },
+    /// The destination is known to be farther away than the max amount of hops.
+    #[error("Destination is {hop_distance} hops away, at most {max_hops} are allowed")]
+    DestinationTooFar {
+        /// Known hop distance to the destination.
+        hop_distance: u8,
+        /// Max amount of hops of the hints.
+        max_hops: u8,
+    },
 }
 
 /// Errors occurring when extracting the [`LoraModulationInfo`](chirpstack_api::gw::LoraModulationInfo).
@@ -239,42 +171,6 @@ pub enum LoRaModulationExtractionError {
     NoLoRaParameters,
 }
 
-/// Errors occurring when creating a [`Hop2HopReceiveBuffer`](crate::receive_buffers::Hop2HopReceiveBuffer).
-#[derive(Error, Debug, PartialEq, Eq)]
-pub enum Hop2HopReceiveBufferCreationError {
-    /// Fragment index is larger than total amount of fragments
-    #[error("Fragment index is larger than total amount of fragments")]
-    IndexLargerThanTotal,
-}
-
-/// Errors occurring when processing a Hop2Hop packet fragment.
-#[derive(Error, Debug, PartialEq, Eq)]
-pub enum Hop2HopReceiveBufferProcessPacketError {
-    /// The fragments packet hash does not match.
-    #[error("The fragments packet hash does not match")]
-    HashMismatch,
-    /// The fragments packet total fragment amount does not match.
-    #[error("The fragments packet total fragment amount does not match")]
-    TotalFragmentsMismatch,
-    /// Fragment index is larger than total amount of fragments.
-    #[error("Fragment index is larger than total amount of fragments")]
-    IndexLargerThanTotal,
-    /// A packet with this index has already been received.
-    #[error("A packet with this index has already been received")]
-    IndexAlreadyReceived,
-}
-
-/// Errors occurring when combining the fragments in a [`Hop2HopReceiveBuffer`](crate::receive_buffers::Hop2HopReceiveBuffer).
-#[derive(Error, Debug, PartialEq, Eq)]
-pub enum Hop2HopReceiveBufferCombineError {
-    /// Not all fragment received.
-    #[error("Not all fragments received")]
-    FragmentsMissing,
-    /// Protocol parser error.
-    #[error("Protocol parser error: {0}")]
-    ProtocolParser(#[from] ProtocolParserError),
-}
-
 /// Errors occurring when interacting with the database.
 #[derive(Error, Debug)]
 pub enum DbError {
@@ -282,31 +178,327 @@ pub enum DbError {
     #[error("Deserializing error from serde_json: {0}")]
     SerdeJson(#[from] serde_json::Error),
     /// Sqlx error
+    #[cfg(feature = "database")]
     #[error("Database error form sqlx: {0}")]
     Sqlx(#[from] sqlx::Error),
+    /// The database is read-only, nothing is persisted.
+    #[error("Database is read-only")]
+    ReadOnly,
+    /// The data was written with a newer schema version than supported by this version.
+    #[error("{data_key:?} has schema version {version}, at most {supported} is supported")]
+    UnsupportedSchemaVersion {
+        /// Key of the data.
+        data_key: crate::database::DataKey,
+        /// Schema version of the stored data.
+        version: u32,
+        /// Current schema version of the data key.
+        supported: u32,
+    },
+    /// Nothing is stored for the key in the in-memory store.
+    #[cfg(not(feature = "database"))]
+    #[error("No data stored for {0:?}")]
+    NotFound(crate::database::DataKey),
 }
 
-impl ErrorConvert<ProtocolParserError> for ProtocolParserError {
-    fn convert(self) -> ProtocolParserError {
-        self
-    }
+/// Problems of a configuration which deserializes but cannot be applied.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ConfigurationValidationError {
+    /// A value which has to be greater than zero is zero.
+    #[error("{0} must be greater than zero")]
+    Zero(String),
+    /// An end device ID or group ID is configured multiple times or collides with another end
+    /// device ID or group ID.
+    #[error(
+        "End device or group ID {0} is configured multiple times or collides with another one"
+    )]
+    DuplicateEndDeviceId(String),
+    /// The logging configuration is invalid.
+    #[error("Invalid logging configuration: {0}")]
+    Logging(String),
+    /// A destination class is assigned to multiple scoped routing algorithms.
+    #[error("Destination class {0:?} is assigned to multiple scoped routing algorithms")]
+    DuplicateDestinationClass(DestinationClass),
+    /// The minimum of a range exceeds its maximum.
+    #[error("{0} is empty, the minimum exceeds the maximum")]
+    EmptyRange(String),
+    /// A list contains more entries than supported.
+    #[error("{0} contains more than {1} entries")]
+    TooMany(String, usize),
+    /// A frequency in Hz is none of the predefined frequencies.
+    #[error("{0} contains the unsupported frequency {1} Hz")]
+    UnsupportedFrequency(String, u32),
+    /// A value exceeds its maximum.
+    #[error("{0} must not exceed {1}")]
+    AboveMaximum(String, u64),
+    /// A payload profile byte is assigned to multiple codecs.
+    #[error("Payload profile {0} is configured multiple times")]
+    DuplicatePayloadProfile(u8),
+    /// An API token is empty or configured multiple times.
+    #[error("API token of {0} is empty or configured multiple times")]
+    InvalidApiToken(String),
+    /// A board and antenna are listed multiple times for a gateway.
+    #[error("{0} contains a board and antenna multiple times")]
+    DuplicateTxPath(String),
+    /// The profile compressing oversize bundles is no payload profile with the deflate codec.
+    #[error("{0} is no payload profile with the Deflate codec")]
+    NoCompressionProfile(String),
+}
+
+/// Errors occurring during ping or traceroute diagnostics.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiagnosticsError {
+    /// No end device ID managed by this node to receive the reply.
+    #[error("No end device ID managed by this node to receive the echo reply")]
+    NoLocalEndDeviceId,
+    /// The relay queue is full.
+    #[error("Relay packet queue is full")]
+    QueueFull,
+    /// No echo reply received in time.
+    #[error("No echo reply received in time")]
+    Timeout,
+}
+
+/// Errors occurring when requesting the retransmission of a bundle.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BundleResendError {
+    /// The node does not serve local bundles, the resent bundle would be dropped.
+    #[error("Relay-only node, bundles cannot be received")]
+    RelayOnlyNode,
+    /// The requesting end device ID is not managed by this node.
+    #[error("End device ID {end_device_id} is not managed by this node")]
+    NotLocal {
+        /// The requesting end device ID.
+        end_device_id: u32,
+    },
+    /// The relay queue is full.
+    #[error("Relay packet queue is full")]
+    QueueFull,
+}
+
+/// Errors occurring when measuring the delivery to a destination.
+#[derive(Error, Debug)]
+pub enum MeasurementError {
+    /// Another measurement is running.
+    #[error("Another measurement is running")]
+    AlreadyRunning,
+    /// The amount of test bundles is 0 or exceeds the maximum.
+    #[error("The amount of test bundles must be between 1 and {max}")]
+    InvalidBundleAmount {
+        /// Max amount of test bundles.
+        max: u32,
+    },
+    /// The payload size is 0.
+    #[error("The payload size must be at least 1 byte")]
+    EmptyPayload,
+    /// The interval between test bundles is 0.
+    #[error("The interval between test bundles must be at least 1 second")]
+    ZeroInterval,
+    /// No end device ID managed by this node to receive the status reports.
+    #[error("No end device ID managed by this node to receive the status reports")]
+    NoLocalEndDeviceId,
+    /// Endpoint ID error from bp7.
+    #[error("Endpoint ID error from bp7: {0}")]
+    EndpointId(#[from] bp7::eid::EndpointIdError),
+    /// Primary builder error from bp7.
+    #[error("Primary builder error from bp7: {0}")]
+    PrimaryBuilder(#[from] bp7::primary::PrimaryBuilderError),
+    /// The test bundle cannot be sent via LoRaWAN, e.g. as the payload is too large.
+    #[error(transparent)]
+    Conversion(#[from] BundleSendBufferConversionError),
+    /// The bundle processing task stopped.
+    #[error("Bundle queue closed")]
+    QueueClosed,
 }
 
-impl<I> ParseError<I> for ProtocolParserError {
-    fn from_error_kind(_: I, kind: nom::error::ErrorKind) -> Self {
-        ProtocolParserError::Nom(kind)
-    }
+/// Errors occurring when reordering, pinning or freezing queued bundles.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueueOperationError {
+    /// No queued bundle at the index.
+    #[error("No queued bundle at index {index}")]
+    NoSuchItem {
+        /// The requested index.
+        index: usize,
+    },
+    /// Pinning would exceed the maximum amount of pinned bundles.
+    #[error("At most {max} bundles can be pinned")]
+    TooManyPinned {
+        /// Maximum amount of pinned bundles.
+        max: usize,
+    },
+    /// Freezing would exceed the maximum amount of frozen bundles.
+    #[error("At most {max} bundles can be frozen")]
+    TooManyFrozen {
+        /// Maximum amount of frozen bundles.
+        max: usize,
+    },
+    /// The bundle is frozen and cannot be pinned.
+    #[error("Bundle at index {index} is frozen")]
+    Frozen {
+        /// Index of the frozen bundle.
+        index: usize,
+    },
+}
 
-    fn append(_: I, _: nom::error::ErrorKind, other: Self) -> Self {
-        other
-    }
+/// Rejections of bundles submitted by API clients.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum AirtimeQuotaError {
+    /// The client consumed its daily airtime quota.
+    #[error("Client {client} consumed {used_ms} ms of its daily airtime quota of {quota_ms} ms")]
+    DailyQuotaExceeded {
+        /// ID of the client.
+        client: String,
+        /// Airtime consumed today in milliseconds.
+        used_ms: u64,
+        /// Daily airtime quota in milliseconds.
+        quota_ms: u64,
+    },
 }
 
-impl FromExternalError<&[u8], ProtocolParserError> for ProtocolParserError {
-    fn from_external_error(_: &[u8], _: nom::error::ErrorKind, e: ProtocolParserError) -> Self {
-        e
-    }
+/// Errors occurring when recording or replaying uplink traces.
+#[derive(Error, Debug)]
+pub enum UplinkTraceError {
+    /// Reading or writing the trace file failed.
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    /// A record of the trace could not be de-/serialized.
+    #[error("Invalid trace record: {0}")]
+    Json(#[from] serde_json::Error),
+    /// The topic of a record does not match the topic layout.
+    #[error(transparent)]
+    Topic(#[from] TopicParsingError),
 }
 
-/// Type alias for packet parsing.
-pub type IResult<I, O> = nom::IResult<I, O, ProtocolParserError>;
+/// Errors occurring when starting or feeding a bundle plugin.
+#[derive(Error, Debug)]
+pub enum PluginError {
+    /// Starting or writing to the plugin process failed.
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    /// The plugin process has no piped stdin or stdout.
+    #[error("Plugin process has no piped stdin or stdout")]
+    NoPipe,
+    /// Loading or calling the WASM module failed.
+    #[error("WASM plugin failed: {0}")]
+    Wasm(String),
+    /// A WASM plugin is configured but Spatz was built without the `wasm-plugins` feature.
+    #[error("WASM plugins require the wasm-plugins feature")]
+    WasmNotEnabled,
+}
+
+/// Errors occurring when submitting a file dropped into the watched directory as bundle.
+#[derive(Error, Debug)]
+pub enum FileDropError {
+    /// Reading or moving the file failed.
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    /// Neither the file name nor a sidecar file contains a destination.
+    #[error("No destination in the file name or a sidecar file")]
+    NoDestination,
+    /// The sidecar file is no valid JSON metadata.
+    #[error("Invalid sidecar file: {0}")]
+    Sidecar(#[from] serde_json::Error),
+    /// The file is empty.
+    #[error("File is empty")]
+    Empty,
+    /// Endpoint ID error from bp7.
+    #[error("Endpoint ID error from bp7: {0}")]
+    EndpointId(#[from] bp7::eid::EndpointIdError),
+    /// Primary builder error from bp7.
+    #[error("Primary builder error from bp7: {0}")]
+    PrimaryBuilder(#[from] bp7::primary::PrimaryBuilderError),
+    /// The bundle cannot be sent via LoRaWAN, e.g. as the payload is too large and the oversize
+    /// policy rejects it.
+    #[error(transparent)]
+    Conversion(#[from] OversizeBundleError),
+    /// The routing hints of the sidecar file are invalid.
+    #[error(transparent)]
+    RoutingHints(#[from] RoutingHintsError),
+    /// The bundle processing task stopped.
+    #[error("Bundle queue closed")]
+    QueueClosed,
+}
+
+/// Errors occurring when encoding or decoding the payload of a bundle with its profile.
+#[derive(Error, Debug)]
+pub enum PayloadCodecError {
+    /// No codec is registered for the payload profile.
+    #[error("No codec registered for payload profile {0}")]
+    UnknownProfile(u8),
+    /// The bundle has no payload block.
+    #[error("Bundle has no payload block")]
+    NoPayload,
+    /// The payload is no valid JSON.
+    #[error("Invalid JSON payload: {0}")]
+    Json(#[from] serde_json::Error),
+    /// The payload is no valid CBOR.
+    #[error("Invalid CBOR payload: {0}")]
+    Cbor(#[from] serde_cbor::Error),
+    /// The payload is no valid deflate stream or inflates beyond the maximum size.
+    #[error("Invalid compressed payload: {0}")]
+    Inflate(String),
+}
+
+/// Errors occurring when applying the [`OversizePolicy`](crate::configuration::OversizePolicy)
+/// to a submitted bundle.
+#[derive(Error, Debug)]
+pub enum OversizeBundleError {
+    /// The bundle cannot be sent, e.g. its payload is too large and the policy rejects it.
+    #[error(transparent)]
+    Conversion(#[from] BundleSendBufferConversionError),
+    /// No payload profile is configured to compress oversize bundles.
+    #[error("No payload profile is configured to compress oversize bundles")]
+    NoCompressionProfile,
+    /// Compressing the payload failed.
+    #[error("Failed to compress the payload: {0}")]
+    Compression(#[from] PayloadCodecError),
+    /// The compressed payload still exceeds the maximum size.
+    #[error("The compressed payload of {size} bytes exceeds the maximum of {max} bytes")]
+    StillTooLarge {
+        /// Size of the compressed payload in bytes.
+        size: usize,
+        /// Max size of a payload in bytes.
+        max: usize,
+    },
+}
+
+/// Errors occurring when writing exported packet records.
+#[derive(Error, Debug)]
+pub enum PacketExportError {
+    /// Writing or removing a file failed.
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    /// The schema description cannot be encoded.
+    #[error("Failed to encode the schema description: {0}")]
+    Schema(#[from] serde_json::Error),
+    /// Encoding the records as Parquet failed.
+    #[cfg(feature = "parquet-export")]
+    #[error("Failed to encode the records as Parquet: {0}")]
+    Parquet(#[from] parquet::errors::ParquetError),
+}
+
+/// Errors occurring when exchanging packets with the packet forwarder of the concentrator.
+#[cfg(feature = "beaconing")]
+#[derive(Error, Debug)]
+pub enum BeaconingError {
+    /// The packet is shorter than its header.
+    #[error("Packet truncated")]
+    Truncated,
+    /// The packet uses another version of the Semtech UDP protocol.
+    #[error("Unsupported protocol version {0}")]
+    UnsupportedVersion(u8),
+    /// The packet identifier is unknown or not sent by a packet forwarder.
+    #[error("Unknown packet identifier {0:#04x}")]
+    UnknownIdentifier(u8),
+    /// The JSON payload of the packet is invalid.
+    #[error("Invalid JSON payload: {0}")]
+    Json(#[from] serde_json::Error),
+    /// The received frame is no LoRa frame.
+    #[error("No LoRa frame")]
+    NoLoRa,
+    /// The data rate of the received frame is invalid.
+    #[error("Invalid data rate {0}")]
+    InvalidDataRate(String),
+    /// The PHY payload of the received frame is no valid base64.
+    #[error("Invalid base64 PHY payload: {0}")]
+    Base64(#[from] base64::DecodeError),
+}
@@ -0,0 +1,301 @@
+//! Submission of files dropped into a watched directory as bundles, integrating applications
+//! which can only write files.
+//!
+//! The destination is the end device ID before the first `_` of the file name, e.g.
+//! `0987654321_report.csv`, or the `destination` of a sidecar file named like the file with the
+//! suffix `.meta.json`. The sidecar file may also set the `lifetime_seconds` and the
+//! [`RoutingHints`] of the bundle as `routing_hints` and has to be written before the file. Files starting with `.` or ending with `.part` are ignored, so
+//! applications can write files under a temporary name and rename them when complete.
+//!
+//! Submitted files are moved into the `done` subdirectory, rejected files into the `failed`
+//! subdirectory next to a `.error` file with the reason. Sidecar files are moved along.
+
+use crate::bundle_processing::SubmittedBundle;
+use crate::configuration::FileDropConfig;
+use crate::end_device_id::{EndDeviceId, ManagedEndDeviceId};
+use crate::error::FileDropError;
+use crate::graceful_shutdown::ShutdownAgent;
+use crate::routing_hints::RoutingHints;
+use crate::AppState;
+use bp7::flags::BlockControlFlags;
+use chrono::{DateTime, Utc};
+use lorawan_dtn_protocol::unix_ts_to_dtn_time;
+use serde::Deserialize;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info, instrument, trace};
+
+/// Subdirectory submitted files are moved to.
+const DONE_DIRECTORY: &str = "done";
+
+/// Subdirectory rejected files are moved to.
+const FAILED_DIRECTORY: &str = "failed";
+
+/// Suffix of the sidecar file of a file.
+const SIDECAR_SUFFIX: &str = ".meta.json";
+
+/// Suffix of files which are still being written.
+const PARTIAL_SUFFIX: &str = ".part";
+
+/// Suffix of the file with the reason a file was rejected.
+const ERROR_SUFFIX: &str = ".error";
+
+/// Contents of a sidecar file.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+struct FileDropMetadata {
+    /// End device ID the bundle is addressed to, taken from the file name if not set.
+    #[serde(default)]
+    destination: Option<String>,
+    /// Lifetime of the bundle in seconds, the configured lifetime is used if not set.
+    #[serde(default)]
+    lifetime_seconds: Option<u64>,
+    /// Routing hints of the bundle, the defaults are used if not set.
+    #[serde(default)]
+    routing_hints: RoutingHints,
+}
+
+/// Returns whether the file is skipped when scanning the directory.
+fn is_ignored(file_name: &str) -> bool {
+    file_name.starts_with('.')
+        || file_name.ends_with(PARTIAL_SUFFIX)
+        || file_name.ends_with(SIDECAR_SUFFIX)
+        || file_name.ends_with(ERROR_SUFFIX)
+}
+
+/// Returns the destination before the first `_` of the file name.
+fn destination_from_file_name(file_name: &str) -> Option<String> {
+    file_name
+        .split_once('_')
+        .map(|(destination, _)| destination)
+        .filter(|destination| !destination.is_empty())
+        .map(str::to_owned)
+}
+
+/// Creates a bundle carrying the payload.
+///
+/// # Errors
+///
+/// Returns an error if source or destination cannot be converted into endpoint IDs or the
+/// primary block cannot be built.
+fn create_bundle(
+    source: EndDeviceId,
+    destination: EndDeviceId,
+    payload: Vec<u8>,
+    lifetime: Duration,
+    timestamp: DateTime<Utc>,
+) -> Result<bp7::Bundle, FileDropError> {
+    let primary = bp7::primary::PrimaryBlockBuilder::new()
+        .source(source.try_into()?)
+        .destination(destination.try_into()?)
+        .creation_timestamp(bp7::CreationTimestamp::with_time_and_seq(
+            unix_ts_to_dtn_time(timestamp.timestamp().unsigned_abs()),
+            0,
+        ))
+        .lifetime(lifetime)
+        .build()?;
+    let canonical = bp7::canonical::new_payload_block(BlockControlFlags::empty(), payload);
+    Ok(bp7::Bundle::new(primary, vec![canonical]))
+}
+
+/// Reads the file and its sidecar file and submits the payload as bundle.
+///
+/// # Errors
+///
+/// Returns an error if:
+/// - the file or the sidecar file cannot be read.
+/// - the sidecar file is invalid or contains invalid routing hints.
+/// - neither the file name nor the sidecar file contains a destination.
+/// - the file is empty or too large to be sent via LoRaWAN and the oversize policy rejects it.
+/// - the bundle processing task stopped.
+async fn submit_file(
+    config: &FileDropConfig,
+    state: &AppState,
+    path: &Path,
+    file_name: &str,
+) -> Result<(), FileDropError> {
+    let metadata = match tokio::fs::read(sidecar_path(path, file_name)).await {
+        Ok(sidecar) => serde_json::from_slice::<FileDropMetadata>(&sidecar)?,
+        Err(err) if err.kind() == ErrorKind::NotFound => FileDropMetadata::default(),
+        Err(err) => return Err(err.into()),
+    };
+    let destination = metadata
+        .destination
+        .or_else(|| destination_from_file_name(file_name))
+        .ok_or(FileDropError::NoDestination)?;
+    let payload = tokio::fs::read(path).await?;
+    if payload.is_empty() {
+        return Err(FileDropError::Empty);
+    }
+    let now = Utc::now();
+    let mut bundle = create_bundle(
+        ManagedEndDeviceId::from(&config.source).into(),
+        ManagedEndDeviceId::from(destination).into(),
+        payload,
+        Duration::from_secs(metadata.lifetime_seconds.unwrap_or(config.lifetime_seconds)),
+        now,
+    )?;
+    state.oversize_bundles.send_buffers(
+        &mut bundle,
+        state.oversize_bundles.policy(&metadata.routing_hints),
+        &state.payload_codecs,
+        state.repeater_compatible,
+    )?;
+    metadata.routing_hints.validate(
+        bundle.payload().map_or(0, Vec::len),
+        state.repeater_compatible,
+        now,
+    )?;
+    state
+        .bundles_from_ws
+        .send(SubmittedBundle {
+            bundle,
+            client: None,
+            routing_hints: metadata.routing_hints,
+        })
+        .await
+        .map_err(|_| FileDropError::QueueClosed)
+}
+
+/// Returns the path of the sidecar file of the file.
+fn sidecar_path(path: &Path, file_name: &str) -> PathBuf {
+    path.with_file_name(format!("{file_name}{SIDECAR_SUFFIX}"))
+}
+
+/// Moves the file and its sidecar file into the subdirectory, next to the reason if rejected.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be moved or the reason cannot be written.
+async fn move_file(
+    directory: &Path,
+    subdirectory: &str,
+    file_name: &str,
+    reason: Option<String>,
+) -> std::io::Result<()> {
+    let target = directory.join(subdirectory);
+    tokio::fs::rename(directory.join(file_name), target.join(file_name)).await?;
+    let sidecar_name = format!("{file_name}{SIDECAR_SUFFIX}");
+    match tokio::fs::rename(directory.join(&sidecar_name), target.join(&sidecar_name)).await {
+        Err(err) if err.kind() != ErrorKind::NotFound => return Err(err),
+        _ => {}
+    }
+    if let Some(reason) = reason {
+        tokio::fs::write(target.join(format!("{file_name}{ERROR_SUFFIX}")), reason).await?;
+    }
+    Ok(())
+}
+
+/// Submits every new file of the directory.
+///
+/// # Errors
+///
+/// Returns an error if the directory cannot be read.
+async fn scan_directory(
+    config: &FileDropConfig,
+    directory: &Path,
+    state: &AppState,
+) -> std::io::Result<()> {
+    let mut entries = tokio::fs::read_dir(directory).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        if !entry.file_type().await?.is_file() {
+            continue;
+        }
+        let Some(file_name) = entry.file_name().to_str().map(str::to_owned) else {
+            continue;
+        };
+        if is_ignored(&file_name) {
+            continue;
+        }
+        let (subdirectory, reason) =
+            match submit_file(config, state, &entry.path(), &file_name).await {
+                Ok(()) => {
+                    info!("Submitted {file_name} as bundle");
+                    (DONE_DIRECTORY, None)
+                }
+                Err(FileDropError::QueueClosed) => return Ok(()),
+                Err(err) => {
+                    info!("Rejected {file_name}: {err}");
+                    (FAILED_DIRECTORY, Some(err.to_string()))
+                }
+            };
+        if let Err(err) = move_file(directory, subdirectory, &file_name, reason).await {
+            error!("Failed to move {file_name} to {subdirectory}: {err}");
+        }
+    }
+    Ok(())
+}
+
+/// Task scanning the configured directory for new files and submitting them as bundles.
+#[instrument(skip_all, fields(directory = %config.directory))]
+pub async fn file_drop_task(
+    config: FileDropConfig,
+    state: Arc<AppState>,
+    mut shutdown_agent: ShutdownAgent,
+) {
+    trace!("Starting up");
+    let directory = PathBuf::from(&config.directory);
+    for subdirectory in [DONE_DIRECTORY, FAILED_DIRECTORY] {
+        if let Err(err) = tokio::fs::create_dir_all(directory.join(subdirectory)).await {
+            error!("Failed to create the {subdirectory} directory: {err}");
+            return;
+        }
+    }
+    let mut poll_interval =
+        tokio::time::interval(Duration::from_secs(config.poll_interval_seconds.max(1)));
+
+    loop {
+        tokio::select! {
+            _ = poll_interval.tick() => {}
+            _ = shutdown_agent.await_shutdown() => {
+                trace!("Shutting down");
+                return
+            }
+        }
+        if let Err(err) = scan_directory(&config, &directory, &state).await {
+            error!("Failed to scan the directory: {err}");
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use crate::end_device_id::EndDeviceId;
+    use crate::file_drop::{create_bundle, destination_from_file_name, is_ignored};
+    use chrono::Utc;
+    use std::time::Duration;
+
+    #[test]
+    fn file_names_are_parsed() {
+        assert_eq!(
+            destination_from_file_name("0987654321_report.csv"),
+            Some("0987654321".to_owned())
+        );
+        assert_eq!(destination_from_file_name("report.csv"), None);
+        assert_eq!(destination_from_file_name("_report.csv"), None);
+        assert!(is_ignored(".0987654321_report.csv"));
+        assert!(is_ignored("0987654321_report.csv.part"));
+        assert!(is_ignored("report.csv.meta.json"));
+        assert!(!is_ignored("0987654321_report.json"));
+    }
+
+    #[test]
+    fn bundles_carry_the_file_payload() {
+        let bundle = create_bundle(
+            EndDeviceId(0x1234),
+            EndDeviceId(0x5678),
+            vec![1, 2, 3],
+            Duration::from_secs(3600),
+            Utc::now(),
+        )
+        .unwrap();
+        assert_eq!(bundle.payload(), Some(&vec![1, 2, 3]));
+        assert_eq!(
+            EndDeviceId::try_from(bundle.primary.destination.clone()).unwrap(),
+            EndDeviceId(0x5678)
+        );
+        assert_eq!(bundle.primary.lifetime, Duration::from_secs(3600));
+    }
+}
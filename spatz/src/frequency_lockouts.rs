@@ -0,0 +1,185 @@
+//! Lockouts of single frequencies or whole sub bands, e.g. after local interference complaints.
+//!
+//! Locked out frequencies are not selected for transmissions and the
+//! [`DutyCycleManager`](crate::duty_cycle_manager::DutyCycleManager) grants them no capacity, so
+//! downlinks already queued for them are dropped. Lockouts take effect immediately, are persisted
+//! and last until they are lifted or their optional end passed.
+
+use crate::duty_cycle_manager::EuSubBand;
+use chirpstack_gwb_integration::downlinks::predefined_parameters::Frequency;
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use tracing::info;
+
+/// Frequency or sub band which is locked out.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, JsonSchema)]
+pub enum LockoutTarget {
+    /// A single frequency in Hz.
+    Frequency(u32),
+    /// All frequencies of the sub band.
+    SubBand(EuSubBand),
+}
+
+impl LockoutTarget {
+    /// Returns whether the frequency in Hz is locked out by the target.
+    pub fn covers(self, freq: u32) -> bool {
+        match self {
+            LockoutTarget::Frequency(locked_out) => locked_out == freq,
+            LockoutTarget::SubBand(sub_band) => {
+                EuSubBand::try_from_freq(freq).is_ok_and(|band| band == sub_band)
+            }
+        }
+    }
+}
+
+/// Active lockout, persisted in the database.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Lockout {
+    /// Locked out frequency or sub band.
+    pub target: LockoutTarget,
+    /// Reason of the lockout, e.g. the reference of the complaint.
+    pub reason: String,
+    /// Time the lockout started.
+    pub since: DateTime<Utc>,
+    /// Time the lockout ends, lasts until lifted if not set.
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl Lockout {
+    /// Returns whether the lockout ended before `now`.
+    fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.until.is_some_and(|until| until <= now)
+    }
+}
+
+/// Lockouts of frequencies and sub bands, shared by the frequency selection and the duty cycle
+/// manager.
+#[derive(Debug, Default)]
+pub struct FrequencyLockouts {
+    /// Lockouts in the order they started, at most one per target.
+    lockouts: Mutex<Vec<Lockout>>,
+}
+
+impl FrequencyLockouts {
+    /// Creates a new [`FrequencyLockouts`] restoring the persisted lockouts.
+    pub fn new(lockouts: Vec<Lockout>) -> Self {
+        Self {
+            lockouts: Mutex::new(lockouts),
+        }
+    }
+
+    /// Locks out the target, replacing an earlier lockout of the same target.
+    pub fn lock_out(&self, lockout: Lockout) {
+        info!(
+            "Locked out {:?} until {:?}: {}",
+            lockout.target, lockout.until, lockout.reason
+        );
+        let mut lockouts = self.lock();
+        lockouts.retain(|existing| existing.target != lockout.target);
+        lockouts.push(lockout);
+    }
+
+    /// Lifts the lockout of the target, returns whether it was locked out.
+    pub fn lift(&self, target: LockoutTarget) -> bool {
+        let mut lockouts = self.lock();
+        let len = lockouts.len();
+        lockouts.retain(|lockout| lockout.target != target);
+        let lifted = lockouts.len() != len;
+        if lifted {
+            info!("Lifted lockout of {target:?}");
+        }
+        lifted
+    }
+
+    /// Returns the lockouts which did not end before `now`, ended lockouts are removed.
+    pub fn active(&self, now: DateTime<Utc>) -> Vec<Lockout> {
+        let mut lockouts = self.lock();
+        lockouts.retain(|lockout| !lockout.is_expired(now));
+        lockouts.clone()
+    }
+
+    /// Returns whether the frequency in Hz is locked out at `now`.
+    pub fn is_locked_out(&self, freq: u32, now: DateTime<Utc>) -> bool {
+        self.lock()
+            .iter()
+            .any(|lockout| !lockout.is_expired(now) && lockout.target.covers(freq))
+    }
+
+    /// Returns the preferred frequency if it is not locked out, the first predefined frequency
+    /// which is not locked out otherwise, `None` if all are locked out.
+    pub fn select_frequency(&self, preferred: Frequency, now: DateTime<Utc>) -> Option<Frequency> {
+        std::iter::once(preferred)
+            .chain(Frequency::ALL)
+            .find(|frequency| !self.is_locked_out(frequency.hz(), now))
+    }
+
+    /// Locks the lockouts, a poisoned lock is recovered as the lockouts stay consistent.
+    fn lock(&self) -> MutexGuard<'_, Vec<Lockout>> {
+        self.lockouts.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::duty_cycle_manager::EuSubBand;
+    use crate::frequency_lockouts::{FrequencyLockouts, Lockout, LockoutTarget};
+    use chirpstack_gwb_integration::downlinks::predefined_parameters::Frequency;
+    use chrono::{Duration, Utc};
+
+    fn lockout(target: LockoutTarget, until: Option<chrono::DateTime<Utc>>) -> Lockout {
+        Lockout {
+            target,
+            reason: "interference complaint".to_owned(),
+            since: Utc::now(),
+            until,
+        }
+    }
+
+    #[test]
+    fn locked_out_frequencies_are_not_selected() {
+        let now = Utc::now();
+        let lockouts = FrequencyLockouts::default();
+        lockouts.lock_out(lockout(LockoutTarget::Frequency(868_300_000), None));
+        assert!(lockouts.is_locked_out(868_300_000, now));
+        assert!(!lockouts.is_locked_out(868_100_000, now));
+        assert_eq!(
+            lockouts.select_frequency(Frequency::Freq868_3, now),
+            Some(Frequency::Freq868_1)
+        );
+        assert_eq!(
+            lockouts.select_frequency(Frequency::Freq868_5, now),
+            Some(Frequency::Freq868_5)
+        );
+
+        lockouts.lock_out(lockout(
+            LockoutTarget::SubBand(EuSubBand::Sb868000_868600),
+            None,
+        ));
+        assert_eq!(lockouts.select_frequency(Frequency::Freq868_5, now), None);
+        assert!(!lockouts.is_locked_out(869_525_000, now));
+
+        assert!(lockouts.lift(LockoutTarget::SubBand(EuSubBand::Sb868000_868600)));
+        assert!(!lockouts.lift(LockoutTarget::SubBand(EuSubBand::Sb868000_868600)));
+        assert_eq!(lockouts.active(now).len(), 1);
+    }
+
+    #[test]
+    fn lockouts_end_at_until() {
+        let now = Utc::now();
+        let lockouts = FrequencyLockouts::new(vec![lockout(
+            LockoutTarget::Frequency(868_100_000),
+            Some(now + Duration::minutes(10)),
+        )]);
+        // Locking out the same target again replaces the lockout.
+        lockouts.lock_out(lockout(
+            LockoutTarget::Frequency(868_100_000),
+            Some(now + Duration::minutes(5)),
+        ));
+        assert_eq!(lockouts.active(now).len(), 1);
+        assert!(lockouts.is_locked_out(868_100_000, now + Duration::minutes(4)));
+        assert!(!lockouts.is_locked_out(868_100_000, now + Duration::minutes(5)));
+        assert!(lockouts.active(now + Duration::minutes(5)).is_empty());
+    }
+}
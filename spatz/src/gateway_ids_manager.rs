@@ -1,63 +1,288 @@
-//! Gateway IDs manager keeps the gateway IDs of all connected gateways up to date.
+//! Gateway IDs manager keeps the gateway IDs of all connected gateways and their transmission
+//! capabilities up to date.
 
-use crate::graceful_shutdown::{ShutdownAgent, ShutdownConditions};
+use crate::configuration::GatewayCapabilitiesConfig;
+use crate::database::{persist, DataKey};
+use crate::graceful_shutdown::ShutdownAgent;
+use crate::operating_mode::DegradedCondition;
 use crate::AppState;
-use std::collections::HashSet;
+use async_trait::async_trait;
+use chirpstack_api::gw::modulation;
+use chirpstack_gwb_integration::downlinks::predefined_parameters::{Bandwidth, DataRate};
+use chirpstack_gwb_integration::downlinks::TxPath;
+use chirpstack_gwb_integration::gateway_id::GatewayId;
+use chirpstack_gwb_integration::runtime::callbacks::EventStatsCallback;
+use lorawan_dtn_protocol::{data_rate_bit, DEFAULT_RECEIVE_DATA_RATES};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use tokio::sync::{mpsc, Mutex};
 use tracing::{error, instrument, trace};
 
+/// Gateway stats metadata key announcing whether the gateway supports Class B timing.
+const CLASS_B_TIMING_METADATA_KEY: &str = "class_b_timing";
+
+/// Gateway stats metadata key announcing whether the gateway transmits with 250 kHz bandwidth.
+const BANDWIDTH_250_KHZ_METADATA_KEY: &str = "bandwidth_250_khz";
+
+/// Amount of consecutive failed gateway requests before entering the degraded mode.
+const CHIRPSTACK_API_ERROR_BUDGET: u32 = 3;
+
+/// Delay before retrying a failed gateway request, doubled after every further failure up to the
+/// update interval.
+const INITIAL_RETRY_DELAY: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Transmission features of a gateway, unknown if `None`.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct GatewayCapabilities {
+    /// Whether the gateway transmits at GPS timestamps as required for Class B timing.
+    pub class_b_timing: Option<bool>,
+    /// Whether the gateway transmits with 250 kHz bandwidth as required for DR6.
+    pub bandwidth_250_khz: Option<bool>,
+}
+
+impl GatewayCapabilities {
+    /// Probes the capabilities from the metadata and the transmission counters of gateway stats.
+    ///
+    /// The metadata keys `class_b_timing` and `bandwidth_250_khz` with the values `true` or
+    /// `false` can be configured in the gateway bridge. A gateway which emitted packets with
+    /// 250 kHz bandwidth supports it.
+    pub fn probe(stats: &chirpstack_api::gw::GatewayStats) -> Self {
+        let metadata_flag = |key: &str| {
+            stats
+                .metadata
+                .get(key)
+                .and_then(|value| value.trim().parse::<bool>().ok())
+        };
+        let emitted_250_khz = stats
+            .tx_packets_per_modulation
+            .iter()
+            .any(|per_modulation| {
+                per_modulation.count > 0
+                    && per_modulation
+                        .modulation
+                        .as_ref()
+                        .and_then(|modulation| modulation.parameters.as_ref())
+                        .is_some_and(|parameters| {
+                            matches!(
+                                parameters,
+                                modulation::Parameters::Lora(lora)
+                                    if lora.bandwidth == Bandwidth::Bw250.hz()
+                            )
+                        })
+            });
+        Self {
+            class_b_timing: metadata_flag(CLASS_B_TIMING_METADATA_KEY),
+            bandwidth_250_khz: metadata_flag(BANDWIDTH_250_KHZ_METADATA_KEY)
+                .or(emitted_250_khz.then_some(true)),
+        }
+    }
+
+    /// Returns the capabilities with the unknown features taken from `fallback`.
+    fn or(self, fallback: Self) -> Self {
+        Self {
+            class_b_timing: self.class_b_timing.or(fallback.class_b_timing),
+            bandwidth_250_khz: self.bandwidth_250_khz.or(fallback.bandwidth_250_khz),
+        }
+    }
+
+    /// Returns whether the gateway can transmit at the data rate, assumed if unknown.
+    pub fn supports_data_rate(&self, data_rate: DataRate) -> bool {
+        let (bandwidth, _) = data_rate.into_bandwidth_and_spreading_factor();
+        bandwidth != Bandwidth::Bw250 || self.bandwidth_250_khz != Some(false)
+    }
+
+    /// Returns the bit set of the data rates the gateway can transmit at, bit `n` is DR`n`.
+    pub fn data_rates(&self) -> u8 {
+        if self.supports_data_rate(DataRate::Eu863_870Dr6) {
+            DEFAULT_RECEIVE_DATA_RATES | data_rate_bit(DataRate::Eu863_870Dr6)
+        } else {
+            DEFAULT_RECEIVE_DATA_RATES
+        }
+    }
+}
+
+impl From<&GatewayCapabilitiesConfig> for GatewayCapabilities {
+    fn from(config: &GatewayCapabilitiesConfig) -> Self {
+        Self {
+            class_b_timing: config.class_b_timing,
+            bandwidth_250_khz: config.bandwidth_250_khz,
+        }
+    }
+}
+
+/// Stats callback sending the capabilities probed from the gateway stats to the gateway
+/// capabilities task.
+#[derive(Debug)]
+pub struct GatewayCapabilitiesCallback {
+    /// Channel to send the gateway ID and the probed capabilities.
+    pub capabilities_callback_tx: mpsc::Sender<(GatewayId, GatewayCapabilities)>,
+}
+
+#[async_trait]
+impl EventStatsCallback for GatewayCapabilitiesCallback {
+    /// Send the capabilities probed from incoming gateway stats via the channel in the
+    /// [`GatewayCapabilitiesCallback`] struct.
+    async fn dispatch_stats_event(
+        &self,
+        gateway_id: GatewayId,
+        stats_event: chirpstack_api::gw::GatewayStats,
+    ) {
+        trace!("Dispatch stats event called");
+        if let Err(err) = self
+            .capabilities_callback_tx
+            .try_send((gateway_id, GatewayCapabilities::probe(&stats_event)))
+        {
+            error!(%err);
+        }
+    }
+}
+
 /// Manages all gateway IDs connected to this spatz.
 #[derive(Debug)]
 pub struct GatewayIdsManager {
     /// Hashset of all gateway IDs.
-    pub gateway_ids: Arc<Mutex<HashSet<String>>>,
+    pub gateway_ids: Arc<Mutex<HashSet<GatewayId>>>,
     /// The interval between updates.
     update_interval: std::time::Duration,
+    /// Configured capabilities by gateway ID, override the probed ones.
+    configured_capabilities: HashMap<GatewayId, GatewayCapabilities>,
+    /// Capabilities probed from the gateway stats by gateway ID.
+    probed_capabilities: Mutex<HashMap<GatewayId, GatewayCapabilities>>,
+    /// Configured boards and antennas every downlink is offered on by gateway ID.
+    configured_tx_paths: HashMap<GatewayId, Vec<TxPath>>,
 }
 impl GatewayIdsManager {
-    /// Creates a new [`GatewayIdsManager`] with the provided update interval.
-    pub fn new(update_interval: std::time::Duration) -> Self {
+    /// Creates a new [`GatewayIdsManager`] with the provided update interval and the configured
+    /// gateway capabilities.
+    ///
+    /// The gateway IDs are used until the first successful request, e.g. the last gateway IDs
+    /// persisted in the database.
+    pub fn new(
+        update_interval: std::time::Duration,
+        gateway_ids: HashSet<GatewayId>,
+        configured_capabilities: &[GatewayCapabilitiesConfig],
+    ) -> Self {
         Self {
-            gateway_ids: Arc::new(Mutex::new(HashSet::new())),
+            gateway_ids: Arc::new(Mutex::new(gateway_ids)),
             update_interval,
+            configured_capabilities: configured_capabilities
+                .iter()
+                .map(|config| (config.gateway_id.clone(), GatewayCapabilities::from(config)))
+                .collect(),
+            probed_capabilities: Mutex::new(HashMap::new()),
+            configured_tx_paths: configured_capabilities
+                .iter()
+                .filter(|config| !config.tx_paths.is_empty())
+                .map(|config| (config.gateway_id.clone(), config.tx_paths.clone()))
+                .collect(),
+        }
+    }
+
+    /// Returns the boards and antennas every downlink of the gateway is offered on, empty if
+    /// none are configured.
+    pub fn tx_paths(&self, gateway_id: &GatewayId) -> &[TxPath] {
+        self.configured_tx_paths
+            .get(gateway_id)
+            .map_or(&[], Vec::as_slice)
+    }
+
+    /// Returns the capabilities of the gateway, configured ones take precedence over probed
+    /// ones.
+    pub async fn capabilities(&self, gateway_id: &GatewayId) -> GatewayCapabilities {
+        let probed = self
+            .probed_capabilities
+            .lock()
+            .await
+            .get(gateway_id)
+            .copied()
+            .unwrap_or_default();
+        self.configured_capabilities
+            .get(gateway_id)
+            .copied()
+            .unwrap_or_default()
+            .or(probed)
+    }
+
+    /// Returns the capabilities of all connected, configured or probed gateways.
+    pub async fn all_capabilities(&self) -> HashMap<GatewayId, GatewayCapabilities> {
+        let mut gateway_ids: HashSet<GatewayId> = self.gateway_ids.lock().await.clone();
+        gateway_ids.extend(self.configured_capabilities.keys().cloned());
+        gateway_ids.extend(self.probed_capabilities.lock().await.keys().cloned());
+        let mut capabilities = HashMap::new();
+        for gateway_id in gateway_ids {
+            let gateway_capabilities = self.capabilities(&gateway_id).await;
+            capabilities.insert(gateway_id, gateway_capabilities);
         }
+        capabilities
+    }
+
+    /// Records probed capabilities, features unknown in the probe keep their last probed value.
+    pub async fn update_probed_capabilities(
+        &self,
+        gateway_id: GatewayId,
+        probed: GatewayCapabilities,
+    ) {
+        let mut probed_capabilities = self.probed_capabilities.lock().await;
+        let entry = probed_capabilities.entry(gateway_id).or_default();
+        *entry = probed.or(*entry);
     }
 
     /// Update list of gateways connected to this spatz.
+    ///
+    /// After [`CHIRPSTACK_API_ERROR_BUDGET`] consecutive failed requests, the degraded mode is
+    /// entered and the last known gateway IDs are kept until the ChirpStack API is reachable
+    /// again. Failed requests are retried with an exponential backoff. Changed gateway IDs are
+    /// persisted to be used as fallback on the next start.
     #[instrument(skip_all)]
     pub async fn update_gateways(&self, state: Arc<AppState>, mut shutdown_agent: ShutdownAgent) {
         trace!("Starting up");
-        let mut retry = 0;
+        let mut consecutive_failures = 0;
+        let mut retry_delay = INITIAL_RETRY_DELAY;
         loop {
             trace!("Requesting gateways");
-            if retry <= 3 {
-                tokio::select! {
-                    res = state.chirpstack_api.request_gateway_ids(1000) => {
-                        match res {
-                            Ok(gateway_ids) => {
-                                *self.gateway_ids.lock().await = gateway_ids;
-                                retry = 0;
-                            }
-                            Err(err) => {
-                                error!(%err);
-                                retry += 1;
-                                continue;
+            tokio::select! {
+                res = state.chirpstack_api.request_gateway_ids(1000) => {
+                    match res {
+                        Ok(gateway_ids) => {
+                            self.replace_gateway_ids(gateway_ids, &state).await;
+                            consecutive_failures = 0;
+                            retry_delay = INITIAL_RETRY_DELAY;
+                            state
+                                .operating_mode
+                                .lock()
+                                .await
+                                .leave(DegradedCondition::ChirpStackApiUnreachable);
+                        }
+                        Err(err) => {
+                            error!(%err);
+                            consecutive_failures += 1;
+                            if consecutive_failures >= CHIRPSTACK_API_ERROR_BUDGET {
+                                state.operating_mode.lock().await.enter(
+                                    DegradedCondition::ChirpStackApiUnreachable,
+                                    err.to_string(),
+                                );
                             }
                         }
-                    },
-                    _ = shutdown_agent.await_shutdown() => {
-                        trace!("Shutting down");
-                        return
                     }
+                },
+                _ = shutdown_agent.await_shutdown() => {
+                    trace!("Shutting down");
+                    return
                 }
-            } else {
-                error!("Failed to retrieve gateways after three tries");
-                shutdown_agent.initiate_shutdown(ShutdownConditions::GatewayRetrievalFailed);
             }
 
+            let delay = if consecutive_failures == 0 {
+                self.update_interval
+            } else {
+                let delay = retry_delay;
+                retry_delay = (retry_delay * 2).min(self.update_interval);
+                trace!("Retrying gateway request in {delay:?}");
+                delay
+            };
             tokio::select! {
-                _ = tokio::time::sleep(self.update_interval) => {},
+                _ = tokio::time::sleep(delay) => {},
                 _ = shutdown_agent.await_shutdown() => {
                     trace!("Shutting down");
                     return
@@ -65,4 +290,138 @@ impl GatewayIdsManager {
             }
         }
     }
+
+    /// Replaces the gateway IDs and persists them if they changed. The gateway IDs are not locked
+    /// while persisting.
+    async fn replace_gateway_ids(&self, gateway_ids: HashSet<GatewayId>, state: &AppState) {
+        let mut gateway_ids_lock = self.gateway_ids.lock().await;
+        if *gateway_ids_lock == gateway_ids {
+            return;
+        }
+        gateway_ids_lock.clone_from(&gateway_ids);
+        drop(gateway_ids_lock);
+        trace!("Gateway IDs changed, persisting");
+        if let Err(err) = persist(state, DataKey::GatewayIds, &gateway_ids).await {
+            error!(%err);
+        }
+    }
+}
+
+/// Task recording the capabilities probed from the gateway stats.
+#[instrument(skip_all)]
+pub async fn gateway_capabilities_task(
+    mut capabilities_rx: mpsc::Receiver<(GatewayId, GatewayCapabilities)>,
+    state: Arc<AppState>,
+    mut shutdown_agent: ShutdownAgent,
+) {
+    trace!("Starting up");
+    loop {
+        tokio::select! {
+            Some((gateway_id, probed)) = capabilities_rx.recv() => {
+                trace!("Probed capabilities of gateway \"{gateway_id}\"");
+                state
+                    .gateway_ids_manager
+                    .update_probed_capabilities(gateway_id, probed)
+                    .await;
+            },
+            _ = shutdown_agent.await_shutdown() => {
+                trace!("Shutting down");
+                return
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use crate::configuration::GatewayCapabilitiesConfig;
+    use crate::gateway_ids_manager::{GatewayCapabilities, GatewayIdsManager};
+    use chirpstack_api::gw::{
+        modulation, GatewayStats, LoraModulationInfo, Modulation, PerModulationCount,
+    };
+    use chirpstack_gwb_integration::downlinks::predefined_parameters::DataRate;
+    use chirpstack_gwb_integration::downlinks::TxPath;
+    use chirpstack_gwb_integration::gateway_id::GatewayId;
+    use std::collections::HashSet;
+
+    #[test]
+    fn capabilities_probed_from_stats() {
+        let stats = GatewayStats {
+            metadata: [("class_b_timing".to_owned(), "true".to_owned())].into(),
+            tx_packets_per_modulation: vec![PerModulationCount {
+                modulation: Some(Modulation {
+                    parameters: Some(modulation::Parameters::Lora(LoraModulationInfo {
+                        bandwidth: 250_000,
+                        spreading_factor: 7,
+                        ..Default::default()
+                    })),
+                }),
+                count: 3,
+            }],
+            ..Default::default()
+        };
+        let capabilities = GatewayCapabilities::probe(&stats);
+        assert_eq!(
+            capabilities,
+            GatewayCapabilities {
+                class_b_timing: Some(true),
+                bandwidth_250_khz: Some(true),
+            }
+        );
+        assert_eq!(
+            GatewayCapabilities::probe(&GatewayStats::default()),
+            GatewayCapabilities::default()
+        );
+    }
+
+    #[tokio::test]
+    async fn configured_capabilities_override_probed_ones() {
+        let gateway_id: GatewayId = "a840411d25244150".parse().unwrap();
+        let manager = GatewayIdsManager::new(
+            std::time::Duration::from_secs(60),
+            HashSet::new(),
+            &[GatewayCapabilitiesConfig {
+                gateway_id: "A8-40-41-1D-25-24-41-50".parse().unwrap(),
+                class_b_timing: None,
+                bandwidth_250_khz: Some(false),
+                tx_paths: vec![TxPath {
+                    board: 1,
+                    antenna: 0,
+                }],
+            }],
+        );
+        manager
+            .update_probed_capabilities(
+                gateway_id.clone(),
+                GatewayCapabilities {
+                    class_b_timing: Some(true),
+                    bandwidth_250_khz: Some(true),
+                },
+            )
+            .await;
+        manager
+            .update_probed_capabilities(gateway_id.clone(), GatewayCapabilities::default())
+            .await;
+
+        let capabilities = manager.capabilities(&gateway_id).await;
+        assert_eq!(capabilities.class_b_timing, Some(true));
+        assert!(!capabilities.supports_data_rate(DataRate::Eu863_870Dr6));
+        assert!(capabilities.supports_data_rate(DataRate::Eu863_870Dr5));
+        assert!(manager
+            .capabilities(&"a840411d25244151".parse().unwrap())
+            .await
+            .supports_data_rate(DataRate::Eu863_870Dr6));
+        assert_eq!(manager.all_capabilities().await.len(), 1);
+        assert_eq!(
+            manager.tx_paths(&gateway_id),
+            [TxPath {
+                board: 1,
+                antenna: 0,
+            }]
+        );
+        assert!(manager
+            .tx_paths(&"a840411d25244151".parse().unwrap())
+            .is_empty());
+    }
 }
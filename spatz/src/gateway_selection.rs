@@ -0,0 +1,356 @@
+//! Selection of the gateways a packet is sent from and tracking of the acknowledged
+//! transmissions per gateway.
+
+use crate::configuration::GatewaySelectionConfig;
+use crate::graceful_shutdown::ShutdownAgent;
+use crate::routing::retransmit;
+use crate::AppState;
+use async_trait::async_trait;
+use chirpstack_api::gw::TxAckStatus;
+use chirpstack_gwb_integration::gateway_id::GatewayId;
+use chirpstack_gwb_integration::runtime::callbacks::{EventAckCallback, EventStatsCallback};
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tracing::{error, instrument, trace};
+
+/// Mean earth radius in meters used for distance calculations.
+const EARTH_RADIUS_METERS: f64 = 6_371_000.0;
+
+/// Time after which an enqueued downlink without acknowledgement is forgotten.
+const PENDING_DOWNLINK_TIMEOUT_SECONDS: i64 = 300;
+
+/// Ack callback sending incoming downlink acknowledgements to the gateway selection task.
+#[derive(Debug)]
+pub struct TxAckCallback {
+    /// Channel to send the gateway ID and the acknowledgement.
+    pub ack_callback_tx: mpsc::Sender<(GatewayId, chirpstack_api::gw::DownlinkTxAck)>,
+}
+
+#[async_trait]
+impl EventAckCallback for TxAckCallback {
+    /// Send incoming acknowledgements via the channel in the [`TxAckCallback`] struct.
+    async fn dispatch_ack_event(
+        &self,
+        gateway_id: GatewayId,
+        ack_event: chirpstack_api::gw::DownlinkTxAck,
+    ) {
+        trace!("Dispatch ack event called");
+        if let Err(err) = self.ack_callback_tx.try_send((gateway_id, ack_event)) {
+            error!(%err);
+        }
+    }
+}
+
+/// Stats callback sending the gateway locations to the gateway selection task.
+#[derive(Debug)]
+pub struct GatewayLocationCallback {
+    /// Channel to send the gateway ID and the reported location.
+    pub location_callback_tx: mpsc::Sender<(GatewayId, GatewayLocation)>,
+}
+
+#[async_trait]
+impl EventStatsCallback for GatewayLocationCallback {
+    /// Send the location of incoming gateway stats via the channel in the
+    /// [`GatewayLocationCallback`] struct.
+    async fn dispatch_stats_event(
+        &self,
+        gateway_id: GatewayId,
+        stats_event: chirpstack_api::gw::GatewayStats,
+    ) {
+        trace!("Dispatch stats event called");
+        let Some(location) = stats_event.location else {
+            return;
+        };
+        if let Err(err) = self.location_callback_tx.try_send((
+            gateway_id,
+            GatewayLocation {
+                latitude: location.latitude,
+                longitude: location.longitude,
+            },
+        )) {
+            error!(%err);
+        }
+    }
+}
+
+/// Location of a gateway.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct GatewayLocation {
+    /// Latitude in degrees.
+    pub latitude: f64,
+    /// Longitude in degrees.
+    pub longitude: f64,
+}
+
+impl GatewayLocation {
+    /// Great-circle distance to another location in meters.
+    pub fn distance_meters(&self, other: &GatewayLocation) -> f64 {
+        let latitude = self.latitude.to_radians();
+        let other_latitude = other.latitude.to_radians();
+        let delta_latitude = other_latitude - latitude;
+        let delta_longitude = (other.longitude - self.longitude).to_radians();
+        let a = (delta_latitude / 2.0).sin().powi(2)
+            + latitude.cos() * other_latitude.cos() * (delta_longitude / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS_METERS * a.sqrt().asin()
+    }
+}
+
+/// Transmission counters of a gateway.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct GatewayTransmissions {
+    /// Downlinks enqueued for the gateway.
+    pub enqueued: u64,
+    /// Downlinks the gateway acknowledged as transmitted.
+    pub transmitted: u64,
+    /// Downlinks the gateway rejected.
+    pub rejected: u64,
+    /// Time of the last acknowledged transmission.
+    pub last_transmission: Option<DateTime<Utc>>,
+    /// Last location reported by the gateway.
+    pub location: Option<GatewayLocation>,
+}
+
+/// Selects the gateways a packet is sent from and records which gateways transmitted.
+#[derive(Debug, Default)]
+pub struct GatewaySelector {
+    /// Selection policy, every gateway is selected if not set.
+    policy: Option<GatewaySelectionConfig>,
+    /// Transmission counters by gateway ID.
+    gateways: HashMap<GatewayId, GatewayTransmissions>,
+    /// Enqueued downlinks awaiting their acknowledgement by downlink ID.
+    pending: HashMap<u32, (GatewayId, DateTime<Utc>)>,
+}
+
+impl GatewaySelector {
+    /// Creates a new [`GatewaySelector`] with the selection policy.
+    pub fn new(policy: Option<GatewaySelectionConfig>) -> Self {
+        Self {
+            policy,
+            ..Self::default()
+        }
+    }
+
+    /// Returns the transmission counters by gateway ID.
+    pub fn gateways(&self) -> &HashMap<GatewayId, GatewayTransmissions> {
+        &self.gateways
+    }
+
+    /// Selects the gateways to send a packet from out of the available gateways.
+    ///
+    /// Gateways with fewer acknowledged transmissions are preferred to spread the airtime.
+    pub fn select(&self, gateway_ids: &HashSet<GatewayId>) -> Vec<GatewayId> {
+        let mut candidates: Vec<&GatewayId> = gateway_ids.iter().collect();
+        candidates.sort_by_key(|gateway_id| {
+            (
+                self.gateways
+                    .get(*gateway_id)
+                    .map_or(0, |gateway| gateway.transmitted),
+                *gateway_id,
+            )
+        });
+        match &self.policy {
+            None => candidates.into_iter().cloned().collect(),
+            Some(GatewaySelectionConfig::Subset { k }) => {
+                candidates.into_iter().take(*k).cloned().collect()
+            }
+            Some(GatewaySelectionConfig::Coverage {
+                min_distance_meters,
+            }) => {
+                let min_distance_meters = f64::from(*min_distance_meters);
+                let mut selected_locations: Vec<GatewayLocation> = Vec::new();
+                let mut selected = Vec::new();
+                for gateway_id in candidates {
+                    match self.location(gateway_id) {
+                        Some(location) => {
+                            if selected_locations.iter().all(|selected_location| {
+                                selected_location.distance_meters(&location) >= min_distance_meters
+                            }) {
+                                selected_locations.push(location);
+                                selected.push(gateway_id.clone());
+                            }
+                        }
+                        None => selected.push(gateway_id.clone()),
+                    }
+                }
+                selected
+            }
+        }
+    }
+
+    /// Records a downlink enqueued for a gateway to match its acknowledgement.
+    pub fn record_enqueued(&mut self, gateway_id: &GatewayId, downlink_id: u32) {
+        let now = Utc::now();
+        self.pending.retain(|_, (_, enqueued_at)| {
+            now.signed_duration_since(*enqueued_at).num_seconds() < PENDING_DOWNLINK_TIMEOUT_SECONDS
+        });
+        self.pending.insert(downlink_id, (gateway_id.clone(), now));
+        self.gateways
+            .entry(gateway_id.clone())
+            .or_default()
+            .enqueued += 1;
+    }
+
+    /// Records the acknowledgement of a downlink enqueued by this node, other acknowledgements
+    /// are ignored.
+    pub fn process_ack(&mut self, gateway_id: &GatewayId, ack: &chirpstack_api::gw::DownlinkTxAck) {
+        if !self
+            .pending
+            .get(&ack.downlink_id)
+            .is_some_and(|(pending_gateway_id, _)| pending_gateway_id == gateway_id)
+        {
+            trace!("Ignoring ack of unknown downlink");
+            return;
+        }
+        self.pending.remove(&ack.downlink_id);
+        let gateway = self.gateways.entry(gateway_id.clone()).or_default();
+        if ack
+            .items
+            .iter()
+            .any(|item| item.status() == TxAckStatus::Ok)
+        {
+            gateway.transmitted += 1;
+            gateway.last_transmission = Some(Utc::now());
+        } else {
+            gateway.rejected += 1;
+        }
+    }
+
+    /// Updates the location of a gateway.
+    pub fn update_location(&mut self, gateway_id: &GatewayId, location: GatewayLocation) {
+        self.gateways
+            .entry(gateway_id.clone())
+            .or_default()
+            .location = Some(location);
+    }
+
+    /// Returns the last known location of a gateway.
+    fn location(&self, gateway_id: &GatewayId) -> Option<GatewayLocation> {
+        self.gateways
+            .get(gateway_id)
+            .and_then(|gateway| gateway.location)
+    }
+}
+
+/// Task recording acknowledgements and locations of the gateways in the [`GatewaySelector`] and
+/// retransmitting failed downlinks.
+#[instrument(skip_all)]
+pub async fn gateway_selection_task(
+    mut ack_rx: mpsc::Receiver<(GatewayId, chirpstack_api::gw::DownlinkTxAck)>,
+    mut location_rx: mpsc::Receiver<(GatewayId, GatewayLocation)>,
+    state: Arc<AppState>,
+    mut shutdown_agent: ShutdownAgent,
+) {
+    trace!("Starting up");
+    loop {
+        tokio::select! {
+            Some((gateway_id, ack)) = ack_rx.recv() => {
+                let origin = state.runtime.downlink_origin(&gateway_id, ack.downlink_id).await;
+                trace!(
+                    downlink_id = ack.downlink_id,
+                    subsystem = origin.as_ref().map(|origin| origin.subsystem.as_str()),
+                    "Received ack from gateway \"{gateway_id}\""
+                );
+                state.gateway_selector.lock().await.process_ack(&gateway_id, &ack);
+                let retransmission = state
+                    .downlink_retransmission
+                    .process_ack(&gateway_id, &ack)
+                    .await;
+                if let Some(retransmission) = retransmission {
+                    let state_clone = state.clone();
+                    let retransmit_shutdown_agent = shutdown_agent.clone();
+                    tokio::spawn(async move {
+                        retransmit(state_clone, retransmission, retransmit_shutdown_agent).await;
+                    });
+                }
+            },
+            Some((gateway_id, location)) = location_rx.recv() => {
+                trace!("Received location of gateway \"{gateway_id}\"");
+                state.gateway_selector.lock().await.update_location(&gateway_id, location);
+            },
+            _ = shutdown_agent.await_shutdown() => {
+                trace!("Shutting down");
+                return
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use crate::configuration::GatewaySelectionConfig;
+    use crate::gateway_selection::{GatewayLocation, GatewaySelector};
+    use chirpstack_api::gw::{DownlinkTxAck, DownlinkTxAckItem, TxAckStatus};
+    use chirpstack_gwb_integration::gateway_id::GatewayId;
+    use std::collections::HashSet;
+
+    fn gateway(name: &str) -> GatewayId {
+        format!("a840411d2524415{name}").parse().unwrap()
+    }
+
+    fn gateway_ids() -> HashSet<GatewayId> {
+        ["a", "b", "c"].into_iter().map(gateway).collect()
+    }
+
+    #[test]
+    fn subset_prefers_fewer_transmissions() {
+        let mut selector = GatewaySelector::new(Some(GatewaySelectionConfig::Subset { k: 2 }));
+        assert_eq!(
+            selector.select(&gateway_ids()),
+            vec![gateway("a"), gateway("b")]
+        );
+
+        selector.record_enqueued(&gateway("a"), 1);
+        selector.process_ack(
+            &gateway("a"),
+            &DownlinkTxAck {
+                downlink_id: 1,
+                items: vec![DownlinkTxAckItem {
+                    status: TxAckStatus::Ok as i32,
+                }],
+                ..DownlinkTxAck::default()
+            },
+        );
+        assert_eq!(
+            selector
+                .gateways()
+                .get(&gateway("a"))
+                .map(|a| a.transmitted),
+            Some(1)
+        );
+        assert_eq!(
+            selector.select(&gateway_ids()),
+            vec![gateway("b"), gateway("c")]
+        );
+    }
+
+    #[test]
+    fn coverage_skips_overlapping_gateways() {
+        let mut selector = GatewaySelector::new(Some(GatewaySelectionConfig::Coverage {
+            min_distance_meters: 1000,
+        }));
+        selector.update_location(
+            &gateway("a"),
+            GatewayLocation {
+                latitude: 49.8728,
+                longitude: 8.6512,
+            },
+        );
+        // About 100 m north of gateway "a".
+        selector.update_location(
+            &gateway("b"),
+            GatewayLocation {
+                latitude: 49.8737,
+                longitude: 8.6512,
+            },
+        );
+        assert_eq!(
+            selector.select(&gateway_ids()),
+            vec![gateway("a"), gateway("c")]
+        );
+    }
+}
@@ -0,0 +1,398 @@
+//! Send queues per gateway, each paced by the airtime of its own transmissions and limited by its
+//! own duty cycle budget, so a gateway with an exhausted sub band does not hold back the others.
+//!
+//! The routing algorithm produces every packet once and hands a downlink per selected gateway to
+//! the queues. A phy payload already queued for a gateway is not queued again, fragments are
+//! therefore not transmitted twice by the same gateway.
+
+use crate::configuration::GatewaySendQueueConfig;
+use crate::duty_cycle_manager::{calc_max_downlink_airtime, DutyCycleTenant};
+use crate::graceful_shutdown::ShutdownAgent;
+use crate::routing::enqueue_downlink;
+use crate::scheduling_journal::{packet_hash, JournalEntry, SchedulingDecision};
+use crate::AppState;
+use chirpstack_gwb_integration::downlinks::{Downlink, ImmediatelyClassC};
+use chirpstack_gwb_integration::gateway_id::GatewayId;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, Mutex};
+use tokio::time::Instant;
+use tracing::{error, instrument, trace, warn};
+
+/// Delay before a gateway without duty cycle capacity retries its next downlink.
+const DUTY_CYCLE_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+/// Counters of the send queue of a gateway.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct GatewaySendQueueStats {
+    /// Currently queued downlinks.
+    pub queued: usize,
+    /// Downlinks handed to the gateway.
+    pub sent: u64,
+    /// Downlinks not queued as their phy payload was already queued for the gateway.
+    pub duplicates_dropped: u64,
+    /// Downlinks dropped as the queue was full.
+    pub overflow_dropped: u64,
+    /// Times the next downlink was deferred due to an exhausted duty cycle budget.
+    pub duty_cycle_deferrals: u64,
+}
+
+/// A downlink waiting in the send queue of a gateway.
+#[derive(Debug)]
+pub struct QueuedDownlink {
+    /// The gateway sending the downlink.
+    gateway_id: GatewayId,
+    /// ID of the downlink to match its acknowledgement.
+    downlink_id: u32,
+    /// The downlink.
+    downlink: Downlink<ImmediatelyClassC>,
+    /// Phy payload of the downlink to detect duplicates.
+    phy_payload: Vec<u8>,
+    /// Frequency of the downlink in Hz.
+    frequency: u32,
+    /// Airtime of the downlink in milliseconds.
+    airtime_ms: f64,
+}
+
+/// Downlinks queued for a single gateway.
+#[derive(Debug)]
+struct GatewaySendQueue {
+    /// Queued downlinks, oldest first.
+    downlinks: VecDeque<QueuedDownlink>,
+    /// Earliest time the next downlink is sent.
+    next_send_at: Instant,
+}
+
+impl GatewaySendQueue {
+    /// Creates a new empty [`GatewaySendQueue`] ready to send.
+    fn new() -> Self {
+        Self {
+            downlinks: VecDeque::new(),
+            next_send_at: Instant::now(),
+        }
+    }
+
+    /// Appends the downlink unless its phy payload is already queued or the queue is full.
+    fn push(
+        &mut self,
+        queued: QueuedDownlink,
+        queue_size: usize,
+        stats: &mut GatewaySendQueueStats,
+    ) {
+        if self
+            .downlinks
+            .iter()
+            .any(|downlink| downlink.phy_payload == queued.phy_payload)
+        {
+            trace!("Phy payload already queued for {}", queued.gateway_id);
+            stats.duplicates_dropped += 1;
+        } else if self.downlinks.len() >= queue_size {
+            warn!(
+                "Send queue of {} full, dropping downlink",
+                queued.gateway_id
+            );
+            stats.overflow_dropped += 1;
+        } else {
+            self.downlinks.push_back(queued);
+        }
+        stats.queued = self.downlinks.len();
+    }
+}
+
+/// Handle to the send queues of all gateways, the downlinks are sent by the
+/// [`gateway_send_queues_task`].
+#[derive(Debug)]
+pub struct GatewaySendQueues {
+    /// Queue size and pacing of every gateway.
+    config: GatewaySendQueueConfig,
+    /// Channel to the send queues task.
+    queued_tx: mpsc::Sender<QueuedDownlink>,
+    /// Counters by gateway ID.
+    stats: Mutex<HashMap<GatewayId, GatewaySendQueueStats>>,
+}
+
+impl GatewaySendQueues {
+    /// Creates new [`GatewaySendQueues`] and the receiver to pass to the
+    /// [`gateway_send_queues_task`].
+    pub fn new(config: GatewaySendQueueConfig) -> (Self, mpsc::Receiver<QueuedDownlink>) {
+        let (queued_tx, queued_rx) = mpsc::channel(config.queue_size.max(1));
+        (
+            Self {
+                config,
+                queued_tx,
+                stats: Mutex::new(HashMap::new()),
+            },
+            queued_rx,
+        )
+    }
+
+    /// Returns the minimum gap between transmissions of a gateway, used by the routing
+    /// algorithm as polling interval instead of its own pacing.
+    pub fn min_gap(&self) -> Duration {
+        Duration::from_millis(self.config.min_gap_ms)
+    }
+
+    /// Returns the pause of a gateway after a transmission with the airtime.
+    fn pacing(&self, airtime_ms: f64) -> Duration {
+        Duration::from_secs_f64(airtime_ms * f64::from(self.config.airtime_factor) / 1000.0)
+            .max(self.min_gap())
+    }
+
+    /// Returns whether the queue of at least one of the gateways has room for another downlink.
+    pub async fn has_capacity(&self, gateway_ids: &HashSet<GatewayId>) -> bool {
+        let stats_lock = self.stats.lock().await;
+        gateway_ids.iter().any(|gateway_id| {
+            stats_lock.get(gateway_id).map_or(0, |stats| stats.queued) < self.config.queue_size
+        })
+    }
+
+    /// Hands the downlink to the send queue of the gateway.
+    pub fn enqueue(
+        &self,
+        gateway_id: GatewayId,
+        downlink_id: u32,
+        downlink: Downlink<ImmediatelyClassC>,
+        phy_payload: Vec<u8>,
+    ) {
+        let (frequency, airtime_ms) = match calc_max_downlink_airtime(downlink.clone().into()) {
+            Ok(airtime) => airtime,
+            Err(err) => {
+                error!(%err);
+                return;
+            }
+        };
+        if let Err(err) = self.queued_tx.try_send(QueuedDownlink {
+            gateway_id,
+            downlink_id,
+            downlink,
+            phy_payload,
+            frequency,
+            airtime_ms,
+        }) {
+            error!(%err);
+        }
+    }
+
+    /// Returns the counters by gateway ID.
+    pub async fn stats(&self) -> HashMap<GatewayId, GatewaySendQueueStats> {
+        self.stats.lock().await.clone()
+    }
+
+    /// Adds the downlink to the queue of its gateway.
+    async fn push(
+        &self,
+        queues: &mut HashMap<GatewayId, GatewaySendQueue>,
+        queued: QueuedDownlink,
+    ) {
+        let mut stats_lock = self.stats.lock().await;
+        let stats = stats_lock.entry(queued.gateway_id.clone()).or_default();
+        queues
+            .entry(queued.gateway_id.clone())
+            .or_insert_with(GatewaySendQueue::new)
+            .push(queued, self.config.queue_size, stats);
+    }
+
+    /// Sends the next downlink of every gateway whose pause elapsed and whose duty cycle budget
+    /// allows it, gateways without budget retry after [`DUTY_CYCLE_RETRY_DELAY`].
+    async fn send_due(&self, state: &AppState, queues: &mut HashMap<GatewayId, GatewaySendQueue>) {
+        let now = Instant::now();
+        for (gateway_id, queue) in queues.iter_mut() {
+            if queue.next_send_at > now {
+                continue;
+            }
+            let Some(next) = queue.downlinks.front() else {
+                continue;
+            };
+            let (capacity_available, remaining_ms, band) = {
+                let mut duty_cycle_manager = state.duty_cycle_manager.lock().await;
+                (
+                    duty_cycle_manager.is_capacity_available(
+                        next.airtime_ms,
+                        next.frequency,
+                        gateway_id.clone(),
+                    ),
+                    duty_cycle_manager
+                        .remaining_capacity(next.frequency, gateway_id)
+                        .ok()
+                        .flatten(),
+                    duty_cycle_manager.band(next.frequency).ok(),
+                )
+            };
+            if let Some(scheduling_journal) = &state.scheduling_journal {
+                scheduling_journal.record(JournalEntry {
+                    time: state.clock.now(),
+                    gateway_id: gateway_id.clone(),
+                    frequency: next.frequency,
+                    band,
+                    airtime_ms: next.airtime_ms,
+                    remaining_before_ms: remaining_ms,
+                    remaining_after_ms: remaining_ms,
+                    tenant: DutyCycleTenant::Dtn,
+                    decision: match capacity_available {
+                        Ok(true) => SchedulingDecision::Granted,
+                        Ok(false) => SchedulingDecision::Deferred,
+                        Err(_) => SchedulingDecision::Dropped,
+                    },
+                    downlink_id: next.downlink_id,
+                    packet_hash: packet_hash(&next.phy_payload),
+                    reason: capacity_available.as_ref().err().map(ToString::to_string),
+                });
+            }
+            match capacity_available {
+                Ok(true) => {}
+                Ok(false) => {
+                    trace!("No duty cycle capacity left for {gateway_id}, deferring");
+                    queue.next_send_at = now + DUTY_CYCLE_RETRY_DELAY;
+                    self.stats
+                        .lock()
+                        .await
+                        .entry(gateway_id.clone())
+                        .or_default()
+                        .duty_cycle_deferrals += 1;
+                    continue;
+                }
+                Err(err) => {
+                    error!("Dropping downlink for {gateway_id}: {err}");
+                    queue.downlinks.pop_front();
+                    continue;
+                }
+            }
+            let Some(queued) = queue.downlinks.pop_front() else {
+                continue;
+            };
+            queue.next_send_at = now + self.pacing(queued.airtime_ms);
+
+            trace!("Enqueuing downlink for gateway: {gateway_id}");
+            state
+                .gateway_selector
+                .lock()
+                .await
+                .record_enqueued(gateway_id, queued.downlink_id);
+            enqueue_downlink(state, gateway_id, queued.downlink).await;
+            let mut stats_lock = self.stats.lock().await;
+            let stats = stats_lock.entry(gateway_id.clone()).or_default();
+            stats.sent += 1;
+            stats.queued = queue.downlinks.len();
+        }
+    }
+}
+
+/// Waits until the deadline, forever if there is none.
+async fn sleep_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Task sending the downlinks of the gateway send queues, every gateway paced on its own.
+#[instrument(skip_all)]
+pub async fn gateway_send_queues_task(
+    mut queued_rx: mpsc::Receiver<QueuedDownlink>,
+    state: Arc<AppState>,
+    mut shutdown_agent: ShutdownAgent,
+) {
+    trace!("Starting up");
+    let Some(gateway_send_queues) = &state.gateway_send_queues else {
+        return;
+    };
+    let mut queues: HashMap<GatewayId, GatewaySendQueue> = HashMap::new();
+
+    loop {
+        let next_send_at = queues
+            .values()
+            .filter(|queue| !queue.downlinks.is_empty())
+            .map(|queue| queue.next_send_at)
+            .min();
+        tokio::select! {
+            queued = queued_rx.recv() => {
+                let Some(queued) = queued else {
+                    return;
+                };
+                gateway_send_queues.push(&mut queues, queued).await;
+            }
+            _ = sleep_until(next_send_at) => {
+                if !state.radio_silence.wait_until_over(&mut shutdown_agent).await {
+                    trace!("Shutting down");
+                    return
+                }
+                gateway_send_queues.send_due(&state, &mut queues).await;
+            }
+            _ = shutdown_agent.await_shutdown() => {
+                trace!("Shutting down");
+                return
+            }
+        }
+    }
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use crate::configuration::GatewaySendQueueConfig;
+    use crate::gateway_send_queues::{
+        GatewaySendQueue, GatewaySendQueueStats, GatewaySendQueues, QueuedDownlink,
+    };
+    use chirpstack_gwb_integration::downlinks::downlink_builder::DownlinkBuilder;
+    use chirpstack_gwb_integration::downlinks::downlink_item_builder::DownlinkItemBuilder;
+    use chirpstack_gwb_integration::downlinks::predefined_parameters::{DataRate, Frequency};
+    use chirpstack_gwb_integration::downlinks::ImmediatelyClassC;
+    use std::time::Duration;
+
+    fn queued(phy_payload: Vec<u8>) -> QueuedDownlink {
+        let item = DownlinkItemBuilder::<ImmediatelyClassC>::new()
+            .frequency(Frequency::Freq868_3)
+            .data_rate(DataRate::Eu863_870Dr3)
+            .power(14)
+            .phy_payload(phy_payload.clone())
+            .board(0)
+            .antenna(0)
+            .build()
+            .unwrap();
+        QueuedDownlink {
+            gateway_id: "a840411d25244150".parse().unwrap(),
+            downlink_id: 1,
+            downlink: DownlinkBuilder::single_item("a840411d25244150".parse().unwrap(), 1, item)
+                .unwrap(),
+            phy_payload,
+            frequency: 868_300_000,
+            airtime_ms: 100.0,
+        }
+    }
+
+    #[test]
+    fn duplicates_and_overflow_are_dropped() {
+        let mut queue = GatewaySendQueue::new();
+        let mut stats = GatewaySendQueueStats::default();
+        queue.push(queued(vec![1]), 2, &mut stats);
+        queue.push(queued(vec![1]), 2, &mut stats);
+        queue.push(queued(vec![2]), 2, &mut stats);
+        queue.push(queued(vec![3]), 2, &mut stats);
+        assert_eq!(
+            stats,
+            GatewaySendQueueStats {
+                queued: 2,
+                duplicates_dropped: 1,
+                overflow_dropped: 1,
+                ..GatewaySendQueueStats::default()
+            }
+        );
+    }
+
+    #[test]
+    fn pacing_depends_on_airtime() {
+        let (gateway_send_queues, _) = GatewaySendQueues::new(GatewaySendQueueConfig {
+            queue_size: 10,
+            airtime_factor: 99,
+            min_gap_ms: 500,
+        });
+        assert_eq!(
+            gateway_send_queues.pacing(100.0),
+            Duration::from_secs_f64(9.9)
+        );
+        assert_eq!(gateway_send_queues.pacing(1.0), Duration::from_millis(500));
+    }
+}
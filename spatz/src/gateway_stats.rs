@@ -0,0 +1,135 @@
+//! Persistence of gateway stats snapshots for dashboarding.
+
+use crate::configuration::GatewayStatsConfig;
+use crate::database::{
+    insert_gateway_stats, is_database_read_only, remove_expired_gateway_stats, track_write,
+};
+use crate::graceful_shutdown::ShutdownAgent;
+use crate::AppState;
+use async_trait::async_trait;
+use chirpstack_gwb_integration::gateway_id::GatewayId;
+use chirpstack_gwb_integration::runtime::callbacks::EventStatsCallback;
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tracing::{error, instrument, trace};
+
+/// Interval at which expired gateway stats are removed from the database.
+const GATEWAY_STATS_CLEANUP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(600);
+
+/// Stats callback sending incoming gateway stats to the gateway stats collector task.
+#[derive(Debug)]
+pub struct GatewayStatsCallback {
+    /// Channel to send the gateway ID and the gateway stats.
+    pub stats_callback_tx: mpsc::Sender<(GatewayId, chirpstack_api::gw::GatewayStats)>,
+}
+
+#[async_trait]
+impl EventStatsCallback for GatewayStatsCallback {
+    /// Send incoming gateway stats via the channel in the [`GatewayStatsCallback`] struct.
+    async fn dispatch_stats_event(
+        &self,
+        gateway_id: GatewayId,
+        stats_event: chirpstack_api::gw::GatewayStats,
+    ) {
+        trace!("Dispatch stats event called");
+        if let Err(err) = self.stats_callback_tx.try_send((gateway_id, stats_event)) {
+            error!(%err);
+        }
+    }
+}
+
+/// Snapshot of the counters reported by a gateway in a stats event.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct GatewayStatsSnapshot {
+    /// Time the stats were received.
+    pub time: DateTime<Utc>,
+    /// Number of radio packets received.
+    pub rx_packets_received: u32,
+    /// Number of radio packets received with valid PHY CRC.
+    pub rx_packets_received_ok: u32,
+    /// Number of downlink packets received for transmission.
+    pub tx_packets_received: u32,
+    /// Number of downlink packets emitted.
+    pub tx_packets_emitted: u32,
+    /// Received packets per frequency in Hz.
+    pub rx_packets_per_frequency: HashMap<u32, u32>,
+    /// Emitted packets per frequency in Hz.
+    pub tx_packets_per_frequency: HashMap<u32, u32>,
+}
+
+impl GatewayStatsSnapshot {
+    /// Creates a snapshot of the gateway stats received at `time`.
+    pub fn new(stats: &chirpstack_api::gw::GatewayStats, time: DateTime<Utc>) -> Self {
+        Self {
+            time,
+            rx_packets_received: stats.rx_packets_received,
+            rx_packets_received_ok: stats.rx_packets_received_ok,
+            tx_packets_received: stats.tx_packets_received,
+            tx_packets_emitted: stats.tx_packets_emitted,
+            rx_packets_per_frequency: stats.rx_packets_per_frequency.clone(),
+            tx_packets_per_frequency: stats.tx_packets_per_frequency.clone(),
+        }
+    }
+}
+
+/// Task persisting incoming gateway stats and removing snapshots exceeding the retention limits.
+#[instrument(skip_all)]
+pub async fn gateway_stats_collector_task(
+    mut stats_rx: mpsc::Receiver<(GatewayId, chirpstack_api::gw::GatewayStats)>,
+    state: Arc<AppState>,
+    gateway_stats_config: GatewayStatsConfig,
+    mut shutdown_agent: ShutdownAgent,
+) {
+    trace!("Starting up");
+    let retention = chrono::Duration::from_std(std::time::Duration::from_secs(
+        gateway_stats_config.retention_hours.saturating_mul(3600),
+    ))
+    .unwrap_or(chrono::Duration::MAX);
+    let mut cleanup_interval = tokio::time::interval(GATEWAY_STATS_CLEANUP_INTERVAL);
+    loop {
+        tokio::select! {
+            stats = stats_rx.recv() => {
+                if let Some((gateway_id, stats)) = stats {
+                    if is_database_read_only(&state).await {
+                        trace!("Database is read-only, stats are not persisted");
+                        continue;
+                    }
+                    trace!("Persisting stats of gateway \"{gateway_id}\"");
+                    let snapshot = GatewayStatsSnapshot::new(&stats, Utc::now());
+                    let result =
+                        insert_gateway_stats(&gateway_id, &snapshot, state.db_pool.clone()).await;
+                    if let Err(err) = track_write(&state, result).await {
+                        error!(%err);
+                    }
+                }
+            }
+            _ = cleanup_interval.tick() => {
+                if is_database_read_only(&state).await {
+                    continue;
+                }
+                trace!("Removing expired gateway stats");
+                // Nothing expires if the retention reaches back before the earliest time.
+                let expired_before = Utc::now()
+                    .checked_sub_signed(retention)
+                    .unwrap_or(DateTime::<Utc>::MIN_UTC);
+                let result = remove_expired_gateway_stats(
+                    expired_before,
+                    gateway_stats_config.max_entries_per_gateway,
+                    state.db_pool.clone(),
+                )
+                .await;
+                if let Err(err) = track_write(&state, result).await {
+                    error!(%err);
+                }
+            }
+            _ = shutdown_agent.await_shutdown() => {
+                trace!("Shutting down");
+                return
+            }
+        }
+    }
+}
@@ -10,24 +10,83 @@
 //! This is useful for cases where a shutdown should be signaled but the component should not
 //! be included in the graceful shutdown itself. The panic handler is one example.
 
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 use std::time::Duration;
 use tokio::sync::{mpsc, watch};
 use tokio::time;
 use tracing::{error, trace};
 
 /// Possible conditions leading to a shutdown command.
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub enum ShutdownConditions {
     /// A panic occurred.
     Panic,
     /// A mqtt error occurred in the runtime event loop.
     MqttError,
-    /// Retrieval of gateway IDs failed.
-    GatewayRetrievalFailed,
     /// Axum server could not be started.
     AxumStartFailed,
     /// Spatz should be restarted.
     Restart,
+    /// The database failed and the database error policy requires a shutdown.
+    DatabaseError,
+    /// The process was interrupted, e.g. with Ctrl-C.
+    Interrupted,
+    /// An internal task missed its heartbeat and the watchdog requires a shutdown.
+    TaskStalled,
+}
+
+/// Shutdown condition with the module which signalled it and the chain of errors causing it.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ShutdownReason {
+    /// The condition leading to the shutdown.
+    pub condition: ShutdownConditions,
+    /// Module which signalled the condition, e.g. `spatz::database`, or the source location of a
+    /// panic.
+    pub module: String,
+    /// Error causing the condition followed by its sources, empty if there was no error.
+    pub error_chain: Vec<String>,
+}
+
+impl ShutdownReason {
+    /// Creates a new [`ShutdownReason`] without error signalled by `module`.
+    pub fn new(condition: ShutdownConditions, module: &str) -> Self {
+        Self {
+            condition,
+            module: module.to_owned(),
+            error_chain: Vec::new(),
+        }
+    }
+
+    /// Appends the error and all its sources to the error chain.
+    #[must_use]
+    pub fn with_error(mut self, err: &(dyn std::error::Error + 'static)) -> Self {
+        let mut source = Some(err);
+        while let Some(err) = source {
+            self.error_chain.push(err.to_string());
+            source = err.source();
+        }
+        self
+    }
+
+    /// Appends an error message to the error chain.
+    #[must_use]
+    pub fn with_message(mut self, message: impl Into<String>) -> Self {
+        self.error_chain.push(message.into());
+        self
+    }
+}
+
+/// Report of a shutdown, persisted and served after the next start.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ShutdownReport {
+    /// Time the shutdown completed.
+    pub time: DateTime<Utc>,
+    /// Reason of the shutdown.
+    pub reason: ShutdownReason,
+    /// Whether all tasks shut down before the timeout.
+    pub graceful: bool,
 }
 
 /// Generator for shutdown agents and a shutdown controller.
@@ -38,9 +97,9 @@ pub struct ShutdownGenerator {
     notify_rx: watch::Sender<()>,
     /// Transceiver for shutdown conditions. Used to send a shutdown condition to the
     /// shutdown controller.
-    condition_tx: mpsc::Sender<ShutdownConditions>,
+    condition_tx: mpsc::Sender<ShutdownReason>,
     /// Receiver for shutdown conditions. Used by the shutdown controller.
-    condition_rx: mpsc::Receiver<ShutdownConditions>,
+    condition_rx: mpsc::Receiver<ShutdownReason>,
     /// Transceiver to indicate shutdown completion by shutdown agents.
     complete_indicator_tx: mpsc::Sender<()>,
     /// Receiver to check for shutdown completion by the shutdown controller.
@@ -87,22 +146,22 @@ impl ShutdownGenerator {
     }
 }
 
-/// Can send [`ShutdownConditions`] to the [`ShutdownController`] but is not waited for in the
+/// Can send [`ShutdownReason`]s to the [`ShutdownController`] but is not waited for in the
 /// shutdown process.
 #[derive(Debug)]
 pub struct ShutdownInitiator {
-    /// Transceiver for [`ShutdownConditions`] to the [`ShutdownController`].
-    condition_tx: mpsc::Sender<ShutdownConditions>,
+    /// Transceiver for [`ShutdownReason`]s to the [`ShutdownController`].
+    condition_tx: mpsc::Sender<ShutdownReason>,
 }
 
 impl ShutdownInitiator {
     /// Creates a new [`ShutdownInitiator`].
-    fn new(condition_tx: mpsc::Sender<ShutdownConditions>) -> Self {
+    fn new(condition_tx: mpsc::Sender<ShutdownReason>) -> Self {
         Self { condition_tx }
     }
 
-    /// Send a [`ShutdownConditions`] to the [`ShutdownController`].
-    pub fn initiate_shutdown(&self, reason: ShutdownConditions) {
+    /// Send a [`ShutdownReason`] to the [`ShutdownController`].
+    pub fn initiate_shutdown(&self, reason: ShutdownReason) {
         trace!("Initiate shutdown: {reason:?}");
         if let Err(err) = self.condition_tx.try_send(reason) {
             error!(%err);
@@ -117,7 +176,7 @@ pub struct ShutdownController {
     /// Transceiver for shutdown notification.
     notify_tx: watch::Sender<()>,
     /// Receiver for shutdown conditions.
-    condition_rx: mpsc::Receiver<ShutdownConditions>,
+    condition_rx: mpsc::Receiver<ShutdownReason>,
     /// Receiver to check for shutdown completion.
     complete_indicator_rx: mpsc::Receiver<()>,
 }
@@ -126,7 +185,7 @@ impl ShutdownController {
     /// Creates a new [`ShutdownController`].
     fn new(
         notify_tx: watch::Sender<()>,
-        condition_rx: mpsc::Receiver<ShutdownConditions>,
+        condition_rx: mpsc::Receiver<ShutdownReason>,
         complete_indicator_rx: mpsc::Receiver<()>,
     ) -> Self {
         Self {
@@ -155,17 +214,20 @@ impl ShutdownController {
     /// Waits for all [`ShutdownAgent`] to drop their `complete_indicator_tx` transceiver.
     /// This causes the `complete_indicator_rx` to return with an error and signals no
     /// [`ShutdownAgent`] is still active.
-    pub async fn await_complete_shutdown(&mut self, timeout_secs: u64) {
+    ///
+    /// Returns whether all [`ShutdownAgent`] shut down before the timeout.
+    pub async fn await_complete_shutdown(&mut self, timeout_secs: u64) -> bool {
         tokio::select! {
             _ = time::sleep(Duration::from_secs(timeout_secs)) => {
                 trace!("Timeout over, forcing shutdown");
+                false
             },
-            _ = self.complete_indicator_rx.recv() => {}
+            _ = self.complete_indicator_rx.recv() => true
         }
     }
 
-    /// Awaits the [`ShutdownConditions`] receiver.
-    pub async fn await_shutdown_initiation(&mut self) -> Option<ShutdownConditions> {
+    /// Awaits the [`ShutdownReason`] receiver.
+    pub async fn await_shutdown_initiation(&mut self) -> Option<ShutdownReason> {
         self.condition_rx.recv().await
     }
 }
@@ -186,7 +248,7 @@ pub struct ShutdownAgent {
     notify_rx: watch::Receiver<()>,
     /// Transceiver for shutdown conditions. Used to send a shutdown condition to the
     /// shutdown controller.
-    condition_tx: mpsc::Sender<ShutdownConditions>,
+    condition_tx: mpsc::Sender<ShutdownReason>,
     /// Transceiver to indicate shutdown completion. Must be dropped to signal completion.
     _complete_indicator_tx: mpsc::Sender<()>,
 }
@@ -195,7 +257,7 @@ impl ShutdownAgent {
     /// Create a new [`ShutdownAgent`].
     fn new(
         notify_rx: watch::Receiver<()>,
-        condition_tx: mpsc::Sender<ShutdownConditions>,
+        condition_tx: mpsc::Sender<ShutdownReason>,
         complete_indicator_tx: mpsc::Sender<()>,
     ) -> Self {
         Self {
@@ -206,10 +268,10 @@ impl ShutdownAgent {
         }
     }
 
-    /// Send a [`ShutdownConditions`] to the [`ShutdownController`].
+    /// Send a [`ShutdownReason`] to the [`ShutdownController`].
     ///
     /// Also sets the `shutdown` value to true and will shut down the agent.
-    pub fn initiate_shutdown(&mut self, reason: ShutdownConditions) {
+    pub fn initiate_shutdown(&mut self, reason: ShutdownReason) {
         trace!("Initiate shutdown: {reason:?}");
         if let Err(err) = self.condition_tx.try_send(reason) {
             error!(%err);
@@ -228,3 +290,25 @@ impl ShutdownAgent {
         self.shutdown = true;
     }
 }
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use crate::error::DbError;
+    use crate::graceful_shutdown::{ShutdownConditions, ShutdownReason};
+
+    #[test]
+    fn error_chain_contains_sources() {
+        let json_err = serde_json::from_str::<u8>("x").unwrap_err();
+        let json_msg = json_err.to_string();
+        let err = DbError::from(json_err);
+        let reason = ShutdownReason::new(ShutdownConditions::DatabaseError, module_path!())
+            .with_error(&err)
+            .with_message("last write failed");
+        assert_eq!(reason.module, "spatz::graceful_shutdown::tests");
+        assert_eq!(
+            reason.error_chain,
+            vec![err.to_string(), json_msg, "last write failed".to_owned()]
+        );
+    }
+}
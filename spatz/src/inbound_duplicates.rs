@@ -0,0 +1,161 @@
+//! Cheap suppression of uplinks received multiple times, e.g. via overlapping gateways, before
+//! they are parsed and checked against the packet cache.
+
+use chirpstack_gwb_integration::gateway_id::GatewayId;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Time a phy payload is considered a duplicate after it was first received.
+pub const INBOUND_DUPLICATE_TTL: Duration = Duration::from_secs(10);
+
+/// Result of checking an uplink against the [`InboundDuplicateFilter`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum InboundCheck {
+    /// The phy payload was not received within the TTL.
+    New,
+    /// The phy payload was already received by the same gateway.
+    DuplicateSameGateway,
+    /// The phy payload was already received by another gateway.
+    DuplicateOtherGateway,
+}
+
+/// Remembers the CRC32 of recently received phy payloads with the gateway which received them
+/// first.
+#[derive(Debug)]
+pub struct InboundDuplicateFilter {
+    /// Time a phy payload is considered a duplicate.
+    ttl: Duration,
+    /// First receiving gateway and time by CRC32 of the phy payload.
+    seen: HashMap<u32, (GatewayId, Instant)>,
+    /// Last time expired entries were removed.
+    last_cleanup: Instant,
+}
+
+impl InboundDuplicateFilter {
+    /// Creates a new empty [`InboundDuplicateFilter`].
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            seen: HashMap::new(),
+            last_cleanup: Instant::now(),
+        }
+    }
+
+    /// Checks whether the phy payload was already received within the TTL and remembers it
+    /// otherwise.
+    pub fn check(
+        &mut self,
+        gateway_id: &GatewayId,
+        phy_payload: &[u8],
+        now: Instant,
+    ) -> InboundCheck {
+        if now.saturating_duration_since(self.last_cleanup) >= self.ttl {
+            let ttl = self.ttl;
+            self.seen
+                .retain(|_, (_, first_seen)| now.saturating_duration_since(*first_seen) < ttl);
+            self.last_cleanup = now;
+        }
+
+        let crc = crc32fast::hash(phy_payload);
+        match self.seen.get(&crc) {
+            Some((first_gateway_id, first_seen))
+                if now.saturating_duration_since(*first_seen) < self.ttl =>
+            {
+                if first_gateway_id == gateway_id {
+                    InboundCheck::DuplicateSameGateway
+                } else {
+                    InboundCheck::DuplicateOtherGateway
+                }
+            }
+            _ => {
+                self.seen.insert(crc, (gateway_id.clone(), now));
+                InboundCheck::New
+            }
+        }
+    }
+}
+
+/// Counters of the inbound duplicate suppression, updated without locking.
+#[derive(Debug, Default)]
+pub struct InboundDuplicateMetrics {
+    /// Received uplinks.
+    uplinks: AtomicU64,
+    /// Suppressed uplinks already received by the same gateway.
+    suppressed_same_gateway: AtomicU64,
+    /// Suppressed uplinks already received by another gateway.
+    suppressed_other_gateway: AtomicU64,
+}
+
+/// Snapshot of the [`InboundDuplicateMetrics`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, JsonSchema)]
+pub struct InboundDuplicateStats {
+    /// Received uplinks.
+    pub uplinks: u64,
+    /// Suppressed uplinks already received by the same gateway.
+    pub suppressed_same_gateway: u64,
+    /// Suppressed uplinks already received by another gateway.
+    pub suppressed_other_gateway: u64,
+}
+
+impl InboundDuplicateMetrics {
+    /// Counts a received uplink with the result of the duplicate check.
+    pub fn record(&self, check: InboundCheck) {
+        self.uplinks.fetch_add(1, Ordering::Relaxed);
+        match check {
+            InboundCheck::New => {}
+            InboundCheck::DuplicateSameGateway => {
+                self.suppressed_same_gateway.fetch_add(1, Ordering::Relaxed);
+            }
+            InboundCheck::DuplicateOtherGateway => {
+                self.suppressed_other_gateway
+                    .fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Returns the current counters.
+    pub fn stats(&self) -> InboundDuplicateStats {
+        InboundDuplicateStats {
+            uplinks: self.uplinks.load(Ordering::Relaxed),
+            suppressed_same_gateway: self.suppressed_same_gateway.load(Ordering::Relaxed),
+            suppressed_other_gateway: self.suppressed_other_gateway.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use crate::inbound_duplicates::{InboundCheck, InboundDuplicateFilter};
+    use chirpstack_gwb_integration::gateway_id::GatewayId;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn duplicates_suppressed_within_ttl() {
+        let a: GatewayId = "a840411d2524415a".parse().unwrap();
+        let b: GatewayId = "a840411d2524415b".parse().unwrap();
+        let mut filter = InboundDuplicateFilter::new(Duration::from_secs(10));
+        let now = Instant::now();
+        let payload = [0xE0, 0x01, 0x02, 0x03];
+        assert_eq!(filter.check(&a, &payload, now), InboundCheck::New);
+        assert_eq!(
+            filter.check(&b, &payload, now + Duration::from_secs(1)),
+            InboundCheck::DuplicateOtherGateway
+        );
+        assert_eq!(
+            filter.check(&a, &payload, now + Duration::from_secs(2)),
+            InboundCheck::DuplicateSameGateway
+        );
+        assert_eq!(
+            filter.check(&a, &[0xE0, 0x01], now + Duration::from_secs(2)),
+            InboundCheck::New
+        );
+        assert_eq!(
+            filter.check(&b, &payload, now + Duration::from_secs(11)),
+            InboundCheck::New
+        );
+    }
+}
@@ -8,46 +8,133 @@
 #![warn(clippy::pedantic)]
 #![allow(clippy::doc_markdown)]
 #![allow(clippy::module_name_repetitions)]
+// Stats and control accessors are only used by the API, the database error policy only applies
+// to SQLite and the webhook configuration is only read by the webhooks.
+#![cfg_attr(
+    not(all(feature = "api", feature = "database", feature = "webhooks")),
+    allow(dead_code)
+)]
 
+mod announcements;
+#[cfg(feature = "api")]
 mod api;
 mod app_start;
+#[cfg(feature = "beaconing")]
+mod beaconing;
+mod bp7_interop;
+mod bundle_delivery;
+mod bundle_mailbox;
+mod bundle_parking;
 mod bundle_processing;
+mod bundle_resend;
+mod client_airtime;
+mod clock;
 mod configuration;
 mod database;
+mod delivery_ledger;
+mod diagnostics;
 mod duty_cycle_manager;
 mod end_device_id;
+mod end_device_registry;
 mod error;
+mod file_drop;
+mod frequency_lockouts;
 mod gateway_ids_manager;
+mod gateway_selection;
+mod gateway_send_queues;
+mod gateway_stats;
 mod graceful_shutdown;
+mod inbound_duplicates;
 mod lora_modulation_extraction;
-mod lorawan_protocol;
+mod measurement;
+mod memory_budget;
+mod neighbor_table;
+mod neighbor_trust;
+mod network_filter;
+mod operating_mode;
+mod oversize_bundles;
 mod packet_cache;
+mod packet_export;
 mod packet_queue_manager;
+mod path_cache;
+mod payload_codecs;
+mod plugins;
+mod radio_silence;
+mod radio_stats;
 mod receive_buffers;
+mod received_packets;
 mod routing;
+mod routing_hints;
+mod scheduling_journal;
 mod send_buffers;
+mod startup;
+mod status_reports;
+mod subsystem_control;
+mod topology;
+mod unicast;
 mod uplink_processing;
+mod uplink_trace;
+mod uplink_validation;
+mod watchdog;
+#[cfg(feature = "webhooks")]
+mod webhooks;
 
-use crate::app_start::start_app;
-use crate::configuration::Configuration;
-use crate::database::save_state_to_db;
+use crate::app_start::{setup_logging, start_app};
+use crate::bp7_interop::Bp7Interop;
+use crate::bundle_delivery::LateDelivery;
+use crate::bundle_mailbox::BundleMailbox;
+use crate::bundle_parking::BundleParking;
+use crate::bundle_processing::SubmittedBundle;
+use crate::bundle_resend::BundleResend;
+use crate::client_airtime::ClientAirtime;
+use crate::clock::SharedClock;
+use crate::configuration::{Configuration, NodeProfile};
+use crate::database::{save_shutdown_report, save_state_to_db, DatabaseHealth, DbPool};
+use crate::delivery_ledger::DeliveryLedger;
+use crate::diagnostics::Diagnostics;
 use crate::duty_cycle_manager::DutyCycleManager;
 use crate::end_device_id::ManagedEndDeviceId;
+use crate::end_device_registry::EndDeviceRegistry;
+use crate::frequency_lockouts::FrequencyLockouts;
 use crate::gateway_ids_manager::GatewayIdsManager;
-use crate::graceful_shutdown::{ShutdownConditions, ShutdownGenerator, ShutdownInitiator};
+use crate::gateway_selection::GatewaySelector;
+use crate::gateway_send_queues::GatewaySendQueues;
+use crate::graceful_shutdown::{
+    ShutdownConditions, ShutdownGenerator, ShutdownInitiator, ShutdownReason, ShutdownReport,
+};
+use crate::inbound_duplicates::InboundDuplicateMetrics;
+use crate::measurement::Measurements;
+use crate::memory_budget::MemoryBudget;
+use crate::neighbor_table::NeighborTable;
+use crate::neighbor_trust::NeighborTrust;
+use crate::network_filter::NetworkFilter;
+use crate::operating_mode::OperatingMode;
+use crate::oversize_bundles::OversizeBundles;
+use crate::packet_export::PacketExport;
 use crate::packet_queue_manager::QueueManager;
-use crate::routing::RoutingAlgorithm;
+use crate::path_cache::PathCache;
+use crate::payload_codecs::CodecRegistry;
+use crate::radio_silence::RadioSilence;
+use crate::radio_stats::RadioStats;
+use crate::received_packets::ReceivedPacketLog;
+use crate::routing::{DownlinkRetransmission, RoutingDispatcher};
+use crate::scheduling_journal::SchedulingJournal;
+use crate::startup::StartupSummary;
+use crate::status_reports::StatusReports;
+use crate::subsystem_control::SubsystemControl;
+use crate::unicast::Unicast;
+use crate::uplink_validation::UplinkValidator;
+use crate::watchdog::Watchdog;
 use chirpstack_api_wrapper::ChirpStackApi;
+use chirpstack_gwb_integration::runtime::metrics::CountingMetrics;
 use chrono::Duration;
 use packet_cache::PacketCache;
-use sqlx::SqlitePool;
 use std::collections::HashSet;
 use std::panic::PanicInfo;
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
 use tokio::signal;
 use tokio::sync::{broadcast, mpsc, Mutex};
 use tracing::{error, trace};
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, Layer};
 
 /// Configuration management of the Spatz instance.
 pub struct SpatzConfig {
@@ -69,61 +156,143 @@ impl SpatzConfig {
 /// State of the daemon application.
 pub struct AppState {
     /// Channel from the websocket handler to the bundle handler task.
-    pub bundles_from_ws: mpsc::Sender<bp7::Bundle>,
+    pub bundles_from_ws: mpsc::Sender<SubmittedBundle>,
     /// Channel to the websocket handler for received bundles.
     pub bundles_to_ws: broadcast::Sender<bp7::Bundle>,
     /// The chirpstack_gwb_integration runtime.
     pub runtime: chirpstack_gwb_integration::runtime::Runtime,
+    /// Messages, decode failures, callback latency and publish failures counted by the runtime.
+    pub runtime_metrics: Arc<CountingMetrics>,
     /// The end device IDs used in the daemon.
     pub end_device_ids: Arc<Mutex<HashSet<ManagedEndDeviceId>>>,
+    /// The group IDs this node is a member of.
+    pub group_ids: Arc<Mutex<HashSet<ManagedEndDeviceId>>>,
     /// ChirpStack API information.
     pub chirpstack_api: ChirpStackApi,
     /// Cache to keep track of recently received packets.
     pub packet_cache: PacketCache,
+    /// Source of the current time of the time-dependent components.
+    pub clock: SharedClock,
     /// Duty cycle manager.
     pub duty_cycle_manager: Arc<Mutex<DutyCycleManager>>,
+    /// Frequencies and sub bands locked out from transmissions.
+    pub frequency_lockouts: Arc<FrequencyLockouts>,
     /// Packet and buffer queue manager.
     pub queue_manager: Arc<QueueManager>,
     /// Gateway IDs connected to this spatz.
     pub gateway_ids_manager: GatewayIdsManager,
-    /// The current routing algorithm.
-    pub routing_algo: Box<dyn RoutingAlgorithm>,
-    /// Connection pool to the Sqlite DB.
-    pub db_pool: SqlitePool,
+    /// The current routing algorithms, each responsible for its destination classes.
+    pub routing_dispatcher: RoutingDispatcher,
+    /// Connection pool to the Sqlite DB, the in-memory store without the `database` feature.
+    pub db_pool: DbPool,
     /// Restart initiator.
     pub restart_initiator: ShutdownInitiator,
     /// Configuration management.
     pub configuration: Arc<Mutex<SpatzConfig>>,
+    /// End device IDs announced by neighbors.
+    pub neighbor_table: Arc<Mutex<NeighborTable>>,
+    /// Recently received protocol packets.
+    pub received_packets: Arc<Mutex<ReceivedPacketLog>>,
+    /// Operating mode, degraded if parts of the infrastructure are unavailable.
+    pub operating_mode: Arc<Mutex<OperatingMode>>,
+    /// Typed view on the managed end device IDs and the end device IDs learned from neighbors.
+    pub end_device_registry: EndDeviceRegistry,
+    /// Pending ping and traceroute echo requests.
+    pub diagnostics: Diagnostics,
+    /// Test bundles of the running measurement awaiting their delivery reports.
+    pub measurements: Measurements,
+    /// Status report requests awaiting their bundle and counters of the status reports.
+    pub status_reports: StatusReports,
+    /// Submitted bundles kept for retransmission and counters of the retransmissions.
+    pub bundle_resend: BundleResend,
+    /// Selection of the gateways packets are sent from.
+    pub gateway_selector: Arc<Mutex<GatewaySelector>>,
+    /// Send queues per gateway, packets are handed to the gateways directly if not configured.
+    pub gateway_send_queues: Option<GatewaySendQueues>,
+    /// Acknowledgement counters and retransmission of failed downlinks.
+    pub downlink_retransmission: DownlinkRetransmission,
+    /// Bundles without a known next hop, bundles are flooded right away if not configured.
+    pub bundle_parking: Option<BundleParking>,
+    /// Counters of uplinks suppressed as recently received before parsing.
+    pub inbound_duplicate_metrics: InboundDuplicateMetrics,
+    /// Filter of the received packets by their network ID with counters per network.
+    pub network_filter: NetworkFilter,
+    /// CRC and modulation checks of incoming uplinks with their counters.
+    pub uplink_validator: UplinkValidator,
+    /// Whether packets are fragmented for the payload sizes allowed with a LoRaWAN repeater.
+    pub repeater_compatible: bool,
+    /// Reaction to database errors and counters of the persisted writes.
+    pub database_health: DatabaseHealth,
+    /// Role of the node.
+    pub node_profile: NodeProfile,
+    /// Policy for reassembled bundles past their lifetime and counters of the deliveries.
+    pub late_delivery: LateDelivery,
+    /// Bundles delivered to local applications, duplicates are delivered again if not
+    /// configured.
+    pub delivery_ledger: Option<DeliveryLedger>,
+    /// Delivered bundles kept for clients polling via HTTP, bundles are not kept if not
+    /// configured.
+    pub bundle_mailbox: Option<BundleMailbox>,
+    /// Airtime usage and daily quota of the API clients.
+    pub client_airtime: ClientAirtime,
+    /// Received frames per hour and modulation, not collected if not configured.
+    pub radio_stats: Option<RadioStats>,
+    /// Transmission decisions for the audit of duty cycle violations, not recorded if not
+    /// configured.
+    pub scheduling_journal: Option<SchedulingJournal>,
+    /// Buffered records of the received and sent packets for the research export, not recorded
+    /// if not configured.
+    pub packet_export: Option<PacketExport>,
+    /// Blacklist and trust scores of the nodes sending packets.
+    pub neighbor_trust: NeighborTrust,
+    /// Central gate closing all transmissions during radio silence.
+    pub radio_silence: RadioSilence,
+    /// Report of the shutdown before this start, not set if none was saved.
+    pub last_shutdown: Option<ShutdownReport>,
+    /// Approximate memory usage of the buffers and the optional budget.
+    pub memory_budget: Arc<MemoryBudget>,
+    /// Codecs of the payload profiles bundles are submitted and delivered with.
+    pub payload_codecs: CodecRegistry,
+    /// Policy for bundles exceeding the max size and the statuses of the split bundles.
+    pub oversize_bundles: OversizeBundles,
+    /// Runtime switches pausing the relaying and the announcements.
+    pub subsystem_control: SubsystemControl,
+    /// Directed transmissions to direct neighbors awaiting their acknowledgement, bundles are
+    /// always flooded if not configured.
+    pub unicast: Option<Unicast>,
+    /// Next hop gateways of destinations learned from status reports and echo replies, packets
+    /// are only sent directed to direct neighbors if not configured.
+    pub path_cache: Option<PathCache>,
+    /// Framing of bundles as CBOR encoded BP7 fragments, bundles are always sent with the
+    /// custom headers if not configured.
+    pub bp7_interop: Option<Bp7Interop>,
+    /// Heartbeats of the supervised internal tasks.
+    pub watchdog: Watchdog,
+    /// Outcome of the startup steps, set once the startup completed.
+    pub startup_summary: OnceLock<StartupSummary>,
 }
 
 #[tokio::main]
 async fn main() {
-    #[cfg(debug_assertions)]
-    let filter_directives =
-        std::env::var("RUST_LOG").unwrap_or_else(|_| "spatz=trace,tower_http=trace".into());
-
-    #[cfg(not(debug_assertions))]
-    let filter_directives = std::env::var("RUST_LOG").unwrap_or_else(|_| "spatz=error".into());
-
-    tracing_subscriber::registry()
-        // RUST_LOG=spatz::uplink_processing
-        .with(
-            tracing_subscriber::fmt::layer()
-                .with_filter(tracing_subscriber::EnvFilter::new(filter_directives)),
-        )
-        .init();
-
+    setup_logging();
     loop {
         // repeatable to restart systems
         let graceful_shutdown_generator = ShutdownGenerator::new();
         let shutdown_agent = graceful_shutdown_generator.generate_agent();
         let panic_shutdown_initiator = graceful_shutdown_generator.generate_initiator();
         let app_shutdown_initiator = graceful_shutdown_generator.generate_initiator();
+        let database_shutdown_initiator = graceful_shutdown_generator.generate_initiator();
         // Remove earlier custom panic hook.
         let _ = std::panic::take_hook();
         let default_panic = std::panic::take_hook();
         set_panic_hook(default_panic, panic_shutdown_initiator);
-        let Ok(state) = start_app(shutdown_agent, app_shutdown_initiator).await else {
+        let Ok(state) = start_app(
+            shutdown_agent,
+            app_shutdown_initiator,
+            database_shutdown_initiator,
+        )
+        .await
+        else {
             return;
         };
 
@@ -136,7 +305,10 @@ async fn main() {
                         // do graceful shutdown routine
                         trace!("Graceful shutdown initiated");
                         shutdown_control.start_shutdown();
-                        shutdown_control.await_complete_shutdown(15).await;
+                        let graceful = shutdown_control.await_complete_shutdown(15).await;
+                        let reason =
+                            ShutdownReason::new(ShutdownConditions::Interrupted, module_path!());
+                        save_shutdown_report(&state, reason, graceful).await;
                         save_state_to_db(state).await;
                         return;
                     }
@@ -147,37 +319,35 @@ async fn main() {
                 }
             },
             shutdown_initiation = shutdown_control.await_shutdown_initiation() => {
-                if let Some(shutdown_initiation) = shutdown_initiation {
-                    match shutdown_initiation {
-                        ShutdownConditions::Panic => {
-                            trace!("Some task panicked, shutting down");
-                            shutdown_control.start_shutdown();
-                            shutdown_control.await_complete_shutdown(15).await;
-                        },
+                if let Some(reason) = shutdown_initiation {
+                    let condition = reason.condition;
+                    match condition {
+                        ShutdownConditions::Panic => trace!("Some task panicked, shutting down"),
                         ShutdownConditions::MqttError => {
                             trace!("MQTT connection error, shutting down");
-                            shutdown_control.start_shutdown();
-                            shutdown_control.await_complete_shutdown(15).await;
-                        }
-                        ShutdownConditions::GatewayRetrievalFailed => {
-                            trace!("Failed to retrieve gateways, shutting down");
-                            shutdown_control.start_shutdown();
-                            shutdown_control.await_complete_shutdown(15).await;
                         }
                         ShutdownConditions::AxumStartFailed => {
                             trace!("Failed to start axum server, shutting down");
-                            shutdown_control.start_shutdown();
-                            shutdown_control.await_complete_shutdown(15).await;
                         }
-                        ShutdownConditions::Restart => {
-                            trace!("Restarting all Spatz");
-                            shutdown_control.start_shutdown();
-                            shutdown_control.await_complete_shutdown(15).await;
-                            save_state_to_db(state).await;
-                            continue;
+                        ShutdownConditions::DatabaseError => {
+                            trace!("Database error, shutting down without saving state");
+                        }
+                        ShutdownConditions::Restart => trace!("Restarting all Spatz"),
+                        ShutdownConditions::Interrupted => trace!("Interrupted, shutting down"),
+                        ShutdownConditions::TaskStalled => {
+                            trace!("Task missed its heartbeat, shutting down");
                         }
                     }
+                    shutdown_control.start_shutdown();
+                    let graceful = shutdown_control.await_complete_shutdown(15).await;
+                    save_shutdown_report(&state, reason, graceful).await;
+                    if condition == ShutdownConditions::DatabaseError {
+                        return;
+                    }
                     save_state_to_db(state).await;
+                    if condition == ShutdownConditions::Restart {
+                        continue;
+                    }
                 } else {
                     trace!("No more shutdown agents, shutting down");
                 }
@@ -196,6 +366,17 @@ fn set_panic_hook(
 ) {
     std::panic::set_hook(Box::new(move |panic_info| {
         default_panic(panic_info);
-        shutdown_initiator.initiate_shutdown(ShutdownConditions::Panic);
+        let location = panic_info
+            .location()
+            .map_or_else(|| "unknown".to_owned(), ToString::to_string);
+        let payload = panic_info.payload();
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|message| (*message).to_owned())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+        shutdown_initiator.initiate_shutdown(
+            ShutdownReason::new(ShutdownConditions::Panic, &location).with_message(message),
+        );
     }));
 }
@@ -0,0 +1,522 @@
+//! End-to-end measurement of delivery ratio, latency and airtime cost, e.g. for field acceptance
+//! tests.
+//!
+//! A measurement sends a train of test bundles to a destination, each requesting a delivery
+//! status report, and waits for the reports. The latency of a test bundle is the time from its
+//! submission until its delivery report is received, the airtime cost is the airtime of the
+//! packets this node produced for the test bundles. The destination only reports bundles which it
+//! delivered to a local application, e.g. a connected WebSocket client. Reports of test bundles
+//! are consumed by the measurement and not delivered to local applications.
+
+use crate::bundle_processing::SubmittedBundle;
+use crate::diagnostics::local_end_device_id;
+use crate::end_device_id::EndDeviceId;
+use crate::error::MeasurementError;
+use crate::routing_hints::RoutingHints;
+use crate::send_buffers::BundleSendBuffer;
+use crate::AppState;
+use bp7::flags::{BlockControlFlags, BundleControlFlags};
+use chrono::{DateTime, Utc};
+use lorawan_dtn_protocol::unix_ts_to_dtn_time;
+use lorawan_dtn_protocol::{StatusReport, STATUS_DELIVERED};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use tracing::{info, trace};
+
+/// API client the airtime of the test bundles is accounted to.
+pub const MEASUREMENT_CLIENT: &str = "measurement";
+
+/// Max amount of test bundles of a measurement.
+const MAX_BUNDLES: u32 = 100;
+
+/// Default amount of test bundles.
+const DEFAULT_BUNDLES: u32 = 10;
+
+/// Default payload size of the test bundles in bytes.
+const DEFAULT_PAYLOAD_SIZE: usize = 32;
+
+/// Default time between two test bundles in seconds.
+const DEFAULT_INTERVAL_SECONDS: u64 = 10;
+
+/// Default time to wait for the reports after the last test bundle in seconds.
+const DEFAULT_TIMEOUT_SECONDS: u64 = 600;
+
+/// Parameters of a measurement.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct MeasurementParameter {
+    /// End device ID the test bundles are sent to.
+    pub destination: EndDeviceId,
+    /// Amount of test bundles, at most 100, defaults to 10.
+    pub bundles: Option<u32>,
+    /// Payload size of the test bundles in bytes, defaults to 32.
+    pub payload_size: Option<usize>,
+    /// Time between two test bundles in seconds, at least 1 as test bundles are identified by
+    /// their creation second, defaults to 10.
+    pub interval_seconds: Option<u64>,
+    /// Time to wait for the reports after the last test bundle in seconds, also the lifetime of
+    /// the test bundles, defaults to 600.
+    pub timeout_seconds: Option<u64>,
+}
+
+/// Distribution of the latencies of the delivered test bundles in milliseconds.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, JsonSchema)]
+pub struct LatencyDistribution {
+    /// Lowest latency.
+    pub min_ms: u64,
+    /// Mean latency.
+    pub mean_ms: u64,
+    /// Median latency.
+    pub p50_ms: u64,
+    /// 90th percentile of the latencies.
+    pub p90_ms: u64,
+    /// Highest latency.
+    pub max_ms: u64,
+}
+
+impl LatencyDistribution {
+    /// Returns the distribution of the latencies, `None` without latencies.
+    fn of(latencies_ms: &[u64]) -> Option<Self> {
+        let mut sorted = latencies_ms.to_vec();
+        sorted.sort_unstable();
+        let count = u64::try_from(sorted.len()).ok()?;
+        let sum = sorted
+            .iter()
+            .fold(0_u64, |sum, latency| sum.saturating_add(*latency));
+        Some(Self {
+            min_ms: *sorted.first()?,
+            mean_ms: sum.checked_div(count)?,
+            p50_ms: percentile(&sorted, 50)?,
+            p90_ms: percentile(&sorted, 90)?,
+            max_ms: *sorted.last()?,
+        })
+    }
+}
+
+/// Returns the nearest-rank percentile of the sorted values.
+fn percentile(sorted: &[u64], percent: usize) -> Option<u64> {
+    let rank = sorted
+        .len()
+        .saturating_mul(percent)
+        .saturating_add(99)
+        .saturating_div(100)
+        .max(1);
+    sorted.get(rank.saturating_sub(1)).copied()
+}
+
+/// Result of a single test bundle.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, JsonSchema)]
+pub struct TestBundleResult {
+    /// Sequence number of the test bundle, starting at 0.
+    pub sequence: u32,
+    /// Creation timestamp of the test bundle.
+    pub timestamp: DateTime<Utc>,
+    /// Time until the delivery report was received, `None` if no report was received in time.
+    pub latency_ms: Option<u64>,
+}
+
+/// Report of a measurement.
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+pub struct MeasurementReport {
+    /// End device ID the test bundles were sent to.
+    pub destination: EndDeviceId,
+    /// Time the first test bundle was submitted.
+    pub started_at: DateTime<Utc>,
+    /// Time the measurement finished.
+    pub finished_at: DateTime<Utc>,
+    /// Payload size of the test bundles in bytes.
+    pub payload_size: usize,
+    /// Submitted test bundles.
+    pub sent: u32,
+    /// Test bundles whose delivery was reported in time.
+    pub delivered: u32,
+    /// Share of the delivered test bundles, between 0 and 1.
+    pub delivery_ratio: f64,
+    /// Latencies of the delivered test bundles, `None` if no test bundle was delivered.
+    pub latency: Option<LatencyDistribution>,
+    /// Airtime of the packets this node produced for the test bundles in milliseconds.
+    pub airtime_ms: f64,
+    /// Airtime per delivered test bundle in milliseconds, `None` if no test bundle was
+    /// delivered.
+    pub airtime_ms_per_delivery: Option<f64>,
+    /// Results of the single test bundles.
+    pub bundles: Vec<TestBundleResult>,
+}
+
+/// Key of a test bundle: source and timestamp, as carried by its status reports.
+type TestBundleKey = (EndDeviceId, DateTime<Utc>);
+
+/// Submitted test bundle awaiting its delivery report.
+#[derive(Debug, Clone, Copy)]
+struct PendingTestBundle {
+    /// Time the test bundle was submitted.
+    submitted: Instant,
+    /// Time until the delivery report was received.
+    latency: Option<Duration>,
+}
+
+/// Test bundles of the running measurement.
+///
+/// Uses a [`std::sync::Mutex`] as the status reports are matched without awaiting.
+#[derive(Debug, Default)]
+pub struct Measurements {
+    /// Held while a measurement runs, only one measurement runs at a time.
+    running: tokio::sync::Mutex<()>,
+    /// Test bundles by source and timestamp.
+    pending: Mutex<HashMap<TestBundleKey, PendingTestBundle>>,
+    /// Notified on every received delivery report of a test bundle.
+    delivered: Notify,
+}
+
+impl Measurements {
+    /// Creates a new [`Measurements`] without running measurement.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Consumes a status report of a test bundle, returns whether the report belongs to a test
+    /// bundle. Reports of other bundles are left to the local applications.
+    pub fn process_report(&self, report: &StatusReport) -> bool {
+        let mut pending = self.lock();
+        let Some(test_bundle) = pending.get_mut(&(report.bundle_source, report.bundle_timestamp))
+        else {
+            return false;
+        };
+        if report.status & STATUS_DELIVERED != 0 && test_bundle.latency.is_none() {
+            trace!("Delivery of test bundle reported");
+            test_bundle.latency = Some(test_bundle.submitted.elapsed());
+            self.delivered.notify_one();
+        }
+        true
+    }
+
+    /// Registers a submitted test bundle.
+    fn register(&self, key: TestBundleKey) {
+        self.lock().insert(
+            key,
+            PendingTestBundle {
+                submitted: Instant::now(),
+                latency: None,
+            },
+        );
+    }
+
+    /// Returns whether the delivery of all test bundles was reported.
+    fn all_delivered(&self, keys: &[TestBundleKey]) -> bool {
+        let pending = self.lock();
+        keys.iter().all(|key| {
+            pending
+                .get(key)
+                .is_some_and(|test_bundle| test_bundle.latency.is_some())
+        })
+    }
+
+    /// Removes the test bundles, returns their latencies in the order of the keys.
+    fn take(&self, keys: &[TestBundleKey]) -> Vec<Option<Duration>> {
+        let mut pending = self.lock();
+        keys.iter()
+            .map(|key| {
+                pending
+                    .remove(key)
+                    .and_then(|test_bundle| test_bundle.latency)
+            })
+            .collect()
+    }
+
+    /// Locks the test bundles, a poisoned lock is recovered as the entries stay consistent.
+    fn lock(&self) -> MutexGuard<'_, HashMap<TestBundleKey, PendingTestBundle>> {
+        self.pending.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Creates a test bundle requesting a delivery report to the source, its payload starts with the
+/// sequence number.
+///
+/// # Errors
+///
+/// Returns an error if source or destination cannot be converted into endpoint IDs or the
+/// primary block cannot be built.
+fn test_bundle(
+    source: EndDeviceId,
+    destination: EndDeviceId,
+    sequence: u32,
+    payload_size: usize,
+    lifetime: Duration,
+    timestamp: DateTime<Utc>,
+) -> Result<bp7::Bundle, MeasurementError> {
+    let primary = bp7::primary::PrimaryBlockBuilder::new()
+        .source(source.try_into()?)
+        .destination(destination.try_into()?)
+        .report_to(source.try_into()?)
+        .creation_timestamp(bp7::CreationTimestamp::with_time_and_seq(
+            unix_ts_to_dtn_time(timestamp.timestamp().unsigned_abs()),
+            0,
+        ))
+        .lifetime(lifetime)
+        .bundle_control_flags(BundleControlFlags::BUNDLE_STATUS_REQUEST_DELIVERY.bits())
+        .build()?;
+    let mut payload = sequence.to_be_bytes().to_vec();
+    payload.resize(payload_size, 0);
+    let canonical = bp7::canonical::new_payload_block(BlockControlFlags::empty(), payload);
+    Ok(bp7::Bundle::new(primary, vec![canonical]))
+}
+
+/// Returns the airtime accounted to the test bundles so far in milliseconds.
+async fn measurement_airtime_ms(state: &AppState) -> f64 {
+    state
+        .client_airtime
+        .usage()
+        .await
+        .get(MEASUREMENT_CLIENT)
+        .map_or(0.0, |usage| usage.total_ms)
+}
+
+/// Sends the test bundles of the measurement and waits for their delivery reports until all
+/// were received or the timeout after the last test bundle elapsed.
+///
+/// # Errors
+///
+/// Returns an error if:
+/// - another measurement is running.
+/// - the amount of test bundles, the payload size or the interval is invalid.
+/// - no end device ID is managed by this node to receive the reports.
+/// - a test bundle cannot be created or sent via LoRaWAN.
+/// - the bundle processing task stopped.
+pub async fn measure(
+    state: &AppState,
+    parameter: &MeasurementParameter,
+) -> Result<MeasurementReport, MeasurementError> {
+    let Ok(_running) = state.measurements.running.try_lock() else {
+        return Err(MeasurementError::AlreadyRunning);
+    };
+    let bundles = parameter.bundles.unwrap_or(DEFAULT_BUNDLES);
+    if bundles == 0 || bundles > MAX_BUNDLES {
+        return Err(MeasurementError::InvalidBundleAmount { max: MAX_BUNDLES });
+    }
+    let payload_size = parameter.payload_size.unwrap_or(DEFAULT_PAYLOAD_SIZE);
+    if payload_size == 0 {
+        return Err(MeasurementError::EmptyPayload);
+    }
+    let interval_seconds = parameter
+        .interval_seconds
+        .unwrap_or(DEFAULT_INTERVAL_SECONDS);
+    if interval_seconds == 0 {
+        return Err(MeasurementError::ZeroInterval);
+    }
+    let timeout = Duration::from_secs(parameter.timeout_seconds.unwrap_or(DEFAULT_TIMEOUT_SECONDS));
+    let source = local_end_device_id(state)
+        .await
+        .ok_or(MeasurementError::NoLocalEndDeviceId)?;
+
+    info!(
+        "Measuring the delivery to {:?} with {bundles} test bundles",
+        parameter.destination
+    );
+    let airtime_before_ms = measurement_airtime_ms(state).await;
+    let started_at = Utc::now();
+    let mut keys = Vec::new();
+    let mut interval = tokio::time::interval(Duration::from_secs(interval_seconds));
+    for sequence in 0..bundles {
+        interval.tick().await;
+        let submitted = test_bundle(
+            source,
+            parameter.destination,
+            sequence,
+            payload_size,
+            timeout,
+            Utc::now(),
+        )
+        .and_then(|bundle| {
+            let send_buffer =
+                BundleSendBuffer::from_bundle(bundle.clone(), state.repeater_compatible)?;
+            Ok((bundle, (send_buffer.source(), send_buffer.timestamp())))
+        });
+        let (bundle, key) = match submitted {
+            Ok(submitted) => submitted,
+            Err(err) => {
+                state.measurements.take(&keys);
+                return Err(err);
+            }
+        };
+        state.measurements.register(key);
+        keys.push(key);
+        let submitted_bundle = SubmittedBundle {
+            bundle,
+            client: Some(MEASUREMENT_CLIENT.to_owned()),
+            routing_hints: RoutingHints::default(),
+        };
+        if state.bundles_from_ws.send(submitted_bundle).await.is_err() {
+            state.measurements.take(&keys);
+            return Err(MeasurementError::QueueClosed);
+        }
+    }
+
+    let deadline = tokio::time::Instant::now() + timeout;
+    while !state.measurements.all_delivered(&keys) {
+        if tokio::time::timeout_at(deadline, state.measurements.delivered.notified())
+            .await
+            .is_err()
+        {
+            break;
+        }
+    }
+    let latencies = state.measurements.take(&keys);
+    let airtime_ms = (measurement_airtime_ms(state).await - airtime_before_ms).max(0.0);
+    Ok(report(
+        parameter.destination,
+        started_at,
+        Utc::now(),
+        payload_size,
+        &keys,
+        &latencies,
+        airtime_ms,
+    ))
+}
+
+/// Builds the report from the latencies of the test bundles.
+fn report(
+    destination: EndDeviceId,
+    started_at: DateTime<Utc>,
+    finished_at: DateTime<Utc>,
+    payload_size: usize,
+    keys: &[TestBundleKey],
+    latencies: &[Option<Duration>],
+    airtime_ms: f64,
+) -> MeasurementReport {
+    let bundles: Vec<TestBundleResult> = keys
+        .iter()
+        .zip(latencies)
+        .zip(0..)
+        .map(|(((_, timestamp), latency), sequence)| TestBundleResult {
+            sequence,
+            timestamp: *timestamp,
+            latency_ms: latency
+                .map(|latency| u64::try_from(latency.as_millis()).unwrap_or(u64::MAX)),
+        })
+        .collect();
+    let latencies_ms: Vec<u64> = bundles
+        .iter()
+        .filter_map(|bundle| bundle.latency_ms)
+        .collect();
+    let sent = u32::try_from(bundles.len()).unwrap_or(u32::MAX);
+    let delivered = u32::try_from(latencies_ms.len()).unwrap_or(u32::MAX);
+    MeasurementReport {
+        destination,
+        started_at,
+        finished_at,
+        payload_size,
+        sent,
+        delivered,
+        delivery_ratio: if sent == 0 {
+            0.0
+        } else {
+            f64::from(delivered) / f64::from(sent)
+        },
+        latency: LatencyDistribution::of(&latencies_ms),
+        airtime_ms,
+        airtime_ms_per_delivery: (delivered > 0).then(|| airtime_ms / f64::from(delivered)),
+        bundles,
+    }
+}
+
+/// Requests a measurement from the API of a running node, returns the JSON report.
+///
+/// # Errors
+///
+/// Returns an error if the request fails or the node rejects the measurement.
+#[cfg(feature = "api")]
+pub async fn request_measurement(
+    api_url: &str,
+    api_token: Option<&str>,
+    parameter: &MeasurementParameter,
+) -> Result<String, reqwest::Error> {
+    let mut request = reqwest::Client::new()
+        .post(format!(
+            "{}/api/diagnostics/measure",
+            api_url.trim_end_matches('/')
+        ))
+        .json(parameter);
+    if let Some(api_token) = api_token {
+        request = request.bearer_auth(api_token);
+    }
+    request.send().await?.error_for_status()?.text().await
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use crate::end_device_id::EndDeviceId;
+    use crate::measurement::{report, LatencyDistribution, Measurements};
+    use chrono::{TimeZone, Utc};
+    use lorawan_dtn_protocol::{StatusReport, STATUS_DELIVERED, STATUS_RECEIVED};
+    use std::time::Duration;
+
+    #[test]
+    fn latency_distribution_uses_nearest_rank() {
+        let distribution = LatencyDistribution::of(&[400, 100, 300, 200, 1000]).unwrap();
+        assert_eq!(distribution.min_ms, 100);
+        assert_eq!(distribution.mean_ms, 400);
+        assert_eq!(distribution.p50_ms, 300);
+        assert_eq!(distribution.p90_ms, 1000);
+        assert_eq!(distribution.max_ms, 1000);
+        assert!(LatencyDistribution::of(&[]).is_none());
+    }
+
+    #[test]
+    fn delivery_reports_of_test_bundles_are_consumed() {
+        let measurements = Measurements::new();
+        let timestamp = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        let key = (EndDeviceId(0x1234), timestamp);
+        measurements.register(key);
+        let mut status_report = StatusReport {
+            destination: EndDeviceId(0x1234),
+            source: EndDeviceId(0x5678),
+            status: STATUS_RECEIVED,
+            reason: 0,
+            bundle_source: EndDeviceId(0x1234),
+            bundle_timestamp: timestamp,
+        };
+        assert!(measurements.process_report(&status_report));
+        assert!(!measurements.all_delivered(&[key]));
+        status_report.status = STATUS_DELIVERED;
+        assert!(measurements.process_report(&status_report));
+        assert!(measurements.all_delivered(&[key]));
+        status_report.bundle_source = EndDeviceId(0x9abc);
+        assert!(!measurements.process_report(&status_report));
+
+        let latencies = measurements.take(&[key]);
+        assert!(latencies[0].is_some());
+        assert!(measurements.take(&[key])[0].is_none());
+    }
+
+    #[test]
+    fn report_summarizes_the_test_bundles() {
+        let timestamp = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        let keys = [
+            (EndDeviceId(0x1234), timestamp),
+            (
+                EndDeviceId(0x1234),
+                timestamp + chrono::Duration::seconds(10),
+            ),
+        ];
+        let report = report(
+            EndDeviceId(0x5678),
+            timestamp,
+            timestamp + chrono::Duration::seconds(60),
+            32,
+            &keys,
+            &[Some(Duration::from_millis(1500)), None],
+            400.0,
+        );
+        assert_eq!(report.sent, 2);
+        assert_eq!(report.delivered, 1);
+        assert!((report.delivery_ratio - 0.5).abs() < f64::EPSILON);
+        assert_eq!(report.latency.unwrap().p50_ms, 1500);
+        assert!((report.airtime_ms_per_delivery.unwrap() - 400.0).abs() < f64::EPSILON);
+        assert_eq!(report.bundles[1].sequence, 1);
+        assert_eq!(report.bundles[1].latency_ms, None);
+    }
+}
@@ -0,0 +1,250 @@
+//! Global memory budget of the buffers for constrained hardware.
+//!
+//! The receive buffers, the send queues and the packet cache report their approximate size.
+//! While the budget is exhausted, incomplete receive buffers and the oldest packet cache entries
+//! are evicted and new queue entries are rejected.
+
+use crate::configuration::MemoryBudgetConfig;
+use crate::graceful_shutdown::ShutdownAgent;
+use crate::send_buffers::BundleSendBuffer;
+use crate::AppState;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tracing::{instrument, trace, warn};
+
+/// Approximate size of a queued or buffered LoRaWAN packet, the max LoRa PHY payload.
+pub const PACKET_SIZE: u64 = 256;
+
+/// Approximate size of a packet cache entry, the hex encoded hash and its timestamp.
+pub const PACKET_CACHE_ENTRY_SIZE: u64 = 96;
+
+/// Interval at which the sizes of the queues and the packet cache are measured.
+const MEASURE_INTERVAL_SECONDS: u64 = 5;
+
+/// Buffers accounted in the memory budget.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum BufferCategory {
+    /// Receive buffers of incomplete bundles and hop2hop packets.
+    ReceiveBuffers,
+    /// Relay, bundle and announcement queues.
+    Queues,
+    /// Hashes of recently seen packets.
+    PacketCache,
+}
+
+/// Approximate memory usage of the buffers and counters of the budget enforcement.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, JsonSchema)]
+pub struct MemoryBudgetStats {
+    /// Max approximate bytes of all buffers, unbounded if not set.
+    pub max_bytes: Option<u64>,
+    /// Approximate bytes of the receive buffers.
+    pub receive_buffers_bytes: u64,
+    /// Approximate bytes of the relay, bundle and announcement queues.
+    pub queues_bytes: u64,
+    /// Approximate bytes of the packet cache.
+    pub packet_cache_bytes: u64,
+    /// Fragments and queue entries rejected as the budget was exhausted.
+    pub rejected: u64,
+    /// Receive buffers and packet cache entries evicted to stay within the budget.
+    pub evicted: u64,
+}
+
+/// Tracks the approximate size of the buffers against the configured budget.
+#[derive(Debug)]
+pub struct MemoryBudget {
+    /// Max approximate bytes of all buffers, unbounded if not set.
+    max_bytes: Option<u64>,
+    /// Approximate bytes of the receive buffers.
+    receive_buffers: AtomicU64,
+    /// Approximate bytes of the queues.
+    queues: AtomicU64,
+    /// Approximate bytes of the packet cache.
+    packet_cache: AtomicU64,
+    /// Rejected fragments and queue entries.
+    rejected: AtomicU64,
+    /// Evicted receive buffers and packet cache entries.
+    evicted: AtomicU64,
+}
+
+impl MemoryBudget {
+    /// Creates a new [`MemoryBudget`], only accounting the sizes if not configured.
+    pub fn new(config: Option<&MemoryBudgetConfig>) -> Self {
+        Self {
+            max_bytes: config.map(|config| config.max_bytes),
+            receive_buffers: AtomicU64::new(0),
+            queues: AtomicU64::new(0),
+            packet_cache: AtomicU64::new(0),
+            rejected: AtomicU64::new(0),
+            evicted: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the counter of the category.
+    fn usage(&self, category: BufferCategory) -> &AtomicU64 {
+        match category {
+            BufferCategory::ReceiveBuffers => &self.receive_buffers,
+            BufferCategory::Queues => &self.queues,
+            BufferCategory::PacketCache => &self.packet_cache,
+        }
+    }
+
+    /// Returns the approximate bytes of all buffers.
+    pub fn used(&self) -> u64 {
+        self.receive_buffers
+            .load(Ordering::Relaxed)
+            .saturating_add(self.queues.load(Ordering::Relaxed))
+            .saturating_add(self.packet_cache.load(Ordering::Relaxed))
+    }
+
+    /// Returns by how many bytes the budget is exceeded, `0` if within the budget.
+    pub fn excess(&self) -> u64 {
+        self.max_bytes
+            .map_or(0, |max_bytes| self.used().saturating_sub(max_bytes))
+    }
+
+    /// Returns whether `bytes` more fit into the budget.
+    pub fn fits(&self, bytes: u64) -> bool {
+        match self.max_bytes {
+            Some(max_bytes) => self.used().saturating_add(bytes) <= max_bytes,
+            None => true,
+        }
+    }
+
+    /// Replaces the measured size of the category.
+    pub fn set_usage(&self, category: BufferCategory, bytes: u64) {
+        self.usage(category).store(bytes, Ordering::Relaxed);
+    }
+
+    /// Accounts `bytes` to the category if they fit into the budget, counts a rejection
+    /// otherwise.
+    ///
+    /// Returns whether the bytes fit.
+    pub fn reserve(&self, category: BufferCategory, bytes: u64) -> bool {
+        if self.fits(bytes) {
+            self.usage(category).fetch_add(bytes, Ordering::Relaxed);
+            true
+        } else {
+            self.rejected.fetch_add(1, Ordering::Relaxed);
+            false
+        }
+    }
+
+    /// Counts evicted receive buffers or packet cache entries.
+    pub fn record_evictions(&self, evicted: u64) {
+        self.evicted.fetch_add(evicted, Ordering::Relaxed);
+    }
+
+    /// Returns the current sizes and counters.
+    pub fn stats(&self) -> MemoryBudgetStats {
+        MemoryBudgetStats {
+            max_bytes: self.max_bytes,
+            receive_buffers_bytes: self.receive_buffers.load(Ordering::Relaxed),
+            queues_bytes: self.queues.load(Ordering::Relaxed),
+            packet_cache_bytes: self.packet_cache.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+            evicted: self.evicted.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Returns the approximate size of a queued bundle.
+pub fn bundle_size(bundle: &BundleSendBuffer) -> u64 {
+    u64::try_from(bundle.payload_len())
+        .unwrap_or(u64::MAX)
+        .saturating_add(PACKET_SIZE)
+}
+
+/// Task measuring the sizes of the queues and the packet cache, evicts the oldest packet cache
+/// entries while over the budget.
+#[instrument(skip_all)]
+pub async fn memory_budget_task(state: Arc<AppState>, mut shutdown_agent: ShutdownAgent) {
+    trace!("Starting up");
+    loop {
+        let queue_manager = &state.queue_manager;
+        let queued_packets = queue_manager.relay_packet_queue.lock().await.len()
+            + queue_manager.announcement_queue.lock().await.len();
+        let queued_bundles: u64 = queue_manager
+            .bundle_send_buffer_queue
+            .lock()
+            .await
+            .iter()
+            .map(bundle_size)
+            .sum();
+        let memory_budget = &state.memory_budget;
+        memory_budget.set_usage(
+            BufferCategory::Queues,
+            u64::try_from(queued_packets)
+                .unwrap_or(u64::MAX)
+                .saturating_mul(PACKET_SIZE)
+                .saturating_add(queued_bundles),
+        );
+        let cached_packets =
+            u64::try_from(state.packet_cache.cached_packets().await).unwrap_or(u64::MAX);
+        memory_budget.set_usage(
+            BufferCategory::PacketCache,
+            cached_packets.saturating_mul(PACKET_CACHE_ENTRY_SIZE),
+        );
+
+        let excess = memory_budget.excess();
+        if excess > 0 {
+            let evicted = state
+                .packet_cache
+                .evict_oldest(
+                    usize::try_from(excess.div_ceil(PACKET_CACHE_ENTRY_SIZE)).unwrap_or(usize::MAX),
+                )
+                .await;
+            warn!(
+                excess,
+                evicted, "Memory budget exceeded, evicted packet cache entries"
+            );
+            memory_budget.record_evictions(u64::try_from(evicted).unwrap_or(u64::MAX));
+            memory_budget.set_usage(
+                BufferCategory::PacketCache,
+                cached_packets
+                    .saturating_sub(u64::try_from(evicted).unwrap_or(u64::MAX))
+                    .saturating_mul(PACKET_CACHE_ENTRY_SIZE),
+            );
+        }
+
+        let interval = tokio::time::Duration::from_secs(MEASURE_INTERVAL_SECONDS);
+        tokio::select! {
+            () = tokio::time::sleep(interval) => {}
+            _ = shutdown_agent.await_shutdown() => {
+                trace!("Shutting down");
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use crate::configuration::MemoryBudgetConfig;
+    use crate::memory_budget::{BufferCategory, MemoryBudget};
+
+    #[test]
+    fn reservations_rejected_over_budget() {
+        let memory_budget = MemoryBudget::new(Some(&MemoryBudgetConfig { max_bytes: 1000 }));
+        memory_budget.set_usage(BufferCategory::PacketCache, 600);
+        assert!(memory_budget.reserve(BufferCategory::Queues, 300));
+        assert!(!memory_budget.reserve(BufferCategory::ReceiveBuffers, 200));
+        assert_eq!(memory_budget.excess(), 0);
+
+        memory_budget.set_usage(BufferCategory::PacketCache, 900);
+        assert_eq!(memory_budget.excess(), 200);
+        memory_budget.record_evictions(3);
+
+        let stats = memory_budget.stats();
+        assert_eq!(
+            (stats.queues_bytes, stats.rejected, stats.evicted),
+            (300, 1, 3)
+        );
+
+        let unbounded = MemoryBudget::new(None);
+        assert!(unbounded.reserve(BufferCategory::Queues, u64::MAX / 2));
+        assert_eq!(unbounded.excess(), 0);
+    }
+}
@@ -0,0 +1,839 @@
+//! Neighbor table keeping track of end device IDs announced by neighboring nodes.
+
+use crate::end_device_id::EndDeviceId;
+use crate::graceful_shutdown::ShutdownAgent;
+use crate::AppState;
+use chirpstack_gwb_integration::gateway_id::GatewayId;
+use chrono::{DateTime, Utc};
+use lorawan_dtn_protocol::{
+    CapabilityAnnouncement, ChannelPlanAnnouncement, DataRateAnnouncement, LocalAnnouncement,
+    ReachabilityAnnouncement, ReachableEndDeviceId, ServiceAnnouncement, ServiceTag,
+    DEFAULT_RECEIVE_DATA_RATES,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{instrument, trace};
+
+/// Interval between removals of expired neighbor table entries.
+const EXPIRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How an end device ID is reachable via a neighbor.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize, JsonSchema)]
+pub enum Reachability {
+    /// The end device ID is registered at the neighbor itself.
+    Own,
+    /// The end device ID is reachable through the neighbor.
+    Proxied,
+}
+
+/// Signal quality of a received uplink.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct SignalQuality {
+    /// Received signal strength indicator in dBm.
+    pub rssi: i32,
+    /// Signal to noise ratio in dB.
+    pub snr: f32,
+}
+
+impl From<&chirpstack_api::gw::UplinkRxInfo> for SignalQuality {
+    fn from(rx_info: &chirpstack_api::gw::UplinkRxInfo) -> Self {
+        Self {
+            rssi: rx_info.rssi,
+            snr: rx_info.snr,
+        }
+    }
+}
+
+impl SignalQuality {
+    /// Returns whether the signal quality reaches both thresholds.
+    pub fn reaches(&self, min_rssi: i32, min_snr: i32) -> bool {
+        #[allow(clippy::cast_precision_loss)]
+        let min_snr = min_snr as f32;
+        self.rssi >= min_rssi && self.snr >= min_snr
+    }
+}
+
+/// Information about how an end device ID can be reached via a neighbor.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct NeighborEntry {
+    /// Whether the end device ID is registered at the neighbor or proxied by it.
+    pub reachability: Reachability,
+    /// Amount of hops to the node the end device ID is registered at, 1 for
+    /// [`Reachability::Own`].
+    pub hop_distance: u8,
+    /// The neighbor which announced the entry, identified by the first end device ID of its
+    /// announcement.
+    pub neighbor: EndDeviceId,
+    /// The gateway which received the last announcement.
+    pub gateway_id: GatewayId,
+    /// The signal quality of the last announcement.
+    pub signal_quality: SignalQuality,
+    /// Time of the last announcement.
+    pub last_seen: DateTime<Utc>,
+}
+
+/// Services a neighbor announced for one of its end device IDs.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct NeighborServices {
+    /// Application defined service tags, e.g. ports.
+    pub services: Vec<ServiceTag>,
+    /// The gateway which received the last service announcement.
+    pub gateway_id: GatewayId,
+    /// Time of the last service announcement.
+    pub last_seen: DateTime<Utc>,
+}
+
+/// Channels a neighbor announces on and listens to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct NeighborChannelPlan {
+    /// Frequencies of the channel plan in Hz.
+    pub frequencies: Vec<u32>,
+    /// The gateway which received the last channel plan announcement.
+    pub gateway_id: GatewayId,
+    /// Time of the last channel plan announcement.
+    pub last_seen: DateTime<Utc>,
+}
+
+/// Protocol capabilities a neighbor announced for its end device IDs.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct NeighborCapabilities {
+    /// Bit set of the supported capabilities, e.g.
+    /// [`CAPABILITY_BP7_CBOR`](lorawan_dtn_protocol::CAPABILITY_BP7_CBOR).
+    pub capabilities: u16,
+    /// The gateway which received the last capability announcement.
+    pub gateway_id: GatewayId,
+    /// Time of the last capability announcement.
+    pub last_seen: DateTime<Utc>,
+}
+
+/// Data rates a neighbor announced to prefer receiving with for its end device IDs.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct NeighborDataRates {
+    /// Bit set of the preferred receive data rates, bit `n` is DR`n`.
+    pub data_rates: u8,
+    /// The gateway which received the last data rate announcement.
+    pub gateway_id: GatewayId,
+    /// Time of the last data rate announcement.
+    pub last_seen: DateTime<Utc>,
+}
+
+/// Keeps track of the end device IDs announced by neighbors.
+#[derive(Debug, Default)]
+pub struct NeighborTable {
+    /// Neighbor entries by announced end device ID.
+    entries: HashMap<EndDeviceId, NeighborEntry>,
+    /// Announced services by end device ID.
+    services: HashMap<EndDeviceId, NeighborServices>,
+    /// Announced channel plans by the end device ID identifying the neighbor.
+    channel_plans: HashMap<EndDeviceId, NeighborChannelPlan>,
+    /// Announced capabilities by end device ID.
+    capabilities: HashMap<EndDeviceId, NeighborCapabilities>,
+    /// Announced receive data rates by end device ID.
+    receive_data_rates: HashMap<EndDeviceId, NeighborDataRates>,
+}
+
+impl NeighborTable {
+    /// Creates a new empty [`NeighborTable`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns all entries of the neighbor table.
+    pub fn entries(&self) -> &HashMap<EndDeviceId, NeighborEntry> {
+        &self.entries
+    }
+
+    /// Returns the services announced by neighbors.
+    pub fn services(&self) -> &HashMap<EndDeviceId, NeighborServices> {
+        &self.services
+    }
+
+    /// Returns the channel plans announced by neighbors.
+    pub fn channel_plans(&self) -> &HashMap<EndDeviceId, NeighborChannelPlan> {
+        &self.channel_plans
+    }
+
+    /// Returns the capabilities announced by neighbors.
+    pub fn capabilities(&self) -> &HashMap<EndDeviceId, NeighborCapabilities> {
+        &self.capabilities
+    }
+
+    /// Returns the receive data rates announced by neighbors.
+    pub fn receive_data_rates(&self) -> &HashMap<EndDeviceId, NeighborDataRates> {
+        &self.receive_data_rates
+    }
+
+    /// Returns the bit set of data rates the end device ID prefers to receive with,
+    /// [`DEFAULT_RECEIVE_DATA_RATES`] if its neighbor never announced them.
+    pub fn receive_data_rates_of(&self, end_device_id: EndDeviceId) -> u8 {
+        self.receive_data_rates
+            .get(&end_device_id)
+            .map_or(DEFAULT_RECEIVE_DATA_RATES, |entry| entry.data_rates)
+    }
+
+    /// Replaces the receive data rates of the end device IDs of a received
+    /// [`DataRateAnnouncement`].
+    pub fn process_data_rate_announcement(
+        &mut self,
+        announcement: &DataRateAnnouncement,
+        gateway_id: &GatewayId,
+    ) {
+        let now = Utc::now();
+        for end_device_id in announcement.end_device_ids_ref() {
+            self.receive_data_rates.insert(
+                *end_device_id,
+                NeighborDataRates {
+                    data_rates: announcement.data_rates(),
+                    gateway_id: gateway_id.clone(),
+                    last_seen: now,
+                },
+            );
+        }
+    }
+
+    /// Replaces the capabilities of the end device IDs of a received
+    /// [`CapabilityAnnouncement`].
+    pub fn process_capability_announcement(
+        &mut self,
+        announcement: &CapabilityAnnouncement,
+        gateway_id: &GatewayId,
+    ) {
+        let now = Utc::now();
+        for end_device_id in announcement.end_device_ids_ref() {
+            self.capabilities.insert(
+                *end_device_id,
+                NeighborCapabilities {
+                    capabilities: announcement.capabilities(),
+                    gateway_id: gateway_id.clone(),
+                    last_seen: now,
+                },
+            );
+        }
+    }
+
+    /// Returns whether at least one end device ID was announced by a direct neighbor within
+    /// `max_age` and the capability was announced for all of them within `max_age`.
+    ///
+    /// Proxied entries are ignored, only direct neighbors receive the packets of this node.
+    pub fn direct_neighbors_support(&self, capability: u16, max_age: chrono::Duration) -> bool {
+        let now = Utc::now();
+        let mut direct_neighbors = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| {
+                entry.reachability == Reachability::Own
+                    && now.signed_duration_since(entry.last_seen) <= max_age
+            })
+            .peekable();
+        direct_neighbors.peek().is_some()
+            && direct_neighbors.all(|(end_device_id, _)| {
+                self.capabilities.get(end_device_id).is_some_and(|entry| {
+                    entry.capabilities & capability == capability
+                        && now.signed_duration_since(entry.last_seen) <= max_age
+                })
+            })
+    }
+
+    /// Replaces the channel plan of the neighbor of a received [`ChannelPlanAnnouncement`].
+    pub fn process_channel_plan_announcement(
+        &mut self,
+        announcement: &ChannelPlanAnnouncement,
+        gateway_id: &GatewayId,
+    ) {
+        self.channel_plans.insert(
+            announcement.end_device_id(),
+            NeighborChannelPlan {
+                frequencies: announcement.frequencies().to_vec(),
+                gateway_id: gateway_id.clone(),
+                last_seen: Utc::now(),
+            },
+        );
+    }
+
+    /// Replaces the services of the end device IDs of a received [`ServiceAnnouncement`].
+    pub fn process_service_announcement(
+        &mut self,
+        announcement: &ServiceAnnouncement,
+        gateway_id: &GatewayId,
+    ) {
+        let now = Utc::now();
+        for end_device_services in announcement.end_device_services_ref() {
+            self.services.insert(
+                end_device_services.end_device_id,
+                NeighborServices {
+                    services: end_device_services.services.clone(),
+                    gateway_id: gateway_id.clone(),
+                    last_seen: now,
+                },
+            );
+        }
+    }
+
+    /// Adds the end device IDs of a received [`LocalAnnouncement`] as registered at the neighbor.
+    ///
+    /// The neighbor is identified by the first end device ID, which starts every announcement
+    /// split from the same node.
+    pub fn process_announcement(
+        &mut self,
+        announcement: &LocalAnnouncement,
+        gateway_id: &GatewayId,
+        signal_quality: SignalQuality,
+    ) {
+        let Some(neighbor) = announcement.end_device_ids_ref().first().copied() else {
+            return;
+        };
+        let now = Utc::now();
+        for end_device_id in announcement.end_device_ids_ref() {
+            self.insert(
+                *end_device_id,
+                NeighborEntry {
+                    reachability: Reachability::Own,
+                    hop_distance: 1,
+                    neighbor,
+                    gateway_id: gateway_id.clone(),
+                    signal_quality,
+                    last_seen: now,
+                },
+            );
+        }
+    }
+
+    /// Adds the end device IDs of a received [`ReachabilityAnnouncement`] as proxied by the
+    /// neighbor, one hop further away than announced.
+    ///
+    /// Entries the neighbor learned from this node and the end device IDs registered at this node
+    /// are ignored (split horizon), otherwise a lost route would be learned back from the neighbor
+    /// with an ever increasing hop distance.
+    pub fn process_reachability_announcement(
+        &mut self,
+        announcement: &ReachabilityAnnouncement,
+        own_end_device_ids: &[EndDeviceId],
+        gateway_id: &GatewayId,
+        signal_quality: SignalQuality,
+    ) {
+        let now = Utc::now();
+        for reachable_end_device_id in announcement.reachable_end_device_ids_ref() {
+            if own_end_device_ids.contains(&reachable_end_device_id.next_hop)
+                || own_end_device_ids.contains(&reachable_end_device_id.end_device_id)
+            {
+                continue;
+            }
+            self.insert(
+                reachable_end_device_id.end_device_id,
+                NeighborEntry {
+                    reachability: Reachability::Proxied,
+                    hop_distance: reachable_end_device_id.hop_distance.saturating_add(1),
+                    neighbor: announcement.sender(),
+                    gateway_id: gateway_id.clone(),
+                    signal_quality,
+                    last_seen: now,
+                },
+            );
+        }
+    }
+
+    /// Inserts an entry. An existing entry is replaced by a refresh from the same neighbor or by
+    /// an entry of another neighbor with at most the same hop distance. Entries with
+    /// [`Reachability::Own`] are never replaced by entries of other neighbors with
+    /// [`Reachability::Proxied`], proxied reachability is only a fallback.
+    pub fn insert(&mut self, end_device_id: EndDeviceId, entry: NeighborEntry) {
+        match self.entries.entry(end_device_id) {
+            Entry::Occupied(mut occupied) => {
+                let existing = occupied.get();
+                let refresh = entry.neighbor == existing.neighbor;
+                let shorter = entry.hop_distance <= existing.hop_distance
+                    && !(entry.reachability == Reachability::Proxied
+                        && existing.reachability == Reachability::Own);
+                if refresh || shorter {
+                    occupied.insert(entry);
+                }
+            }
+            Entry::Vacant(vacant) => {
+                vacant.insert(entry);
+            }
+        }
+    }
+
+    /// Removes the entries and announcements of an end device ID, e.g. of a blacklisted node.
+    pub fn remove(&mut self, end_device_id: EndDeviceId) {
+        self.entries.remove(&end_device_id);
+        self.services.remove(&end_device_id);
+        self.channel_plans.remove(&end_device_id);
+        self.capabilities.remove(&end_device_id);
+        self.receive_data_rates.remove(&end_device_id);
+    }
+
+    /// Removes all entries not seen within `max_age`.
+    pub fn remove_expired(&mut self, max_age: chrono::Duration) {
+        let now = Utc::now();
+        self.entries
+            .retain(|_, entry| now.signed_duration_since(entry.last_seen) <= max_age);
+        self.services
+            .retain(|_, services| now.signed_duration_since(services.last_seen) <= max_age);
+        self.channel_plans
+            .retain(|_, channel_plan| now.signed_duration_since(channel_plan.last_seen) <= max_age);
+        self.capabilities
+            .retain(|_, capabilities| now.signed_duration_since(capabilities.last_seen) <= max_age);
+        self.receive_data_rates
+            .retain(|_, data_rates| now.signed_duration_since(data_rates.last_seen) <= max_age);
+    }
+
+    /// Returns the end device IDs reachable through this node to be advertised to neighbors,
+    /// each with the neighbor it was learned from so that neighbor ignores it.
+    ///
+    /// End device IDs registered at this node and entries with a hop distance above
+    /// `max_hop_distance` are left out.
+    pub fn reachable_end_device_ids(
+        &self,
+        own_end_device_ids: &[EndDeviceId],
+        max_hop_distance: u8,
+    ) -> Vec<ReachableEndDeviceId> {
+        let mut reachable_end_device_ids: Vec<ReachableEndDeviceId> = self
+            .entries
+            .iter()
+            .filter(|(end_device_id, entry)| {
+                entry.hop_distance <= max_hop_distance
+                    && !own_end_device_ids.contains(end_device_id)
+            })
+            .map(|(end_device_id, entry)| ReachableEndDeviceId {
+                end_device_id: *end_device_id,
+                hop_distance: entry.hop_distance,
+                next_hop: entry.neighbor,
+            })
+            .collect();
+        reachable_end_device_ids.sort_by_key(|reachable| reachable.hop_distance);
+        reachable_end_device_ids
+    }
+
+    /// Returns the entry of the end device ID if a neighbor announced it as its own within
+    /// `max_age`, i.e. the neighbor is reachable by a single transmission.
+    pub fn direct_neighbor(
+        &self,
+        end_device_id: EndDeviceId,
+        max_age: chrono::Duration,
+    ) -> Option<&NeighborEntry> {
+        let now = Utc::now();
+        self.entries.get(&end_device_id).filter(|entry| {
+            entry.reachability == Reachability::Own
+                && now.signed_duration_since(entry.last_seen) <= max_age
+        })
+    }
+
+    /// Returns whether all supplied end device IDs were recently announced by neighbors as their
+    /// own with at least the supplied signal quality.
+    ///
+    /// Proxied entries are ignored, a neighbor might only reach the end device IDs through this
+    /// node.
+    pub fn announced_by_stronger_neighbors(
+        &self,
+        end_device_ids: &[EndDeviceId],
+        min_rssi: i32,
+        min_snr: i32,
+        max_age: chrono::Duration,
+    ) -> bool {
+        let now = Utc::now();
+        !end_device_ids.is_empty()
+            && end_device_ids.iter().all(|end_device_id| {
+                self.entries.get(end_device_id).is_some_and(|entry| {
+                    entry.reachability == Reachability::Own
+                        && entry.signal_quality.reaches(min_rssi, min_snr)
+                        && now.signed_duration_since(entry.last_seen) <= max_age
+                })
+            })
+    }
+}
+
+/// Task removing neighbor table entries not seen within `max_age`.
+///
+/// Runs independently of the announcements, neighbors are also learned while this node does not
+/// announce itself.
+#[instrument(skip_all)]
+pub async fn expiry_task(
+    state: Arc<AppState>,
+    max_age: chrono::Duration,
+    mut shutdown_agent: ShutdownAgent,
+) {
+    trace!("Starting up");
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(EXPIRY_INTERVAL) => {},
+            _ = shutdown_agent.await_shutdown() => {
+                trace!("Shutting down");
+                return
+            }
+        }
+        state.neighbor_table.lock().await.remove_expired(max_age);
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use crate::end_device_id::EndDeviceId;
+    use crate::neighbor_table::{NeighborEntry, NeighborTable, Reachability, SignalQuality};
+    use chirpstack_gwb_integration::downlinks::predefined_parameters::DataRate;
+    use chrono::Utc;
+    use lorawan_dtn_protocol::{
+        CapabilityAnnouncement, ChannelPlanAnnouncement, DataRateAnnouncement, EndDeviceServices,
+        LocalAnnouncement, ReachabilityAnnouncement, ReachableEndDeviceId, ServiceAnnouncement,
+        CAPABILITY_BP7_CBOR, DEFAULT_RECEIVE_DATA_RATES,
+    };
+
+    #[test]
+    fn stronger_neighbor_suppresses() {
+        let mut neighbor_table = NeighborTable::new();
+        let announcement =
+            LocalAnnouncement::new(None, vec![EndDeviceId(0x1234), EndDeviceId(0x5678)]);
+        neighbor_table.process_announcement(
+            &announcement,
+            &"a840411d25244150".parse().unwrap(),
+            SignalQuality {
+                rssi: -80,
+                snr: 7.5,
+            },
+        );
+        let max_age = chrono::Duration::minutes(10);
+        assert!(neighbor_table.announced_by_stronger_neighbors(
+            &[EndDeviceId(0x1234)],
+            -90,
+            5,
+            max_age
+        ));
+        assert!(!neighbor_table.announced_by_stronger_neighbors(
+            &[EndDeviceId(0x1234)],
+            -70,
+            5,
+            max_age
+        ));
+        assert!(!neighbor_table.announced_by_stronger_neighbors(
+            &[EndDeviceId(0x1234), EndDeviceId(0x9999)],
+            -90,
+            5,
+            max_age
+        ));
+        assert!(!neighbor_table.announced_by_stronger_neighbors(&[], -90, 5, max_age));
+    }
+
+    #[test]
+    fn split_announcements_share_neighbor() {
+        let mut neighbor_table = NeighborTable::new();
+        let end_device_ids: Vec<EndDeviceId> = (1..=30).map(EndDeviceId).collect();
+        let announcements = LocalAnnouncement::split_to_data_rate(
+            None,
+            &end_device_ids,
+            DataRate::Eu863_870Dr0,
+            false,
+        );
+        assert!(announcements.len() > 1);
+        for announcement in &announcements {
+            neighbor_table.process_announcement(
+                announcement,
+                &"a840411d25244150".parse().unwrap(),
+                SignalQuality {
+                    rssi: -80,
+                    snr: 7.5,
+                },
+            );
+        }
+        for end_device_id in &end_device_ids {
+            assert_eq!(
+                neighbor_table
+                    .entries()
+                    .get(end_device_id)
+                    .unwrap()
+                    .neighbor,
+                EndDeviceId(1)
+            );
+        }
+    }
+
+    #[test]
+    fn proxied_does_not_replace_own() {
+        let mut neighbor_table = NeighborTable::new();
+        let own_entry = NeighborEntry {
+            reachability: Reachability::Own,
+            hop_distance: 1,
+            neighbor: EndDeviceId(0x1234),
+            gateway_id: "a840411d25244150".parse().unwrap(),
+            signal_quality: SignalQuality {
+                rssi: -100,
+                snr: 1.0,
+            },
+            last_seen: Utc::now(),
+        };
+        neighbor_table.insert(EndDeviceId(0x1234), own_entry.clone());
+        neighbor_table.insert(
+            EndDeviceId(0x1234),
+            NeighborEntry {
+                reachability: Reachability::Proxied,
+                hop_distance: 2,
+                neighbor: EndDeviceId(0x5678),
+                signal_quality: SignalQuality {
+                    rssi: -50,
+                    snr: 10.0,
+                },
+                ..own_entry.clone()
+            },
+        );
+        assert_eq!(
+            neighbor_table.entries().get(&EndDeviceId(0x1234)),
+            Some(&own_entry)
+        );
+        assert!(!neighbor_table.announced_by_stronger_neighbors(
+            &[EndDeviceId(0x1234)],
+            -90,
+            5,
+            chrono::Duration::minutes(10)
+        ));
+    }
+
+    #[test]
+    fn reachability_announcement_adds_proxied_entries() {
+        let mut neighbor_table = NeighborTable::new();
+        let signal_quality = SignalQuality {
+            rssi: -80,
+            snr: 7.5,
+        };
+        neighbor_table.process_announcement(
+            &LocalAnnouncement::new(None, vec![EndDeviceId(0x1234)]),
+            &"a840411d25244150".parse().unwrap(),
+            signal_quality,
+        );
+        neighbor_table.process_reachability_announcement(
+            &ReachabilityAnnouncement::new(
+                EndDeviceId(0x4321),
+                vec![
+                    ReachableEndDeviceId {
+                        end_device_id: EndDeviceId(0x1234),
+                        hop_distance: 1,
+                        next_hop: EndDeviceId(0x1234),
+                    },
+                    ReachableEndDeviceId {
+                        end_device_id: EndDeviceId(0x5678),
+                        hop_distance: 1,
+                        next_hop: EndDeviceId(0x5678),
+                    },
+                    ReachableEndDeviceId {
+                        end_device_id: EndDeviceId(0x9999),
+                        hop_distance: 3,
+                        next_hop: EndDeviceId(0x5678),
+                    },
+                ],
+            ),
+            &[],
+            &"b840411d25244150".parse().unwrap(),
+            signal_quality,
+        );
+        assert_eq!(
+            neighbor_table
+                .entries()
+                .get(&EndDeviceId(0x1234))
+                .map(|entry| entry.reachability),
+            Some(Reachability::Own)
+        );
+        assert_eq!(
+            neighbor_table
+                .entries()
+                .get(&EndDeviceId(0x5678))
+                .map(|entry| entry.hop_distance),
+            Some(2)
+        );
+
+        assert_eq!(
+            neighbor_table.reachable_end_device_ids(&[EndDeviceId(0x1234)], 2),
+            vec![ReachableEndDeviceId {
+                end_device_id: EndDeviceId(0x5678),
+                hop_distance: 2,
+                next_hop: EndDeviceId(0x4321),
+            }]
+        );
+
+        let max_age = chrono::Duration::minutes(10);
+        assert_eq!(
+            neighbor_table
+                .direct_neighbor(EndDeviceId(0x1234), max_age)
+                .map(|entry| entry.gateway_id.as_str()),
+            Some("a840411d25244150")
+        );
+        assert!(neighbor_table
+            .direct_neighbor(EndDeviceId(0x5678), max_age)
+            .is_none());
+        assert!(neighbor_table
+            .direct_neighbor(EndDeviceId(0x1234), chrono::Duration::seconds(-1))
+            .is_none());
+    }
+
+    #[test]
+    fn reachability_announcement_applies_split_horizon_and_hop_distances() {
+        fn announce(
+            neighbor_table: &mut NeighborTable,
+            sender: u32,
+            hop_distance: u8,
+            next_hop: u32,
+        ) -> Option<(EndDeviceId, u8)> {
+            neighbor_table.process_reachability_announcement(
+                &ReachabilityAnnouncement::new(
+                    EndDeviceId(sender),
+                    vec![ReachableEndDeviceId {
+                        end_device_id: EndDeviceId(0x1234),
+                        hop_distance,
+                        next_hop: EndDeviceId(next_hop),
+                    }],
+                ),
+                &[EndDeviceId(0xAAAA)],
+                &"a840411d25244150".parse().unwrap(),
+                SignalQuality {
+                    rssi: -80,
+                    snr: 7.5,
+                },
+            );
+            neighbor_table
+                .entries()
+                .get(&EndDeviceId(0x1234))
+                .map(|entry| (entry.neighbor, entry.hop_distance))
+        }
+
+        let mut neighbor_table = NeighborTable::new();
+        // Learned from this node.
+        assert_eq!(announce(&mut neighbor_table, 0xBBBB, 1, 0xAAAA), None);
+        assert_eq!(
+            announce(&mut neighbor_table, 0xCCCC, 1, 0x1234),
+            Some((EndDeviceId(0xCCCC), 2))
+        );
+        // Longer routes of other neighbors are ignored, although received by the same gateway.
+        assert_eq!(
+            announce(&mut neighbor_table, 0xBBBB, 2, 0xDDDD),
+            Some((EndDeviceId(0xCCCC), 2))
+        );
+        // Refreshes of the same neighbor replace the entry.
+        assert_eq!(
+            announce(&mut neighbor_table, 0xCCCC, 4, 0xDDDD),
+            Some((EndDeviceId(0xCCCC), 5))
+        );
+        assert_eq!(
+            announce(&mut neighbor_table, 0xBBBB, 3, 0xDDDD),
+            Some((EndDeviceId(0xBBBB), 4))
+        );
+    }
+
+    #[test]
+    fn service_announcement_replaces_services() {
+        let mut neighbor_table = NeighborTable::new();
+        for services in [vec![80, 443], vec![8080]] {
+            neighbor_table.process_service_announcement(
+                &ServiceAnnouncement::new(vec![EndDeviceServices {
+                    end_device_id: EndDeviceId(0x1234),
+                    services,
+                }]),
+                &"a840411d25244150".parse().unwrap(),
+            );
+        }
+        assert_eq!(
+            neighbor_table
+                .services()
+                .get(&EndDeviceId(0x1234))
+                .map(|services| services.services.clone()),
+            Some(vec![8080])
+        );
+        neighbor_table.remove_expired(chrono::Duration::seconds(-1));
+        assert!(neighbor_table.services().is_empty());
+    }
+
+    #[test]
+    fn channel_plan_announcement_replaces_channel_plan() {
+        let mut neighbor_table = NeighborTable::new();
+        for frequencies in [vec![868_100_000], vec![868_300_000, 868_500_000]] {
+            neighbor_table.process_channel_plan_announcement(
+                &ChannelPlanAnnouncement::new(EndDeviceId(0x1234), frequencies),
+                &"a840411d25244150".parse().unwrap(),
+            );
+        }
+        assert_eq!(
+            neighbor_table
+                .channel_plans()
+                .get(&EndDeviceId(0x1234))
+                .map(|channel_plan| channel_plan.frequencies.clone()),
+            Some(vec![868_300_000, 868_500_000])
+        );
+        neighbor_table.remove_expired(chrono::Duration::seconds(-1));
+        assert!(neighbor_table.channel_plans().is_empty());
+    }
+
+    #[test]
+    fn capabilities_require_all_direct_neighbors() {
+        let mut neighbor_table = NeighborTable::new();
+        let signal_quality = SignalQuality {
+            rssi: -80,
+            snr: 7.5,
+        };
+        let max_age = chrono::Duration::minutes(10);
+        assert!(!neighbor_table.direct_neighbors_support(CAPABILITY_BP7_CBOR, max_age));
+
+        neighbor_table.process_announcement(
+            &LocalAnnouncement::new(None, vec![EndDeviceId(0x1234), EndDeviceId(0x5678)]),
+            &"a840411d25244150".parse().unwrap(),
+            signal_quality,
+        );
+        neighbor_table.process_capability_announcement(
+            &CapabilityAnnouncement::new(CAPABILITY_BP7_CBOR, vec![EndDeviceId(0x1234)]),
+            &"a840411d25244150".parse().unwrap(),
+        );
+        assert!(!neighbor_table.direct_neighbors_support(CAPABILITY_BP7_CBOR, max_age));
+
+        neighbor_table.process_capability_announcement(
+            &CapabilityAnnouncement::new(CAPABILITY_BP7_CBOR, vec![EndDeviceId(0x5678)]),
+            &"a840411d25244150".parse().unwrap(),
+        );
+        assert!(neighbor_table.direct_neighbors_support(CAPABILITY_BP7_CBOR, max_age));
+
+        // Proxied end device IDs do not take part in the negotiation.
+        neighbor_table.process_reachability_announcement(
+            &ReachabilityAnnouncement::new(
+                EndDeviceId(0x1234),
+                vec![ReachableEndDeviceId {
+                    end_device_id: EndDeviceId(0x9ABC),
+                    hop_distance: 1,
+                    next_hop: EndDeviceId(0x9ABC),
+                }],
+            ),
+            &[],
+            &"a840411d25244150".parse().unwrap(),
+            signal_quality,
+        );
+        assert!(neighbor_table.direct_neighbors_support(CAPABILITY_BP7_CBOR, max_age));
+
+        neighbor_table.process_capability_announcement(
+            &CapabilityAnnouncement::new(0, vec![EndDeviceId(0x5678)]),
+            &"a840411d25244150".parse().unwrap(),
+        );
+        assert!(!neighbor_table.direct_neighbors_support(CAPABILITY_BP7_CBOR, max_age));
+    }
+
+    #[test]
+    fn data_rate_announcement_replaces_receive_data_rates() {
+        let mut neighbor_table = NeighborTable::new();
+        assert_eq!(
+            neighbor_table.receive_data_rates_of(EndDeviceId(0x1234)),
+            DEFAULT_RECEIVE_DATA_RATES
+        );
+        for data_rates in [0b0111_1111, 0b0100_1000] {
+            neighbor_table.process_data_rate_announcement(
+                &DataRateAnnouncement::new(
+                    data_rates,
+                    vec![EndDeviceId(0x1234), EndDeviceId(0x5678)],
+                ),
+                &"a840411d25244150".parse().unwrap(),
+            );
+        }
+        assert_eq!(
+            neighbor_table.receive_data_rates_of(EndDeviceId(0x5678)),
+            0b0100_1000
+        );
+        neighbor_table.remove_expired(chrono::Duration::seconds(-1));
+        assert!(neighbor_table.receive_data_rates().is_empty());
+    }
+}
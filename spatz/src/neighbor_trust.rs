@@ -0,0 +1,561 @@
+//! Blacklist and trust scores of the nodes sending packets.
+//!
+//! Packets are attributed to the node ID they carry as sender: the source of bundle packets,
+//! echoes, status reports and hop acknowledgements and the first end device ID of local,
+//! capability, data rate and channel plan announcements. Reachability and service announcements,
+//! hop-to-hop fragments and BP7 bundles are not attributed.
+//!
+//! Packets of blacklisted nodes are dropped. Operators blacklist nodes in the configuration or
+//! via the API, nodes blacklisted in the configuration are blacklisted again on every start.
+//!
+//! With a [`NeighborTrustConfig`] every node starts with the score [`MAX_SCORE`], which is lowered
+//! for every minute the node sends more packets than allowed and for every malformed packet, i.e.
+//! bundle fragments not matching the fragments received before or not reassembling into a bundle.
+//! Lowered scores recover linearly over time. Packets of nodes below the minimum score are
+//! neither processed nor relayed and the nodes are removed from the neighbor table, so routing no
+//! longer sends to them directly.
+//!
+//! Every penalty, every recovery above the minimum score and every change of the blacklist is
+//! recorded in the audit trail. The blacklist, the lowered scores and the audit trail are
+//! persisted.
+
+use crate::configuration::NeighborTrustConfig;
+use crate::end_device_id::EndDeviceId;
+use chrono::{DateTime, Duration, Utc};
+use lorawan_dtn_protocol::{
+    BundleResendRequest, CapabilityAnnouncement, ChannelPlanAnnouncement, DataRateAnnouncement,
+    EchoReply, EchoRequest, HopAck, LoRaWanPacket, LocalAnnouncement, ReachabilityAnnouncement,
+    StatusReport, StatusReportRequest,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use tracing::{info, warn};
+
+/// Score of nodes without observed misbehavior.
+pub const MAX_SCORE: f64 = 100.0;
+
+/// Maximum amount of kept changes in the audit trail, the oldest changes are removed first.
+pub const MAX_AUDIT_ENTRIES: usize = 1000;
+
+/// Amount of tracked traffic windows above which the windows of the previous minutes are removed.
+const MAX_TRAFFIC_WINDOWS: usize = 1024;
+
+/// Returns the node ID a packet was sent by, `None` if the packet carries no sender.
+pub fn packet_sender(packet: &dyn LoRaWanPacket) -> Option<EndDeviceId> {
+    if let Some(bundle_packet) = packet.as_bundle_packet() {
+        return Some(bundle_packet.source());
+    }
+    let any = packet.as_any();
+    if let Some(announcement) = any.downcast_ref::<LocalAnnouncement>() {
+        announcement.end_device_ids_ref().first().copied()
+    } else if let Some(announcement) = any.downcast_ref::<CapabilityAnnouncement>() {
+        announcement.end_device_ids_ref().first().copied()
+    } else if let Some(announcement) = any.downcast_ref::<DataRateAnnouncement>() {
+        announcement.end_device_ids_ref().first().copied()
+    } else if let Some(announcement) = any.downcast_ref::<ChannelPlanAnnouncement>() {
+        Some(announcement.end_device_id())
+    } else if let Some(announcement) = any.downcast_ref::<ReachabilityAnnouncement>() {
+        Some(announcement.sender())
+    } else if let Some(echo_request) = any.downcast_ref::<EchoRequest>() {
+        Some(echo_request.source)
+    } else if let Some(echo_reply) = any.downcast_ref::<EchoReply>() {
+        Some(echo_reply.source)
+    } else if let Some(report) = any.downcast_ref::<StatusReport>() {
+        Some(report.source)
+    } else if let Some(request) = any.downcast_ref::<StatusReportRequest>() {
+        Some(request.source)
+    } else if let Some(request) = any.downcast_ref::<BundleResendRequest>() {
+        Some(request.source)
+    } else {
+        any.downcast_ref::<HopAck>().map(|hop_ack| hop_ack.source)
+    }
+}
+
+/// Blacklisted node.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct BlacklistEntry {
+    /// ID of the node.
+    pub node_id: EndDeviceId,
+    /// Reason of the blacklisting, e.g. the observed misbehavior.
+    pub reason: String,
+    /// Time the node was blacklisted.
+    pub since: DateTime<Utc>,
+}
+
+/// Lowered score of a node.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct NodeScore {
+    /// Score at `updated_at`, between 0 and [`MAX_SCORE`].
+    pub score: f64,
+    /// Time the score was last lowered or recovered.
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Cause of a change of the score or the blacklisting of a node.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TrustChangeCause {
+    /// The node sent more packets within a minute than allowed.
+    ExcessiveTraffic,
+    /// The node sent a malformed packet.
+    MalformedPacket,
+    /// The score recovered to at least the minimum score.
+    Recovered,
+    /// The node was blacklisted.
+    Blacklisted,
+    /// The node was removed from the blacklist.
+    Unblacklisted,
+}
+
+/// Entry of the audit trail.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct TrustChange {
+    /// Time of the change.
+    pub time: DateTime<Utc>,
+    /// ID of the node.
+    pub node_id: EndDeviceId,
+    /// Cause of the change.
+    pub cause: TrustChangeCause,
+    /// Score before the change.
+    pub previous_score: f64,
+    /// Score after the change.
+    pub score: f64,
+    /// Reason given by the operator for changes of the blacklist.
+    pub reason: Option<String>,
+}
+
+/// Trust state persisted in the database.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PersistedNeighborTrust {
+    /// Blacklisted nodes.
+    pub blacklist: Vec<BlacklistEntry>,
+    /// Nodes whose score is below [`MAX_SCORE`].
+    pub scores: HashMap<EndDeviceId, NodeScore>,
+    /// Changes of the scores and the blacklist, oldest first.
+    pub audit_trail: Vec<TrustChange>,
+}
+
+/// Trust of a node, served by the API.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, JsonSchema)]
+pub struct NodeTrust {
+    /// ID of the node.
+    pub node_id: EndDeviceId,
+    /// Current score, between 0 and [`MAX_SCORE`].
+    pub score: f64,
+    /// Whether the packets of the node are processed and relayed.
+    pub trusted: bool,
+}
+
+/// Packets of a node within the current minute.
+#[derive(Debug, Copy, Clone)]
+struct TrafficWindow {
+    /// Start of the minute.
+    start: DateTime<Utc>,
+    /// Packets received within the minute.
+    packets: u32,
+}
+
+/// Blacklist, scores and audit trail guarded by a single lock.
+#[derive(Debug, Default)]
+struct TrustState {
+    /// Blacklisted nodes.
+    blacklist: Vec<BlacklistEntry>,
+    /// Nodes whose score is below [`MAX_SCORE`].
+    scores: HashMap<EndDeviceId, NodeScore>,
+    /// Traffic of the nodes within the current minute.
+    traffic: HashMap<EndDeviceId, TrafficWindow>,
+    /// Changes of the scores and the blacklist, oldest first.
+    audit_trail: VecDeque<TrustChange>,
+}
+
+impl TrustState {
+    /// Returns whether the node is blacklisted.
+    fn is_blacklisted(&self, node_id: EndDeviceId) -> bool {
+        self.blacklist.iter().any(|entry| entry.node_id == node_id)
+    }
+
+    /// Records a change, removing the oldest change if the audit trail is full.
+    fn audit(&mut self, change: TrustChange) {
+        if self.audit_trail.len() >= MAX_AUDIT_ENTRIES {
+            self.audit_trail.pop_front();
+        }
+        self.audit_trail.push_back(change);
+    }
+
+    /// Returns the score of the node at `now` after applying the recovery since its last
+    /// change. Recovered scores are stored, fully recovered nodes are removed.
+    fn score(
+        &mut self,
+        config: &NeighborTrustConfig,
+        node_id: EndDeviceId,
+        now: DateTime<Utc>,
+    ) -> f64 {
+        let Some(node_score) = self.scores.get(&node_id).copied() else {
+            return MAX_SCORE;
+        };
+        #[allow(clippy::cast_precision_loss)]
+        let hours = now
+            .signed_duration_since(node_score.updated_at)
+            .num_milliseconds()
+            .max(0) as f64
+            / 3_600_000.0;
+        let score = (node_score.score + hours * f64::from(config.recovery_per_hour)).min(MAX_SCORE);
+        if score >= MAX_SCORE {
+            self.scores.remove(&node_id);
+        } else if score > node_score.score {
+            self.scores.insert(
+                node_id,
+                NodeScore {
+                    score,
+                    updated_at: now,
+                },
+            );
+        }
+        let min_score = f64::from(config.min_score);
+        if node_score.score < min_score && score >= min_score {
+            info!(?node_id, score, "Trust of node recovered");
+            self.audit(TrustChange {
+                time: now,
+                node_id,
+                cause: TrustChangeCause::Recovered,
+                previous_score: node_score.score,
+                score,
+                reason: None,
+            });
+        }
+        score
+    }
+
+    /// Lowers the score of the node by the penalty.
+    fn penalize(
+        &mut self,
+        config: &NeighborTrustConfig,
+        node_id: EndDeviceId,
+        penalty: u32,
+        cause: TrustChangeCause,
+        now: DateTime<Utc>,
+    ) {
+        let previous_score = self.score(config, node_id, now);
+        let score = (previous_score - f64::from(penalty)).max(0.0);
+        if score >= previous_score {
+            return;
+        }
+        self.scores.insert(
+            node_id,
+            NodeScore {
+                score,
+                updated_at: now,
+            },
+        );
+        if score < f64::from(config.min_score) {
+            warn!(?node_id, score, ?cause, "Node distrusted");
+        }
+        self.audit(TrustChange {
+            time: now,
+            node_id,
+            cause,
+            previous_score,
+            score,
+            reason: None,
+        });
+    }
+}
+
+/// Blacklist and automatic trust scores of the nodes sending packets.
+#[derive(Debug, Default)]
+pub struct NeighborTrust {
+    /// Thresholds and penalties, nodes are not scored if not set.
+    config: Option<NeighborTrustConfig>,
+    /// Blacklist, scores and audit trail.
+    state: Mutex<TrustState>,
+}
+
+impl NeighborTrust {
+    /// Creates a new [`NeighborTrust`] restoring the persisted state. Nodes blacklisted in the
+    /// configuration are blacklisted again if they were removed from the blacklist via the API.
+    pub fn new(
+        config: Option<NeighborTrustConfig>,
+        configured_blacklist: &[EndDeviceId],
+        persisted: PersistedNeighborTrust,
+        now: DateTime<Utc>,
+    ) -> Self {
+        let mut state = TrustState {
+            blacklist: persisted.blacklist,
+            scores: persisted.scores,
+            traffic: HashMap::new(),
+            audit_trail: persisted.audit_trail.into(),
+        };
+        for node_id in configured_blacklist {
+            if !state.is_blacklisted(*node_id) {
+                state.blacklist.push(BlacklistEntry {
+                    node_id: *node_id,
+                    reason: "Blacklisted in the configuration".to_owned(),
+                    since: now,
+                });
+            }
+        }
+        Self {
+            config,
+            state: Mutex::new(state),
+        }
+    }
+
+    /// Counts a packet of the node and returns whether it is processed and relayed, i.e. the node
+    /// is neither blacklisted nor below the minimum score.
+    ///
+    /// The score is lowered once per minute in which the node exceeds the allowed amount of
+    /// packets.
+    pub fn observe(&self, node_id: EndDeviceId, now: DateTime<Utc>) -> bool {
+        let mut state = self.lock();
+        if state.is_blacklisted(node_id) {
+            return false;
+        }
+        let Some(config) = &self.config else {
+            return true;
+        };
+        if state.traffic.len() >= MAX_TRAFFIC_WINDOWS {
+            state
+                .traffic
+                .retain(|_, window| now.signed_duration_since(window.start) < Duration::minutes(1));
+        }
+        let window = state.traffic.entry(node_id).or_insert(TrafficWindow {
+            start: now,
+            packets: 0,
+        });
+        if now.signed_duration_since(window.start) >= Duration::minutes(1) {
+            *window = TrafficWindow {
+                start: now,
+                packets: 0,
+            };
+        }
+        window.packets = window.packets.saturating_add(1);
+        if window.packets == config.max_packets_per_minute.saturating_add(1) {
+            state.penalize(
+                config,
+                node_id,
+                config.traffic_penalty,
+                TrustChangeCause::ExcessiveTraffic,
+                now,
+            );
+        }
+        state.score(config, node_id, now) >= f64::from(config.min_score)
+    }
+
+    /// Lowers the score of the node for a malformed packet.
+    pub fn record_malformed(&self, node_id: EndDeviceId, now: DateTime<Utc>) {
+        if let Some(config) = &self.config {
+            self.lock().penalize(
+                config,
+                node_id,
+                config.malformed_penalty,
+                TrustChangeCause::MalformedPacket,
+                now,
+            );
+        }
+    }
+
+    /// Returns whether the packets of the node are processed and relayed.
+    pub fn is_trusted(&self, node_id: EndDeviceId, now: DateTime<Utc>) -> bool {
+        let mut state = self.lock();
+        if state.is_blacklisted(node_id) {
+            return false;
+        }
+        self.config.as_ref().map_or(true, |config| {
+            state.score(config, node_id, now) >= f64::from(config.min_score)
+        })
+    }
+
+    /// Blacklists the node, replacing the reason of an earlier blacklisting.
+    pub fn blacklist(&self, node_id: EndDeviceId, reason: String, now: DateTime<Utc>) {
+        info!(?node_id, reason, "Node blacklisted");
+        let mut state = self.lock();
+        let score = self
+            .config
+            .as_ref()
+            .map_or(MAX_SCORE, |config| state.score(config, node_id, now));
+        state.blacklist.retain(|entry| entry.node_id != node_id);
+        state.blacklist.push(BlacklistEntry {
+            node_id,
+            reason: reason.clone(),
+            since: now,
+        });
+        state.audit(TrustChange {
+            time: now,
+            node_id,
+            cause: TrustChangeCause::Blacklisted,
+            previous_score: score,
+            score,
+            reason: Some(reason),
+        });
+    }
+
+    /// Removes the node from the blacklist, returns whether it was blacklisted.
+    pub fn unblacklist(&self, node_id: EndDeviceId, now: DateTime<Utc>) -> bool {
+        let mut state = self.lock();
+        let len = state.blacklist.len();
+        state.blacklist.retain(|entry| entry.node_id != node_id);
+        if state.blacklist.len() == len {
+            return false;
+        }
+        info!(?node_id, "Node removed from the blacklist");
+        let score = self
+            .config
+            .as_ref()
+            .map_or(MAX_SCORE, |config| state.score(config, node_id, now));
+        state.audit(TrustChange {
+            time: now,
+            node_id,
+            cause: TrustChangeCause::Unblacklisted,
+            previous_score: score,
+            score,
+            reason: None,
+        });
+        true
+    }
+
+    /// Returns the blacklisted nodes.
+    pub fn blacklisted(&self) -> Vec<BlacklistEntry> {
+        self.lock().blacklist.clone()
+    }
+
+    /// Returns the nodes whose score is below [`MAX_SCORE`] at `now`, ordered by their ID.
+    pub fn scores(&self, now: DateTime<Utc>) -> Vec<NodeTrust> {
+        let Some(config) = &self.config else {
+            return Vec::new();
+        };
+        let mut state = self.lock();
+        let mut node_ids: Vec<EndDeviceId> = state.scores.keys().copied().collect();
+        node_ids.sort_unstable_by_key(|node_id| node_id.0);
+        node_ids
+            .into_iter()
+            .map(|node_id| {
+                let score = state.score(config, node_id, now);
+                NodeTrust {
+                    node_id,
+                    score,
+                    trusted: score >= f64::from(config.min_score) && !state.is_blacklisted(node_id),
+                }
+            })
+            .filter(|node_trust| node_trust.score < MAX_SCORE)
+            .collect()
+    }
+
+    /// Returns the changes of the scores and the blacklist since `since`, oldest first, all kept
+    /// changes if not set.
+    pub fn audit_trail(&self, since: Option<DateTime<Utc>>) -> Vec<TrustChange> {
+        self.lock()
+            .audit_trail
+            .iter()
+            .filter(|change| since.map_or(true, |since| change.time >= since))
+            .cloned()
+            .collect()
+    }
+
+    /// Returns the state to persist.
+    pub fn persisted(&self) -> PersistedNeighborTrust {
+        let state = self.lock();
+        PersistedNeighborTrust {
+            blacklist: state.blacklist.clone(),
+            scores: state.scores.clone(),
+            audit_trail: state.audit_trail.iter().cloned().collect(),
+        }
+    }
+
+    /// Locks the state, a poisoned lock is recovered as the state stays consistent.
+    fn lock(&self) -> MutexGuard<'_, TrustState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::configuration::NeighborTrustConfig;
+    use crate::end_device_id::EndDeviceId;
+    use crate::neighbor_trust::{
+        NeighborTrust, PersistedNeighborTrust, TrustChangeCause, MAX_SCORE,
+    };
+    use chrono::{Duration, Utc};
+
+    fn config() -> NeighborTrustConfig {
+        NeighborTrustConfig {
+            max_packets_per_minute: 3,
+            traffic_penalty: 30,
+            malformed_penalty: 15,
+            recovery_per_hour: 20,
+            min_score: 50,
+        }
+    }
+
+    #[test]
+    fn blacklisted_nodes_are_rejected() {
+        let now = Utc::now();
+        let trust = NeighborTrust::new(
+            None,
+            &[EndDeviceId(1)],
+            PersistedNeighborTrust::default(),
+            now,
+        );
+        assert!(!trust.observe(EndDeviceId(1), now));
+        assert!(trust.observe(EndDeviceId(2), now));
+
+        trust.blacklist(EndDeviceId(2), "flooding the network".to_owned(), now);
+        assert!(!trust.is_trusted(EndDeviceId(2), now));
+        assert!(trust.unblacklist(EndDeviceId(1), now));
+        assert!(!trust.unblacklist(EndDeviceId(1), now));
+        assert!(trust.is_trusted(EndDeviceId(1), now));
+
+        let causes: Vec<_> = trust
+            .audit_trail(None)
+            .iter()
+            .map(|change| change.cause)
+            .collect();
+        assert_eq!(
+            causes,
+            vec![
+                TrustChangeCause::Blacklisted,
+                TrustChangeCause::Unblacklisted
+            ]
+        );
+        // Nodes blacklisted in the configuration are blacklisted again after a restart.
+        let restored = NeighborTrust::new(None, &[EndDeviceId(1)], trust.persisted(), now);
+        assert_eq!(restored.blacklisted().len(), 2);
+    }
+
+    #[test]
+    fn misbehavior_lowers_the_score_until_it_recovers() {
+        let now = Utc::now();
+        let trust = NeighborTrust::new(Some(config()), &[], PersistedNeighborTrust::default(), now);
+        let node_id = EndDeviceId(7);
+        for _ in 0..5 {
+            assert!(trust.observe(node_id, now));
+        }
+        assert!((trust.scores(now)[0].score - (MAX_SCORE - 30.0)).abs() < f64::EPSILON);
+
+        trust.record_malformed(node_id, now);
+        trust.record_malformed(node_id, now);
+        assert!(!trust.is_trusted(node_id, now));
+        assert!(!trust.observe(node_id, now + Duration::minutes(1)));
+        assert!(!trust.scores(now + Duration::minutes(1))[0].trusted);
+
+        // Recovering by 20 per hour, the score of 40 exceeds the minimum score after 30 minutes.
+        let later = now + Duration::minutes(40);
+        assert!(trust.is_trusted(node_id, later));
+        let causes: Vec<_> = trust
+            .audit_trail(None)
+            .iter()
+            .map(|change| change.cause)
+            .collect();
+        assert_eq!(
+            causes,
+            vec![
+                TrustChangeCause::ExcessiveTraffic,
+                TrustChangeCause::MalformedPacket,
+                TrustChangeCause::MalformedPacket,
+                TrustChangeCause::Recovered,
+            ]
+        );
+        assert!(trust.scores(later + Duration::hours(3)).is_empty());
+    }
+}
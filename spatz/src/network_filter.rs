@@ -0,0 +1,116 @@
+//! Filtering of the received packets by their optional network ID prefix, letting multiple
+//! logical networks share the gateways, and counters per network.
+//!
+//! Relayed packets are encoded again and lose their network ID prefix.
+
+use crate::configuration::NetworkFilterConfig;
+use lorawan_dtn_protocol::NetworkId;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// Received packets of a network.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Serialize, JsonSchema)]
+pub struct NetworkStats {
+    /// Network ID of the packets, `None` for packets without network ID prefix.
+    pub network_id: Option<NetworkId>,
+    /// Processed packets.
+    pub accepted: u64,
+    /// Dropped packets of a network which is not accepted.
+    pub dropped: u64,
+}
+
+/// Filter of the received packets by their network ID, counting the packets of every network.
+#[derive(Debug)]
+pub struct NetworkFilter {
+    /// Accepted networks, all networks are accepted if not set.
+    config: Option<NetworkFilterConfig>,
+    /// Counters by network ID.
+    stats: Mutex<BTreeMap<Option<NetworkId>, NetworkStats>>,
+}
+
+impl NetworkFilter {
+    /// Creates a new [`NetworkFilter`] accepting the configured networks or all if not configured.
+    pub fn new(config: Option<NetworkFilterConfig>) -> Self {
+        Self {
+            config,
+            stats: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Returns whether a packet with the network ID is processed and counts it.
+    pub fn accept(&self, network_id: Option<NetworkId>) -> bool {
+        let accepted = self
+            .config
+            .as_ref()
+            .map_or(true, |config| match network_id {
+                Some(network_id) => config.accepted_network_ids.contains(&network_id),
+                None => config.accept_untagged,
+            });
+        let mut stats = self.lock();
+        let entry = stats.entry(network_id).or_insert(NetworkStats {
+            network_id,
+            ..NetworkStats::default()
+        });
+        if accepted {
+            entry.accepted = entry.accepted.saturating_add(1);
+        } else {
+            entry.dropped = entry.dropped.saturating_add(1);
+        }
+        accepted
+    }
+
+    /// Returns the counters of all networks packets were received from, packets without
+    /// network ID first.
+    pub fn stats(&self) -> Vec<NetworkStats> {
+        self.lock().values().copied().collect()
+    }
+
+    /// Locks the counters, a poisoned lock is recovered as the counters stay consistent.
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<Option<NetworkId>, NetworkStats>> {
+        self.stats.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::configuration::NetworkFilterConfig;
+    use crate::network_filter::{NetworkFilter, NetworkStats};
+
+    #[test]
+    fn only_configured_networks_are_accepted() {
+        let filter = NetworkFilter::new(Some(NetworkFilterConfig {
+            accepted_network_ids: vec![7],
+            accept_untagged: false,
+        }));
+        assert!(filter.accept(Some(7)));
+        assert!(!filter.accept(Some(8)));
+        assert!(!filter.accept(None));
+        assert!(filter.accept(Some(7)));
+        assert_eq!(
+            filter.stats(),
+            vec![
+                NetworkStats {
+                    network_id: None,
+                    accepted: 0,
+                    dropped: 1,
+                },
+                NetworkStats {
+                    network_id: Some(7),
+                    accepted: 2,
+                    dropped: 0,
+                },
+                NetworkStats {
+                    network_id: Some(8),
+                    accepted: 0,
+                    dropped: 1,
+                },
+            ]
+        );
+
+        let unfiltered = NetworkFilter::new(None);
+        assert!(unfiltered.accept(Some(8)));
+        assert!(unfiltered.accept(None));
+    }
+}
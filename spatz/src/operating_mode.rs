@@ -0,0 +1,162 @@
+//! Operating mode of the Spatz instance.
+//!
+//! Instead of shutting down when parts of the infrastructure become unavailable, the instance
+//! keeps operating in a degraded mode as long as the MQTT connection to the gateways works.
+
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tracing::{info, warn};
+
+/// Conditions leading to the degraded mode.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize, JsonSchema)]
+pub enum DegradedCondition {
+    /// The ChirpStack API is unreachable, the last known gateway set is used.
+    ChirpStackApiUnreachable,
+    /// The database is read-only, state changes are kept in memory only.
+    DatabaseReadOnly,
+    /// The ChirpStack API and the MQTT broker are unreachable, the local announcement is beaconed
+    /// via the directly attached concentrator.
+    #[cfg(feature = "beaconing")]
+    GatewaysUnreachable,
+}
+
+/// Information about an active [`DegradedCondition`].
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct DegradedConditionInfo {
+    /// The active condition.
+    pub condition: DegradedCondition,
+    /// Time the condition became active.
+    pub since: DateTime<Utc>,
+    /// The last error causing the condition.
+    pub reason: String,
+}
+
+/// The current operating mode.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum Mode {
+    /// All parts of the infrastructure are available.
+    Normal,
+    /// At least one [`DegradedCondition`] is active.
+    Degraded,
+}
+
+/// Status of the operating mode as returned by the API.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct OperatingModeStatus {
+    /// The current operating mode.
+    pub mode: Mode,
+    /// All active degraded conditions, oldest first.
+    pub degraded_conditions: Vec<DegradedConditionInfo>,
+}
+
+/// Keeps track of the active [`DegradedCondition`]s.
+#[derive(Debug, Default)]
+pub struct OperatingMode {
+    /// Active degraded conditions.
+    conditions: HashMap<DegradedCondition, DegradedConditionInfo>,
+}
+
+impl OperatingMode {
+    /// Creates a new [`OperatingMode`] without active degraded conditions.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Activates a degraded condition or updates the reason if it is already active.
+    pub fn enter(&mut self, condition: DegradedCondition, reason: String) {
+        if let Some(info) = self.conditions.get_mut(&condition) {
+            info.reason = reason;
+            return;
+        }
+        warn!("Entering degraded mode, {condition:?}: {reason}");
+        self.conditions.insert(
+            condition,
+            DegradedConditionInfo {
+                condition,
+                since: Utc::now(),
+                reason,
+            },
+        );
+    }
+
+    /// Deactivates a degraded condition.
+    pub fn leave(&mut self, condition: DegradedCondition) {
+        if self.conditions.remove(&condition).is_some() {
+            info!("Recovered from degraded condition {condition:?}");
+        }
+    }
+
+    /// Returns whether the degraded condition is active.
+    pub fn is_active(&self, condition: DegradedCondition) -> bool {
+        self.conditions.contains_key(&condition)
+    }
+
+    /// Returns the current operating mode.
+    pub fn mode(&self) -> Mode {
+        if self.conditions.is_empty() {
+            Mode::Normal
+        } else {
+            Mode::Degraded
+        }
+    }
+
+    /// Returns the status of the operating mode.
+    pub fn status(&self) -> OperatingModeStatus {
+        let mut degraded_conditions: Vec<DegradedConditionInfo> =
+            self.conditions.values().cloned().collect();
+        degraded_conditions.sort_by_key(|info| info.since);
+        OperatingModeStatus {
+            mode: self.mode(),
+            degraded_conditions,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::operating_mode::{DegradedCondition, Mode, OperatingMode};
+
+    #[test]
+    fn enter_and_leave_degraded_mode() {
+        let mut operating_mode = OperatingMode::new();
+        assert_eq!(operating_mode.mode(), Mode::Normal);
+
+        operating_mode.enter(
+            DegradedCondition::ChirpStackApiUnreachable,
+            "connection refused".to_owned(),
+        );
+        operating_mode.enter(
+            DegradedCondition::DatabaseReadOnly,
+            "attempt to write a readonly database".to_owned(),
+        );
+        assert_eq!(operating_mode.mode(), Mode::Degraded);
+        assert!(operating_mode.is_active(DegradedCondition::DatabaseReadOnly));
+
+        operating_mode.leave(DegradedCondition::ChirpStackApiUnreachable);
+        let status = operating_mode.status();
+        assert_eq!(status.mode, Mode::Degraded);
+        assert_eq!(status.degraded_conditions.len(), 1);
+
+        operating_mode.leave(DegradedCondition::DatabaseReadOnly);
+        assert_eq!(operating_mode.mode(), Mode::Normal);
+    }
+
+    #[test]
+    fn reentering_keeps_since() {
+        let mut operating_mode = OperatingMode::new();
+        operating_mode.enter(
+            DegradedCondition::ChirpStackApiUnreachable,
+            "first".to_owned(),
+        );
+        let since = operating_mode.status().degraded_conditions[0].since;
+        operating_mode.enter(
+            DegradedCondition::ChirpStackApiUnreachable,
+            "second".to_owned(),
+        );
+        let status = operating_mode.status();
+        assert_eq!(status.degraded_conditions[0].since, since);
+        assert_eq!(status.degraded_conditions[0].reason, "second");
+    }
+}
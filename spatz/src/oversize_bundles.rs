@@ -0,0 +1,383 @@
+//! Handling of bundles whose payload exceeds the max size of a bundle.
+//!
+//! A bundle is sent in at most 128 fragments at the lowest data rate, which limits its payload to
+//! a few kilobytes. The [`OversizePolicy`] of the routing hints of a bundle, or the configured one
+//! if not set, decides what happens with larger payloads:
+//! - `Reject`: the submission fails, API clients get a `PAYLOAD_TOO_LARGE` error.
+//! - `Compress`: the payload is compressed with the configured payload profile of the `Deflate`
+//!   codec and inflated by the destination before the delivery. Payloads still too large are
+//!   rejected.
+//! - `Split`: consecutive parts of the payload are sent as sub-bundles in fragmented bundle
+//!   fragments. The destination combines every sub-bundle into a BP7 fragment and reassembles the
+//!   original bundle from them. The status of the sub-bundles of recently split bundles is shown
+//!   by the API.
+
+use crate::configuration::{OversizeBundleConfig, OversizePolicy};
+use crate::end_device_id::EndDeviceId;
+use crate::error::{
+    BundleSendBufferConversionError, BundleSendBufferCreationError, OversizeBundleError,
+};
+use crate::payload_codecs::CodecRegistry;
+use crate::routing_hints::RoutingHints;
+use crate::send_buffers::{BundleSendBuffer, SubBundleKey};
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// Max amount of split bundles whose sub-bundle statuses are kept, the oldest are removed first.
+const MAX_TRACKED_SPLITS: usize = 64;
+
+/// Transmission status of a sub-bundle.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SubBundleStatus {
+    /// Queued, not sent completely yet.
+    Queued,
+    /// All fragments were handed to the gateways.
+    Sent,
+    /// Dropped before it was sent completely, e.g. as the queue was full or its routing hints
+    /// expired.
+    Dropped,
+}
+
+/// Sub-bundle of a split bundle.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, JsonSchema)]
+pub struct SubBundleReport {
+    /// Offset of the payload of the sub-bundle within the payload of the original bundle.
+    pub offset: u64,
+    /// Length of the payload of the sub-bundle.
+    pub length: usize,
+    /// Transmission status.
+    pub status: SubBundleStatus,
+}
+
+/// Bundle split into sub-bundles.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, JsonSchema)]
+pub struct SplitBundleReport {
+    /// Source of the bundle.
+    pub source: EndDeviceId,
+    /// Destination of the bundle.
+    pub destination: EndDeviceId,
+    /// Creation timestamp of the bundle.
+    pub timestamp: DateTime<Utc>,
+    /// Length of the payload of the bundle.
+    pub total_length: u64,
+    /// Sub-bundles in the order of their payloads.
+    pub sub_bundles: Vec<SubBundleReport>,
+}
+
+/// Counters of the oversize bundles.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Serialize, JsonSchema)]
+pub struct OversizeBundleStats {
+    /// Oversize bundles rejected, including compressed ones still too large.
+    pub rejected: u64,
+    /// Oversize bundles compressed below the max size.
+    pub compressed: u64,
+    /// Oversize bundles split into sub-bundles.
+    pub split: u64,
+}
+
+/// Policy, counters and recently split bundles.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, JsonSchema)]
+pub struct OversizeBundlesReport {
+    /// Policy applied unless the routing hints of a bundle select another one.
+    pub policy: OversizePolicy,
+    /// Counters of the oversize bundles.
+    pub stats: OversizeBundleStats,
+    /// Recently split bundles, oldest first.
+    pub splits: Vec<SplitBundleReport>,
+}
+
+/// Applies the [`OversizePolicy`] to submitted bundles and tracks the sub-bundles of split
+/// bundles.
+///
+/// Uses a [`std::sync::Mutex`] as the statuses are updated without awaiting.
+#[derive(Debug)]
+pub struct OversizeBundles {
+    /// The configuration.
+    config: OversizeBundleConfig,
+    /// Recently split bundles, oldest first.
+    splits: Mutex<VecDeque<SplitBundleReport>>,
+    /// Oversize bundles rejected.
+    rejected: AtomicU64,
+    /// Oversize bundles compressed below the max size.
+    compressed: AtomicU64,
+    /// Oversize bundles split into sub-bundles.
+    split: AtomicU64,
+}
+
+impl OversizeBundles {
+    /// Creates a new [`OversizeBundles`] without tracked splits.
+    pub fn new(config: OversizeBundleConfig) -> Self {
+        Self {
+            config,
+            splits: Mutex::new(VecDeque::new()),
+            rejected: AtomicU64::new(0),
+            compressed: AtomicU64::new(0),
+            split: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the policy of the bundle, the configured one if its routing hints select none.
+    pub fn policy(&self, routing_hints: &RoutingHints) -> OversizePolicy {
+        routing_hints.oversize_policy.unwrap_or(self.config.policy)
+    }
+
+    /// Returns whether the payload profile compresses oversize bundles.
+    pub fn is_compression_profile(&self, profile: u8) -> bool {
+        self.config.compression_profile == Some(profile)
+    }
+
+    /// Creates the send buffers of a bundle, a single one if its payload does not exceed the max
+    /// size. The payload of a compressed bundle is replaced by the compressed one, so the bundle
+    /// is not compressed again when submitted once more.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the bundle cannot be converted into a send buffer, e.g. its payload
+    /// exceeds the max size and the policy rejects it, or the payload could not be compressed
+    /// below the max size.
+    pub fn send_buffers(
+        &self,
+        bundle: &mut bp7::Bundle,
+        policy: OversizePolicy,
+        payload_codecs: &CodecRegistry,
+        repeater_compatible: bool,
+    ) -> Result<Vec<BundleSendBuffer>, OversizeBundleError> {
+        match BundleSendBuffer::from_bundle(bundle.clone(), repeater_compatible) {
+            Err(BundleSendBufferConversionError::BundleSendBuffer(
+                BundleSendBufferCreationError::PayloadTooLarge,
+            )) => {}
+            result => return Ok(vec![result?]),
+        }
+        match policy {
+            OversizePolicy::Reject => {
+                self.rejected.fetch_add(1, Ordering::Relaxed);
+                Err(BundleSendBufferConversionError::from(
+                    BundleSendBufferCreationError::PayloadTooLarge,
+                )
+                .into())
+            }
+            OversizePolicy::Compress => {
+                let profile = self
+                    .config
+                    .compression_profile
+                    .ok_or(OversizeBundleError::NoCompressionProfile)?;
+                payload_codecs.encode(profile, bundle)?;
+                match BundleSendBuffer::from_bundle(bundle.clone(), repeater_compatible) {
+                    Ok(send_buffer) => {
+                        self.compressed.fetch_add(1, Ordering::Relaxed);
+                        Ok(vec![send_buffer])
+                    }
+                    Err(BundleSendBufferConversionError::BundleSendBuffer(
+                        BundleSendBufferCreationError::PayloadTooLarge,
+                    )) => {
+                        self.rejected.fetch_add(1, Ordering::Relaxed);
+                        Err(OversizeBundleError::StillTooLarge {
+                            size: bundle.payload().map_or(0, Vec::len),
+                            max: BundleSendBuffer::max_payload_len(repeater_compatible),
+                        })
+                    }
+                    Err(err) => Err(err.into()),
+                }
+            }
+            OversizePolicy::Split => Ok(BundleSendBuffer::sub_bundles_from_bundle(
+                bundle.clone(),
+                repeater_compatible,
+            )?),
+        }
+    }
+
+    /// Tracks the sub-bundles of a split bundle as queued, send buffers of bundles which were not
+    /// split are ignored.
+    pub fn track_split(&self, send_buffers: &[BundleSendBuffer]) {
+        let Some(first) = send_buffers.first() else {
+            return;
+        };
+        let Some(total_length) = first.sub_bundle().map(|sub_bundle| sub_bundle.total_length)
+        else {
+            return;
+        };
+        let sub_bundles = send_buffers
+            .iter()
+            .filter_map(|send_buffer| {
+                send_buffer.sub_bundle().map(|sub_bundle| SubBundleReport {
+                    offset: sub_bundle.offset,
+                    length: send_buffer.payload_len(),
+                    status: SubBundleStatus::Queued,
+                })
+            })
+            .collect();
+        self.split.fetch_add(1, Ordering::Relaxed);
+        let mut splits = self.lock();
+        while splits.len() >= MAX_TRACKED_SPLITS {
+            splits.pop_front();
+        }
+        splits.push_back(SplitBundleReport {
+            source: first.source(),
+            destination: first.destination(),
+            timestamp: first.timestamp(),
+            total_length,
+            sub_bundles,
+        });
+    }
+
+    /// Updates the status of a tracked sub-bundle, untracked ones are ignored.
+    pub fn update(&self, key: SubBundleKey, status: SubBundleStatus) {
+        let (source, destination, timestamp, offset) = key;
+        let mut splits = self.lock();
+        let sub_bundle = splits
+            .iter_mut()
+            .rev()
+            .filter(|split| {
+                split.source == source
+                    && split.destination == destination
+                    && split.timestamp == timestamp
+            })
+            .flat_map(|split| split.sub_bundles.iter_mut())
+            .find(|sub_bundle| sub_bundle.offset == offset);
+        if let Some(sub_bundle) = sub_bundle {
+            sub_bundle.status = status;
+        }
+    }
+
+    /// Returns the policy, the counters and the recently split bundles.
+    pub fn report(&self) -> OversizeBundlesReport {
+        OversizeBundlesReport {
+            policy: self.config.policy,
+            stats: OversizeBundleStats {
+                rejected: self.rejected.load(Ordering::Relaxed),
+                compressed: self.compressed.load(Ordering::Relaxed),
+                split: self.split.load(Ordering::Relaxed),
+            },
+            splits: self.lock().iter().cloned().collect(),
+        }
+    }
+
+    /// Locks the split bundles, a poisoned lock is still used as the statuses stay valid.
+    fn lock(&self) -> MutexGuard<'_, VecDeque<SplitBundleReport>> {
+        self.splits.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use crate::configuration::{
+        OversizeBundleConfig, OversizePolicy, PayloadCodecKind, PayloadProfileConfig,
+    };
+    use crate::end_device_id::EndDeviceId;
+    use crate::error::OversizeBundleError;
+    use crate::oversize_bundles::{OversizeBundles, SubBundleStatus};
+    use crate::payload_codecs::CodecRegistry;
+    use crate::routing_hints::RoutingHints;
+    use crate::send_buffers::{BundleSendBuffer, SendBuffer};
+    use bp7::flags::BlockControlFlags;
+    use chirpstack_gwb_integration::downlinks::predefined_parameters::DataRate;
+    use lorawan_dtn_protocol::reassembly::BundleReceiveBuffer;
+
+    fn bundle(payload: Vec<u8>) -> bp7::Bundle {
+        bp7::primary::PrimaryBlockBuilder::new()
+            .source(EndDeviceId(0x1234).try_into().unwrap())
+            .destination(EndDeviceId(0x5678).try_into().unwrap())
+            .build()
+            .map(|primary| {
+                bp7::Bundle::new(
+                    primary,
+                    vec![bp7::canonical::new_payload_block(
+                        BlockControlFlags::empty(),
+                        payload,
+                    )],
+                )
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn oversize_bundles_are_rejected_or_compressed() {
+        let codecs = CodecRegistry::new(&[PayloadProfileConfig {
+            profile: 9,
+            name: "compressed".to_owned(),
+            codec: PayloadCodecKind::Deflate,
+        }]);
+        let oversize_bundles = OversizeBundles::new(OversizeBundleConfig {
+            policy: OversizePolicy::Reject,
+            compression_profile: Some(9),
+        });
+        let payload = vec![0xAB; BundleSendBuffer::max_payload_len(false) + 1];
+        assert_eq!(
+            oversize_bundles.policy(&RoutingHints::default()),
+            OversizePolicy::Reject
+        );
+        let policy = oversize_bundles.policy(&RoutingHints {
+            oversize_policy: Some(OversizePolicy::Compress),
+            ..RoutingHints::default()
+        });
+        assert_eq!(policy, OversizePolicy::Compress);
+
+        let mut rejected = bundle(payload.clone());
+        assert!(matches!(
+            oversize_bundles.send_buffers(&mut rejected, OversizePolicy::Reject, &codecs, false),
+            Err(OversizeBundleError::Conversion(_))
+        ));
+        let mut compressed = bundle(payload);
+        let send_buffers = oversize_bundles
+            .send_buffers(&mut compressed, policy, &codecs, false)
+            .unwrap();
+        assert_eq!(send_buffers.len(), 1);
+        assert_eq!(compressed.payload().unwrap()[0], 9);
+
+        let stats = oversize_bundles.report().stats;
+        assert_eq!((stats.rejected, stats.compressed, stats.split), (1, 1, 0));
+    }
+
+    #[test]
+    fn split_bundles_are_sent_as_bp7_fragments() {
+        let oversize_bundles = OversizeBundles::new(OversizeBundleConfig::default());
+        let payload: Vec<u8> = (0..BundleSendBuffer::max_payload_len(false) * 2)
+            .map(|index| u8::try_from(index % 251).unwrap())
+            .collect();
+        let mut split = bundle(payload.clone());
+        let mut send_buffers = oversize_bundles
+            .send_buffers(
+                &mut split,
+                OversizePolicy::Split,
+                &CodecRegistry::default(),
+                false,
+            )
+            .unwrap();
+        assert_eq!(send_buffers.len(), 3);
+        oversize_bundles.track_split(&send_buffers);
+
+        let mut reassembled = vec![0; payload.len()];
+        for send_buffer in &mut send_buffers {
+            let mut packet = send_buffer.next_packet(DataRate::Eu863_870Dr0).unwrap();
+            let mut receive_buffer =
+                BundleReceiveBuffer::from(packet.as_bundle_packet_mut().unwrap());
+            while !send_buffer.is_empty() {
+                let mut packet = send_buffer.next_packet(DataRate::Eu863_870Dr0).unwrap();
+                receive_buffer
+                    .process_packet(packet.as_bundle_packet_mut().unwrap())
+                    .unwrap();
+            }
+            let fragment = receive_buffer.combine().unwrap();
+            assert!(fragment.primary.has_fragmentation());
+            let offset = usize::try_from(fragment.primary.fragmentation_offset).unwrap();
+            let fragment_payload = fragment.payload().unwrap();
+            reassembled[offset..offset + fragment_payload.len()].copy_from_slice(fragment_payload);
+            oversize_bundles.update(send_buffer.sub_bundle_key().unwrap(), SubBundleStatus::Sent);
+        }
+        assert_eq!(reassembled, payload);
+
+        let report = oversize_bundles.report();
+        assert_eq!(report.stats.split, 1);
+        assert_eq!(report.splits.len(), 1);
+        assert!(report.splits[0]
+            .sub_bundles
+            .iter()
+            .all(|sub_bundle| sub_bundle.status == SubBundleStatus::Sent));
+    }
+}
@@ -1,15 +1,150 @@
 //! Packet cache to prevent sending packets that were already sent.
+//!
+//! Announcements, fragments and complete bundles may be cached with their own timeout and size
+//! limit. The class of restored entries is not persisted, they expire after the global timeout.
 
+use crate::clock::SharedClock;
+use crate::configuration::{PacketCacheConfig, PacketClassCacheConfig};
 use crate::error::PacketCacheError;
 use crate::graceful_shutdown::ShutdownAgent;
+use crate::watchdog::PACKET_CACHE_CLEANER;
 use crate::{AppState, Duration};
+use chirpstack_gwb_integration::gateway_id::GatewayId;
 use chrono::{DateTime, Utc};
+use lorawan_dtn_protocol::{PacketType, NETWORK_ID_FLAG};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 use sha3::Digest;
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use tokio::sync::Mutex;
-use tracing::{instrument, trace};
+use tracing::{info, instrument, trace};
+
+/// Origin of a cached packet.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, JsonSchema)]
+pub enum PacketSource {
+    /// Received in an uplink of the gateway.
+    Uplink {
+        /// ID of the receiving gateway.
+        gateway_id: GatewayId,
+    },
+    /// Sent, relayed or injected by this node.
+    Local,
+    /// Restored from the database, the origin is not persisted.
+    Restored,
+}
+
+/// Class of a cached packet, every class but [`PacketClass::Other`] may be configured with its own
+/// timeout and size limit.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+pub enum PacketClass {
+    /// Announcements of end device IDs, services, channels, capabilities or data rates.
+    Announcement,
+    /// Bundle fragments and hop-to-hop fragments.
+    Fragment,
+    /// Bundles sent in a single packet.
+    CompleteBundle,
+    /// All other packets, e.g. echo requests and status reports, and restored entries.
+    Other,
+}
+
+impl PacketClass {
+    /// Returns the class of the phy payload from its packet type, skipping a network ID prefix.
+    pub fn of(phy_payload: &[u8]) -> Self {
+        let type_index = if phy_payload
+            .first()
+            .is_some_and(|mhdr| mhdr & NETWORK_ID_FLAG != 0)
+        {
+            5
+        } else {
+            1
+        };
+        let packet_type = phy_payload.get(type_index).and_then(|packet_type| {
+            PacketType::ALL
+                .into_iter()
+                .find(|candidate| *candidate as u8 == *packet_type)
+        });
+        match packet_type {
+            Some(
+                PacketType::LocalAnnouncement
+                | PacketType::ReachabilityAnnouncement
+                | PacketType::ServiceAnnouncement
+                | PacketType::ChannelPlanAnnouncement
+                | PacketType::CapabilityAnnouncement
+                | PacketType::DataRateAnnouncement,
+            ) => PacketClass::Announcement,
+            Some(
+                PacketType::BundleFragment
+                | PacketType::BundleFragmentEnd
+                | PacketType::FragmentedBundleFragment
+                | PacketType::FragmentedBundleFragmentEnd
+                | PacketType::Hop2HopFragment,
+            ) => PacketClass::Fragment,
+            Some(PacketType::CompleteBundle | PacketType::Bp7Bundle) => PacketClass::CompleteBundle,
+            Some(
+                PacketType::EchoRequest
+                | PacketType::EchoReply
+                | PacketType::HopAck
+                | PacketType::StatusReportRequest
+                | PacketType::StatusReport
+                | PacketType::BundleResendRequest,
+            )
+            | None => PacketClass::Other,
+        }
+    }
+}
+
+/// Entry of the packet cache.
+#[derive(Debug, Clone)]
+struct CacheEntry {
+    /// Time the packet was last inserted.
+    seen_at: DateTime<Utc>,
+    /// Origin of the packet when it was last inserted.
+    source: PacketSource,
+    /// Class of the packet.
+    class: PacketClass,
+}
+
+/// Cached packet hash as returned by the API.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct CachedPacket {
+    /// Hex encoded SHA3-256 hash of the phy payload.
+    pub hash: String,
+    /// Time the packet was last inserted.
+    pub seen_at: DateTime<Utc>,
+    /// Seconds since the packet was last inserted.
+    pub age_seconds: i64,
+    /// Origin of the packet when it was last inserted.
+    pub source: PacketSource,
+    /// Class of the packet.
+    pub class: PacketClass,
+}
+
+/// Counters of the packet cache since the start.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct PacketCacheStats {
+    /// Inserted packets already seen within the timeout, these were ignored.
+    pub hits: u64,
+    /// Inserted packets not seen within the timeout.
+    pub misses: u64,
+    /// Entries removed as their timeout elapsed.
+    pub expired: u64,
+    /// Entries evicted to stay within the memory budget or the limit of their class.
+    pub evicted: u64,
+    /// Entries removed by flushing the cache.
+    pub flushed: u64,
+}
+
+/// Contents and counters of the packet cache.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct PacketCacheReport {
+    /// Cached packets, most recently seen first.
+    pub packets: Vec<CachedPacket>,
+    /// Counters since the start.
+    pub stats: PacketCacheStats,
+}
 
 /// Caches hashes of sent and received packets.
 ///
@@ -17,83 +152,259 @@ use tracing::{instrument, trace};
 /// processing and routing of the same packet until the timeout has run out.
 #[derive(Debug)]
 pub struct PacketCache {
-    /// HashMap containing the uplink hash and a timestamp.
-    cache: Arc<Mutex<HashMap<String, DateTime<Utc>>>>,
+    /// HashMap containing the uplink hash, a timestamp and the origin.
+    cache: Arc<Mutex<HashMap<String, CacheEntry>>>,
     /// Timeout duration. Withing this duration, the same uplink will be ignored.
     timeout: Duration,
+    /// Timeout and size limit of the configured packet classes.
+    class_configs: HashMap<PacketClass, PacketClassCacheConfig>,
     /// Interval at which the expired entries are removed from the cache.
     cleanup_interval_seconds: u64,
     /// Reset the timeout if the packet is seen again.
     reset_timeout: bool,
+    /// Source of the time the packets are seen at.
+    clock: SharedClock,
+    /// Inserted packets already seen within the timeout.
+    hits: AtomicU64,
+    /// Inserted packets not seen within the timeout.
+    misses: AtomicU64,
+    /// Entries removed as their timeout elapsed.
+    expired: AtomicU64,
+    /// Entries evicted to stay within the memory budget or the limit of their class.
+    evicted: AtomicU64,
+    /// Entries removed by flushing the cache.
+    flushed: AtomicU64,
 }
 
 impl PacketCache {
-    /// Create a new [`PacketCache`].
+    /// Create a new [`PacketCache`] from the persisted hashes and timestamps.
     pub fn new(
         cache: HashMap<String, DateTime<Utc>>,
-        timeout_minutes: u32,
-        cleanup_interval_seconds: u64,
-        reset_timeout: bool,
+        config: &PacketCacheConfig,
+        clock: SharedClock,
     ) -> Self {
+        let cache = cache
+            .into_iter()
+            .map(|(hash, seen_at)| {
+                (
+                    hash,
+                    CacheEntry {
+                        seen_at,
+                        source: PacketSource::Restored,
+                        class: PacketClass::Other,
+                    },
+                )
+            })
+            .collect();
+        let class_configs = [
+            PacketClass::Announcement,
+            PacketClass::Fragment,
+            PacketClass::CompleteBundle,
+        ]
+        .into_iter()
+        .filter_map(|class| {
+            config
+                .class_config(class)
+                .map(|class_config| (class, class_config.clone()))
+        })
+        .collect();
         PacketCache {
             cache: Arc::new(Mutex::new(cache)),
-            timeout: Duration::minutes(i64::from(timeout_minutes)),
-            cleanup_interval_seconds,
-            reset_timeout,
+            timeout: Duration::minutes(i64::from(config.timeout_minutes)),
+            class_configs,
+            cleanup_interval_seconds: config.cleanup_interval_seconds,
+            reset_timeout: config.reset_timeout,
+            clock,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            expired: AtomicU64::new(0),
+            evicted: AtomicU64::new(0),
+            flushed: AtomicU64::new(0),
         }
     }
-    /// Remove all entries of the cache for which the timout has elapsed.
+    /// Returns the timeout of the packet class, the global timeout if the class has none.
+    fn timeout(&self, class: PacketClass) -> Duration {
+        self.class_configs
+            .get(&class)
+            .and_then(|class_config| class_config.timeout_minutes)
+            .map_or(self.timeout, |timeout_minutes| {
+                Duration::minutes(i64::from(timeout_minutes))
+            })
+    }
+
+    /// Remove all entries of the cache for which the timout of their class has elapsed.
     pub async fn remove_expired_packets(&self) {
         trace!("Removing expired packets from packet cache");
-        let timeout = self.timeout;
-        let now = Utc::now();
-        self.cache
-            .lock()
-            .await
-            .retain(|_hash, timestamp| now - *timestamp < timeout);
+        let now = self.clock.now();
+        let mut cache_lock = self.cache.lock().await;
+        let cached_packets = cache_lock.len();
+        cache_lock.retain(|_hash, entry| now - entry.seen_at < self.timeout(entry.class));
+        self.expired.fetch_add(
+            u64::try_from(cached_packets.saturating_sub(cache_lock.len())).unwrap_or(u64::MAX),
+            Ordering::Relaxed,
+        );
     }
 
     /// Insert a new entry into the cache.
     ///
     /// Depending on the `reset_timeout` field of the [`PacketCache`] struct, the timeout is reset when the
-    /// same entry is inserted while already present.
+    /// same entry is inserted while already present. The timeout depends on the class of the
+    /// packet, the entries of the class seen the longest time ago are evicted beyond its limit.
     ///
     /// # Error:
     /// If the entry is already present in the cache, an error is returned.
-    pub async fn insert(&self, packet: &[u8]) -> Result<(), PacketCacheError> {
+    pub async fn insert(
+        &self,
+        packet: &[u8],
+        source: PacketSource,
+    ) -> Result<(), PacketCacheError> {
         let packet_hash: [u8; 32] = <[u8; 32]>::from(sha3::Sha3_256::digest(packet));
         // Use the string representation as that can be de-/serialized.
         let packet_hash_string = hex::encode(packet_hash);
 
+        let class = PacketClass::of(packet);
+        let now = self.clock.now();
         let mut cache_lock = self.cache.lock().await;
-        match cache_lock.entry(packet_hash_string) {
+        let new_entry = CacheEntry {
+            seen_at: now,
+            source,
+            class,
+        };
+        let inserted = match cache_lock.entry(packet_hash_string) {
             Entry::Occupied(mut entry) => {
-                if Utc::now() - *entry.get() < self.timeout {
+                if now - entry.get().seen_at < self.timeout(class) {
                     trace!("Packet has already been seen within the timeout duration, skipping");
+                    self.hits.fetch_add(1, Ordering::Relaxed);
                     if self.reset_timeout {
                         trace!("Resetting packet timeout.");
-                        entry.insert(Utc::now());
+                        entry.insert(new_entry);
                     }
                     Err(PacketCacheError::NotTimedOut)
                 } else {
                     trace!(
                         "Packet has already been seen but timeout elapsed, adding to packet cache"
                     );
-                    entry.insert(Utc::now());
+                    self.misses.fetch_add(1, Ordering::Relaxed);
+                    entry.insert(new_entry);
                     Ok(())
                 }
             }
             Entry::Vacant(entry) => {
                 trace!("Packet has not been seen before, adding to packet cache");
-                entry.insert(Utc::now());
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                entry.insert(new_entry);
                 Ok(())
             }
+        };
+        if let Some(max_entries) = self
+            .class_configs
+            .get(&class)
+            .and_then(|class_config| class_config.max_entries)
+        {
+            let mut entries: Vec<_> = cache_lock
+                .iter()
+                .filter(|(_, entry)| entry.class == class)
+                .map(|(hash, entry)| (entry.seen_at, hash.clone()))
+                .collect();
+            if entries.len() > max_entries {
+                entries.sort_unstable();
+                let evicted = entries.len().saturating_sub(max_entries);
+                trace!("Evicting {evicted} packets of class {class:?} beyond its limit");
+                for (_, hash) in entries.into_iter().take(evicted) {
+                    cache_lock.remove(&hash);
+                }
+                self.evicted.fetch_add(
+                    u64::try_from(evicted).unwrap_or(u64::MAX),
+                    Ordering::Relaxed,
+                );
+            }
         }
+        inserted
     }
 
-    /// Returns the contents of the packet cache.
+    /// Returns the hashes and timestamps of the packet cache.
     pub async fn contents(&self) -> HashMap<String, DateTime<Utc>> {
-        self.cache.lock().await.clone()
+        self.cache
+            .lock()
+            .await
+            .iter()
+            .map(|(hash, entry)| (hash.clone(), entry.seen_at))
+            .collect()
+    }
+
+    /// Returns the cached packets with their age and origin, and the counters.
+    pub async fn report(&self, now: DateTime<Utc>) -> PacketCacheReport {
+        let mut packets: Vec<_> = self
+            .cache
+            .lock()
+            .await
+            .iter()
+            .map(|(hash, entry)| CachedPacket {
+                hash: hash.clone(),
+                seen_at: entry.seen_at,
+                age_seconds: (now - entry.seen_at).num_seconds(),
+                source: entry.source.clone(),
+                class: entry.class,
+            })
+            .collect();
+        packets.sort_unstable_by(|a, b| b.seen_at.cmp(&a.seen_at));
+        PacketCacheReport {
+            packets,
+            stats: self.stats(),
+        }
+    }
+
+    /// Returns the counters since the start.
+    pub fn stats(&self) -> PacketCacheStats {
+        PacketCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            expired: self.expired.load(Ordering::Relaxed),
+            evicted: self.evicted.load(Ordering::Relaxed),
+            flushed: self.flushed.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Removes all entries so every packet is processed again, returns the amount of removed
+    /// entries.
+    pub async fn flush(&self) -> usize {
+        let flushed = {
+            let mut cache_lock = self.cache.lock().await;
+            let flushed = cache_lock.len();
+            cache_lock.clear();
+            flushed
+        };
+        info!(flushed, "Packet cache flushed");
+        self.flushed.fetch_add(
+            u64::try_from(flushed).unwrap_or(u64::MAX),
+            Ordering::Relaxed,
+        );
+        flushed
+    }
+
+    /// Returns the amount of cached packets.
+    pub async fn cached_packets(&self) -> usize {
+        self.cache.lock().await.len()
+    }
+
+    /// Removes up to `count` entries seen the longest time ago, returns the amount of removed
+    /// entries.
+    pub async fn evict_oldest(&self, count: usize) -> usize {
+        let mut cache_lock = self.cache.lock().await;
+        let mut entries: Vec<_> = cache_lock
+            .iter()
+            .map(|(hash, entry)| (entry.seen_at, hash.clone()))
+            .collect();
+        entries.sort_unstable();
+        let evicted = count.min(entries.len());
+        for (_, hash) in entries.into_iter().take(evicted) {
+            cache_lock.remove(&hash);
+        }
+        self.evicted.fetch_add(
+            u64::try_from(evicted).unwrap_or(u64::MAX),
+            Ordering::Relaxed,
+        );
+        evicted
     }
 }
 
@@ -103,6 +414,7 @@ pub async fn cache_clean_task(state: Arc<AppState>, mut shutdown_agent: Shutdown
     trace!("Starting up");
     loop {
         state.packet_cache.remove_expired_packets().await;
+        state.watchdog.beat(PACKET_CACHE_CLEANER);
 
         tokio::select! {
             _ = tokio::time::sleep(tokio::time::Duration::from_secs(state.packet_cache.cleanup_interval_seconds)) => {},
@@ -116,15 +428,164 @@ pub async fn cache_clean_task(state: Arc<AppState>, mut shutdown_agent: Shutdown
 
 #[allow(clippy::unwrap_used)]
 #[cfg(test)]
+#[allow(clippy::unwrap_used)]
 mod tests {
+    use crate::clock::{MockClock, SystemClock};
+    use crate::configuration::{PacketCacheConfig, PacketClassCacheConfig};
+    use crate::packet_cache::{PacketClass, PacketSource};
     use crate::PacketCache;
+    use chrono::{Duration, Utc};
     use std::collections::HashMap;
 
+    fn config() -> PacketCacheConfig {
+        PacketCacheConfig {
+            timeout_minutes: 30,
+            cleanup_interval_seconds: 30,
+            reset_timeout: false,
+            announcements: None,
+            fragments: None,
+            complete_bundles: None,
+        }
+    }
+
     #[tokio::test]
     async fn packet_cache_insert() {
-        let packet_cache = PacketCache::new(HashMap::new(), 30, 30, false);
+        let packet_cache = PacketCache::new(HashMap::new(), &config(), SystemClock::shared());
         let packet = [0xFF; 300];
-        assert!(packet_cache.insert(&packet).await.is_ok());
-        assert!(packet_cache.insert(&packet).await.is_err());
+        assert!(packet_cache
+            .insert(&packet, PacketSource::Local)
+            .await
+            .is_ok());
+        assert!(packet_cache
+            .insert(&packet, PacketSource::Local)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn report_sources_and_counters() {
+        let restored_hash = "ab".repeat(32);
+        let packet_cache = PacketCache::new(
+            HashMap::from([(restored_hash.clone(), Utc::now())]),
+            &config(),
+            SystemClock::shared(),
+        );
+        let source = PacketSource::Uplink {
+            gateway_id: "0016c001ff10a235".parse().unwrap(),
+        };
+        assert!(packet_cache.insert(&[0x01], source.clone()).await.is_ok());
+        assert!(packet_cache
+            .insert(&[0x01], PacketSource::Local)
+            .await
+            .is_err());
+
+        let report = packet_cache.report(Utc::now()).await;
+        assert_eq!(report.packets.len(), 2);
+        assert_eq!(report.packets[0].source, source);
+        assert_eq!(report.packets[1].hash, restored_hash);
+        assert_eq!(report.packets[1].source, PacketSource::Restored);
+        assert_eq!((report.stats.hits, report.stats.misses), (1, 1));
+
+        assert_eq!(packet_cache.evict_oldest(1).await, 1);
+        assert_eq!(packet_cache.flush().await, 1);
+        assert_eq!(packet_cache.cached_packets().await, 0);
+        let stats = packet_cache.stats();
+        assert_eq!((stats.evicted, stats.flushed), (1, 1));
+        assert!(packet_cache
+            .insert(&[0x01], PacketSource::Local)
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn class_timeouts_and_limits() {
+        let packet_cache = PacketCache::new(
+            HashMap::new(),
+            &PacketCacheConfig {
+                announcements: Some(PacketClassCacheConfig {
+                    timeout_minutes: Some(0),
+                    max_entries: None,
+                }),
+                fragments: Some(PacketClassCacheConfig {
+                    timeout_minutes: None,
+                    max_entries: Some(1),
+                }),
+                ..config()
+            },
+            SystemClock::shared(),
+        );
+        // Local announcement, seen again right away as the timeout of announcements is 0.
+        let announcement = [0xE0, 0x06, 0x01, 0x02, 0x03, 0x04];
+        assert_eq!(PacketClass::of(&announcement), PacketClass::Announcement);
+        assert!(packet_cache
+            .insert(&announcement, PacketSource::Local)
+            .await
+            .is_ok());
+        assert!(packet_cache
+            .insert(&announcement, PacketSource::Local)
+            .await
+            .is_ok());
+
+        // Bundle fragments with and without network ID, only one is kept.
+        let fragment = [0xE0, 0x01, 0x01];
+        let tagged_fragment = [0xE4, 0x01, 0x00, 0x00, 0x00, 0x01, 0x02];
+        assert_eq!(PacketClass::of(&tagged_fragment), PacketClass::Fragment);
+        assert!(packet_cache
+            .insert(&fragment, PacketSource::Local)
+            .await
+            .is_ok());
+        assert!(packet_cache
+            .insert(&tagged_fragment, PacketSource::Local)
+            .await
+            .is_ok());
+
+        let report = packet_cache.report(Utc::now()).await;
+        assert_eq!(report.stats.evicted, 1);
+        assert_eq!(
+            report
+                .packets
+                .iter()
+                .filter(|packet| packet.class == PacketClass::Fragment)
+                .count(),
+            1
+        );
+        assert_eq!(PacketClass::of(&[0xE0]), PacketClass::Other);
+    }
+
+    #[tokio::test]
+    async fn packets_expire_after_the_timeout() {
+        let clock = MockClock::new(Utc::now());
+        let packet_cache = PacketCache::new(
+            HashMap::new(),
+            &PacketCacheConfig {
+                reset_timeout: true,
+                ..config()
+            },
+            clock.clone(),
+        );
+        let packet = [0xE0, 0x01, 0x01];
+        assert!(packet_cache
+            .insert(&packet, PacketSource::Local)
+            .await
+            .is_ok());
+
+        // Seeing the packet again resets the timeout.
+        clock.advance(Duration::minutes(20));
+        assert!(packet_cache
+            .insert(&packet, PacketSource::Local)
+            .await
+            .is_err());
+        clock.advance(Duration::minutes(20));
+        packet_cache.remove_expired_packets().await;
+        assert_eq!(packet_cache.cached_packets().await, 1);
+
+        clock.advance(Duration::minutes(10));
+        packet_cache.remove_expired_packets().await;
+        assert_eq!(packet_cache.cached_packets().await, 0);
+        assert_eq!(packet_cache.stats().expired, 1);
+        assert!(packet_cache
+            .insert(&packet, PacketSource::Local)
+            .await
+            .is_ok());
     }
 }
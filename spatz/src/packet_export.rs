@@ -0,0 +1,814 @@
+//! Export of anonymized per-packet records for research.
+//!
+//! If configured, every received and sent protocol packet is recorded with its type, size, data
+//! rate, signal quality, the hop distance to its sender and the age of its bundle where known. The
+//! records are buffered and periodically written to CSV files, which are rotated, or Parquet
+//! files, which cannot be appended to and are written once per flush. Files older than the
+//! retention are removed.
+//!
+//! End device IDs and gateway IDs are replaced by truncated keyed SHA3-256 pseudonyms, so the
+//! records of a run can be correlated without revealing the nodes. The columns are described in
+//! the `schema.json` file next to the records, so exports can be processed without this
+//! documentation.
+
+use crate::configuration::{PacketExportConfig, PacketExportField, PacketExportFormat};
+use crate::error::PacketExportError;
+use crate::graceful_shutdown::ShutdownAgent;
+use crate::neighbor_trust::packet_sender;
+use crate::AppState;
+use chirpstack_gwb_integration::downlinks::predefined_parameters::DataRate;
+use chirpstack_gwb_integration::gateway_id::GatewayId;
+use chirpstack_gwb_integration::uplinks::UplinkInfo;
+use chrono::{DateTime, Duration, Utc};
+use lorawan_dtn_protocol::{parse_phy_payload, LoRaWanPacket, PacketType};
+use serde::Serialize;
+use sha3::{Digest, Sha3_256};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use tokio::io::AsyncWriteExt;
+use tracing::{error, instrument, trace, warn};
+
+/// Maximum amount of records buffered between two flushes, further records are dropped.
+const MAX_BUFFERED_RECORDS: usize = 10_000;
+
+/// Bytes of the keyed hash kept as pseudonym.
+const PSEUDONYM_LENGTH: usize = 8;
+
+/// Prefix of the exported files, only files with this prefix are removed after the retention.
+const FILE_PREFIX: &str = "packets-";
+
+/// Name of the file describing the columns.
+const SCHEMA_FILE: &str = "schema.json";
+
+/// Whether a packet was received or sent.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum PacketDirection {
+    /// Received via a gateway.
+    Uplink,
+    /// Sent via a gateway.
+    Downlink,
+}
+
+/// Anonymized record of a received or sent packet.
+#[derive(Debug, Clone, PartialEq)]
+struct PacketRecord {
+    /// Time the packet was received or sent.
+    timestamp: DateTime<Utc>,
+    /// Whether the packet was received or sent.
+    direction: PacketDirection,
+    /// Type of the packet.
+    packet_type: PacketType,
+    /// Size of the phy payload in bytes.
+    size: usize,
+    /// Index of the data rate, unknown if the gateway did not report the modulation.
+    data_rate: Option<u8>,
+    /// RSSI in dBm, only known for received packets.
+    rssi: Option<i32>,
+    /// SNR in dB, only known for received packets.
+    snr: Option<f32>,
+    /// Hop distance to the sender, only known for received packets of nodes in the neighbor
+    /// table.
+    hops: Option<u8>,
+    /// Time since the creation of the bundle in milliseconds, only known for bundle packets.
+    latency_ms: Option<i64>,
+    /// Pseudonym of the source or announcing end device ID.
+    source: Option<String>,
+    /// Pseudonym of the gateway which received the packet.
+    gateway: Option<String>,
+}
+
+/// Type of the values of a column.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum ColumnType {
+    /// Milliseconds since the Unix epoch.
+    Timestamp,
+    /// Signed integer.
+    Integer,
+    /// Floating point number.
+    Real,
+    /// UTF-8 text.
+    Text,
+}
+
+/// Description of a column in the schema file.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+struct ColumnDescription {
+    /// Name of the column, used in the CSV header row and the Parquet schema.
+    name: &'static str,
+    /// Type of the values.
+    #[serde(rename = "type")]
+    column_type: ColumnType,
+    /// Unit of the values, if any.
+    unit: Option<&'static str>,
+    /// Whether values may be missing, empty in CSV files and null in Parquet files.
+    nullable: bool,
+    /// Meaning of the values.
+    description: &'static str,
+}
+
+/// Contents of the schema file.
+#[derive(Debug, Clone, Serialize)]
+struct SchemaDescription {
+    /// Format of the files.
+    format: &'static str,
+    /// Pattern of the file names.
+    files: String,
+    /// How the IDs are pseudonymized.
+    pseudonyms: &'static str,
+    /// Columns in their order.
+    columns: Vec<ColumnDescription>,
+}
+
+/// Returns the description of the column of the field.
+fn column(field: PacketExportField) -> ColumnDescription {
+    let (name, column_type, unit, nullable, description) = match field {
+        PacketExportField::Timestamp => (
+            "timestamp",
+            ColumnType::Timestamp,
+            Some("ms"),
+            false,
+            "Time the packet was received or sent, since the Unix epoch in UTC",
+        ),
+        PacketExportField::Direction => (
+            "direction",
+            ColumnType::Text,
+            None,
+            false,
+            "Uplink if the packet was received, Downlink if it was sent",
+        ),
+        PacketExportField::PacketType => (
+            "packet_type",
+            ColumnType::Text,
+            None,
+            false,
+            "Type of the protocol packet, e.g. CompleteBundle or LocalAnnouncement",
+        ),
+        PacketExportField::Size => (
+            "size",
+            ColumnType::Integer,
+            Some("bytes"),
+            false,
+            "Size of the phy payload",
+        ),
+        PacketExportField::DataRate => (
+            "data_rate",
+            ColumnType::Integer,
+            None,
+            true,
+            "Index of the EU868 data rate, missing if the modulation was not reported",
+        ),
+        PacketExportField::Rssi => (
+            "rssi",
+            ColumnType::Integer,
+            Some("dBm"),
+            true,
+            "RSSI reported by the gateway, missing for sent packets",
+        ),
+        PacketExportField::Snr => (
+            "snr",
+            ColumnType::Real,
+            Some("dB"),
+            true,
+            "LoRa SNR reported by the gateway, missing for sent packets",
+        ),
+        PacketExportField::Hops => (
+            "hops",
+            ColumnType::Integer,
+            None,
+            true,
+            "Hop distance to the sender known from the neighbor table, missing if unknown",
+        ),
+        PacketExportField::LatencyMs => (
+            "latency_ms",
+            ColumnType::Integer,
+            Some("ms"),
+            true,
+            "Time since the creation of the bundle, missing for packets without bundle timestamp",
+        ),
+        PacketExportField::Source => (
+            "source",
+            ColumnType::Text,
+            None,
+            true,
+            "Pseudonym of the source or announcing end device ID, missing if the packet has none",
+        ),
+        PacketExportField::Gateway => (
+            "gateway",
+            ColumnType::Text,
+            None,
+            true,
+            "Pseudonym of the gateway which received the packet, missing for sent packets",
+        ),
+    };
+    ColumnDescription {
+        name,
+        column_type,
+        unit,
+        nullable,
+        description,
+    }
+}
+
+/// Value of a record in a column.
+#[derive(Debug, Clone, PartialEq)]
+enum ColumnValue {
+    /// Value of a timestamp or integer column.
+    Integer(Option<i64>),
+    /// Value of a real column.
+    Real(Option<f32>),
+    /// Value of a text column.
+    Text(Option<String>),
+}
+
+impl ColumnValue {
+    /// Returns whether the value is missing.
+    #[cfg(feature = "parquet-export")]
+    fn is_null(&self) -> bool {
+        match self {
+            ColumnValue::Integer(value) => value.is_none(),
+            ColumnValue::Real(value) => value.is_none(),
+            ColumnValue::Text(value) => value.is_none(),
+        }
+    }
+
+    /// Returns the value as CSV cell, empty if missing.
+    fn to_csv(&self) -> String {
+        match self {
+            ColumnValue::Integer(value) => value.as_ref().map(ToString::to_string),
+            ColumnValue::Real(value) => value.as_ref().map(ToString::to_string),
+            ColumnValue::Text(value) => value.clone(),
+        }
+        .unwrap_or_default()
+    }
+}
+
+impl PacketRecord {
+    /// Returns the value of the field.
+    fn value(&self, field: PacketExportField) -> ColumnValue {
+        match field {
+            PacketExportField::Timestamp => {
+                ColumnValue::Integer(Some(self.timestamp.timestamp_millis()))
+            }
+            PacketExportField::Direction => {
+                ColumnValue::Text(Some(format!("{:?}", self.direction)))
+            }
+            PacketExportField::PacketType => {
+                ColumnValue::Text(Some(format!("{:?}", self.packet_type)))
+            }
+            PacketExportField::Size => {
+                ColumnValue::Integer(Some(i64::try_from(self.size).unwrap_or(i64::MAX)))
+            }
+            PacketExportField::DataRate => ColumnValue::Integer(self.data_rate.map(i64::from)),
+            PacketExportField::Rssi => ColumnValue::Integer(self.rssi.map(i64::from)),
+            PacketExportField::Snr => ColumnValue::Real(self.snr),
+            PacketExportField::Hops => ColumnValue::Integer(self.hops.map(i64::from)),
+            PacketExportField::LatencyMs => ColumnValue::Integer(self.latency_ms),
+            PacketExportField::Source => ColumnValue::Text(self.source.clone()),
+            PacketExportField::Gateway => ColumnValue::Text(self.gateway.clone()),
+        }
+    }
+}
+
+/// Buffer of the packet records awaiting the next flush.
+#[derive(Debug)]
+pub struct PacketExport {
+    /// Key the IDs are pseudonymized with.
+    pseudonym_key: Vec<u8>,
+    /// Records since the last flush, oldest first.
+    records: Mutex<Vec<PacketRecord>>,
+    /// Records dropped since the last flush as the buffer was full.
+    dropped: AtomicU64,
+}
+
+impl PacketExport {
+    /// Creates a new empty [`PacketExport`], pseudonymizing with a random key if none is
+    /// configured.
+    pub fn new(config: &PacketExportConfig) -> Self {
+        Self {
+            pseudonym_key: config.pseudonym_key.as_ref().map_or_else(
+                || rand::random::<[u8; 32]>().to_vec(),
+                |key| key.as_bytes().to_vec(),
+            ),
+            records: Mutex::new(Vec::new()),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Records a received packet.
+    pub fn record_received(
+        &self,
+        packet: &dyn LoRaWanPacket,
+        size: usize,
+        gateway_id: &GatewayId,
+        uplink_info: Option<&UplinkInfo>,
+        hops: Option<u8>,
+        now: DateTime<Utc>,
+    ) {
+        let mut record = self.record(PacketDirection::Uplink, packet, size, now);
+        record.data_rate = uplink_info.map(|uplink_info| uplink_info.data_rate as u8);
+        record.rssi = uplink_info.map(|uplink_info| uplink_info.rssi);
+        record.snr = uplink_info.map(|uplink_info| uplink_info.snr);
+        record.hops = hops;
+        record.gateway = Some(self.pseudonym(gateway_id.to_string().as_bytes()));
+        self.push(record);
+    }
+
+    /// Records a sent packet, phy payloads which are no protocol packets are not recorded.
+    pub fn record_sent(&self, phy_payload: &[u8], data_rate: DataRate, now: DateTime<Utc>) {
+        let Ok(packet) = parse_phy_payload(phy_payload) else {
+            return;
+        };
+        let mut record = self.record(PacketDirection::Downlink, &*packet, phy_payload.len(), now);
+        record.data_rate = Some(data_rate as u8);
+        self.push(record);
+    }
+
+    /// Returns the record of the packet with the fields known for both directions.
+    fn record(
+        &self,
+        direction: PacketDirection,
+        packet: &dyn LoRaWanPacket,
+        size: usize,
+        now: DateTime<Utc>,
+    ) -> PacketRecord {
+        PacketRecord {
+            timestamp: now,
+            direction,
+            packet_type: packet.packet_type(),
+            size,
+            data_rate: None,
+            rssi: None,
+            snr: None,
+            hops: None,
+            latency_ms: packet
+                .as_bundle_packet()
+                .map(|bundle_packet| (now - bundle_packet.timestamp()).num_milliseconds()),
+            source: packet_sender(packet)
+                .map(|end_device_id| self.pseudonym(&end_device_id.0.to_be_bytes())),
+            gateway: None,
+        }
+    }
+
+    /// Returns the hex encoded pseudonym of the ID. SHA3-256 is not prone to length extension, so
+    /// prefixing the key is enough to key the hash.
+    fn pseudonym(&self, id: &[u8]) -> String {
+        let digest = Sha3_256::new()
+            .chain_update(&self.pseudonym_key)
+            .chain_update(id)
+            .finalize();
+        hex::encode(&digest[..PSEUDONYM_LENGTH])
+    }
+
+    /// Buffers the record, dropping it if the buffer is full.
+    fn push(&self, record: PacketRecord) {
+        let mut records = self.lock();
+        if records.len() < MAX_BUFFERED_RECORDS {
+            records.push(record);
+        } else {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Takes the buffered records and the amount of records dropped since the last call.
+    fn take(&self) -> (Vec<PacketRecord>, u64) {
+        let records = std::mem::take(&mut *self.lock());
+        (records, self.dropped.swap(0, Ordering::Relaxed))
+    }
+
+    /// Locks the buffered records, a poisoned lock is still used as the records stay valid.
+    fn lock(&self) -> MutexGuard<'_, Vec<PacketRecord>> {
+        self.records.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Encodes the records as CSV rows of the fields.
+fn csv_rows(fields: &[PacketExportField], records: &[PacketRecord]) -> String {
+    records
+        .iter()
+        .map(|record| {
+            let cells: Vec<String> = fields
+                .iter()
+                .map(|field| record.value(*field).to_csv())
+                .collect();
+            format!("{}\n", cells.join(","))
+        })
+        .collect()
+}
+
+/// Returns the CSV header row of the fields.
+fn csv_header(fields: &[PacketExportField]) -> String {
+    let names: Vec<&str> = fields.iter().map(|field| column(*field).name).collect();
+    format!("{}\n", names.join(","))
+}
+
+/// Returns the Parquet message type of the fields.
+#[cfg(feature = "parquet-export")]
+fn parquet_message_type(fields: &[PacketExportField]) -> String {
+    let columns: String = fields
+        .iter()
+        .map(|field| {
+            let column = column(*field);
+            let repetition = if column.nullable {
+                "optional"
+            } else {
+                "required"
+            };
+            let (physical_type, annotation) = match column.column_type {
+                ColumnType::Timestamp => ("int64", " (TIMESTAMP(MILLIS,true))"),
+                ColumnType::Integer => ("int64", ""),
+                ColumnType::Real => ("float", ""),
+                ColumnType::Text => ("binary", " (UTF8)"),
+            };
+            format!(
+                "  {repetition} {physical_type} {}{annotation};\n",
+                column.name
+            )
+        })
+        .collect();
+    format!("message packet_record {{\n{columns}}}")
+}
+
+/// Encodes the records as Parquet file with a single row group.
+///
+/// # Errors
+///
+/// Returns an error if the records cannot be encoded.
+#[cfg(feature = "parquet-export")]
+fn parquet_file(
+    fields: &[PacketExportField],
+    records: &[PacketRecord],
+) -> Result<Vec<u8>, parquet::errors::ParquetError> {
+    use parquet::data_type::{ByteArray, ByteArrayType, FloatType, Int64Type};
+    use parquet::file::properties::WriterProperties;
+    use parquet::file::writer::SerializedFileWriter;
+    use parquet::schema::parser::parse_message_type;
+
+    let schema = Arc::new(parse_message_type(&parquet_message_type(fields))?);
+    let mut buffer = Vec::new();
+    let mut writer = SerializedFileWriter::new(
+        &mut buffer,
+        schema,
+        Arc::new(WriterProperties::builder().build()),
+    )?;
+    let mut row_group = writer.next_row_group()?;
+    for field in fields {
+        let Some(mut column_writer) = row_group.next_column()? else {
+            break;
+        };
+        let values: Vec<ColumnValue> = records.iter().map(|record| record.value(*field)).collect();
+        let definition_levels: Vec<i16> = values
+            .iter()
+            .map(|value| i16::from(!value.is_null()))
+            .collect();
+        let definition_levels = column(*field)
+            .nullable
+            .then_some(definition_levels.as_slice());
+        match column(*field).column_type {
+            ColumnType::Timestamp | ColumnType::Integer => {
+                let present: Vec<i64> = values
+                    .iter()
+                    .filter_map(|value| match value {
+                        ColumnValue::Integer(value) => *value,
+                        _ => None,
+                    })
+                    .collect();
+                column_writer.typed::<Int64Type>().write_batch(
+                    &present,
+                    definition_levels,
+                    None,
+                )?;
+            }
+            ColumnType::Real => {
+                let present: Vec<f32> = values
+                    .iter()
+                    .filter_map(|value| match value {
+                        ColumnValue::Real(value) => *value,
+                        _ => None,
+                    })
+                    .collect();
+                column_writer.typed::<FloatType>().write_batch(
+                    &present,
+                    definition_levels,
+                    None,
+                )?;
+            }
+            ColumnType::Text => {
+                let present: Vec<ByteArray> = values
+                    .iter()
+                    .filter_map(|value| match value {
+                        ColumnValue::Text(value) => value.as_deref().map(ByteArray::from),
+                        _ => None,
+                    })
+                    .collect();
+                column_writer.typed::<ByteArrayType>().write_batch(
+                    &present,
+                    definition_levels,
+                    None,
+                )?;
+            }
+        }
+        column_writer.close()?;
+    }
+    row_group.close()?;
+    writer.close()?;
+    Ok(buffer)
+}
+
+/// Writer of the exported files.
+#[derive(Debug)]
+struct ExportWriter {
+    /// Directory the files are written to.
+    directory: PathBuf,
+    /// Format of the files.
+    format: PacketExportFormat,
+    /// Exported fields in column order.
+    fields: Vec<PacketExportField>,
+    /// Time after which a new CSV file is started.
+    rotation: Duration,
+    /// Time files are kept, forever if not set.
+    retention: Option<Duration>,
+    /// Current CSV file and the time it was started.
+    current: Option<(PathBuf, DateTime<Utc>)>,
+}
+
+impl ExportWriter {
+    /// Creates a new [`ExportWriter`] of the configuration.
+    fn new(config: &PacketExportConfig) -> Self {
+        Self {
+            directory: PathBuf::from(&config.directory),
+            format: config.format,
+            fields: if config.fields.is_empty() {
+                PacketExportField::ALL.to_vec()
+            } else {
+                config.fields.clone()
+            },
+            rotation: Duration::seconds(i64::try_from(config.rotation_seconds).unwrap_or(i64::MAX)),
+            retention: config
+                .retention_hours
+                .map(|hours| Duration::hours(i64::try_from(hours).unwrap_or(i64::MAX))),
+            current: None,
+        }
+    }
+
+    /// Returns the path of a new file started at the time.
+    fn file_path(&self, now: DateTime<Utc>) -> PathBuf {
+        let extension = match self.format {
+            PacketExportFormat::Csv => "csv",
+            PacketExportFormat::Parquet => "parquet",
+        };
+        self.directory.join(format!(
+            "{FILE_PREFIX}{}.{extension}",
+            now.format("%Y%m%dT%H%M%S%.3fZ")
+        ))
+    }
+
+    /// Creates the directory and writes the schema file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the directory or the file cannot be written.
+    async fn write_schema(&self) -> Result<(), PacketExportError> {
+        tokio::fs::create_dir_all(&self.directory).await?;
+        let (format, extension) = match self.format {
+            PacketExportFormat::Csv => ("csv", "csv"),
+            PacketExportFormat::Parquet => ("parquet", "parquet"),
+        };
+        let schema = SchemaDescription {
+            format,
+            files: format!("{FILE_PREFIX}<UTC start time>.{extension}"),
+            pseudonyms: "First 8 bytes of the SHA3-256 of the key followed by the big endian end \
+                         device ID or the gateway ID, hex encoded. Only comparable within one key, \
+                         the key changes with every start unless configured.",
+            columns: self.fields.iter().map(|field| column(*field)).collect(),
+        };
+        tokio::fs::write(
+            self.directory.join(SCHEMA_FILE),
+            serde_json::to_vec_pretty(&schema)?,
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Writes the records, appending to the current CSV file unless it is due for rotation.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the records cannot be encoded or written.
+    async fn write(
+        &mut self,
+        records: &[PacketRecord],
+        now: DateTime<Utc>,
+    ) -> Result<(), PacketExportError> {
+        if records.is_empty() {
+            return Ok(());
+        }
+        match self.format {
+            PacketExportFormat::Csv => {
+                let path = match &self.current {
+                    Some((path, started)) if now - *started < self.rotation => path.clone(),
+                    _ => {
+                        let path = self.file_path(now);
+                        tokio::fs::write(&path, csv_header(&self.fields)).await?;
+                        self.current = Some((path.clone(), now));
+                        path
+                    }
+                };
+                let mut file = tokio::fs::OpenOptions::new()
+                    .append(true)
+                    .open(path)
+                    .await?;
+                file.write_all(csv_rows(&self.fields, records).as_bytes())
+                    .await?;
+                file.flush().await?;
+            }
+            #[cfg(feature = "parquet-export")]
+            PacketExportFormat::Parquet => {
+                tokio::fs::write(self.file_path(now), parquet_file(&self.fields, records)?).await?;
+            }
+            // Not spawned without the feature, see `app_start`.
+            #[cfg(not(feature = "parquet-export"))]
+            PacketExportFormat::Parquet => {}
+        }
+        Ok(())
+    }
+
+    /// Removes the exported files last modified before the retention, except the current file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the directory cannot be read or a file cannot be removed.
+    async fn remove_expired(&self, now: DateTime<Utc>) -> Result<(), PacketExportError> {
+        let Some(retention) = self.retention else {
+            return Ok(());
+        };
+        let mut entries = tokio::fs::read_dir(&self.directory).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if !entry
+                .file_name()
+                .to_str()
+                .is_some_and(|file_name| file_name.starts_with(FILE_PREFIX))
+                || self
+                    .current
+                    .as_ref()
+                    .is_some_and(|(current, _)| *current == path)
+            {
+                continue;
+            }
+            let modified = DateTime::<Utc>::from(entry.metadata().await?.modified()?);
+            if now - modified > retention {
+                trace!("Removing expired export {}", path.display());
+                tokio::fs::remove_file(&path).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Writes the buffered records and removes the expired files.
+async fn flush(writer: &mut ExportWriter, packet_export: &PacketExport) {
+    let (records, dropped) = packet_export.take();
+    if dropped > 0 {
+        warn!("Dropped {dropped} packet records as the buffer was full");
+    }
+    let now = Utc::now();
+    if let Err(err) = writer.write(&records, now).await {
+        error!(
+            "Failed to write {} packet records to {}: {err}",
+            records.len(),
+            writer.directory.display()
+        );
+    }
+    if let Err(err) = writer.remove_expired(now).await {
+        error!("Failed to remove expired exports: {err}");
+    }
+}
+
+/// Task periodically writing the buffered packet records, flushes a last time on shutdown.
+#[instrument(skip_all, fields(directory = %config.directory))]
+pub async fn packet_export_task(
+    config: PacketExportConfig,
+    state: Arc<AppState>,
+    mut shutdown_agent: ShutdownAgent,
+) {
+    trace!("Starting up");
+    let Some(packet_export) = &state.packet_export else {
+        return;
+    };
+    let mut writer = ExportWriter::new(&config);
+    if let Err(err) = writer.write_schema().await {
+        error!("Failed to write the schema: {err}");
+        return;
+    }
+    let mut flush_interval = tokio::time::interval(std::time::Duration::from_secs(
+        config.flush_interval_seconds.max(1),
+    ));
+    // The first tick completes immediately.
+    flush_interval.tick().await;
+
+    loop {
+        tokio::select! {
+            _ = flush_interval.tick() => flush(&mut writer, packet_export).await,
+            _ = shutdown_agent.await_shutdown() => {
+                flush(&mut writer, packet_export).await;
+                trace!("Shutting down");
+                return
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use crate::configuration::{PacketExportConfig, PacketExportField, PacketExportFormat};
+    use crate::end_device_id::EndDeviceId;
+    use crate::packet_export::{csv_header, csv_rows, PacketExport};
+    use chirpstack_gwb_integration::downlinks::predefined_parameters::DataRate;
+    use chirpstack_gwb_integration::gateway_id::GatewayId;
+    use chrono::{Duration, Utc};
+    use lorawan_dtn_protocol::{CompleteBundle, LoRaWanPacket, PacketType};
+
+    fn config(pseudonym_key: Option<&str>) -> PacketExportConfig {
+        PacketExportConfig {
+            directory: "export".to_owned(),
+            format: PacketExportFormat::Csv,
+            fields: Vec::new(),
+            flush_interval_seconds: 60,
+            rotation_seconds: 3600,
+            retention_hours: None,
+            pseudonym_key: pseudonym_key.map(str::to_owned),
+        }
+    }
+
+    fn bundle(timestamp: chrono::DateTime<Utc>) -> CompleteBundle {
+        CompleteBundle::new(
+            EndDeviceId(0x5678),
+            EndDeviceId(0x1234),
+            timestamp,
+            &mut vec![1, 2, 3],
+            DataRate::Eu863_870Dr3,
+            false,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn records_are_pseudonymized_and_buffered() {
+        let packet_export = PacketExport::new(&config(Some("secret")));
+        let now = Utc::now();
+        let bundle = bundle(now - Duration::seconds(2));
+        packet_export.record_received(
+            &bundle,
+            20,
+            &GatewayId::from([1, 2, 3, 4, 5, 6, 7, 8]),
+            None,
+            Some(2),
+            now,
+        );
+        packet_export.record_sent(
+            &bundle.convert_to_lorawan_phy_payload(),
+            DataRate::Eu863_870Dr5,
+            now,
+        );
+
+        let (records, dropped) = packet_export.take();
+        assert_eq!(dropped, 0);
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].packet_type, PacketType::CompleteBundle);
+        assert_eq!(records[0].hops, Some(2));
+        assert_eq!(records[0].latency_ms, Some(2000));
+        assert_eq!(records[1].data_rate, Some(5));
+        // The source is pseudonymized the same in both directions, differently with another key.
+        assert_eq!(records[0].source, records[1].source);
+        let source = records[0].source.clone().unwrap();
+        assert_eq!(source.len(), 16);
+        assert_ne!(
+            PacketExport::new(&config(Some("other"))).pseudonym(&0x1234_u32.to_be_bytes()),
+            source
+        );
+        assert!(packet_export.take().0.is_empty());
+    }
+
+    #[test]
+    fn csv_rows_contain_the_configured_fields() {
+        let packet_export = PacketExport::new(&config(None));
+        let now = Utc::now();
+        packet_export.record_sent(
+            &bundle(now).convert_to_lorawan_phy_payload(),
+            DataRate::Eu863_870Dr3,
+            now,
+        );
+        let (records, _) = packet_export.take();
+        let fields = [
+            PacketExportField::PacketType,
+            PacketExportField::DataRate,
+            PacketExportField::Rssi,
+        ];
+
+        assert_eq!(csv_header(&fields), "packet_type,data_rate,rssi\n");
+        assert_eq!(csv_rows(&fields, &records), "CompleteBundle,3,\n");
+    }
+}
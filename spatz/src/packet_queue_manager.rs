@@ -1,12 +1,40 @@
 //! Send manager responsible for sending packets.
 
+use crate::configuration::PreemptionPolicy;
+use crate::error::QueueOperationError;
 use crate::graceful_shutdown::ShutdownAgent;
-use crate::lorawan_protocol::LoRaWanPacket;
-use crate::send_buffers::BundleSendBuffer;
+use crate::memory_budget::{bundle_size, BufferCategory, MemoryBudget, PACKET_SIZE};
+use crate::send_buffers::{BundleSendBuffer, SendBuffer};
 use chirpstack_gwb_integration::downlinks::predefined_parameters::DataRate;
+use lorawan_dtn_protocol::LoRaWanPacket;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
 use std::sync::Arc;
 use tokio::sync::{mpsc, Mutex};
-use tracing::{instrument, trace, warn};
+use tracing::{info, instrument, trace, warn};
+
+/// Max amount of pinned bundles, limits how long other bundles can be held back by pinning.
+pub const MAX_PINNED_BUNDLES: usize = 2;
+
+/// Amount of queued entries and the maximum of every queue.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, JsonSchema)]
+pub struct QueueStats {
+    /// Queued relay packets.
+    pub relay_packets: usize,
+    /// Max amount of queued relay packets.
+    pub max_relay_packets: usize,
+    /// Queued bundles.
+    pub bundles: usize,
+    /// Max amount of queued bundles.
+    pub max_bundles: usize,
+    /// Queued announcements.
+    pub announcements: usize,
+    /// Max amount of queued announcements.
+    pub max_announcements: usize,
+    /// Bundle transfers in progress preempted by bundles queued ahead of them.
+    pub preemptions: u64,
+}
 
 /// Queues of LoRaWAN frames and [`BundleSendBuffer`].
 #[derive(Debug)]
@@ -19,23 +47,247 @@ pub struct QueueManager {
     pub(crate) bundle_send_buffer_queue: Arc<Mutex<Vec<BundleSendBuffer>>>,
     /// Max amount of queued [`BundleSendBuffer`].
     pub(crate) max_bundle_buffers: usize,
+    /// Local and reachability announcements to be sent.
+    pub(crate) announcement_queue: Arc<Mutex<Vec<Box<dyn LoRaWanPacket>>>>,
+    /// Max amount of queued announcements.
+    pub(crate) max_announcements: usize,
+    /// Budget of all buffers, entries exceeding it are rejected.
+    memory_budget: Arc<MemoryBudget>,
+    /// Handling of a bundle transfer in progress when another bundle is queued ahead of it.
+    preemption: PreemptionPolicy,
+    /// Whether a bundle transfer in progress is currently preempted.
+    preempted: AtomicBool,
+    /// Packets of the bundles ahead of the preempted transfer sent since its last packet.
+    preempting_packets: AtomicU32,
+    /// Bundle transfers in progress preempted by bundles queued ahead of them.
+    preemptions: AtomicU64,
 }
 
 impl QueueManager {
     /// Create a new [`QueueManager`].
-    /// Takes the maximum amount of queued entries per queue.
+    /// Takes the maximum amount of queued entries per queue, the budget of all buffers and the
+    /// handling of preempted bundle transfers.
     pub fn new(
         relay_packet_queue: Arc<Mutex<Vec<(Box<dyn LoRaWanPacket>, DataRate)>>>,
         max_relay_packets: usize,
         bundle_send_buffer_queue: Arc<Mutex<Vec<BundleSendBuffer>>>,
         max_bundle_buffers: usize,
+        max_announcements: usize,
+        memory_budget: Arc<MemoryBudget>,
+        preemption: PreemptionPolicy,
     ) -> Self {
         Self {
             relay_packet_queue,
             max_relay_packets,
             bundle_send_buffer_queue,
             max_bundle_buffers,
+            announcement_queue: Arc::new(Mutex::new(Vec::new())),
+            max_announcements,
+            memory_budget,
+            preemption,
+            preempted: AtomicBool::new(false),
+            preempting_packets: AtomicU32::new(0),
+            preemptions: AtomicU64::new(0),
+        }
+    }
+
+    /// Enqueues local or reachability announcements, announcements exceeding the maximum amount
+    /// of queued announcements or the memory budget are dropped.
+    pub async fn enqueue_announcements(&self, announcements: Vec<Box<dyn LoRaWanPacket>>) {
+        let mut announcement_lock = self.announcement_queue.lock().await;
+        for announcement in announcements {
+            if announcement_lock.len() >= self.max_announcements {
+                warn!("Max amount of queued announcements reached, dropping announcement");
+                return;
+            }
+            if !self
+                .memory_budget
+                .reserve(BufferCategory::Queues, PACKET_SIZE)
+            {
+                warn!("Memory budget exhausted, dropping announcement");
+                return;
+            }
+            announcement_lock.push(announcement);
+        }
+    }
+
+    /// Enqueues a packet originating from this node to be sent by the routing algorithm.
+    ///
+    /// Returns `false` if the maximum amount of queued relay packets is reached or the memory
+    /// budget is exhausted.
+    pub async fn enqueue_relay_packet(
+        &self,
+        packet: Box<dyn LoRaWanPacket>,
+        data_rate: DataRate,
+    ) -> bool {
+        let mut relay_packet_lock = self.relay_packet_queue.lock().await;
+        if relay_packet_lock.len() >= self.max_relay_packets {
+            warn!("Max amount of queued relay packets reached, rejecting packet");
+            return false;
+        }
+        if !self
+            .memory_budget
+            .reserve(BufferCategory::Queues, PACKET_SIZE)
+        {
+            warn!("Memory budget exhausted, rejecting packet");
+            return false;
+        }
+        relay_packet_lock.push((packet, data_rate));
+        true
+    }
+
+    /// Returns the amount of queued entries and the maximum of every queue.
+    pub async fn stats(&self) -> QueueStats {
+        QueueStats {
+            relay_packets: self.relay_packet_queue.lock().await.len(),
+            max_relay_packets: self.max_relay_packets,
+            bundles: self.bundle_send_buffer_queue.lock().await.len(),
+            max_bundles: self.max_bundle_buffers,
+            announcements: self.announcement_queue.lock().await.len(),
+            max_announcements: self.max_announcements,
+            preemptions: self.preemptions.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Returns whether at least `relay_packets_threshold` relay packets or `bundles_threshold`
+    /// bundles are queued, i.e. the queues compete with announcements for airtime.
+    pub async fn is_congested(
+        &self,
+        relay_packets_threshold: usize,
+        bundles_threshold: usize,
+    ) -> bool {
+        self.relay_packet_queue.lock().await.len() >= relay_packets_threshold
+            || self.bundle_send_buffer_queue.lock().await.len() >= bundles_threshold
+    }
+
+    /// Returns the index of the send buffer producing the next packet, `None` if all send
+    /// buffers are frozen.
+    ///
+    /// The first send buffer which is not frozen is selected. A transfer in progress queued
+    /// behind it, e.g. as a bundle of a higher priority arrived or a bundle was pinned, is
+    /// preempted and resumes at its progress once no bundle is queued ahead of it anymore. With
+    /// [`PreemptionPolicy::Interleave`] the transfer is selected after every configured amount of
+    /// packets of the bundles ahead instead.
+    pub fn select_send_buffer(&self, send_buffers: &[impl SendBuffer]) -> Option<usize> {
+        let mut candidates = send_buffers
+            .iter()
+            .enumerate()
+            .filter(|(_, send_buffer)| !send_buffer.is_frozen());
+        let (head, head_buffer) = candidates.next()?;
+        let Some((transfer, transfer_buffer)) =
+            candidates.find(|(_, send_buffer)| send_buffer.is_started())
+        else {
+            self.preempted.store(false, Ordering::Relaxed);
+            self.preempting_packets.store(0, Ordering::Relaxed);
+            return Some(head);
+        };
+        if !self.preempted.swap(true, Ordering::Relaxed) {
+            self.preemptions.fetch_add(1, Ordering::Relaxed);
+            info!(
+                preempted_priority = ?transfer_buffer.routing_hints().map(|hints| hints.priority),
+                priority = ?head_buffer.routing_hints().map(|hints| hints.priority),
+                "Preempting bundle transfer in progress"
+            );
+        }
+        match self.preemption {
+            PreemptionPolicy::Interleave { packets }
+                if self.preempting_packets.load(Ordering::Relaxed) >= packets =>
+            {
+                trace!("Interleaving packet of the preempted bundle transfer");
+                self.preempting_packets.store(0, Ordering::Relaxed);
+                Some(transfer)
+            }
+            PreemptionPolicy::Preempt | PreemptionPolicy::Interleave { .. } => {
+                self.preempting_packets.fetch_add(1, Ordering::Relaxed);
+                Some(head)
+            }
+        }
+    }
+
+    /// Moves the queued bundle at `index` to the front of the queue.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if:
+    /// - there is no bundle at `index`.
+    /// - the bundle is frozen.
+    /// - [`MAX_PINNED_BUNDLES`] other bundles are already pinned.
+    pub async fn pin_bundle(&self, index: usize) -> Result<(), QueueOperationError> {
+        let mut bundle_buffers_lock = self.bundle_send_buffer_queue.lock().await;
+        let bundle = bundle_buffers_lock
+            .get(index)
+            .ok_or(QueueOperationError::NoSuchItem { index })?;
+        if bundle.is_frozen() {
+            return Err(QueueOperationError::Frozen { index });
+        }
+        if !bundle.is_pinned()
+            && bundle_buffers_lock
+                .iter()
+                .filter(|bundle| bundle.is_pinned())
+                .count()
+                >= MAX_PINNED_BUNDLES
+        {
+            return Err(QueueOperationError::TooManyPinned {
+                max: MAX_PINNED_BUNDLES,
+            });
+        }
+        let mut bundle = bundle_buffers_lock.remove(index);
+        audit_log("pin", index, &bundle);
+        bundle.set_pinned(true);
+        bundle_buffers_lock.insert(0, bundle);
+        Ok(())
+    }
+
+    /// Moves the queued bundle at `index` to the back of the queue and removes its pin.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is no bundle at `index`.
+    pub async fn deprioritize_bundle(&self, index: usize) -> Result<(), QueueOperationError> {
+        let mut bundle_buffers_lock = self.bundle_send_buffer_queue.lock().await;
+        if index >= bundle_buffers_lock.len() {
+            return Err(QueueOperationError::NoSuchItem { index });
+        }
+        let mut bundle = bundle_buffers_lock.remove(index);
+        audit_log("deprioritize", index, &bundle);
+        bundle.set_pinned(false);
+        bundle_buffers_lock.push(bundle);
+        Ok(())
+    }
+
+    /// Freezes or unfreezes the queued bundle at `index`. Frozen bundles keep their position
+    /// but are skipped when sending. Freezing removes the pin of the bundle.
+    ///
+    /// At most half of the bundle queue can be frozen to leave room for new bundles.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if:
+    /// - there is no bundle at `index`.
+    /// - freezing would exceed the maximum amount of frozen bundles.
+    pub async fn set_bundle_frozen(
+        &self,
+        index: usize,
+        frozen: bool,
+    ) -> Result<(), QueueOperationError> {
+        let mut bundle_buffers_lock = self.bundle_send_buffer_queue.lock().await;
+        let max_frozen = self.max_bundle_buffers / 2;
+        let frozen_amount = bundle_buffers_lock
+            .iter()
+            .filter(|bundle| bundle.is_frozen())
+            .count();
+        let bundle = bundle_buffers_lock
+            .get_mut(index)
+            .ok_or(QueueOperationError::NoSuchItem { index })?;
+        if frozen && !bundle.is_frozen() && frozen_amount >= max_frozen {
+            return Err(QueueOperationError::TooManyFrozen { max: max_frozen });
+        }
+        audit_log(if frozen { "freeze" } else { "unfreeze" }, index, bundle);
+        bundle.set_frozen(frozen);
+        if frozen {
+            bundle.set_pinned(false);
         }
+        Ok(())
     }
 
     /// Task to collect incoming packets, bundles into the [`QueueManager`]
@@ -57,6 +309,10 @@ impl QueueManager {
                         warn!("Max amount of queued relay packets reached, dropping packet");
                         continue
                     }
+                    if !self.memory_budget.reserve(BufferCategory::Queues, PACKET_SIZE) {
+                        warn!("Memory budget exhausted, dropping packet");
+                        continue
+                    }
                     relay_packet_lock.push(relay_packet);
                 },
                 Some(bundle_send_buffer) = bundle_send_buffer_rx.recv() =>  {
@@ -66,7 +322,12 @@ impl QueueManager {
                         warn!("Max amount of queued bundle buffers reached, dropping buffer");
                         continue
                     }
-                    bundle_buffers_lock.push(bundle_send_buffer);
+                    let size = bundle_size(&bundle_send_buffer);
+                    if !self.memory_budget.reserve(BufferCategory::Queues, size) {
+                        warn!("Memory budget exhausted, dropping buffer");
+                        continue
+                    }
+                    insert_by_priority(&mut bundle_buffers_lock, bundle_send_buffer);
                 },
                 _ = shutdown_agent.await_shutdown() => {
                     trace!("Shutting down");
@@ -76,3 +337,236 @@ impl QueueManager {
         }
     }
 }
+
+/// Inserts the bundle behind the pinned bundles and the bundles of the same or a higher
+/// [`BundlePriority`](crate::routing_hints::BundlePriority).
+fn insert_by_priority(queue: &mut Vec<BundleSendBuffer>, bundle: BundleSendBuffer) {
+    let priority = bundle.priority();
+    let index = queue
+        .iter()
+        .position(|queued| !queued.is_pinned() && queued.priority() < priority)
+        .unwrap_or(queue.len());
+    queue.insert(index, bundle);
+}
+
+/// Logs an operator initiated change of the bundle queue.
+fn audit_log(operation: &str, index: usize, bundle: &BundleSendBuffer) {
+    info!(
+        operation,
+        index,
+        source = ?bundle.source(),
+        destination = ?bundle.destination(),
+        timestamp = %bundle.timestamp(),
+        "Bundle queue changed by operator"
+    );
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use crate::configuration::PreemptionPolicy;
+    use crate::end_device_id::EndDeviceId;
+    use crate::error::QueueOperationError;
+    use crate::memory_budget::MemoryBudget;
+    use crate::packet_queue_manager::{insert_by_priority, QueueManager, MAX_PINNED_BUNDLES};
+    use crate::routing_hints::{BundlePriority, RoutingHints};
+    use crate::send_buffers::{BundleSendBuffer, SendBuffer};
+    use chirpstack_gwb_integration::downlinks::predefined_parameters::DataRate;
+    use chrono::Utc;
+    use lorawan_dtn_protocol::HopAck;
+    use std::sync::Arc;
+    use tokio::sync::Mutex;
+
+    #[tokio::test]
+    async fn pin_deprioritize_and_freeze_bundles() {
+        let bundles = (0..4)
+            .map(|source| {
+                BundleSendBuffer::new(
+                    EndDeviceId(0x1234),
+                    EndDeviceId(source),
+                    Utc::now(),
+                    vec![0xFF; 10],
+                    false,
+                )
+                .unwrap()
+            })
+            .collect();
+        let queue_manager = QueueManager::new(
+            Arc::new(Mutex::new(Vec::new())),
+            4,
+            Arc::new(Mutex::new(bundles)),
+            4,
+            4,
+            Arc::new(MemoryBudget::new(None)),
+            PreemptionPolicy::Preempt,
+        );
+        let sources = |queue: &Vec<BundleSendBuffer>| {
+            queue
+                .iter()
+                .map(|bundle| bundle.source().0)
+                .collect::<Vec<_>>()
+        };
+
+        queue_manager.pin_bundle(2).await.unwrap();
+        queue_manager.pin_bundle(3).await.unwrap();
+        assert_eq!(
+            sources(&*queue_manager.bundle_send_buffer_queue.lock().await),
+            vec![3, 2, 0, 1]
+        );
+        assert_eq!(
+            queue_manager.pin_bundle(2).await,
+            Err(QueueOperationError::TooManyPinned {
+                max: MAX_PINNED_BUNDLES
+            })
+        );
+
+        queue_manager.deprioritize_bundle(0).await.unwrap();
+        assert_eq!(
+            sources(&*queue_manager.bundle_send_buffer_queue.lock().await),
+            vec![2, 0, 1, 3]
+        );
+
+        queue_manager.set_bundle_frozen(1, true).await.unwrap();
+        queue_manager.set_bundle_frozen(2, true).await.unwrap();
+        assert_eq!(
+            queue_manager.set_bundle_frozen(3, true).await,
+            Err(QueueOperationError::TooManyFrozen { max: 2 })
+        );
+        assert_eq!(
+            queue_manager.pin_bundle(1).await,
+            Err(QueueOperationError::Frozen { index: 1 })
+        );
+        assert!(queue_manager.bundle_send_buffer_queue.lock().await[1].is_frozen());
+        assert_eq!(
+            queue_manager.deprioritize_bundle(4).await,
+            Err(QueueOperationError::NoSuchItem { index: 4 })
+        );
+    }
+
+    #[test]
+    fn bundles_are_queued_by_priority() {
+        let bundle = |source, priority| {
+            let mut bundle = BundleSendBuffer::new(
+                EndDeviceId(0x1234),
+                EndDeviceId(source),
+                Utc::now(),
+                vec![0xFF; 10],
+                false,
+            )
+            .unwrap();
+            bundle.set_routing_hints(RoutingHints {
+                priority,
+                ..RoutingHints::default()
+            });
+            bundle
+        };
+        let mut pinned = bundle(0, BundlePriority::Low);
+        pinned.set_pinned(true);
+        let mut queue = vec![pinned];
+        insert_by_priority(&mut queue, bundle(1, BundlePriority::Normal));
+        insert_by_priority(&mut queue, bundle(2, BundlePriority::Low));
+        insert_by_priority(&mut queue, bundle(3, BundlePriority::High));
+        insert_by_priority(&mut queue, bundle(4, BundlePriority::Normal));
+        insert_by_priority(&mut queue, bundle(5, BundlePriority::High));
+        assert_eq!(
+            queue
+                .iter()
+                .map(|bundle| bundle.source().0)
+                .collect::<Vec<_>>(),
+            vec![0, 3, 5, 1, 4, 2]
+        );
+    }
+
+    #[tokio::test]
+    async fn transfers_in_progress_are_preempted_and_resumed() {
+        let bundle = |source, priority| {
+            let mut bundle = BundleSendBuffer::new(
+                EndDeviceId(0x1234),
+                EndDeviceId(source),
+                Utc::now(),
+                vec![0xFF; 200],
+                false,
+            )
+            .unwrap();
+            bundle.set_routing_hints(RoutingHints {
+                priority,
+                ..RoutingHints::default()
+            });
+            bundle
+        };
+        let queue_manager = |preemption| {
+            QueueManager::new(
+                Arc::new(Mutex::new(Vec::new())),
+                4,
+                Arc::new(Mutex::new(Vec::new())),
+                4,
+                4,
+                Arc::new(MemoryBudget::new(None)),
+                preemption,
+            )
+        };
+        let mut bulk = bundle(1, BundlePriority::Low);
+        bulk.next_packet(DataRate::Eu863_870Dr0).unwrap();
+        let mut queue = vec![bulk];
+        insert_by_priority(&mut queue, bundle(2, BundlePriority::High));
+
+        let preempting = queue_manager(PreemptionPolicy::Preempt);
+        assert_eq!(preempting.select_send_buffer(&queue), Some(0));
+        queue[0].next_packet(DataRate::Eu863_870Dr0).unwrap();
+        assert_eq!(preempting.select_send_buffer(&queue), Some(0));
+        assert_eq!(preempting.stats().await.preemptions, 1);
+
+        let interleaving = queue_manager(PreemptionPolicy::Interleave { packets: 2 });
+        let selected: Vec<_> = (0..6)
+            .map(|_| interleaving.select_send_buffer(&queue).unwrap())
+            .collect();
+        assert_eq!(selected, vec![0, 0, 1, 0, 0, 1]);
+        assert_eq!(interleaving.stats().await.preemptions, 1);
+
+        // The preempted transfer resumes with its next fragment.
+        queue.remove(0);
+        assert_eq!(preempting.select_send_buffer(&queue), Some(0));
+        let packet = queue[0].next_packet(DataRate::Eu863_870Dr0).unwrap();
+        assert_eq!(packet.as_bundle_packet().unwrap().fragment_index(), 1);
+    }
+
+    #[tokio::test]
+    async fn congestion_follows_relay_and_bundle_queues() {
+        let bundles = (0..2)
+            .map(|source| {
+                BundleSendBuffer::new(
+                    EndDeviceId(0x1234),
+                    EndDeviceId(source),
+                    Utc::now(),
+                    vec![0xFF; 10],
+                    false,
+                )
+                .unwrap()
+            })
+            .collect();
+        let queue_manager = QueueManager::new(
+            Arc::new(Mutex::new(Vec::new())),
+            4,
+            Arc::new(Mutex::new(bundles)),
+            4,
+            4,
+            Arc::new(MemoryBudget::new(None)),
+            PreemptionPolicy::Preempt,
+        );
+        assert!(!queue_manager.is_congested(2, 3).await);
+        assert!(queue_manager.is_congested(2, 2).await);
+
+        for _ in 0..2 {
+            assert!(
+                queue_manager
+                    .enqueue_relay_packet(
+                        Box::new(HopAck::acknowledge(&[1, 2, 3], EndDeviceId(1))),
+                        DataRate::Eu863_870Dr0,
+                    )
+                    .await
+            );
+        }
+        assert!(queue_manager.is_congested(2, 3).await);
+        assert!(!queue_manager.is_congested(3, 3).await);
+    }
+}
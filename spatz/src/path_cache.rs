@@ -0,0 +1,237 @@
+//! Paths to multi-hop destinations learned from successful deliveries.
+//!
+//! [`StatusReport`](lorawan_dtn_protocol::StatusReport)s and
+//! [`EchoReply`](lorawan_dtn_protocol::EchoReply)s travel from a destination back to the
+//! source, so the gateway which received them last is on a working path to their sender. Every
+//! node passed by such a packet remembers the gateway as next hop of the sender. Packets addressed
+//! to a destination with a known path are sent once from that gateway instead of being flooded,
+//! the next relay forwards them as usual.
+//!
+//! A path expires after the TTL since the last status report or echo reply of its destination.
+//! Directed transmissions which cannot be handed to the gateway, e.g. as it disconnected or
+//! cannot transmit at the data rate, are flooded after all and count as failure. The path is
+//! invalidated after the configured consecutive failures.
+
+use crate::configuration::PathCacheConfig;
+use crate::end_device_id::EndDeviceId;
+use chirpstack_gwb_integration::gateway_id::GatewayId;
+use chrono::{DateTime, Utc};
+use lorawan_dtn_protocol::parse_phy_payload;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tracing::trace;
+
+/// Counters of the path cache.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Serialize, JsonSchema)]
+pub struct PathCacheStats {
+    /// Paths learned or refreshed from status reports and echo replies.
+    pub learned: u64,
+    /// Packets handed to the gateway of a learned path.
+    pub directed: u64,
+    /// Directed transmissions flooded after all as the gateway was unavailable.
+    pub failures: u64,
+    /// Paths invalidated after consecutive failures.
+    pub invalidated: u64,
+}
+
+/// Learned path to a destination.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, JsonSchema)]
+pub struct CachedPath {
+    /// Destination of the path.
+    pub destination: EndDeviceId,
+    /// Gateway which received the last status report or echo reply of the destination.
+    pub gateway_id: GatewayId,
+    /// Time the path was learned or last refreshed.
+    pub learned_at: DateTime<Utc>,
+    /// Consecutive failed directed transmissions.
+    pub failures: u32,
+}
+
+/// Learned paths and the counters of the path cache.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, JsonSchema)]
+pub struct PathCacheReport {
+    /// Counters of the path cache.
+    pub stats: PathCacheStats,
+    /// Unexpired learned paths.
+    pub paths: Vec<CachedPath>,
+}
+
+/// Keeps the learned paths to destinations and the counters of the directed transmissions.
+#[derive(Debug)]
+pub struct PathCache {
+    /// TTL and failure threshold.
+    config: PathCacheConfig,
+    /// Learned paths by destination.
+    paths: Mutex<HashMap<EndDeviceId, CachedPath>>,
+    /// Paths learned or refreshed.
+    learned: AtomicU64,
+    /// Packets handed to the gateway of a learned path.
+    directed: AtomicU64,
+    /// Failed directed transmissions.
+    failures: AtomicU64,
+    /// Paths invalidated after consecutive failures.
+    invalidated: AtomicU64,
+}
+
+impl PathCache {
+    /// Creates a new [`PathCache`] without learned paths.
+    pub fn new(config: PathCacheConfig) -> Self {
+        Self {
+            config,
+            paths: Mutex::new(HashMap::new()),
+            learned: AtomicU64::new(0),
+            directed: AtomicU64::new(0),
+            failures: AtomicU64::new(0),
+            invalidated: AtomicU64::new(0),
+        }
+    }
+
+    /// Learns the gateway which received a status report or echo reply of the destination as
+    /// its next hop, replacing an earlier path.
+    pub async fn learn(
+        &self,
+        destination: EndDeviceId,
+        gateway_id: &GatewayId,
+        now: DateTime<Utc>,
+    ) {
+        trace!("Learned path to {destination:?} via gateway {gateway_id}");
+        self.learned.fetch_add(1, Ordering::Relaxed);
+        self.paths.lock().await.insert(
+            destination,
+            CachedPath {
+                destination,
+                gateway_id: gateway_id.clone(),
+                learned_at: now,
+                failures: 0,
+            },
+        );
+    }
+
+    /// Returns the learned path to the destination of the phy payload, `None` if the payload has
+    /// no destination or no unexpired path to it is known.
+    pub async fn path(&self, phy_payload: &[u8], now: DateTime<Utc>) -> Option<CachedPath> {
+        let destination = parse_phy_payload(phy_payload).ok()?.packet_destination()?;
+        let mut paths = self.paths.lock().await;
+        self.remove_expired(&mut paths, now);
+        paths.get(&destination).cloned()
+    }
+
+    /// Counts a packet handed to the gateway of the path, resetting its failures.
+    pub async fn record_directed(&self, destination: EndDeviceId) {
+        self.directed.fetch_add(1, Ordering::Relaxed);
+        if let Some(path) = self.paths.lock().await.get_mut(&destination) {
+            path.failures = 0;
+        }
+    }
+
+    /// Counts a failed directed transmission, the path is invalidated after the configured
+    /// consecutive failures.
+    pub async fn record_failure(&self, destination: EndDeviceId) {
+        self.failures.fetch_add(1, Ordering::Relaxed);
+        let mut paths = self.paths.lock().await;
+        let Some(path) = paths.get_mut(&destination) else {
+            return;
+        };
+        path.failures = path.failures.saturating_add(1);
+        if path.failures >= self.config.max_failures {
+            trace!(
+                "Invalidating path to {destination:?} after {} failures",
+                path.failures
+            );
+            paths.remove(&destination);
+            self.invalidated.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Returns the unexpired learned paths, ordered by destination, and the counters.
+    pub async fn report(&self, now: DateTime<Utc>) -> PathCacheReport {
+        let mut paths = self.paths.lock().await;
+        self.remove_expired(&mut paths, now);
+        let mut paths: Vec<CachedPath> = paths.values().cloned().collect();
+        paths.sort_by_key(|path| path.destination.0);
+        PathCacheReport {
+            stats: PathCacheStats {
+                learned: self.learned.load(Ordering::Relaxed),
+                directed: self.directed.load(Ordering::Relaxed),
+                failures: self.failures.load(Ordering::Relaxed),
+                invalidated: self.invalidated.load(Ordering::Relaxed),
+            },
+            paths,
+        }
+    }
+
+    /// Removes the paths learned longer than the TTL ago.
+    fn remove_expired(&self, paths: &mut HashMap<EndDeviceId, CachedPath>, now: DateTime<Utc>) {
+        let ttl = chrono::Duration::from_std(Duration::from_secs(self.config.ttl_seconds))
+            .unwrap_or(chrono::Duration::MAX);
+        paths.retain(|_, path| now.signed_duration_since(path.learned_at) < ttl);
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use crate::configuration::PathCacheConfig;
+    use crate::end_device_id::EndDeviceId;
+    use crate::path_cache::PathCache;
+    use chirpstack_gwb_integration::downlinks::predefined_parameters::DataRate;
+    use chirpstack_gwb_integration::gateway_id::GatewayId;
+    use chrono::{Duration, Utc};
+    use lorawan_dtn_protocol::{CompleteBundle, LoRaWanPacket};
+
+    fn complete_bundle(destination: EndDeviceId) -> Vec<u8> {
+        CompleteBundle::new(
+            destination,
+            EndDeviceId(0x5678),
+            Utc::now(),
+            &mut vec![1, 2, 3],
+            DataRate::Eu863_870Dr3,
+            false,
+        )
+        .unwrap()
+        .convert_to_lorawan_phy_payload()
+    }
+
+    #[tokio::test]
+    async fn paths_expire_and_are_invalidated_after_failures() {
+        let path_cache = PathCache::new(PathCacheConfig {
+            ttl_seconds: 600,
+            max_failures: 2,
+        });
+        let now = Utc::now();
+        let payload = complete_bundle(EndDeviceId(0x1234));
+        let gateway_id: GatewayId = "A8-40-41-1D-25-24-41-50".parse().unwrap();
+        assert!(path_cache.path(&payload, now).await.is_none());
+
+        path_cache
+            .learn(EndDeviceId(0x1234), &gateway_id, now)
+            .await;
+        let path = path_cache.path(&payload, now).await.unwrap();
+        assert_eq!(path.gateway_id, "a840411d25244150");
+        assert!(path_cache
+            .path(&payload, now + Duration::seconds(600))
+            .await
+            .is_none());
+
+        path_cache
+            .learn(EndDeviceId(0x1234), &gateway_id, now)
+            .await;
+        path_cache.record_failure(EndDeviceId(0x1234)).await;
+        path_cache.record_directed(EndDeviceId(0x1234)).await;
+        path_cache.record_failure(EndDeviceId(0x1234)).await;
+        assert!(path_cache.path(&payload, now).await.is_some());
+        path_cache.record_failure(EndDeviceId(0x1234)).await;
+        assert!(path_cache.path(&payload, now).await.is_none());
+
+        let report = path_cache.report(now).await;
+        assert!(report.paths.is_empty());
+        assert_eq!(report.stats.learned, 2);
+        assert_eq!(report.stats.directed, 1);
+        assert_eq!(report.stats.failures, 3);
+        assert_eq!(report.stats.invalidated, 1);
+    }
+}
@@ -0,0 +1,347 @@
+//! Codec registry for payload profiles, letting applications with different payload encodings
+//! share one DTN.
+//!
+//! The payload of a bundle submitted with a profile is encoded by the codec of the profile and
+//! prefixed with the profile byte. Before the delivery to local services the profile byte is
+//! removed and the payload decoded again. Payloads without a registered profile byte are
+//! delivered unchanged.
+
+use crate::configuration::{PayloadCodecKind, PayloadProfileConfig};
+use crate::error::PayloadCodecError;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Encoding of the payloads of a profile.
+pub trait PayloadCodec: Debug + Send + Sync {
+    /// Encodes the payload submitted by an application for the transmission.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the payload does not match the encoding of the codec.
+    fn encode(&self, payload: &[u8]) -> Result<Vec<u8>, PayloadCodecError>;
+
+    /// Decodes a received payload for the delivery to an application.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the payload was not encoded by the codec.
+    fn decode(&self, payload: &[u8]) -> Result<Vec<u8>, PayloadCodecError>;
+}
+
+/// Sends payloads unchanged, the profile byte only tags their encoding.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct RawCodec;
+
+impl PayloadCodec for RawCodec {
+    fn encode(&self, payload: &[u8]) -> Result<Vec<u8>, PayloadCodecError> {
+        Ok(payload.to_vec())
+    }
+
+    fn decode(&self, payload: &[u8]) -> Result<Vec<u8>, PayloadCodecError> {
+        Ok(payload.to_vec())
+    }
+}
+
+/// Sends JSON payloads as CBOR, which omits the whitespace and quotes of JSON.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct JsonCborCodec;
+
+impl PayloadCodec for JsonCborCodec {
+    fn encode(&self, payload: &[u8]) -> Result<Vec<u8>, PayloadCodecError> {
+        let value: serde_json::Value = serde_json::from_slice(payload)?;
+        Ok(serde_cbor::to_vec(&value)?)
+    }
+
+    fn decode(&self, payload: &[u8]) -> Result<Vec<u8>, PayloadCodecError> {
+        let value: serde_json::Value = serde_cbor::from_slice(payload)?;
+        Ok(serde_json::to_vec(&value)?)
+    }
+}
+
+/// Max size of an inflated payload, protecting the node from payloads inflating to huge sizes.
+pub const MAX_INFLATED_SIZE: usize = 1 << 20;
+
+/// Compression level of the deflate codec, favoring the size over the speed.
+const DEFLATE_LEVEL: u8 = 9;
+
+/// Sends payloads compressed with deflate, e.g. oversize bundles, see
+/// [`oversize_bundles`](crate::oversize_bundles).
+#[derive(Debug, Copy, Clone, Default)]
+pub struct DeflateCodec;
+
+impl PayloadCodec for DeflateCodec {
+    fn encode(&self, payload: &[u8]) -> Result<Vec<u8>, PayloadCodecError> {
+        Ok(miniz_oxide::deflate::compress_to_vec(
+            payload,
+            DEFLATE_LEVEL,
+        ))
+    }
+
+    fn decode(&self, payload: &[u8]) -> Result<Vec<u8>, PayloadCodecError> {
+        miniz_oxide::inflate::decompress_to_vec_with_limit(payload, MAX_INFLATED_SIZE)
+            .map_err(|err| PayloadCodecError::Inflate(format!("{:?}", err.status)))
+    }
+}
+
+/// Size statistics of a payload profile.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, JsonSchema)]
+pub struct PayloadProfileStats {
+    /// Profile byte in front of the payload.
+    pub profile: u8,
+    /// Name of the profile.
+    pub name: String,
+    /// Payloads encoded on submission.
+    pub encoded: u64,
+    /// Bytes of the payloads submitted by the applications.
+    pub submitted_bytes: u64,
+    /// Bytes of the encoded payloads including the profile byte.
+    pub encoded_bytes: u64,
+    /// Payloads decoded before the delivery.
+    pub decoded: u64,
+    /// Payloads which could not be encoded or decoded.
+    pub failed: u64,
+}
+
+/// Registered codec of a payload profile and its counters.
+#[derive(Debug)]
+struct PayloadProfile {
+    /// Name of the profile.
+    name: String,
+    /// Codec of the profile.
+    codec: Box<dyn PayloadCodec>,
+    /// Payloads encoded on submission.
+    encoded: AtomicU64,
+    /// Bytes of the payloads submitted by the applications.
+    submitted_bytes: AtomicU64,
+    /// Bytes of the encoded payloads including the profile byte.
+    encoded_bytes: AtomicU64,
+    /// Payloads decoded before the delivery.
+    decoded: AtomicU64,
+    /// Payloads which could not be encoded or decoded.
+    failed: AtomicU64,
+}
+
+/// Codecs of the payload profiles by profile byte.
+#[derive(Debug, Default)]
+pub struct CodecRegistry {
+    /// Registered profiles.
+    profiles: BTreeMap<u8, PayloadProfile>,
+}
+
+impl CodecRegistry {
+    /// Creates a new [`CodecRegistry`] with the configured profiles.
+    pub fn new(payload_profiles: &[PayloadProfileConfig]) -> Self {
+        let mut registry = Self::default();
+        for payload_profile in payload_profiles {
+            let codec: Box<dyn PayloadCodec> = match payload_profile.codec {
+                PayloadCodecKind::Raw => Box::new(RawCodec),
+                PayloadCodecKind::JsonCbor => Box::new(JsonCborCodec),
+                PayloadCodecKind::Deflate => Box::new(DeflateCodec),
+            };
+            registry.register(payload_profile.profile, payload_profile.name.clone(), codec);
+        }
+        registry
+    }
+
+    /// Registers the codec for the profile byte, replacing a codec already registered for it.
+    pub fn register(&mut self, profile: u8, name: String, codec: Box<dyn PayloadCodec>) {
+        self.profiles.insert(
+            profile,
+            PayloadProfile {
+                name,
+                codec,
+                encoded: AtomicU64::new(0),
+                submitted_bytes: AtomicU64::new(0),
+                encoded_bytes: AtomicU64::new(0),
+                decoded: AtomicU64::new(0),
+                failed: AtomicU64::new(0),
+            },
+        );
+    }
+
+    /// Returns whether a codec is registered for the profile byte.
+    pub fn contains(&self, profile: u8) -> bool {
+        self.profiles.contains_key(&profile)
+    }
+
+    /// Encodes the payload of a submitted bundle with the codec of the profile and prefixes it
+    /// with the profile byte.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no codec is registered for the profile, the bundle has no payload or
+    /// the codec rejected the payload.
+    pub fn encode(&self, profile: u8, bundle: &mut bp7::Bundle) -> Result<(), PayloadCodecError> {
+        let payload_profile = self
+            .profiles
+            .get(&profile)
+            .ok_or(PayloadCodecError::UnknownProfile(profile))?;
+        let payload = bundle.payload().ok_or(PayloadCodecError::NoPayload)?;
+        let encoded = match payload_profile.codec.encode(payload) {
+            Ok(encoded) => encoded,
+            Err(err) => {
+                payload_profile.failed.fetch_add(1, Ordering::Relaxed);
+                return Err(err);
+            }
+        };
+        let submitted_bytes = u64::try_from(payload.len()).unwrap_or(u64::MAX);
+        let mut envelope = Vec::with_capacity(encoded.len().saturating_add(1));
+        envelope.push(profile);
+        envelope.extend(encoded);
+        payload_profile.encoded.fetch_add(1, Ordering::Relaxed);
+        payload_profile
+            .submitted_bytes
+            .fetch_add(submitted_bytes, Ordering::Relaxed);
+        payload_profile.encoded_bytes.fetch_add(
+            u64::try_from(envelope.len()).unwrap_or(u64::MAX),
+            Ordering::Relaxed,
+        );
+        bundle.set_payload(envelope);
+        Ok(())
+    }
+
+    /// Removes the profile byte from the payload of a received bundle and decodes the payload
+    /// with the codec of the profile, returns the profile.
+    ///
+    /// Bundles without payload or a registered profile byte are left unchanged.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the codec rejected the payload, the bundle is left unchanged.
+    pub fn decode(&self, bundle: &mut bp7::Bundle) -> Result<Option<u8>, PayloadCodecError> {
+        let Some((profile, payload_profile, encoded)) = bundle
+            .payload()
+            .and_then(|payload| payload.split_first())
+            .and_then(|(profile, encoded)| {
+                self.profiles
+                    .get(profile)
+                    .map(|payload_profile| (*profile, payload_profile, encoded))
+            })
+        else {
+            return Ok(None);
+        };
+        match payload_profile.codec.decode(encoded) {
+            Ok(decoded) => {
+                payload_profile.decoded.fetch_add(1, Ordering::Relaxed);
+                bundle.set_payload(decoded);
+                Ok(Some(profile))
+            }
+            Err(err) => {
+                payload_profile.failed.fetch_add(1, Ordering::Relaxed);
+                Err(err)
+            }
+        }
+    }
+
+    /// Returns the size statistics of all profiles, ordered by profile byte.
+    pub fn stats(&self) -> Vec<PayloadProfileStats> {
+        self.profiles
+            .iter()
+            .map(|(profile, payload_profile)| PayloadProfileStats {
+                profile: *profile,
+                name: payload_profile.name.clone(),
+                encoded: payload_profile.encoded.load(Ordering::Relaxed),
+                submitted_bytes: payload_profile.submitted_bytes.load(Ordering::Relaxed),
+                encoded_bytes: payload_profile.encoded_bytes.load(Ordering::Relaxed),
+                decoded: payload_profile.decoded.load(Ordering::Relaxed),
+                failed: payload_profile.failed.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use crate::configuration::{PayloadCodecKind, PayloadProfileConfig};
+    use crate::end_device_id::EndDeviceId;
+    use crate::payload_codecs::CodecRegistry;
+    use bp7::flags::BlockControlFlags;
+
+    fn bundle(payload: &[u8]) -> bp7::Bundle {
+        bp7::primary::PrimaryBlockBuilder::new()
+            .source(EndDeviceId(0x1234).try_into().unwrap())
+            .destination(EndDeviceId(0x5678).try_into().unwrap())
+            .build()
+            .map(|primary| {
+                bp7::Bundle::new(
+                    primary,
+                    vec![bp7::canonical::new_payload_block(
+                        BlockControlFlags::empty(),
+                        payload.to_vec(),
+                    )],
+                )
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn json_round_trips_as_cbor_with_profile_byte() {
+        let registry = CodecRegistry::new(&[
+            PayloadProfileConfig {
+                profile: 1,
+                name: "sensors".to_owned(),
+                codec: PayloadCodecKind::JsonCbor,
+            },
+            PayloadProfileConfig {
+                profile: 2,
+                name: "chat".to_owned(),
+                codec: PayloadCodecKind::Raw,
+            },
+        ]);
+        let json = br#"{ "temperature": 21, "humidity": 40 }"#;
+        let mut sensor_bundle = bundle(json);
+        registry.encode(1, &mut sensor_bundle).unwrap();
+        let encoded = sensor_bundle.payload().unwrap().clone();
+        assert_eq!(encoded[0], 1);
+        assert!(encoded.len() < json.len());
+
+        assert_eq!(registry.decode(&mut sensor_bundle).unwrap(), Some(1));
+        let decoded: serde_json::Value =
+            serde_json::from_slice(sensor_bundle.payload().unwrap()).unwrap();
+        assert_eq!(
+            decoded,
+            serde_json::from_slice::<serde_json::Value>(json).unwrap()
+        );
+
+        let mut invalid = bundle(b"no json");
+        assert!(registry.encode(1, &mut invalid).is_err());
+        assert!(registry.encode(3, &mut invalid).is_err());
+        let mut unknown = bundle(&[7, 1, 2]);
+        assert_eq!(registry.decode(&mut unknown).unwrap(), None);
+        assert_eq!(unknown.payload().unwrap(), &vec![7, 1, 2]);
+
+        let stats = registry.stats();
+        assert_eq!(stats.len(), 2);
+        assert_eq!(
+            (stats[0].encoded, stats[0].decoded, stats[0].failed),
+            (1, 1, 1)
+        );
+        assert_eq!(
+            stats[0].encoded_bytes,
+            u64::try_from(encoded.len()).unwrap()
+        );
+    }
+
+    #[test]
+    fn deflate_round_trips_compressed() {
+        let registry = CodecRegistry::new(&[PayloadProfileConfig {
+            profile: 9,
+            name: "compressed".to_owned(),
+            codec: PayloadCodecKind::Deflate,
+        }]);
+        let payload = b"temperature=21;".repeat(100);
+        let mut compressed = bundle(&payload);
+        registry.encode(9, &mut compressed).unwrap();
+        assert!(compressed.payload().unwrap().len() < payload.len() / 10);
+
+        assert_eq!(registry.decode(&mut compressed).unwrap(), Some(9));
+        assert_eq!(compressed.payload().unwrap(), &payload);
+
+        let mut invalid = bundle(&[9, 0xFF, 0xFF]);
+        assert!(registry.decode(&mut invalid).is_err());
+    }
+}
@@ -0,0 +1,394 @@
+//! Plugins processing the bundles received for configured end device IDs on the node, e.g. to
+//! aggregate sensor data, without modifying Spatz itself.
+//!
+//! Plugins are either external processes exchanging bundles as JSON lines via stdin/stdout or
+//! WASM modules, which require the `wasm-plugins` feature.
+
+use crate::bundle_processing::SubmittedBundle;
+use crate::configuration::{PluginConfig, PluginKind};
+use crate::end_device_id::{EndDeviceId, ManagedEndDeviceId};
+use crate::error::PluginError;
+use crate::graceful_shutdown::ShutdownAgent;
+use crate::AppState;
+use async_trait::async_trait;
+use std::collections::HashSet;
+use std::process::Stdio;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStdin, Command};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc;
+use tracing::{error, info, instrument, trace};
+
+/// Receives the bundles addressed to the end device IDs of a plugin.
+#[async_trait]
+pub trait BundlePlugin: Send {
+    /// Hands a received bundle to the plugin.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the plugin failed to process the bundle.
+    async fn process_bundle(&mut self, bundle: &mut bp7::Bundle) -> Result<(), PluginError>;
+}
+
+/// External process receiving every bundle as a line of JSON on stdin.
+///
+/// Lines written to stdout which deserialize into a bundle are sent like bundles received via
+/// WebSocket, every other line is logged.
+#[derive(Debug)]
+pub struct ProcessPlugin {
+    /// The running process, killed when the plugin is dropped.
+    _child: Child,
+    /// Stdin of the process.
+    stdin: ChildStdin,
+}
+
+impl ProcessPlugin {
+    /// Starts the process and forwards bundles written to its stdout to `bundles_tx`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the process cannot be started.
+    pub fn spawn(
+        command: &str,
+        args: &[String],
+        bundles_tx: mpsc::Sender<SubmittedBundle>,
+    ) -> Result<Self, PluginError> {
+        let mut child = Command::new(command)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+        let stdin = child.stdin.take().ok_or(PluginError::NoPipe)?;
+        let stdout = child.stdout.take().ok_or(PluginError::NoPipe)?;
+
+        let command = command.to_owned();
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(stdout).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                match serde_json::from_str::<bp7::Bundle>(&line) {
+                    Ok(bundle) => {
+                        trace!("Plugin \"{command}\" emitted a bundle");
+                        if let Err(err) = bundles_tx.try_send(bundle.into()) {
+                            error!(%err);
+                        }
+                    }
+                    Err(_) => info!("Plugin \"{command}\": {line}"),
+                }
+            }
+            trace!("Plugin \"{command}\" closed stdout");
+        });
+
+        Ok(Self {
+            _child: child,
+            stdin,
+        })
+    }
+}
+
+#[async_trait]
+impl BundlePlugin for ProcessPlugin {
+    async fn process_bundle(&mut self, bundle: &mut bp7::Bundle) -> Result<(), PluginError> {
+        let mut line = bundle.to_json().into_bytes();
+        line.push(b'\n');
+        self.stdin.write_all(&line).await?;
+        self.stdin.flush().await?;
+        Ok(())
+    }
+}
+
+/// Fuel available to a WASM plugin per bundle, roughly the amount of executed instructions.
+/// A plugin running out of fuel traps instead of blocking the node.
+#[cfg(feature = "wasm-plugins")]
+pub const WASM_FUEL_PER_BUNDLE: u64 = 100_000_000;
+
+/// WASM module exporting `memory`, `alloc(len: i32) -> i32` and `on_bundle(ptr: i32, len: i32)`.
+///
+/// Every bundle is written CBOR encoded into the memory allocated by `alloc` and handed to
+/// `on_bundle`. The module is run on the blocking thread pool with [`WASM_FUEL_PER_BUNDLE`].
+#[cfg(feature = "wasm-plugins")]
+pub struct WasmPlugin {
+    /// The instance, shared with the blocking task calling it.
+    instance: Arc<std::sync::Mutex<WasmInstance>>,
+}
+
+/// Instantiated WASM module of a [`WasmPlugin`].
+#[cfg(feature = "wasm-plugins")]
+struct WasmInstance {
+    /// Store of the instance.
+    store: wasmtime::Store<()>,
+    /// Exported memory.
+    memory: wasmtime::Memory,
+    /// Exported allocation function.
+    alloc: wasmtime::TypedFunc<i32, i32>,
+    /// Exported bundle handler.
+    on_bundle: wasmtime::TypedFunc<(i32, i32), ()>,
+}
+
+#[cfg(feature = "wasm-plugins")]
+impl WasmPlugin {
+    /// Loads and instantiates the module.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the module cannot be loaded or lacks an export.
+    pub fn load(module_path: &str) -> Result<Self, PluginError> {
+        let engine = Self::engine()?;
+        let module = wasmtime::Module::from_file(&engine, module_path)
+            .map_err(|err| PluginError::Wasm(err.to_string()))?;
+        Self::instantiate(&engine, &module)
+    }
+
+    /// Creates an engine metering the executed instructions with fuel.
+    fn engine() -> Result<wasmtime::Engine, PluginError> {
+        let mut config = wasmtime::Config::new();
+        config.consume_fuel(true);
+        wasmtime::Engine::new(&config).map_err(|err| PluginError::Wasm(err.to_string()))
+    }
+
+    /// Instantiates the module and looks up its exports.
+    fn instantiate(
+        engine: &wasmtime::Engine,
+        module: &wasmtime::Module,
+    ) -> Result<Self, PluginError> {
+        let mut store = wasmtime::Store::new(engine, ());
+        let instance = wasmtime::Instance::new(&mut store, module, &[])
+            .map_err(|err| PluginError::Wasm(err.to_string()))?;
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or_else(|| PluginError::Wasm("No exported memory".to_owned()))?;
+        let alloc = instance
+            .get_typed_func::<i32, i32>(&mut store, "alloc")
+            .map_err(|err| PluginError::Wasm(err.to_string()))?;
+        let on_bundle = instance
+            .get_typed_func::<(i32, i32), ()>(&mut store, "on_bundle")
+            .map_err(|err| PluginError::Wasm(err.to_string()))?;
+        Ok(Self {
+            instance: Arc::new(std::sync::Mutex::new(WasmInstance {
+                store,
+                memory,
+                alloc,
+                on_bundle,
+            })),
+        })
+    }
+}
+
+#[cfg(feature = "wasm-plugins")]
+impl WasmInstance {
+    /// Hands the CBOR encoded bundle to the module, refilling the fuel to
+    /// [`WASM_FUEL_PER_BUNDLE`] first.
+    fn process_cbor(&mut self, cbor: &[u8]) -> Result<(), PluginError> {
+        let remaining = self
+            .store
+            .consume_fuel(0)
+            .map_err(|err| PluginError::Wasm(err.to_string()))?;
+        self.store
+            .add_fuel(WASM_FUEL_PER_BUNDLE.saturating_sub(remaining))
+            .map_err(|err| PluginError::Wasm(err.to_string()))?;
+        let len = i32::try_from(cbor.len())
+            .map_err(|_| PluginError::Wasm("Bundle too large".to_owned()))?;
+        let ptr = self
+            .alloc
+            .call(&mut self.store, len)
+            .map_err(|err| PluginError::Wasm(err.to_string()))?;
+        let offset =
+            usize::try_from(ptr).map_err(|_| PluginError::Wasm("Invalid pointer".to_owned()))?;
+        self.memory
+            .write(&mut self.store, offset, cbor)
+            .map_err(|err| PluginError::Wasm(err.to_string()))?;
+        self.on_bundle
+            .call(&mut self.store, (ptr, len))
+            .map_err(|err| PluginError::Wasm(err.to_string()))
+    }
+}
+
+#[cfg(feature = "wasm-plugins")]
+#[async_trait]
+impl BundlePlugin for WasmPlugin {
+    async fn process_bundle(&mut self, bundle: &mut bp7::Bundle) -> Result<(), PluginError> {
+        let cbor = bundle.to_cbor();
+        let instance = self.instance.clone();
+        tokio::task::spawn_blocking(move || {
+            instance
+                .lock()
+                .unwrap_or_else(std::sync::PoisonError::into_inner)
+                .process_cbor(&cbor)
+        })
+        .await
+        .map_err(|err| PluginError::Wasm(err.to_string()))?
+    }
+}
+
+/// Creates the plugin described by the configuration.
+///
+/// # Errors
+///
+/// Returns an error if the plugin cannot be started or WASM plugins are not enabled.
+pub fn create_plugin(
+    plugin: &PluginKind,
+    bundles_tx: mpsc::Sender<SubmittedBundle>,
+) -> Result<Box<dyn BundlePlugin>, PluginError> {
+    match plugin {
+        PluginKind::Process { command, args } => {
+            Ok(Box::new(ProcessPlugin::spawn(command, args, bundles_tx)?))
+        }
+        #[cfg(feature = "wasm-plugins")]
+        PluginKind::Wasm { module_path } => Ok(Box::new(WasmPlugin::load(module_path)?)),
+        #[cfg(not(feature = "wasm-plugins"))]
+        PluginKind::Wasm { .. } => Err(PluginError::WasmNotEnabled),
+    }
+}
+
+/// Task handing the received bundles addressed to the end device IDs of the plugin to the
+/// plugin.
+#[instrument(skip_all)]
+pub async fn plugin_task(
+    plugin_config: PluginConfig,
+    state: Arc<AppState>,
+    mut shutdown_agent: ShutdownAgent,
+) {
+    trace!("Starting up");
+    let end_device_ids: HashSet<EndDeviceId> = plugin_config
+        .end_device_ids
+        .iter()
+        .map(|end_device_id| EndDeviceId::from(ManagedEndDeviceId::from(end_device_id)))
+        .collect();
+    let mut bundles_rx = state.bundles_to_ws.subscribe();
+    let mut plugin = match create_plugin(&plugin_config.plugin, state.bundles_from_ws.clone()) {
+        Ok(plugin) => plugin,
+        Err(err) => {
+            error!("Failed to create plugin: {err}");
+            return;
+        }
+    };
+
+    loop {
+        let mut bundle = tokio::select! {
+            bundle = bundles_rx.recv() => {
+                match bundle {
+                    Ok(bundle) => bundle,
+                    Err(RecvError::Lagged(skipped)) => {
+                        error!("Plugin lagging behind, {skipped} bundles skipped");
+                        continue;
+                    }
+                    Err(RecvError::Closed) => return,
+                }
+            }
+            _ = shutdown_agent.await_shutdown() => {
+                trace!("Shutting down");
+                return
+            }
+        };
+
+        let Ok(destination) = EndDeviceId::try_from(bundle.primary.destination.clone()) else {
+            continue;
+        };
+        if !end_device_ids.contains(&destination) {
+            continue;
+        }
+        trace!("Handing bundle for {destination:?} to plugin");
+        if let Err(err) = plugin.process_bundle(&mut bundle).await {
+            error!(%err);
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use crate::configuration::PluginKind;
+    use crate::end_device_id::EndDeviceId;
+    use crate::error::PluginError;
+    use crate::plugins::{create_plugin, BundlePlugin, ProcessPlugin};
+    use bp7::flags::BlockControlFlags;
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+
+    fn bundle() -> bp7::Bundle {
+        bp7::primary::PrimaryBlockBuilder::new()
+            .source(EndDeviceId(0x1234).try_into().unwrap())
+            .destination(EndDeviceId(0x5678).try_into().unwrap())
+            .lifetime(Duration::from_secs(3600))
+            .build()
+            .map(|primary| {
+                bp7::Bundle::new(
+                    primary,
+                    vec![bp7::canonical::new_payload_block(
+                        BlockControlFlags::empty(),
+                        b"payload".to_vec(),
+                    )],
+                )
+            })
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn create_plugin_reports_unstartable_plugins() {
+        let (bundles_tx, _bundles_rx) = mpsc::channel(1);
+        let result = create_plugin(
+            &PluginKind::Process {
+                command: "/nonexistent/spatz-plugin".to_owned(),
+                args: Vec::new(),
+            },
+            bundles_tx.clone(),
+        );
+        assert!(matches!(result, Err(PluginError::Io(_))));
+
+        let result = create_plugin(
+            &PluginKind::Wasm {
+                module_path: "/nonexistent/spatz-plugin.wasm".to_owned(),
+            },
+            bundles_tx,
+        );
+        #[cfg(feature = "wasm-plugins")]
+        assert!(matches!(result, Err(PluginError::Wasm(_))));
+        #[cfg(not(feature = "wasm-plugins"))]
+        assert!(matches!(result, Err(PluginError::WasmNotEnabled)));
+    }
+
+    #[tokio::test]
+    async fn process_plugin_reports_exited_process() {
+        let (bundles_tx, _bundles_rx) = mpsc::channel(1);
+        let mut plugin = ProcessPlugin::spawn("true", &[], bundles_tx).unwrap();
+        // Writes succeed until the process exited and closed stdin.
+        let mut result = Ok(());
+        for _ in 0..100 {
+            result = plugin.process_bundle(&mut bundle()).await;
+            if result.is_err() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        assert!(matches!(result, Err(PluginError::Io(_))));
+    }
+
+    #[cfg(feature = "wasm-plugins")]
+    #[tokio::test]
+    async fn wasm_plugin_runs_out_of_fuel() {
+        use crate::plugins::WasmPlugin;
+
+        let engine = WasmPlugin::engine().unwrap();
+        let module = wasmtime::Module::new(
+            &engine,
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "alloc") (param i32) (result i32) i32.const 0)
+                (func (export "on_bundle") (param i32 i32) (loop br 0)))"#,
+        )
+        .unwrap();
+        let mut plugin = WasmPlugin::instantiate(&engine, &module).unwrap();
+        for _ in 0..2 {
+            assert!(matches!(
+                tokio::time::timeout(
+                    Duration::from_secs(30),
+                    plugin.process_bundle(&mut bundle())
+                )
+                .await
+                .unwrap(),
+                Err(PluginError::Wasm(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,192 @@
+//! Radio silence windows during which neither packets are relayed nor announcements or bundles
+//! are sent, packets are only buffered.
+//!
+//! Every task handing packets to the gateways awaits [`RadioSilence::wait_until_over`] before
+//! taking packets from its queue, so the windows are enforced in one place. Packets received
+//! meanwhile stay in the queues.
+
+use crate::configuration::RadioSilenceWindow;
+use crate::graceful_shutdown::ShutdownAgent;
+use chrono::{DateTime, Duration, NaiveTime, Utc};
+use schemars::JsonSchema;
+use serde::Serialize;
+use tokio::sync::{Mutex, Notify};
+use tracing::info;
+
+/// Current state of the radio silence.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct RadioSilenceStatus {
+    /// Whether the radio silence is active.
+    pub active: bool,
+    /// Reason of the active radio silence.
+    pub reason: Option<String>,
+    /// End of the radio silence started via the API.
+    pub silent_until: Option<DateTime<Utc>>,
+    /// Daily radio silence windows.
+    pub windows: Vec<RadioSilenceWindow>,
+}
+
+/// Daily windows and radio silence started via the API.
+#[derive(Debug, Clone, Default)]
+struct Schedule {
+    /// Daily radio silence windows.
+    windows: Vec<RadioSilenceWindow>,
+    /// End of the radio silence started via the API.
+    silent_until: Option<DateTime<Utc>>,
+}
+
+impl Schedule {
+    /// Returns the end and the reason of the radio silence active at `now`.
+    fn active(&self, now: DateTime<Utc>) -> Option<(DateTime<Utc>, String)> {
+        if let Some(silent_until) = self.silent_until.filter(|silent_until| *silent_until > now) {
+            return Some((silent_until, "Started via API".to_owned()));
+        }
+        let time = now.time();
+        self.windows
+            .iter()
+            .find(|window| window.contains(time))
+            .map(|window| (window.next_end(now), window.reason.clone()))
+    }
+}
+
+impl RadioSilenceWindow {
+    /// Returns whether the time of day is within the window.
+    fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            self.start <= time || time < self.end
+        }
+    }
+
+    /// Returns the next end of the window after `now`.
+    fn next_end(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        let end = now.date_naive().and_time(self.end).and_utc();
+        if end > now {
+            end
+        } else {
+            end + Duration::days(1)
+        }
+    }
+}
+
+/// Central gate for all transmissions, closed during radio silence.
+#[derive(Debug, Default)]
+pub struct RadioSilence {
+    /// Daily windows and radio silence started via the API.
+    schedule: Mutex<Schedule>,
+    /// Signalled when the schedule changed.
+    changed: Notify,
+}
+
+impl RadioSilence {
+    /// Creates a new [`RadioSilence`] with the daily windows.
+    pub fn new(windows: Vec<RadioSilenceWindow>) -> Self {
+        Self {
+            schedule: Mutex::new(Schedule {
+                windows,
+                silent_until: None,
+            }),
+            changed: Notify::new(),
+        }
+    }
+
+    /// Replaces the daily windows, takes effect immediately.
+    pub async fn set_windows(&self, windows: Vec<RadioSilenceWindow>) {
+        info!(?windows, "Radio silence windows changed");
+        self.schedule.lock().await.windows = windows;
+        self.changed.notify_waiters();
+    }
+
+    /// Starts a radio silence until `silent_until` or ends it if `silent_until` is not set.
+    pub async fn set_silent_until(&self, silent_until: Option<DateTime<Utc>>) {
+        info!(?silent_until, "Radio silence changed via API");
+        self.schedule.lock().await.silent_until = silent_until;
+        self.changed.notify_waiters();
+    }
+
+    /// Returns the current state of the radio silence.
+    pub async fn status(&self, now: DateTime<Utc>) -> RadioSilenceStatus {
+        let schedule = self.schedule.lock().await;
+        let active = schedule.active(now);
+        RadioSilenceStatus {
+            active: active.is_some(),
+            reason: active.map(|(_, reason)| reason),
+            silent_until: schedule
+                .silent_until
+                .filter(|silent_until| *silent_until > now),
+            windows: schedule.windows.clone(),
+        }
+    }
+
+    /// Returns whether a radio silence is active.
+    pub async fn is_active(&self) -> bool {
+        self.schedule.lock().await.active(Utc::now()).is_some()
+    }
+
+    /// Returns immediately if no radio silence is active, waits until the radio silence is over
+    /// otherwise.
+    ///
+    /// Returns `false` if the shutdown started while waiting.
+    pub async fn wait_until_over(&self, shutdown_agent: &mut ShutdownAgent) -> bool {
+        tokio::select! {
+            () = self.wait() => true,
+            _ = shutdown_agent.await_shutdown() => false,
+        }
+    }
+
+    /// Waits until no radio silence is active.
+    async fn wait(&self) {
+        loop {
+            let changed = self.changed.notified();
+            let now = Utc::now();
+            let Some((end, reason)) = self.schedule.lock().await.active(now) else {
+                return;
+            };
+            info!(%end, "Radio silence: {reason}");
+            let remaining = (end - now).to_std().unwrap_or_default();
+            tokio::select! {
+                () = tokio::time::sleep(remaining) => {}
+                () = changed => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use crate::configuration::RadioSilenceWindow;
+    use crate::radio_silence::RadioSilence;
+    use chrono::{NaiveTime, TimeZone, Utc};
+
+    #[tokio::test]
+    async fn windows_spanning_midnight_and_api_silence() {
+        let radio_silence = RadioSilence::new(vec![RadioSilenceWindow {
+            start: NaiveTime::from_hms_opt(22, 0, 0).unwrap(),
+            end: NaiveTime::from_hms_opt(6, 0, 0).unwrap(),
+            reason: "Night".to_owned(),
+        }]);
+        let night = Utc.with_ymd_and_hms(2024, 5, 1, 23, 0, 0).unwrap();
+        let status = radio_silence.status(night).await;
+        assert!(status.active);
+        assert_eq!(status.reason.as_deref(), Some("Night"));
+        assert_eq!(
+            radio_silence.schedule.lock().await.active(night).unwrap().0,
+            Utc.with_ymd_and_hms(2024, 5, 2, 6, 0, 0).unwrap()
+        );
+
+        let noon = Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap();
+        assert!(!radio_silence.status(noon).await.active);
+        radio_silence
+            .set_silent_until(Some(noon + chrono::Duration::minutes(10)))
+            .await;
+        assert!(radio_silence.status(noon).await.active);
+        assert!(
+            !radio_silence
+                .status(noon + chrono::Duration::minutes(10))
+                .await
+                .active
+        );
+    }
+}
@@ -0,0 +1,235 @@
+//! Distribution of the received frames per spreading factor, bandwidth, frequency and hour, so
+//! operators can tune channel plans and default data rates based on the real traffic.
+
+use crate::configuration::RadioStatsConfig;
+use chirpstack_api::gw::{modulation, UplinkFrame};
+use chrono::{DateTime, DurationRound, Timelike, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use tokio::sync::Mutex;
+
+/// Received frames of an hour with the same modulation, the persisted form of the distribution.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct RadioFrameCount {
+    /// Start of the hour the frames were received in.
+    pub hour: DateTime<Utc>,
+    /// Spreading factor of the frames.
+    pub spreading_factor: u32,
+    /// Bandwidth of the frames in Hz.
+    pub bandwidth: u32,
+    /// Frequency of the frames in Hz.
+    pub frequency: u32,
+    /// Received frames.
+    pub frames: u64,
+}
+
+/// Distribution of the received frames within the retention.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct RadioStatsReport {
+    /// Time in hours the counts are kept.
+    pub retention_hours: u64,
+    /// Received frames.
+    pub total_frames: u64,
+    /// Received frames by spreading factor.
+    pub per_spreading_factor: BTreeMap<u32, u64>,
+    /// Received frames by bandwidth in Hz.
+    pub per_bandwidth: BTreeMap<u32, u64>,
+    /// Received frames by frequency in Hz.
+    pub per_frequency: BTreeMap<u32, u64>,
+    /// Received frames by hour of the day in UTC, index 0 is midnight.
+    pub per_hour_of_day: Vec<u64>,
+    /// Received frames per hour and modulation, oldest first.
+    pub hourly: Vec<RadioFrameCount>,
+}
+
+/// Key of the counted frames: start of the hour, spreading factor, bandwidth and frequency.
+type RadioBucketKey = (DateTime<Utc>, u32, u32, u32);
+
+/// Counts the received frames per hour and modulation, removing hours past the retention.
+#[derive(Debug)]
+pub struct RadioStats {
+    /// Time in hours the counts are kept.
+    retention_hours: u64,
+    /// Received frames by hour and modulation.
+    buckets: Mutex<BTreeMap<RadioBucketKey, u64>>,
+}
+
+impl RadioStats {
+    /// Creates a new [`RadioStats`] with the counts loaded from the database.
+    pub fn new(config: &RadioStatsConfig, counts: Vec<RadioFrameCount>) -> Self {
+        let buckets = counts
+            .into_iter()
+            .map(|count| {
+                (
+                    (
+                        count.hour,
+                        count.spreading_factor,
+                        count.bandwidth,
+                        count.frequency,
+                    ),
+                    count.frames,
+                )
+            })
+            .collect();
+        Self {
+            retention_hours: config.retention_hours,
+            buckets: Mutex::new(buckets),
+        }
+    }
+
+    /// Counts a received frame, ignored if the frame was not received with LoRa modulation.
+    pub async fn record(&self, uplink: &UplinkFrame, now: DateTime<Utc>) {
+        let Some(tx_info) = &uplink.tx_info else {
+            return;
+        };
+        let Some(modulation::Parameters::Lora(lora_modulation_info)) = tx_info
+            .modulation
+            .as_ref()
+            .and_then(|modulation| modulation.parameters.as_ref())
+        else {
+            return;
+        };
+        let key = (
+            start_of_hour(now),
+            lora_modulation_info.spreading_factor,
+            lora_modulation_info.bandwidth,
+            tx_info.frequency,
+        );
+        let mut buckets = self.buckets.lock().await;
+        let frames = buckets.entry(key).or_insert(0);
+        *frames = frames.saturating_add(1);
+        self.remove_expired(&mut buckets, now);
+    }
+
+    /// Returns the counts per hour and modulation within the retention, oldest first.
+    pub async fn counts(&self, now: DateTime<Utc>) -> Vec<RadioFrameCount> {
+        let mut buckets = self.buckets.lock().await;
+        self.remove_expired(&mut buckets, now);
+        buckets
+            .iter()
+            .map(
+                |(&(hour, spreading_factor, bandwidth, frequency), &frames)| RadioFrameCount {
+                    hour,
+                    spreading_factor,
+                    bandwidth,
+                    frequency,
+                    frames,
+                },
+            )
+            .collect()
+    }
+
+    /// Returns the distribution of the received frames within the retention.
+    pub async fn report(&self, now: DateTime<Utc>) -> RadioStatsReport {
+        let hourly = self.counts(now).await;
+        let mut report = RadioStatsReport {
+            retention_hours: self.retention_hours,
+            total_frames: 0,
+            per_spreading_factor: BTreeMap::new(),
+            per_bandwidth: BTreeMap::new(),
+            per_frequency: BTreeMap::new(),
+            per_hour_of_day: vec![0; 24],
+            hourly: Vec::new(),
+        };
+        for count in &hourly {
+            report.total_frames = report.total_frames.saturating_add(count.frames);
+            for (map, key) in [
+                (&mut report.per_spreading_factor, count.spreading_factor),
+                (&mut report.per_bandwidth, count.bandwidth),
+                (&mut report.per_frequency, count.frequency),
+            ] {
+                let frames = map.entry(key).or_insert(0);
+                *frames = frames.saturating_add(count.frames);
+            }
+            if let Some(frames) = usize::try_from(count.hour.hour())
+                .ok()
+                .and_then(|hour| report.per_hour_of_day.get_mut(hour))
+            {
+                *frames = frames.saturating_add(count.frames);
+            }
+        }
+        report.hourly = hourly;
+        report
+    }
+
+    /// Removes the counts of the hours ended before the retention.
+    fn remove_expired(&self, buckets: &mut BTreeMap<RadioBucketKey, u64>, now: DateTime<Utc>) {
+        let retention = chrono::Duration::from_std(std::time::Duration::from_secs(
+            self.retention_hours.saturating_mul(3600),
+        ))
+        .unwrap_or(chrono::Duration::MAX);
+        let oldest_hour = now
+            .checked_sub_signed(retention)
+            .map_or(DateTime::<Utc>::MIN_UTC, start_of_hour);
+        buckets.retain(|(hour, ..), _| *hour >= oldest_hour);
+    }
+}
+
+/// Returns the start of the hour of the time.
+fn start_of_hour(time: DateTime<Utc>) -> DateTime<Utc> {
+    time.duration_trunc(chrono::Duration::hours(1))
+        .unwrap_or(time)
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use crate::configuration::RadioStatsConfig;
+    use crate::radio_stats::RadioStats;
+    use chirpstack_api::gw::{
+        modulation, LoraModulationInfo, Modulation, UplinkFrame, UplinkTxInfo,
+    };
+    use chrono::{TimeZone, Utc};
+
+    fn uplink(frequency: u32, spreading_factor: u32) -> UplinkFrame {
+        UplinkFrame {
+            tx_info: Some(UplinkTxInfo {
+                frequency,
+                modulation: Some(Modulation {
+                    parameters: Some(modulation::Parameters::Lora(LoraModulationInfo {
+                        bandwidth: 125_000,
+                        spreading_factor,
+                        ..LoraModulationInfo::default()
+                    })),
+                }),
+            }),
+            ..UplinkFrame::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn frames_are_counted_per_modulation_and_hour() {
+        let radio_stats = RadioStats::new(&RadioStatsConfig { retention_hours: 2 }, Vec::new());
+        let start = Utc.with_ymd_and_hms(2024, 5, 1, 10, 15, 0).unwrap();
+        radio_stats.record(&uplink(868_100_000, 7), start).await;
+        radio_stats.record(&uplink(868_100_000, 7), start).await;
+        radio_stats.record(&uplink(868_300_000, 12), start).await;
+        radio_stats.record(&UplinkFrame::default(), start).await;
+
+        let later = Utc.with_ymd_and_hms(2024, 5, 1, 11, 5, 0).unwrap();
+        radio_stats.record(&uplink(868_500_000, 9), later).await;
+
+        let report = radio_stats.report(later).await;
+        assert_eq!(report.total_frames, 4);
+        assert_eq!(report.per_spreading_factor.get(&7), Some(&2));
+        assert_eq!(report.per_bandwidth.get(&125_000), Some(&4));
+        assert_eq!(report.per_frequency.get(&868_300_000), Some(&1));
+        assert_eq!(
+            (report.per_hour_of_day[10], report.per_hour_of_day[11]),
+            (3, 1)
+        );
+        assert_eq!(report.hourly.len(), 3);
+        assert_eq!(
+            report.hourly[0].hour,
+            Utc.with_ymd_and_hms(2024, 5, 1, 10, 0, 0).unwrap()
+        );
+
+        let counts = radio_stats.counts(later).await;
+        let restored = RadioStats::new(&RadioStatsConfig { retention_hours: 2 }, counts);
+        let expired = Utc.with_ymd_and_hms(2024, 5, 1, 13, 0, 0).unwrap();
+        let report = restored.report(expired).await;
+        assert_eq!(report.total_frames, 1);
+        assert_eq!(report.per_spreading_factor.get(&9), Some(&1));
+    }
+}
@@ -1,43 +1,54 @@
 //! Receive buffers collecting incoming fragments.
 //! Receive buffer manager to manage all receive buffers.
+//!
+//! Bundle fragments are reassembled per destination, source, timestamp and fragment offset hash,
+//! so the fragments of bundles to different groups never mix. Group members hear the fragments of
+//! a bundle from every relay flooding it, fragments received again and fragments of recently
+//! combined bundles are dropped without counting against the trust of the source.
 
-mod bundle;
-mod hop2hop;
-
+use crate::delivery_ledger::DeliveryLedger;
 use crate::end_device_id::EndDeviceId;
-use crate::lorawan_protocol::{
-    BundleFragmentOffsetHash, Hop2HopFragment, LoRaWanPacket, LocalAnnouncement,
-};
+use crate::memory_budget::{BufferCategory, PACKET_SIZE};
+use crate::status_reports::{self, REASON_LIFETIME_EXPIRED, REASON_NO_INFORMATION};
 use crate::AppState;
-pub use bundle::BundleReceiveBuffer;
 use chrono::{DateTime, Utc};
-pub use hop2hop::Hop2HopReceiveBuffer;
+use lorawan_dtn_protocol::error::BundleReceiveBufferProcessError;
+use lorawan_dtn_protocol::reassembly::{
+    Bp7BundleKey, Bp7ReceiveBuffer, BundleReceiveBuffer, Hop2HopReceiveBuffer,
+};
+use lorawan_dtn_protocol::{
+    Bp7Bundle, BundleFragmentOffsetHash, Hop2HopFragment, LoRaWanPacket, LocalAnnouncement,
+    BUNDLE_LIFETIME, STATUS_DELETED, STATUS_DELIVERED, STATUS_RECEIVED,
+};
 use std::collections::hash_map::Entry;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
-use tracing::{error, trace};
+use tracing::{error, info, trace, warn};
 
-/// Convert a unix timestamp to a [`bp7::DtnTime`].
-pub fn unix_ts_to_dtn_time(timestamp: u64) -> bp7::DtnTime {
-    (timestamp - bp7::dtntime::SECONDS1970_TO2K) * 1000
-}
+/// Maximum amount of remembered combined bundles, the ones with the oldest bundle timestamp are
+/// forgotten first.
+const MAX_COMBINED_BUNDLES: usize = 1024;
+
+/// Key of a bundle receive buffer: destination, source, timestamp and fragment offset hash.
+type BundleReceiveKey = (
+    EndDeviceId,
+    EndDeviceId,
+    DateTime<Utc>,
+    Option<BundleFragmentOffsetHash>,
+);
 
 /// Manages receive buffers.
 pub struct ReceiveBufferManager {
     /// Application state.
     state: Arc<AppState>,
     /// Bundle receive buffer.
-    bundle_receive_buffers: HashMap<
-        (
-            EndDeviceId,
-            EndDeviceId,
-            DateTime<Utc>,
-            Option<BundleFragmentOffsetHash>,
-        ),
-        BundleReceiveBuffer,
-    >,
+    bundle_receive_buffers: HashMap<BundleReceiveKey, BundleReceiveBuffer>,
+    /// Keys of recently combined bundles, late fragments of them are dropped.
+    combined_bundles: HashSet<BundleReceiveKey>,
     /// Hop2Hop receive buffer.
     hop2hop_receive_buffers: HashMap<u32, Hop2HopReceiveBuffer>,
+    /// BP7 fragment receive buffer.
+    bp7_receive_buffers: HashMap<Bp7BundleKey, Bp7ReceiveBuffer>,
 }
 
 impl ReceiveBufferManager {
@@ -46,32 +57,136 @@ impl ReceiveBufferManager {
         Self {
             state,
             bundle_receive_buffers: HashMap::new(),
+            combined_bundles: HashSet::new(),
             hop2hop_receive_buffers: HashMap::new(),
+            bp7_receive_buffers: HashMap::new(),
         }
     }
 
     /// Process a packet into the corresponding buffer or create a new buffer if there is no
     /// corresponding buffer.
-    pub fn process_packet(&mut self, mut packet: Box<dyn LoRaWanPacket>) {
+    ///
+    /// Fragments not fitting into the [`MemoryBudget`](crate::memory_budget::MemoryBudget) evict
+    /// the incomplete receive buffers, the ones with the oldest bundle timestamp first, and are
+    /// dropped if they still do not fit.
+    pub fn process_packet(&mut self, packet: Box<dyn LoRaWanPacket>) {
+        let is_fragment = packet.as_bundle_packet().is_some()
+            || packet.as_any().is::<Hop2HopFragment>()
+            || packet.as_any().is::<Bp7Bundle>();
+        if is_fragment && !self.reserve_fragment() {
+            warn!("Memory budget exhausted, fragment dropped");
+            return;
+        }
+        self.buffer_packet(packet);
+        self.update_memory_usage();
+    }
+
+    /// Evicts incomplete receive buffers until a fragment fits into the memory budget and
+    /// reserves its size.
+    ///
+    /// Returns `false` if the fragment does not fit even without receive buffers.
+    fn reserve_fragment(&mut self) -> bool {
+        let state = self.state.clone();
+        let mut evicted = 0;
+        while !state.memory_budget.fits(PACKET_SIZE) {
+            if let Some(oldest) = self
+                .bundle_receive_buffers
+                .keys()
+                .min_by_key(|(_, _, timestamp, _)| *timestamp)
+                .copied()
+            {
+                self.bundle_receive_buffers.remove(&oldest);
+            } else if let Some(packet_hash) = self.hop2hop_receive_buffers.keys().next().copied() {
+                self.hop2hop_receive_buffers.remove(&packet_hash);
+            } else if let Some(oldest) = self
+                .bp7_receive_buffers
+                .iter()
+                .min_by_key(|(_, receive_buffer)| receive_buffer.creation_time())
+                .map(|(key, _)| key.clone())
+            {
+                self.bp7_receive_buffers.remove(&oldest);
+            } else {
+                break;
+            }
+            evicted += 1;
+            self.update_memory_usage();
+        }
+        if evicted > 0 {
+            warn!(
+                evicted,
+                "Memory budget exhausted, evicted incomplete receive buffers"
+            );
+            state.memory_budget.record_evictions(evicted);
+        }
+        state
+            .memory_budget
+            .reserve(BufferCategory::ReceiveBuffers, PACKET_SIZE)
+    }
+
+    /// Reports the total length of the buffered fragments to the memory budget.
+    fn update_memory_usage(&self) {
+        let size = self
+            .bundle_receive_buffers
+            .values()
+            .map(BundleReceiveBuffer::size)
+            .sum::<usize>()
+            + self
+                .hop2hop_receive_buffers
+                .values()
+                .map(Hop2HopReceiveBuffer::size)
+                .sum::<usize>()
+            + self
+                .bp7_receive_buffers
+                .values()
+                .map(Bp7ReceiveBuffer::size)
+                .sum::<usize>();
+        self.state.memory_budget.set_usage(
+            BufferCategory::ReceiveBuffers,
+            u64::try_from(size).unwrap_or(u64::MAX),
+        );
+    }
+
+    /// Buffers a packet, delivers reassembled bundles and processes reassembled hop2hop packets.
+    fn buffer_packet(&mut self, mut packet: Box<dyn LoRaWanPacket>) {
         if let Some(bundle_fragment) = packet.as_bundle_packet_mut() {
-            match self.bundle_receive_buffers.entry((
+            let source = bundle_fragment.source();
+            let key = (
                 bundle_fragment.destination(),
-                bundle_fragment.source(),
+                source,
                 bundle_fragment.timestamp(),
                 bundle_fragment.bundle_fragment_offset_hash(),
-            )) {
+            );
+            if self.combined_bundles.contains(&key) {
+                trace!("Fragment of an already combined bundle, e.g. via another relay");
+                return;
+            }
+            match self.bundle_receive_buffers.entry(key) {
                 Entry::Occupied(mut entry) => {
-                    if let Err(err) = entry.get_mut().process_packet(bundle_fragment) {
-                        error!(%err);
-                        return;
+                    match entry.get_mut().process_packet(bundle_fragment) {
+                        Ok(()) => {}
+                        Err(BundleReceiveBufferProcessError::DuplicateFragment) => {
+                            trace!("Fragment already received, e.g. via another relay");
+                            return;
+                        }
+                        Err(err) => {
+                            error!(%err);
+                            self.state
+                                .neighbor_trust
+                                .record_malformed(source, Utc::now());
+                            return;
+                        }
                     }
                     if entry.get().is_combinable() {
                         trace!("Bundle is combinable");
                         let receive_buffer = entry.remove();
+                        self.remember_combined(key);
                         match receive_buffer.combine() {
-                            Ok(bp7_bundle) => self.send_pb7_bundle_to_ws(bp7_bundle),
+                            Ok(bp7_bundle) => self.buffer_bp7_fragment(bp7_bundle),
                             Err(err) => {
                                 error!(%err);
+                                self.state
+                                    .neighbor_trust
+                                    .record_malformed(source, Utc::now());
                             }
                         }
                     }
@@ -81,10 +196,14 @@ impl ReceiveBufferManager {
 
                     if receive_buffer.is_combinable() {
                         trace!("Bundle is combinable");
+                        self.remember_combined(key);
                         match receive_buffer.combine() {
-                            Ok(bp7_bundle) => self.send_pb7_bundle_to_ws(bp7_bundle),
+                            Ok(bp7_bundle) => self.buffer_bp7_fragment(bp7_bundle),
                             Err(err) => {
                                 error!(%err);
+                                self.state
+                                    .neighbor_trust
+                                    .record_malformed(source, Utc::now());
                             }
                         }
                     } else {
@@ -137,6 +256,8 @@ impl ReceiveBufferManager {
                     }
                 }
             }
+        } else if let Some(bp7_bundle) = packet.as_any().downcast_ref::<Bp7Bundle>() {
+            self.buffer_bp7_bundle(bp7_bundle);
         } else if let Some(local_announcement) =
             packet.as_any_mut().downcast_mut::<LocalAnnouncement>()
         {
@@ -154,15 +275,166 @@ impl ReceiveBufferManager {
         }
     }
 
-    /// Send [`bp7::Bundle`] to all connected websocket clients.
-    /// If no clients are connected, the bundle is dropped.
-    fn send_pb7_bundle_to_ws(&self, bundle: bp7::Bundle) {
+    /// Remembers the key of a combined bundle, forgetting the bundles past their lifetime and the
+    /// ones with the oldest bundle timestamp beyond [`MAX_COMBINED_BUNDLES`].
+    fn remember_combined(&mut self, key: BundleReceiveKey) {
+        let oldest_alive = Utc::now()
+            - chrono::Duration::from_std(BUNDLE_LIFETIME).unwrap_or(chrono::Duration::zero());
+        self.combined_bundles
+            .retain(|(_, _, timestamp, _)| *timestamp >= oldest_alive);
+        while self.combined_bundles.len() >= MAX_COMBINED_BUNDLES {
+            let Some(oldest) = self
+                .combined_bundles
+                .iter()
+                .min_by_key(|(_, _, timestamp, _)| *timestamp)
+                .copied()
+            else {
+                break;
+            };
+            self.combined_bundles.remove(&oldest);
+        }
+        self.combined_bundles.insert(key);
+    }
+
+    /// Delivers complete BP7 bundles and buffers BP7 fragments until the bundle is combinable.
+    fn buffer_bp7_bundle(&mut self, bp7_bundle: &Bp7Bundle) {
+        let bundle = match bp7_bundle.bundle() {
+            Ok(bundle) => bundle,
+            Err(err) => {
+                error!(%err);
+                return;
+            }
+        };
+        if let Some(bp7_interop) = &self.state.bp7_interop {
+            bp7_interop.record_received();
+        }
+        self.buffer_bp7_fragment(bundle);
+    }
+
+    /// Delivers complete bundles and buffers BP7 fragments until the bundle is combinable.
+    ///
+    /// Fragments are received as BP7 bundles or combined from fragmented bundle fragments, e.g.
+    /// of an oversize bundle split by the sender, see
+    /// [`oversize_bundles`](crate::oversize_bundles).
+    fn buffer_bp7_fragment(&mut self, bundle: bp7::Bundle) {
+        if !bundle.primary.has_fragmentation() {
+            self.send_pb7_bundle_to_ws(bundle);
+            return;
+        }
+        match self
+            .bp7_receive_buffers
+            .entry(Bp7ReceiveBuffer::key(&bundle))
+        {
+            Entry::Occupied(mut entry) => {
+                if let Err(err) = entry.get_mut().process_fragment(&bundle) {
+                    error!(%err);
+                    return;
+                }
+                if entry.get().is_combinable() {
+                    trace!("BP7 bundle is combinable");
+                    match entry.remove().combine() {
+                        Ok(bp7_bundle) => self.send_pb7_bundle_to_ws(bp7_bundle),
+                        Err(err) => {
+                            error!(%err);
+                        }
+                    }
+                }
+            }
+            Entry::Vacant(entry) => {
+                let receive_buffer = match Bp7ReceiveBuffer::new(&bundle) {
+                    Ok(receive_buffer) => receive_buffer,
+                    Err(err) => {
+                        error!(%err);
+                        return;
+                    }
+                };
+                if receive_buffer.is_combinable() {
+                    trace!("BP7 bundle is combinable");
+                    match receive_buffer.combine() {
+                        Ok(bp7_bundle) => self.send_pb7_bundle_to_ws(bp7_bundle),
+                        Err(err) => {
+                            error!(%err);
+                        }
+                    }
+                } else {
+                    entry.insert(receive_buffer);
+                }
+            }
+        }
+    }
+
+    /// Send [`bp7::Bundle`] to all connected websocket clients and keep it in the
+    /// [`BundleMailbox`](crate::bundle_mailbox::BundleMailbox) for polling clients if configured.
+    /// If no clients are connected and no mailbox is configured or the bundle is past its lifetime and the
+    /// [`LateDeliveryPolicy`](crate::configuration::LateDeliveryPolicy) suppresses it, the bundle
+    /// is dropped. The fragments of a dropped bundle are still treated as received.
+    /// Bundles recorded in the [`DeliveryLedger`](crate::delivery_ledger::DeliveryLedger) are not
+    /// delivered again. Payloads with a registered payload profile are delivered decoded.
+    /// The reception, delivery and deletion are reported if requested, see
+    /// [`status_reports`](crate::status_reports).
+    fn send_pb7_bundle_to_ws(&self, mut bundle: bp7::Bundle) {
+        let now = Utc::now();
+        let ledger_key = self
+            .state
+            .delivery_ledger
+            .as_ref()
+            .and_then(|_| DeliveryLedger::key(&bundle));
+        if let (Some(delivery_ledger), Some(key)) = (&self.state.delivery_ledger, &ledger_key) {
+            if delivery_ledger.is_duplicate(key, now) {
+                info!(
+                    source = %bundle.primary.source,
+                    "Bundle already delivered, duplicate suppressed"
+                );
+                return;
+            }
+        }
+        let requested_reports = self.state.status_reports.take_requested(&bundle);
+        let report = |status, reason| {
+            status_reports::send_report(&self.state, requested_reports.as_ref(), status, reason);
+        };
+        report(STATUS_RECEIVED, REASON_NO_INFORMATION);
+        if !self.state.late_delivery.admit(&bundle, now) {
+            info!(
+                source = %bundle.primary.source,
+                "Bundle past its lifetime, delivery suppressed"
+            );
+            report(STATUS_DELETED, REASON_LIFETIME_EXPIRED);
+            return;
+        }
+        // The payload of a compressed oversize bundle is still encoded with the payload profile
+        // it was submitted with.
+        let mut decoded = self.state.payload_codecs.decode(&mut bundle);
+        if let Ok(Some(profile)) = decoded {
+            if self.state.oversize_bundles.is_compression_profile(profile) {
+                decoded = self.state.payload_codecs.decode(&mut bundle);
+            }
+        }
+        if let Err(err) = decoded {
+            error!(
+                source = %bundle.primary.source,
+                "Failed to decode payload, delivered encoded: {err}"
+            );
+        }
+        let mut delivered = false;
+        if let Some(bundle_mailbox) = &self.state.bundle_mailbox {
+            bundle_mailbox.store(&bundle, now);
+            delivered = true;
+        }
         if self.state.bundles_to_ws.receiver_count() > 0 {
-            if let Err(e) = self.state.bundles_to_ws.send(bundle) {
-                error!(%e);
+            match self.state.bundles_to_ws.send(bundle) {
+                Ok(_) => delivered = true,
+                Err(e) => error!(%e),
             }
-        } else {
+        } else if !delivered {
             error!("No WS client connected, bundle dropped");
         }
+        if delivered {
+            report(STATUS_DELIVERED, REASON_NO_INFORMATION);
+            if let (Some(delivery_ledger), Some(key)) = (&self.state.delivery_ledger, ledger_key) {
+                delivery_ledger.record(key, now);
+            }
+        } else {
+            report(STATUS_DELETED, REASON_NO_INFORMATION);
+        }
     }
 }
@@ -1,6 +1,6 @@
 use crate::end_device_id::EndDeviceId;
 use crate::error::ReceiveBufferError;
-use crate::lorawan_protocol::{AnnouncementPayload, Fragment, GpsLocation};
+use lorawan_dtn_protocol::{AnnouncementPayload, Fragment, GpsLocation};
 use std::collections::btree_map::Entry;
 use std::collections::{BTreeMap, HashSet};
 
@@ -119,8 +119,8 @@ impl AnnouncementReceiveBuffer {
 #[cfg(test)]
 mod tests {
     use crate::end_device_id::EndDeviceId;
-    use crate::lorawan_protocol::{AnnouncementPayload, Fragment, GpsLocation};
     use crate::receive_buffers::announcement::AnnouncementReceiveBuffer;
+    use lorawan_dtn_protocol::{AnnouncementPayload, Fragment, GpsLocation};
 
     #[test]
     fn announcement_receive_buffer() {
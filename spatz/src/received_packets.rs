@@ -0,0 +1,121 @@
+//! Log of recently received protocol packets for research tooling.
+
+use crate::neighbor_table::SignalQuality;
+use chirpstack_gwb_integration::gateway_id::GatewayId;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::VecDeque;
+
+/// Amount of received packets kept in the log.
+pub const RECEIVED_PACKETS_LOG_SIZE: usize = 100;
+
+/// A received and successfully parsed protocol packet.
+#[derive(Debug, Serialize)]
+pub struct ReceivedPacket {
+    /// Time the packet was received.
+    pub received_at: DateTime<Utc>,
+    /// The gateway which received the packet.
+    pub gateway_id: GatewayId,
+    /// The signal quality of the uplink, if reported by the gateway.
+    pub signal_quality: Option<SignalQuality>,
+    /// The parsed packet, serialized as served by the API.
+    pub packet: serde_json::Value,
+}
+
+/// Bounded log of the most recently received packets, the oldest packets are dropped first.
+#[derive(Debug)]
+pub struct ReceivedPacketLog {
+    /// Received packets, oldest first.
+    packets: VecDeque<ReceivedPacket>,
+    /// Max amount of logged packets.
+    capacity: usize,
+}
+
+impl ReceivedPacketLog {
+    /// Creates a new empty [`ReceivedPacketLog`] holding at most `capacity` packets.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            packets: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Adds a received packet, dropping the oldest packet if the log is full.
+    pub fn push(&mut self, received_packet: ReceivedPacket) {
+        if self.capacity == 0 {
+            return;
+        }
+        while self.packets.len() >= self.capacity {
+            self.packets.pop_front();
+        }
+        self.packets.push_back(received_packet);
+    }
+
+    /// Returns the packets received after `since`, oldest first, limited to the `limit` most
+    /// recent packets.
+    pub fn query(
+        &self,
+        since: Option<DateTime<Utc>>,
+        limit: Option<usize>,
+    ) -> Vec<&ReceivedPacket> {
+        let matching: Vec<&ReceivedPacket> = self
+            .packets
+            .iter()
+            .filter(|received_packet| {
+                !since.is_some_and(|since| received_packet.received_at <= since)
+            })
+            .collect();
+        let skip = limit.map_or(0, |limit| matching.len().saturating_sub(limit));
+        matching.into_iter().skip(skip).collect()
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use crate::end_device_id::EndDeviceId;
+    use crate::received_packets::{ReceivedPacket, ReceivedPacketLog};
+    use chrono::{Duration, Utc};
+    use lorawan_dtn_protocol::{LoRaWanPacket, LocalAnnouncement};
+
+    fn packet(end_device_id: u32) -> serde_json::Value {
+        let announcement = LocalAnnouncement::new(None, vec![EndDeviceId(end_device_id)]);
+        serde_json::to_value(&announcement as &dyn LoRaWanPacket).unwrap()
+    }
+
+    fn received_packet(end_device_id: u32, age_seconds: i64) -> ReceivedPacket {
+        ReceivedPacket {
+            received_at: Utc::now() - Duration::seconds(age_seconds),
+            gateway_id: "a840411d25244150".parse().unwrap(),
+            signal_quality: None,
+            packet: packet(end_device_id),
+        }
+    }
+
+    #[test]
+    fn oldest_packets_dropped() {
+        let mut log = ReceivedPacketLog::new(2);
+        log.push(received_packet(1, 30));
+        log.push(received_packet(2, 20));
+        log.push(received_packet(3, 10));
+        let packets = log.query(None, None);
+        assert_eq!(packets.len(), 2);
+        assert_eq!(packets[0].packet, packet(2));
+    }
+
+    #[test]
+    fn query_since_and_limit() {
+        let mut log = ReceivedPacketLog::new(10);
+        log.push(received_packet(1, 30));
+        log.push(received_packet(2, 20));
+        log.push(received_packet(3, 10));
+        assert_eq!(
+            log.query(Some(Utc::now() - Duration::seconds(25)), None)
+                .len(),
+            2
+        );
+        let packets = log.query(None, Some(1));
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].packet, packet(3));
+    }
+}
@@ -1,21 +1,38 @@
 //! Routing algorithms.
 
 mod flooding;
+mod retransmission;
+mod tdma;
 
 pub use flooding::Flooding;
+pub use retransmission::{retransmit, DownlinkRetransmission};
+pub use tdma::TdmaCoordinator;
 
+use crate::configuration::DestinationClass;
+use crate::database::save_send_buffer_progress;
+use crate::duty_cycle_manager::calc_downlink_airtime;
 use crate::error::NextPacketFromSendBufferError;
 use crate::graceful_shutdown::ShutdownAgent;
-use crate::send_buffers::SendBuffer;
+use crate::neighbor_table::SignalQuality;
+use crate::oversize_bundles::SubBundleStatus;
+use crate::packet_cache::PacketSource;
+use crate::send_buffers::{SendBuffer, SendBufferProgress};
 use crate::AppState;
 use async_trait::async_trait;
 use chirpstack_gwb_integration::downlinks::downlink_builder::DownlinkBuilder;
 use chirpstack_gwb_integration::downlinks::downlink_item_builder::DownlinkItemBuilder;
 use chirpstack_gwb_integration::downlinks::predefined_parameters::{DataRate, Frequency};
-use chirpstack_gwb_integration::downlinks::{Downlink, DownlinkItem, ImmediatelyClassC};
+use chirpstack_gwb_integration::downlinks::{Downlink, DownlinkItem, ImmediatelyClassC, TxPath};
+use chirpstack_gwb_integration::error::EnqueueError;
+use chirpstack_gwb_integration::gateway_id::GatewayId;
+use futures_util::future::join_all;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use tokio::sync::MutexGuard;
-use tracing::info;
+use tracing::{error, info, trace, warn};
 
 /// Routing need to be a task running and update itself (async task spawned)
 ///
@@ -36,6 +53,172 @@ pub trait RoutingAlgorithm: Send + Sync {
     /// The routing algorithm should use the [`ShutdownAgent`] when performing asynchronous tasks
     /// outside of the `routing_task`.
     fn provide_shutdown_agent(&mut self, shutdown_agent: ShutdownAgent);
+    /// Returns whether a packet for another node received with the signal quality is relayed.
+    ///
+    /// The signal quality is `None` if the gateway did not report it.
+    fn relays(&self, _signal_quality: Option<SignalQuality>) -> bool {
+        true
+    }
+    /// Name of the routing algorithm used in the statistics.
+    fn name(&self) -> &'static str;
+}
+
+/// Restricts a routing algorithm to a set of destination classes and counts its sent packets.
+#[derive(Debug, Default)]
+pub struct RoutingScope {
+    /// Destination classes the routing algorithm is responsible for.
+    destination_classes: HashSet<DestinationClass>,
+    /// Sent relay packets.
+    relay_packets_sent: AtomicU64,
+    /// Sent bundle packets.
+    bundle_packets_sent: AtomicU64,
+    /// Sent announcements.
+    announcements_sent: AtomicU64,
+    /// Received relay packets not relayed due to their signal quality.
+    relays_skipped: AtomicU64,
+}
+
+/// Statistics of a routing algorithm.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct RoutingAlgorithmStats {
+    /// Name of the routing algorithm.
+    pub name: &'static str,
+    /// Destination classes the routing algorithm is responsible for.
+    pub destination_classes: Vec<DestinationClass>,
+    /// Sent relay packets.
+    pub relay_packets_sent: u64,
+    /// Sent bundle packets.
+    pub bundle_packets_sent: u64,
+    /// Sent announcements.
+    pub announcements_sent: u64,
+    /// Received relay packets not relayed due to their signal quality.
+    pub relays_skipped: u64,
+}
+
+impl RoutingScope {
+    /// Creates a new [`RoutingScope`] for the destination classes.
+    pub fn new(destination_classes: HashSet<DestinationClass>) -> Self {
+        Self {
+            destination_classes,
+            ..Self::default()
+        }
+    }
+
+    /// Returns whether the routing algorithm is responsible for the destination class.
+    pub fn handles(&self, destination_class: DestinationClass) -> bool {
+        self.destination_classes.contains(&destination_class)
+    }
+
+    /// Counts a packet of the destination class sent by the routing algorithm.
+    pub fn record_sent(&self, destination_class: DestinationClass) {
+        match destination_class {
+            DestinationClass::Relay => &self.relay_packets_sent,
+            DestinationClass::Bundle => &self.bundle_packets_sent,
+            DestinationClass::Announcement => &self.announcements_sent,
+        }
+        .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a received relay packet not relayed due to its signal quality.
+    pub fn record_relay_skipped(&self) {
+        self.relays_skipped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the statistics of the routing algorithm with the provided name.
+    fn stats(&self, name: &'static str) -> RoutingAlgorithmStats {
+        RoutingAlgorithmStats {
+            name,
+            destination_classes: DestinationClass::ALL
+                .into_iter()
+                .filter(|destination_class| self.handles(*destination_class))
+                .collect(),
+            relay_packets_sent: self.relay_packets_sent.load(Ordering::Relaxed),
+            bundle_packets_sent: self.bundle_packets_sent.load(Ordering::Relaxed),
+            announcements_sent: self.announcements_sent.load(Ordering::Relaxed),
+            relays_skipped: self.relays_skipped.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Runs multiple routing algorithms concurrently, each responsible for its own destination
+/// classes.
+pub struct RoutingDispatcher {
+    /// Routing algorithms with their scopes.
+    routing_algorithms: Vec<(Box<dyn RoutingAlgorithm>, Arc<RoutingScope>)>,
+}
+
+impl RoutingDispatcher {
+    /// Creates a new [`RoutingDispatcher`] for the routing algorithms and their scopes.
+    pub fn new(routing_algorithms: Vec<(Box<dyn RoutingAlgorithm>, Arc<RoutingScope>)>) -> Self {
+        Self { routing_algorithms }
+    }
+
+    /// Runs the routing tasks of all routing algorithms until all of them returned.
+    pub async fn routing_task(&self, state: Arc<AppState>, shutdown_agent: ShutdownAgent) {
+        join_all(
+            self.routing_algorithms
+                .iter()
+                .map(|(routing_algorithm, _)| {
+                    routing_algorithm.routing_task(state.clone(), shutdown_agent.clone())
+                }),
+        )
+        .await;
+    }
+
+    /// Returns whether the routing algorithm responsible for relay packets relays a packet
+    /// received with the signal quality.
+    pub fn relays(&self, signal_quality: Option<SignalQuality>) -> bool {
+        let Some((routing_algorithm, scope)) = self
+            .routing_algorithms
+            .iter()
+            .find(|(_, scope)| scope.handles(DestinationClass::Relay))
+        else {
+            return true;
+        };
+        let relays = routing_algorithm.relays(signal_quality);
+        if !relays {
+            scope.record_relay_skipped();
+        }
+        relays
+    }
+
+    /// Returns the statistics of all routing algorithms.
+    pub fn stats(&self) -> Vec<RoutingAlgorithmStats> {
+        self.routing_algorithms
+            .iter()
+            .map(|(routing_algorithm, scope)| scope.stats(routing_algorithm.name()))
+            .collect()
+    }
+}
+
+/// Hands the downlink for the gateway to the runtime without waiting. If the MQTT request queue
+/// is full, waits until it has room instead of dropping the downlink.
+///
+/// Returns whether the downlink was handed over. Downlinks are not handed over while the broker
+/// is disconnected, or if they cannot be transmitted or serialized at all.
+pub async fn enqueue_downlink(
+    state: &AppState,
+    gateway: &GatewayId,
+    downlink: Downlink<ImmediatelyClassC>,
+) -> bool {
+    let result = match state.runtime.try_enqueue(gateway, downlink.clone()) {
+        Err(EnqueueError::BrokerBackPressure) => {
+            trace!("MQTT request queue is full, waiting to enqueue for gateway: {gateway}");
+            state.runtime.enqueue(gateway, downlink).await
+        }
+        result => result,
+    };
+    match result {
+        Ok(()) => true,
+        Err(err) if err.is_transient() => {
+            warn!("Downlink for gateway {gateway} not enqueued: {err}");
+            false
+        }
+        Err(err) => {
+            error!("Dropping downlink for gateway {gateway}: {err}");
+            false
+        }
+    }
 }
 
 /// Create a [`DownlinkItem<ImmediatelyClassC>`].
@@ -61,52 +244,116 @@ fn create_downlink_item(
         .build()
 }
 
-/// Create a [`Downlink<ImmediatelyClassC>`].
+/// Create a [`Downlink<ImmediatelyClassC>`] offering the item on every board and antenna of
+/// `tx_paths`, a single item if there are none.
 ///
 /// # Errors
 ///
 /// Returns an error if the downlink builder encountered an error.
 fn create_downlink(
-    gateway_id: String,
+    gateway_id: GatewayId,
     downlink_id: u32,
     item: DownlinkItem<ImmediatelyClassC>,
+    tx_paths: &[TxPath],
 ) -> Result<Downlink<ImmediatelyClassC>, chirpstack_gwb_integration::error::DownlinkBuilderError> {
     DownlinkBuilder::new()
         .gateway_id(gateway_id)
         .downlink_id(downlink_id)
-        .add_item(item)
+        .add_item_on_tx_paths(item, tx_paths)
         .build()
 }
 
 /// Process a send buffer queue. If a payload is available, the payload is processed by the
-/// [`process_next_packet`] function.
+/// [`process_next_packet`] function. Frozen send buffers are skipped, transfers in progress may be
+/// preempted, see
+/// [`select_send_buffer`](crate::packet_queue_manager::QueueManager::select_send_buffer). The
+/// airtime of the payload is accounted to the API client which submitted it and the progress of
+/// the send buffers is persisted. Send buffers whose
+/// [`RoutingHints`](crate::routing_hints::RoutingHints) expired are removed. Writes the payload
+/// into the cleared `phy_payload` and returns the preferred gateway of the send buffer.
 ///
 /// # Errors
 ///
 /// Returns an error if:
 /// - the send buffer does not contain any more fragments.
-/// - there is no send buffer in the queue.
+/// - there is no send buffer in the queue which is not frozen.
 /// - the [`process_next_packet`] function returned an error.
 async fn get_next_payload_from_send_buffer_queue(
     mut send_buffer_vec: MutexGuard<'_, Vec<impl SendBuffer>>,
     data_rate: DataRate,
     state: &Arc<AppState>,
-) -> Result<Vec<u8>, NextPacketFromSendBufferError> {
-    if let Some(entry_ref) = send_buffer_vec.first_mut() {
+    phy_payload: &mut Vec<u8>,
+) -> Result<Option<GatewayId>, NextPacketFromSendBufferError> {
+    let now = state.clock.now();
+    send_buffer_vec.retain(|send_buffer| {
+        let expired = send_buffer
+            .routing_hints()
+            .is_some_and(|routing_hints| routing_hints.is_expired(now));
+        if expired {
+            info!("Dropping send buffer as its routing hints expired");
+            if let Some(key) = send_buffer
+                .progress()
+                .as_ref()
+                .and_then(SendBufferProgress::sub_bundle_key)
+            {
+                state.oversize_bundles.update(key, SubBundleStatus::Dropped);
+            }
+        }
+        !expired
+    });
+    let next_index = state.queue_manager.select_send_buffer(&send_buffer_vec);
+    if let Some(index) = next_index {
+        let entry_ref = &mut send_buffer_vec[index];
         if entry_ref.is_empty() {
-            send_buffer_vec.pop();
+            send_buffer_vec.remove(index);
             let err = NextPacketFromSendBufferError::NoRemainingFragments;
             info!(%err);
             Err(err)
         } else {
             let lorawan_packet = entry_ref.next_packet(data_rate)?;
-            // Remove empty send buffers after the last packet has been produced.
-            if entry_ref.is_empty() {
-                send_buffer_vec.pop();
+            let client = entry_ref.client().map(ToOwned::to_owned);
+            let preferred_gateway = entry_ref
+                .routing_hints()
+                .and_then(|routing_hints| routing_hints.preferred_gateway.clone());
+            // Remove empty send buffers after the last packet has been produced, their progress
+            // is persisted once more to not resend them after a restart.
+            let completed = if entry_ref.is_empty() {
+                send_buffer_vec.remove(index).progress()
+            } else {
+                None
+            };
+            if let Some(key) = completed
+                .as_ref()
+                .and_then(SendBufferProgress::sub_bundle_key)
+            {
+                state.oversize_bundles.update(key, SubBundleStatus::Sent);
+            }
+            let progress: Vec<SendBufferProgress> = send_buffer_vec
+                .iter()
+                .filter_map(SendBuffer::progress)
+                .chain(completed)
+                .collect();
+            drop(send_buffer_vec);
+            if !progress.is_empty() {
+                save_send_buffer_progress(state, &progress).await;
             }
-            let phy_payload = lorawan_packet.convert_to_lorawan_phy_payload();
-            state.packet_cache.insert(&phy_payload).await?;
-            Ok(phy_payload)
+            phy_payload.clear();
+            lorawan_packet.write_phy_payload(phy_payload);
+            state
+                .packet_cache
+                .insert(phy_payload, PacketSource::Local)
+                .await?;
+            if let Some(client) = client {
+                let airtime_ms = calc_downlink_airtime(
+                    u32::try_from(phy_payload.len()).unwrap_or(u32::MAX),
+                    data_rate,
+                );
+                state
+                    .client_airtime
+                    .record(&client, airtime_ms, state.clock.now())
+                    .await;
+            }
+            Ok(preferred_gateway)
         }
     } else {
         let err = NextPacketFromSendBufferError::NoSendBufferInQueue;
@@ -114,3 +361,32 @@ async fn get_next_payload_from_send_buffer_queue(
         Err(err)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::configuration::DestinationClass;
+    use crate::routing::RoutingScope;
+
+    #[test]
+    fn routing_scope_counts_sent_packets_per_class() {
+        let scope = RoutingScope::new(
+            [DestinationClass::Announcement, DestinationClass::Relay]
+                .into_iter()
+                .collect(),
+        );
+        assert!(scope.handles(DestinationClass::Relay));
+        assert!(!scope.handles(DestinationClass::Bundle));
+
+        scope.record_sent(DestinationClass::Announcement);
+        scope.record_sent(DestinationClass::Announcement);
+        scope.record_sent(DestinationClass::Relay);
+        let stats = scope.stats("Flooding");
+        assert_eq!(
+            stats.destination_classes,
+            vec![DestinationClass::Relay, DestinationClass::Announcement]
+        );
+        assert_eq!(stats.relay_packets_sent, 1);
+        assert_eq!(stats.bundle_packets_sent, 0);
+        assert_eq!(stats.announcements_sent, 2);
+    }
+}
@@ -1,42 +1,174 @@
 //! Flooding routing algorithm.
 
+use crate::configuration::{AirtimePacingConfig, DestinationClass, RelaySignalPolicy};
+use crate::duty_cycle_manager::calc_downlink_airtime;
 use crate::error::NextPacketFromSendBufferError;
+use crate::gateway_send_queues::GatewaySendQueues;
 use crate::graceful_shutdown::ShutdownAgent;
+use crate::neighbor_table::SignalQuality;
+use crate::path_cache::CachedPath;
 use crate::routing::{
-    create_downlink, create_downlink_item, get_next_payload_from_send_buffer_queue,
-    RoutingAlgorithm,
+    create_downlink, create_downlink_item, enqueue_downlink,
+    get_next_payload_from_send_buffer_queue, RoutingAlgorithm, RoutingScope, TdmaCoordinator,
 };
+use crate::unicast::UnicastTarget;
+use crate::watchdog::{HEARTBEAT_INTERVAL, ROUTING};
 use crate::AppState;
 use async_trait::async_trait;
 use chirpstack_gwb_integration::downlinks::predefined_parameters::{DataRate, Frequency};
-use rand::Rng;
+use chirpstack_gwb_integration::downlinks::{Downlink, DownlinkItem, ImmediatelyClassC};
+use chirpstack_gwb_integration::gateway_id::GatewayId;
+use chrono::Utc;
+use lorawan_dtn_protocol::{LoRaWanPacket, MAX_PHY_PAYLOAD_SIZE};
 use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 use tracing::{error, instrument, trace};
 
 /// The flooding routing algorithm.
 pub struct Flooding {
     /// The delay betweens send operations.
-    delay_between_sends: std::time::Duration,
+    delay_between_sends: Duration,
+    /// Pacing derived from the airtime of the previous transmission, `delay_between_sends` is
+    /// used if `None`.
+    airtime_pacing: Option<AirtimePacingConfig>,
+    /// Signal thresholds for relaying, every packet is relayed if `None`.
+    relay_signal_policy: Option<RelaySignalPolicy>,
+    /// TDMA coordinator, sends are unslotted if `None`.
+    tdma_coordinator: Option<TdmaCoordinator>,
+    /// Destination classes handled by this instance and its statistics.
+    scope: Arc<RoutingScope>,
+    /// Frequencies the announcements rotate across, the default frequency is used if empty.
+    announcement_frequencies: Vec<Frequency>,
 }
 
 impl Flooding {
-    /// Create a new [`Flooding`].
-    pub fn new(delay_between_sends: std::time::Duration) -> Self {
+    /// Create a new [`Flooding`] sending only packets of the destination classes in `scope`.
+    pub fn new(
+        delay_between_sends: Duration,
+        airtime_pacing: Option<AirtimePacingConfig>,
+        relay_signal_policy: Option<RelaySignalPolicy>,
+        tdma_coordinator: Option<TdmaCoordinator>,
+        scope: Arc<RoutingScope>,
+        announcement_frequencies: Vec<Frequency>,
+    ) -> Self {
         Self {
             delay_between_sends,
+            airtime_pacing,
+            relay_signal_policy,
+            tdma_coordinator,
+            scope,
+            announcement_frequencies,
         }
     }
 
-    /// Sends the payload from every gateway connected to the ChirpStack.
+    /// Returns the frequency of the announcement sent after `sent_announcements` announcements,
+    /// rotating across the announcement frequencies, `default` if none are configured.
+    fn announcement_frequency(&self, sent_announcements: usize, default: Frequency) -> Frequency {
+        self.announcement_frequencies
+            .get(sent_announcements % self.announcement_frequencies.len().max(1))
+            .copied()
+            .unwrap_or(default)
+    }
+
+    /// Returns the delay until the next send opportunity after sending a phy payload of
+    /// `phy_payload_len` bytes at the data rate, `None` if nothing was sent.
+    fn delay_after(&self, sent: Option<(usize, DataRate)>) -> Duration {
+        let Some(airtime_pacing) = &self.airtime_pacing else {
+            return self.delay_between_sends;
+        };
+        let min_gap = Duration::from_millis(airtime_pacing.min_gap_ms);
+        let Some((phy_payload_len, data_rate)) = sent else {
+            return min_gap;
+        };
+        let airtime_ms = calc_downlink_airtime(
+            u32::try_from(phy_payload_len).unwrap_or(u32::MAX),
+            data_rate,
+        );
+        Duration::from_secs_f64(airtime_ms * f64::from(airtime_pacing.airtime_factor) / 1000.0)
+            .max(min_gap)
+    }
+
+    /// Returns the delay until the next send opportunity, the minimum gap of the gateway send
+    /// queues if they are used as they pace every gateway on their own.
+    fn next_delay(&self, state: &AppState, sent: Option<(usize, DataRate)>) -> Duration {
+        state
+            .gateway_send_queues
+            .as_ref()
+            .map_or_else(|| self.delay_after(sent), GatewaySendQueues::min_gap)
+    }
+
+    /// Waits `delay` or for the next slot if TDMA is used.
+    ///
+    /// Returns the start of the own slot if TDMA is used.
+    async fn await_send_opportunity(&self, delay: Duration) -> Option<SystemTime> {
+        if let Some(tdma_coordinator) = &self.tdma_coordinator {
+            Some(tdma_coordinator.await_next_slot().await)
+        } else {
+            tokio::time::sleep(delay).await;
+            None
+        }
+    }
+
+    /// Returns the start of the own slot to send in without waiting if TDMA is used, the slot of
+    /// the previous send opportunity may already have passed.
+    fn send_opportunity_without_delay(&self, now: SystemTime) -> Option<SystemTime> {
+        self.tdma_coordinator
+            .map(|tdma_coordinator| tdma_coordinator.upcoming_slot_start(now))
+    }
+
+    /// Enqueues the downlink to be sent in the slot starting at `slot_start`.
+    ///
+    /// Gateways without GPS timing send immediately if `fallback_to_unslotted` is set. GPS
+    /// timing is taken from the gateway capabilities, detected from the uplinks if unknown.
+    async fn enqueue_slotted(
+        state: &Arc<AppState>,
+        gateway: &GatewayId,
+        downlink: Downlink<ImmediatelyClassC>,
+        slot_start: SystemTime,
+        fallback_to_unslotted: bool,
+    ) {
+        let supports_gps_timing = match state
+            .gateway_ids_manager
+            .capabilities(gateway)
+            .await
+            .class_b_timing
+        {
+            Some(class_b_timing) => class_b_timing,
+            None => state
+                .runtime
+                .gateway_time(gateway)
+                .await
+                .is_some_and(|gateway_time| gateway_time.supports_gps_timing()),
+        };
+        if !supports_gps_timing && fallback_to_unslotted {
+            trace!("Gateway {gateway} does not support GPS timing, sending unslotted");
+            enqueue_downlink(state, gateway, downlink).await;
+        } else if let Err(err) = state
+            .runtime
+            .enqueue_at(gateway, downlink, slot_start)
+            .await
+        {
+            error!(%err);
+        }
+    }
+
+    /// Sends the payload from the gateways connected to the ChirpStack selected by the
+    /// [`GatewaySelector`](crate::gateway_selection::GatewaySelector). Gateways which cannot
+    /// transmit at the data rate according to their capabilities are skipped.
+    ///
+    /// The payload is sent in the slot starting at `slot_start` if TDMA is used, otherwise it is
+    /// handed to the gateway send queues if configured and retransmitted if it fails.
     #[instrument(skip_all)]
     async fn flooding(
         state: Arc<AppState>,
         payload: Vec<u8>,
         data_rate: DataRate,
         frequency: Frequency,
+        slot_start: Option<SystemTime>,
+        fallback_to_unslotted: bool,
     ) {
         trace!("Creating downlink item");
-        let downlink_item = match create_downlink_item(payload, frequency, data_rate) {
+        let downlink_item = match create_downlink_item(payload.clone(), frequency, data_rate) {
             Ok(downlink_item) => downlink_item,
             Err(err) => {
                 error!(%err);
@@ -44,12 +176,41 @@ impl Flooding {
             }
         };
 
-        trace!("Iterating over gateways");
-        for gateway in state.gateway_ids_manager.gateway_ids.lock().await.iter() {
+        trace!("Selecting gateways");
+        let gateway_ids = state
+            .gateway_selector
+            .lock()
+            .await
+            .select(&*state.gateway_ids_manager.gateway_ids.lock().await);
+        for gateway in &gateway_ids {
+            if !state
+                .gateway_ids_manager
+                .capabilities(gateway)
+                .await
+                .supports_data_rate(data_rate)
+            {
+                trace!("Gateway {gateway} cannot transmit at {data_rate:?}, skipping");
+                continue;
+            }
+            let Some(slot_start) = slot_start else {
+                Self::send_unslotted(
+                    &state,
+                    gateway,
+                    &downlink_item,
+                    &payload,
+                    data_rate,
+                    frequency,
+                    "flooding",
+                )
+                .await;
+                continue;
+            };
+            let downlink_id = state.runtime.next_downlink_id(gateway, "flooding").await;
             let downlink = match create_downlink(
                 gateway.clone(),
-                rand::thread_rng().gen(),
+                downlink_id,
                 downlink_item.clone(),
+                state.gateway_ids_manager.tx_paths(gateway),
             ) {
                 Ok(downlink) => downlink,
                 Err(err) => {
@@ -58,9 +219,235 @@ impl Flooding {
                 }
             };
             trace!("Enqueuing downlink for gateway: {gateway}");
-            if let Err(err) = state.runtime.try_enqueue(gateway, downlink) {
+            state
+                .gateway_selector
+                .lock()
+                .await
+                .record_enqueued(gateway, downlink_id);
+            Self::enqueue_slotted(&state, gateway, downlink, slot_start, fallback_to_unslotted)
+                .await;
+        }
+    }
+
+    /// Sends the downlink item from the gateway right away, handed to the gateway send queues if
+    /// configured and retransmitted if it fails.
+    ///
+    /// Returns whether the downlink was handed over.
+    async fn send_unslotted(
+        state: &Arc<AppState>,
+        gateway: &GatewayId,
+        downlink_item: &DownlinkItem<ImmediatelyClassC>,
+        payload: &[u8],
+        data_rate: DataRate,
+        frequency: Frequency,
+        subsystem: &str,
+    ) -> bool {
+        let downlink_id = state.runtime.next_downlink_id(gateway, subsystem).await;
+        let downlink = match create_downlink(
+            gateway.clone(),
+            downlink_id,
+            downlink_item.clone(),
+            state.gateway_ids_manager.tx_paths(gateway),
+        ) {
+            Ok(downlink) => downlink,
+            Err(err) => {
                 error!(%err);
+                return false;
+            }
+        };
+        state
+            .downlink_retransmission
+            .record_sent(gateway, downlink_id, payload.to_vec(), data_rate, frequency)
+            .await;
+        if let Some(gateway_send_queues) = &state.gateway_send_queues {
+            trace!("Queuing downlink for gateway: {gateway}");
+            gateway_send_queues.enqueue(gateway.clone(), downlink_id, downlink, payload.to_vec());
+            return true;
+        }
+        trace!("Enqueuing downlink for gateway: {gateway}");
+        state
+            .gateway_selector
+            .lock()
+            .await
+            .record_enqueued(gateway, downlink_id);
+        enqueue_downlink(state, gateway, downlink).await
+    }
+
+    /// Sends the payload once from the gateway which received the announcement of the
+    /// destination and waits for the acknowledgement of the neighbor.
+    ///
+    /// Returns whether the payload was acknowledged, it has to be flooded otherwise.
+    async fn unicast(
+        state: &Arc<AppState>,
+        payload: &[u8],
+        target: &UnicastTarget,
+        frequency: Frequency,
+    ) -> bool {
+        let Some(unicast) = &state.unicast else {
+            return false;
+        };
+        let gateway = &target.gateway_id;
+        let connected = state
+            .gateway_ids_manager
+            .gateway_ids
+            .lock()
+            .await
+            .contains(gateway);
+        if !connected
+            || !state
+                .gateway_ids_manager
+                .capabilities(gateway)
+                .await
+                .supports_data_rate(target.data_rate)
+        {
+            trace!("Gateway {gateway} of the direct neighbor is unavailable, flooding");
+            return false;
+        }
+        let downlink_item =
+            match create_downlink_item(payload.to_vec(), frequency, target.data_rate) {
+                Ok(downlink_item) => downlink_item,
+                Err(err) => {
+                    error!(%err);
+                    return false;
+                }
             };
+        trace!(
+            "Sending to direct neighbor {:?} via gateway {gateway} at {:?}",
+            target.destination,
+            target.data_rate
+        );
+        let ack_rx = unicast.register(target.destination, payload).await;
+        if !Self::send_unslotted(
+            state,
+            gateway,
+            &downlink_item,
+            payload,
+            target.data_rate,
+            frequency,
+            "unicast",
+        )
+        .await
+        {
+            unicast.cancel(target.destination, payload).await;
+            return false;
+        }
+        if unicast.await_ack(ack_rx, target.destination, payload).await {
+            trace!("Direct neighbor acknowledged the packet");
+            true
+        } else {
+            trace!("No acknowledgement of the direct neighbor, flooding");
+            false
+        }
+    }
+
+    /// Sends the payload once from the gateway of the learned path to its destination.
+    ///
+    /// Returns whether the payload was handed to the gateway, it has to be flooded otherwise.
+    async fn directed(
+        state: &Arc<AppState>,
+        payload: &[u8],
+        path: &CachedPath,
+        data_rate: DataRate,
+        frequency: Frequency,
+    ) -> bool {
+        let Some(path_cache) = &state.path_cache else {
+            return false;
+        };
+        let gateway = &path.gateway_id;
+        trace!(
+            "Sending to {:?} via gateway {gateway} of the learned path",
+            path.destination
+        );
+        let handed_over =
+            Self::via_gateway(state, gateway, payload, data_rate, frequency, "path_cache").await;
+        if handed_over {
+            path_cache.record_directed(path.destination).await;
+        } else {
+            trace!("Gateway {gateway} of the learned path is unavailable, flooding");
+            path_cache.record_failure(path.destination).await;
+        }
+        handed_over
+    }
+
+    /// Sends the payload once from the gateway preferred by the client which submitted it.
+    ///
+    /// Returns whether the payload was handed to the gateway, it has to be routed as usual
+    /// otherwise.
+    async fn preferred(
+        state: &Arc<AppState>,
+        payload: &[u8],
+        gateway: &GatewayId,
+        data_rate: DataRate,
+        frequency: Frequency,
+    ) -> bool {
+        trace!("Sending via preferred gateway {gateway}");
+        let handed_over = Self::via_gateway(
+            state,
+            gateway,
+            payload,
+            data_rate,
+            frequency,
+            "routing_hints",
+        )
+        .await;
+        if !handed_over {
+            trace!("Preferred gateway {gateway} is unavailable, routing as usual");
+        }
+        handed_over
+    }
+
+    /// Sends the payload unslotted from the gateway if it is connected and supports the data
+    /// rate, returns whether the payload was handed to the gateway.
+    async fn via_gateway(
+        state: &Arc<AppState>,
+        gateway: &GatewayId,
+        payload: &[u8],
+        data_rate: DataRate,
+        frequency: Frequency,
+        subsystem: &str,
+    ) -> bool {
+        let connected = state
+            .gateway_ids_manager
+            .gateway_ids
+            .lock()
+            .await
+            .contains(gateway);
+        connected
+            && state
+                .gateway_ids_manager
+                .capabilities(gateway)
+                .await
+                .supports_data_rate(data_rate)
+            && match create_downlink_item(payload.to_vec(), frequency, data_rate) {
+                Ok(downlink_item) => {
+                    Self::send_unslotted(
+                        state,
+                        gateway,
+                        &downlink_item,
+                        payload,
+                        data_rate,
+                        frequency,
+                        subsystem,
+                    )
+                    .await
+                }
+                Err(err) => {
+                    error!(%err);
+                    false
+                }
+            }
+    }
+
+    /// Returns the learned path to the destination of the payload, `None` if the path cache is
+    /// not configured or the payload is sent in a TDMA slot.
+    async fn learned_path(
+        state: &AppState,
+        payload: &[u8],
+        slot_start: Option<SystemTime>,
+    ) -> Option<CachedPath> {
+        match (&state.path_cache, slot_start) {
+            (Some(path_cache), None) => path_cache.path(payload, Utc::now()).await,
+            _ => None,
         }
     }
 }
@@ -72,27 +459,84 @@ impl RoutingAlgorithm for Flooding {
         // Hardcoded data rate and frequency
         let data_rate = DataRate::Eu863_870Dr3;
         let frequency = Frequency::Freq868_3;
+        let fallback_to_unslotted = self
+            .tdma_coordinator
+            .is_some_and(|tdma_coordinator| tdma_coordinator.fallback_to_unslotted());
+        // Start of the own slot of the current send opportunity if TDMA is used.
+        let mut slot_start = None;
         // If we encounter an error before we send, we want to be able to skip the delay to not miss
         // a send opportunity.
         let mut skip_delay = false;
+        // Delay until the next send opportunity if TDMA is not used.
+        let mut delay = self.delay_between_sends;
+        // Amount of sent announcements to rotate their frequency.
+        let mut sent_announcements: usize = 0;
+        let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+        // Reused for every sent packet, the spawned send task gets a copy of the exact size.
+        let mut phy_payload = Vec::with_capacity(MAX_PHY_PAYLOAD_SIZE);
 
         loop {
+            state.watchdog.beat(ROUTING);
             if skip_delay {
                 trace!("Skipping delay");
                 skip_delay = false;
+                slot_start = self.send_opportunity_without_delay(SystemTime::now());
             } else {
                 trace!("Starting sleep");
-                tokio::select! {
-                    _ = tokio::time::sleep(self.delay_between_sends) => {},
-                    _ = shutdown_agent.await_shutdown() => {
-                        trace!("Shutting down");
-                        return
-                    }
-                };
+                let send_opportunity = self.await_send_opportunity(delay);
+                tokio::pin!(send_opportunity);
+                loop {
+                    tokio::select! {
+                        next_slot_start = &mut send_opportunity => {
+                            slot_start = next_slot_start;
+                            break;
+                        },
+                        _ = heartbeat.tick() => state.watchdog.beat(ROUTING),
+                        _ = shutdown_agent.await_shutdown() => {
+                            trace!("Shutting down");
+                            return
+                        }
+                    };
+                }
                 trace!("Ending sleep");
             }
 
+            if state.radio_silence.is_active().await {
+                state.watchdog.idle(ROUTING);
+                if !state
+                    .radio_silence
+                    .wait_until_over(&mut shutdown_agent)
+                    .await
+                {
+                    trace!("Shutting down");
+                    return;
+                }
+                // The send opportunity passed while waiting.
+                continue;
+            }
+
+            let Some(frequency) = state
+                .frequency_lockouts
+                .select_frequency(frequency, Utc::now())
+            else {
+                trace!("All frequencies locked out");
+                continue;
+            };
+
+            if let Some(gateway_send_queues) = &state.gateway_send_queues {
+                if !gateway_send_queues
+                    .has_capacity(&*state.gateway_ids_manager.gateway_ids.lock().await)
+                    .await
+                {
+                    trace!("Gateway send queues full");
+                    delay = gateway_send_queues.min_gap();
+                    continue;
+                }
+            }
+
             // relay packets
+            if self.scope.handles(DestinationClass::Relay)
+                && !state.subsystem_control.relaying_paused()
             {
                 trace!("Checking for relay packets");
 
@@ -100,10 +544,33 @@ impl RoutingAlgorithm for Flooding {
                     state.queue_manager.relay_packet_queue.lock().await.pop()
                 {
                     trace!("Spawning flooding task with payload");
+                    self.scope.record_sent(DestinationClass::Relay);
+                    phy_payload.clear();
+                    relay_packet.write_phy_payload(&mut phy_payload);
+                    delay = self.next_delay(&state, Some((phy_payload.len(), data_rate)));
+                    if let Some(packet_export) = &state.packet_export {
+                        packet_export.record_sent(&phy_payload, data_rate, Utc::now());
+                    }
+                    let path = Self::learned_path(&state, &phy_payload, slot_start).await;
                     let state_clone = state.clone();
-                    let payload = relay_packet.convert_to_lorawan_phy_payload();
+                    let payload = phy_payload.clone();
                     tokio::spawn(async move {
-                        Self::flooding(state_clone, payload, data_rate, frequency).await;
+                        if let Some(path) = path {
+                            if Self::directed(&state_clone, &payload, &path, data_rate, frequency)
+                                .await
+                            {
+                                return;
+                            }
+                        }
+                        Self::flooding(
+                            state_clone,
+                            payload,
+                            data_rate,
+                            frequency,
+                            slot_start,
+                            fallback_to_unslotted,
+                        )
+                        .await;
                     });
 
                     continue;
@@ -111,20 +578,93 @@ impl RoutingAlgorithm for Flooding {
             }
 
             // Next bundle fragment payload
-            {
+            if self.scope.handles(DestinationClass::Bundle) {
                 trace!("Checking for bundle fragment");
 
                 match get_next_payload_from_send_buffer_queue(
                     state.queue_manager.bundle_send_buffer_queue.lock().await,
                     data_rate,
                     &state,
+                    &mut phy_payload,
                 )
                 .await
                 {
-                    Ok(payload) => {
+                    Ok(preferred_gateway) => {
+                        self.scope.record_sent(DestinationClass::Bundle);
+                        // Like learned paths, preferred gateways are only used for unslotted sends.
+                        let preferred_gateway = preferred_gateway.filter(|_| slot_start.is_none());
+                        delay = self.next_delay(&state, Some((phy_payload.len(), data_rate)));
+                        if let Some(packet_export) = &state.packet_export {
+                            packet_export.record_sent(&phy_payload, data_rate, Utc::now());
+                        }
+                        let unicast_target = match (&state.unicast, slot_start) {
+                            (Some(unicast), None) => {
+                                let gateway_capabilities =
+                                    state.gateway_ids_manager.all_capabilities().await;
+                                // Distrusted neighbors are not sent to directly, the packet is flooded.
+                                unicast
+                                    .target(
+                                        &*state.neighbor_table.lock().await,
+                                        &gateway_capabilities,
+                                        &phy_payload,
+                                        data_rate,
+                                    )
+                                    .filter(|target| {
+                                        state
+                                            .neighbor_trust
+                                            .is_trusted(target.destination, Utc::now())
+                                    })
+                            }
+                            _ => None,
+                        };
+                        let path = if unicast_target.is_none() {
+                            Self::learned_path(&state, &phy_payload, slot_start).await
+                        } else {
+                            None
+                        };
                         let state_clone = state.clone();
+                        let payload = phy_payload.clone();
                         tokio::spawn(async move {
-                            Self::flooding(state_clone, payload, data_rate, frequency).await;
+                            if let Some(gateway) = preferred_gateway {
+                                if Self::preferred(
+                                    &state_clone,
+                                    &payload,
+                                    &gateway,
+                                    data_rate,
+                                    frequency,
+                                )
+                                .await
+                                {
+                                    return;
+                                }
+                            }
+                            if let Some(target) = unicast_target {
+                                if Self::unicast(&state_clone, &payload, &target, frequency).await {
+                                    return;
+                                }
+                            }
+                            if let Some(path) = path {
+                                if Self::directed(
+                                    &state_clone,
+                                    &payload,
+                                    &path,
+                                    data_rate,
+                                    frequency,
+                                )
+                                .await
+                                {
+                                    return;
+                                }
+                            }
+                            Self::flooding(
+                                state_clone,
+                                payload,
+                                data_rate,
+                                frequency,
+                                slot_start,
+                                fallback_to_unslotted,
+                            )
+                            .await;
                         });
 
                         continue;
@@ -136,9 +676,212 @@ impl RoutingAlgorithm for Flooding {
                     }
                 }
             }
+
+            // Local and reachability announcements
+            if self.scope.handles(DestinationClass::Announcement)
+                && !state.subsystem_control.announcements_paused()
+            {
+                trace!("Checking for announcements");
+
+                if let Some(announcement) =
+                    state.queue_manager.announcement_queue.lock().await.pop()
+                {
+                    self.scope.record_sent(DestinationClass::Announcement);
+                    phy_payload.clear();
+                    announcement.write_phy_payload(&mut phy_payload);
+                    delay = self.next_delay(&state, Some((phy_payload.len(), data_rate)));
+                    if let Some(packet_export) = &state.packet_export {
+                        packet_export.record_sent(&phy_payload, data_rate, Utc::now());
+                    }
+                    let announcement_frequency = state
+                        .frequency_lockouts
+                        .select_frequency(
+                            self.announcement_frequency(sent_announcements, frequency),
+                            Utc::now(),
+                        )
+                        .unwrap_or(frequency);
+                    sent_announcements = sent_announcements.wrapping_add(1);
+                    let state_clone = state.clone();
+                    let payload = phy_payload.clone();
+                    tokio::spawn(async move {
+                        Self::flooding(
+                            state_clone,
+                            payload,
+                            data_rate,
+                            announcement_frequency,
+                            slot_start,
+                            fallback_to_unslotted,
+                        )
+                        .await;
+                    });
+
+                    continue;
+                }
+            }
+
+            delay = self.next_delay(&state, None);
         }
     }
 
     /// Not used.
     fn provide_shutdown_agent(&mut self, _shutdown_agent: ShutdownAgent) {}
+
+    fn relays(&self, signal_quality: Option<SignalQuality>) -> bool {
+        match (&self.relay_signal_policy, signal_quality) {
+            (Some(relay_signal_policy), Some(signal_quality)) => {
+                relay_signal_policy.relays(signal_quality)
+            }
+            _ => true,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "Flooding"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::configuration::{AirtimePacingConfig, RelaySignalPolicy};
+    use crate::neighbor_table::SignalQuality;
+    use crate::routing::{Flooding, RoutingAlgorithm, RoutingScope, TdmaCoordinator};
+    use chirpstack_gwb_integration::downlinks::predefined_parameters::{DataRate, Frequency};
+    use std::sync::Arc;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn skipped_delay_uses_upcoming_slot() {
+        let flooding = Flooding::new(
+            Duration::from_secs(10),
+            None,
+            None,
+            Some(TdmaCoordinator::new(1000, 10, 3, false)),
+            Arc::new(RoutingScope::default()),
+            Vec::new(),
+        );
+        // The slot at 103 s passed, downlinks handed over at 103.8 s reach the slot at 113 s.
+        assert_eq!(
+            flooding.send_opportunity_without_delay(
+                SystemTime::UNIX_EPOCH + Duration::from_millis(103_800)
+            ),
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(113))
+        );
+        // Too late for the slot at 103 s, considering the scheduling lead time.
+        assert_eq!(
+            flooding.send_opportunity_without_delay(
+                SystemTime::UNIX_EPOCH + Duration::from_millis(102_800)
+            ),
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(113))
+        );
+        assert_eq!(
+            flooding
+                .send_opportunity_without_delay(SystemTime::UNIX_EPOCH + Duration::from_secs(102)),
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(103))
+        );
+
+        let unslotted = Flooding::new(
+            Duration::from_secs(10),
+            None,
+            None,
+            None,
+            Arc::new(RoutingScope::default()),
+            Vec::new(),
+        );
+        assert_eq!(
+            unslotted.send_opportunity_without_delay(SystemTime::now()),
+            None
+        );
+    }
+
+    #[test]
+    fn airtime_pacing_depends_on_data_rate() {
+        let flooding = Flooding::new(
+            Duration::from_secs(10),
+            Some(AirtimePacingConfig {
+                airtime_factor: 99,
+                min_gap_ms: 500,
+            }),
+            None,
+            None,
+            Arc::new(RoutingScope::default()),
+            Vec::new(),
+        );
+        let sf7_delay = flooding.delay_after(Some((20, DataRate::Eu863_870Dr5)));
+        let sf12_delay = flooding.delay_after(Some((20, DataRate::Eu863_870Dr0)));
+        // About 51 ms airtime at SF7 and 1.3 s at SF12.
+        assert!(sf7_delay < Duration::from_secs(10));
+        assert!(sf12_delay > Duration::from_secs(100));
+        assert_eq!(flooding.delay_after(None), Duration::from_millis(500));
+
+        let fixed_delay = Flooding::new(
+            Duration::from_secs(10),
+            None,
+            None,
+            None,
+            Arc::new(RoutingScope::default()),
+            Vec::new(),
+        );
+        assert_eq!(
+            fixed_delay.delay_after(Some((50, DataRate::Eu863_870Dr0))),
+            Duration::from_secs(10)
+        );
+    }
+
+    #[test]
+    fn relay_signal_policy_skips_strong_and_weak_packets() {
+        let flooding = Flooding::new(
+            Duration::from_secs(10),
+            None,
+            Some(RelaySignalPolicy {
+                max_rssi: Some(-40),
+                min_rssi: Some(-120),
+                min_snr: Some(-15),
+            }),
+            None,
+            Arc::new(RoutingScope::default()),
+            Vec::new(),
+        );
+        let signal_quality = |rssi, snr| Some(SignalQuality { rssi, snr });
+        assert!(flooding.relays(signal_quality(-90, 5.0)));
+        assert!(!flooding.relays(signal_quality(-30, 10.0)));
+        assert!(!flooding.relays(signal_quality(-125, 5.0)));
+        assert!(!flooding.relays(signal_quality(-90, -18.5)));
+        assert!(flooding.relays(None));
+    }
+
+    #[test]
+    fn announcements_rotate_across_frequencies() {
+        let flooding = Flooding::new(
+            Duration::from_secs(10),
+            None,
+            None,
+            None,
+            Arc::new(RoutingScope::default()),
+            vec![Frequency::Freq868_1, Frequency::Freq868_5],
+        );
+        let frequencies: Vec<Frequency> = (0..3)
+            .map(|sent| flooding.announcement_frequency(sent, Frequency::Freq868_3))
+            .collect();
+        assert_eq!(
+            frequencies,
+            [
+                Frequency::Freq868_1,
+                Frequency::Freq868_5,
+                Frequency::Freq868_1
+            ]
+        );
+
+        let default_only = Flooding::new(
+            Duration::from_secs(10),
+            None,
+            None,
+            None,
+            Arc::new(RoutingScope::default()),
+            Vec::new(),
+        );
+        assert_eq!(
+            default_only.announcement_frequency(5, Frequency::Freq868_3),
+            Frequency::Freq868_3
+        );
+    }
 }
@@ -0,0 +1,397 @@
+//! Retransmission of downlinks the gateways acknowledged as failed.
+
+use crate::configuration::DownlinkRetransmissionConfig;
+use crate::graceful_shutdown::ShutdownAgent;
+use crate::routing::{create_downlink, create_downlink_item, enqueue_downlink};
+use crate::AppState;
+use chirpstack_api::gw::{DownlinkTxAck, TxAckStatus};
+use chirpstack_gwb_integration::downlinks::predefined_parameters::{DataRate, Frequency};
+use chirpstack_gwb_integration::gateway_id::GatewayId;
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tracing::{error, info, trace, warn};
+
+/// Time after which a sent downlink without acknowledgement is no longer retransmitted.
+const PENDING_DOWNLINK_TIMEOUT_SECONDS: i64 = 300;
+
+/// Counters of the acknowledgements and the retransmissions.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, JsonSchema)]
+pub struct DownlinkAckStats {
+    /// Received acknowledgements by status, e.g. `TOO_LATE` or `COLLISION_PACKET`.
+    pub statuses: BTreeMap<String, u64>,
+    /// Retransmitted downlinks.
+    pub retransmitted: u64,
+    /// Failed downlinks not retransmitted as they reached the max amount of retries.
+    pub retries_exhausted: u64,
+}
+
+/// Sent downlink which may be retransmitted.
+#[derive(Debug, Clone, PartialEq)]
+struct PendingDownlink {
+    /// The gateway sending the downlink.
+    gateway_id: GatewayId,
+    /// Phy payload of the downlink.
+    phy_payload: Vec<u8>,
+    /// Data rate of the downlink.
+    data_rate: DataRate,
+    /// Frequency of the downlink.
+    frequency: Frequency,
+    /// Retransmissions of the phy payload so far.
+    retries: u32,
+    /// Time the downlink was sent.
+    sent_at: DateTime<Utc>,
+}
+
+/// Downlink to be rebuilt and sent again after a delay.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Retransmission {
+    /// The gateway sending the downlink.
+    gateway_id: GatewayId,
+    /// Phy payload of the downlink.
+    phy_payload: Vec<u8>,
+    /// Data rate of the downlink.
+    data_rate: DataRate,
+    /// Frequency of the rebuilt downlink.
+    frequency: Frequency,
+    /// Retransmissions of the phy payload including this one.
+    retries: u32,
+    /// Backoff before the downlink is sent again.
+    delay: Duration,
+}
+
+/// Tracks the sent downlinks and decides which failed downlinks are retransmitted, based on the
+/// status of their acknowledgement.
+#[derive(Debug, Default)]
+pub struct DownlinkRetransmission {
+    /// Retransmission policy, failed downlinks are only counted if not set.
+    config: Option<DownlinkRetransmissionConfig>,
+    /// Sent downlinks awaiting their acknowledgement by downlink ID.
+    pending: Mutex<HashMap<u32, PendingDownlink>>,
+    /// Counters of the acknowledgements and the retransmissions.
+    stats: Mutex<DownlinkAckStats>,
+}
+
+impl DownlinkRetransmission {
+    /// Creates a new [`DownlinkRetransmission`], failed downlinks are only retransmitted if
+    /// `config` is set.
+    pub fn new(config: Option<DownlinkRetransmissionConfig>) -> Self {
+        Self {
+            config,
+            ..Self::default()
+        }
+    }
+
+    /// Records a sent downlink to retransmit it if its acknowledgement reports a failure.
+    pub async fn record_sent(
+        &self,
+        gateway_id: &GatewayId,
+        downlink_id: u32,
+        phy_payload: Vec<u8>,
+        data_rate: DataRate,
+        frequency: Frequency,
+    ) {
+        self.record(
+            gateway_id,
+            downlink_id,
+            phy_payload,
+            data_rate,
+            frequency,
+            0,
+        )
+        .await;
+    }
+
+    /// Records a downlink sent after `retries` retransmissions of its phy payload.
+    async fn record(
+        &self,
+        gateway_id: &GatewayId,
+        downlink_id: u32,
+        phy_payload: Vec<u8>,
+        data_rate: DataRate,
+        frequency: Frequency,
+        retries: u32,
+    ) {
+        if self.config.is_none() {
+            return;
+        }
+        let now = Utc::now();
+        let mut pending = self.pending.lock().await;
+        pending.retain(|_, pending_downlink| {
+            now.signed_duration_since(pending_downlink.sent_at)
+                .num_seconds()
+                < PENDING_DOWNLINK_TIMEOUT_SECONDS
+        });
+        pending.insert(
+            downlink_id,
+            PendingDownlink {
+                gateway_id: gateway_id.clone(),
+                phy_payload,
+                data_rate,
+                frequency,
+                retries,
+                sent_at: now,
+            },
+        );
+    }
+
+    /// Counts the acknowledgement and returns the retransmission of the acknowledged downlink if
+    /// it failed with a status worth retrying and retries are left.
+    pub async fn process_ack(
+        &self,
+        gateway_id: &GatewayId,
+        ack: &DownlinkTxAck,
+    ) -> Option<Retransmission> {
+        let status = ack_status(ack);
+        let mut stats = self.stats.lock().await;
+        *stats
+            .statuses
+            .entry(status.as_str_name().to_owned())
+            .or_default() += 1;
+
+        let config = self.config.as_ref()?;
+        let mut pending = self.pending.lock().await;
+        if !pending
+            .get(&ack.downlink_id)
+            .is_some_and(|pending_downlink| pending_downlink.gateway_id == *gateway_id)
+        {
+            return None;
+        }
+        let pending_downlink = pending.remove(&ack.downlink_id)?;
+        if !is_retriable(status) {
+            return None;
+        }
+        if pending_downlink.retries >= config.max_retries {
+            stats.retries_exhausted += 1;
+            return None;
+        }
+        stats.retransmitted += 1;
+        let delay = Duration::from_millis(
+            config
+                .backoff_ms
+                .saturating_mul(2_u64.saturating_pow(pending_downlink.retries)),
+        );
+        let frequency = if changes_frequency(status) {
+            next_frequency(pending_downlink.frequency)
+        } else {
+            pending_downlink.frequency
+        };
+        Some(Retransmission {
+            gateway_id: pending_downlink.gateway_id,
+            phy_payload: pending_downlink.phy_payload,
+            data_rate: pending_downlink.data_rate,
+            frequency,
+            retries: pending_downlink.retries + 1,
+            delay,
+        })
+    }
+
+    /// Returns the counters of the acknowledgements and the retransmissions.
+    pub async fn stats(&self) -> DownlinkAckStats {
+        self.stats.lock().await.clone()
+    }
+}
+
+/// Returns the status of an acknowledgement, ok if any item was transmitted.
+fn ack_status(ack: &DownlinkTxAck) -> TxAckStatus {
+    let mut statuses = ack.items.iter().map(|item| item.status());
+    if statuses.clone().any(|status| status == TxAckStatus::Ok) {
+        return TxAckStatus::Ok;
+    }
+    statuses.next().unwrap_or(TxAckStatus::Ignored)
+}
+
+/// Whether a downlink failed with the status may succeed if it is sent again.
+fn is_retriable(status: TxAckStatus) -> bool {
+    matches!(
+        status,
+        TxAckStatus::TooLate
+            | TxAckStatus::TooEarly
+            | TxAckStatus::CollisionPacket
+            | TxAckStatus::CollisionBeacon
+            | TxAckStatus::TxFreq
+            | TxAckStatus::QueueFull
+            | TxAckStatus::InternalError
+            | TxAckStatus::DutyCycleOverflow
+    )
+}
+
+/// Whether a downlink failed with the status is sent again on another frequency.
+fn changes_frequency(status: TxAckStatus) -> bool {
+    matches!(
+        status,
+        TxAckStatus::CollisionPacket | TxAckStatus::TxFreq | TxAckStatus::DutyCycleOverflow
+    )
+}
+
+/// Returns the next of the default frequencies.
+fn next_frequency(frequency: Frequency) -> Frequency {
+    match frequency {
+        Frequency::Freq868_1 => Frequency::Freq868_3,
+        Frequency::Freq868_3 => Frequency::Freq868_5,
+        Frequency::Freq868_5 => Frequency::Freq868_1,
+    }
+}
+
+/// Rebuilds the downlink of the retransmission with a new downlink ID and sends it after the
+/// backoff and any radio silence, via the gateway send queues if configured.
+pub async fn retransmit(
+    state: Arc<AppState>,
+    retransmission: Retransmission,
+    mut shutdown_agent: ShutdownAgent,
+) {
+    tokio::select! {
+        () = tokio::time::sleep(retransmission.delay) => {},
+        _ = shutdown_agent.await_shutdown() => {
+            trace!("Shutting down");
+            return
+        }
+    }
+    if !state
+        .radio_silence
+        .wait_until_over(&mut shutdown_agent)
+        .await
+    {
+        trace!("Shutting down");
+        return;
+    }
+    let Retransmission {
+        gateway_id,
+        phy_payload,
+        data_rate,
+        frequency,
+        retries,
+        ..
+    } = retransmission;
+    let Some(frequency) = state
+        .frequency_lockouts
+        .select_frequency(frequency, Utc::now())
+    else {
+        warn!("All frequencies locked out, dropping retransmission");
+        return;
+    };
+    let downlink_item = match create_downlink_item(phy_payload.clone(), frequency, data_rate) {
+        Ok(downlink_item) => downlink_item,
+        Err(err) => {
+            error!(%err);
+            return;
+        }
+    };
+    let downlink_id = state
+        .runtime
+        .next_downlink_id(&gateway_id, "retransmission")
+        .await;
+    let downlink = match create_downlink(
+        gateway_id.clone(),
+        downlink_id,
+        downlink_item,
+        state.gateway_ids_manager.tx_paths(&gateway_id),
+    ) {
+        Ok(downlink) => downlink,
+        Err(err) => {
+            error!(%err);
+            return;
+        }
+    };
+    info!(
+        retries,
+        "Retransmitting failed downlink from gateway: {gateway_id}"
+    );
+    state
+        .downlink_retransmission
+        .record(
+            &gateway_id,
+            downlink_id,
+            phy_payload.clone(),
+            data_rate,
+            frequency,
+            retries,
+        )
+        .await;
+    if let Some(gateway_send_queues) = &state.gateway_send_queues {
+        gateway_send_queues.enqueue(gateway_id, downlink_id, downlink, phy_payload);
+        return;
+    }
+    state
+        .gateway_selector
+        .lock()
+        .await
+        .record_enqueued(&gateway_id, downlink_id);
+    enqueue_downlink(&state, &gateway_id, downlink).await;
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use crate::configuration::DownlinkRetransmissionConfig;
+    use crate::routing::retransmission::DownlinkRetransmission;
+    use chirpstack_api::gw::{DownlinkTxAck, DownlinkTxAckItem, TxAckStatus};
+    use chirpstack_gwb_integration::downlinks::predefined_parameters::{DataRate, Frequency};
+    use chirpstack_gwb_integration::gateway_id::GatewayId;
+    use std::time::Duration;
+
+    fn ack(downlink_id: u32, status: TxAckStatus) -> DownlinkTxAck {
+        DownlinkTxAck {
+            downlink_id,
+            items: vec![DownlinkTxAckItem {
+                status: status as i32,
+            }],
+            ..DownlinkTxAck::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_downlinks_are_retried_with_backoff() {
+        let a: GatewayId = "a840411d2524415a".parse().unwrap();
+        let b: GatewayId = "a840411d2524415b".parse().unwrap();
+        let retransmission = DownlinkRetransmission::new(Some(DownlinkRetransmissionConfig {
+            max_retries: 1,
+            backoff_ms: 100,
+        }));
+        retransmission
+            .record_sent(
+                &a,
+                1,
+                vec![1, 2],
+                DataRate::Eu863_870Dr3,
+                Frequency::Freq868_3,
+            )
+            .await;
+        assert!(retransmission
+            .process_ack(&b, &ack(1, TxAckStatus::TooLate))
+            .await
+            .is_none());
+
+        let retry = retransmission
+            .process_ack(&a, &ack(1, TxAckStatus::CollisionPacket))
+            .await
+            .unwrap();
+        assert_eq!(retry.frequency, Frequency::Freq868_5);
+        assert_eq!(retry.retries, 1);
+        assert_eq!(retry.delay, Duration::from_millis(100));
+
+        retransmission
+            .record(
+                &a,
+                2,
+                retry.phy_payload,
+                retry.data_rate,
+                retry.frequency,
+                1,
+            )
+            .await;
+        assert!(retransmission
+            .process_ack(&a, &ack(2, TxAckStatus::TooLate))
+            .await
+            .is_none());
+
+        let stats = retransmission.stats().await;
+        assert_eq!(stats.statuses.get("TOO_LATE"), Some(&2));
+        assert_eq!(stats.statuses.get("COLLISION_PACKET"), Some(&1));
+        assert_eq!((stats.retransmitted, stats.retries_exhausted), (1, 1));
+    }
+}
@@ -0,0 +1,132 @@
+//! Slotted TDMA coordination of inter-node transmissions.
+
+use std::time::{Duration, SystemTime};
+
+/// Time before the start of a slot at which downlinks are handed to the gateways.
+const SCHEDULING_LEAD_TIME: Duration = Duration::from_millis(500);
+
+/// Coordinates transmissions into the own slot of a TDMA frame.
+///
+/// Frames are aligned to the UNIX epoch, nodes with synchronized clocks therefore agree on the
+/// frame boundaries without further coordination.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct TdmaCoordinator {
+    /// Length of a single slot in milliseconds.
+    slot_length_ms: u64,
+    /// Amount of slots per frame.
+    frame_length: u32,
+    /// The own slot inside the frame.
+    slot: u32,
+    /// Whether gateways without GPS timing send unslotted instead of delaying locally.
+    fallback_to_unslotted: bool,
+}
+
+impl TdmaCoordinator {
+    /// Creates a new [`TdmaCoordinator`], the own slot is derived from the node ID.
+    pub fn new(
+        slot_length_ms: u64,
+        frame_length: u32,
+        node_id: u32,
+        fallback_to_unslotted: bool,
+    ) -> Self {
+        let frame_length = frame_length.max(1);
+        Self {
+            slot_length_ms: slot_length_ms.max(1),
+            frame_length,
+            slot: node_id % frame_length,
+            fallback_to_unslotted,
+        }
+    }
+
+    /// Returns the own slot inside the frame.
+    pub fn slot(&self) -> u32 {
+        self.slot
+    }
+
+    /// Returns whether gateways without GPS timing send unslotted.
+    pub fn fallback_to_unslotted(&self) -> bool {
+        self.fallback_to_unslotted
+    }
+
+    /// Returns the start of the next own slot beginning at or after `earliest`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the slot start cannot be represented in milliseconds since the UNIX epoch as
+    /// [`u64`].
+    pub fn next_slot_start(&self, earliest: SystemTime) -> SystemTime {
+        let earliest_ms = earliest
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let frame_length_ms = u128::from(self.slot_length_ms) * u128::from(self.frame_length);
+        let frame_start_ms = earliest_ms - earliest_ms % frame_length_ms;
+        let mut slot_start_ms =
+            frame_start_ms + u128::from(self.slot_length_ms) * u128::from(self.slot);
+        if slot_start_ms < earliest_ms {
+            slot_start_ms += frame_length_ms;
+        }
+        SystemTime::UNIX_EPOCH
+            + Duration::from_millis(
+                u64::try_from(slot_start_ms)
+                    .expect("Slot start does not fit into u64 milliseconds"),
+            )
+    }
+
+    /// Returns the start of the next own slot downlinks handed to the gateways at `now` still
+    /// reach in time.
+    pub fn upcoming_slot_start(&self, now: SystemTime) -> SystemTime {
+        self.next_slot_start(now + SCHEDULING_LEAD_TIME)
+    }
+
+    /// Waits until downlinks for the next own slot have to be handed to the gateways.
+    ///
+    /// Returns the start of the slot.
+    pub async fn await_next_slot(&self) -> SystemTime {
+        let now = SystemTime::now();
+        let slot_start = self.upcoming_slot_start(now);
+        if let Ok(delay) = (slot_start - SCHEDULING_LEAD_TIME).duration_since(now) {
+            tokio::time::sleep(delay).await;
+        }
+        slot_start
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::routing::tdma::TdmaCoordinator;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn slot_derived_from_node_id() {
+        assert_eq!(TdmaCoordinator::new(1000, 10, 23, false).slot(), 3);
+        assert_eq!(TdmaCoordinator::new(1000, 0, 23, false).slot(), 0);
+    }
+
+    #[test]
+    fn next_slot_in_current_frame() {
+        let coordinator = TdmaCoordinator::new(1000, 10, 3, false);
+        let earliest = SystemTime::UNIX_EPOCH + Duration::from_millis(100_500);
+        assert_eq!(
+            coordinator.next_slot_start(earliest),
+            SystemTime::UNIX_EPOCH + Duration::from_secs(103)
+        );
+    }
+
+    #[test]
+    fn next_slot_at_slot_start() {
+        let coordinator = TdmaCoordinator::new(1000, 10, 3, false);
+        let earliest = SystemTime::UNIX_EPOCH + Duration::from_secs(103);
+        assert_eq!(coordinator.next_slot_start(earliest), earliest);
+    }
+
+    #[test]
+    fn next_slot_in_next_frame() {
+        let coordinator = TdmaCoordinator::new(1000, 10, 3, false);
+        let earliest = SystemTime::UNIX_EPOCH + Duration::from_millis(103_001);
+        assert_eq!(
+            coordinator.next_slot_start(earliest),
+            SystemTime::UNIX_EPOCH + Duration::from_secs(113)
+        );
+    }
+}
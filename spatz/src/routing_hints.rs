@@ -0,0 +1,187 @@
+//! Routing hints attached by the submitting client to a bundle.
+//!
+//! Every hint is optional, a bundle without hints is queued behind the bundles of the same
+//! priority, fragmented as needed and sent until it was transmitted completely:
+//! - `max_hops`: bundles whose destination is known to be farther away are rejected on
+//!   submission. The hop distance is only known for announced destinations and is not carried in
+//!   the packets, so it is not enforced by other nodes.
+//! - `preferred_gateway`: gateway of this node the packets are sent from once, the packets are
+//!   flooded if the gateway is disconnected or cannot transmit at the data rate.
+//! - `expires_at`: the bundle is removed from the queue once expired, including the packets not
+//!   sent yet.
+//! - `do_not_fragment`: the bundle is sent as a single packet, bundles whose payload does not fit
+//!   into a single packet at the lowest data rate are rejected.
+//! - `priority`: bundles are queued behind the pinned bundles and the bundles of the same or a
+//!   higher priority, `normal` if not set. Transfers in progress of a lower priority are
+//!   preempted or interleaved, see [`PreemptionPolicy`](crate::configuration::PreemptionPolicy).
+//! - `oversize_policy`: handling of a payload exceeding the max size of a bundle, the configured
+//!   one if not set, see [`oversize_bundles`](crate::oversize_bundles).
+
+use crate::configuration::OversizePolicy;
+use crate::error::RoutingHintsError;
+use chirpstack_gwb_integration::downlinks::predefined_parameters::DataRate;
+use chirpstack_gwb_integration::gateway_id::GatewayId;
+use chrono::{DateTime, Utc};
+use lorawan_dtn_protocol::COMPLETE_BUNDLE_HEADERS_SIZE;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Priority of a bundle in the bundle queue.
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum BundlePriority {
+    /// Queued behind all other bundles.
+    Low,
+    /// Queued behind the bundles of normal and high priority.
+    #[default]
+    Normal,
+    /// Queued behind the pinned bundles and the other bundles of high priority.
+    High,
+}
+
+/// Routing hints of a bundle, see the [module documentation](self) for the defaults.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct RoutingHints {
+    /// Max amount of hops to the destination, not limited if not set.
+    #[serde(default)]
+    pub max_hops: Option<u8>,
+    /// Gateway of this node the packets are sent from, routed as usual if not set.
+    #[serde(default)]
+    pub preferred_gateway: Option<GatewayId>,
+    /// Time after which the bundle is dropped from the queue, kept until sent if not set.
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Whether the bundle must be sent as a single packet.
+    #[serde(default)]
+    pub do_not_fragment: bool,
+    /// Priority of the bundle in the bundle queue.
+    #[serde(default)]
+    pub priority: BundlePriority,
+    /// Handling of a payload exceeding the max size of a bundle, the configured one if not set.
+    #[serde(default)]
+    pub oversize_policy: Option<OversizePolicy>,
+}
+
+impl RoutingHints {
+    /// Checks the hints of a bundle with the payload length on submission.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if:
+    /// - `max_hops` is 0.
+    /// - `expires_at` is not in the future.
+    /// - `do_not_fragment` is set and the payload does not fit into a single packet at the lowest
+    ///   data rate.
+    pub fn validate(
+        &self,
+        payload_len: usize,
+        repeater_compatible: bool,
+        now: DateTime<Utc>,
+    ) -> Result<(), RoutingHintsError> {
+        if self.max_hops == Some(0) {
+            return Err(RoutingHintsError::ZeroMaxHops);
+        }
+        if self.expires_at.is_some_and(|expires_at| expires_at <= now) {
+            return Err(RoutingHintsError::Expired);
+        }
+        let max = DataRate::Eu863_870Dr0
+            .max_usable_payload_size(repeater_compatible)
+            .saturating_sub(COMPLETE_BUNDLE_HEADERS_SIZE);
+        if self.do_not_fragment && payload_len > max {
+            return Err(RoutingHintsError::TooLargeForSinglePacket {
+                size: payload_len,
+                max,
+            });
+        }
+        Ok(())
+    }
+
+    /// Checks the known hop distance to the destination against `max_hops`, destinations
+    /// without known hop distance are accepted.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the destination is farther away than `max_hops`.
+    pub fn check_hop_distance(&self, hop_distance: Option<u8>) -> Result<(), RoutingHintsError> {
+        match (self.max_hops, hop_distance) {
+            (Some(max_hops), Some(hop_distance)) if hop_distance > max_hops => {
+                Err(RoutingHintsError::DestinationTooFar {
+                    hop_distance,
+                    max_hops,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns whether the bundle expired.
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use crate::error::RoutingHintsError;
+    use crate::routing_hints::{BundlePriority, RoutingHints};
+    use chrono::{Duration, Utc};
+
+    #[test]
+    fn hints_are_validated() {
+        let now = Utc::now();
+        let hints: RoutingHints = serde_json::from_str("{}").unwrap();
+        assert_eq!(hints, RoutingHints::default());
+        assert_eq!(hints.priority, BundlePriority::Normal);
+        assert!(hints.validate(1000, false, now).is_ok());
+
+        let hints = RoutingHints {
+            max_hops: Some(0),
+            ..RoutingHints::default()
+        };
+        assert_eq!(
+            hints.validate(10, false, now),
+            Err(RoutingHintsError::ZeroMaxHops)
+        );
+
+        let hints = RoutingHints {
+            expires_at: Some(now - Duration::seconds(1)),
+            ..RoutingHints::default()
+        };
+        assert_eq!(
+            hints.validate(10, false, now),
+            Err(RoutingHintsError::Expired)
+        );
+        assert!(hints.is_expired(now));
+
+        let hints = RoutingHints {
+            do_not_fragment: true,
+            ..RoutingHints::default()
+        };
+        assert!(hints.validate(10, false, now).is_ok());
+        assert!(matches!(
+            hints.validate(1000, false, now),
+            Err(RoutingHintsError::TooLargeForSinglePacket { size: 1000, .. })
+        ));
+    }
+
+    #[test]
+    fn hop_distance_is_limited_by_max_hops() {
+        let hints = RoutingHints {
+            max_hops: Some(2),
+            ..RoutingHints::default()
+        };
+        assert!(hints.check_hop_distance(None).is_ok());
+        assert!(hints.check_hop_distance(Some(2)).is_ok());
+        assert_eq!(
+            hints.check_hop_distance(Some(3)),
+            Err(RoutingHintsError::DestinationTooFar {
+                hop_distance: 3,
+                max_hops: 2
+            })
+        );
+        assert!(RoutingHints::default().check_hop_distance(Some(3)).is_ok());
+    }
+}
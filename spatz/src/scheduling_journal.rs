@@ -0,0 +1,196 @@
+//! Journal of the transmission decisions for the post-mortem of reported duty cycle violations.
+//!
+//! The duty cycle checks of the gateway send queues and the accounting of every observed downlink
+//! by the duty cycle collector are recorded with the gateway, the frequency, the airtime, the
+//! remaining budget of the band and the originating packet. The journal is a ring buffer of the
+//! configured size, persisted with the state and served by the API.
+
+use crate::configuration::SchedulingJournalConfig;
+use crate::duty_cycle_manager::DutyCycleTenant;
+use chirpstack_gwb_integration::gateway_id::GatewayId;
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha3::Digest;
+use std::collections::VecDeque;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// Decision about a downlink.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SchedulingDecision {
+    /// Sent by the gateway send queues as the budget allowed it.
+    Granted,
+    /// Deferred by the gateway send queues as the budget was exhausted.
+    Deferred,
+    /// Dropped by the gateway send queues, e.g. as its frequency is locked out.
+    Dropped,
+    /// Observed downlink whose airtime was consumed from the budget.
+    Accounted,
+    /// Observed downlink exceeding the remaining budget, a potential violation.
+    Overused,
+    /// Observed downlink on a frequency in no band, its airtime is not accounted.
+    OutOfBand,
+}
+
+/// Recorded decision about a downlink.
+///
+/// Decisions of the gateway send queues do not consume the budget, the airtime is consumed when
+/// the downlink is observed and recorded as [`SchedulingDecision::Accounted`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct JournalEntry {
+    /// Time of the decision.
+    pub time: DateTime<Utc>,
+    /// Gateway sending the downlink.
+    pub gateway_id: GatewayId,
+    /// Frequency of the downlink in Hz.
+    pub frequency: u32,
+    /// Band of the frequency, not set if the frequency is in no band.
+    pub band: Option<String>,
+    /// Airtime of the downlink in milliseconds.
+    pub airtime_ms: f64,
+    /// Airtime left to all tenants in the band before the decision in milliseconds, not set if
+    /// the band has no accumulated limit.
+    pub remaining_before_ms: Option<f64>,
+    /// Airtime left to all tenants in the band after the decision in milliseconds, not set if the
+    /// band has no accumulated limit.
+    pub remaining_after_ms: Option<f64>,
+    /// Originator of the downlink.
+    pub tenant: DutyCycleTenant,
+    /// The decision.
+    pub decision: SchedulingDecision,
+    /// ID of the downlink, see `/api/gateways/{gateway_id}/downlinks` for the sending subsystem.
+    pub downlink_id: u32,
+    /// Hex encoded SHA3-256 hash of the phy payload, as used by the packet cache.
+    pub packet_hash: String,
+    /// Error causing the decision, e.g. the lockout of a dropped downlink.
+    pub reason: Option<String>,
+}
+
+/// Returns the hex encoded SHA3-256 hash of the phy payload.
+pub fn packet_hash(phy_payload: &[u8]) -> String {
+    hex::encode(sha3::Sha3_256::digest(phy_payload))
+}
+
+/// Ring buffer of the latest decisions.
+#[derive(Debug)]
+pub struct SchedulingJournal {
+    /// Maximum amount of kept decisions.
+    max_entries: usize,
+    /// Decisions, oldest first.
+    entries: Mutex<VecDeque<JournalEntry>>,
+}
+
+impl SchedulingJournal {
+    /// Creates a new [`SchedulingJournal`] restoring the persisted decisions, the oldest
+    /// decisions exceeding the configured size are removed.
+    pub fn new(config: &SchedulingJournalConfig, entries: Vec<JournalEntry>) -> Self {
+        let mut entries = VecDeque::from(entries);
+        let excess = entries.len().saturating_sub(config.max_entries);
+        entries.drain(..excess);
+        Self {
+            max_entries: config.max_entries,
+            entries: Mutex::new(entries),
+        }
+    }
+
+    /// Records a decision, removing the oldest decision if the journal is full.
+    pub fn record(&self, entry: JournalEntry) {
+        let mut entries = self.lock();
+        if entries.len() >= self.max_entries {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+
+    /// Returns all decisions, oldest first.
+    pub fn entries(&self) -> Vec<JournalEntry> {
+        self.lock().iter().cloned().collect()
+    }
+
+    /// Returns the decisions of the gateway since `since`, oldest first, decisions of all
+    /// gateways or of all times if not set.
+    pub fn query(
+        &self,
+        gateway_id: Option<&GatewayId>,
+        since: Option<DateTime<Utc>>,
+    ) -> Vec<JournalEntry> {
+        self.lock()
+            .iter()
+            .filter(|entry| gateway_id.map_or(true, |gateway_id| entry.gateway_id == *gateway_id))
+            .filter(|entry| since.map_or(true, |since| entry.time >= since))
+            .cloned()
+            .collect()
+    }
+
+    /// Locks the decisions, a poisoned lock is recovered as the decisions stay consistent.
+    fn lock(&self) -> MutexGuard<'_, VecDeque<JournalEntry>> {
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use crate::configuration::SchedulingJournalConfig;
+    use crate::duty_cycle_manager::DutyCycleTenant;
+    use crate::scheduling_journal::{
+        packet_hash, JournalEntry, SchedulingDecision, SchedulingJournal,
+    };
+    use chirpstack_gwb_integration::gateway_id::GatewayId;
+    use chrono::{DateTime, Duration, Utc};
+
+    fn gateway(name: &str) -> GatewayId {
+        format!("a840411d2524415{name}").parse().unwrap()
+    }
+
+    fn entry(gateway_id: &str, time: DateTime<Utc>, downlink_id: u32) -> JournalEntry {
+        JournalEntry {
+            time,
+            gateway_id: gateway(gateway_id),
+            frequency: 868_100_000,
+            band: Some("Sb868000_868600".to_owned()),
+            airtime_ms: 100.0,
+            remaining_before_ms: Some(36_000.0),
+            remaining_after_ms: Some(35_900.0),
+            tenant: DutyCycleTenant::Dtn,
+            decision: SchedulingDecision::Accounted,
+            downlink_id,
+            packet_hash: packet_hash(&[0xE0, 0x06]),
+            reason: None,
+        }
+    }
+
+    #[test]
+    fn journal_keeps_latest_decisions() {
+        let now = Utc::now();
+        let journal = SchedulingJournal::new(
+            &SchedulingJournalConfig { max_entries: 3 },
+            vec![
+                entry("a", now - Duration::minutes(4), 1),
+                entry("b", now - Duration::minutes(3), 2),
+                entry("a", now - Duration::minutes(2), 3),
+                entry("a", now - Duration::minutes(1), 4),
+            ],
+        );
+        let downlink_ids = |entries: Vec<JournalEntry>| {
+            entries
+                .iter()
+                .map(|entry| entry.downlink_id)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(downlink_ids(journal.entries()), vec![2, 3, 4]);
+
+        journal.record(entry("b", now, 5));
+        assert_eq!(downlink_ids(journal.entries()), vec![3, 4, 5]);
+        assert_eq!(
+            downlink_ids(journal.query(Some(&gateway("a")), None)),
+            vec![3, 4]
+        );
+        assert_eq!(
+            downlink_ids(journal.query(None, Some(now - Duration::seconds(90)))),
+            vec![4, 5]
+        );
+        assert_eq!(journal.entries()[0].packet_hash.len(), 64);
+    }
+}
@@ -3,9 +3,10 @@
 mod bundle;
 
 use crate::error::SendBufferError;
-use crate::lorawan_protocol::LoRaWanPacket;
-pub use bundle::BundleSendBuffer;
+use crate::routing_hints::RoutingHints;
+pub use bundle::{BundleSendBuffer, SendBufferProgress, SubBundle, SubBundleKey};
 use chirpstack_gwb_integration::downlinks::predefined_parameters::DataRate;
+use lorawan_dtn_protocol::LoRaWanPacket;
 
 /// Trait for all send buffers.
 pub trait SendBuffer {
@@ -22,4 +23,30 @@ pub trait SendBuffer {
 
     /// Returns whether the send buffer has produced all available packets and is empty.
     fn is_empty(&self) -> bool;
+
+    /// Returns whether the send buffer is frozen and must be skipped when sending.
+    fn is_frozen(&self) -> bool;
+
+    /// Returns whether the send buffer produced packets but not all of them yet, i.e. its
+    /// transfer is in progress.
+    fn is_started(&self) -> bool {
+        false
+    }
+
+    /// Returns the API client which submitted the payload, `None` if it was not submitted by an
+    /// API client.
+    fn client(&self) -> Option<&str> {
+        None
+    }
+
+    /// Returns the routing hints of the submitting client, `None` if the payload carries no hints.
+    fn routing_hints(&self) -> Option<&RoutingHints> {
+        None
+    }
+
+    /// Returns the transmission progress to persist after every produced packet, `None` if the
+    /// send buffer is not resumed after a restart.
+    fn progress(&self) -> Option<SendBufferProgress> {
+        None
+    }
 }
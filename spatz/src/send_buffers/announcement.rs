@@ -1,10 +1,10 @@
 use crate::end_device_id::EndDeviceId;
 use crate::error::SendBufferError;
-use crate::lorawan_protocol::{
-    AnnouncementPayload, Fragment, GpsLocation, LoRaWanProtocol, MessageType,
-};
 use crate::send_buffers::SendBuffer;
 use chirpstack_gwb_integration::downlinks::predefined_parameters::DataRate;
+use lorawan_dtn_protocol::{
+    AnnouncementPayload, Fragment, GpsLocation, LoRaWanProtocol, MessageType,
+};
 use std::cmp::Ordering;
 use std::f64;
 
@@ -136,20 +136,16 @@ impl SendBuffer for AnnouncementSendBuffer {
                 // First fragment, leave space for location, only use end_device_ids_in_first_fragment
                 if self.fragment_index == 0 {
                     for _ in 0..self.end_device_ids_in_first_fragment {
-                        let Some(end_device_id) = self
-                            .payload
-                            .get(self.payload_index) else {
-                            return Err(SendBufferError::FragmentCountCalculationWrong)
+                        let Some(end_device_id) = self.payload.get(self.payload_index) else {
+                            return Err(SendBufferError::FragmentCountCalculationWrong);
                         };
                         reachable_ids.push(*end_device_id);
                         self.payload_index += 1;
                     }
                 } else {
                     for _ in 0..self.end_device_ids_per_fragment {
-                        let Some(end_device_id) = self
-                            .payload
-                            .get(self.payload_index) else {
-                            return Err(SendBufferError::FragmentCountCalculationWrong)
+                        let Some(end_device_id) = self.payload.get(self.payload_index) else {
+                            return Err(SendBufferError::FragmentCountCalculationWrong);
                         };
                         reachable_ids.push(*end_device_id);
                         self.payload_index += 1;
@@ -159,10 +155,8 @@ impl SendBuffer for AnnouncementSendBuffer {
             // Last fragment
             Ordering::Equal => {
                 for _ in self.payload_index..self.payload.len() {
-                    let Some(end_device_id) = self
-                        .payload
-                        .get(self.payload_index) else {
-                        return Err(SendBufferError::FragmentCountCalculationWrong)
+                    let Some(end_device_id) = self.payload.get(self.payload_index) else {
+                        return Err(SendBufferError::FragmentCountCalculationWrong);
                     };
                     reachable_ids.push(*end_device_id);
                     self.payload_index += 1;
@@ -217,10 +211,10 @@ impl SendBuffer for AnnouncementSendBuffer {
 #[cfg(test)]
 mod tests {
     use crate::end_device_id::EndDeviceId;
-    use crate::lorawan_protocol::{AnnouncementPayload, Fragment, GpsLocation, MessageType};
     use crate::send_buffers::announcement::AnnouncementSendBuffer;
     use crate::send_buffers::SendBuffer;
     use chirpstack_gwb_integration::downlinks::predefined_parameters::DataRate;
+    use lorawan_dtn_protocol::{AnnouncementPayload, Fragment, GpsLocation, MessageType};
 
     #[test]
     fn announcement_send_buffer() {
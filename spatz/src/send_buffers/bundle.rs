@@ -4,17 +4,66 @@ use crate::end_device_id::EndDeviceId;
 use crate::error::{
     BundleSendBufferConversionError, BundleSendBufferCreationError, SendBufferError,
 };
-use crate::lorawan_protocol::{
-    BundleFragment, CompleteBundle, LoRaWanPacket, BUNDLE_FRAGMENT_HEADERS_SIZE,
-    COMPLETE_BUNDLE_HEADERS_SIZE,
-};
+use crate::routing_hints::{BundlePriority, RoutingHints};
 use crate::send_buffers::SendBuffer;
 use bp7::dtntime::DtnTimeHelpers;
 use bp7::Bundle;
 use chirpstack_gwb_integration::downlinks::predefined_parameters::DataRate;
 use chrono::{DateTime, NaiveDateTime, Utc};
+use lorawan_dtn_protocol::{
+    Bp7Bundle, BundleFragment, CompleteBundle, FragmentedBundleFragment,
+    FragmentedBundleFragmentEnd, LoRaWanPacket, BUNDLE_FRAGMENT_HEADERS_SIZE,
+    COMPLETE_BUNDLE_HEADERS_SIZE, FRAGMENTED_BUNDLE_FRAGMENT_END_HEADERS_SIZE,
+    FRAGMENTED_BUNDLE_FRAGMENT_HEADERS_SIZE,
+};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+/// Transmission progress of a [`BundleSendBuffer`], persisted after every produced packet to
+/// resume the bundle after a restart instead of sending it from the start.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SendBufferProgress {
+    /// Destination of the bundle.
+    destination: EndDeviceId,
+    /// Source of the bundle.
+    source: EndDeviceId,
+    /// Timestamp of the bundle.
+    timestamp: DateTime<Utc>,
+    /// Length of the complete payload.
+    payload_len: usize,
+    /// Index of the first payload byte not sent yet.
+    payload_index: usize,
+    /// The fragment index of the packet to be sent next.
+    fragment_index: u8,
+    /// Data rate of the last produced packet.
+    data_rate: Option<DataRate>,
+    /// Offset of the payload of a sub-bundle within the payload of the original bundle.
+    #[serde(default)]
+    fragment_offset: Option<u64>,
+}
+
+impl SendBufferProgress {
+    /// Returns the key of the sub-bundle the progress belongs to, `None` for whole bundles.
+    pub fn sub_bundle_key(&self) -> Option<SubBundleKey> {
+        self.fragment_offset
+            .map(|offset| (self.source, self.destination, self.timestamp, offset))
+    }
+}
+
+/// Source, destination, timestamp and payload offset identifying a sub-bundle of a split
+/// bundle.
+pub type SubBundleKey = (EndDeviceId, EndDeviceId, DateTime<Utc>, u64);
+
+/// Part of the payload of a bundle split into sub-bundles, sent as fragmented bundle fragments
+/// and reassembled from the BP7 fragments by the destination.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct SubBundle {
+    /// Offset of the payload of the sub-bundle within the payload of the original bundle.
+    pub offset: u64,
+    /// Length of the payload of the original bundle.
+    pub total_length: u64,
+}
 
 /// Send buffer for bundles.
 ///
@@ -31,6 +80,35 @@ pub struct BundleSendBuffer {
     fragment_index: u8,
     /// The payload, will be fragmented and sent via multiple packets.
     payload: Vec<u8>,
+    /// Index of the first payload byte not sent yet.
+    #[serde(default)]
+    payload_index: usize,
+    /// Data rate of the last produced packet.
+    #[serde(default)]
+    #[schemars(skip)]
+    data_rate: Option<DataRate>,
+    /// Whether the packets are sized for the payload sizes allowed with a LoRaWAN repeater.
+    #[serde(default)]
+    repeater_compatible: bool,
+    /// Whether the bundle was moved to the front of the queue by an operator.
+    #[serde(default)]
+    pinned: bool,
+    /// Whether the bundle is held back from sending by an operator.
+    #[serde(default)]
+    frozen: bool,
+    /// API client which submitted the bundle and is accounted the airtime of its fragments.
+    #[serde(default)]
+    client: Option<String>,
+    /// Whether the bundle is sent as CBOR encoded BP7 fragments instead of with the custom
+    /// headers, see [`bp7_interop`](crate::bp7_interop).
+    #[serde(default)]
+    bp7_framing: bool,
+    /// Routing hints of the submitting client.
+    #[serde(default)]
+    routing_hints: RoutingHints,
+    /// Position of the payload within the original bundle if the bundle was split.
+    #[serde(default)]
+    sub_bundle: Option<SubBundle>,
 }
 
 impl BundleSendBuffer {
@@ -44,11 +122,9 @@ impl BundleSendBuffer {
         source: EndDeviceId,
         timestamp: DateTime<Utc>,
         payload: Vec<u8>,
+        repeater_compatible: bool,
     ) -> Result<Self, BundleSendBufferCreationError> {
-        if payload.len()
-            > (DataRate::Eu863_870Dr0.max_usable_payload_size(false) - BUNDLE_FRAGMENT_HEADERS_SIZE)
-                * 128
-        {
+        if payload.len() > Self::max_payload_len(repeater_compatible) {
             Err(BundleSendBufferCreationError::PayloadTooLarge)
         } else {
             Ok(Self {
@@ -57,69 +133,453 @@ impl BundleSendBuffer {
                 timestamp,
                 fragment_index: 0,
                 payload,
+                payload_index: 0,
+                data_rate: None,
+                repeater_compatible,
+                pinned: false,
+                frozen: false,
+                client: None,
+                bp7_framing: false,
+                routing_hints: RoutingHints::default(),
+                sub_bundle: None,
             })
         }
     }
-}
 
-impl SendBuffer for BundleSendBuffer {
-    fn next_packet(
-        &mut self,
-        data_rate: DataRate,
-    ) -> Result<Box<dyn LoRaWanPacket>, SendBufferError> {
-        if self.payload.is_empty() {
-            return Err(SendBufferError::PayloadConsumed);
+    /// Creates a new [`BundleSendBuffer`] for the sub-bundle of a split bundle.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the payload is too large and cannot be sent completely at the lowest data rate.
+    pub fn new_sub_bundle(
+        destination: EndDeviceId,
+        source: EndDeviceId,
+        timestamp: DateTime<Utc>,
+        payload: Vec<u8>,
+        sub_bundle: SubBundle,
+        repeater_compatible: bool,
+    ) -> Result<Self, BundleSendBufferCreationError> {
+        if payload.len() > Self::max_sub_bundle_payload_len(repeater_compatible) {
+            return Err(BundleSendBufferCreationError::PayloadTooLarge);
         }
-        let packet_max_size =
-            data_rate.max_usable_payload_size(false) - COMPLETE_BUNDLE_HEADERS_SIZE;
-        if self.fragment_index == 0 && self.payload.len() <= packet_max_size {
-            let complete_bundle = CompleteBundle::new(
-                self.destination,
+        let mut send_buffer =
+            Self::new(destination, source, timestamp, payload, repeater_compatible)?;
+        send_buffer.sub_bundle = Some(sub_bundle);
+        Ok(send_buffer)
+    }
+
+    /// Returns the max length of the payload of a bundle, sent in up to 128 fragments at the
+    /// lowest data rate.
+    pub fn max_payload_len(repeater_compatible: bool) -> usize {
+        (DataRate::Eu863_870Dr0.max_usable_payload_size(repeater_compatible)
+            - BUNDLE_FRAGMENT_HEADERS_SIZE)
+            * 128
+    }
+
+    /// Returns the max length of the payload of a sub-bundle, sent in up to 128 fragments with
+    /// the larger headers of fragmented bundle fragments at the lowest data rate.
+    pub fn max_sub_bundle_payload_len(repeater_compatible: bool) -> usize {
+        (DataRate::Eu863_870Dr0.max_usable_payload_size(repeater_compatible)
+            - FRAGMENTED_BUNDLE_FRAGMENT_END_HEADERS_SIZE)
+            * 128
+    }
+
+    /// Returns the destination of the bundle.
+    pub fn destination(&self) -> EndDeviceId {
+        self.destination
+    }
+
+    /// Returns the source of the bundle.
+    pub fn source(&self) -> EndDeviceId {
+        self.source
+    }
+
+    /// Returns the creation timestamp of the bundle.
+    pub fn timestamp(&self) -> DateTime<Utc> {
+        self.timestamp
+    }
+
+    /// Returns the length of the payload including the part already sent.
+    pub fn payload_len(&self) -> usize {
+        self.payload.len()
+    }
+
+    /// Returns whether the bundle was moved to the front of the queue by an operator.
+    pub fn is_pinned(&self) -> bool {
+        self.pinned
+    }
+
+    /// Sets whether the bundle was moved to the front of the queue by an operator.
+    pub fn set_pinned(&mut self, pinned: bool) {
+        self.pinned = pinned;
+    }
+
+    /// Sets whether the bundle is held back from sending.
+    pub fn set_frozen(&mut self, frozen: bool) {
+        self.frozen = frozen;
+    }
+
+    /// Sets the API client which submitted the bundle.
+    pub fn set_client(&mut self, client: Option<String>) {
+        self.client = client;
+    }
+
+    /// Sets whether the bundle is sent as CBOR encoded BP7 fragments, sub-bundles are always
+    /// sent as fragmented bundle fragments.
+    pub fn set_bp7_framing(&mut self, bp7_framing: bool) {
+        self.bp7_framing = bp7_framing && self.sub_bundle.is_none();
+    }
+
+    /// Returns the position of the payload within the original bundle if the bundle was split.
+    pub fn sub_bundle(&self) -> Option<SubBundle> {
+        self.sub_bundle
+    }
+
+    /// Returns the key of the sub-bundle, `None` if the bundle was not split.
+    pub fn sub_bundle_key(&self) -> Option<SubBundleKey> {
+        self.sub_bundle.map(|sub_bundle| {
+            (
                 self.source,
-                self.timestamp,
-                &mut self.payload,
-                data_rate,
-            )
-            .expect("Payload size checking is wrong");
-            Ok(Box::new(complete_bundle))
-        } else if packet_max_size <= self.payload.len() {
-            let bundle_fragment = BundleFragment::new(
                 self.destination,
-                self.source,
                 self.timestamp,
-                true,
-                self.fragment_index,
-                &mut self.payload,
-                data_rate,
+                sub_bundle.offset,
             )
-            .expect("Payload size checking is wrong");
-            self.fragment_index += 1;
-            Ok(Box::new(bundle_fragment))
+        })
+    }
+
+    /// Returns the priority of the bundle in the bundle queue.
+    pub fn priority(&self) -> BundlePriority {
+        self.routing_hints.priority
+    }
+
+    /// Sets the routing hints of the submitting client.
+    pub fn set_routing_hints(&mut self, routing_hints: RoutingHints) {
+        self.routing_hints = routing_hints;
+    }
+
+    /// Continues the transmission at the persisted progress, returns whether the progress
+    /// belongs to the bundle and was applied.
+    ///
+    /// Progress of another bundle, of a payload with a different length, with an inconsistent
+    /// payload and fragment index or behind the progress of the send buffer is ignored.
+    pub fn resume(&mut self, progress: &SendBufferProgress) -> bool {
+        if progress.destination != self.destination
+            || progress.source != self.source
+            || progress.timestamp != self.timestamp
+            || progress.payload_len != self.payload.len()
+            || progress.fragment_offset != self.sub_bundle.map(|sub_bundle| sub_bundle.offset)
+            || progress.payload_index > self.payload.len()
+            || (progress.fragment_index == 0 && progress.payload_index > 0)
+            || progress.payload_index < self.payload_index
+        {
+            return false;
+        }
+        self.payload_index = progress.payload_index;
+        self.fragment_index = progress.fragment_index;
+        self.data_rate = progress.data_rate;
+        true
+    }
+
+    /// Returns whether the remaining payload can be sent at the data rate without exceeding the
+    /// range of the fragment index.
+    fn fits_fragment_indices(&self, data_rate: DataRate) -> bool {
+        let headers_size = if self.sub_bundle.is_some() {
+            FRAGMENTED_BUNDLE_FRAGMENT_HEADERS_SIZE
         } else {
-            let bundle_fragment = BundleFragment::new(
+            BUNDLE_FRAGMENT_HEADERS_SIZE
+        };
+        let fragment_size = data_rate
+            .max_usable_payload_size(self.repeater_compatible)
+            .saturating_sub(headers_size)
+            .max(1);
+        let remaining = self.payload.len().saturating_sub(self.payload_index);
+        let needed_fragments = remaining
+            .saturating_add(fragment_size - 1)
+            .saturating_div(fragment_size);
+        usize::from(self.fragment_index).saturating_add(needed_fragments)
+            <= usize::from(u8::MAX) + 1
+    }
+
+    /// Returns the next BP7 bundle fitting into a packet at the data rate, the complete bundle
+    /// if nothing was sent yet and it fits, a BP7 fragment otherwise. Returns `None` if not even
+    /// a single payload byte fits next to the BP7 blocks or the complete bundle does not fit and
+    /// the bundle must not be fragmented.
+    fn next_bp7_bundle(&self, data_rate: DataRate) -> Option<(Bp7Bundle, usize)> {
+        // 1B Packet type
+        let available_bytes = data_rate
+            .max_usable_payload_size(self.repeater_compatible)
+            .saturating_sub(1);
+        let create = |payload: &[u8], offset: usize| {
+            Bp7Bundle::new(
                 self.destination,
                 self.source,
                 self.timestamp,
-                false,
-                self.fragment_index,
-                &mut self.payload,
-                data_rate,
+                payload,
+                offset,
+                self.payload.len(),
             )
-            .expect("Payload size checking is wrong");
-            self.fragment_index += 1;
-            Ok(Box::new(bundle_fragment))
+            .ok()
+        };
+        if self.payload_index == 0 {
+            if let Some(complete) =
+                create(&self.payload, 0).filter(|complete| complete.cbor().len() <= available_bytes)
+            {
+                return Some((complete, self.payload.len()));
+            }
         }
+        if self.routing_hints.do_not_fragment {
+            return None;
+        }
+        let overhead = create(&[], self.payload_index)?.cbor().len();
+        // The length prefix of the payload grows by up to 2 bytes with the payload.
+        let fragment_size = available_bytes
+            .saturating_sub(overhead.saturating_add(2))
+            .min(self.payload.len().saturating_sub(self.payload_index));
+        if fragment_size == 0 {
+            return None;
+        }
+        let end = self.payload_index.saturating_add(fragment_size);
+        let fragment = create(&self.payload[self.payload_index..end], self.payload_index)?;
+        Some((fragment, end))
+    }
+
+    /// Returns the next fragmented bundle fragment of a sub-bundle, the fragment carrying the
+    /// rest of the payload is the end fragment carrying the offset and the total length.
+    fn next_sub_bundle_packet(
+        &mut self,
+        sub_bundle: SubBundle,
+        data_rate: DataRate,
+    ) -> Box<dyn LoRaWanPacket> {
+        let usable_size = data_rate.max_usable_payload_size(self.repeater_compatible);
+        let remaining = &self.payload[self.payload_index..];
+        let packet: Box<dyn LoRaWanPacket> =
+            if remaining.len() <= usable_size - FRAGMENTED_BUNDLE_FRAGMENT_END_HEADERS_SIZE {
+                let end = FragmentedBundleFragmentEnd::new(
+                    self.destination,
+                    self.source,
+                    self.timestamp,
+                    self.fragment_index,
+                    sub_bundle.offset,
+                    sub_bundle.total_length,
+                    remaining.to_vec(),
+                );
+                self.payload_index = self.payload.len();
+                Box::new(end)
+            } else {
+                // At least one byte is left for the end fragment.
+                let fragment_size = (usable_size - FRAGMENTED_BUNDLE_FRAGMENT_HEADERS_SIZE)
+                    .min(remaining.len() - 1);
+                let fragment = FragmentedBundleFragment::new(
+                    self.destination,
+                    self.source,
+                    self.timestamp,
+                    self.fragment_index,
+                    sub_bundle.offset,
+                    remaining[..fragment_size].to_vec(),
+                );
+                self.payload_index += fragment_size;
+                Box::new(fragment)
+            };
+        self.fragment_index = self.fragment_index.saturating_add(1);
+        packet
+    }
+}
+
+impl SendBuffer for BundleSendBuffer {
+    fn next_packet(
+        &mut self,
+        data_rate: DataRate,
+    ) -> Result<Box<dyn LoRaWanPacket>, SendBufferError> {
+        if self.is_empty() {
+            return Err(SendBufferError::PayloadConsumed);
+        }
+        if self.bp7_framing {
+            if let Some((bp7_bundle, end)) = self.next_bp7_bundle(data_rate) {
+                self.data_rate = Some(data_rate);
+                self.payload_index = end;
+                self.fragment_index = self.fragment_index.saturating_add(1);
+                return Ok(Box::new(bp7_bundle));
+            }
+            // The custom headers fit at every data rate, fragments sent so far cannot be
+            // combined with them.
+            warn!(
+                "BP7 blocks do not fit into a packet at {data_rate:?}, \
+                 sending the bundle from the start with the custom headers"
+            );
+            self.bp7_framing = false;
+            self.payload_index = 0;
+            self.fragment_index = 0;
+        }
+        // A bundle resumed after a restart may continue at another data rate, the remaining
+        // fragments must still fit into the fragment indices.
+        if self.data_rate.is_some_and(|last| last != data_rate)
+            && !self.fits_fragment_indices(data_rate)
+        {
+            warn!(
+                "Remaining payload does not fit the fragment indices at {data_rate:?}, \
+                 sending the bundle from the start"
+            );
+            self.payload_index = 0;
+            self.fragment_index = 0;
+        }
+        self.data_rate = Some(data_rate);
+        if let Some(sub_bundle) = self.sub_bundle {
+            return Ok(self.next_sub_bundle_packet(sub_bundle, data_rate));
+        }
+        let mut remaining = self.payload[self.payload_index..].to_vec();
+        let remaining_len = remaining.len();
+        let packet_max_size = data_rate.max_usable_payload_size(self.repeater_compatible)
+            - COMPLETE_BUNDLE_HEADERS_SIZE;
+        let packet: Box<dyn LoRaWanPacket> =
+            if self.fragment_index == 0 && remaining_len <= packet_max_size {
+                Box::new(
+                    CompleteBundle::new(
+                        self.destination,
+                        self.source,
+                        self.timestamp,
+                        &mut remaining,
+                        data_rate,
+                        self.repeater_compatible,
+                    )
+                    .expect("Payload size checking is wrong"),
+                )
+            } else {
+                // The fragment carrying the rest of the payload is the end fragment.
+                let is_end = remaining_len
+                    <= data_rate.max_usable_payload_size(self.repeater_compatible)
+                        - BUNDLE_FRAGMENT_HEADERS_SIZE;
+                let bundle_fragment = BundleFragment::new(
+                    self.destination,
+                    self.source,
+                    self.timestamp,
+                    is_end,
+                    self.fragment_index,
+                    &mut remaining,
+                    data_rate,
+                    self.repeater_compatible,
+                )
+                .expect("Payload size checking is wrong");
+                self.fragment_index += 1;
+                Box::new(bundle_fragment)
+            };
+        self.payload_index = self
+            .payload_index
+            .saturating_add(remaining_len.saturating_sub(remaining.len()));
+        Ok(packet)
     }
 
     fn is_empty(&self) -> bool {
-        self.payload.is_empty()
+        self.payload_index >= self.payload.len()
+    }
+
+    fn is_frozen(&self) -> bool {
+        self.frozen
+    }
+
+    fn is_started(&self) -> bool {
+        self.payload_index > 0 && !self.is_empty()
+    }
+
+    fn client(&self) -> Option<&str> {
+        self.client.as_deref()
+    }
+
+    fn routing_hints(&self) -> Option<&RoutingHints> {
+        Some(&self.routing_hints)
+    }
+
+    fn progress(&self) -> Option<SendBufferProgress> {
+        Some(SendBufferProgress {
+            destination: self.destination,
+            source: self.source,
+            timestamp: self.timestamp,
+            payload_len: self.payload.len(),
+            payload_index: self.payload_index,
+            fragment_index: self.fragment_index,
+            data_rate: self.data_rate,
+            fragment_offset: self.sub_bundle.map(|sub_bundle| sub_bundle.offset),
+        })
     }
 }
 
-impl TryFrom<Bundle> for BundleSendBuffer {
-    type Error = BundleSendBufferConversionError;
+impl BundleSendBuffer {
+    /// Creates a [`BundleSendBuffer`] from a [`Bundle`], fragmented for the payload sizes allowed
+    /// with a LoRaWAN repeater if `repeater_compatible` is set.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if:
+    /// - the bundle has no payload.
+    /// - source or destination are no valid end device IDs.
+    /// - the creation timestamp cannot be converted.
+    /// - the payload is too large to be sent.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the DTN time does not fit into an `i64`.
+    pub fn from_bundle(
+        bundle: Bundle,
+        repeater_compatible: bool,
+    ) -> Result<Self, BundleSendBufferConversionError> {
+        let (destination, source, timestamp, payload) = Self::bundle_parts(bundle)?;
+        Ok(BundleSendBuffer::new(
+            destination,
+            source,
+            timestamp,
+            payload,
+            repeater_compatible,
+        )?)
+    }
+
+    /// Splits a [`Bundle`] into sub-bundles of consecutive parts of the payload, sent as
+    /// fragmented bundle fragments and reassembled from the BP7 fragments by the destination.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if:
+    /// - the bundle has no payload.
+    /// - source or destination are no valid end device IDs.
+    /// - the creation timestamp cannot be converted.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the DTN time does not fit into an `i64`.
+    pub fn sub_bundles_from_bundle(
+        bundle: Bundle,
+        repeater_compatible: bool,
+    ) -> Result<Vec<Self>, BundleSendBufferConversionError> {
+        let (destination, source, timestamp, payload) = Self::bundle_parts(bundle)?;
+        let total_length = u64::try_from(payload.len()).unwrap_or(u64::MAX);
+        let chunk_size = Self::max_sub_bundle_payload_len(repeater_compatible);
+        let mut sub_bundles = Vec::new();
+        let mut offset = 0_u64;
+        for chunk in payload.chunks(chunk_size) {
+            sub_bundles.push(BundleSendBuffer::new_sub_bundle(
+                destination,
+                source,
+                timestamp,
+                chunk.to_vec(),
+                SubBundle {
+                    offset,
+                    total_length,
+                },
+                repeater_compatible,
+            )?);
+            offset = offset.saturating_add(u64::try_from(chunk.len()).unwrap_or(u64::MAX));
+        }
+        Ok(sub_bundles)
+    }
 
-    fn try_from(bundle: Bundle) -> Result<Self, Self::Error> {
+    /// Returns the destination, source, creation timestamp and payload of a [`Bundle`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the bundle has no payload, source or destination are no valid end
+    /// device IDs or the creation timestamp cannot be converted.
+    fn bundle_parts(
+        bundle: Bundle,
+    ) -> Result<(EndDeviceId, EndDeviceId, DateTime<Utc>, Vec<u8>), BundleSendBufferConversionError>
+    {
         let payload = if let Some(payload) = bundle.payload() {
             payload.clone()
         } else {
@@ -128,18 +588,276 @@ impl TryFrom<Bundle> for BundleSendBuffer {
         let primary = bundle.primary;
         let source: EndDeviceId = primary.source.try_into()?;
         let destination: EndDeviceId = primary.destination.try_into()?;
-        let Some(naive_time) =
-            NaiveDateTime::from_timestamp_opt(
-                i64::try_from(
-                    primary.creation_timestamp.dtntime().unix()).expect("Dtn time does not fit into i64"), 0) else {
+        let Some(naive_time) = NaiveDateTime::from_timestamp_opt(
+            i64::try_from(primary.creation_timestamp.dtntime().unix())
+                .expect("Dtn time does not fit into i64"),
+            0,
+        ) else {
             return Err(BundleSendBufferConversionError::TryFromTimestampError);
         };
-        let timestamp = DateTime::from_utc(naive_time, Utc);
-        Ok(BundleSendBuffer::new(
+        Ok((
             destination,
             source,
-            timestamp,
+            DateTime::from_utc(naive_time, Utc),
             payload,
-        )?)
+        ))
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use crate::end_device_id::EndDeviceId;
+    use crate::routing_hints::RoutingHints;
+    use crate::send_buffers::{BundleSendBuffer, SendBuffer};
+    use chirpstack_gwb_integration::downlinks::predefined_parameters::DataRate;
+    use chrono::{TimeZone, Utc};
+    use lorawan_dtn_protocol::error::BundleReceiveBufferProcessError;
+    use lorawan_dtn_protocol::reassembly::{Bp7ReceiveBuffer, BundleReceiveBuffer};
+    use lorawan_dtn_protocol::{Bp7Bundle, LoRaWanPacket, PacketType};
+
+    #[test]
+    fn resumes_at_persisted_progress() {
+        let timestamp = Utc::now();
+        let payload: Vec<u8> = (0..=255).collect();
+        let new_buffer = || {
+            BundleSendBuffer::new(
+                EndDeviceId(0x5678),
+                EndDeviceId(0x1234),
+                timestamp,
+                payload.clone(),
+                false,
+            )
+            .unwrap()
+        };
+        let mut sent = new_buffer();
+        sent.next_packet(DataRate::Eu863_870Dr0).unwrap();
+        sent.next_packet(DataRate::Eu863_870Dr0).unwrap();
+        let progress = sent.progress().unwrap();
+
+        let mut restored = new_buffer();
+        assert!(restored.resume(&progress));
+        let expected = sent.next_packet(DataRate::Eu863_870Dr0).unwrap();
+        let resumed = restored.next_packet(DataRate::Eu863_870Dr0).unwrap();
+        assert_eq!(
+            expected.convert_to_lorawan_phy_payload(),
+            resumed.convert_to_lorawan_phy_payload()
+        );
+
+        // Progress behind the send buffer or of another payload is ignored.
+        assert!(!restored.resume(&progress));
+        let mut other = BundleSendBuffer::new(
+            EndDeviceId(0x5678),
+            EndDeviceId(0x1234),
+            timestamp,
+            vec![0; 10],
+            false,
+        )
+        .unwrap();
+        assert!(!other.resume(&progress));
+    }
+
+    #[test]
+    fn bp7_framing_falls_back_to_custom_headers() {
+        let mut send_buffer = BundleSendBuffer::new(
+            EndDeviceId(0x1122_3344),
+            EndDeviceId(0x5566_7788),
+            Utc::now(),
+            vec![0xFF; 300],
+            false,
+        )
+        .unwrap();
+        send_buffer.set_bp7_framing(true);
+        let packet = send_buffer.next_packet(DataRate::Eu863_870Dr5).unwrap();
+        assert_eq!(packet.packet_type(), PacketType::Bp7Bundle);
+
+        // The BP7 blocks alone exceed the packets at DR0, the bundle is sent again from the
+        // start with the custom headers.
+        let packet = send_buffer.next_packet(DataRate::Eu863_870Dr0).unwrap();
+        assert_eq!(packet.packet_type(), PacketType::BundleFragment);
+        assert_eq!(packet.as_bundle_packet().unwrap().fragment_index(), 0);
+    }
+
+    #[test]
+    fn do_not_fragment_sends_a_complete_bundle() {
+        let mut send_buffer = BundleSendBuffer::new(
+            EndDeviceId(0x1122_3344),
+            EndDeviceId(0x5566_7788),
+            Utc::now(),
+            vec![0xFF; 40],
+            false,
+        )
+        .unwrap();
+        send_buffer.set_bp7_framing(true);
+        send_buffer.set_routing_hints(RoutingHints {
+            do_not_fragment: true,
+            ..RoutingHints::default()
+        });
+        // The BP7 bundle would have to be fragmented at DR0, the custom headers fit.
+        let packet = send_buffer.next_packet(DataRate::Eu863_870Dr0).unwrap();
+        assert_eq!(packet.packet_type(), PacketType::CompleteBundle);
+        assert!(send_buffer.is_empty());
+    }
+
+    /// Sends the payload as BP7 fragments, switching to the next data rate after every packet.
+    fn bp7_fragments(payload: &[u8], data_rates: &[DataRate]) -> Vec<bp7::Bundle> {
+        let mut send_buffer = BundleSendBuffer::new(
+            EndDeviceId(0x5678),
+            EndDeviceId(0x1234),
+            Utc.timestamp_opt(1_700_000_000, 0).unwrap(),
+            payload.to_vec(),
+            false,
+        )
+        .unwrap();
+        send_buffer.set_bp7_framing(true);
+        let mut data_rates = data_rates.iter().cycle();
+        let mut fragments = Vec::new();
+        while !send_buffer.is_empty() {
+            let data_rate = *data_rates.next().unwrap();
+            let packet = send_buffer.next_packet(data_rate).unwrap();
+            // 1B MHDR
+            assert!(
+                packet.convert_to_lorawan_phy_payload().len()
+                    <= data_rate.max_usable_payload_size(false) + 1
+            );
+            let bp7_bundle = packet.as_any().downcast_ref::<Bp7Bundle>().unwrap();
+            fragments.push(bp7_bundle.bundle().unwrap());
+        }
+        fragments
+    }
+
+    #[test]
+    fn reassembles_bp7_fragments() {
+        let payload: Vec<u8> = (0..400_u16)
+            .map(|byte| u8::try_from(byte % 251).unwrap())
+            .collect();
+        let mut fragments =
+            bp7_fragments(&payload, &[DataRate::Eu863_870Dr3, DataRate::Eu863_870Dr5]);
+        assert!(fragments.len() > 2);
+        assert!(fragments
+            .iter()
+            .all(|fragment| fragment.primary.has_fragmentation()));
+        fragments.reverse();
+
+        let (first, rest) = fragments.split_first().unwrap();
+        let key = Bp7ReceiveBuffer::key(first);
+        let mut receive_buffer = Bp7ReceiveBuffer::new(first).unwrap();
+        for fragment in rest {
+            assert_eq!(Bp7ReceiveBuffer::key(fragment), key);
+            assert!(!receive_buffer.is_combinable());
+            receive_buffer.process_fragment(fragment).unwrap();
+        }
+        assert_eq!(
+            receive_buffer.process_fragment(first),
+            Err(BundleReceiveBufferProcessError::IndexAlreadyReceived)
+        );
+        assert!(receive_buffer.is_combinable());
+        let bundle = receive_buffer.combine().unwrap();
+        assert!(!bundle.primary.has_fragmentation());
+        assert_eq!(bundle.payload(), Some(&payload));
+        assert_eq!(
+            EndDeviceId::try_from(bundle.primary.destination).unwrap(),
+            EndDeviceId(0x5678)
+        );
+    }
+
+    #[test]
+    fn small_bundles_are_sent_unfragmented() {
+        let fragments = bp7_fragments(b"hello", &[DataRate::Eu863_870Dr5]);
+        assert_eq!(fragments.len(), 1);
+        assert!(!fragments[0].primary.has_fragmentation());
+        assert_eq!(fragments[0].payload(), Some(&b"hello".to_vec()));
+    }
+
+    /// Fragments the payload, switching to the next data rate after every fragment.
+    fn fragments(payload: &[u8], data_rates: &[DataRate]) -> Vec<Box<dyn LoRaWanPacket>> {
+        let mut send_buffer = BundleSendBuffer::new(
+            EndDeviceId(0x5678),
+            EndDeviceId(0x1234),
+            Utc.timestamp_opt(1_700_000_000, 0).unwrap(),
+            payload.to_vec(),
+            false,
+        )
+        .unwrap();
+        let mut data_rates = data_rates.iter().cycle();
+        let mut packets = Vec::new();
+        while !send_buffer.is_empty() {
+            packets.push(
+                send_buffer
+                    .next_packet(*data_rates.next().unwrap())
+                    .unwrap(),
+            );
+        }
+        packets
+    }
+
+    /// Feeds the packets into a receive buffer and returns the reassembled payload.
+    fn reassemble(packets: &mut [Box<dyn LoRaWanPacket>]) -> Vec<u8> {
+        let (first, rest) = packets.split_first_mut().unwrap();
+        let mut receive_buffer = BundleReceiveBuffer::from(first.as_bundle_packet_mut().unwrap());
+        for packet in rest {
+            receive_buffer
+                .process_packet(packet.as_bundle_packet_mut().unwrap())
+                .unwrap();
+        }
+        assert!(receive_buffer.is_combinable());
+        receive_buffer.combine().unwrap().payload().unwrap().clone()
+    }
+
+    #[test]
+    fn reassembles_fragments_of_changing_data_rates() {
+        let payload: Vec<u8> = (0..400_u16)
+            .map(|byte| u8::try_from(byte % 251).unwrap())
+            .collect();
+        let data_rates = [
+            DataRate::Eu863_870Dr0,
+            DataRate::Eu863_870Dr5,
+            DataRate::Eu863_870Dr3,
+        ];
+        let mut packets = fragments(&payload, &data_rates);
+        assert_eq!(packets.len(), 3);
+        assert_eq!(reassemble(&mut packets), payload);
+
+        packets.reverse();
+        assert_eq!(reassemble(&mut packets), payload);
+
+        let mut packets = fragments(&payload, &[DataRate::Eu863_870Dr5, DataRate::Eu863_870Dr0]);
+        let receive_buffer = BundleReceiveBuffer::from(packets[0].as_bundle_packet_mut().unwrap());
+        assert_eq!(receive_buffer.fragment_offsets().get(&0), Some(&0));
+        packets.swap(0, 1);
+        assert_eq!(reassemble(&mut packets), payload);
+    }
+
+    #[test]
+    fn duplicate_fragments_are_told_apart_from_conflicting_ones() {
+        let mut packets = fragments(&[7; 120], &[DataRate::Eu863_870Dr0]);
+        let mut receive_buffer =
+            BundleReceiveBuffer::from(packets[0].as_bundle_packet_mut().unwrap());
+        assert_eq!(
+            receive_buffer.process_packet(packets[0].as_bundle_packet_mut().unwrap()),
+            Err(BundleReceiveBufferProcessError::DuplicateFragment)
+        );
+        let mut conflicting = fragments(&[8; 120], &[DataRate::Eu863_870Dr0]);
+        assert_eq!(
+            receive_buffer.process_packet(conflicting[0].as_bundle_packet_mut().unwrap()),
+            Err(BundleReceiveBufferProcessError::IndexAlreadyReceived)
+        );
+    }
+
+    #[test]
+    fn complete_bundle_is_combinable_and_indices_beyond_end_are_rejected() {
+        let mut packets = fragments(&[1, 2, 3], &[DataRate::Eu863_870Dr0]);
+        assert_eq!(reassemble(&mut packets), vec![1, 2, 3]);
+
+        let mut packets = fragments(&[7; 120], &[DataRate::Eu863_870Dr0]);
+        let end = packets.len() - 1;
+        let mut receive_buffer =
+            BundleReceiveBuffer::from(packets[end].as_bundle_packet_mut().unwrap());
+        let mut beyond_end = fragments(&[7; 200], &[DataRate::Eu863_870Dr0]);
+        assert_eq!(
+            receive_buffer.process_packet(beyond_end[end + 1].as_bundle_packet_mut().unwrap()),
+            Err(BundleReceiveBufferProcessError::IndexBeyondEnd)
+        );
+        assert!(!receive_buffer.is_combinable());
     }
 }
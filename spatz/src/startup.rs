@@ -0,0 +1,457 @@
+//! Ordered startup of the internal tasks with a summary of the outcome of every step.
+//!
+//! Every startup step, e.g. registering a callback at the runtime or spawning a task, declares the
+//! steps it depends on. A step only starts once all of its dependencies started and is skipped if
+//! one of them failed, was skipped or did not run yet, so a wrong order shows up in the summary
+//! instead of as lost uplinks. Fallible steps which are safe to repeat are retried. The summary is
+//! logged once the startup completed and served with the status.
+
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::fmt::Display;
+use std::future::Future;
+use std::time::{Duration, Instant};
+use tracing::{error, info, warn};
+
+/// Connection of the runtime to the MQTT broker.
+pub const RUNTIME: &str = "runtime";
+
+/// Callback forwarding the uplinks to the uplink processor.
+pub const UPLINK_CALLBACK: &str = "uplink_callback";
+
+/// Callback forwarding the downlinks to the duty cycle accounting.
+pub const DOWNLINK_CALLBACK: &str = "downlink_callback";
+
+/// Callback forwarding the gateway stats to the gateway stats collector.
+pub const GATEWAY_STATS_CALLBACK: &str = "gateway_stats_callback";
+
+/// Callback forwarding the downlink acknowledgements to the gateway selection.
+pub const ACK_CALLBACK: &str = "ack_callback";
+
+/// Callback forwarding the gateway locations to the gateway selection.
+pub const GATEWAY_LOCATION_CALLBACK: &str = "gateway_location_callback";
+
+/// Callback forwarding the gateway capabilities to the gateway capabilities task.
+pub const GATEWAY_CAPABILITIES_CALLBACK: &str = "gateway_capabilities_callback";
+
+/// Task listening for repeated MQTT connection errors.
+pub const MQTT_CONNECTION_ERRORS: &str = "mqtt_connection_errors";
+
+/// Task stopping the runtime on shutdown.
+pub const RUNTIME_SHUTDOWN: &str = "runtime_shutdown";
+
+/// Task collecting the relay packets and bundle send buffers into the queues.
+pub const SEND_ITEM_COLLECTOR: &str = "send_item_collector";
+
+/// Task accounting the duty cycle of the downlinks.
+pub const DUTY_CYCLE_COLLECTOR: &str = "duty_cycle_collector";
+
+/// Task evicting buffers exceeding the memory budget.
+pub const MEMORY_BUDGET: &str = "memory_budget";
+
+/// Task replaying a recorded uplink trace.
+pub const UPLINK_TRACE_REPLAY: &str = "uplink_trace_replay";
+
+/// Task parking bundles to unknown destinations.
+pub const BUNDLE_PARKING: &str = "bundle_parking";
+
+/// Task fragmenting the submitted bundles.
+pub const BUNDLES_PROCESSOR: &str = "bundles_processor";
+
+/// Task enqueuing the local announcements.
+pub const ANNOUNCEMENTS: &str = "announcements";
+
+/// Task removing expired neighbor table entries.
+pub const NEIGHBOR_EXPIRY: &str = "neighbor_expiry";
+
+/// Task persisting the gateway stats.
+pub const GATEWAY_STATS_COLLECTOR: &str = "gateway_stats_collector";
+
+/// Task selecting the gateways by their acknowledgements and locations.
+pub const GATEWAY_SELECTION: &str = "gateway_selection";
+
+/// Task probing the transmission capabilities of the gateways.
+pub const GATEWAY_CAPABILITIES: &str = "gateway_capabilities";
+
+/// Task sending the downlinks queued per gateway.
+pub const GATEWAY_SEND_QUEUES: &str = "gateway_send_queues";
+
+/// Task of a WebAssembly plugin, started once per plugin.
+pub const PLUGIN: &str = "plugin";
+
+/// Task submitting the bundles dropped into a directory.
+pub const FILE_DROP: &str = "file_drop";
+
+/// Task writing the packet records of the research export.
+pub const PACKET_EXPORT: &str = "packet_export";
+
+/// Task sending and receiving beacons.
+pub const BEACONING: &str = "beaconing";
+
+/// Task of a webhook, started once per webhook.
+pub const WEBHOOK: &str = "webhook";
+
+/// Task discovering the gateways at the ChirpStack API.
+pub const GATEWAY_DISCOVERY: &str = "gateway_discovery";
+
+/// Task reacting to missed heartbeats.
+pub const WATCHDOG: &str = "watchdog";
+
+/// HTTP server of the API.
+pub const API: &str = "api";
+
+/// Outcome of a startup step.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum StepOutcome {
+    /// The step started.
+    Started,
+    /// The step is not needed with the configuration.
+    Disabled,
+    /// The step did not start as one of its dependencies did not start.
+    Skipped,
+    /// All attempts to start the step failed.
+    Failed,
+}
+
+/// Outcome of a startup step, served with the startup summary.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, JsonSchema)]
+pub struct StepReport {
+    /// Name of the step.
+    pub name: String,
+    /// Steps which had to start before the step.
+    pub dependencies: Vec<String>,
+    /// Outcome of the step.
+    pub outcome: StepOutcome,
+    /// Attempts made to start the step, 0 if it was not attempted.
+    pub attempts: u32,
+    /// Time spent starting the step including the retries in milliseconds.
+    pub duration_ms: u64,
+    /// Why the step is disabled, the dependencies which did not start or the error of the last
+    /// attempt.
+    pub detail: Option<String>,
+}
+
+/// Outcome of all startup steps in the order they were started.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, JsonSchema)]
+pub struct StartupSummary {
+    /// Outcome of every step.
+    pub steps: Vec<StepReport>,
+    /// Time spent starting all steps in milliseconds.
+    pub duration_ms: u64,
+}
+
+impl StartupSummary {
+    /// Returns whether all steps which are not disabled started.
+    pub fn is_complete(&self) -> bool {
+        self.steps
+            .iter()
+            .all(|step| matches!(step.outcome, StepOutcome::Started | StepOutcome::Disabled))
+    }
+}
+
+/// Retries of a fallible startup step.
+#[derive(Debug, Copy, Clone)]
+pub struct Retry {
+    /// Maximum amount of attempts including the first one.
+    pub attempts: u32,
+    /// Delay between two attempts.
+    pub delay: Duration,
+}
+
+/// Starts the startup steps once their dependencies started and records their outcome.
+#[derive(Debug)]
+pub struct StartupSupervisor {
+    /// Start of the startup.
+    started_at: Instant,
+    /// Outcome of the steps so far.
+    steps: Vec<StepReport>,
+}
+
+impl StartupSupervisor {
+    /// Creates a new [`StartupSupervisor`] without any steps.
+    pub fn new() -> Self {
+        Self {
+            started_at: Instant::now(),
+            steps: Vec::new(),
+        }
+    }
+
+    /// Starts the fallible step once all dependencies started, attempting it according to the
+    /// retries.
+    ///
+    /// Returns the result of the first successful attempt, `None` if the step was skipped or all
+    /// attempts failed.
+    pub async fn run<T, E, F, Fut>(
+        &mut self,
+        name: &'static str,
+        dependencies: &[&'static str],
+        retry: Retry,
+        mut attempt: F,
+    ) -> Option<T>
+    where
+        E: Display,
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        if !self.dependencies_started(name, dependencies) {
+            return None;
+        }
+        let start = Instant::now();
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            match attempt().await {
+                Ok(value) => {
+                    self.record(
+                        name,
+                        dependencies,
+                        StepOutcome::Started,
+                        attempts,
+                        start,
+                        None,
+                    );
+                    return Some(value);
+                }
+                Err(err) if attempts < retry.attempts => {
+                    warn!(
+                        step = name,
+                        attempts, "Startup step failed, retrying: {err}"
+                    );
+                    tokio::time::sleep(retry.delay).await;
+                }
+                Err(err) => {
+                    error!(step = name, attempts, "Startup step failed: {err}");
+                    let detail = Some(err.to_string());
+                    self.record(
+                        name,
+                        dependencies,
+                        StepOutcome::Failed,
+                        attempts,
+                        start,
+                        detail,
+                    );
+                    return None;
+                }
+            }
+        }
+    }
+
+    /// Starts the infallible step, e.g. spawning a task, once all dependencies started.
+    ///
+    /// Returns the result of the step, `None` if the step was skipped.
+    pub fn start<T>(
+        &mut self,
+        name: &'static str,
+        dependencies: &[&'static str],
+        start: impl FnOnce() -> T,
+    ) -> Option<T> {
+        if !self.dependencies_started(name, dependencies) {
+            return None;
+        }
+        let started_at = Instant::now();
+        let value = start();
+        self.record(
+            name,
+            dependencies,
+            StepOutcome::Started,
+            1,
+            started_at,
+            None,
+        );
+        Some(value)
+    }
+
+    /// Records a step which is not needed with the configuration.
+    pub fn disable(&mut self, name: &'static str, reason: &str) {
+        self.record(
+            name,
+            &[],
+            StepOutcome::Disabled,
+            0,
+            Instant::now(),
+            Some(reason.to_owned()),
+        );
+    }
+
+    /// Returns the outcome of the steps so far.
+    pub fn summary(&self) -> StartupSummary {
+        StartupSummary {
+            steps: self.steps.clone(),
+            duration_ms: millis(self.started_at.elapsed()),
+        }
+    }
+
+    /// Returns the outcome of all steps and logs it, steps which did not start as warning.
+    pub fn finish(self) -> StartupSummary {
+        let summary = self.summary();
+        for step in &summary.steps {
+            if matches!(step.outcome, StepOutcome::Skipped | StepOutcome::Failed) {
+                warn!(
+                    step = %step.name,
+                    outcome = ?step.outcome,
+                    detail = ?step.detail,
+                    "Startup step did not start"
+                );
+            }
+        }
+        let count = |outcome| {
+            summary
+                .steps
+                .iter()
+                .filter(|step| step.outcome == outcome)
+                .count()
+        };
+        info!(
+            started = count(StepOutcome::Started),
+            disabled = count(StepOutcome::Disabled),
+            skipped = count(StepOutcome::Skipped),
+            failed = count(StepOutcome::Failed),
+            duration_ms = summary.duration_ms,
+            "Startup completed"
+        );
+        summary
+    }
+
+    /// Returns whether all dependencies of the step started, records the step as skipped
+    /// otherwise.
+    fn dependencies_started(&mut self, name: &'static str, dependencies: &[&'static str]) -> bool {
+        let missing: Vec<&str> = dependencies
+            .iter()
+            .copied()
+            .filter(|dependency| {
+                !self
+                    .steps
+                    .iter()
+                    .any(|step| step.name == *dependency && step.outcome == StepOutcome::Started)
+            })
+            .collect();
+        if missing.is_empty() {
+            return true;
+        }
+        let detail = Some(format!(
+            "Dependencies did not start: {}",
+            missing.join(", ")
+        ));
+        self.record(
+            name,
+            dependencies,
+            StepOutcome::Skipped,
+            0,
+            Instant::now(),
+            detail,
+        );
+        false
+    }
+
+    /// Records the outcome of the step.
+    fn record(
+        &mut self,
+        name: &'static str,
+        dependencies: &[&'static str],
+        outcome: StepOutcome,
+        attempts: u32,
+        started_at: Instant,
+        detail: Option<String>,
+    ) {
+        self.steps.push(StepReport {
+            name: name.to_owned(),
+            dependencies: dependencies
+                .iter()
+                .map(|dependency| (*dependency).to_owned())
+                .collect(),
+            outcome,
+            attempts,
+            duration_ms: millis(started_at.elapsed()),
+            detail,
+        });
+    }
+}
+
+impl Default for StartupSupervisor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Converts the duration into milliseconds, saturating on overflow.
+fn millis(duration: Duration) -> u64 {
+    u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use crate::startup::{Retry, StartupSupervisor, StepOutcome};
+    use std::time::Duration;
+
+    #[test]
+    fn steps_start_after_their_dependencies() {
+        let mut supervisor = StartupSupervisor::new();
+        assert_eq!(supervisor.start("b", &["a"], || 2), None);
+        assert_eq!(supervisor.start("a", &[], || 1), Some(1));
+        assert_eq!(supervisor.start("c", &["a"], || 3), Some(3));
+        supervisor.disable("d", "not configured");
+        assert_eq!(supervisor.start("e", &["d"], || 5), None);
+
+        let summary = supervisor.finish();
+        let outcomes: Vec<(&str, StepOutcome)> = summary
+            .steps
+            .iter()
+            .map(|step| (step.name.as_str(), step.outcome))
+            .collect();
+        assert_eq!(
+            outcomes,
+            vec![
+                ("b", StepOutcome::Skipped),
+                ("a", StepOutcome::Started),
+                ("c", StepOutcome::Started),
+                ("d", StepOutcome::Disabled),
+                ("e", StepOutcome::Skipped),
+            ]
+        );
+        assert_eq!(
+            summary.steps[0].detail.as_deref(),
+            Some("Dependencies did not start: a")
+        );
+        assert!(!summary.is_complete());
+    }
+
+    #[tokio::test]
+    async fn failed_steps_are_retried() {
+        let retry = Retry {
+            attempts: 3,
+            delay: Duration::ZERO,
+        };
+        let mut supervisor = StartupSupervisor::new();
+        let mut failures = 2;
+        let value = supervisor
+            .run("flaky", &[], retry, || {
+                let result = if failures > 0 {
+                    failures -= 1;
+                    Err("unavailable")
+                } else {
+                    Ok(42)
+                };
+                async move { result }
+            })
+            .await;
+        assert_eq!(value, Some(42));
+        assert!(supervisor
+            .run("broken", &[], retry, || async {
+                Err::<(), _>("unavailable")
+            })
+            .await
+            .is_none());
+        assert_eq!(supervisor.start("dependent", &["broken"], || ()), None);
+
+        let summary = supervisor.summary();
+        assert_eq!(summary.steps[0].outcome, StepOutcome::Started);
+        assert_eq!(summary.steps[0].attempts, 3);
+        assert_eq!(summary.steps[1].outcome, StepOutcome::Failed);
+        assert_eq!(summary.steps[1].attempts, 3);
+        assert_eq!(summary.steps[1].detail.as_deref(), Some("unavailable"));
+        assert_eq!(summary.steps[2].outcome, StepOutcome::Skipped);
+        assert!(!summary.is_complete());
+    }
+}
@@ -0,0 +1,513 @@
+//! Bundle status reports per BP7 status report semantics, sent as compact protocol packets.
+//!
+//! The custom headers carry no bundle control flags, so the source node sends a
+//! [`StatusReportRequest`] along with every queued bundle requesting the reception, delivery or
+//! deletion report. BP7 bundles received as fragments carry the flags themselves. The destination
+//! node sends a [`StatusReport`] to the report-to end device ID of the bundle once it reassembled
+//! the bundle, delivered it to a local application or dropped it. The node of the report-to end
+//! device ID delivers the report to its local applications as a BP7 administrative record.
+//!
+//! Relays forward requests and reports like any other packet addressed to another node, they do
+//! not send forwarding reports.
+
+use crate::end_device_id::EndDeviceId;
+use crate::error::StatusReportError;
+use crate::packet_cache::PacketSource;
+use crate::AppState;
+use bp7::flags::{BlockControlFlags, BundleControlFlags, BundleControlFlagsType};
+use chirpstack_gwb_integration::downlinks::predefined_parameters::DataRate;
+use chrono::{DateTime, TimeZone, Utc};
+use lorawan_dtn_protocol::unix_ts_to_dtn_time;
+use lorawan_dtn_protocol::{
+    LoRaWanPacket, StatusReport, StatusReportRequest, BUNDLE_LIFETIME, STATUS_DELETED,
+    STATUS_DELIVERED, STATUS_RECEIVED,
+};
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use tracing::{error, trace};
+
+/// Data rate requests and reports are sent with.
+const STATUS_REPORT_DATA_RATE: DataRate = DataRate::Eu863_870Dr3;
+
+/// BP7 status report reason code if no further information is available.
+pub const REASON_NO_INFORMATION: u8 = 0;
+
+/// BP7 status report reason code of bundles whose lifetime expired.
+pub const REASON_LIFETIME_EXPIRED: u8 = 1;
+
+/// BP7 administrative record type code of bundle status reports.
+const BUNDLE_STATUS_REPORT_RECORD_TYPE: u64 = 1;
+
+/// Counters of the status reports.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Serialize, JsonSchema)]
+pub struct StatusReportStats {
+    /// Requests sent along with queued bundles.
+    pub requests_sent: u64,
+    /// Requests received for bundles addressed to this node.
+    pub requests_received: u64,
+    /// Requests awaiting their bundle.
+    pub pending_requests: u64,
+    /// Reports sent about bundles addressed to this node.
+    pub reports_sent: u64,
+    /// Reports received and delivered to local applications.
+    pub reports_delivered: u64,
+}
+
+/// Reports requested for a bundle received by this node.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct RequestedReports {
+    /// End device ID the reports are sent to.
+    report_to: EndDeviceId,
+    /// End device ID the reports are sent from, the destination of the bundle.
+    reporter: EndDeviceId,
+    /// Bit set of the requested reports.
+    requested_reports: u8,
+    /// Source of the bundle.
+    bundle_source: EndDeviceId,
+    /// Timestamp of the bundle.
+    bundle_timestamp: DateTime<Utc>,
+}
+
+impl RequestedReports {
+    /// Returns the report of the status, `None` if the status was not requested.
+    pub fn report(&self, status: u8, reason: u8) -> Option<StatusReport> {
+        (self.requested_reports & status != 0).then_some(StatusReport {
+            destination: self.report_to,
+            source: self.reporter,
+            status,
+            reason,
+            bundle_source: self.bundle_source,
+            bundle_timestamp: self.bundle_timestamp,
+        })
+    }
+}
+
+/// Key of a request: source and timestamp of its bundle.
+type RequestKey = (EndDeviceId, DateTime<Utc>);
+
+/// Request received before its bundle.
+#[derive(Debug, Clone, Copy)]
+struct PendingRequest {
+    /// End device ID the reports are sent to.
+    report_to: EndDeviceId,
+    /// Bit set of the requested reports.
+    requested_reports: u8,
+    /// Time the request was received.
+    received: DateTime<Utc>,
+}
+
+/// Requests awaiting their bundle and counters of the status reports.
+///
+/// Uses a [`std::sync::Mutex`] as the receive buffers deliver bundles outside of an async
+/// context.
+#[derive(Debug, Default)]
+pub struct StatusReports {
+    /// Received requests by source and timestamp of their bundle.
+    pending: Mutex<HashMap<RequestKey, PendingRequest>>,
+    /// Requests sent along with queued bundles.
+    requests_sent: AtomicU64,
+    /// Requests received for bundles addressed to this node.
+    requests_received: AtomicU64,
+    /// Reports sent about bundles addressed to this node.
+    reports_sent: AtomicU64,
+    /// Reports received and delivered to local applications.
+    reports_delivered: AtomicU64,
+}
+
+impl StatusReports {
+    /// Creates a new [`StatusReports`] without pending requests.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stores a request received for a bundle addressed to this node until the bundle is
+    /// reassembled, requests older than the bundle lifetime are removed.
+    pub fn process_request(&self, request: &StatusReportRequest, now: DateTime<Utc>) {
+        self.requests_received.fetch_add(1, Ordering::Relaxed);
+        let mut pending = self.lock();
+        remove_expired(&mut pending, now);
+        pending.insert(
+            (request.source, request.timestamp),
+            PendingRequest {
+                report_to: request.report_to,
+                requested_reports: request.requested_reports,
+                received: now,
+            },
+        );
+    }
+
+    /// Returns the reports requested for a received bundle, either by a received request or by
+    /// the control flags of the bundle. A received request is removed, so every bundle is only
+    /// reported once.
+    pub fn take_requested(&self, bundle: &bp7::Bundle) -> Option<RequestedReports> {
+        let bundle_source = EndDeviceId::try_from(bundle.primary.source.clone()).ok()?;
+        let bundle_timestamp = creation_time(bundle)?;
+        let reporter = EndDeviceId::try_from(bundle.primary.destination.clone()).ok()?;
+        let request = self.lock().remove(&(bundle_source, bundle_timestamp));
+        let (report_to, requested_reports) = match request {
+            Some(request) => (request.report_to, request.requested_reports),
+            None => (
+                report_to(bundle)?,
+                requested_reports(bundle.primary.bundle_control_flags),
+            ),
+        };
+        (requested_reports != 0).then_some(RequestedReports {
+            report_to,
+            reporter,
+            requested_reports,
+            bundle_source,
+            bundle_timestamp,
+        })
+    }
+
+    /// Returns the counters of the status reports.
+    pub fn stats(&self) -> StatusReportStats {
+        StatusReportStats {
+            requests_sent: self.requests_sent.load(Ordering::Relaxed),
+            requests_received: self.requests_received.load(Ordering::Relaxed),
+            pending_requests: u64::try_from(self.lock().len()).unwrap_or(u64::MAX),
+            reports_sent: self.reports_sent.load(Ordering::Relaxed),
+            reports_delivered: self.reports_delivered.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Locks the pending requests, a poisoned lock is still used as the requests stay valid.
+    fn lock(&self) -> MutexGuard<'_, HashMap<RequestKey, PendingRequest>> {
+        self.pending.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Removes the requests received longer than the bundle lifetime ago.
+fn remove_expired(pending: &mut HashMap<RequestKey, PendingRequest>, now: DateTime<Utc>) {
+    let lifetime = chrono::Duration::from_std(BUNDLE_LIFETIME).unwrap_or(chrono::Duration::MAX);
+    pending.retain(|_, request| now.signed_duration_since(request.received) < lifetime);
+}
+
+/// Returns the creation time of the bundle in seconds precision, as sent in the custom headers.
+pub fn creation_time(bundle: &bp7::Bundle) -> Option<DateTime<Utc>> {
+    let seconds = i64::try_from(bundle.primary.creation_timestamp.dtntime().unix()).ok()?;
+    Utc.timestamp_opt(seconds, 0).single()
+}
+
+/// Returns the report-to end device ID of the bundle, the source if the bundle has no report-to
+/// end device ID.
+fn report_to(bundle: &bp7::Bundle) -> Option<EndDeviceId> {
+    EndDeviceId::try_from(bundle.primary.report_to.clone())
+        .or_else(|_| EndDeviceId::try_from(bundle.primary.source.clone()))
+        .ok()
+}
+
+/// Returns the bit set of the reports requested by the bundle control flags.
+pub fn requested_reports(flags: BundleControlFlagsType) -> u8 {
+    let flags = BundleControlFlags::from_bits_truncate(flags);
+    [
+        (
+            BundleControlFlags::BUNDLE_STATUS_REQUEST_RECEPTION,
+            STATUS_RECEIVED,
+        ),
+        (
+            BundleControlFlags::BUNDLE_STATUS_REQUEST_DELIVERY,
+            STATUS_DELIVERED,
+        ),
+        (
+            BundleControlFlags::BUNDLE_STATUS_REQUEST_DELETION,
+            STATUS_DELETED,
+        ),
+    ]
+    .into_iter()
+    .filter(|(flag, _)| flags.contains(*flag))
+    .fold(0, |requested, (_, status)| requested | status)
+}
+
+/// Returns the request to send along with a submitted bundle, `None` if the bundle requests no
+/// reports or its end points are no end device IDs.
+pub fn request_for(bundle: &bp7::Bundle) -> Option<StatusReportRequest> {
+    let requested_reports = requested_reports(bundle.primary.bundle_control_flags);
+    if requested_reports == 0 {
+        return None;
+    }
+    Some(StatusReportRequest {
+        destination: EndDeviceId::try_from(bundle.primary.destination.clone()).ok()?,
+        source: EndDeviceId::try_from(bundle.primary.source.clone()).ok()?,
+        timestamp: creation_time(bundle)?,
+        report_to: report_to(bundle)?,
+        requested_reports,
+    })
+}
+
+/// Enqueues a request of a queued bundle and adds it to the packet cache to not relay it again.
+pub async fn send_request(state: &AppState, request: StatusReportRequest) {
+    trace!("Sending status report request: {request:?}");
+    if enqueue(state, Box::new(request)).await {
+        state
+            .status_reports
+            .requests_sent
+            .fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Sends the report of the status in the background if it was requested for the bundle.
+pub fn send_report(
+    state: &Arc<AppState>,
+    requested: Option<&RequestedReports>,
+    status: u8,
+    reason: u8,
+) {
+    let Some(report) = requested.and_then(|requested| requested.report(status, reason)) else {
+        return;
+    };
+    let state = state.clone();
+    tokio::spawn(async move {
+        trace!("Sending status report: {report:?}");
+        if enqueue(&state, Box::new(report)).await {
+            state
+                .status_reports
+                .reports_sent
+                .fetch_add(1, Ordering::Relaxed);
+        }
+    });
+}
+
+/// Enqueues the packet in the relay queue, returns whether it was enqueued.
+async fn enqueue(state: &AppState, packet: Box<dyn LoRaWanPacket>) -> bool {
+    let phy_payload = packet.convert_to_lorawan_phy_payload();
+    if !state
+        .queue_manager
+        .enqueue_relay_packet(packet, STATUS_REPORT_DATA_RATE)
+        .await
+    {
+        error!("Relay queue full, status report packet dropped");
+        return false;
+    }
+    let _ = state
+        .packet_cache
+        .insert(&phy_payload, PacketSource::Local)
+        .await;
+    true
+}
+
+/// Delivers a received report to the local applications as BP7 administrative record.
+pub fn deliver_report(state: &AppState, report: &StatusReport) {
+    let bundle = match administrative_record(report, Utc::now()) {
+        Ok(bundle) => bundle,
+        Err(err) => {
+            error!(%err);
+            return;
+        }
+    };
+    let mut delivered = false;
+    if let Some(bundle_mailbox) = &state.bundle_mailbox {
+        bundle_mailbox.store(&bundle, Utc::now());
+        delivered = true;
+    }
+    if state.bundles_to_ws.receiver_count() > 0 {
+        match state.bundles_to_ws.send(bundle) {
+            Ok(_) => delivered = true,
+            Err(err) => error!(%err),
+        }
+    } else if !delivered {
+        error!("No WS client connected, status report dropped");
+    }
+    if delivered {
+        state
+            .status_reports
+            .reports_delivered
+            .fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Status of a BP7 status report, whether the status is asserted.
+type StatusItem = (bool,);
+
+/// Content of a BP7 bundle status report: status of reception, forwarding, delivery and
+/// deletion, reason code, source and creation timestamp of the bundle.
+type StatusReportContent<'a> = (
+    [StatusItem; 4],
+    u8,
+    &'a bp7::EndpointID,
+    &'a bp7::CreationTimestamp,
+);
+
+/// Converts a report into a bundle carrying the BP7 administrative record of the report.
+///
+/// # Errors
+///
+/// Returns an error if:
+/// - the end device IDs cannot be converted to endpoint IDs.
+/// - the primary block cannot be built.
+/// - the administrative record cannot be encoded.
+pub fn administrative_record(
+    report: &StatusReport,
+    now: DateTime<Utc>,
+) -> Result<bp7::Bundle, StatusReportError> {
+    let bundle_source: bp7::EndpointID = report.bundle_source.try_into()?;
+    let bundle_timestamp = bp7::CreationTimestamp::with_time_and_seq(
+        unix_ts_to_dtn_time(report.bundle_timestamp.timestamp().unsigned_abs()),
+        0,
+    );
+    let asserted = |status| (report.status & status != 0,);
+    // Relays send no forwarding reports.
+    let content: StatusReportContent = (
+        [
+            asserted(STATUS_RECEIVED),
+            (false,),
+            asserted(STATUS_DELIVERED),
+            asserted(STATUS_DELETED),
+        ],
+        report.reason,
+        &bundle_source,
+        &bundle_timestamp,
+    );
+    let record = serde_cbor::to_vec(&(BUNDLE_STATUS_REPORT_RECORD_TYPE, content))?;
+
+    let primary_block = bp7::primary::PrimaryBlockBuilder::new()
+        .source(report.source.try_into()?)
+        .destination(report.destination.try_into()?)
+        .report_to(report.source.try_into()?)
+        .creation_timestamp(bp7::CreationTimestamp::with_time_and_seq(
+            unix_ts_to_dtn_time(now.timestamp().unsigned_abs()),
+            0,
+        ))
+        .lifetime(BUNDLE_LIFETIME)
+        .bundle_control_flags(BundleControlFlags::BUNDLE_ADMINISTRATIVE_RECORD_PAYLOAD.bits())
+        .build()?;
+    let canonical = bp7::canonical::new_payload_block(BlockControlFlags::empty(), record);
+    Ok(bp7::Bundle::new(primary_block, vec![canonical]))
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use crate::end_device_id::EndDeviceId;
+    use crate::status_reports::{
+        administrative_record, request_for, StatusReports, REASON_LIFETIME_EXPIRED,
+        REASON_NO_INFORMATION,
+    };
+    use bp7::flags::{BlockControlFlags, BundleControlFlags};
+    use chrono::{TimeZone, Utc};
+    use lorawan_dtn_protocol::unix_ts_to_dtn_time;
+    use lorawan_dtn_protocol::{
+        StatusReport, StatusReportRequest, STATUS_DELETED, STATUS_DELIVERED, STATUS_RECEIVED,
+    };
+    use serde_cbor::Value;
+
+    fn bundle(flags: BundleControlFlags) -> bp7::Bundle {
+        let primary_block = bp7::primary::PrimaryBlockBuilder::new()
+            .source(EndDeviceId(0x1234).try_into().unwrap())
+            .destination(EndDeviceId(0x5678).try_into().unwrap())
+            .report_to(EndDeviceId(0x9abc).try_into().unwrap())
+            .creation_timestamp(bp7::CreationTimestamp::with_time_and_seq(
+                unix_ts_to_dtn_time(1_700_000_000),
+                0,
+            ))
+            .lifetime(std::time::Duration::from_secs(3600))
+            .bundle_control_flags(flags.bits())
+            .build()
+            .unwrap();
+        let canonical =
+            bp7::canonical::new_payload_block(BlockControlFlags::empty(), b"hello".to_vec());
+        bp7::Bundle::new(primary_block, vec![canonical])
+    }
+
+    #[test]
+    fn requested_reports_follow_requests_and_flags() {
+        let timestamp = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        let status_reports = StatusReports::new();
+        assert_eq!(request_for(&bundle(BundleControlFlags::empty())), None);
+        assert!(status_reports
+            .take_requested(&bundle(BundleControlFlags::empty()))
+            .is_none());
+
+        let request = request_for(&bundle(
+            BundleControlFlags::BUNDLE_STATUS_REQUEST_DELIVERY
+                | BundleControlFlags::BUNDLE_STATUS_REQUEST_DELETION,
+        ))
+        .unwrap();
+        assert_eq!(
+            request,
+            StatusReportRequest {
+                destination: EndDeviceId(0x5678),
+                source: EndDeviceId(0x1234),
+                timestamp,
+                report_to: EndDeviceId(0x9abc),
+                requested_reports: STATUS_DELIVERED | STATUS_DELETED,
+            }
+        );
+
+        // Bundles reassembled from the custom headers carry no flags.
+        status_reports.process_request(&request, Utc::now());
+        let requested = status_reports
+            .take_requested(&bundle(BundleControlFlags::empty()))
+            .unwrap();
+        assert_eq!(
+            requested.report(STATUS_RECEIVED, REASON_NO_INFORMATION),
+            None
+        );
+        assert_eq!(
+            requested.report(STATUS_DELIVERED, REASON_NO_INFORMATION),
+            Some(StatusReport {
+                destination: EndDeviceId(0x9abc),
+                source: EndDeviceId(0x5678),
+                status: STATUS_DELIVERED,
+                reason: REASON_NO_INFORMATION,
+                bundle_source: EndDeviceId(0x1234),
+                bundle_timestamp: timestamp,
+            })
+        );
+        assert!(status_reports
+            .take_requested(&bundle(BundleControlFlags::empty()))
+            .is_none());
+
+        let requested = status_reports
+            .take_requested(&bundle(BundleControlFlags::BUNDLE_STATUS_REQUEST_RECEPTION))
+            .unwrap();
+        assert!(requested
+            .report(STATUS_RECEIVED, REASON_NO_INFORMATION)
+            .is_some());
+        assert_eq!(status_reports.stats().requests_received, 1);
+        assert_eq!(status_reports.stats().pending_requests, 0);
+    }
+
+    #[test]
+    fn reports_are_delivered_as_administrative_records() {
+        let report = StatusReport {
+            destination: EndDeviceId(0x9abc),
+            source: EndDeviceId(0x5678),
+            status: STATUS_DELETED,
+            reason: REASON_LIFETIME_EXPIRED,
+            bundle_source: EndDeviceId(0x1234),
+            bundle_timestamp: Utc.timestamp_opt(1_700_000_000, 0).unwrap(),
+        };
+        let bundle = administrative_record(&report, Utc::now()).unwrap();
+        assert!(
+            BundleControlFlags::from_bits_truncate(bundle.primary.bundle_control_flags)
+                .contains(BundleControlFlags::BUNDLE_ADMINISTRATIVE_RECORD_PAYLOAD)
+        );
+        assert_eq!(
+            EndDeviceId::try_from(bundle.primary.destination.clone()).unwrap(),
+            EndDeviceId(0x9abc)
+        );
+
+        let record: Value = serde_cbor::from_slice(bundle.payload().unwrap()).unwrap();
+        let Value::Array(record) = record else {
+            panic!("Administrative record is no array");
+        };
+        assert_eq!(record[0], Value::Integer(1));
+        let Value::Array(content) = &record[1] else {
+            panic!("Status report is no array: {:?}", record[1]);
+        };
+        let asserted =
+            |item: &Value| matches!(item, Value::Array(item) if item[0] == Value::Bool(true));
+        let Value::Array(status_items) = &content[0] else {
+            panic!("Status information is no array: {:?}", content[0]);
+        };
+        assert_eq!(
+            status_items.iter().map(asserted).collect::<Vec<_>>(),
+            vec![false, false, false, true]
+        );
+        assert_eq!(content[1], Value::Integer(1));
+    }
+}
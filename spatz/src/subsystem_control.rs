@@ -0,0 +1,68 @@
+//! Runtime switches pausing the relaying of foreign packets and the announcements independently,
+//! e.g. to silence a node during a nearby licensed operation.
+//!
+//! Unlike a radio silence, the own bundles are still sent and bundles addressed to local
+//! services are still received and delivered. The switches are persisted and survive restarts.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use tracing::info;
+
+/// Paused subsystems, persisted in the database.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct SubsystemControlStatus {
+    /// Whether packets for other nodes are neither queued nor relayed.
+    pub relaying_paused: bool,
+    /// Whether announcements are neither enqueued nor sent.
+    pub announcements_paused: bool,
+}
+
+/// Switches pausing the relaying and the announcements.
+#[derive(Debug, Default)]
+pub struct SubsystemControl {
+    /// Whether the relaying is paused.
+    relaying_paused: AtomicBool,
+    /// Whether the announcements are paused.
+    announcements_paused: AtomicBool,
+}
+
+impl SubsystemControl {
+    /// Creates a new [`SubsystemControl`] restoring the persisted switches.
+    pub fn new(status: SubsystemControlStatus) -> Self {
+        Self {
+            relaying_paused: AtomicBool::new(status.relaying_paused),
+            announcements_paused: AtomicBool::new(status.announcements_paused),
+        }
+    }
+
+    /// Returns whether the relaying is paused.
+    pub fn relaying_paused(&self) -> bool {
+        self.relaying_paused.load(Ordering::Relaxed)
+    }
+
+    /// Returns whether the announcements are paused.
+    pub fn announcements_paused(&self) -> bool {
+        self.announcements_paused.load(Ordering::Relaxed)
+    }
+
+    /// Pauses or resumes the relaying, takes effect immediately.
+    pub fn set_relaying_paused(&self, paused: bool) {
+        info!(paused, "Relaying paused changed");
+        self.relaying_paused.store(paused, Ordering::Relaxed);
+    }
+
+    /// Pauses or resumes the announcements, takes effect immediately.
+    pub fn set_announcements_paused(&self, paused: bool) {
+        info!(paused, "Announcements paused changed");
+        self.announcements_paused.store(paused, Ordering::Relaxed);
+    }
+
+    /// Returns the current switches.
+    pub fn status(&self) -> SubsystemControlStatus {
+        SubsystemControlStatus {
+            relaying_paused: self.relaying_paused(),
+            announcements_paused: self.announcements_paused(),
+        }
+    }
+}
@@ -0,0 +1,274 @@
+//! Graph of this node, its gateways and the neighbors known from announcements, e.g. for
+//! visualization with Graphviz or in a web UI.
+//!
+//! The graph only contains the current state, the neighbor table is not persisted.
+
+use crate::end_device_id::EndDeviceId;
+use crate::neighbor_table::{NeighborEntry, Reachability, SignalQuality};
+use chirpstack_gwb_integration::gateway_id::GatewayId;
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+/// ID of the node of this Spatz instance.
+const LOCAL_NODE_ID: &str = "local";
+
+/// Kind of a node of the topology.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TopologyNodeKind {
+    /// This Spatz instance with its end device IDs.
+    Local,
+    /// A gateway connected to this instance.
+    Gateway,
+    /// An end device ID announced by a neighbor.
+    Neighbor,
+}
+
+/// Node of the topology.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, JsonSchema)]
+pub struct TopologyNode {
+    /// Unique ID of the node, e.g. `gateway:0102030405060708` or `neighbor:1234`.
+    pub id: String,
+    /// Kind of the node.
+    pub kind: TopologyNodeKind,
+    /// Human readable label of the node.
+    pub label: String,
+}
+
+/// Link between two nodes of the topology, links to neighbors carry the state of the last
+/// announcement.
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+pub struct TopologyLink {
+    /// ID of the node the link starts at.
+    pub from: String,
+    /// ID of the node the link ends at.
+    pub to: String,
+    /// Whether the end device ID is registered at the neighbor or proxied by it.
+    pub reachability: Option<Reachability>,
+    /// Amount of hops to the node the end device ID is registered at.
+    pub hop_distance: Option<u8>,
+    /// Signal quality of the last announcement.
+    pub signal_quality: Option<SignalQuality>,
+    /// Time of the last announcement.
+    pub last_seen: Option<DateTime<Utc>>,
+}
+
+/// Graph of this node, its gateways and the known neighbors.
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+pub struct Topology {
+    /// Time the graph was generated.
+    pub generated_at: DateTime<Utc>,
+    /// Nodes ordered by kind and ID.
+    pub nodes: Vec<TopologyNode>,
+    /// Links to the gateways followed by the links to the neighbors.
+    pub links: Vec<TopologyLink>,
+}
+
+/// Returns the node ID of a gateway.
+fn gateway_node_id(gateway_id: &str) -> String {
+    format!("gateway:{gateway_id}")
+}
+
+/// Returns the node ID of an end device ID announced by a neighbor.
+fn neighbor_node_id(end_device_id: EndDeviceId) -> String {
+    format!("neighbor:{}", end_device_id.0)
+}
+
+/// Escapes a string for a quoted DOT ID.
+fn escape_dot(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+impl Topology {
+    /// Creates the graph from the local end device IDs, the connected gateways and the neighbor
+    /// table. Gateways which received announcements but are no longer connected are included.
+    pub fn new(
+        local_end_device_ids: &[EndDeviceId],
+        gateway_ids: &[GatewayId],
+        neighbors: &HashMap<EndDeviceId, NeighborEntry>,
+        generated_at: DateTime<Utc>,
+    ) -> Self {
+        let mut local_end_device_ids = local_end_device_ids.to_vec();
+        local_end_device_ids.sort_unstable_by_key(|end_device_id| end_device_id.0);
+        let local_label = local_end_device_ids
+            .iter()
+            .map(|end_device_id| end_device_id.0.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let mut nodes = vec![TopologyNode {
+            id: LOCAL_NODE_ID.to_owned(),
+            kind: TopologyNodeKind::Local,
+            label: format!("This node ({local_label})"),
+        }];
+
+        let mut gateway_ids = gateway_ids.to_vec();
+        gateway_ids.extend(neighbors.values().map(|entry| entry.gateway_id.clone()));
+        gateway_ids.sort_unstable();
+        gateway_ids.dedup();
+        let mut links = Vec::new();
+        for gateway_id in gateway_ids {
+            links.push(TopologyLink {
+                from: LOCAL_NODE_ID.to_owned(),
+                to: gateway_node_id(&gateway_id),
+                reachability: None,
+                hop_distance: None,
+                signal_quality: None,
+                last_seen: None,
+            });
+            nodes.push(TopologyNode {
+                id: gateway_node_id(&gateway_id),
+                kind: TopologyNodeKind::Gateway,
+                label: gateway_id.to_string(),
+            });
+        }
+
+        let mut neighbors: Vec<_> = neighbors.iter().collect();
+        neighbors.sort_unstable_by_key(|(end_device_id, _)| end_device_id.0);
+        for (end_device_id, entry) in neighbors {
+            nodes.push(TopologyNode {
+                id: neighbor_node_id(*end_device_id),
+                kind: TopologyNodeKind::Neighbor,
+                label: end_device_id.0.to_string(),
+            });
+            links.push(TopologyLink {
+                from: gateway_node_id(&entry.gateway_id),
+                to: neighbor_node_id(*end_device_id),
+                reachability: Some(entry.reachability),
+                hop_distance: Some(entry.hop_distance),
+                signal_quality: Some(entry.signal_quality),
+                last_seen: Some(entry.last_seen),
+            });
+        }
+
+        Self {
+            generated_at,
+            nodes,
+            links,
+        }
+    }
+
+    /// Returns the graph in the DOT language of Graphviz. Links to proxied end device IDs are
+    /// dashed and labeled with the hop distance, the signal quality and the age of the last
+    /// announcement.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph topology {\n    rankdir=LR;\n");
+        for node in &self.nodes {
+            let shape = match node.kind {
+                TopologyNodeKind::Local => "doubleoctagon",
+                TopologyNodeKind::Gateway => "box",
+                TopologyNodeKind::Neighbor => "ellipse",
+            };
+            let _ = writeln!(
+                dot,
+                "    \"{}\" [label=\"{}\", shape={shape}];",
+                escape_dot(&node.id),
+                escape_dot(&node.label)
+            );
+        }
+        for link in &self.links {
+            let mut attributes = Vec::new();
+            if let (Some(signal_quality), Some(last_seen)) = (link.signal_quality, link.last_seen) {
+                let age = self
+                    .generated_at
+                    .signed_duration_since(last_seen)
+                    .num_seconds()
+                    .max(0);
+                attributes.push(format!(
+                    "label=\"{} hop(s)\\n{} dBm / {:.1} dB\\n{age} s ago\"",
+                    link.hop_distance.unwrap_or(1),
+                    signal_quality.rssi,
+                    signal_quality.snr
+                ));
+            }
+            if link.reachability == Some(Reachability::Proxied) {
+                attributes.push("style=dashed".to_owned());
+            }
+            let attributes = if attributes.is_empty() {
+                String::new()
+            } else {
+                format!(" [{}]", attributes.join(", "))
+            };
+            let _ = writeln!(
+                dot,
+                "    \"{}\" -> \"{}\"{attributes};",
+                escape_dot(&link.from),
+                escape_dot(&link.to)
+            );
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use crate::end_device_id::EndDeviceId;
+    use crate::neighbor_table::{NeighborEntry, Reachability, SignalQuality};
+    use crate::topology::{Topology, TopologyNodeKind};
+    use chirpstack_gwb_integration::gateway_id::GatewayId;
+    use chrono::{DateTime, Duration, Utc};
+    use std::collections::HashMap;
+
+    fn entry(reachability: Reachability, gateway_id: &str, now: DateTime<Utc>) -> NeighborEntry {
+        NeighborEntry {
+            reachability,
+            hop_distance: if reachability == Reachability::Own {
+                1
+            } else {
+                2
+            },
+            gateway_id: gateway_id.parse().unwrap(),
+            signal_quality: SignalQuality {
+                rssi: -90,
+                snr: 7.5,
+            },
+            last_seen: now - Duration::seconds(30),
+        }
+    }
+
+    #[test]
+    fn topology_links_neighbors_via_their_gateway() {
+        let now = Utc::now();
+        let neighbors = HashMap::from([
+            (
+                EndDeviceId(20),
+                entry(Reachability::Own, "a840411d2524415a", now),
+            ),
+            (
+                EndDeviceId(30),
+                entry(Reachability::Proxied, "A840411D2524415B", now),
+            ),
+        ]);
+        let gateway_ids: [GatewayId; 1] = ["a840411d2524415a".parse().unwrap()];
+        let topology = Topology::new(&[EndDeviceId(10)], &gateway_ids, &neighbors, now);
+
+        let kinds: Vec<_> = topology.nodes.iter().map(|node| node.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                TopologyNodeKind::Local,
+                TopologyNodeKind::Gateway,
+                TopologyNodeKind::Gateway,
+                TopologyNodeKind::Neighbor,
+                TopologyNodeKind::Neighbor,
+            ]
+        );
+        assert_eq!(topology.nodes[0].label, "This node (10)");
+        let link = topology
+            .links
+            .iter()
+            .find(|link| link.to == "neighbor:30")
+            .unwrap();
+        assert_eq!(link.from, "gateway:a840411d2524415b");
+        assert_eq!(link.hop_distance, Some(2));
+
+        let dot = topology.to_dot();
+        assert!(dot.starts_with("digraph topology {"));
+        assert!(dot.contains("\"local\" -> \"gateway:a840411d2524415a\";"));
+        assert!(dot.contains("\"gateway:a840411d2524415b\" -> \"neighbor:30\" [label=\"2 hop(s)\\n-90 dBm / 7.5 dB\\n30 s ago\", style=dashed];"));
+    }
+}
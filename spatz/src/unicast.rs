@@ -0,0 +1,413 @@
+//! Directed transmission of bundle packets to direct neighbors.
+//!
+//! If the destination of a bundle packet was recently announced by a neighbor as its own end
+//! device ID, the packet is sent once from the gateway which received the announcement, at the
+//! fastest data rate the signal quality of the announcement allows, instead of being flooded from
+//! all gateways. Only data rates the neighbor announced to receive with and the gateway can
+//! transmit at are considered. The neighbor answers with a [`HopAck`]. If the acknowledgement is not received
+//! in time, the packet is flooded after all.
+//!
+//! Nodes only acknowledge packets if unicast is configured, so all nodes need it to benefit.
+
+use crate::configuration::UnicastConfig;
+use crate::end_device_id::EndDeviceId;
+use crate::gateway_ids_manager::GatewayCapabilities;
+use crate::neighbor_table::NeighborTable;
+use crate::packet_cache::PacketSource;
+use crate::AppState;
+use chirpstack_gwb_integration::downlinks::predefined_parameters::DataRate;
+use chirpstack_gwb_integration::gateway_id::GatewayId;
+use lorawan_dtn_protocol::{data_rate_bit, parse_phy_payload, HopAck, LoRaWanPacket};
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::sync::{oneshot, Mutex};
+use tracing::trace;
+
+/// Data rates considered for directed transmissions, fastest first.
+const UNICAST_DATA_RATES: [DataRate; 7] = [
+    DataRate::Eu863_870Dr6,
+    DataRate::Eu863_870Dr5,
+    DataRate::Eu863_870Dr4,
+    DataRate::Eu863_870Dr3,
+    DataRate::Eu863_870Dr2,
+    DataRate::Eu863_870Dr1,
+    DataRate::Eu863_870Dr0,
+];
+
+/// SNR in dB required to demodulate spreading factor 12, every lower spreading factor requires
+/// 2.5 dB more.
+const SF12_REQUIRED_SNR_DB: f64 = -20.0;
+
+/// Counters of the directed transmissions.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Serialize, JsonSchema)]
+pub struct UnicastStats {
+    /// Packets sent from a single gateway to a direct neighbor.
+    pub sent: u64,
+    /// Directed packets acknowledged by the neighbor.
+    pub acknowledged: u64,
+    /// Directed packets flooded after all as the acknowledgement was not received in time or
+    /// the send failed.
+    pub flooded: u64,
+    /// Acknowledgements sent for packets received by this node.
+    pub acks_sent: u64,
+}
+
+/// Direct neighbor a packet is sent to.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct UnicastTarget {
+    /// Destination of the packet, registered at the neighbor.
+    pub destination: EndDeviceId,
+    /// Gateway which received the last announcement of the destination.
+    pub gateway_id: GatewayId,
+    /// Data rate the packet is sent with.
+    pub data_rate: DataRate,
+}
+
+/// Keeps track of the directed transmissions awaiting their acknowledgement.
+#[derive(Debug)]
+pub struct Unicast {
+    /// Timeouts and thresholds.
+    config: UnicastConfig,
+    /// Acknowledgement channels by destination and CRC32 of the phy payload.
+    pending: Mutex<HashMap<(EndDeviceId, u32), oneshot::Sender<()>>>,
+    /// Packets sent from a single gateway.
+    sent: AtomicU64,
+    /// Directed packets acknowledged by the neighbor.
+    acknowledged: AtomicU64,
+    /// Directed packets flooded after all.
+    flooded: AtomicU64,
+    /// Acknowledgements sent.
+    acks_sent: AtomicU64,
+}
+
+impl Unicast {
+    /// Creates a new [`Unicast`] without pending acknowledgements.
+    pub fn new(config: UnicastConfig) -> Self {
+        Self {
+            config,
+            pending: Mutex::new(HashMap::new()),
+            sent: AtomicU64::new(0),
+            acknowledged: AtomicU64::new(0),
+            flooded: AtomicU64::new(0),
+            acks_sent: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the direct neighbor the phy payload is sent to, `None` if the destination of the
+    /// packet is not a recently announced own end device ID of a neighbor.
+    ///
+    /// The data rate is the fastest one the neighbor announced to receive with and the gateway
+    /// can transmit at, gateways without known capabilities are assumed to support all. It is
+    /// never below `min_data_rate`, the packet was sized for it.
+    pub fn target(
+        &self,
+        neighbor_table: &NeighborTable,
+        gateway_capabilities: &HashMap<GatewayId, GatewayCapabilities>,
+        phy_payload: &[u8],
+        min_data_rate: DataRate,
+    ) -> Option<UnicastTarget> {
+        let destination = parse_phy_payload(phy_payload).ok()?.packet_destination()?;
+        let max_age =
+            chrono::Duration::from_std(Duration::from_secs(self.config.max_neighbor_age_seconds))
+                .unwrap_or(chrono::Duration::MAX);
+        let entry = neighbor_table.direct_neighbor(destination, max_age)?;
+        let data_rates = neighbor_table.receive_data_rates_of(destination)
+            & gateway_capabilities
+                .get(&entry.gateway_id)
+                .copied()
+                .unwrap_or_default()
+                .data_rates();
+        Some(UnicastTarget {
+            destination,
+            gateway_id: entry.gateway_id.clone(),
+            data_rate: best_data_rate(
+                entry.signal_quality.snr,
+                self.config.snr_margin_db,
+                min_data_rate,
+                data_rates,
+            ),
+        })
+    }
+
+    /// Registers a directed transmission of the phy payload, returns the receiver of the
+    /// acknowledgement.
+    pub async fn register(
+        &self,
+        destination: EndDeviceId,
+        phy_payload: &[u8],
+    ) -> oneshot::Receiver<()> {
+        let (ack_tx, ack_rx) = oneshot::channel();
+        self.pending
+            .lock()
+            .await
+            .insert((destination, crc32fast::hash(phy_payload)), ack_tx);
+        self.sent.fetch_add(1, Ordering::Relaxed);
+        ack_rx
+    }
+
+    /// Waits for the acknowledgement of a registered transmission, returns whether it was
+    /// received within the timeout.
+    pub async fn await_ack(
+        &self,
+        ack_rx: oneshot::Receiver<()>,
+        destination: EndDeviceId,
+        phy_payload: &[u8],
+    ) -> bool {
+        let timeout = Duration::from_secs(self.config.ack_timeout_seconds);
+        if let Ok(Ok(())) = tokio::time::timeout(timeout, ack_rx).await {
+            self.acknowledged.fetch_add(1, Ordering::Relaxed);
+            return true;
+        }
+        self.cancel(destination, phy_payload).await;
+        false
+    }
+
+    /// Removes a registered transmission whose packet is flooded instead.
+    pub async fn cancel(&self, destination: EndDeviceId, phy_payload: &[u8]) {
+        self.pending
+            .lock()
+            .await
+            .remove(&(destination, crc32fast::hash(phy_payload)));
+        self.flooded.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Hands a received acknowledgement to the waiting transmission, acknowledgements without a
+    /// waiting transmission are dropped.
+    pub async fn process_hop_ack(&self, hop_ack: &HopAck) {
+        let ack_tx = self
+            .pending
+            .lock()
+            .await
+            .remove(&(hop_ack.source, hop_ack.packet_hash));
+        if let Some(ack_tx) = ack_tx {
+            let _ = ack_tx.send(());
+        } else {
+            trace!("Dropping hop acknowledgement without pending transmission");
+        }
+    }
+
+    /// Returns the counters of the directed transmissions.
+    pub fn stats(&self) -> UnicastStats {
+        UnicastStats {
+            sent: self.sent.load(Ordering::Relaxed),
+            acknowledged: self.acknowledged.load(Ordering::Relaxed),
+            flooded: self.flooded.load(Ordering::Relaxed),
+            acks_sent: self.acks_sent.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Returns the fastest data rate of the bit set `data_rates` whose required SNR plus the margin
+/// is reached by `snr`, at least `min_data_rate`.
+pub fn best_data_rate(
+    snr: f32,
+    snr_margin_db: i32,
+    min_data_rate: DataRate,
+    data_rates: u8,
+) -> DataRate {
+    if !UNICAST_DATA_RATES.contains(&min_data_rate) {
+        return min_data_rate;
+    }
+    UNICAST_DATA_RATES
+        .into_iter()
+        .take_while(|data_rate| *data_rate != min_data_rate)
+        .filter(|data_rate| data_rates & data_rate_bit(*data_rate) != 0)
+        .find(|data_rate| {
+            let (_, spreading_factor) = data_rate.into_raw_bandwidth_and_spreading_factor();
+            let required_snr = SF12_REQUIRED_SNR_DB + 2.5 * (12.0 - f64::from(spreading_factor));
+            f64::from(snr) >= required_snr + f64::from(snr_margin_db)
+        })
+        .unwrap_or(min_data_rate)
+}
+
+/// Acknowledges a phy payload addressed to `destination`, a local service, sent with the data
+/// rate it was received with. The acknowledgement is added to the packet cache to not process it
+/// again.
+pub async fn send_hop_ack(
+    state: &AppState,
+    phy_payload: &[u8],
+    destination: EndDeviceId,
+    data_rate: DataRate,
+) {
+    let Some(unicast) = &state.unicast else {
+        return;
+    };
+    let hop_ack = HopAck::acknowledge(phy_payload, destination);
+    trace!("Sending hop acknowledgement: {hop_ack:?}");
+    let hop_ack_payload = hop_ack.convert_to_lorawan_phy_payload();
+    if state
+        .queue_manager
+        .enqueue_relay_packet(Box::new(hop_ack), data_rate)
+        .await
+    {
+        unicast.acks_sent.fetch_add(1, Ordering::Relaxed);
+        let _ = state
+            .packet_cache
+            .insert(&hop_ack_payload, PacketSource::Local)
+            .await;
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use crate::configuration::UnicastConfig;
+    use crate::end_device_id::EndDeviceId;
+    use crate::gateway_ids_manager::GatewayCapabilities;
+    use crate::neighbor_table::{NeighborTable, SignalQuality};
+    use crate::unicast::{best_data_rate, Unicast, UnicastStats, UnicastTarget};
+    use chirpstack_gwb_integration::downlinks::predefined_parameters::DataRate;
+    use chirpstack_gwb_integration::gateway_id::GatewayId;
+    use chrono::Utc;
+    use lorawan_dtn_protocol::{
+        CompleteBundle, DataRateAnnouncement, HopAck, LoRaWanPacket, LocalAnnouncement,
+        DEFAULT_RECEIVE_DATA_RATES,
+    };
+    use std::collections::HashMap;
+
+    fn complete_bundle(destination: EndDeviceId) -> Vec<u8> {
+        CompleteBundle::new(
+            destination,
+            EndDeviceId(0x5678),
+            Utc::now(),
+            &mut vec![1, 2, 3],
+            DataRate::Eu863_870Dr3,
+            false,
+        )
+        .unwrap()
+        .convert_to_lorawan_phy_payload()
+    }
+
+    #[test]
+    fn best_data_rate_respects_margin_and_minimum() {
+        assert_eq!(
+            best_data_rate(5.0, 5, DataRate::Eu863_870Dr3, DEFAULT_RECEIVE_DATA_RATES),
+            DataRate::Eu863_870Dr5
+        );
+        assert_eq!(
+            best_data_rate(5.0, 5, DataRate::Eu863_870Dr3, 0b0111_1111),
+            DataRate::Eu863_870Dr6
+        );
+        // SF8 requires -10 dB, SF7 -7.5 dB.
+        assert_eq!(
+            best_data_rate(-8.0, 0, DataRate::Eu863_870Dr0, DEFAULT_RECEIVE_DATA_RATES),
+            DataRate::Eu863_870Dr4
+        );
+        assert_eq!(
+            best_data_rate(-8.0, 5, DataRate::Eu863_870Dr0, DEFAULT_RECEIVE_DATA_RATES),
+            DataRate::Eu863_870Dr2
+        );
+        assert_eq!(
+            best_data_rate(-8.0, 0, DataRate::Eu863_870Dr0, 0b0000_1001),
+            DataRate::Eu863_870Dr3
+        );
+        assert_eq!(
+            best_data_rate(-25.0, 0, DataRate::Eu863_870Dr3, DEFAULT_RECEIVE_DATA_RATES),
+            DataRate::Eu863_870Dr3
+        );
+    }
+
+    #[tokio::test]
+    async fn directed_transmission_is_acknowledged_or_times_out() {
+        let unicast = Unicast::new(UnicastConfig {
+            ack_timeout_seconds: 1,
+            max_neighbor_age_seconds: 600,
+            snr_margin_db: 5,
+        });
+        let mut neighbor_table = NeighborTable::new();
+        neighbor_table.process_announcement(
+            &LocalAnnouncement::new(None, vec![EndDeviceId(0x1234)]),
+            &"a840411d25244150".parse().unwrap(),
+            SignalQuality {
+                rssi: -80,
+                snr: 7.5,
+            },
+        );
+        let to_neighbor = complete_bundle(EndDeviceId(0x1234));
+        let to_unknown = complete_bundle(EndDeviceId(0x9999));
+
+        let target = unicast
+            .target(
+                &neighbor_table,
+                &HashMap::new(),
+                &to_neighbor,
+                DataRate::Eu863_870Dr3,
+            )
+            .unwrap();
+        assert_eq!(
+            target,
+            UnicastTarget {
+                destination: EndDeviceId(0x1234),
+                gateway_id: "a840411d25244150".parse().unwrap(),
+                data_rate: DataRate::Eu863_870Dr5,
+            }
+        );
+        assert!(unicast
+            .target(
+                &neighbor_table,
+                &HashMap::new(),
+                &to_unknown,
+                DataRate::Eu863_870Dr3
+            )
+            .is_none());
+
+        // DR6 is used once the neighbor announced it and the gateway transmits 250 kHz.
+        neighbor_table.process_data_rate_announcement(
+            &DataRateAnnouncement::new(0b0111_1000, vec![EndDeviceId(0x1234)]),
+            &"a840411d25244150".parse().unwrap(),
+        );
+        let mut gateway_capabilities: HashMap<GatewayId, _> = HashMap::from([(
+            "a840411d25244150".parse().unwrap(),
+            GatewayCapabilities {
+                class_b_timing: None,
+                bandwidth_250_khz: Some(false),
+            },
+        )]);
+        let data_rate = |gateway_capabilities: &HashMap<GatewayId, GatewayCapabilities>| {
+            unicast
+                .target(
+                    &neighbor_table,
+                    gateway_capabilities,
+                    &to_neighbor,
+                    DataRate::Eu863_870Dr3,
+                )
+                .unwrap()
+                .data_rate
+        };
+        assert_eq!(data_rate(&gateway_capabilities), DataRate::Eu863_870Dr5);
+        gateway_capabilities.clear();
+        assert_eq!(data_rate(&gateway_capabilities), DataRate::Eu863_870Dr6);
+
+        let ack_rx = unicast.register(target.destination, &to_neighbor).await;
+        unicast
+            .process_hop_ack(&HopAck::acknowledge(&to_neighbor, EndDeviceId(0x1234)))
+            .await;
+        assert!(
+            unicast
+                .await_ack(ack_rx, target.destination, &to_neighbor)
+                .await
+        );
+
+        let ack_rx = unicast.register(target.destination, &to_neighbor).await;
+        unicast
+            .process_hop_ack(&HopAck::acknowledge(&to_unknown, EndDeviceId(0x1234)))
+            .await;
+        assert!(
+            !unicast
+                .await_ack(ack_rx, target.destination, &to_neighbor)
+                .await
+        );
+        assert_eq!(
+            unicast.stats(),
+            UnicastStats {
+                sent: 2,
+                acknowledged: 1,
+                flooded: 1,
+                acks_sent: 0,
+            }
+        );
+    }
+}
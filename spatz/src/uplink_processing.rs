@@ -1,14 +1,35 @@
 //! Processing of incoming uplinks.
 
+use crate::bundle_resend;
+use crate::diagnostics::{local_end_device_id, send_echo_reply};
+use crate::end_device_id::EndDeviceId;
+use crate::end_device_registry::EndDeviceCategory;
 use crate::graceful_shutdown::ShutdownAgent;
-use crate::lora_modulation_extraction::extract_modulation_info_from_uplink_tx_info;
-use crate::lorawan_protocol::{parse_phy_payload, LoRaWanPacket};
+use crate::inbound_duplicates::{InboundCheck, InboundDuplicateFilter, INBOUND_DUPLICATE_TTL};
+use crate::neighbor_table::SignalQuality;
+use crate::neighbor_trust::packet_sender;
+use crate::packet_cache::PacketSource;
 use crate::receive_buffers::ReceiveBufferManager;
+use crate::received_packets::ReceivedPacket;
+use crate::status_reports;
+use crate::unicast::send_hop_ack;
+use crate::uplink_trace::UplinkTraceRecorder;
+use crate::watchdog::{HEARTBEAT_INTERVAL, UPLINK_PROCESSOR};
 use crate::AppState;
 use async_trait::async_trait;
 use chirpstack_gwb_integration::downlinks::predefined_parameters::DataRate;
+use chirpstack_gwb_integration::gateway_id::GatewayId;
 use chirpstack_gwb_integration::runtime::callbacks::EventUpCallback;
+use chirpstack_gwb_integration::uplinks::UplinkInfo;
+use chrono::Utc;
+use lorawan_dtn_protocol::{
+    parse_phy_payload, parse_phy_payload_with_network_id, BundleResendRequest,
+    CapabilityAnnouncement, ChannelPlanAnnouncement, DataRateAnnouncement, EchoReply, EchoRequest,
+    HopAck, LoRaWanPacket, LocalAnnouncement, ReachabilityAnnouncement, ServiceAnnouncement,
+    StatusReport, StatusReportRequest,
+};
 use std::sync::Arc;
+use std::time::Instant;
 use tokio::sync::mpsc;
 use tokio::sync::mpsc::error::TrySendError;
 use tracing::{error, instrument, trace};
@@ -17,7 +38,7 @@ use tracing::{error, instrument, trace};
 #[derive(Debug)]
 pub struct UplinkCallback {
     /// Channel to send the gateway ID and the uplink frame.
-    pub uplink_callback_tx: mpsc::Sender<(String, chirpstack_api::gw::UplinkFrame)>,
+    pub uplink_callback_tx: mpsc::Sender<(GatewayId, chirpstack_api::gw::UplinkFrame)>,
 }
 
 #[async_trait]
@@ -25,7 +46,7 @@ impl EventUpCallback for UplinkCallback {
     /// Send incoming uplinks via the channel in the [`UplinkCallback`] struct.
     async fn dispatch_up_event(
         &self,
-        gateway_id: String,
+        gateway_id: GatewayId,
         up_event: chirpstack_api::gw::UplinkFrame,
     ) {
         trace!("Dispatch up event called");
@@ -35,97 +56,438 @@ impl EventUpCallback for UplinkCallback {
     }
 }
 
-/// Task to processes incoming uplinks.
+/// Hands the packet to the routing task to relay it.
+fn send_to_relay(
+    relay_tx: &mpsc::Sender<(Box<dyn LoRaWanPacket>, DataRate)>,
+    packet: Box<dyn LoRaWanPacket>,
+    data_rate: DataRate,
+) {
+    if let Err(err) = relay_tx.try_send((packet, data_rate)) {
+        match err {
+            TrySendError::Full(_) => {
+                error!("Relay channel is full, dropping relay packet");
+            }
+            TrySendError::Closed(_) => {
+                error!("Relay channel is closed");
+            }
+        }
+    }
+}
+
+/// Relays a copy of a packet addressed to a group this node is a member of, the packet itself is
+/// delivered locally. Skipped like other relaying if the node does not relay foreign traffic, the
+/// relaying is paused or the signal quality is outside the relay thresholds.
+fn relay_group_packet(
+    state: &AppState,
+    relay_tx: &mpsc::Sender<(Box<dyn LoRaWanPacket>, DataRate)>,
+    uplink: &chirpstack_api::gw::UplinkFrame,
+) {
+    if !state.node_profile.relays_foreign_traffic()
+        || state.subsystem_control.relaying_paused()
+        || !state
+            .routing_dispatcher
+            .relays(uplink.rx_info.as_ref().map(SignalQuality::from))
+    {
+        trace!("Not relaying group packet");
+        return;
+    }
+    match (
+        parse_phy_payload(&uplink.phy_payload),
+        UplinkInfo::try_from(uplink),
+    ) {
+        (Ok(packet), Ok(uplink_info)) => send_to_relay(relay_tx, packet, uplink_info.data_rate),
+        (Err(err), _) => error!(%err),
+        (_, Err(err)) => error!(%err),
+    }
+}
+
+/// Task to processes incoming uplinks, stops if the uplink channel closed.
 ///
+/// Appends every uplink to the trace if recording.
+/// Drops uplinks with an invalid CRC, unless configured otherwise, or an unknown modulation.
+/// Suppresses uplinks with a phy payload received within [`INBOUND_DUPLICATE_TTL`] before parsing.
+/// Counts the remaining uplinks in the radio stats if configured.
+/// Drops packets of networks which are not accepted, the network ID prefix is stripped otherwise.
+/// Records the new packets for the packet export if configured.
+/// Drops packets of blacklisted or untrusted nodes and removes them from the neighbor table.
 /// Checks whether the uplink was already seen within the timeout window. If not, adds it to the
 /// uplink cache, checks the addressing to determine whether it was addressed to this instance or
-/// should be routed further.
+/// should be routed further. Packets addressed to a group this instance is a member of are both.
 #[instrument(skip_all)]
 pub async fn uplink_processor_task(
-    mut uplink_rx: mpsc::Receiver<(String, chirpstack_api::gw::UplinkFrame)>,
+    mut uplink_rx: mpsc::Receiver<(GatewayId, chirpstack_api::gw::UplinkFrame)>,
     relay_tx: mpsc::Sender<(Box<dyn LoRaWanPacket>, DataRate)>,
+    mut trace_recorder: Option<UplinkTraceRecorder>,
     state: Arc<AppState>,
     mut shutdown_agent: ShutdownAgent,
 ) {
     trace!("Starting up");
     let mut receive_buffer_manager = ReceiveBufferManager::new(state.clone());
+    let mut inbound_duplicate_filter = InboundDuplicateFilter::new(INBOUND_DUPLICATE_TTL);
+    let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
     loop {
         let uplink = tokio::select! {
             uplink = uplink_rx.recv() => { uplink}
+            _ = heartbeat.tick() => {
+                state.watchdog.beat(UPLINK_PROCESSOR);
+                continue
+            }
             _ = shutdown_agent.await_shutdown() => {
                 trace!("Shutting down");
                 return
             }
         };
 
-        if let Some((gateway_id, uplink)) = uplink {
-            trace!(
-                "Received uplink from gateway \"{gateway_id}\": {:?}",
-                uplink.phy_payload
-            );
-
-            match parse_phy_payload(&uplink.phy_payload) {
-                Ok(parsed_packet) => {
-                    if state
-                        .packet_cache
-                        .insert(&uplink.phy_payload)
+        let Some((gateway_id, uplink)) = uplink else {
+            error!("Uplink channel closed, stopping");
+            return;
+        };
+
+        trace!(
+            "Received uplink from gateway \"{gateway_id}\": {:?}",
+            uplink.phy_payload
+        );
+
+        if let Some(recorder) = &mut trace_recorder {
+            if let Err(err) = recorder.record(&gateway_id, &uplink, Utc::now()).await {
+                error!("Failed to record uplink trace: {err}");
+            }
+        }
+
+        let (validity, process) = state.uplink_validator.check(&uplink);
+        if !process {
+            trace!("Dropping invalid uplink: {validity:?}");
+            continue;
+        }
+
+        let inbound_check =
+            inbound_duplicate_filter.check(&gateway_id, &uplink.phy_payload, Instant::now());
+        state.inbound_duplicate_metrics.record(inbound_check);
+        if inbound_check != InboundCheck::New {
+            trace!("Uplink recently received, suppressing: {inbound_check:?}");
+            continue;
+        }
+
+        if let Some(radio_stats) = &state.radio_stats {
+            radio_stats.record(&uplink, Utc::now()).await;
+        }
+
+        match parse_phy_payload_with_network_id(&uplink.phy_payload) {
+            Ok((network_id, mut parsed_packet)) => {
+                if !state.network_filter.accept(network_id) {
+                    trace!("Dropping uplink of network {network_id:?}");
+                    continue;
+                }
+
+                if state
+                    .packet_cache
+                    .insert(
+                        &uplink.phy_payload,
+                        PacketSource::Uplink {
+                            gateway_id: gateway_id.clone(),
+                        },
+                    )
+                    .await
+                    .is_err()
+                {
+                    trace!("Uplink already seen");
+                    continue;
+                }
+
+                match serde_json::to_value(&*parsed_packet) {
+                    Ok(packet) => state.received_packets.lock().await.push(ReceivedPacket {
+                        received_at: Utc::now(),
+                        gateway_id: gateway_id.clone(),
+                        signal_quality: uplink.rx_info.as_ref().map(SignalQuality::from),
+                        packet,
+                    }),
+                    Err(err) => error!("Failed to log received packet: {err}"),
+                }
+
+                if let Some(packet_export) = &state.packet_export {
+                    let hops = match packet_sender(&*parsed_packet) {
+                        Some(node_id) => state
+                            .neighbor_table
+                            .lock()
+                            .await
+                            .entries()
+                            .get(&node_id)
+                            .map(|entry| entry.hop_distance),
+                        None => None,
+                    };
+                    packet_export.record_received(
+                        &*parsed_packet,
+                        uplink.phy_payload.len(),
+                        &gateway_id,
+                        UplinkInfo::try_from(&uplink).ok().as_ref(),
+                        hops,
+                        Utc::now(),
+                    );
+                }
+
+                if let Some(node_id) = packet_sender(&*parsed_packet) {
+                    if !state.neighbor_trust.observe(node_id, Utc::now()) {
+                        trace!("Dropping packet of untrusted node {}", node_id.0);
+                        state.neighbor_table.lock().await.remove(node_id);
+                        continue;
+                    }
+                }
+
+                if let (Some(local_announcement), Some(rx_info)) = (
+                    parsed_packet.as_any().downcast_ref::<LocalAnnouncement>(),
+                    &uplink.rx_info,
+                ) {
+                    trace!("Adding local announcement to neighbor table");
+                    state.neighbor_table.lock().await.process_announcement(
+                        local_announcement,
+                        &gateway_id,
+                        SignalQuality::from(rx_info),
+                    );
+                    if let Some(bundle_parking) = &state.bundle_parking {
+                        bundle_parking.notify_destinations_changed();
+                    }
+                }
+
+                if let (Some(reachability_announcement), Some(rx_info)) = (
+                    parsed_packet
+                        .as_any()
+                        .downcast_ref::<ReachabilityAnnouncement>(),
+                    &uplink.rx_info,
+                ) {
+                    trace!("Adding reachability announcement to neighbor table");
+                    let own_end_device_ids: Vec<EndDeviceId> = state
+                        .end_device_ids
+                        .lock()
                         .await
-                        .is_err()
-                    {
-                        trace!("Uplink already seen");
+                        .iter()
+                        .cloned()
+                        .map(EndDeviceId::from)
+                        .collect();
+                    state
+                        .neighbor_table
+                        .lock()
+                        .await
+                        .process_reachability_announcement(
+                            reachability_announcement,
+                            &own_end_device_ids,
+                            &gateway_id,
+                            SignalQuality::from(rx_info),
+                        );
+                    if let Some(bundle_parking) = &state.bundle_parking {
+                        bundle_parking.notify_destinations_changed();
+                    }
+                }
+
+                if let Some(service_announcement) =
+                    parsed_packet.as_any().downcast_ref::<ServiceAnnouncement>()
+                {
+                    trace!("Adding service announcement to neighbor table");
+                    state
+                        .neighbor_table
+                        .lock()
+                        .await
+                        .process_service_announcement(service_announcement, &gateway_id);
+                }
+
+                if let Some(channel_plan_announcement) = parsed_packet
+                    .as_any()
+                    .downcast_ref::<ChannelPlanAnnouncement>()
+                {
+                    trace!("Adding channel plan announcement to neighbor table");
+                    state
+                        .neighbor_table
+                        .lock()
+                        .await
+                        .process_channel_plan_announcement(channel_plan_announcement, &gateway_id);
+                }
+
+                if let Some(capability_announcement) = parsed_packet
+                    .as_any()
+                    .downcast_ref::<CapabilityAnnouncement>()
+                {
+                    trace!("Adding capability announcement to neighbor table");
+                    state
+                        .neighbor_table
+                        .lock()
+                        .await
+                        .process_capability_announcement(capability_announcement, &gateway_id);
+                }
+
+                if let Some(data_rate_announcement) = parsed_packet
+                    .as_any()
+                    .downcast_ref::<DataRateAnnouncement>()
+                {
+                    trace!("Adding data rate announcement to neighbor table");
+                    state
+                        .neighbor_table
+                        .lock()
+                        .await
+                        .process_data_rate_announcement(data_rate_announcement, &gateway_id);
+                }
+
+                if let Some(path_cache) = &state.path_cache {
+                    let any = parsed_packet.as_any();
+                    let confirmed = any
+                        .downcast_ref::<StatusReport>()
+                        .map(|report| report.source)
+                        .or_else(|| any.downcast_ref::<EchoReply>().map(|reply| reply.source));
+                    if let Some(destination) = confirmed {
+                        path_cache.learn(destination, &gateway_id, Utc::now()).await;
+                    }
+                }
+
+                let category = match parsed_packet.packet_destination() {
+                    Some(destination) => state.end_device_registry.category(destination).await,
+                    None => None,
+                };
+                trace!("Destination category: {category:?}");
+                let relay = parsed_packet.packet_destination().is_some()
+                    && category != Some(EndDeviceCategory::LocalService);
+                if category == Some(EndDeviceCategory::Group) {
+                    // The packet cache entry of the uplink covers the relayed copy as well, so
+                    // the packet is neither delivered nor relayed again when heard from the
+                    // other members.
+                    trace!("Uplink destination is a group, delivering locally and relaying");
+                    relay_group_packet(&state, &relay_tx, &uplink);
+                } else if relay {
+                    if !state.node_profile.relays_foreign_traffic() {
+                        trace!("Endpoint-only node, dropping uplink for another node");
+                        continue;
+                    }
+                    if state.subsystem_control.relaying_paused() {
+                        trace!("Relaying paused, dropping uplink for another node");
                         continue;
                     }
+                    trace!("Uplink destination is not a local service, relaying");
 
-                    let end_device_id_match = {
-                        if let Some(destination) = parsed_packet.packet_destination() {
-                            let end_device_ids_lock = state.end_device_ids.lock().await;
-                            !end_device_ids_lock.contains(&destination.into())
-                        } else {
-                            false
+                    if let Some(echo_request) =
+                        parsed_packet.as_any_mut().downcast_mut::<EchoRequest>()
+                    {
+                        if state
+                            .end_device_registry
+                            .category(echo_request.source)
+                            .await
+                            == Some(EndDeviceCategory::LocalService)
+                        {
+                            trace!("Own echo request received from a neighbor, dropping");
+                            continue;
                         }
-                    };
-                    if end_device_id_match {
-                        trace!("Uplink end device ID did not match, relaying");
-
-                        let modulation_info =
-                            match extract_modulation_info_from_uplink_tx_info(uplink.tx_info) {
-                                Ok(modulation_info) => modulation_info,
-                                Err(err) => {
-                                    error!(%err);
-                                    continue;
-                                }
-                            };
-                        let data_rate = match DataRate::from_raw_bandwidth_and_spreading_factor(
-                            modulation_info.bandwidth,
-                            modulation_info.spreading_factor,
-                        ) {
-                            Ok(data_rate) => data_rate,
-                            Err(err) => {
-                                error!(%err);
-                                continue;
-                            }
-                        };
-
-                        // relay packet
-                        if let Err(err) = relay_tx.try_send((parsed_packet, data_rate)) {
-                            match err {
-                                TrySendError::Full(_) => {
-                                    error!("Relay channel is full, dropping relay packet");
-                                }
-                                TrySendError::Closed(_) => {
-                                    error!("Relay channel is closed");
-                                }
+                        if !state.diagnostics.first_seen(echo_request).await {
+                            trace!("Echo request already relayed, dropping");
+                            continue;
+                        }
+                        if echo_request.increment_hop_count() {
+                            trace!("Echo request hop limit reached, replying");
+                            if let Some(own_end_device_id) = local_end_device_id(&state).await {
+                                send_echo_reply(
+                                    &state,
+                                    EchoReply::answer(echo_request, own_end_device_id, true),
+                                )
+                                .await;
                             }
+                            continue;
                         }
+                    }
+
+                    // Neighbors route packets to advertised destinations through this node, so they
+                    // are not left to the relay thresholds.
+                    if category != Some(EndDeviceCategory::Proxy)
+                        && !state
+                            .routing_dispatcher
+                            .relays(uplink.rx_info.as_ref().map(SignalQuality::from))
+                    {
+                        trace!("Signal quality outside the relay thresholds, not relaying");
                         continue;
                     }
-                    receive_buffer_manager.process_packet(parsed_packet);
+
+                    let data_rate = match UplinkInfo::try_from(&uplink) {
+                        Ok(uplink_info) => uplink_info.data_rate,
+                        Err(err) => {
+                            error!(%err);
+                            continue;
+                        }
+                    };
+
+                    send_to_relay(&relay_tx, parsed_packet, data_rate);
+                    continue;
+                }
+                if let Some(echo_request) = parsed_packet.as_any().downcast_ref::<EchoRequest>() {
+                    if !state.diagnostics.first_seen(echo_request).await {
+                        trace!("Echo request already answered, dropping");
+                        continue;
+                    }
+                    trace!("Echo request reached its destination, replying");
+                    send_echo_reply(
+                        &state,
+                        EchoReply::answer(echo_request, echo_request.destination, false),
+                    )
+                    .await;
+                    continue;
+                }
+                if let Some(echo_reply) = parsed_packet.as_any().downcast_ref::<EchoReply>() {
+                    trace!("Received echo reply");
+                    state
+                        .diagnostics
+                        .process_echo_reply(echo_reply.clone())
+                        .await;
+                    continue;
+                }
+                if let Some(hop_ack) = parsed_packet.as_any().downcast_ref::<HopAck>() {
+                    trace!("Received hop acknowledgement");
+                    if let Some(unicast) = &state.unicast {
+                        unicast.process_hop_ack(hop_ack).await;
+                    }
                     continue;
                 }
-                Err(e) => {
-                    error!("The following is caused by a parsing error or the incoming payload not being proprietary");
-                    error!(%e);
+                if !state.node_profile.serves_local_bundles() {
+                    trace!("Relay-only node, dropping packet addressed to a local service");
+                    continue;
+                }
+                if let Some(request) = parsed_packet.as_any().downcast_ref::<StatusReportRequest>()
+                {
+                    trace!("Received status report request");
+                    state.status_reports.process_request(request, Utc::now());
+                    continue;
+                }
+                if let Some(request) = parsed_packet.as_any().downcast_ref::<BundleResendRequest>()
+                {
+                    trace!("Received bundle resend request");
+                    bundle_resend::process_request(&state, request, Utc::now());
+                    continue;
+                }
+                if let Some(report) = parsed_packet.as_any().downcast_ref::<StatusReport>() {
+                    trace!("Received status report");
+                    if !state.measurements.process_report(report) {
+                        status_reports::deliver_report(&state, report);
+                    }
+                    continue;
+                }
+                // Packets to groups are flooded, not sent directed to a member awaiting its
+                // acknowledgement.
+                if let (Some(destination), Some(_), false) = (
+                    parsed_packet.packet_destination(),
+                    &state.unicast,
+                    category == Some(EndDeviceCategory::Group),
+                ) {
+                    match UplinkInfo::try_from(&uplink) {
+                        Ok(uplink_info) => {
+                            send_hop_ack(
+                                &state,
+                                &uplink.phy_payload,
+                                destination,
+                                uplink_info.data_rate,
+                            )
+                            .await;
+                        }
+                        Err(err) => error!(%err),
+                    }
                 }
+                receive_buffer_manager.process_packet(parsed_packet);
+                continue;
+            }
+            Err(e) => {
+                error!("The following is caused by a parsing error or the incoming payload not being proprietary");
+                error!(%e);
             }
         }
     }
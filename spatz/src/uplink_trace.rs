@@ -0,0 +1,177 @@
+//! Recording of the raw uplink stream into a trace file and its replay through the uplink
+//! pipeline, e.g. for regression tests of routing and reassembly against field captures.
+//!
+//! A trace file contains one [`TraceRecord`] as JSON per line.
+
+use crate::error::UplinkTraceError;
+use crate::graceful_shutdown::ShutdownAgent;
+use chirpstack_gwb_integration::gateway_id::GatewayId;
+use chirpstack_gwb_integration::gateway_topics::{EventType, TopicLayout, TopicType};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tracing::{error, info, instrument, trace};
+
+/// Uplink recorded in a trace file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TraceRecord {
+    /// Time the uplink was received.
+    pub received_at: DateTime<Utc>,
+    /// MQTT topic the uplink was published on.
+    pub topic: String,
+    /// The uplink frame as published by the gateway bridge with the JSON marshaler.
+    pub payload: chirpstack_api::gw::UplinkFrame,
+}
+
+/// Appends every incoming uplink to a trace file.
+#[derive(Debug)]
+pub struct UplinkTraceRecorder {
+    /// The trace file.
+    file: File,
+    /// Topic layout of the gateway bridge to restore the topic of the uplinks.
+    topic_layout: TopicLayout,
+}
+
+impl UplinkTraceRecorder {
+    /// Opens the trace file, uplinks are appended if it already exists.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be opened.
+    pub async fn open(path: &str, topic_layout: TopicLayout) -> Result<Self, UplinkTraceError> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+        info!(path, "Recording uplink trace");
+        Ok(Self { file, topic_layout })
+    }
+
+    /// Appends the uplink received from the gateway.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the uplink cannot be serialized or written.
+    pub async fn record(
+        &mut self,
+        gateway_id: &GatewayId,
+        uplink: &chirpstack_api::gw::UplinkFrame,
+        received_at: DateTime<Utc>,
+    ) -> Result<(), UplinkTraceError> {
+        let record = TraceRecord {
+            received_at,
+            topic: self
+                .topic_layout
+                .topic(gateway_id, TopicType::Event(EventType::Up)),
+            payload: uplink.clone(),
+        };
+        let mut line = serde_json::to_vec(&record)?;
+        line.push(b'\n');
+        self.file.write_all(&line).await?;
+        self.file.flush().await?;
+        Ok(())
+    }
+}
+
+/// Task feeding the uplinks of a trace file through the uplink pipeline once.
+///
+/// The gaps between the uplinks are kept, shortened by the `speedup` factor. Records which
+/// cannot be parsed are skipped.
+#[instrument(skip_all)]
+pub async fn replay_task(
+    path: String,
+    speedup: u32,
+    topic_layout: TopicLayout,
+    uplink_tx: mpsc::Sender<(GatewayId, chirpstack_api::gw::UplinkFrame)>,
+    mut shutdown_agent: ShutdownAgent,
+) {
+    trace!("Starting up");
+    tokio::select! {
+        result = replay(&path, speedup, &topic_layout, &uplink_tx) => match result {
+            Ok(replayed) => info!(path, replayed, "Uplink trace replayed"),
+            Err(err) => error!(path, "Failed to replay uplink trace: {err}"),
+        },
+        _ = shutdown_agent.await_shutdown() => trace!("Shutting down"),
+    }
+}
+
+/// Feeds the records of the trace file into the uplink channel, returns the amount of replayed
+/// uplinks.
+async fn replay(
+    path: &str,
+    speedup: u32,
+    topic_layout: &TopicLayout,
+    uplink_tx: &mpsc::Sender<(GatewayId, chirpstack_api::gw::UplinkFrame)>,
+) -> Result<usize, UplinkTraceError> {
+    let mut lines = BufReader::new(File::open(path).await?).lines();
+    let replay_start = Instant::now();
+    let mut first_received_at = None;
+    let mut replayed = 0;
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (gateway_id, record) = match parse_record(&line, topic_layout) {
+            Ok(parsed) => parsed,
+            Err(err) => {
+                error!("Skipping trace record: {err}");
+                continue;
+            }
+        };
+        let first_received_at = *first_received_at.get_or_insert(record.received_at);
+        let offset = (record.received_at - first_received_at)
+            .to_std()
+            .unwrap_or_default()
+            / speedup.max(1);
+        tokio::time::sleep_until(replay_start + offset).await;
+        if uplink_tx.send((gateway_id, record.payload)).await.is_err() {
+            break;
+        }
+        replayed += 1;
+    }
+    Ok(replayed)
+}
+
+/// Parses a line of a trace file and the gateway ID of its topic.
+fn parse_record(
+    line: &str,
+    topic_layout: &TopicLayout,
+) -> Result<(GatewayId, TraceRecord), UplinkTraceError> {
+    let record: TraceRecord = serde_json::from_str(line)?;
+    let gateway_id = topic_layout.parse(&record.topic)?.gateway_id;
+    Ok((gateway_id, record))
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use crate::uplink_trace::{parse_record, TraceRecord};
+    use chirpstack_gwb_integration::gateway_topics::{EventType, TopicLayout, TopicType};
+    use chrono::Utc;
+
+    #[test]
+    fn records_round_trip_with_topic_layout() {
+        let topic_layout = TopicLayout::default();
+        let record = TraceRecord {
+            received_at: Utc::now(),
+            topic: topic_layout.topic(
+                &"0016c001ff10a235".parse().unwrap(),
+                TopicType::Event(EventType::Up),
+            ),
+            payload: chirpstack_api::gw::UplinkFrame {
+                phy_payload: vec![0xE0, 0x01, 0x02],
+                ..Default::default()
+            },
+        };
+        let line = serde_json::to_string(&record).unwrap();
+
+        let (gateway_id, parsed) = parse_record(&line, &topic_layout).unwrap();
+        assert_eq!(gateway_id, "0016c001ff10a235");
+        assert_eq!(parsed, record);
+        assert!(parse_record(&line, &TopicLayout::V3).is_err());
+    }
+}
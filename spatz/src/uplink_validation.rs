@@ -0,0 +1,168 @@
+//! Validation of the CRC status and the modulation of incoming uplinks before they are processed.
+
+use chirpstack_api::gw::{CrcStatus, UplinkFrame};
+use chirpstack_gwb_integration::uplinks::UplinkInfo;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Result of validating an uplink.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum UplinkValidity {
+    /// The gateway reported a valid CRC.
+    Valid,
+    /// The gateway received the frame without CRC.
+    NoCrc,
+    /// The gateway reported an invalid CRC.
+    BadCrc,
+    /// The uplink lacks the radio metadata or was not received with a known LoRa data rate.
+    InvalidModulation,
+}
+
+impl UplinkValidity {
+    /// Validates the CRC status and the modulation of an uplink.
+    pub fn of(uplink: &UplinkFrame) -> Self {
+        if UplinkInfo::try_from(uplink).is_err() {
+            return UplinkValidity::InvalidModulation;
+        }
+        match uplink.rx_info.as_ref().map(|rx_info| rx_info.crc_status()) {
+            Some(CrcStatus::CrcOk) => UplinkValidity::Valid,
+            Some(CrcStatus::BadCrc) => UplinkValidity::BadCrc,
+            Some(CrcStatus::NoCrc) | None => UplinkValidity::NoCrc,
+        }
+    }
+}
+
+/// Decides which uplinks are processed and counts them by [`UplinkValidity`], updated without
+/// locking.
+#[derive(Debug, Default)]
+pub struct UplinkValidator {
+    /// Whether uplinks with an invalid CRC are processed.
+    process_crc_errors: bool,
+    /// Uplinks with a valid CRC.
+    valid: AtomicU64,
+    /// Uplinks without CRC.
+    no_crc: AtomicU64,
+    /// Uplinks with an invalid CRC.
+    bad_crc: AtomicU64,
+    /// Uplinks with missing radio metadata or an unknown modulation.
+    invalid_modulation: AtomicU64,
+}
+
+/// Snapshot of the [`UplinkValidator`] counters.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, JsonSchema)]
+pub struct UplinkValidationStats {
+    /// Whether uplinks with an invalid CRC are processed.
+    pub process_crc_errors: bool,
+    /// Uplinks with a valid CRC.
+    pub valid: u64,
+    /// Uplinks without CRC, processed.
+    pub no_crc: u64,
+    /// Uplinks with an invalid CRC, only processed if `process_crc_errors` is set.
+    pub bad_crc: u64,
+    /// Dropped uplinks with missing radio metadata or an unknown modulation.
+    pub invalid_modulation: u64,
+}
+
+impl UplinkValidator {
+    /// Creates a new [`UplinkValidator`], uplinks with an invalid CRC are only processed if
+    /// `process_crc_errors` is set.
+    pub fn new(process_crc_errors: bool) -> Self {
+        Self {
+            process_crc_errors,
+            ..Self::default()
+        }
+    }
+
+    /// Validates and counts an uplink, returns the validity and whether the uplink is processed.
+    pub fn check(&self, uplink: &UplinkFrame) -> (UplinkValidity, bool) {
+        let validity = UplinkValidity::of(uplink);
+        let (counter, process) = match validity {
+            UplinkValidity::Valid => (&self.valid, true),
+            UplinkValidity::NoCrc => (&self.no_crc, true),
+            UplinkValidity::BadCrc => (&self.bad_crc, self.process_crc_errors),
+            UplinkValidity::InvalidModulation => (&self.invalid_modulation, false),
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        (validity, process)
+    }
+
+    /// Returns the current counters.
+    pub fn stats(&self) -> UplinkValidationStats {
+        UplinkValidationStats {
+            process_crc_errors: self.process_crc_errors,
+            valid: self.valid.load(Ordering::Relaxed),
+            no_crc: self.no_crc.load(Ordering::Relaxed),
+            bad_crc: self.bad_crc.load(Ordering::Relaxed),
+            invalid_modulation: self.invalid_modulation.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::uplink_validation::{UplinkValidator, UplinkValidity};
+    use chirpstack_api::gw::{
+        modulation, CrcStatus, LoraModulationInfo, Modulation, UplinkFrame, UplinkRxInfo,
+        UplinkTxInfo,
+    };
+
+    fn uplink(crc_status: CrcStatus) -> UplinkFrame {
+        UplinkFrame {
+            tx_info: Some(UplinkTxInfo {
+                frequency: 868_100_000,
+                modulation: Some(Modulation {
+                    parameters: Some(modulation::Parameters::Lora(LoraModulationInfo {
+                        bandwidth: 125_000,
+                        spreading_factor: 7,
+                        ..LoraModulationInfo::default()
+                    })),
+                }),
+            }),
+            rx_info: Some(UplinkRxInfo {
+                crc_status: crc_status as i32,
+                ..UplinkRxInfo::default()
+            }),
+            ..UplinkFrame::default()
+        }
+    }
+
+    #[test]
+    fn bad_crc_only_processed_if_enabled() {
+        let validator = UplinkValidator::new(false);
+        assert_eq!(
+            validator.check(&uplink(CrcStatus::CrcOk)),
+            (UplinkValidity::Valid, true)
+        );
+        assert_eq!(
+            validator.check(&uplink(CrcStatus::NoCrc)),
+            (UplinkValidity::NoCrc, true)
+        );
+        assert_eq!(
+            validator.check(&uplink(CrcStatus::BadCrc)),
+            (UplinkValidity::BadCrc, false)
+        );
+        let mut without_modulation = uplink(CrcStatus::CrcOk);
+        without_modulation.tx_info = None;
+        assert_eq!(
+            validator.check(&without_modulation),
+            (UplinkValidity::InvalidModulation, false)
+        );
+        let stats = validator.stats();
+        assert_eq!(
+            (
+                stats.valid,
+                stats.no_crc,
+                stats.bad_crc,
+                stats.invalid_modulation
+            ),
+            (1, 1, 1, 1)
+        );
+
+        let research_validator = UplinkValidator::new(true);
+        assert_eq!(
+            research_validator.check(&uplink(CrcStatus::BadCrc)),
+            (UplinkValidity::BadCrc, true)
+        );
+    }
+}
@@ -0,0 +1,322 @@
+//! Liveness of the internal tasks, detecting tasks which stopped without panicking, e.g. because
+//! a channel closed, or which hang.
+//!
+//! Every supervised task is registered with the interval it checks in and beats at least once per
+//! interval. A task misses its heartbeat if it did not beat for the configured amount of
+//! intervals. Tasks waiting intentionally for longer, e.g. during radio silence, mark themselves
+//! as idle until their next beat. The liveness of the tasks is always tracked and served with the
+//! status, the [`watchdog_task`] reacts to missed heartbeats if configured.
+
+use crate::configuration::{WatchdogAction, WatchdogConfig};
+use crate::graceful_shutdown::{ShutdownAgent, ShutdownConditions, ShutdownReason};
+use crate::AppState;
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{error, instrument, trace, warn};
+
+/// Task processing the uplinks of the gateways.
+pub const UPLINK_PROCESSOR: &str = "uplink_processor";
+
+/// Task sending the queued packets.
+pub const ROUTING: &str = "routing";
+
+/// Task removing expired packets from the packet cache.
+pub const PACKET_CACHE_CLEANER: &str = "packet_cache_cleaner";
+
+/// Interval tasks without an own interval, e.g. waiting for uplinks, check in.
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Missed intervals after which a task missed its heartbeat if no watchdog is configured.
+const DEFAULT_MISSED_BEATS: u32 = 3;
+
+/// Liveness of a supervised task.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Liveness {
+    /// The task beat within its interval.
+    Alive,
+    /// The task waits intentionally until its next beat.
+    Idle,
+    /// The task did not beat for the configured amount of intervals.
+    Missed,
+}
+
+/// Liveness of a supervised task, served with the status.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, JsonSchema)]
+pub struct TaskStatus {
+    /// Name of the task.
+    pub name: String,
+    /// Liveness of the task.
+    pub liveness: Liveness,
+    /// Time of the last beat, the registration if the task did not beat yet.
+    pub last_beat: DateTime<Utc>,
+    /// Interval the task checks in, in seconds.
+    pub interval_seconds: u64,
+    /// Restarts of the task by the watchdog.
+    pub restarts: u32,
+}
+
+/// Heartbeat of a supervised task.
+#[derive(Debug)]
+struct Heartbeat {
+    /// Interval the task checks in.
+    interval: Duration,
+    /// Time of the last beat or the registration.
+    last_beat: DateTime<Utc>,
+    /// Whether the task waits intentionally until its next beat.
+    idle: bool,
+    /// Whether the missed heartbeat was already returned by [`Watchdog::newly_missed`].
+    reported: bool,
+    /// Restarts of the task by the watchdog.
+    restarts: u32,
+}
+
+/// Registry of the heartbeats of the supervised tasks.
+#[derive(Debug)]
+pub struct Watchdog {
+    /// Missed intervals after which a task missed its heartbeat.
+    missed_beats: u32,
+    /// Heartbeats by task name.
+    heartbeats: Mutex<BTreeMap<&'static str, Heartbeat>>,
+}
+
+impl Watchdog {
+    /// Creates a new [`Watchdog`] without registered tasks, a task misses its heartbeat after the
+    /// configured amount of intervals or 3 if not configured.
+    pub fn new(config: Option<&WatchdogConfig>) -> Self {
+        Self {
+            missed_beats: config.map_or(DEFAULT_MISSED_BEATS, |config| config.missed_beats),
+            heartbeats: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Registers a task checking in every interval, it is alive until the interval passed.
+    pub fn register(&self, task: &'static str, interval: Duration, now: DateTime<Utc>) {
+        self.lock().insert(
+            task,
+            Heartbeat {
+                interval,
+                last_beat: now,
+                idle: false,
+                reported: false,
+                restarts: 0,
+            },
+        );
+    }
+
+    /// Records a beat of the task, ending an idle phase.
+    pub fn beat(&self, task: &'static str) {
+        if let Some(heartbeat) = self.lock().get_mut(task) {
+            heartbeat.last_beat = Utc::now();
+            heartbeat.idle = false;
+            heartbeat.reported = false;
+        }
+    }
+
+    /// Marks the task as waiting intentionally, it is not missed until its next beat.
+    pub fn idle(&self, task: &'static str) {
+        if let Some(heartbeat) = self.lock().get_mut(task) {
+            heartbeat.idle = true;
+        }
+    }
+
+    /// Records a restart of the task, which counts as beat.
+    pub fn record_restart(&self, task: &'static str, now: DateTime<Utc>) {
+        if let Some(heartbeat) = self.lock().get_mut(task) {
+            heartbeat.last_beat = now;
+            heartbeat.idle = false;
+            heartbeat.reported = false;
+            heartbeat.restarts = heartbeat.restarts.saturating_add(1);
+        }
+    }
+
+    /// Returns the tasks which missed their heartbeat since the last call or their last beat.
+    pub fn newly_missed(&self, now: DateTime<Utc>) -> Vec<&'static str> {
+        let missed_beats = self.missed_beats;
+        self.lock()
+            .iter_mut()
+            .filter(|(_, heartbeat)| {
+                !heartbeat.reported && heartbeat.liveness(missed_beats, now) == Liveness::Missed
+            })
+            .map(|(task, heartbeat)| {
+                heartbeat.reported = true;
+                *task
+            })
+            .collect()
+    }
+
+    /// Returns the liveness of all registered tasks, ordered by name.
+    pub fn status(&self, now: DateTime<Utc>) -> Vec<TaskStatus> {
+        self.lock()
+            .iter()
+            .map(|(task, heartbeat)| TaskStatus {
+                name: (*task).to_owned(),
+                liveness: heartbeat.liveness(self.missed_beats, now),
+                last_beat: heartbeat.last_beat,
+                interval_seconds: heartbeat.interval.as_secs(),
+                restarts: heartbeat.restarts,
+            })
+            .collect()
+    }
+
+    /// Locks the heartbeats, a poisoned lock is recovered as the heartbeats stay consistent.
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<&'static str, Heartbeat>> {
+        self.heartbeats
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Heartbeat {
+    /// Returns the liveness of the task.
+    fn liveness(&self, missed_beats: u32, now: DateTime<Utc>) -> Liveness {
+        if self.idle {
+            return Liveness::Idle;
+        }
+        let allowed = chrono::Duration::from_std(self.interval.saturating_mul(missed_beats))
+            .unwrap_or(chrono::Duration::MAX);
+        if now.signed_duration_since(self.last_beat) > allowed {
+            Liveness::Missed
+        } else {
+            Liveness::Alive
+        }
+    }
+}
+
+/// Spawns a supervised task again.
+pub type RestartTask = Box<dyn Fn() -> JoinHandle<()> + Send>;
+
+/// Running supervised task.
+pub struct SupervisedTask {
+    /// Name the task is registered with.
+    pub name: &'static str,
+    /// Handle of the running task, aborted on restart.
+    pub handle: JoinHandle<()>,
+    /// Spawns the task again, tasks owning their inputs, e.g. the uplink channel, cannot be
+    /// restarted on their own.
+    pub restart: Option<RestartTask>,
+}
+
+/// Task checking the heartbeats on the configured interval and reacting to missed heartbeats.
+///
+/// Missed heartbeats are always logged. Restartable tasks are restarted if configured, otherwise
+/// all of Spatz is restarted. A missed heartbeat shuts Spatz down if configured.
+#[instrument(skip_all)]
+pub async fn watchdog_task(
+    config: WatchdogConfig,
+    state: Arc<AppState>,
+    mut tasks: Vec<SupervisedTask>,
+    mut shutdown_agent: ShutdownAgent,
+) {
+    trace!("Starting up");
+    let mut check_interval =
+        tokio::time::interval(Duration::from_secs(config.check_interval_seconds.max(1)));
+
+    loop {
+        tokio::select! {
+            _ = check_interval.tick() => {}
+            _ = shutdown_agent.await_shutdown() => {
+                trace!("Shutting down");
+                return
+            }
+        }
+        for name in state.watchdog.newly_missed(Utc::now()) {
+            let task = tasks.iter_mut().find(|task| task.name == name);
+            let finished = task.as_ref().is_some_and(|task| task.handle.is_finished());
+            warn!(task = name, finished, "Task missed its heartbeat");
+            match config.action {
+                WatchdogAction::Log => {}
+                WatchdogAction::Restart => match task {
+                    Some(SupervisedTask {
+                        handle,
+                        restart: Some(restart),
+                        ..
+                    }) => {
+                        warn!(task = name, "Restarting task");
+                        handle.abort();
+                        *handle = restart();
+                        state.watchdog.record_restart(name, Utc::now());
+                    }
+                    _ => {
+                        error!(task = name, "Task cannot be restarted, restarting Spatz");
+                        state.restart_initiator.initiate_shutdown(
+                            ShutdownReason::new(ShutdownConditions::Restart, module_path!())
+                                .with_message(format!("Task {name} missed its heartbeat")),
+                        );
+                        return;
+                    }
+                },
+                WatchdogAction::Shutdown => {
+                    error!(task = name, "Shutting down");
+                    state.restart_initiator.initiate_shutdown(
+                        ShutdownReason::new(ShutdownConditions::TaskStalled, module_path!())
+                            .with_message(format!("Task {name} missed its heartbeat")),
+                    );
+                    return;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use crate::configuration::{WatchdogAction, WatchdogConfig};
+    use crate::watchdog::{Liveness, Watchdog};
+    use chrono::{Duration, Utc};
+
+    #[test]
+    fn missed_heartbeats_are_reported_once() {
+        let watchdog = Watchdog::new(Some(&WatchdogConfig {
+            check_interval_seconds: 5,
+            missed_beats: 2,
+            action: WatchdogAction::Log,
+        }));
+        let start = Utc::now();
+        watchdog.register("task", std::time::Duration::from_secs(10), start);
+        assert!(watchdog
+            .newly_missed(start + Duration::seconds(20))
+            .is_empty());
+        assert_eq!(
+            watchdog.status(start + Duration::seconds(20))[0].liveness,
+            Liveness::Alive
+        );
+
+        let late = start + Duration::seconds(21);
+        assert_eq!(watchdog.newly_missed(late), vec!["task"]);
+        assert!(watchdog.newly_missed(late).is_empty());
+        assert_eq!(watchdog.status(late)[0].liveness, Liveness::Missed);
+
+        watchdog.record_restart("task", late);
+        let status = watchdog.status(late);
+        assert_eq!(status[0].liveness, Liveness::Alive);
+        assert_eq!(status[0].restarts, 1);
+        assert_eq!(
+            watchdog.newly_missed(late + Duration::seconds(21)),
+            vec!["task"]
+        );
+    }
+
+    #[test]
+    fn idle_tasks_are_not_missed() {
+        let watchdog = Watchdog::new(None);
+        let start = Utc::now();
+        watchdog.register("task", std::time::Duration::from_secs(10), start);
+        watchdog.idle("task");
+        let late = start + Duration::seconds(3600);
+        assert!(watchdog.newly_missed(late).is_empty());
+        assert_eq!(watchdog.status(late)[0].liveness, Liveness::Idle);
+
+        watchdog.beat("task");
+        assert_eq!(watchdog.status(Utc::now())[0].liveness, Liveness::Alive);
+        // Unregistered tasks are ignored.
+        watchdog.beat("other");
+        assert_eq!(watchdog.status(Utc::now()).len(), 1);
+    }
+}
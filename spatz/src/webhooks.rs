@@ -0,0 +1,239 @@
+//! Outbound webhooks pushing received bundles and changes of the operating mode to HTTP
+//! endpoints, as an alternative to the WebSocket.
+//!
+//! Every request carries the event type in the `X-Spatz-Event` header and, if a secret is
+//! configured, the hex encoded HMAC-SHA256 of the body in the `X-Spatz-Signature` header.
+//!
+//! Events are delivered by a separate task from a bounded queue, so a slow or failing endpoint
+//! drops events once the queue is full instead of stalling the reception of the bundles.
+
+use crate::configuration::WebhookConfig;
+use crate::graceful_shutdown::ShutdownAgent;
+use crate::operating_mode::OperatingModeStatus;
+use crate::AppState;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+use tracing::{error, instrument, trace, warn};
+
+/// Interval at which the operating mode is checked for changes.
+const STATUS_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Upper bound of the delay between two delivery attempts.
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+/// Timeout of a single delivery attempt.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Amount of events waiting for delivery, further events are dropped.
+const DELIVERY_QUEUE_SIZE: usize = 64;
+
+/// Header containing the event type.
+const EVENT_HEADER: &str = "X-Spatz-Event";
+
+/// Header containing the signature of the body.
+const SIGNATURE_HEADER: &str = "X-Spatz-Signature";
+
+/// Returns the `X-Spatz-Signature` header value of the body, `sha256=` followed by the hex
+/// encoded HMAC-SHA256.
+fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Returns the delay before the retry following the failed attempt, doubling with every attempt.
+fn backoff(initial_backoff: Duration, attempt: u32) -> Duration {
+    initial_backoff
+        .saturating_mul(2_u32.saturating_pow(attempt))
+        .min(MAX_BACKOFF)
+}
+
+/// Sends events to the endpoint of a webhook.
+#[derive(Debug)]
+struct Webhook {
+    /// Configuration of the webhook.
+    config: WebhookConfig,
+    /// HTTP client.
+    client: reqwest::Client,
+}
+
+impl Webhook {
+    /// POSTs the body and retries with exponential backoff until the endpoint answers with a
+    /// success status or the retries are exhausted.
+    ///
+    /// Returns `false` if the instance is shutting down.
+    async fn deliver(
+        &self,
+        event: &str,
+        body: Vec<u8>,
+        shutdown_agent: &mut ShutdownAgent,
+    ) -> bool {
+        let signature = self
+            .config
+            .secret
+            .as_ref()
+            .map(|secret| sign(secret, &body));
+        let initial_backoff = Duration::from_millis(self.config.initial_backoff_ms);
+        for attempt in 0..=self.config.max_retries {
+            let mut request = self
+                .client
+                .post(&self.config.url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .header(EVENT_HEADER, event)
+                .body(body.clone());
+            if let Some(signature) = &signature {
+                request = request.header(SIGNATURE_HEADER, signature);
+            }
+            match request.send().await {
+                Ok(response) if response.status().is_success() => {
+                    trace!("Delivered {event} event to {}", self.config.url);
+                    return true;
+                }
+                Ok(response) => warn!(
+                    "Webhook {} answered {} to {event} event",
+                    self.config.url,
+                    response.status()
+                ),
+                Err(err) => warn!("Webhook {} failed: {err}", self.config.url),
+            }
+            if attempt == self.config.max_retries {
+                break;
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(backoff(initial_backoff, attempt)) => {}
+                _ = shutdown_agent.await_shutdown() => return false,
+            }
+        }
+        error!(
+            "Dropping {event} event, webhook {} failed {} times",
+            self.config.url,
+            self.config.max_retries + 1
+        );
+        true
+    }
+}
+
+/// Delivers the queued events to the endpoint of the webhook one after another.
+#[instrument(skip_all, fields(url = %webhook.config.url))]
+async fn delivery_task(
+    webhook: Webhook,
+    mut events_rx: mpsc::Receiver<(&'static str, Vec<u8>)>,
+    mut shutdown_agent: ShutdownAgent,
+) {
+    loop {
+        let (event, body) = tokio::select! {
+            event = events_rx.recv() => {
+                let Some(event) = event else {
+                    return;
+                };
+                event
+            }
+            _ = shutdown_agent.await_shutdown() => return,
+        };
+        if !webhook.deliver(event, body, &mut shutdown_agent).await {
+            return;
+        }
+    }
+}
+
+/// Task POSTing received bundles and, if enabled, changes of the operating mode to the
+/// endpoint of the webhook.
+#[instrument(skip_all, fields(url = %config.url))]
+pub async fn webhook_task(
+    config: WebhookConfig,
+    state: Arc<AppState>,
+    mut shutdown_agent: ShutdownAgent,
+) {
+    trace!("Starting up");
+    let mut bundles_rx = state.bundles_to_ws.subscribe();
+    let mut status_interval = tokio::time::interval(STATUS_POLL_INTERVAL);
+    let mut last_status: Option<OperatingModeStatus> = None;
+    let status_events = config.status_events;
+    let client = match reqwest::Client::builder().timeout(REQUEST_TIMEOUT).build() {
+        Ok(client) => client,
+        Err(err) => {
+            error!("Failed to create HTTP client: {err}");
+            return;
+        }
+    };
+    let (events_tx, events_rx) = mpsc::channel(DELIVERY_QUEUE_SIZE);
+    tokio::spawn(delivery_task(
+        Webhook { config, client },
+        events_rx,
+        shutdown_agent.clone(),
+    ));
+
+    loop {
+        let (event, body) = tokio::select! {
+            bundle = bundles_rx.recv() => {
+                match bundle {
+                    Ok(mut bundle) => ("bundle", bundle.to_json().into_bytes()),
+                    Err(RecvError::Lagged(skipped)) => {
+                        error!("Webhook lagging behind, {skipped} bundles skipped");
+                        continue;
+                    }
+                    Err(RecvError::Closed) => return,
+                }
+            }
+            _ = status_interval.tick(), if status_events => {
+                let status = state.operating_mode.lock().await.status();
+                if last_status.as_ref() == Some(&status) {
+                    continue;
+                }
+                let body = match serde_json::to_vec(&status) {
+                    Ok(body) => body,
+                    Err(err) => {
+                        error!(%err);
+                        continue;
+                    }
+                };
+                last_status = Some(status);
+                ("status", body)
+            }
+            _ = shutdown_agent.await_shutdown() => {
+                trace!("Shutting down");
+                return
+            }
+        };
+
+        match events_tx.try_send((event, body)) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                error!("Webhook delivery queue full, dropping {event} event");
+            }
+            Err(TrySendError::Closed(_)) => {
+                trace!("Shutting down");
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::webhooks::{backoff, sign, MAX_BACKOFF};
+    use std::time::Duration;
+
+    #[test]
+    fn signature_is_hmac_sha256() {
+        // RFC 4231 test case 2.
+        assert_eq!(
+            sign("Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_maximum() {
+        let initial_backoff = Duration::from_millis(500);
+        assert_eq!(backoff(initial_backoff, 0), Duration::from_millis(500));
+        assert_eq!(backoff(initial_backoff, 3), Duration::from_secs(4));
+        assert_eq!(backoff(initial_backoff, 40), MAX_BACKOFF);
+    }
+}